@@ -0,0 +1,102 @@
+//! Embed build metadata (git sha, build date, target) and the locked
+//! dependency list for `/api/version` and `/api/sbom`, plus the release
+//! signing key used by `ten self-update`.
+
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("..");
+
+    for path in [".git/HEAD", ".git/refs", "Cargo.lock"] {
+        if workspace.join(path).exists() {
+            println!("cargo:rerun-if-changed=../{}", path);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=TENEMENT_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=TENEMENT_RELEASE_PUBKEY");
+
+    // Packagers building from a tarball can pass the sha in
+    let git_sha = std::env::var("TENEMENT_GIT_SHA")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .current_dir(&workspace)
+                .output()
+                .ok()
+                .filter(|o| o.status.success())
+                .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // Honour SOURCE_DATE_EPOCH for reproducible builds
+    let epoch = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        });
+    let build_date = civil_date(epoch / 86_400);
+
+    let mut packages = Vec::new();
+    if let Ok(lock) = std::fs::read_to_string(workspace.join("Cargo.lock")) {
+        if let Ok(lock) = lock.parse::<toml::Table>() {
+            for package in lock
+                .get("package")
+                .and_then(|p| p.as_array())
+                .into_iter()
+                .flatten()
+            {
+                let field = |name: &str| {
+                    package
+                        .get(name)
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("")
+                        .to_string()
+                };
+                packages.push((field("name"), field("version"), field("source")));
+            }
+        }
+    }
+
+    let target = std::env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+
+    // minisign public key for release binaries; release builds set it, local
+    // builds leave it unset and `ten self-update` then needs --public-key
+    let release_public_key = std::env::var("TENEMENT_RELEASE_PUBKEY")
+        .ok()
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty());
+
+    let mut code = format!(
+        "pub const GIT_SHA: &str = {:?};\npub const BUILD_DATE: &str = {:?};\npub const TARGET: &str = {:?};\npub const RELEASE_PUBLIC_KEY: Option<&str> = {:?};\n",
+        git_sha, build_date, target, release_public_key
+    );
+    code.push_str("pub const PACKAGES: &[(&str, &str, &str)] = &[\n");
+    for (name, version, source) in packages {
+        code.push_str(&format!("    ({:?}, {:?}, {:?}),\n", name, version, source));
+    }
+    code.push_str("];\n");
+
+    std::fs::write(Path::new(&out_dir).join("build_info.rs"), code).unwrap();
+}
+
+/// Days since 1970-01-01 to "YYYY-MM-DD" (Howard Hinnant's civil_from_days)
+fn civil_date(days: u64) -> String {
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -4,11 +4,12 @@
 //! All routes are under /api/* and protected by Bearer token auth.
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     Json,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 
 use crate::server::AppState;
 
@@ -20,6 +21,12 @@ use crate::server::AppState;
 pub struct SpawnRequest {
     pub process: String,
     pub id: String,
+    /// Env vars merged over the service's `env` for this instance only
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Labels merged over the service's `labels` for this instance only
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,6 +47,83 @@ pub struct WeightResponse {
     pub weight: u8,
 }
 
+/// New resource limits for a running instance. Omitted limits are left as
+/// they are; 0 lifts a memory, IO or process limit.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct LimitsRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit_mb: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_high_mb: Option<u32>,
+    /// 0 means no swap rather than no limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_swap_max_mb: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_shares: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_pids: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_read_bps: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_write_bps: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub io_iops: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpuset: Option<String>,
+}
+
+impl LimitsRequest {
+    /// True if no limit is set
+    pub fn is_empty(&self) -> bool {
+        self.summary().is_empty()
+    }
+
+    /// `key=value` for each limit set, for the audit log
+    fn summary(&self) -> String {
+        let number = |n: Option<u64>| n.map(|n| n.to_string());
+        [
+            (
+                "memory_limit_mb",
+                number(self.memory_limit_mb.map(u64::from)),
+            ),
+            ("memory_high_mb", number(self.memory_high_mb.map(u64::from))),
+            (
+                "memory_swap_max_mb",
+                number(self.memory_swap_max_mb.map(u64::from)),
+            ),
+            ("cpu_shares", number(self.cpu_shares.map(u64::from))),
+            ("max_pids", number(self.max_pids.map(u64::from))),
+            ("io_read_bps", number(self.io_read_bps)),
+            ("io_write_bps", number(self.io_write_bps)),
+            ("io_iops", number(self.io_iops)),
+            ("cpuset", self.cpuset.clone()),
+        ]
+        .iter()
+        .filter_map(|(key, value)| value.as_ref().map(|v| format!("{}={}", key, v)))
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LimitsResponse {
+    pub instance: String,
+    /// The limits that were changed
+    #[serde(flatten)]
+    pub limits: LimitsRequest,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DrainRequest {
+    /// Seconds to wait for in-flight requests before stopping
+    #[serde(default = "default_drain_timeout")]
+    pub timeout: u64,
+}
+
+fn default_drain_timeout() -> u64 {
+    30
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeployRequest {
     pub process: String,
@@ -48,6 +132,9 @@ pub struct DeployRequest {
     pub weight: u8,
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    /// Deploy even outside the service's maintenance windows (admin only)
+    #[serde(default)]
+    pub force: bool,
 }
 
 fn default_weight() -> u8 {
@@ -65,6 +152,25 @@ pub struct DeployResponse {
     pub status: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceDeployRequest {
+    pub version: String,
+    /// Command for the new version (default: the service's `command`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Deploy even outside the service's maintenance windows
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Query parameters for restart
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ForceParams {
+    /// Restart even outside the service's maintenance windows (admin only)
+    #[serde(default)]
+    pub force: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RouteRequest {
     pub process: String,
@@ -80,6 +186,60 @@ pub struct RouteResponse {
     pub to_weight: u8,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdoptConfigRequest {
+    /// Service the orphaned instances were running under
+    pub from: String,
+    /// Configured service to map them onto
+    pub to: String,
+    /// Stop a running `to:<id>` and move its data dir aside
+    #[serde(default)]
+    pub replace: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AdoptConfigResponse {
+    /// Remapped instances ("process:id")
+    pub instances: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConfigDiffResponse {
+    pub diff: tenement::ConfigDiff,
+    /// Running instances ("process:id") of removed or modified services
+    pub affected_instances: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VersionResponse {
+    pub version: String,
+    pub git_sha: String,
+    pub build_date: String,
+    /// Compiled-in Cargo features (e.g. "firecracker", "sandbox")
+    pub features: Vec<String>,
+    /// Isolation runtime name -> usable on this host
+    pub runtimes: std::collections::BTreeMap<String, bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SbomPackage {
+    pub name: String,
+    pub version: String,
+    /// Cargo source ("registry+..." or "git+..."); empty for workspace crates
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub source: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SbomResponse {
+    pub name: String,
+    pub version: String,
+    pub git_sha: String,
+    /// Every package in Cargo.lock at build time. A superset of what's linked:
+    /// optional and dev dependencies are included.
+    pub packages: Vec<SbomPackage>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiError {
     pub error: String,
@@ -103,23 +263,63 @@ impl ApiError {
 // ===================
 
 /// Spawn a new instance: POST /api/instances/spawn
+///
+/// Answers with the running instance if it already exists.
 pub async fn post_spawn(
     State(state): State<AppState>,
     axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
     Json(req): Json<SpawnRequest>,
 ) -> Result<Json<SpawnResponse>, (StatusCode, Json<ApiError>)> {
     check_tenant_access(&auth, &req.id)?;
-    let socket = state
-        .hypervisor
-        .spawn(&req.process, &req.id)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to spawn {}:{}: {}", req.process, req.id, e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiError::new(e.to_string())),
-            )
-        })?;
+    spawn(&state, &req, false).await.map(Json)
+}
+
+/// Create an instance: POST /api/instances
+///
+/// Like `/api/instances/spawn`, but 409 if the instance is already running,
+/// so deploy tooling can tell it didn't start it.
+pub async fn post_instance(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Json(req): Json<SpawnRequest>,
+) -> Result<(StatusCode, Json<SpawnResponse>), (StatusCode, Json<ApiError>)> {
+    check_tenant_access(&auth, &req.id)?;
+    let resp = spawn(&state, &req, true).await?;
+    Ok((StatusCode::CREATED, Json(resp)))
+}
+
+/// Spawn `req`; with `exclusive`, 409 if the instance is already running or
+/// being spawned
+async fn spawn(
+    state: &AppState,
+    req: &SpawnRequest,
+    exclusive: bool,
+) -> Result<SpawnResponse, (StatusCode, Json<ApiError>)> {
+    let opts = tenement::SpawnOptions {
+        env: req.env.clone(),
+        labels: req.labels.clone(),
+        ..Default::default()
+    };
+    opts.validate()
+        .map_err(|e| (StatusCode::BAD_REQUEST, Json(ApiError::new(e.to_string()))))?;
+    let spawned = if exclusive {
+        state.hypervisor.create(&req.process, &req.id, opts).await
+    } else {
+        state
+            .hypervisor
+            .spawn_with_opts(&req.process, &req.id, opts)
+            .await
+    };
+    let socket = spawned.map_err(|e| {
+        if e.is::<tenement::InstanceExists>() {
+            return (StatusCode::CONFLICT, Json(ApiError::new(e.to_string())));
+        }
+        tracing::error!("Failed to spawn {}:{}: {}", req.process, req.id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(e.to_string())),
+        )
+    })?;
 
     let port = state
         .hypervisor
@@ -136,11 +336,11 @@ pub async fn post_spawn(
         tracing::error!("Audit log failed: {}", e);
     }
 
-    Ok(Json(SpawnResponse {
+    Ok(SpawnResponse {
         instance: format!("{}:{}", req.process, req.id),
         socket: socket.display().to_string(),
         port,
-    }))
+    })
 }
 
 /// Stop an instance: DELETE /api/instances/{process:id}
@@ -173,14 +373,75 @@ pub async fn delete_instance(
     Ok(StatusCode::NO_CONTENT)
 }
 
-/// Restart an instance: POST /api/instances/{process:id}/restart
+/// Refuse a restart or deploy that the service's maintenance windows don't
+/// allow right now. Admins can override with `force`, which is audited.
+async fn check_maintenance(
+    state: &AppState,
+    auth: &crate::server::AuthIdentity,
+    action: &str,
+    process: &str,
+    instance_id: Option<&str>,
+    audit_id: &str,
+    force: bool,
+) -> Result<(), (StatusCode, Json<ApiError>)> {
+    let Some(reason) = state.hypervisor.maintenance_blocked(process, instance_id) else {
+        return Ok(());
+    };
+    if !force {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ApiError::new(format!(
+                "Refusing to {}: {}. Use --force to override",
+                action, reason
+            ))),
+        ));
+    }
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new(format!(
+                "Refusing to {}: {}. Only admin tokens can override maintenance windows",
+                action, reason
+            ))),
+        ));
+    }
+
+    tracing::warn!("Maintenance override for {}: {}", action, reason);
+    if let Err(e) = state
+        .deploy_log
+        .log(
+            "maintenance_override",
+            process,
+            audit_id,
+            Some(&format!("{}: {}", action, reason)),
+            true,
+        )
+        .await
+    {
+        tracing::error!("Audit log failed: {}", e);
+    }
+    Ok(())
+}
+
+/// Restart an instance: POST /api/instances/{process:id}/restart[?force=true]
 pub async fn post_restart(
     State(state): State<AppState>,
     axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
     Path(id): Path<String>,
+    Query(params): Query<ForceParams>,
 ) -> Result<Json<SpawnResponse>, (StatusCode, Json<ApiError>)> {
     let (process, instance_id) = parse_instance_id(&id)?;
     check_tenant_access(&auth, &instance_id)?;
+    check_maintenance(
+        &state,
+        &auth,
+        "restart",
+        &process,
+        Some(&instance_id),
+        &instance_id,
+        params.force,
+    )
+    .await?;
 
     let socket = state
         .hypervisor
@@ -207,6 +468,47 @@ pub async fn post_restart(
     }))
 }
 
+/// Drain then stop an instance: POST /api/instances/{process:id}/drain
+pub async fn post_drain(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Path(id): Path<String>,
+    Json(req): Json<DrainRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ApiError>)> {
+    let (process, instance_id) = parse_instance_id(&id)?;
+    check_tenant_access(&auth, &instance_id)?;
+
+    state
+        .hypervisor
+        .drain(
+            &process,
+            &instance_id,
+            std::time::Duration::from_secs(req.timeout),
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to drain {}: {}", id, e);
+            (StatusCode::NOT_FOUND, Json(ApiError::new(e.to_string())))
+        })?;
+
+    // Audit log
+    if let Err(e) = state
+        .deploy_log
+        .log(
+            "drain",
+            &process,
+            &instance_id,
+            Some(&format!("timeout={}s", req.timeout)),
+            true,
+        )
+        .await
+    {
+        tracing::error!("Audit log failed: {}", e);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
 /// Set weight: PUT /api/instances/{process:id}/weight
 pub async fn put_weight(
     State(state): State<AppState>,
@@ -229,6 +531,64 @@ pub async fn put_weight(
     }))
 }
 
+/// Change resource limits in place: PUT /api/instances/{process:id}/limits
+/// (admin only)
+pub async fn put_limits(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Path(id): Path<String>,
+    Json(req): Json<LimitsRequest>,
+) -> Result<Json<LimitsResponse>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Changing limits requires admin token")),
+        ));
+    }
+    let (process, instance_id) = parse_instance_id(&id)?;
+    if state.hypervisor.get(&process, &instance_id).await.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(format!("Instance not found: {}", id))),
+        ));
+    }
+
+    let limits = tenement::ResourceLimits {
+        memory_limit_mb: req.memory_limit_mb,
+        memory_high_mb: req.memory_high_mb,
+        memory_swap_max_mb: req.memory_swap_max_mb,
+        cpu_shares: req.cpu_shares,
+        max_pids: req.max_pids,
+        io_read_bps: req.io_read_bps,
+        io_write_bps: req.io_write_bps,
+        io_iops: req.io_iops,
+        io_device: None,
+        cpuset: req.cpuset.clone(),
+    };
+    state
+        .hypervisor
+        .update_limits(&process, &instance_id, limits)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to change limits of {}: {}", id, e);
+            (StatusCode::BAD_REQUEST, Json(ApiError::new(e.to_string())))
+        })?;
+
+    // Audit log
+    if let Err(e) = state
+        .deploy_log
+        .log("limits", &process, &instance_id, Some(&req.summary()), true)
+        .await
+    {
+        tracing::error!("Audit log failed: {}", e);
+    }
+
+    Ok(Json(LimitsResponse {
+        instance: id,
+        limits: req,
+    }))
+}
+
 /// Check health: GET /api/instances/{process:id}/health
 pub async fn get_health_check(
     State(state): State<AppState>,
@@ -258,6 +618,16 @@ pub async fn post_deploy(
             Json(ApiError::new("Deploy requires admin token")),
         ));
     }
+    check_maintenance(
+        &state,
+        &auth,
+        "deploy",
+        &req.process,
+        None,
+        &req.version,
+        req.force,
+    )
+    .await?;
     let socket = state
         .hypervisor
         .deploy_and_wait_healthy(&req.process, &req.version, req.weight, req.timeout)
@@ -293,6 +663,162 @@ pub async fn post_deploy(
     }))
 }
 
+/// Blue/green deploy: POST /api/services/{name}/deploy (admin only)
+pub async fn post_service_deploy(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Path(name): Path<String>,
+    Json(req): Json<ServiceDeployRequest>,
+) -> Result<Json<tenement::ServiceVersions>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Deploy requires admin token")),
+        ));
+    }
+    if !state.hypervisor.has_process(&name) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(format!("Unknown service: {}", name))),
+        ));
+    }
+    check_maintenance(
+        &state,
+        &auth,
+        "deploy",
+        &name,
+        None,
+        &req.version,
+        req.force,
+    )
+    .await?;
+    let result = state
+        .hypervisor
+        .deploy(&name, &req.version, req.command.clone())
+        .await;
+
+    // Audit log
+    if let Err(e) = state
+        .deploy_log
+        .log(
+            "deploy",
+            &name,
+            &req.version,
+            Some("blue-green"),
+            result.is_ok(),
+        )
+        .await
+    {
+        tracing::error!("Audit log failed: {}", e);
+    }
+
+    let versions = result.map_err(|e| {
+        tracing::error!("Deploy failed for {} {}: {:#}", name, req.version, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })?;
+    Ok(Json(versions))
+}
+
+/// Deployed versions: GET /api/services/{name}/versions (admin only)
+pub async fn get_service_versions(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Path(name): Path<String>,
+) -> Result<Json<tenement::ServiceVersions>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Service versions require admin token")),
+        ));
+    }
+    if !state.hypervisor.has_process(&name) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(format!("Unknown service: {}", name))),
+        ));
+    }
+    Ok(Json(state.hypervisor.service_versions(&name).await))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceInfo {
+    pub name: String,
+    /// Number of instances `[instances]` spawns on boot
+    pub desired: usize,
+    /// IDs of those instances
+    pub autospawn: Vec<String>,
+    /// IDs of the instances running now, warm pool spares excluded
+    pub running: Vec<String>,
+    /// The service's config, secrets redacted
+    pub config: tenement::config::ProcessConfig,
+}
+
+async fn service_info(state: &AppState, config: &tenement::Config, name: &str) -> ServiceInfo {
+    let mut autospawn = config.instances.get(name).cloned().unwrap_or_default();
+    autospawn.sort();
+    let mut running: Vec<String> = state
+        .hypervisor
+        .list_by_process(name)
+        .await
+        .into_iter()
+        .map(|i| i.id.id)
+        .collect();
+    running.sort();
+    ServiceInfo {
+        name: name.to_string(),
+        desired: autospawn.len(),
+        autospawn,
+        running,
+        config: config.service[name].redacted(),
+    }
+}
+
+/// Configured services: GET /api/services (admin only)
+pub async fn list_services(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Result<Json<Vec<ServiceInfo>>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Listing services requires admin token")),
+        ));
+    }
+    let config = state.hypervisor.config();
+    let mut names: Vec<&String> = config.service.keys().collect();
+    names.sort();
+    let mut services = Vec::with_capacity(names.len());
+    for name in names {
+        services.push(service_info(&state, &config, name).await);
+    }
+    Ok(Json(services))
+}
+
+/// One service: GET /api/services/{name} (admin only)
+pub async fn get_service(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Path(name): Path<String>,
+) -> Result<Json<ServiceInfo>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Service details require admin token")),
+        ));
+    }
+    let config = state.hypervisor.config();
+    if !config.service.contains_key(&name) {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ApiError::new(format!("Unknown service: {}", name))),
+        ));
+    }
+    Ok(Json(service_info(&state, &config, &name).await))
+}
+
 /// Route swap: POST /api/route (admin only)
 pub async fn post_route(
     State(state): State<AppState>,
@@ -346,6 +872,338 @@ pub async fn post_route(
     }))
 }
 
+/// Map orphaned instances onto a renamed service: POST /api/adopt-config (admin only)
+pub async fn post_adopt_config(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Json(req): Json<AdoptConfigRequest>,
+) -> Result<Json<AdoptConfigResponse>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Adopting config requires admin token")),
+        ));
+    }
+    let remapped = state
+        .hypervisor
+        .adopt_config(&req.from, &req.to, req.replace)
+        .await
+        .map_err(|e| {
+            tracing::error!(
+                "Adopt config failed for {} -> {}: {:#}",
+                req.from,
+                req.to,
+                e
+            );
+            (
+                StatusCode::CONFLICT,
+                Json(ApiError::new(format!("{:#}", e))),
+            )
+        })?;
+
+    // Audit log
+    for id in &remapped {
+        let details = format!("from {}:{}", req.from, id.id);
+        if let Err(e) = state
+            .deploy_log
+            .log("adopt_config", &id.process, &id.id, Some(&details), true)
+            .await
+        {
+            tracing::error!("Audit log failed: {}", e);
+        }
+    }
+
+    Ok(Json(AdoptConfigResponse {
+        instances: remapped.iter().map(|id| id.to_string()).collect(),
+    }))
+}
+
+/// Config diff: GET /api/config/diff (admin only)
+///
+/// Re-reads tenement.toml and reports what differs from the running config.
+/// Nothing is applied.
+pub async fn get_config_diff(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Result<Json<ConfigDiffResponse>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Config diff requires admin token")),
+        ));
+    }
+    let running = state.hypervisor.config();
+    let candidate = crate::server::load_candidate_config(&running).map_err(|e| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })?;
+
+    let diff = tenement::ConfigDiff::between(&running, &candidate);
+    let affected_instances = state.hypervisor.affected_instances(&diff).await;
+
+    Ok(Json(ConfigDiffResponse {
+        diff,
+        affected_instances,
+    }))
+}
+
+/// Config drift: GET /api/config/drift (admin only)
+///
+/// Re-reads tenement.toml and lists running instances spawned from a
+/// different service definition, i.e. that need a restart to pick it up.
+pub async fn get_config_drift(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Result<Json<Vec<tenement::InstanceDrift>>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Config drift requires admin token")),
+        ));
+    }
+    let running = state.hypervisor.config();
+    let candidate = crate::server::load_candidate_config(&running).map_err(|e| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })?;
+
+    Ok(Json(state.hypervisor.config_drift(&candidate).await))
+}
+
+/// Config reload: POST /api/config/reload (admin only)
+///
+/// Re-reads tenement.toml and applies it, as on SIGHUP.
+pub async fn post_config_reload(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Result<Json<tenement::ReloadReport>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Config reload requires admin token")),
+        ));
+    }
+    crate::server::reload_config(&state.hypervisor)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ApiError::new(format!("{:#}", e))),
+            )
+        })
+}
+
+/// Recently fired alerts: GET /api/alerts
+///
+/// Tenant tokens only see alerts for their own instances.
+pub async fn get_alerts(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Json<Vec<tenement::AlertEvent>> {
+    let alerts = state
+        .hypervisor
+        .alerts()
+        .map(|engine| engine.recent())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|event| match &auth.tenant_id {
+            Some(tenant) => &event.instance_id == tenant,
+            None => true,
+        })
+        .collect();
+    Json(alerts)
+}
+
+/// Host load, memory and data dir disk usage: GET /api/host (admin only)
+pub async fn get_host(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+) -> Result<Json<tenement::HostStats>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Host stats require admin token")),
+        ));
+    }
+    tenement::HostStats::collect(&state.hypervisor.config().settings.data_dir)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!("Failed to collect host stats: {:#}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ApiError::new(format!("{:#}", e))),
+            )
+        })
+}
+
+/// Query parameters for the capacity report
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CapacityParams {
+    /// Days of usage history to fit the forecast to
+    #[serde(default = "default_capacity_days")]
+    pub days: u64,
+    /// How many instances to suggest migrating
+    #[serde(default = "default_capacity_top")]
+    pub top: usize,
+}
+
+fn default_capacity_days() -> u64 {
+    7
+}
+
+fn default_capacity_top() -> usize {
+    5
+}
+
+/// Memory and disk forecast with migration candidates:
+/// GET /api/capacity?days=7&top=5 (admin only)
+pub async fn get_capacity(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Query(params): Query<CapacityParams>,
+) -> Result<Json<tenement::CapacityReport>, (StatusCode, Json<ApiError>)> {
+    if auth.tenant_id.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ApiError::new("Capacity report requires admin token")),
+        ));
+    }
+    let Some(usage) = state.hypervisor.usage_store() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::new("Usage history is not being recorded")),
+        ));
+    };
+    let history = std::time::Duration::from_secs(params.days.saturating_mul(86400));
+    tenement::CapacityReport::load(
+        &usage,
+        &state.hypervisor.config().settings.data_dir,
+        history,
+        params.top,
+    )
+    .await
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to build capacity report: {:#}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })
+}
+
+/// Query parameters for the access log
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AccessLogParams {
+    pub process: Option<String>,
+    /// Instance ID
+    pub id: Option<String>,
+    pub status: Option<u16>,
+    /// Only requests at or after this time (millis since epoch)
+    pub since: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+/// Proxied requests, newest first:
+/// GET /api/access-logs?process=api&id=prod&status=502&since=...&limit=100
+pub async fn get_access_logs(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Query(params): Query<AccessLogParams>,
+) -> Result<Json<Vec<tenement::AccessLogEntry>>, (StatusCode, Json<ApiError>)> {
+    let Some(access_log) = state.hypervisor.access_log() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::new("Access log is not being recorded")),
+        ));
+    };
+    let mut query = tenement::AccessLogQuery {
+        process: params.process,
+        instance_id: params.id,
+        status: params.status,
+        since: params.since,
+        limit: params.limit,
+    };
+    // Tenant tokens can only see their own requests
+    if let Some(ref tenant) = auth.tenant_id {
+        query.instance_id = Some(tenant.clone());
+    }
+    access_log.query(&query).await.map(Json).map_err(|e| {
+        tracing::error!("Failed to query access log: {:#}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })
+}
+
+/// Query parameters for metered usage
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UsageParams {
+    /// `process:id` for one instance, or `process` for all of a service's
+    pub instance: Option<String>,
+    /// How far back to report, e.g. "30d" or "12h" (default 30d)
+    pub range: Option<String>,
+}
+
+/// Requests, bytes in/out and compute-seconds per instance, in total and per
+/// day: GET /api/usage?instance=api:prod&range=30d
+pub async fn get_usage(
+    State(state): State<AppState>,
+    axum::Extension(auth): axum::Extension<crate::server::AuthIdentity>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<tenement::UsageReport>, (StatusCode, Json<ApiError>)> {
+    let Some(usage) = state.hypervisor.usage_store() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ApiError::new("Usage is not being recorded")),
+        ));
+    };
+    let range = tenement::config::parse_duration_secs(params.range.as_deref().unwrap_or("30d"))
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ApiError::new(format!("Invalid range: {:#}", e))),
+            )
+        })?;
+    let (process, mut instance_id) = match params.instance.as_deref() {
+        Some(instance) if instance.contains(':') => {
+            let (process, id) = parse_instance_id(instance)?;
+            (Some(process), Some(id))
+        }
+        Some(process) => (Some(process.to_string()), None),
+        None => (None, None),
+    };
+    // Tenant tokens can only see their own usage
+    if let Some(ref tenant) = auth.tenant_id {
+        if let Some(ref id) = instance_id {
+            check_tenant_access(&auth, id)?;
+        }
+        instance_id = Some(tenant.clone());
+    }
+    tenement::UsageReport::load(
+        &usage,
+        std::time::Duration::from_secs(range),
+        process.as_deref(),
+        instance_id.as_deref(),
+    )
+    .await
+    .map(Json)
+    .map_err(|e| {
+        tracing::error!("Failed to load usage: {:#}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ApiError::new(format!("{:#}", e))),
+        )
+    })
+}
+
 // ===================
 // Helpers
 // ===================
@@ -385,6 +1243,40 @@ fn parse_instance_id(s: &str) -> Result<(String, String), (StatusCode, Json<ApiE
     Ok((parts[0].to_string(), parts[1].to_string()))
 }
 
+/// Version info: GET /api/version
+///
+/// What this server is running: version, build, features and which
+/// isolation runtimes work on this host.
+pub async fn get_version(State(state): State<AppState>) -> Json<VersionResponse> {
+    Json(VersionResponse {
+        version: crate::build_info::VERSION.to_string(),
+        git_sha: crate::build_info::GIT_SHA.to_string(),
+        build_date: crate::build_info::BUILD_DATE.to_string(),
+        features: crate::build_info::features()
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        runtimes: state.hypervisor.runtime_availability(),
+    })
+}
+
+/// Software bill of materials: GET /api/sbom
+pub async fn get_sbom() -> Json<SbomResponse> {
+    Json(SbomResponse {
+        name: "tenement".to_string(),
+        version: crate::build_info::VERSION.to_string(),
+        git_sha: crate::build_info::GIT_SHA.to_string(),
+        packages: crate::build_info::PACKAGES
+            .iter()
+            .map(|(name, version, source)| SbomPackage {
+                name: name.to_string(),
+                version: version.to_string(),
+                source: source.to_string(),
+            })
+            .collect(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,22 @@
+//! Build metadata embedded by build.rs
+
+include!(concat!(env!("OUT_DIR"), "/build_info.rs"));
+
+/// Crate version of this build
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Cargo features this binary was compiled with (including the tenement
+/// library's)
+pub fn features() -> Vec<&'static str> {
+    let mut features = tenement::enabled_features();
+    features.extend(
+        [
+            ("otlp", cfg!(feature = "otlp")),
+            ("http3", cfg!(feature = "http3")),
+        ]
+        .into_iter()
+        .filter(|(_, enabled)| *enabled)
+        .map(|(name, _)| name),
+    );
+    features
+}
@@ -6,18 +6,27 @@
 use anyhow::{Context, Result};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
 use crate::api_routes::{
-    ApiError, DeployRequest, DeployResponse, RouteRequest, RouteResponse, SpawnRequest,
-    SpawnResponse, WeightRequest, WeightResponse,
+    AdoptConfigRequest, AdoptConfigResponse, ApiError, ConfigDiffResponse, DeployRequest,
+    DeployResponse, DrainRequest, ForceParams, LimitsRequest, LimitsResponse, RouteRequest,
+    RouteResponse, ServiceDeployRequest, SpawnRequest, SpawnResponse, WeightRequest,
+    WeightResponse,
 };
 
 /// Token file name stored in data_dir alongside tenement.db
 const TOKEN_FILE: &str = "api_token";
 
+/// Server used when there is no `--server` and no usable admin socket
+const DEFAULT_SERVER: &str = "http://localhost:8080";
+
 /// HTTP client for the tenement API
 pub struct ApiClient {
     server_url: String,
+    /// Where requests go, for messages: the server URL or the admin socket
+    server: String,
     token: String,
     client: reqwest::Client,
 }
@@ -27,23 +36,59 @@ impl ApiClient {
     pub fn new(server_url: &str, token: String) -> Self {
         let server_url = server_url.trim_end_matches('/').to_string();
         Self {
+            server: server_url.clone(),
             server_url,
             token,
             client: reqwest::Client::new(),
         }
     }
 
-    /// Create an API client by auto-detecting the token.
+    /// Create an API client that talks to the daemon over its admin socket.
+    /// The socket's file permissions stand in for a token.
+    #[cfg(unix)]
+    pub fn unix(socket: &std::path::Path) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .unix_socket(socket)
+            .build()
+            .context("Failed to build admin socket client")?;
+        Ok(Self {
+            server_url: "http://localhost".to_string(),
+            server: format!("unix:{}", socket.display()),
+            token: String::new(),
+            client,
+        })
+    }
+
+    /// Where requests go: the server URL, or `unix:<path>` for the admin
+    /// socket
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Create an API client by auto-detecting the server and token.
+    ///
+    /// Without `server` (`--server` or TENEMENT_SERVER), the daemon's
+    /// `admin_socket` is used when it is configured and can be connected to,
+    /// and no token is needed. Otherwise requests go to `server` (default
+    /// http://localhost:8080).
     ///
     /// Token resolution order:
     /// 1. Explicit token passed via --token flag
     /// 2. TENEMENT_TOKEN environment variable
     /// 3. Token file at {data_dir}/api_token  (data_dir_override takes precedence over config)
     pub fn from_args(
-        server_url: &str,
+        server: Option<&str>,
         explicit_token: Option<String>,
         data_dir_override: Option<&std::path::Path>,
     ) -> Result<Self> {
+        #[cfg(unix)]
+        if server.is_none() {
+            if let Some(socket) = reachable_admin_socket(data_dir_override) {
+                return Self::unix(&socket);
+            }
+        }
+        let server_url = server.unwrap_or(DEFAULT_SERVER);
+
         let token = if let Some(t) = explicit_token {
             t
         } else if let Ok(t) = std::env::var("TENEMENT_TOKEN") {
@@ -80,11 +125,19 @@ impl ApiClient {
     // Instance operations
     // ===================
 
-    /// Spawn a new instance
-    pub async fn spawn(&self, process: &str, id: &str) -> Result<SpawnResponse> {
+    /// Spawn a new instance with env and label overrides for that instance
+    pub async fn spawn(
+        &self,
+        process: &str,
+        id: &str,
+        env: HashMap<String, String>,
+        labels: BTreeMap<String, String>,
+    ) -> Result<SpawnResponse> {
         let req = SpawnRequest {
             process: process.to_string(),
             id: id.to_string(),
+            env,
+            labels,
         };
         self.post("/api/instances/spawn", &req).await
     }
@@ -98,7 +151,7 @@ impl ApiClient {
             .bearer_auth(&self.token)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         if resp.status().is_success() {
             Ok(())
@@ -108,20 +161,43 @@ impl ApiClient {
         }
     }
 
-    /// Restart an instance
-    pub async fn restart(&self, instance: &str) -> Result<SpawnResponse> {
+    /// Restart an instance (`force` overrides maintenance windows)
+    pub async fn restart(&self, instance: &str, force: bool) -> Result<SpawnResponse> {
         let url = format!("{}/api/instances/{}/restart", self.server_url, instance);
         let resp = self
             .client
             .post(&url)
+            .query(&ForceParams { force })
             .bearer_auth(&self.token)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
 
+    /// Drain an instance (stop new traffic, wait for in-flight requests), then stop it
+    pub async fn drain(&self, instance: &str, timeout: u64) -> Result<()> {
+        let url = format!("{}/api/instances/{}/drain", self.server_url, instance);
+        let req = DrainRequest { timeout };
+        let resp = self
+            .client
+            .post(&url)
+            .bearer_auth(&self.token)
+            .json(&req)
+            .timeout(std::time::Duration::from_secs(timeout + 10))
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
+
+        if resp.status().is_success() {
+            Ok(())
+        } else {
+            let err = self.parse_error(resp).await;
+            anyhow::bail!("{}", err)
+        }
+    }
+
     /// Set traffic weight
     pub async fn set_weight(&self, instance: &str, weight: u8) -> Result<WeightResponse> {
         let url = format!("{}/api/instances/{}/weight", self.server_url, instance);
@@ -133,7 +209,26 @@ impl ApiClient {
             .json(&req)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
+
+        self.handle_response(resp).await
+    }
+
+    /// Change a running instance's resource limits
+    pub async fn set_limits(
+        &self,
+        instance: &str,
+        limits: &LimitsRequest,
+    ) -> Result<LimitsResponse> {
+        let url = format!("{}/api/instances/{}/limits", self.server_url, instance);
+        let resp = self
+            .client
+            .put(&url)
+            .bearer_auth(&self.token)
+            .json(limits)
+            .send()
+            .await
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
@@ -147,7 +242,7 @@ impl ApiClient {
             .bearer_auth(&self.token)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
@@ -159,12 +254,14 @@ impl ApiClient {
         version: &str,
         weight: u8,
         timeout: u64,
+        force: bool,
     ) -> Result<DeployResponse> {
         let req = DeployRequest {
             process: process.to_string(),
             version: version.to_string(),
             weight,
             timeout,
+            force,
         };
 
         let url = format!("{}/api/deploy", self.server_url);
@@ -176,11 +273,28 @@ impl ApiClient {
             .timeout(std::time::Duration::from_secs(timeout + 10))
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
 
+    /// Blue/green deploy: replace a service's running version set
+    pub async fn deploy_service(
+        &self,
+        process: &str,
+        version: &str,
+        command: Option<String>,
+        force: bool,
+    ) -> Result<tenement::ServiceVersions> {
+        let req = ServiceDeployRequest {
+            version: version.to_string(),
+            command,
+            force,
+        };
+        self.post(&format!("/api/services/{}/deploy", process), &req)
+            .await
+    }
+
     /// Atomic traffic swap between versions
     pub async fn route(&self, process: &str, from: &str, to: &str) -> Result<RouteResponse> {
         let req = RouteRequest {
@@ -191,9 +305,59 @@ impl ApiClient {
         self.post("/api/route", &req).await
     }
 
+    /// Map orphaned instances of a renamed service onto a configured one
+    pub async fn adopt_config(
+        &self,
+        from: &str,
+        to: &str,
+        replace: bool,
+    ) -> Result<AdoptConfigResponse> {
+        let req = AdoptConfigRequest {
+            from: from.to_string(),
+            to: to.to_string(),
+            replace,
+        };
+        self.post("/api/adopt-config", &req).await
+    }
+
+    /// Diff tenement.toml on disk against the server's running config
+    pub async fn config_diff(&self) -> Result<ConfigDiffResponse> {
+        self.get("/api/config/diff").await
+    }
+
+    /// Running instances spawned from a definition that differs from
+    /// tenement.toml on disk
+    pub async fn config_drift(&self) -> Result<Vec<tenement::InstanceDrift>> {
+        self.get("/api/config/drift").await
+    }
+
+    /// Make the server re-read tenement.toml and apply it
+    pub async fn reload(&self) -> Result<tenement::ReloadReport> {
+        self.post("/api/config/reload", &serde_json::json!({}))
+            .await
+    }
+
+    /// Memory and disk forecast from the last `days` of usage history
+    pub async fn capacity(&self, days: u64, top: usize) -> Result<tenement::CapacityReport> {
+        self.get(&format!("/api/capacity?days={}&top={}", days, top))
+            .await
+    }
+
     /// List all running instances
-    pub async fn list(&self) -> Result<Vec<serde_json::Value>> {
-        self.get("/api/instances").await
+    pub async fn list(&self, labels: &BTreeMap<String, String>) -> Result<Vec<serde_json::Value>> {
+        if labels.is_empty() {
+            return self.get("/api/instances").await;
+        }
+        let selector = labels
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join(",");
+        self.get(&format!(
+            "/api/instances?label={}",
+            urlencoding::encode(&selector)
+        ))
+        .await
     }
 
     // ===================
@@ -256,7 +420,7 @@ impl ApiClient {
             .bearer_auth(&self.token)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         if !resp.status().is_success() {
             let err = self.parse_error(resp).await;
@@ -309,7 +473,7 @@ impl ApiClient {
             .bearer_auth(&self.token)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
@@ -323,7 +487,7 @@ impl ApiClient {
             .json(body)
             .send()
             .await
-            .with_context(|| format!("Failed to connect to server at {}", self.server_url))?;
+            .with_context(|| format!("Failed to connect to server at {}", self.server))?;
 
         self.handle_response(resp).await
     }
@@ -349,6 +513,17 @@ impl ApiClient {
     }
 }
 
+/// The configured `admin_socket`, if a daemon is listening on it and this
+/// user may connect
+#[cfg(unix)]
+fn reachable_admin_socket(data_dir_override: Option<&std::path::Path>) -> Option<PathBuf> {
+    let config =
+        tenement::Config::load_with_override(data_dir_override.map(|p| p.to_path_buf())).ok()?;
+    let socket = config.settings.admin_socket?;
+    std::os::unix::net::UnixStream::connect(&socket).ok()?;
+    Some(socket)
+}
+
 /// Save a token to the data_dir for future CLI use.
 /// Called by `ten token-gen` after generating a new token.
 pub fn save_token_file(data_dir: &std::path::Path, token: &str) -> Result<()> {
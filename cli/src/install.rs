@@ -11,6 +11,7 @@ use crate::caddy;
 
 /// Paths for systemd installation
 const SYSTEMD_UNIT_PATH: &str = "/etc/systemd/system/tenement.service";
+const SYSTEMD_SOCKET_PATH: &str = "/etc/systemd/system/tenement.socket";
 const BINARY_INSTALL_PATH: &str = "/usr/local/bin/ten";
 const CONFIG_DIR: &str = "/etc/tenement";
 const DATA_DIR: &str = "/var/lib/tenement";
@@ -28,7 +29,9 @@ pub fn generate_unit(domain: &str, port: u16, config_path: &Path) -> String {
 [Unit]
 Description=Tenement Process Supervisor
 Documentation=https://github.com/anthropics/tenement
-After=network.target
+After=network.target tenement.socket
+# systemd holds the listening socket, so it stays open across restarts
+Requires=tenement.socket
 
 [Service]
 Type=simple
@@ -36,6 +39,10 @@ ExecStart={binary_path} serve --port {port} --domain {domain}
 WorkingDirectory={DATA_DIR}
 Restart=always
 RestartSec=5
+# SIGTERM only tenement itself: it drains requests and stops instances with
+# their stop_grace_period. Anything left after TimeoutStopSec gets SIGKILL.
+KillMode=mixed
+TimeoutStopSec=90
 
 # Environment
 Environment=TENEMENT_CONFIG={config_path_str}
@@ -54,6 +61,28 @@ WantedBy=multi-user.target
     )
 }
 
+/// Generate the systemd socket unit for `port`. systemd keeps the socket
+/// open while the service restarts, so connections queue in the kernel
+/// instead of being refused until the new daemon is listening.
+pub fn generate_socket_unit(domain: &str, port: u16) -> String {
+    format!(
+        r#"# Tenement listening socket
+# Generated by: ten install --domain {domain} --port {port}
+# Held open by systemd across `systemctl restart tenement`
+
+[Unit]
+Description=Tenement listening socket
+
+[Socket]
+ListenStream={port}
+NoDelay=true
+
+[Install]
+WantedBy=sockets.target
+"#
+    )
+}
+
 /// Install tenement as a systemd service
 /// Optionally installs Caddy as a reverse proxy with automatic TLS
 pub fn install(
@@ -92,6 +121,7 @@ pub fn install(
     // Generate unit file content
     let dest_config = PathBuf::from(CONFIG_DIR).join("tenement.toml");
     let unit_content = generate_unit(&domain, port, &dest_config);
+    let socket_content = generate_socket_unit(&domain, port);
 
     if dry_run {
         println!("=== Dry run - would perform these actions ===\n");
@@ -99,7 +129,10 @@ pub fn install(
         println!("2. Create directory {}", CONFIG_DIR);
         println!("3. Copy config to {}", dest_config.display());
         println!("4. Create directory {}", DATA_DIR);
-        println!("5. Create systemd unit at {}", SYSTEMD_UNIT_PATH);
+        println!(
+            "5. Create systemd units at {} and {}",
+            SYSTEMD_UNIT_PATH, SYSTEMD_SOCKET_PATH
+        );
         if with_caddy {
             println!("6. Install Caddy (if not present)");
             println!("7. Generate Caddyfile at /etc/caddy/Caddyfile");
@@ -113,6 +146,8 @@ pub fn install(
         }
         println!("\n=== Generated systemd unit ===\n");
         println!("{}", unit_content);
+        println!("\n=== Generated systemd socket ===\n");
+        println!("{}", socket_content);
         if with_caddy {
             println!("\n=== Would generate Caddyfile (see `ten caddy` for preview) ===\n");
         }
@@ -156,19 +191,22 @@ pub fn install(
     println!("  Creating data directory {}...", DATA_DIR);
     std::fs::create_dir_all(DATA_DIR).context("Failed to create data directory")?;
 
-    // Step 4: Write systemd unit
+    // Step 4: Write systemd units
     println!("  Creating systemd unit at {}...", SYSTEMD_UNIT_PATH);
     std::fs::write(SYSTEMD_UNIT_PATH, &unit_content).context("Failed to write systemd unit")?;
+    println!("  Creating systemd socket at {}...", SYSTEMD_SOCKET_PATH);
+    std::fs::write(SYSTEMD_SOCKET_PATH, &socket_content)
+        .context("Failed to write systemd socket")?;
 
     // Step 5: Reload systemd and enable service
     println!("  Reloading systemd daemon...");
     run_command("systemctl", &["daemon-reload"])?;
 
     println!("  Enabling tenement service...");
-    run_command("systemctl", &["enable", "tenement"])?;
+    run_command("systemctl", &["enable", "tenement.socket", "tenement"])?;
 
     println!("  Starting tenement service...");
-    run_command("systemctl", &["start", "tenement"])?;
+    run_command("systemctl", &["start", "tenement.socket", "tenement"])?;
 
     // Wait a moment and check status
     std::thread::sleep(std::time::Duration::from_secs(1));
@@ -238,15 +276,17 @@ pub fn uninstall() -> Result<()> {
 
     // Stop and disable service
     println!("  Stopping tenement service...");
-    let _ = run_command("systemctl", &["stop", "tenement"]);
+    let _ = run_command("systemctl", &["stop", "tenement.socket", "tenement"]);
 
     println!("  Disabling tenement service...");
-    let _ = run_command("systemctl", &["disable", "tenement"]);
+    let _ = run_command("systemctl", &["disable", "tenement.socket", "tenement"]);
 
-    // Remove unit file
-    if Path::new(SYSTEMD_UNIT_PATH).exists() {
-        println!("  Removing systemd unit...");
-        std::fs::remove_file(SYSTEMD_UNIT_PATH)?;
+    // Remove unit files
+    for path in [SYSTEMD_UNIT_PATH, SYSTEMD_SOCKET_PATH] {
+        if Path::new(path).exists() {
+            println!("  Removing {}...", path);
+            std::fs::remove_file(path)?;
+        }
     }
 
     // Reload systemd
@@ -355,6 +395,19 @@ mod tests {
         assert!(unit.contains("After=network.target"));
     }
 
+    #[test]
+    fn test_generate_socket_unit() {
+        let config_path = PathBuf::from("/etc/tenement/tenement.toml");
+        let unit = generate_unit("example.com", 8080, &config_path);
+        let socket = generate_socket_unit("example.com", 8080);
+
+        // The service gets its listener from the socket, which survives restarts
+        assert!(unit.contains("Requires=tenement.socket"));
+        assert!(socket.contains("[Socket]"));
+        assert!(socket.contains("ListenStream=8080"));
+        assert!(socket.contains("WantedBy=sockets.target"));
+    }
+
     #[test]
     fn test_install_missing_config() {
         let result = install(
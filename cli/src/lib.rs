@@ -3,6 +3,9 @@
 //! Exposes server, dashboard, API routes, and client modules.
 
 pub mod api_routes;
+pub mod build_info;
 pub mod client;
 pub mod dashboard;
 pub mod server;
+pub mod socket_activation;
+pub mod tls;
@@ -8,19 +8,19 @@ use tenement_cli::server;
 
 mod caddy;
 mod install;
+mod progress;
+mod self_update;
+
+use progress::{Progress, ProgressArgs};
 
 #[derive(Parser)]
 #[command(name = "tenement")]
 #[command(author, version, about = "Hyperlightweight process hypervisor")]
 struct Cli {
-    /// Server URL for CLI commands (default: http://localhost:8080)
-    #[arg(
-        long,
-        default_value = "http://localhost:8080",
-        global = true,
-        env = "TENEMENT_SERVER"
-    )]
-    server: String,
+    /// Server URL for CLI commands (default: the configured admin_socket
+    /// when the daemon is listening on it, else http://localhost:8080)
+    #[arg(long, global = true, env = "TENEMENT_SERVER")]
+    server: Option<String>,
 
     /// API token (overrides TENEMENT_TOKEN env var and token file)
     #[arg(long, global = true)]
@@ -30,10 +30,25 @@ struct Cli {
     #[arg(long, global = true, env = "TENEMENT_DATA_DIR")]
     data_dir: Option<PathBuf>,
 
+    /// Config profile to apply ([profile.NAME] in tenement.toml)
+    #[arg(long, global = true, env = "TENEMENT_PROFILE")]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Check tenement.toml and its includes without starting anything
+    Validate {
+        /// Also reject keys tenement doesn't know, such as a misspelled
+        /// `memroy_limit_mb`, instead of ignoring them
+        #[arg(long)]
+        strict: bool,
+    },
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Start the HTTP server with dashboard and reverse proxy
@@ -50,28 +65,63 @@ enum Commands {
         /// Email for Let's Encrypt registration (required with --tls)
         #[arg(long, requires = "tls")]
         email: Option<String>,
+        /// Extra domain to get its own certificate for (repeatable)
+        #[arg(long = "tls-domain")]
+        tls_domains: Vec<String>,
         /// Use Let's Encrypt staging environment (for testing, avoids rate limits)
         #[arg(long)]
         staging: bool,
+        /// Serve the dashboard and API only on this address (e.g.
+        /// 127.0.0.1:9090), not the public listener. Overrides api_listen.
+        #[arg(long)]
+        api_listen: Option<std::net::SocketAddr>,
+        /// Don't serve the web dashboard (the API is unaffected)
+        #[arg(long)]
+        no_dashboard: bool,
     },
     /// Spawn a new process instance (e.g., ten spawn api:prod)
     Spawn {
         /// Instance identifier (process:id)
         instance: String,
+        /// Extra environment variable for this instance (KEY=VALUE, repeatable)
+        #[arg(long = "env", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Label for this instance (key=value, repeatable)
+        #[arg(long = "label", value_parser = tenement::instance::parse_label)]
+        labels: Vec<(String, String)>,
     },
     /// Stop a running instance (e.g., ten stop api:prod)
     Stop {
         /// Instance identifier (process:id)
         instance: String,
     },
+    /// Drain an instance, then stop it (e.g., ten drain api:prod)
+    Drain {
+        /// Instance identifier (process:id)
+        instance: String,
+        /// Seconds to wait for in-flight requests before stopping (default 30)
+        #[arg(long, default_value = "30")]
+        timeout: u64,
+        #[command(flatten)]
+        progress: ProgressArgs,
+    },
     /// Restart an instance (e.g., ten restart api:prod)
     Restart {
         /// Instance identifier (process:id)
         instance: String,
+        /// Restart even outside the service's maintenance windows (audited)
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        progress: ProgressArgs,
     },
     /// List running instances
     #[command(alias = "ls")]
-    Ps,
+    Ps {
+        /// Only show matching instances (label=key=value, repeatable)
+        #[arg(long = "filter", value_parser = parse_ps_filter)]
+        filters: Vec<(String, String)>,
+    },
     /// Check health of an instance (e.g., ten health api:prod)
     Health {
         /// Instance identifier (process:id)
@@ -84,6 +134,40 @@ enum Commands {
         /// Traffic weight (0-100, default 100)
         weight: u8,
     },
+    /// Change a running instance's resource limits without restarting it
+    /// (e.g., ten limit api:prod --memory 512). 0 lifts a limit; a restart
+    /// goes back to the configured limits.
+    Limit {
+        /// Instance identifier (process:id)
+        instance: String,
+        /// Memory limit in MB
+        #[arg(long)]
+        memory: Option<u32>,
+        /// Memory soft limit in MB
+        #[arg(long)]
+        memory_high: Option<u32>,
+        /// Swap limit in MB (0 = no swap)
+        #[arg(long)]
+        swap: Option<u32>,
+        /// CPU weight (1-10000)
+        #[arg(long)]
+        cpu: Option<u32>,
+        /// Maximum processes and threads
+        #[arg(long)]
+        pids: Option<u32>,
+        /// Read bandwidth in bytes/s
+        #[arg(long)]
+        io_read_bps: Option<u64>,
+        /// Write bandwidth in bytes/s
+        #[arg(long)]
+        io_write_bps: Option<u64>,
+        /// Read and write IO operations/s, each
+        #[arg(long)]
+        io_iops: Option<u64>,
+        /// CPUs to pin to, e.g. 0-3,6
+        #[arg(long)]
+        cpuset: Option<String>,
+    },
     /// Deploy a new version and wait for it to be healthy
     Deploy {
         /// Instance identifier (process:version, e.g., api:v2)
@@ -94,6 +178,17 @@ enum Commands {
         /// Health check timeout in seconds (default 30)
         #[arg(long, default_value = "30")]
         timeout: u64,
+        /// Blue/green: replace the service's running version once this one is ready
+        #[arg(long, conflicts_with = "weight")]
+        replace: bool,
+        /// Command to run for this version (with --replace)
+        #[arg(long, requires = "replace")]
+        command: Option<String>,
+        /// Deploy even outside the service's maintenance windows (audited)
+        #[arg(long)]
+        force: bool,
+        #[command(flatten)]
+        progress: ProgressArgs,
     },
     /// Atomically swap traffic from one version to another (blue/green)
     Route {
@@ -133,7 +228,44 @@ enum Commands {
         command: Option<String>,
     },
     /// Show config
-    Config,
+    Config {
+        #[command(subcommand)]
+        action: Option<ConfigAction>,
+    },
+    /// Reload tenement.toml into the running server
+    Reload {
+        /// Print what would change without applying it
+        #[arg(long)]
+        diff: bool,
+    },
+    /// List running instances that need a restart to pick up tenement.toml
+    Diff {
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Map instances orphaned by a service rename onto a configured service
+    AdoptConfig {
+        /// Old service name (the orphaned instances' process)
+        from: String,
+        /// Service in tenement.toml to map them onto
+        to: String,
+        /// Stop a running instance with the same ID and move its data dir aside
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Forecast when memory and disk run out, and which tenants to migrate first
+    Capacity {
+        /// Days of usage history to base the forecast on
+        #[arg(long, default_value = "7")]
+        days: u64,
+        /// Number of instances to suggest migrating
+        #[arg(long, default_value = "5")]
+        top: usize,
+        /// Print the report as JSON (for migration tooling)
+        #[arg(long)]
+        json: bool,
+    },
     /// Generate a new API token (admin or tenant-scoped)
     TokenGen {
         /// Generate a tenant-scoped token (can only access this tenant's instances/logs)
@@ -143,6 +275,11 @@ enum Commands {
         #[arg(long)]
         description: Option<String>,
     },
+    /// Print a `user:hash` line for a service's auth users_file
+    HashPassword {
+        /// User name
+        user: String,
+    },
     /// Install tenement as a systemd service
     Install {
         /// Domain for the service (e.g., example.com)
@@ -167,6 +304,22 @@ enum Commands {
     },
     /// Uninstall tenement systemd service
     Uninstall,
+    /// Update this binary to a signed release and restart the service
+    SelfUpdate {
+        /// Release to install (e.g., 0.3.0; default: latest)
+        #[arg(long)]
+        version: Option<String>,
+        /// minisign public key (base64 or .pub file) to verify against
+        /// (default: the key this binary was built with)
+        #[arg(long)]
+        public_key: Option<String>,
+        /// Reinstall even if already on that version
+        #[arg(long)]
+        force: bool,
+        /// Don't restart the systemd service after installing
+        #[arg(long)]
+        no_restart: bool,
+    },
     /// Generate Caddyfile for HTTPS reverse proxy
     Caddy {
         /// Domain for the service (e.g., example.com)
@@ -203,91 +356,297 @@ async fn main() -> Result<()> {
             domain,
             tls,
             email,
+            tls_domains,
             staging,
+            api_listen,
+            no_dashboard,
         } => {
-            cmd_serve(port, domain, tls, email, staging, cli.data_dir).await?;
+            let tls = TlsFlags {
+                enabled: tls,
+                email,
+                domains: tls_domains,
+                staging,
+            };
+            let listen = ListenFlags {
+                port,
+                api_listen,
+                no_dashboard,
+            };
+            cmd_serve(listen, domain, tls, cli.data_dir, cli.profile).await?;
         }
-        Commands::Spawn { instance } => {
+        Commands::Spawn {
+            instance,
+            env,
+            labels,
+        } => {
             let (process, id) = parse_instance(&instance)?;
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
-            let resp = client.spawn(&process, &id).await?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let resp = client
+                .spawn(
+                    &process,
+                    &id,
+                    env.into_iter().collect(),
+                    labels.into_iter().collect(),
+                )
+                .await?;
             println!("Spawned {}", resp.instance);
             if let Some(port) = resp.port {
                 println!("Listening on 127.0.0.1:{}", port);
             }
         }
         Commands::Stop { instance } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
             client.stop(&instance).await?;
             println!("Stopped {}", instance);
         }
-        Commands::Restart { instance } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
-            let resp = client.restart(&instance).await?;
-            println!("Restarted {}", resp.instance);
+        Commands::Drain {
+            instance,
+            timeout,
+            progress,
+        } => {
+            let (process, _) = parse_instance(&instance)?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let mut progress = Progress::new(
+                progress,
+                vec![format!("Draining {} (timeout: {}s)", instance, timeout)],
+            )
+            .deadline(std::time::Duration::from_secs(timeout));
+            progress
+                .run(&client, &process, |_| 0, client.drain(&instance, timeout))
+                .await?;
+            progress.done(
+                &[format!("Stopped {}", instance)],
+                serde_json::json!({"instance": instance, "status": "stopped"}),
+            );
+        }
+        Commands::Restart {
+            instance,
+            force,
+            progress,
+        } => {
+            let (process, _) = parse_instance(&instance)?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let mut progress = Progress::new(progress, vec![format!("Restarting {}", instance)])
+                .targets(vec![instance.clone()]);
+            let resp = progress
+                .run(&client, &process, |_| 0, client.restart(&instance, force))
+                .await?;
+            progress.done(
+                &[format!("Restarted {}", resp.instance)],
+                serde_json::to_value(&resp)?,
+            );
         }
-        Commands::Ps => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
-            let instances = client.list().await?;
+        Commands::Ps { filters } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let instances = client.list(&filters.into_iter().collect()).await?;
             if instances.is_empty() {
                 println!("No running instances");
-                println!("Server: {}", cli.server);
+                println!("Server: {}", client.server());
             } else {
                 println!(
-                    "{:<20} {:<20} {:<10} {:<10} {:<8} {:<6}",
+                    "{:<20} {:<20} {:<10} {:<10} {:<8} {:<6} LABELS",
                     "INSTANCE", "LISTEN", "UPTIME", "IDLE", "HEALTH", "WEIGHT"
                 );
                 for info in &instances {
                     let id = info["id"].as_str().unwrap_or("?");
                     let uptime = info["uptime_secs"].as_u64().unwrap_or(0);
-                    let health = info["health"].as_str().unwrap_or("?");
+                    // Kept down by its restart policy: show why
+                    let health = match info["exit_code"].as_i64() {
+                        Some(code) => format!("exit:{}", code),
+                        // Its service was renamed or removed: see adopt-config
+                        None if info["status"] == "orphaned" => "orphaned".to_string(),
+                        None => info["health"].as_str().unwrap_or("?").to_string(),
+                    };
                     let weight = info["weight"].as_u64().unwrap_or(0);
                     let idle = info["idle_secs"].as_u64().unwrap_or(0);
                     let listen = info["socket"].as_str().unwrap_or("?");
+                    let labels = info["labels"]
+                        .as_object()
+                        .map(|labels| {
+                            labels
+                                .iter()
+                                .map(|(k, v)| format!("{}={}", k, v.as_str().unwrap_or("")))
+                                .collect::<Vec<_>>()
+                                .join(",")
+                        })
+                        .unwrap_or_default();
 
                     println!(
-                        "{:<20} {:<20} {:<10} {:<10} {:<8} {:<6}",
+                        "{:<20} {:<20} {:<10} {:<10} {:<8} {:<6} {}",
                         id,
                         listen,
                         format_uptime(uptime),
                         format_uptime(idle),
                         health,
-                        weight
+                        weight,
+                        labels
                     );
                 }
                 println!();
-                println!("{} instance(s) running on {}", instances.len(), cli.server);
+                println!(
+                    "{} instance(s) running on {}",
+                    instances.len(),
+                    client.server()
+                );
             }
         }
         Commands::Health { instance } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
             let resp = client.health(&instance).await?;
             let health = resp["health"].as_str().unwrap_or("unknown");
             println!("{}: {}", instance, health);
         }
         Commands::Weight { instance, weight } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
             let resp = client.set_weight(&instance, weight).await?;
             println!("Set {} weight to {}", resp.instance, resp.weight);
         }
+        Commands::Limit {
+            instance,
+            memory,
+            memory_high,
+            swap,
+            cpu,
+            pids,
+            io_read_bps,
+            io_write_bps,
+            io_iops,
+            cpuset,
+        } => {
+            let limits = tenement_cli::api_routes::LimitsRequest {
+                memory_limit_mb: memory,
+                memory_high_mb: memory_high,
+                memory_swap_max_mb: swap,
+                cpu_shares: cpu,
+                max_pids: pids,
+                io_read_bps,
+                io_write_bps,
+                io_iops,
+                cpuset,
+            };
+            if limits.is_empty() {
+                anyhow::bail!(
+                    "Nothing to change: pass --memory, --memory-high, --swap, --cpu, --pids, --io-read-bps, --io-write-bps, --io-iops or --cpuset"
+                );
+            }
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let resp = client.set_limits(&instance, &limits).await?;
+            println!("Updated {} limits", resp.instance);
+        }
         Commands::Deploy {
             instance,
             weight,
             timeout,
+            replace,
+            command,
+            force,
+            progress,
         } => {
             let (process, version) = parse_instance(&instance)?;
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
-            println!("Deploying {}:{} with weight {}", process, version, weight);
-            println!("Waiting for health check (timeout: {}s)...", timeout);
-
-            let resp = client.deploy(&process, &version, weight, timeout).await?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            if replace {
+                // The server starts as many instances as the running version has
+                let replicas = progress::list_process(&client, &process)
+                    .await?
+                    .iter()
+                    .filter(|i| {
+                        i["labels"]["version"]
+                            .as_str()
+                            .is_some_and(|v| v != version)
+                    })
+                    .count()
+                    .max(1);
+                let green: Vec<String> = if replicas == 1 {
+                    vec![format!("{}:{}", process, version)]
+                } else {
+                    (1..=replicas)
+                        .map(|n| format!("{}:{}-{}", process, version, n))
+                        .collect()
+                };
+                let mut progress = Progress::new(
+                    progress,
+                    vec![
+                        format!(
+                            "Starting {} instance(s) of {} version {} (blue/green)",
+                            replicas, process, version
+                        ),
+                        "Switching traffic and draining the previous version".to_string(),
+                    ],
+                )
+                .targets(green.clone());
+                // Green instances stay at weight 0 until the switch
+                let stage = |instances: &[serde_json::Value]| {
+                    let switched = instances.iter().any(|i| {
+                        i["id"]
+                            .as_str()
+                            .is_some_and(|id| green.iter().any(|g| g == id))
+                            && i["weight"].as_u64().unwrap_or(0) > 0
+                    });
+                    usize::from(switched)
+                };
+                let versions = progress
+                    .run(
+                        &client,
+                        &process,
+                        stage,
+                        client.deploy_service(&process, &version, command, force),
+                    )
+                    .await?;
+                let mut lines = vec![format!(
+                    "Deployed {} version {}",
+                    process,
+                    versions.current.as_deref().unwrap_or("-")
+                )];
+                if let Some(previous) = &versions.previous {
+                    lines.push(format!("Replaced: {}", previous));
+                }
+                lines.push(format!("Instances: {}", versions.instances.join(", ")));
+                progress.done(&lines, serde_json::to_value(&versions)?);
+                return Ok(());
+            }
+            let id = format!("{}:{}", process, version);
+            let mut progress = Progress::new(
+                progress,
+                vec![
+                    format!("Spawning {} with weight {}", id, weight),
+                    format!("Waiting for health check (timeout: {}s)", timeout),
+                ],
+            )
+            .targets(vec![id.clone()])
+            .deadline(std::time::Duration::from_secs(timeout));
+            let stage = |instances: &[serde_json::Value]| {
+                usize::from(instances.iter().any(|i| i["id"] == id.as_str()))
+            };
+            let resp = progress
+                .run(
+                    &client,
+                    &process,
+                    stage,
+                    client.deploy(&process, &version, weight, timeout, force),
+                )
+                .await?;
 
-            println!("Deployed {}", resp.instance);
-            println!("Weight: {}", resp.weight);
-            println!("Status: {}", resp.status);
+            progress.done(
+                &[
+                    format!("Deployed {}", resp.instance),
+                    format!("Weight: {}", resp.weight),
+                    format!("Status: {}", resp.status),
+                ],
+                serde_json::to_value(&resp)?,
+            );
         }
         Commands::Route { process, from, to } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
             let resp = client.route(&process, &from, &to).await?;
 
             println!(
@@ -304,7 +663,8 @@ async fn main() -> Result<()> {
             limit,
             follow,
         } => {
-            let client = ApiClient::from_args(&cli.server, cli.token, cli.data_dir.as_deref())?;
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
             let (process, id) = match &instance {
                 Some(inst) => {
                     let (p, i) = parse_instance(inst)?;
@@ -354,8 +714,29 @@ async fn main() -> Result<()> {
         Commands::Init { name, command } => {
             cmd_init(name, command)?;
         }
-        Commands::Config => {
-            let config = Config::load_with_override(cli.data_dir)?;
+        Commands::Config {
+            action: Some(ConfigAction::Validate { strict }),
+        } => {
+            let path = Config::find_config_file()?;
+            let profile = cli.profile.as_deref();
+            let config = if strict {
+                Config::load_from_path_strict(&path, profile)?
+            } else {
+                Config::load_from_path_with_profile(&path, profile)?
+            };
+            println!(
+                "{} is valid: {} services, {} instances",
+                path.display(),
+                config.service.len(),
+                config.get_instances_to_spawn().len()
+            );
+        }
+        Commands::Config { action: None } => {
+            let mut config = Config::load_profile(cli.profile.as_deref())?;
+            config.apply_data_dir_override(cli.data_dir)?;
+            if let Some(profile) = &config.active_profile {
+                println!("Profile: {}", profile);
+            }
             println!("Data dir: {:?}", config.settings.data_dir);
             println!(
                 "Health interval: {}s",
@@ -369,11 +750,93 @@ async fn main() -> Result<()> {
                 if let Some(health) = &svc.health {
                     println!("    health: {}", health);
                 }
+                if let Some(health_cmd) = &svc.health_cmd {
+                    println!("    health_cmd: {}", health_cmd);
+                }
+                if svc.has_health_check() {
+                    println!(
+                        "    health_interval: {}s (timeout {}s, initial delay {}s)",
+                        config.health_interval(svc),
+                        svc.health_timeout,
+                        svc.health_initial_delay
+                    );
+                }
                 if let Some(idle) = svc.idle_timeout {
                     println!("    idle_timeout: {}s", idle);
                 }
             }
         }
+        Commands::AdoptConfig { from, to, replace } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let resp = client.adopt_config(&from, &to, replace).await?;
+            for instance in &resp.instances {
+                println!("Adopted {}", instance);
+            }
+        }
+        Commands::Capacity { days, top, json } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let report = client.capacity(days, top).await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                print_capacity(&report);
+            }
+        }
+        Commands::Reload { diff: false } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let report = client.reload().await?;
+            print!("{}", report.diff);
+            for (heading, instances) in [
+                ("Rolling restart:", &report.restarting),
+                ("Stopped:", &report.stopped),
+                ("Spawned:", &report.spawned),
+            ] {
+                if !instances.is_empty() {
+                    println!();
+                    println!("{}", heading);
+                    for instance in instances {
+                        println!("  {}", instance);
+                    }
+                }
+            }
+            if !report.needs_restart.is_empty() {
+                println!();
+                println!(
+                    "Changes to [{}] apply after a server restart",
+                    report.needs_restart.join("], [")
+                );
+            }
+        }
+        Commands::Reload { diff: true } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let resp = client.config_diff().await?;
+            print!("{}", resp.diff);
+            if !resp.affected_instances.is_empty() {
+                println!();
+                println!("Affected instances:");
+                for instance in &resp.affected_instances {
+                    println!("  {}", instance);
+                }
+            }
+        }
+        Commands::Diff { json } => {
+            let client =
+                ApiClient::from_args(cli.server.as_deref(), cli.token, cli.data_dir.as_deref())?;
+            let drift = client.config_drift().await?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&drift)?);
+            } else if drift.is_empty() {
+                println!("All instances match tenement.toml");
+            } else {
+                for instance in &drift {
+                    print!("{}", instance);
+                }
+            }
+        }
         Commands::TokenGen {
             tenant,
             description,
@@ -416,6 +879,20 @@ async fn main() -> Result<()> {
                 println!("Use it in the Authorization header: Bearer {}", token);
             }
         }
+        Commands::HashPassword { user } => {
+            if user.is_empty() || user.contains(':') {
+                anyhow::bail!("User name must be non-empty and can't contain ':'");
+            }
+            // Read from stdin so the password stays out of shell history
+            eprintln!("Password for {} (read from stdin):", user);
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            let password = password.trim_end_matches(['\r', '\n']);
+            if password.is_empty() {
+                anyhow::bail!("Password is empty");
+            }
+            println!("{}:{}", user, tenement::hash_token(password)?);
+        }
         Commands::Install {
             domain,
             port,
@@ -429,6 +906,20 @@ async fn main() -> Result<()> {
         Commands::Uninstall => {
             install::uninstall()?;
         }
+        Commands::SelfUpdate {
+            version,
+            public_key,
+            force,
+            no_restart,
+        } => {
+            self_update::run(self_update::UpdateOptions {
+                version,
+                public_key,
+                force,
+                restart: !no_restart,
+            })
+            .await?;
+        }
         Commands::Caddy {
             domain,
             port,
@@ -444,23 +935,97 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Names for the main certificate besides the domain: `alt_names`, plus,
+/// when DNS-01 is configured, wildcards covering subdomain routing
+/// (`*.{domain}` and `*.{service}.{domain}`)
+fn tls_alt_names(config: &Config, domain: &str) -> Vec<String> {
+    let mut names = config.settings.tls.alt_names.clone();
+    if config.settings.tls.dns.is_some() {
+        let wildcards = std::iter::once(format!("*.{}", domain))
+            .chain(config.service.keys().map(|s| format!("*.{}.{}", s, domain)));
+        for name in wildcards {
+            if names.contains(&name) {
+                continue;
+            }
+            match tenement::config::validate_tls_domain(&name, true) {
+                Ok(()) => names.push(name),
+                Err(e) => tracing::warn!("Not adding {} to the certificate: {:#}", name, e),
+            }
+        }
+    }
+    names
+}
+
+/// Domains with a certificate of their own: `[settings.tls] domains`, then
+/// `--tls-domain`, then `[domains]` custom domains not already listed
+fn tls_extra_domains(config: &Config, flags: Vec<String>) -> Vec<String> {
+    let mut names = [config.settings.tls.domains.clone(), flags].concat();
+    let mut mapped: Vec<&String> = config.domains.keys().collect();
+    mapped.sort();
+    for name in mapped {
+        if !names.contains(name) && !config.settings.tls.alt_names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// TLS flags given to `ten serve`
+struct TlsFlags {
+    enabled: bool,
+    email: Option<String>,
+    domains: Vec<String>,
+    staging: bool,
+}
+
+/// Listener flags given to `ten serve`
+struct ListenFlags {
+    port: u16,
+    api_listen: Option<std::net::SocketAddr>,
+    no_dashboard: bool,
+}
+
 /// Start the server (this is the only command that creates a Hypervisor directly)
 async fn cmd_serve(
-    port: u16,
+    listen_flags: ListenFlags,
     domain: String,
-    tls: bool,
-    email: Option<String>,
-    staging: bool,
+    tls_flags: TlsFlags,
     data_dir_override: Option<PathBuf>,
+    profile: Option<String>,
 ) -> Result<()> {
-    let config = Config::load_with_override(data_dir_override)?;
+    let TlsFlags {
+        enabled: tls,
+        email,
+        domains: tls_domains,
+        staging,
+    } = tls_flags;
+    let mut config = Config::load_profile(profile.as_deref())?;
+    config.apply_data_dir_override(data_dir_override)?;
+    if let Some(profile) = &config.active_profile {
+        tracing::info!("Using config profile '{}'", profile);
+    }
+    let listen = server::ListenOptions {
+        port: listen_flags.port,
+        api: listen_flags.api_listen.or(config.settings.api_listen),
+        dashboard: config.settings.dashboard && !listen_flags.no_dashboard,
+    };
     let db_path = config.settings.data_dir.join("tenement.db");
     let pool = init_db(&db_path).await?;
     let config_store = std::sync::Arc::new(ConfigStore::new(pool.clone()));
     let state_store = std::sync::Arc::new(tenement::StateStore::new(pool.clone()));
     let deploy_log = std::sync::Arc::new(tenement::DeployLogStore::new(pool.clone()));
+    let log_store = tenement::LogStore::new(pool.clone());
+    let tenant_keys = std::sync::Arc::new(tenement::TenantKeyStore::new(pool.clone()));
+    let tenant_secrets = std::sync::Arc::new(tenement::TenantSecretStore::new(pool.clone()));
+    let usage_store = std::sync::Arc::new(tenement::UsageStore::new(pool.clone()));
+    let access_log = tenement::AccessLogStore::new(pool.clone());
     let tenant_tokens = std::sync::Arc::new(tenement::TenantTokenStore::new(pool));
 
+    // Fail fast on a missing or unsafe master key rather than at first spawn
+    if let Some(ref key_file) = config.settings.encryption_key_file {
+        tenement::encryption::load_master_key(key_file)?;
+    }
+
     let tls_options = if tls {
         let acme_email = email
             .or_else(|| config.settings.tls.acme_email.clone())
@@ -491,6 +1056,10 @@ async fn cmd_serve(
             enabled: true,
             email: acme_email,
             domain: domain.clone(),
+            alt_names: tls_alt_names(&config, &domain),
+            domains: tls_extra_domains(&config, tls_domains),
+            dns: config.settings.tls.dns.clone(),
+            client_ca: config.settings.tls.client_ca.clone(),
             cache_dir,
             staging: staging || config.settings.tls.staging,
             https_port: config.settings.tls.https_port,
@@ -530,7 +1099,11 @@ async fn cmd_serve(
         Some(server::TlsOptions {
             enabled: true,
             email: acme_email,
+            alt_names: tls_alt_names(&config, &tls_domain),
             domain: tls_domain,
+            domains: tls_extra_domains(&config, tls_domains),
+            dns: config.settings.tls.dns.clone(),
+            client_ca: config.settings.tls.client_ca.clone(),
             cache_dir,
             staging: staging || config.settings.tls.staging,
             https_port: config.settings.tls.https_port,
@@ -541,6 +1114,15 @@ async fn cmd_serve(
     };
 
     if let Some(ref tls_opts) = tls_options {
+        for name in &tls_opts.domains {
+            tenement::config::validate_tls_domain(name, tls_opts.dns.is_some())?;
+        }
+        let mut seen = std::collections::HashSet::new();
+        for name in tls_opts.certificates().concat() {
+            if !seen.insert(name.clone()) {
+                anyhow::bail!("TLS domain '{}' is listed more than once", name);
+            }
+        }
         if tls_opts.http_port == tls_opts.https_port {
             anyhow::bail!(
                 "HTTP port ({}) and HTTPS port ({}) cannot be the same.\n\
@@ -551,11 +1133,58 @@ async fn cmd_serve(
         }
     }
 
-    let hypervisor = Hypervisor::with_state_store(config, state_store);
+    let check_interval = std::time::Duration::from_secs(config.settings.health_check_interval);
+    let hypervisor = Hypervisor::with_state_store(config, state_store)
+        .with_tenant_keys(tenant_keys)
+        .with_secrets(tenant_secrets)
+        .with_usage(usage_store.clone())
+        .with_access_log(access_log.clone());
+
+    // Persist logs to SQLite, pausing while the data dir's disk is full
+    log_store.record(&hypervisor.log_buffer());
+    tenement::DiskGuard::new(&hypervisor.config(), log_store.clone(), hypervisor.alerts())
+        .start(check_interval);
+
+    // Delete persisted logs older than each service's log_retention, and
+    // access log entries older than access_log_retention. The config is read
+    // on every pass so reloads take effect.
+    {
+        let hypervisor = hypervisor.clone();
+        tokio::spawn(async move {
+            loop {
+                let retention = hypervisor.config().log_retention();
+                if !retention.is_empty() {
+                    match log_store.rotate_services(&retention).await {
+                        Ok(0) => {}
+                        Ok(deleted) => {
+                            tracing::info!("Deleted {} log line(s) past retention", deleted)
+                        }
+                        Err(e) => tracing::warn!("Failed to rotate logs: {:#}", e),
+                    }
+                }
+                let retention = hypervisor.config().settings.access_log_retention;
+                if retention > 0 {
+                    let max_age = std::time::Duration::from_secs(retention);
+                    if let Err(e) = access_log.rotate(max_age).await {
+                        tracing::warn!("Failed to rotate access log: {:#}", e);
+                    }
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            }
+        });
+    }
+
+    // Sample per-instance memory and disk for `ten capacity`
+    tenement::UsageRecorder::new(hypervisor.clone(), usage_store.clone())
+        .start(tenement::capacity::SAMPLE_INTERVAL);
+    // Roll up per-instance requests, bytes and compute for `/api/usage`
+    tenement::MeterRecorder::new(hypervisor.clone(), usage_store)
+        .start(tenement::metering::FLUSH_INTERVAL);
+
     server::serve(
         hypervisor,
         domain,
-        port,
+        listen,
         config_store,
         deploy_log,
         tenant_tokens,
@@ -565,6 +1194,77 @@ async fn cmd_serve(
     Ok(())
 }
 
+/// Print a capacity report as tables
+fn print_capacity(report: &tenement::CapacityReport) {
+    if report.samples < 2 {
+        println!(
+            "Not enough usage history yet ({} sample(s))",
+            report.samples
+        );
+        println!(
+            "Usage is sampled every {} minutes while `ten serve` runs",
+            tenement::capacity::SAMPLE_INTERVAL.as_secs() / 60
+        );
+        return;
+    }
+    println!(
+        "Based on {} samples over {:.1} days",
+        report.samples,
+        report.history_secs as f64 / 86400.0
+    );
+    println!();
+    println!(
+        "{:<10} {:<12} {:<12} {:<14} RUNS OUT",
+        "RESOURCE", "USED", "AVAILABLE", "GROWTH/DAY"
+    );
+    for (name, forecast) in [("memory", &report.memory), ("disk", &report.disk)] {
+        let runs_out = match forecast.days_left {
+            Some(days) => format!("in {:.0} days", days),
+            None => "not growing".to_string(),
+        };
+        println!(
+            "{:<10} {:<12} {:<12} {:<14} {}",
+            name,
+            tenement::format_bytes(forecast.used_bytes),
+            forecast
+                .available_bytes
+                .map(tenement::format_bytes)
+                .unwrap_or_else(|| "-".to_string()),
+            format_growth(forecast.growth_bytes_per_day),
+            runs_out
+        );
+    }
+    if report.migrate.is_empty() {
+        return;
+    }
+    println!();
+    println!("Migrate first (fastest {} growth):", report.bottleneck);
+    println!(
+        "{:<24} {:<12} {:<12} {:<14} DISK/DAY",
+        "INSTANCE", "MEMORY", "DISK", "MEMORY/DAY"
+    );
+    for tenant in &report.migrate {
+        println!(
+            "{:<24} {:<12} {:<12} {:<14} {}",
+            format!("{}:{}", tenant.process, tenant.id),
+            tenement::format_bytes(tenant.memory_bytes),
+            tenement::format_bytes(tenant.disk_bytes),
+            format_growth(tenant.memory_growth_bytes_per_day),
+            format_growth(tenant.disk_growth_bytes_per_day)
+        );
+    }
+}
+
+/// Signed bytes-per-day growth, e.g. "+1.2 GB"
+fn format_growth(bytes_per_day: f64) -> String {
+    let sign = if bytes_per_day < 0.0 { "-" } else { "+" };
+    format!(
+        "{}{}",
+        sign,
+        tenement::format_bytes(bytes_per_day.abs().round() as u64)
+    )
+}
+
 /// Initialize a new tenement project
 fn cmd_init(name: Option<String>, command: Option<String>) -> Result<()> {
     let config_path = std::path::Path::new("tenement.toml");
@@ -664,6 +1364,20 @@ fn parse_instance(s: &str) -> Result<(String, String)> {
     Ok((parts[0].to_string(), parts[1].to_string()))
 }
 
+fn parse_ps_filter(s: &str) -> Result<(String, String)> {
+    match s.strip_prefix("label=") {
+        Some(label) => tenement::instance::parse_label(label),
+        None => anyhow::bail!("Unsupported filter '{}'. Use label=key=value", s),
+    }
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => anyhow::bail!("Invalid env var '{}'. Use KEY=VALUE", s),
+    }
+}
+
 fn validate_acme_email(email: &str) -> Result<()> {
     if email.is_empty() {
         anyhow::bail!(
@@ -0,0 +1,382 @@
+//! Progress output for long-running commands (deploys, drains, restarts)
+//!
+//! The server runs these operations in a single request, so the CLI polls
+//! `/api/instances` while it waits and reports what changes: the current
+//! step, each affected instance's status, health and weight, and how long is
+//! left. `--json` streams the same events as JSON lines for scripts and CI;
+//! `--quiet` prints only the result.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tenement_cli::client::ApiClient;
+
+/// How often instance state is polled while an operation runs
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Output flags shared by long-running commands
+#[derive(clap::Args, Debug, Clone, Copy, Default)]
+pub struct ProgressArgs {
+    /// Only print the result, no progress
+    #[arg(long, short)]
+    pub quiet: bool,
+    /// Stream progress as JSON lines (one event per line)
+    #[arg(long, conflicts_with = "quiet")]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Human,
+    Quiet,
+    Json,
+}
+
+/// What the CLI last saw of an instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InstanceState {
+    status: String,
+    health: String,
+    weight: u64,
+}
+
+impl InstanceState {
+    fn from_info(info: &Value) -> Self {
+        Self {
+            status: info["status"].as_str().unwrap_or("unknown").to_string(),
+            health: info["health"].as_str().unwrap_or("unknown").to_string(),
+            weight: info["weight"].as_u64().unwrap_or(0),
+        }
+    }
+
+    /// An instance that disappeared from the listing
+    fn gone() -> Self {
+        Self {
+            status: "stopped".to_string(),
+            health: "unknown".to_string(),
+            weight: 0,
+        }
+    }
+}
+
+/// Progress reporter for one operation
+pub struct Progress {
+    mode: Mode,
+    /// Step descriptions, in order
+    steps: Vec<String>,
+    /// Index of the step last reported
+    step: Option<usize>,
+    /// Instances the operation waits on to become ready (drives the ETA)
+    targets: Vec<String>,
+    /// When the server gives up on the operation, if known
+    deadline: Option<Duration>,
+    seen: HashMap<String, InstanceState>,
+    started: Instant,
+}
+
+impl Progress {
+    pub fn new(args: ProgressArgs, steps: Vec<String>) -> Self {
+        let mode = if args.json {
+            Mode::Json
+        } else if args.quiet {
+            Mode::Quiet
+        } else {
+            Mode::Human
+        };
+        Self {
+            mode,
+            steps,
+            step: None,
+            targets: Vec::new(),
+            deadline: None,
+            seen: HashMap::new(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Instances that must become ready for the operation to finish
+    pub fn targets(mut self, targets: Vec<String>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// How long the server waits before failing the operation
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Run `op`, reporting progress for the instances of `process` until it
+    /// finishes. `stage` maps those instances to the current step; steps
+    /// only move forward.
+    pub async fn run<T>(
+        &mut self,
+        client: &ApiClient,
+        process: &str,
+        stage: impl Fn(&[Value]) -> usize,
+        op: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        self.advance(0);
+        if self.mode == Mode::Quiet {
+            return op.await;
+        }
+        tokio::pin!(op);
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        let result = loop {
+            tokio::select! {
+                result = &mut op => break result,
+                _ = ticker.tick() => {
+                    // A missed poll only delays the next update
+                    if let Ok(instances) = list_process(client, process).await {
+                        self.advance(stage(&instances));
+                        self.observe(&instances);
+                    }
+                }
+            }
+        };
+        // Report where things ended up (e.g. drained instances gone)
+        if let Ok(instances) = list_process(client, process).await {
+            self.observe(&instances);
+        }
+        if let Err(e) = &result {
+            self.emit(json!({"event": "error", "message": format!("{:#}", e)}));
+        }
+        result
+    }
+
+    /// Print the result: `lines` for people, `result` for `--json`
+    pub fn done(&self, lines: &[String], result: Value) {
+        match self.mode {
+            Mode::Json => self.emit(json!({"event": "done", "result": result})),
+            Mode::Human | Mode::Quiet => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+
+    fn advance(&mut self, stage: usize) {
+        let stage = stage.min(self.steps.len().saturating_sub(1));
+        if self.steps.is_empty() || self.step.is_some_and(|s| s >= stage) {
+            return;
+        }
+        self.step = Some(stage);
+        self.emit(json!({
+            "event": "step",
+            "step": stage + 1,
+            "steps": self.steps.len(),
+            "message": self.steps[stage],
+        }));
+    }
+
+    /// Report instances whose state changed since the last poll
+    fn observe(&mut self, instances: &[Value]) {
+        let current: HashMap<String, InstanceState> = instances
+            .iter()
+            .filter_map(|info| {
+                let id = info["id"].as_str()?;
+                Some((id.to_string(), InstanceState::from_info(info)))
+            })
+            .collect();
+
+        let mut changed: Vec<_> = current
+            .iter()
+            .filter(|(id, state)| self.seen.get(*id) != Some(*state))
+            .map(|(id, state)| (id.clone(), state.clone()))
+            .collect();
+        // Gone instances are reported once, then forgotten
+        changed.extend(
+            self.seen
+                .keys()
+                .filter(|id| !current.contains_key(*id))
+                .map(|id| (id.clone(), InstanceState::gone())),
+        );
+        changed.sort_by(|a, b| a.0.cmp(&b.0));
+        self.seen = current;
+
+        let elapsed = self.started.elapsed();
+        let eta = self.eta(elapsed);
+        let timeout = self.deadline.map(|d| d.saturating_sub(elapsed).as_secs());
+        for (id, state) in changed {
+            self.emit(json!({
+                "event": "instance",
+                "instance": id,
+                "status": state.status,
+                "health": state.health,
+                "weight": state.weight,
+                "eta_secs": eta,
+                "timeout_secs": timeout,
+            }));
+        }
+    }
+
+    /// Estimated seconds left, from how long the ready targets took
+    fn eta(&self, elapsed: Duration) -> Option<u64> {
+        let ready = self
+            .targets
+            .iter()
+            .filter(|id| self.seen.get(*id).is_some_and(|s| s.status == "running"))
+            .count();
+        if ready == 0 || ready >= self.targets.len() {
+            return None;
+        }
+        let pending = (self.targets.len() - ready) as f64;
+        Some((elapsed.as_secs_f64() / ready as f64 * pending).ceil() as u64)
+    }
+
+    fn emit(&self, mut event: Value) {
+        event["elapsed_secs"] = json!(self.started.elapsed().as_secs());
+        if let Some(line) = render(self.mode, &event) {
+            println!("{}", line);
+        }
+    }
+}
+
+/// One output line for `event`, or None if the mode doesn't show it
+fn render(mode: Mode, event: &Value) -> Option<String> {
+    match mode {
+        Mode::Quiet => None,
+        Mode::Json => Some(event.to_string()),
+        Mode::Human => match event["event"].as_str()? {
+            "step" => Some(format!(
+                "[{}/{}] {}",
+                event["step"],
+                event["steps"],
+                event["message"].as_str()?
+            )),
+            "instance" => {
+                let mut timing = vec![format!("{}s", event["elapsed_secs"])];
+                if let Some(eta) = event["eta_secs"].as_u64() {
+                    timing.push(format!("~{}s left", eta));
+                } else if let Some(timeout) = event["timeout_secs"].as_u64() {
+                    timing.push(format!("times out in {}s", timeout));
+                }
+                Some(format!(
+                    "      {:<20} {:<9} {:<10} weight={:<4} ({})",
+                    event["instance"].as_str()?,
+                    event["status"].as_str()?,
+                    event["health"].as_str()?,
+                    event["weight"],
+                    timing.join(", ")
+                ))
+            }
+            // Errors are printed by main; results by `done`
+            _ => None,
+        },
+    }
+}
+
+/// Instances of `process`
+pub async fn list_process(client: &ApiClient, process: &str) -> Result<Vec<Value>> {
+    let prefix = format!("{}:", process);
+    Ok(client
+        .list(&BTreeMap::new())
+        .await?
+        .into_iter()
+        .filter(|info| {
+            info["id"]
+                .as_str()
+                .is_some_and(|id| id.starts_with(&prefix))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(id: &str, status: &str, health: &str, weight: u64) -> Value {
+        json!({"id": id, "status": status, "health": health, "weight": weight})
+    }
+
+    fn progress(targets: &[&str]) -> Progress {
+        Progress::new(
+            ProgressArgs::default(),
+            vec!["Starting".to_string(), "Draining".to_string()],
+        )
+        .targets(targets.iter().map(|t| t.to_string()).collect())
+    }
+
+    #[test]
+    fn test_observe_tracks_changes() {
+        let mut p = progress(&["api:v2-1", "api:v2-2"]);
+        p.observe(&[
+            info("api:v1", "running", "healthy", 100),
+            info("api:v2-1", "starting", "unknown", 0),
+        ]);
+        assert_eq!(p.seen.len(), 2);
+        assert_eq!(p.seen["api:v2-1"].status, "starting");
+
+        // api:v1 went away: reported as stopped once, then forgotten
+        p.observe(&[info("api:v2-1", "running", "healthy", 0)]);
+        assert_eq!(p.seen.len(), 1);
+        assert!(!p.seen.contains_key("api:v1"));
+    }
+
+    #[test]
+    fn test_eta_from_ready_targets() {
+        let mut p = progress(&["api:v2-1", "api:v2-2", "api:v2-3"]);
+        assert_eq!(p.eta(Duration::from_secs(10)), None);
+        p.observe(&[
+            info("api:v2-1", "running", "healthy", 0),
+            info("api:v2-2", "starting", "unknown", 0),
+        ]);
+        // One ready in 10s, two to go
+        assert_eq!(p.eta(Duration::from_secs(10)), Some(20));
+        p.observe(&[
+            info("api:v2-1", "running", "healthy", 0),
+            info("api:v2-2", "running", "healthy", 0),
+            info("api:v2-3", "running", "healthy", 0),
+        ]);
+        assert_eq!(p.eta(Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn test_steps_only_move_forward() {
+        let mut p = progress(&[]);
+        p.advance(1);
+        assert_eq!(p.step, Some(1));
+        p.advance(0);
+        assert_eq!(p.step, Some(1));
+        // Past the last step stays on the last step
+        p.advance(5);
+        assert_eq!(p.step, Some(1));
+    }
+
+    #[test]
+    fn test_render() {
+        let step = json!({"event": "step", "step": 1, "steps": 2, "message": "Starting", "elapsed_secs": 0});
+        assert_eq!(render(Mode::Human, &step).unwrap(), "[1/2] Starting");
+        assert_eq!(render(Mode::Quiet, &step), None);
+        let json_line = render(Mode::Json, &step).unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&json_line).unwrap(), step);
+
+        let instance = json!({
+            "event": "instance",
+            "instance": "api:v2",
+            "status": "starting",
+            "health": "unknown",
+            "weight": 0,
+            "eta_secs": null,
+            "timeout_secs": 27,
+            "elapsed_secs": 3,
+        });
+        let line = render(Mode::Human, &instance).unwrap();
+        assert!(line.contains("api:v2"));
+        assert!(line.contains("starting"));
+        assert!(line.ends_with("(3s, times out in 27s)"));
+
+        let mut instance = instance;
+        instance["eta_secs"] = json!(8);
+        assert!(render(Mode::Human, &instance)
+            .unwrap()
+            .ends_with("(3s, ~8s left)"));
+
+        let error = json!({"event": "error", "message": "boom", "elapsed_secs": 1});
+        assert_eq!(render(Mode::Human, &error), None);
+        assert!(render(Mode::Json, &error).is_some());
+    }
+}
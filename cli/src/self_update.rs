@@ -0,0 +1,343 @@
+//! Self-update from GitHub releases
+//!
+//! Downloads the `ten-{target}` release binary and its minisign signature
+//! (`ten-{target}.minisig`), verifies the signature against the release public
+//! key, then atomically replaces the running binary. Nothing on disk changes
+//! unless the signature verifies and the new binary runs.
+//!
+//! If tenement runs under systemd, the service is restarted afterwards; the
+//! old daemon drains requests and stops its instances gracefully before the
+//! new one starts. With `tenement.socket` (written by `ten install`) systemd
+//! holds the listening socket across the restart, so the new daemon takes
+//! over the same listener and connections arriving meanwhile wait instead of
+//! being refused.
+
+use anyhow::{Context, Result};
+use minisign_verify::{PublicKey, Signature};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tenement_cli::build_info;
+
+const REPO: &str = "russellromney/tenement";
+const SERVICE_NAME: &str = "tenement";
+
+/// Options for `ten self-update`
+pub struct UpdateOptions {
+    /// Release tag to install (default: latest)
+    pub version: Option<String>,
+    /// minisign public key (base64) or path to a .pub file
+    pub public_key: Option<String>,
+    /// Reinstall even if already on this version
+    pub force: bool,
+    /// Restart the systemd service after installing
+    pub restart: bool,
+}
+
+/// Run the update
+pub async fn run(opts: UpdateOptions) -> Result<()> {
+    let public_key = load_public_key(opts.public_key.as_deref())?;
+    let client = reqwest::Client::builder()
+        .user_agent(format!("tenement/{}", build_info::VERSION))
+        .build()?;
+
+    let tag = match opts.version {
+        Some(version) => release_tag(&version),
+        None => latest_tag(&client).await?,
+    };
+    if !opts.force && tag.trim_start_matches('v') == build_info::VERSION {
+        println!("Already on {} (use --force to reinstall)", tag);
+        return Ok(());
+    }
+
+    let asset = asset_name(build_info::TARGET);
+    let url = format!(
+        "https://github.com/{}/releases/download/{}/{}",
+        REPO, tag, asset
+    );
+    println!("Downloading {} ({})", asset, tag);
+    let binary = download(&client, &url).await?;
+    let signature = download(&client, &format!("{}.minisig", url)).await?;
+
+    verify(
+        &binary,
+        &String::from_utf8_lossy(&signature),
+        &public_key,
+        &asset,
+    )?;
+    println!("Signature verified");
+
+    let exe = std::env::current_exe()
+        .and_then(|p| p.canonicalize())
+        .context("Failed to locate the running binary")?;
+    let staged = stage(&exe, &binary)?;
+    if let Err(e) = check_runs(&staged) {
+        let _ = std::fs::remove_file(&staged);
+        return Err(e);
+    }
+    swap(&staged, &exe)?;
+    println!("Installed {} at {}", tag, exe.display());
+    println!("Previous binary kept at {}", backup_path(&exe).display());
+
+    if opts.restart {
+        restart_service()?;
+    } else {
+        println!("Restart tenement to run the new version");
+    }
+    Ok(())
+}
+
+/// Release asset name for a target triple
+pub fn asset_name(target: &str) -> String {
+    format!("ten-{}", target)
+}
+
+/// Normalize a user-supplied version to a release tag ("0.3.0" -> "v0.3.0")
+pub fn release_tag(version: &str) -> String {
+    if version.starts_with('v') {
+        version.to_string()
+    } else {
+        format!("v{}", version)
+    }
+}
+
+/// Resolve the release public key: `--public-key` (base64 or a .pub file),
+/// else the key baked in at build time.
+fn load_public_key(arg: Option<&str>) -> Result<PublicKey> {
+    let key = match arg {
+        Some(arg) if Path::new(arg).is_file() => {
+            return PublicKey::from_file(arg)
+                .map_err(|e| anyhow::anyhow!("Invalid public key file {}: {}", arg, e));
+        }
+        Some(arg) => arg,
+        None => build_info::RELEASE_PUBLIC_KEY.ok_or_else(|| {
+            anyhow::anyhow!(
+                "This build has no release public key; pass --public-key <base64 key or .pub file>"
+            )
+        })?,
+    };
+    PublicKey::from_base64(key).map_err(|e| anyhow::anyhow!("Invalid public key: {}", e))
+}
+
+/// Verify a minisign signature over `binary`. The trusted comment (covered by
+/// the signature) must name `asset`, so a validly signed binary for another
+/// platform can't be substituted.
+pub fn verify(binary: &[u8], signature: &str, public_key: &PublicKey, asset: &str) -> Result<()> {
+    let signature = Signature::decode(signature)
+        .map_err(|e| anyhow::anyhow!("Invalid signature file: {}", e))?;
+    public_key
+        .verify(binary, &signature, false)
+        .map_err(|e| anyhow::anyhow!("Signature verification failed: {}", e))?;
+
+    let signed_file = signature
+        .trusted_comment()
+        .split('\t')
+        .find_map(|field| field.strip_prefix("file:"));
+    if signed_file != Some(asset) {
+        anyhow::bail!(
+            "Signature is for {:?}, expected {:?}",
+            signed_file.unwrap_or("(unknown)"),
+            asset
+        );
+    }
+    Ok(())
+}
+
+/// Write the new binary next to `dest` (same filesystem, so the final rename
+/// is atomic) and make it executable
+pub fn stage(dest: &Path, binary: &[u8]) -> Result<PathBuf> {
+    let name = dest
+        .file_name()
+        .and_then(|n| n.to_str())
+        .context("Invalid binary path")?;
+    let staged = dest.with_file_name(format!(".{}.new", name));
+
+    std::fs::write(&staged, binary)
+        .with_context(|| format!("Failed to write {} (need write access?)", staged.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(staged)
+}
+
+/// Make sure the staged binary actually runs on this host
+pub fn check_runs(staged: &Path) -> Result<()> {
+    let out = Command::new(staged)
+        .arg("--version")
+        .output()
+        .context("New binary failed to start")?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "New binary failed `--version` check: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Keep a copy of the current binary, then rename the staged one over it
+pub fn swap(staged: &Path, dest: &Path) -> Result<()> {
+    let backup = backup_path(dest);
+    std::fs::copy(dest, &backup)
+        .with_context(|| format!("Failed to back up {}", dest.display()))?;
+    std::fs::rename(staged, dest)
+        .with_context(|| format!("Failed to replace {}", dest.display()))?;
+    Ok(())
+}
+
+fn backup_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".old");
+    PathBuf::from(name)
+}
+
+async fn latest_tag(client: &reqwest::Client) -> Result<String> {
+    #[derive(serde::Deserialize)]
+    struct Release {
+        tag_name: String,
+    }
+    let release: Release = client
+        .get(format!(
+            "https://api.github.com/repos/{}/releases/latest",
+            REPO
+        ))
+        .send()
+        .await
+        .context("Failed to query latest release")?
+        .error_for_status()
+        .context("Failed to query latest release")?
+        .json()
+        .await
+        .context("Unexpected release API response")?;
+    Ok(release.tag_name)
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Restart the systemd service if it's running; otherwise just say so.
+///
+/// The old daemon drains in-flight requests and stops its instances, then
+/// the new one starts. Under `tenement.socket` the listener is handed over:
+/// systemd keeps it open throughout and passes it to the new daemon, so
+/// connections queue until it accepts them. Without the socket unit they are
+/// refused until the new daemon is listening.
+fn restart_service() -> Result<()> {
+    let socket = format!("{}.socket", SERVICE_NAME);
+    if !is_active(SERVICE_NAME) {
+        println!(
+            "{} service is not running under systemd; restart `ten serve` to use the new version",
+            SERVICE_NAME
+        );
+        return Ok(());
+    }
+
+    if is_active(&socket) {
+        println!(
+            "Restarting {}: in-flight requests drain and instances stop first; new connections wait on {} for the new daemon...",
+            SERVICE_NAME, socket
+        );
+    } else {
+        println!(
+            "Restarting {}: in-flight requests drain and instances stop first; new connections are refused until the new daemon is listening (re-run `ten install` to add {} and keep the listener open across restarts)...",
+            SERVICE_NAME, socket
+        );
+    }
+    let status = Command::new("systemctl")
+        .args(["restart", SERVICE_NAME])
+        .status()
+        .context("Failed to run systemctl")?;
+    if !status.success() {
+        anyhow::bail!("systemctl restart {} failed", SERVICE_NAME);
+    }
+    println!("Restarted");
+    Ok(())
+}
+
+fn is_active(unit: &str) -> bool {
+    Command::new("systemctl")
+        .args(["is-active", "--quiet", unit])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    // Test vector from the minisign-verify crate: a prehashed signature of
+    // b"test" with trusted comment "file:test"
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+
+    #[test]
+    fn test_asset_and_tag_names() {
+        assert_eq!(
+            asset_name("x86_64-unknown-linux-gnu"),
+            "ten-x86_64-unknown-linux-gnu"
+        );
+        assert_eq!(release_tag("0.3.0"), "v0.3.0");
+        assert_eq!(release_tag("v0.3.0"), "v0.3.0");
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let key = PublicKey::from_base64(PUBLIC_KEY).unwrap();
+        verify(b"test", SIGNATURE, &key, "test").unwrap();
+
+        let err = verify(b"tampered", SIGNATURE, &key, "test").unwrap_err();
+        assert!(err.to_string().contains("verification failed"));
+    }
+
+    #[test]
+    fn test_verify_rejects_other_asset() {
+        let key = PublicKey::from_base64(PUBLIC_KEY).unwrap();
+        let err = verify(b"test", SIGNATURE, &key, "ten-x86_64-unknown-linux-gnu").unwrap_err();
+        assert!(err.to_string().contains("Signature is for"));
+    }
+
+    #[test]
+    fn test_load_public_key_from_arg() {
+        assert!(load_public_key(Some(PUBLIC_KEY)).is_ok());
+        assert!(load_public_key(Some("not-a-key")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_stage_check_and_swap() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("ten");
+        std::fs::write(&dest, "old").unwrap();
+
+        let staged = stage(&dest, b"#!/bin/sh\necho tenement 9.9.9\n").unwrap();
+        assert_eq!(staged, dir.path().join(".ten.new"));
+        check_runs(&staged).unwrap();
+
+        swap(&staged, &dest).unwrap();
+        assert!(!staged.exists());
+        assert!(std::fs::read_to_string(&dest).unwrap().contains("9.9.9"));
+        assert_eq!(std::fs::read_to_string(backup_path(&dest)).unwrap(), "old");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_runs_rejects_broken_binary() {
+        let dir = TempDir::new().unwrap();
+        let staged = stage(&dir.path().join("ten"), b"#!/bin/sh\nexit 1\n").unwrap();
+        assert!(check_runs(&staged).is_err());
+    }
+}
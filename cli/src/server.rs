@@ -1,5 +1,7 @@
 //! HTTP server with subdomain routing, reverse proxy, and automatic TLS
 
+use crate::socket_activation;
+use crate::tls::{Acceptor, ClientCertificate, Dns01, SniResolver};
 use anyhow::{Context, Result};
 use axum::{
     body::Body,
@@ -16,34 +18,95 @@ use axum::{
 use futures::stream::Stream;
 use hyper_util::{client::legacy::Client, rt::TokioExecutor};
 use hyperlocal::UnixConnector;
-use rustls_acme::{caches::DirCache, AcmeConfig};
+use rustls::server::ResolvesServerCert;
+use rustls_acme::{acme::ACME_TLS_ALPN_NAME, caches::DirCache, AcmeConfig, AcmeState};
 use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tenement::{ConfigStore, Hypervisor, LogLevel, LogQuery, TokenStore};
+use tenement::experiment::ClientIdSource;
+use tenement::{
+    ConfigStore, Hypervisor, InstanceStatus, LogLevel, LogQuery, RateLimitKey, TokenStore,
+};
 use tokio_stream::wrappers::BroadcastStream;
 use tokio_stream::StreamExt;
 use tower_http::trace::TraceLayer;
 
+/// Request header pinning a proxied request to one instance (`process:id`),
+/// bypassing weights. Needs the admin token in [`TOKEN_HEADER`].
+pub const INSTANCE_HEADER: &str = "x-tenement-instance";
+/// Admin token authorizing an [`INSTANCE_HEADER`] override
+pub const TOKEN_HEADER: &str = "x-tenement-token";
+/// Cookie naming the instance a client sticks to, for `sticky = true` services
+pub const STICKY_COOKIE: &str = "tenement-instance";
+
 /// TLS configuration for the server
 #[derive(Debug, Clone)]
 pub struct TlsOptions {
     pub enabled: bool,
     pub email: String,
     pub domain: String,
+    /// Extra names on the main certificate
+    pub alt_names: Vec<String>,
+    /// Further domains, each with its own certificate
+    pub domains: Vec<String>,
+    /// DNS-01 challenges, for certificates with wildcard names
+    pub dns: Option<tenement::ChallengeDnsConfig>,
+    /// CA bundle client certificates for `/api` requests must be signed by
+    pub client_ca: Option<PathBuf>,
     pub cache_dir: PathBuf,
     pub staging: bool,
     pub https_port: u16,
     pub http_port: u16,
 }
 
+impl TlsOptions {
+    /// Names on each certificate: the main domain with its alt names, then
+    /// one per extra domain
+    pub fn certificates(&self) -> Vec<Vec<String>> {
+        let main = std::iter::once(self.domain.clone())
+            .chain(self.alt_names.iter().cloned())
+            .collect();
+        std::iter::once(main)
+            .chain(self.domains.iter().map(|d| vec![d.clone()]))
+            .collect()
+    }
+}
+
+/// Where the server listens besides its TLS ports
+#[derive(Debug, Clone, Copy)]
+pub struct ListenOptions {
+    /// Public port (used when TLS is disabled)
+    pub port: u16,
+    /// Separate address for the dashboard and API, which the public
+    /// listener then stops serving
+    pub api: Option<SocketAddr>,
+    /// Serve the web dashboard at `/`
+    pub dashboard: bool,
+}
+
+/// Which routes a listener serves
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Serves {
+    /// Tenant instances, the dashboard and the API
+    #[default]
+    All,
+    /// Tenant instances and `/health` only; the API is on its own listener
+    Proxy,
+    /// The dashboard and API only, never instances
+    Api,
+}
+
 /// TLS status information for the status endpoint
 #[derive(Clone, Default)]
 pub struct TlsStatus {
     pub enabled: bool,
     pub domain: Option<String>,
+    /// Every name certificates are served for
+    pub domains: Vec<String>,
+    /// Whether `/api` requests need a client certificate
+    pub client_auth: bool,
     pub staging: bool,
     pub https_port: u16,
     pub http_port: u16,
@@ -54,12 +117,22 @@ pub struct TlsStatus {
 pub struct AppState {
     pub hypervisor: Arc<Hypervisor>,
     pub domain: String,
+    /// Proxy clients, shared by every request so keep-alive connections are
+    /// pooled per instance address or socket path. Connections an instance
+    /// closes (e.g. when it stops) are dropped from the pool.
     pub client: Client<hyper_util::client::legacy::connect::HttpConnector, Body>,
     pub unix_client: Client<UnixConnector, Body>,
+    /// HTTP/2 cleartext (h2c) clients, used for gRPC requests
+    pub h2_client: Client<hyper_util::client::legacy::connect::HttpConnector, Body>,
+    pub unix_h2_client: Client<UnixConnector, Body>,
     pub config_store: Arc<ConfigStore>,
     pub deploy_log: Arc<tenement::DeployLogStore>,
     pub tenant_tokens: Arc<tenement::TenantTokenStore>,
     pub tls_status: TlsStatus,
+    /// Serve the web dashboard at `/`
+    pub dashboard: bool,
+    /// Which routes this listener serves
+    pub serves: Serves,
     /// Tracks failed auth attempts for rate limiting.
     /// Stores (failure_count, last_failure_time). Resets after cooldown.
     pub auth_failures: Arc<tokio::sync::RwLock<(u32, Option<std::time::Instant>)>>,
@@ -81,7 +154,10 @@ pub fn create_router(state: AppState) -> Router {
         .route("/health", get(health))
         .route("/metrics", get(metrics_endpoint))
         .route("/api/telemetry", get(telemetry_endpoint))
-        .route("/api/instances", get(list_instances))
+        .route(
+            "/api/instances",
+            get(list_instances).post(crate::api_routes::post_instance),
+        )
         .route(
             "/api/instances/spawn",
             axum::routing::post(crate::api_routes::post_spawn),
@@ -95,10 +171,18 @@ pub fn create_router(state: AppState) -> Router {
             "/api/instances/:id/restart",
             axum::routing::post(crate::api_routes::post_restart),
         )
+        .route(
+            "/api/instances/:id/drain",
+            axum::routing::post(crate::api_routes::post_drain),
+        )
         .route(
             "/api/instances/:id/weight",
             axum::routing::put(crate::api_routes::put_weight),
         )
+        .route(
+            "/api/instances/:id/limits",
+            axum::routing::put(crate::api_routes::put_limits),
+        )
         .route(
             "/api/instances/:id/health",
             axum::routing::get(crate::api_routes::get_health_check),
@@ -107,12 +191,42 @@ pub fn create_router(state: AppState) -> Router {
             "/api/deploy",
             axum::routing::post(crate::api_routes::post_deploy),
         )
+        .route("/api/services", get(crate::api_routes::list_services))
+        .route("/api/services/:name", get(crate::api_routes::get_service))
+        .route(
+            "/api/services/:name/deploy",
+            axum::routing::post(crate::api_routes::post_service_deploy),
+        )
+        .route(
+            "/api/services/:name/versions",
+            get(crate::api_routes::get_service_versions),
+        )
         .route(
             "/api/route",
             axum::routing::post(crate::api_routes::post_route),
         )
+        .route("/api/config/diff", get(crate::api_routes::get_config_diff))
+        .route(
+            "/api/config/drift",
+            get(crate::api_routes::get_config_drift),
+        )
+        .route(
+            "/api/config/reload",
+            axum::routing::post(crate::api_routes::post_config_reload),
+        )
+        .route(
+            "/api/adopt-config",
+            axum::routing::post(crate::api_routes::post_adopt_config),
+        )
+        .route("/api/version", get(crate::api_routes::get_version))
+        .route("/api/sbom", get(crate::api_routes::get_sbom))
+        .route("/api/alerts", get(crate::api_routes::get_alerts))
+        .route("/api/host", get(crate::api_routes::get_host))
+        .route("/api/capacity", get(crate::api_routes::get_capacity))
         .route("/api/logs", get(query_logs))
         .route("/api/logs/stream", get(stream_logs))
+        .route("/api/access-logs", get(crate::api_routes::get_access_logs))
+        .route("/api/usage", get(crate::api_routes::get_usage))
         .route("/api/tls/status", get(tls_status_endpoint))
         // Dashboard static assets
         .route("/assets/*path", get(dashboard_asset))
@@ -134,8 +248,8 @@ pub fn create_router(state: AppState) -> Router {
         .with_state(state)
 }
 
-/// Wait for shutdown signal (SIGTERM or SIGINT), then stop all instances.
-async fn shutdown_signal(hypervisor: Arc<Hypervisor>) {
+/// Wait for a shutdown signal (SIGTERM or SIGINT).
+async fn shutdown_signal() {
     let ctrl_c = async {
         tokio::signal::ctrl_c()
             .await
@@ -161,21 +275,154 @@ async fn shutdown_signal(hypervisor: Arc<Hypervisor>) {
             tracing::info!("Received SIGTERM, shutting down");
         },
     }
+}
+
+/// How long to let in-flight requests finish after we stop accepting
+/// connections: the longest per-service request timeout, since no proxied
+/// request can legitimately take longer.
+fn proxy_drain_timeout(hypervisor: &Hypervisor) -> std::time::Duration {
+    let config = hypervisor.config();
+    let secs = config
+        .service
+        .values()
+        .map(|s| config.request_timeout(s))
+        .max()
+        .unwrap_or(config.settings.request_timeout);
+    std::time::Duration::from_secs(secs)
+}
+
+/// Re-read tenement.toml to diff against or reload over the running config.
+///
+/// `data_dir` is carried over from the running config: it may come from
+/// `--data-dir` and cannot change while the server is running.
+pub fn load_candidate_config(running: &tenement::Config) -> Result<tenement::Config> {
+    let mut candidate = tenement::Config::load_profile(running.active_profile.as_deref())?;
+    candidate.settings.data_dir = running.settings.data_dir.clone();
+    Ok(candidate)
+}
+
+/// POST fired alerts to their rule's webhook. Delivery is best-effort: a
+/// failed POST is logged and not retried.
+async fn deliver_alert_webhooks(alerts: Arc<tenement::AlertEngine>) {
+    let client = match reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            tracing::error!("Alert webhooks disabled: {}", e);
+            return;
+        }
+    };
+    let mut rx = alerts.subscribe();
+    loop {
+        let event = match rx.recv().await {
+            Ok(event) => event,
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                tracing::warn!("Alert webhooks fell behind; {} alert(s) not delivered", n);
+                continue;
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        };
+        let Some(url) = event.webhook.clone() else {
+            continue;
+        };
+        let client = client.clone();
+        tokio::spawn(async move {
+            let result = client
+                .post(&url)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status());
+            if let Err(e) = result {
+                tracing::warn!("Alert '{}' webhook to {} failed: {}", event.rule, url, e);
+            }
+        });
+    }
+}
+
+/// How often `watch_config` checks tenement.toml for changes
+const CONFIG_WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Re-read tenement.toml and apply it, logging what changed. A config that
+/// fails to load leaves the running one in place.
+pub async fn reload_config(hypervisor: &Hypervisor) -> Result<tenement::ReloadReport> {
+    let result = match load_candidate_config(&hypervisor.config()) {
+        Ok(candidate) => hypervisor.reload(candidate).await,
+        Err(e) => Err(e),
+    };
+    match &result {
+        Ok(report) => log_reload(report),
+        Err(e) => tracing::error!("Config reload failed: {:#}", e),
+    }
+    result
+}
 
-    hypervisor.stop_all().await;
+fn log_reload(report: &tenement::ReloadReport) {
+    if report.diff.is_empty() {
+        tracing::info!("Config unchanged");
+        return;
+    }
+    tracing::info!("Config reloaded:\n{}", report.diff);
+    if !report.restarting.is_empty() {
+        tracing::info!("Rolling restart: {}", report.restarting.join(", "));
+    }
+    if !report.stopped.is_empty() {
+        tracing::info!("Stopped: {}", report.stopped.join(", "));
+    }
+    if !report.spawned.is_empty() {
+        tracing::info!("Spawned: {}", report.spawned.join(", "));
+    }
+    if !report.needs_restart.is_empty() {
+        tracing::warn!(
+            "Changes to [{}] apply after a daemon restart",
+            report.needs_restart.join("], [")
+        );
+    }
 }
 
-/// Constant-time byte comparison to prevent timing attacks on token verification
-#[allow(dead_code)]
-fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
-    if a.len() != b.len() {
-        return false;
+/// On SIGHUP, reload tenement.toml.
+#[cfg(unix)]
+async fn reload_signal(hypervisor: Arc<Hypervisor>) {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(s) => s,
+        Err(e) => {
+            tracing::warn!("Failed to install SIGHUP handler: {}", e);
+            return;
+        }
+    };
+
+    while hangup.recv().await.is_some() {
+        tracing::info!("Received SIGHUP, reloading tenement.toml");
+        reload_config(&hypervisor).await.ok();
     }
-    let mut result = 0u8;
-    for (x, y) in a.iter().zip(b.iter()) {
-        result |= x ^ y;
+}
+
+/// With `watch_config`, reload tenement.toml whenever its modification
+/// time changes.
+async fn watch_config(hypervisor: Arc<Hypervisor>) {
+    let path = match tenement::Config::find_config_file() {
+        Ok(path) => path,
+        Err(e) => {
+            tracing::warn!("Not watching tenement.toml: {:#}", e);
+            return;
+        }
+    };
+    let modified = |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    tracing::info!("Watching {} for changes", path.display());
+    let mut last = modified(&path);
+    let mut interval = tokio::time::interval(CONFIG_WATCH_INTERVAL);
+    loop {
+        interval.tick().await;
+        let current = modified(&path);
+        if current.is_none() || current == last {
+            continue;
+        }
+        last = current;
+        tracing::info!("{} changed, reloading", path.display());
+        reload_config(&hypervisor).await.ok();
     }
-    result == 0
 }
 
 /// Subdomain routing middleware - intercepts subdomain requests before routes match
@@ -188,27 +435,290 @@ async fn subdomain_middleware(
     req: Request<Body>,
     next: Next,
 ) -> Response {
+    // The admin socket and a separate API listener never reach instances
+    if state.serves == Serves::Api {
+        return next.run(req).await;
+    }
     let host = req
         .headers()
         .get("host")
         .and_then(|h| h.to_str().ok())
-        .unwrap_or("");
+        .unwrap_or("")
+        .to_string();
+    let received = std::time::SystemTime::now();
+    let start = std::time::Instant::now();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let grpc = is_grpc(req.headers());
+    let accept_encoding = req
+        .headers()
+        .get(axum::http::header::ACCEPT_ENCODING)
+        .cloned();
+
+    // Check if this is a custom domain or subdomain request
+    let (process, id) = match route_for_host(&state, &host) {
+        // Direct route to specific instance: :id.{process}.{domain}
+        Some(SubdomainRoute::Direct { process, id }) => (process, Some(id)),
+        // Weighted route across instances: {process}.{domain}
+        Some(SubdomainRoute::Weighted { process }) => (process, None),
+        None => {
+            // Not a subdomain request - continue to normal routes
+            return next.run(req).await;
+        }
+    };
+    let response = match state.hypervisor.cors(&process) {
+        Some(cors) => {
+            use tower::{Layer, ServiceExt};
+            let route = tower::service_fn(|req: Request<Body>| async {
+                Ok::<_, Infallible>(proxy_route(&state, &process, id.as_deref(), req).await)
+            });
+            let Ok(response) = cors_layer(&cors).layer(route).oneshot(req).await;
+            response
+        }
+        None => proxy_route(&state, &process, id.as_deref(), req).await,
+    };
+    let response = if grpc {
+        grpc_error_response(response)
+    } else {
+        let response = error_page(&state, &process, response).await;
+        if state.hypervisor.compresses(&process) {
+            compress(accept_encoding, response).await
+        } else {
+            response
+        }
+    };
 
-    // Check if this is a subdomain request
-    match parse_subdomain(host, &state.domain) {
-        Some(SubdomainRoute::Direct { process, id }) => {
-            // Direct route to specific instance: :id.{process}.{domain}
-            proxy_to_instance(&state, &process, Some(&id), req).await
+    // Recorded once the body has been sent, to count its bytes
+    let Some(store) = state.hypervisor.access_log() else {
+        return response;
+    };
+    let experiment = response.extensions().get::<ExperimentGroup>().cloned();
+    let entry = tenement::AccessLogEntry {
+        timestamp: received
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        host,
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        process,
+        instance_id: response
+            .extensions()
+            .get::<ProxiedInstance>()
+            .map(|i| i.0.clone()),
+        bytes: 0,
+        experiment: experiment.as_ref().map(|e| e.experiment.clone()),
+        experiment_group: experiment.map(|e| e.group),
+    };
+    response.map(|inner| {
+        Body::new(AccessLogBody {
+            inner,
+            store,
+            entry: Some(entry),
+        })
+    })
+}
+
+/// Rate-limit, check the `auth` gate, then proxy a subdomain request, to
+/// instance `id` or weighted
+async fn proxy_route(
+    state: &AppState,
+    process: &str,
+    id: Option<&str>,
+    req: Request<Body>,
+) -> Response {
+    if let Some(id) = id {
+        if let Some(throttled) = throttle(state, process, id, Some(id)).await {
+            return throttled;
         }
-        Some(SubdomainRoute::Weighted { process }) => {
-            // Weighted route across instances: {process}.{domain}
-            proxy_to_instance(&state, &process, None, req).await
+        if let Some(denied) = gate(state, process, req.headers()).await {
+            return denied;
         }
-        None => {
-            // Not a subdomain request - continue to normal routes
-            next.run(req).await
+        return proxy_to_instance(state, process, Some(id), req).await;
+    }
+    // Instance-keyed limits are checked once an instance is picked
+    let by_subdomain = state
+        .hypervisor
+        .rate_limit(process)
+        .is_some_and(|l| l.key == RateLimitKey::Subdomain);
+    if by_subdomain {
+        if let Some(throttled) = throttle(state, process, process, None).await {
+            return throttled;
+        }
+    }
+    if let Some(denied) = gate(state, process, req.headers()).await {
+        return denied;
+    }
+    proxy_to_instance(state, process, None, req).await
+}
+
+/// 401 for a request without valid credentials for the service's `auth`
+/// gate, before any instance is picked or woken
+async fn gate(
+    state: &AppState,
+    process: &str,
+    headers: &axum::http::HeaderMap,
+) -> Option<Response> {
+    let gate = state.hypervisor.auth_gate(process)?;
+    let authorization = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if state.hypervisor.authorize(process, authorization).await {
+        return None;
+    }
+    tracing::debug!("Request to {} without valid credentials", process);
+    Some(
+        (
+            StatusCode::UNAUTHORIZED,
+            [(
+                axum::http::header::WWW_AUTHENTICATE,
+                gate.challenge(process),
+            )],
+            "Unauthorized",
+        )
+            .into_response(),
+    )
+}
+
+/// Build the tower-http CORS layer for a service's `cors` policy. Values
+/// were validated when the config loaded.
+fn cors_layer(cors: &tenement::CorsConfig) -> tower_http::cors::CorsLayer {
+    use tower_http::cors::{AllowHeaders, Any, CorsLayer};
+
+    let wildcard = |values: &[String]| values.iter().any(|v| v == "*");
+    let header_names = |values: &[String]| {
+        values
+            .iter()
+            .filter_map(|v| axum::http::HeaderName::from_bytes(v.as_bytes()).ok())
+            .collect::<Vec<_>>()
+    };
+
+    let mut layer = CorsLayer::new()
+        .allow_methods(
+            cors.allow_methods
+                .iter()
+                .filter_map(|m| axum::http::Method::from_bytes(m.as_bytes()).ok())
+                .collect::<Vec<_>>(),
+        )
+        .allow_credentials(cors.allow_credentials);
+    layer = if wildcard(&cors.allow_origins) {
+        layer.allow_origin(Any)
+    } else {
+        layer.allow_origin(
+            cors.allow_origins
+                .iter()
+                .filter_map(|o| axum::http::HeaderValue::from_str(o).ok())
+                .collect::<Vec<_>>(),
+        )
+    };
+    layer = if cors.allow_headers.is_empty() {
+        layer.allow_headers(AllowHeaders::mirror_request())
+    } else if wildcard(&cors.allow_headers) {
+        layer.allow_headers(Any)
+    } else {
+        layer.allow_headers(header_names(&cors.allow_headers))
+    };
+    layer = if wildcard(&cors.expose_headers) {
+        layer.expose_headers(Any)
+    } else {
+        layer.expose_headers(header_names(&cors.expose_headers))
+    };
+    if let Some(max_age) = cors.max_age {
+        layer = layer.max_age(std::time::Duration::from_secs(max_age));
+    }
+    layer
+}
+
+/// Replace the body of an error the proxy answered with (not one from an
+/// instance) with its configured error page, keeping status and headers
+async fn error_page(state: &AppState, process: &str, response: Response) -> Response {
+    let status = response.status();
+    if !(status.is_client_error() || status.is_server_error())
+        || response.extensions().get::<FromInstance>().is_some()
+    {
+        return response;
+    }
+    let Some(path) = state.hypervisor.error_page(process, status.as_u16()) else {
+        return response;
+    };
+    let page = match tokio::fs::read_to_string(&path).await {
+        Ok(page) => page,
+        Err(e) => {
+            tracing::warn!("Failed to read error page {}: {}", path.display(), e);
+            return response;
         }
+    };
+    let page = page
+        .replace("{status}", status.as_str())
+        .replace("{reason}", status.canonical_reason().unwrap_or(""))
+        .replace("{service}", process);
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(
+        axum::http::header::CONTENT_TYPE,
+        axum::http::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    parts.headers.remove(axum::http::header::CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(page))
+}
+
+/// Compress a response with gzip or brotli, whichever `accept_encoding`
+/// prefers. Responses that are already encoded, under 32 bytes, images or
+/// event streams are returned as they are.
+async fn compress(
+    accept_encoding: Option<axum::http::HeaderValue>,
+    response: Response,
+) -> Response {
+    use tower::ServiceExt;
+
+    let mut req = Request::new(Body::empty());
+    if let Some(accept_encoding) = accept_encoding {
+        req.headers_mut()
+            .insert(axum::http::header::ACCEPT_ENCODING, accept_encoding);
+    }
+    let mut response = Some(response);
+    let respond = tower::service_fn(move |_: Request<Body>| {
+        std::future::ready(Ok::<_, Infallible>(response.take().expect("called once")))
+    });
+    let Ok(compressed) = tower_http::compression::Compression::new(respond)
+        .oneshot(req)
+        .await;
+    compressed.map(Body::new)
+}
+
+/// Take a token from `process`'s rate limit bucket for `key`, or answer
+/// 429 when it is empty. `instance` labels the throttled-requests metric.
+async fn throttle(
+    state: &AppState,
+    process: &str,
+    key: &str,
+    instance: Option<&str>,
+) -> Option<Response> {
+    if state.hypervisor.allow_request(process, key) {
+        return None;
+    }
+    tracing::debug!("Rate limit exceeded for {} ({})", process, key);
+    let mut labels = std::collections::HashMap::new();
+    labels.insert("process".to_string(), process.to_string());
+    if let Some(instance) = instance {
+        labels.insert("instance".to_string(), instance.to_string());
     }
+    state
+        .hypervisor
+        .metrics()
+        .requests_throttled
+        .with_labels(&labels)
+        .await
+        .inc();
+    Some(
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(axum::http::header::RETRY_AFTER, "1")],
+            "Too many requests",
+        )
+            .into_response(),
+    )
 }
 
 /// Auth middleware - requires Bearer token for API endpoints
@@ -219,6 +729,27 @@ async fn auth_middleware(
 ) -> Result<Response, StatusCode> {
     let path = req.uri().path();
 
+    // With the API on its own listener, the public one only has instances
+    if state.serves == Serves::Proxy && path != "/health" {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Whoever can open the admin socket is trusted by its file permissions
+    if req.extensions().get::<AdminSocket>().is_some() {
+        req.extensions_mut()
+            .insert(AuthIdentity { tenant_id: None });
+        return Ok(next.run(req).await);
+    }
+
+    // Locked-down deployments need a client certificate for the whole API
+    if state.tls_status.client_auth
+        && path.starts_with("/api/")
+        && req.extensions().get::<ClientCertificate>().is_none()
+    {
+        tracing::debug!("API request without a client certificate");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
     // Skip auth for public endpoints
     if path == "/health"
         || path == "/metrics"
@@ -309,13 +840,17 @@ async fn auth_middleware(
 pub async fn serve(
     hypervisor: Arc<Hypervisor>,
     domain: String,
-    port: u16,
+    listen: ListenOptions,
     config_store: Arc<ConfigStore>,
     deploy_log: Arc<tenement::DeployLogStore>,
     tenant_tokens: Arc<tenement::TenantTokenStore>,
     tls_options: Option<TlsOptions>,
 ) -> Result<()> {
-    // Recover any orphaned instances from a previous crash
+    // Restore weights and deployed versions, then re-adopt (or kill)
+    // instances left running by a previous daemon
+    if let Err(e) = hypervisor.load_routing_state().await {
+        tracing::error!("Failed to load routing state: {}", e);
+    }
     hypervisor.recover_orphans().await;
 
     // Spawn configured instances before accepting connections
@@ -330,17 +865,40 @@ pub async fn serve(
         tracing::info!("Auto-spawn: {} instance(s) started", success);
     }
 
+    let warm = hypervisor.fill_warm_pools().await;
+    if warm > 0 {
+        tracing::info!("Warm pools: {} instance(s) pre-spawned", warm);
+    }
+
     // Start health monitor
     hypervisor.clone().start_monitor();
 
+    #[cfg(unix)]
+    tokio::spawn(reload_signal(hypervisor.clone()));
+    if hypervisor.config().settings.watch_config {
+        tokio::spawn(watch_config(hypervisor.clone()));
+    }
+
+    if let Some(alerts) = hypervisor.alerts() {
+        tokio::spawn(deliver_alert_webhooks(alerts));
+    }
+
     let client = Client::builder(TokioExecutor::new()).build_http();
     let unix_client = Client::builder(TokioExecutor::new()).build(UnixConnector);
+    let h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+    let unix_h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(UnixConnector);
 
     // Build TLS status from options
     let tls_status = match &tls_options {
         Some(tls) if tls.enabled => TlsStatus {
             enabled: true,
             domain: Some(tls.domain.clone()),
+            domains: tls.certificates().concat(),
+            client_auth: tls.client_ca.is_some(),
             staging: tls.staging,
             https_port: tls.https_port,
             http_port: tls.http_port,
@@ -353,32 +911,168 @@ pub async fn serve(
         domain: domain.clone(),
         client,
         unix_client,
+        h2_client,
+        unix_h2_client,
         config_store,
         deploy_log,
         tenant_tokens,
         tls_status,
+        dashboard: listen.dashboard,
+        serves: Serves::All,
         auth_failures: Arc::new(tokio::sync::RwLock::new((0, None))),
     };
 
-    match tls_options {
+    let admin_socket = state.hypervisor.config().settings.admin_socket.clone();
+    if let Some(ref path) = admin_socket {
+        serve_admin_socket(state.clone(), path).await?;
+    }
+    let state = match listen.api {
+        Some(addr) => {
+            serve_api_listener(state.clone(), addr).await?;
+            AppState {
+                serves: Serves::Proxy,
+                ..state
+            }
+        }
+        None => state,
+    };
+
+    let result = match tls_options {
         Some(tls) if tls.enabled => serve_with_tls(state, tls).await,
-        _ => serve_http_only(state, port).await,
+        _ => serve_http_only(state, listen.port).await,
+    };
+    if let Some(path) = admin_socket {
+        std::fs::remove_file(path).ok();
+    }
+    result
+}
+
+/// Marks requests that came in on the admin socket
+#[derive(Clone)]
+struct AdminSocket;
+
+/// Serve the dashboard and API on their own listener at `addr`, over plain
+/// HTTP
+async fn serve_api_listener(state: AppState, addr: SocketAddr) -> Result<()> {
+    let listener = socket_activation::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind API listener {}", addr))?;
+    tracing::info!("Dashboard and API on http://{}", addr);
+    let app = create_router(api_listener_state(state));
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            tracing::error!("API listener error: {}", e);
+        }
+    });
+    Ok(())
+}
+
+/// State for the `api_listen` router. It is plain HTTP, so no request on it
+/// can carry a client certificate: `client_ca` only locks down the HTTPS
+/// listener, and this one relies on being bound to a private address.
+fn api_listener_state(mut state: AppState) -> AppState {
+    state.serves = Serves::Api;
+    state.tls_status.client_auth = false;
+    state
+}
+
+/// Serve the dashboard and API on a Unix socket at `path`, so the CLI can
+/// manage the daemon when the public listener is down or firewalled.
+/// Requests on it need no token: the socket is only accessible to the
+/// daemon's user and group.
+async fn serve_admin_socket(state: AppState, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    // A socket left behind by a daemon that didn't shut down cleanly
+    if path.exists() {
+        if tokio::net::UnixStream::connect(path).await.is_ok() {
+            anyhow::bail!(
+                "Admin socket {} is in use by another tenement",
+                path.display()
+            );
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind admin socket {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))
+            .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
     }
+    tracing::info!("Admin API on unix socket {}", path.display());
+
+    let app = create_router(AppState {
+        serves: Serves::Api,
+        ..state
+    })
+    .layer(axum::Extension(AdminSocket));
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    tracing::warn!("Admin socket accept failed: {}", e);
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+            let app = app.clone();
+            tokio::spawn(async move {
+                let service =
+                    hyper::service::service_fn(move |req: Request<hyper::body::Incoming>| {
+                        tower::ServiceExt::oneshot(app.clone(), req.map(Body::new))
+                    });
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                    .await
+                {
+                    tracing::debug!("Admin socket connection error: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
 }
 
 /// HTTP-only server (no TLS)
 async fn serve_http_only(state: AppState, port: u16) -> Result<()> {
     let app = create_router(state.clone());
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let listener = socket_activation::bind(addr).await?;
 
     tracing::info!("tenement listening on http://{}", addr);
-    tracing::info!("Dashboard at http://{}", state.domain);
+    if state.serves == Serves::All && state.dashboard {
+        tracing::info!("Dashboard at http://{}", state.domain);
+    }
 
-    let hypervisor = state.hypervisor.clone();
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal(hypervisor))
-        .await?;
+    // Stop accepting on signal, let in-flight requests finish (bounded, since
+    // streams like /api/logs/stream never end on their own), then stop instances
+    let signalled = Arc::new(tokio::sync::Notify::new());
+    let notify = signalled.clone();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        notify.notify_one();
+    });
+    let drain_timeout = proxy_drain_timeout(&state.hypervisor);
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            signalled.notified().await;
+            tokio::time::sleep(drain_timeout).await;
+        } => {
+            tracing::warn!(
+                "In-flight requests still open after {:?}, closing them",
+                drain_timeout
+            );
+        }
+    }
+
+    state.hypervisor.shutdown_all().await;
     Ok(())
 }
 
@@ -401,21 +1095,110 @@ async fn serve_with_tls(state: AppState, tls: TlsOptions) -> Result<()> {
         })?;
     }
 
-    // Create ACME configuration - uses TLS-ALPN-01 by default
-    // TLS-ALPN-01 handles challenges on port 443, no separate port 80 listener needed
-    let cache_dir = tls.cache_dir.clone();
-    let mut acme_state = AcmeConfig::new([tls.domain.clone()])
-        .contact([format!("mailto:{}", tls.email)])
-        .cache(DirCache::new(cache_dir))
-        .directory_lets_encrypt(!tls.staging) // true = production, false = staging
-        .state();
+    // Create one ACME order per certificate - uses TLS-ALPN-01 by default
+    // TLS-ALPN-01 handles challenges on port 443, no separate port 80 listener needed.
+    // Certificates with wildcard names use DNS-01 through [settings.tls.dns].
+    let certificates = tls.certificates();
+    let mut resolvers: Vec<(Vec<String>, Arc<dyn ResolvesServerCert>)> = Vec::new();
+    for names in &certificates {
+        if names.iter().any(|name| name.starts_with("*.")) {
+            let dns = tls
+                .dns
+                .as_ref()
+                .context("Wildcard certificates need [settings.tls.dns]")?;
+            let order = Dns01::new(
+                names.clone(),
+                &tls.email,
+                tls.cache_dir.clone(),
+                tls.staging,
+                dns,
+            );
+            resolvers.push((names.clone(), order.resolver()));
+            order.spawn();
+        } else {
+            let acme_state = AcmeConfig::new(names.clone())
+                .contact([format!("mailto:{}", tls.email)])
+                .cache(DirCache::new(tls.cache_dir.clone()))
+                .directory_lets_encrypt(!tls.staging) // true = production, false = staging
+                .state();
+            resolvers.push((names.clone(), acme_state.resolver()));
+            // Spawn ACME event handler (handles cert acquisition/renewal)
+            spawn_acme_events(acme_state, names.join(", "));
+        }
+    }
+
+    // Pick each connection's certificate by SNI. Offer HTTP/2 over ALPN so
+    // gRPC clients can connect, and acme-tls/1 so TLS-ALPN-01 validation
+    // handshakes get their challenge certificate.
+    // With client_ca, handshakes for the dashboard/API host also ask for a
+    // client certificate.
+    let acceptor = Acceptor::new(
+        Arc::new(SniResolver::new(resolvers)),
+        vec![
+            b"h2".to_vec(),
+            b"http/1.1".to_vec(),
+            ACME_TLS_ALPN_NAME.to_vec(),
+        ],
+        &state.domain,
+        tls.client_ca.as_deref(),
+    )?;
+
+    // Spawn HTTP redirect server on port 80
+    let https_port = tls.https_port;
+    let http_port = tls.http_port;
+
+    let http_server = tokio::spawn(async move {
+        if let Err(e) = serve_http_redirect(http_port, https_port).await {
+            tracing::error!("HTTP redirect server error: {}", e);
+        }
+    });
+
+    // Create HTTPS server
+    let app = create_router(state.clone());
+    let https_addr = SocketAddr::from(([0, 0, 0, 0], tls.https_port));
+
+    tracing::info!(
+        "tenement listening on https://{}:{}",
+        tls.domain,
+        tls.https_port
+    );
+    if certificates.len() > 1 || !tls.alt_names.is_empty() {
+        tracing::info!("TLS certificates for {}", certificates.concat().join(", "));
+    }
+    tracing::info!("HTTP redirect on port {}", tls.http_port);
+    if tls.staging {
+        tracing::warn!("Using Let's Encrypt STAGING environment (certs not trusted by browsers)");
+    }
+
+    // On signal: stop accepting, give in-flight requests time to finish,
+    // then stop instances
+    let handle = axum_server::Handle::new();
+    let drain_timeout = proxy_drain_timeout(&state.hypervisor);
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        shutdown_handle.graceful_shutdown(Some(drain_timeout));
+    });
+
+    // Bind and serve HTTPS
+    let server = match socket_activation::take(https_addr.port())? {
+        Some(listener) => axum_server::from_tcp(listener),
+        None => axum_server::bind(https_addr),
+    };
+    server
+        .acceptor(acceptor)
+        .handle(handle)
+        .serve(app.into_make_service())
+        .await?;
 
-    // Get acceptor for TLS connections (includes ACME challenge handling)
-    let acceptor = acme_state.axum_acceptor(acme_state.default_rustls_config());
+    http_server.abort();
+    state.hypervisor.shutdown_all().await;
+    Ok(())
+}
 
-    // Spawn ACME event handler (handles cert acquisition/renewal)
-    // Tracks consecutive errors and provides troubleshooting hints
-    let acme_domain = tls.domain.clone();
+/// Log certificate acquisition/renewal for one ACME order.
+/// Tracks consecutive errors and provides troubleshooting hints
+fn spawn_acme_events(mut acme_state: AcmeState<std::io::Error>, acme_domain: String) {
     tokio::spawn(async move {
         let mut consecutive_errors: u32 = 0;
         let mut cert_acquired = false;
@@ -462,40 +1245,7 @@ async fn serve_with_tls(state: AppState, tls: TlsOptions) -> Result<()> {
             }
         }
     });
-
-    // Spawn HTTP redirect server on port 80
-    let https_port = tls.https_port;
-    let http_port = tls.http_port;
-
-    let http_server = tokio::spawn(async move {
-        if let Err(e) = serve_http_redirect(http_port, https_port).await {
-            tracing::error!("HTTP redirect server error: {}", e);
-        }
-    });
-
-    // Create HTTPS server
-    let app = create_router(state.clone());
-    let https_addr = SocketAddr::from(([0, 0, 0, 0], tls.https_port));
-
-    tracing::info!(
-        "tenement listening on https://{}:{}",
-        tls.domain,
-        tls.https_port
-    );
-    tracing::info!("HTTP redirect on port {}", tls.http_port);
-    if tls.staging {
-        tracing::warn!("Using Let's Encrypt STAGING environment (certs not trusted by browsers)");
-    }
-
-    // Bind and serve HTTPS
-    axum_server::bind(https_addr)
-        .acceptor(acceptor)
-        .serve(app.into_make_service())
-        .await?;
-
-    http_server.abort();
-    Ok(())
-}
+}
 
 /// HTTP server on port 80 - redirects all traffic to HTTPS
 /// (TLS-ALPN-01 handles ACME challenges on port 443, so no challenge handling needed here)
@@ -521,7 +1271,7 @@ async fn serve_http_redirect(http_port: u16, https_port: u16) -> Result<()> {
     });
 
     let addr = SocketAddr::from(([0, 0, 0, 0], http_port));
-    let listener = tokio::net::TcpListener::bind(addr).await?;
+    let listener = socket_activation::bind(addr).await?;
 
     tracing::debug!("HTTP redirect server listening on port {}", http_port);
 
@@ -530,15 +1280,22 @@ async fn serve_http_redirect(http_port: u16, https_port: u16) -> Result<()> {
 }
 
 /// Serve dashboard
-async fn dashboard() -> impl IntoResponse {
-    crate::dashboard::serve_asset("").await
+async fn dashboard(State(state): State<AppState>) -> Response {
+    if !state.dashboard {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+    crate::dashboard::serve_asset("").await.into_response()
 }
 
 /// Serve dashboard assets
 async fn dashboard_asset(
+    State(state): State<AppState>,
     axum::extract::Path(path): axum::extract::Path<String>,
-) -> impl IntoResponse {
-    crate::dashboard::serve_asset(&path).await
+) -> Response {
+    if !state.dashboard {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+    crate::dashboard::serve_asset(&path).await.into_response()
 }
 
 /// Health check endpoint
@@ -556,6 +1313,8 @@ async fn tls_status_endpoint(State(state): State<AppState>) -> impl IntoResponse
     Json(TlsStatusResponse {
         enabled: state.tls_status.enabled,
         domain: state.tls_status.domain.clone(),
+        domains: state.tls_status.domains.clone(),
+        client_auth: state.tls_status.client_auth,
         staging: state.tls_status.staging,
         https_port: state.tls_status.https_port,
         http_port: state.tls_status.http_port,
@@ -571,6 +1330,8 @@ async fn tls_status_endpoint(State(state): State<AppState>) -> impl IntoResponse
 struct TlsStatusResponse {
     enabled: bool,
     domain: Option<String>,
+    domains: Vec<String>,
+    client_auth: bool,
     staging: bool,
     https_port: u16,
     http_port: u16,
@@ -580,7 +1341,11 @@ struct TlsStatusResponse {
 /// Prometheus metrics endpoint
 async fn metrics_endpoint(State(state): State<AppState>) -> impl IntoResponse {
     let metrics = state.hypervisor.metrics();
-    let output = metrics.format_prometheus().await;
+    let mut output = metrics.format_prometheus().await;
+    match tenement::HostStats::collect(&state.hypervisor.config().settings.data_dir).await {
+        Ok(host) => output.push_str(&host.format_prometheus()),
+        Err(e) => tracing::warn!("Host metrics unavailable: {:#}", e),
+    }
     (
         [(
             axum::http::header::CONTENT_TYPE,
@@ -651,12 +1416,25 @@ async fn telemetry_endpoint(State(state): State<AppState>) -> impl IntoResponse
     }))
 }
 
+/// Query parameters for the instance list
+#[derive(Debug, Deserialize)]
+struct ListInstancesParams {
+    /// Label selector: `key=value[,key=value...]`, all must match
+    label: Option<String>,
+}
+
 /// List all running instances (scoped by tenant token if present)
 async fn list_instances(
     State(state): State<AppState>,
     axum::Extension(auth): axum::Extension<AuthIdentity>,
-) -> impl IntoResponse {
-    let instances = state.hypervisor.list().await;
+    Query(params): Query<ListInstancesParams>,
+) -> Result<Json<Vec<InstanceInfo>>, (StatusCode, String)> {
+    let selector = match params.label {
+        Some(label) => tenement::instance::parse_label_selector(&label)
+            .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?,
+        None => Default::default(),
+    };
+    let instances = state.hypervisor.list_matching(&selector).await;
     let response: Vec<InstanceInfo> = instances
         .into_iter()
         .filter(|i| {
@@ -673,12 +1451,15 @@ async fn list_instances(
             idle_secs: i.idle_secs,
             restarts: i.restarts,
             health: i.health.to_string(),
+            status: i.status.to_string(),
             storage_used_bytes: i.storage_used_bytes,
             storage_quota_bytes: i.storage_quota_bytes,
             weight: i.weight,
+            exit_code: i.exit_code,
+            labels: i.labels,
         })
         .collect();
-    Json(response)
+    Ok(Json(response))
 }
 
 #[derive(Serialize)]
@@ -689,9 +1470,14 @@ struct InstanceInfo {
     idle_secs: u64,
     restarts: u32,
     health: String,
+    status: String,
     storage_used_bytes: u64,
     storage_quota_bytes: Option<u64>,
     weight: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "std::collections::BTreeMap::is_empty")]
+    labels: std::collections::BTreeMap<String, String>,
 }
 
 /// Get storage info for a specific instance
@@ -836,6 +1622,31 @@ struct ProxyTarget {
 }
 
 impl ProxyTarget {
+    /// Where a request for `listener` on this instance goes. An instance
+    /// spawned before the listener was configured serves it on its main one.
+    fn new(info: &tenement::instance::InstanceInfo, listener: &str) -> Self {
+        match info.listeners.iter().find(|l| l.name == listener) {
+            Some(tenement::Listener {
+                addr: tenement::ListenAddr::Tcp(_, port),
+                ..
+            }) => Self {
+                socket: info.socket.clone(),
+                port: Some(*port),
+            },
+            Some(tenement::Listener {
+                addr: tenement::ListenAddr::Socket(path),
+                ..
+            }) => Self {
+                socket: path.clone(),
+                port: None,
+            },
+            None => Self {
+                socket: info.socket.clone(),
+                port: info.port,
+            },
+        }
+    }
+
     #[allow(dead_code)]
     fn uses_tcp(&self) -> bool {
         self.port.is_some()
@@ -876,6 +1687,7 @@ async fn wait_for_alive_target(
     state: &AppState,
     process: &str,
     id: &str,
+    listener: &str,
     initial: ProxyTarget,
     deadline: std::time::Instant,
 ) -> Option<ProxyTarget> {
@@ -890,10 +1702,7 @@ async fn wait_for_alive_target(
     while std::time::Instant::now() < deadline {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
         if let Some(info) = state.hypervisor.get(process, id).await {
-            let candidate = ProxyTarget {
-                socket: info.socket,
-                port: info.port,
-            };
+            let candidate = ProxyTarget::new(&info, listener);
             if candidate.probe().await {
                 tracing::info!("Backend for {}:{} is back", process, id);
                 return Some(candidate);
@@ -909,8 +1718,8 @@ async fn handle_request(
     State(state): State<AppState>,
     req: Request<Body>,
 ) -> Response {
-    // Parse subdomain pattern
-    match parse_subdomain(&host, &state.domain) {
+    // Custom domain or subdomain pattern
+    match route_for_host(&state, &host) {
         Some(SubdomainRoute::Direct { process, id }) => {
             // Direct route to specific instance: :id.{process}.{domain}
             proxy_to_instance(&state, &process, Some(&id), req).await
@@ -935,6 +1744,24 @@ enum SubdomainRoute {
     Weighted { process: String },
 }
 
+/// Route for a request's host: a custom domain from `[domains]`, else the
+/// subdomain pattern
+fn route_for_host(state: &AppState, host: &str) -> Option<SubdomainRoute> {
+    let name = host.split(':').next().unwrap_or(host);
+    if let Some(mapped) = state.hypervisor.custom_domain(name) {
+        return Some(match mapped.instance {
+            Some(id) => SubdomainRoute::Direct {
+                process: mapped.service,
+                id,
+            },
+            None => SubdomainRoute::Weighted {
+                process: mapped.service,
+            },
+        });
+    }
+    parse_subdomain(host, &state.domain)
+}
+
 /// Parse subdomain pattern:
 /// - :id.{process}.{domain} -> Direct route to specific instance
 /// - {process}.{domain} -> Weighted route across all instances
@@ -989,7 +1816,7 @@ async fn proxy_to_instance(
     state: &AppState,
     process: &str,
     id: Option<&str>,
-    req: Request<Body>,
+    mut req: Request<Body>,
 ) -> Response {
     let start = std::time::Instant::now();
     tracing::debug!(
@@ -1006,27 +1833,91 @@ async fn proxy_to_instance(
         return (StatusCode::NOT_FOUND, "Not found").into_response();
     }
 
+    // Reject bodies over the service's limit up front when they declare a
+    // length, and cut off any that grow past it while streaming
+    let body_too_large = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    if let Some(limit) = state.hypervisor.max_body_size(process) {
+        let declared = req
+            .headers()
+            .get(axum::http::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .or_else(|| axum::body::HttpBody::size_hint(req.body()).exact());
+        if declared.is_some_and(|len| len > limit) {
+            tracing::debug!("Request body for {} exceeds {} bytes", process, limit);
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large").into_response();
+        }
+        let exceeded = body_too_large.clone();
+        req = req.map(|inner| {
+            Body::new(LimitedBody {
+                inner,
+                remaining: limit,
+                exceeded,
+            })
+        });
+    }
+
+    let pinned = match instance_override(state, process, req.headers_mut()).await {
+        Ok(pinned) => pinned,
+        Err(response) => return response,
+    };
+    let id = pinned.as_deref().or(id);
+    let listener = state.hypervisor.listener_for(process, req.uri().path());
+    let sticky = id.is_none() && state.hypervisor.is_sticky(process);
+    let sticky_id = if sticky {
+        ClientIdSource::Cookie(STICKY_COOKIE.to_string()).extract(
+            req.headers()
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?))),
+        )
+    } else {
+        None
+    };
+
     let mut resolved_instance_id: Option<String> = None;
+    // Instances already tried (or found dead) for this request
+    let mut tried: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut assignment: Option<ExperimentAssignment> = None;
     let target = match id {
         Some(instance_id) => {
             // Direct routing to specific instance
             let registered = match state.hypervisor.get_and_touch(process, instance_id).await {
-                Some(info) => Some(ProxyTarget {
-                    socket: info.socket,
-                    port: info.port,
-                }),
+                Some(info) if info.status == InstanceStatus::Stopping => {
+                    // Draining: don't send new requests, don't wake a replacement
+                    tracing::debug!("Instance {}:{} is draining", process, instance_id);
+                    return (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Service temporarily unavailable",
+                    )
+                        .into_response();
+                }
+                Some(info) if info.status == InstanceStatus::Starting => {
+                    // Not past its readiness gate yet: hold the request until it is
+                    if !state
+                        .hypervisor
+                        .wait_until_ready(process, instance_id)
+                        .await
+                    {
+                        tracing::debug!("Instance {}:{} never became ready", process, instance_id);
+                        return (
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            "Service temporarily unavailable",
+                        )
+                            .into_response();
+                    }
+                    Some(ProxyTarget::new(&info, &listener))
+                }
+                Some(info) => Some(ProxyTarget::new(&info, &listener)),
                 None => {
                     // Wake-on-request: spawn and wait for instance to be ready
                     tracing::info!("Waking instance {}:{}", process, instance_id);
                     match state.hypervisor.spawn_and_wait(process, instance_id).await {
                         Ok(socket) => {
-                            // Get port info from the now-running instance
-                            let port = state
-                                .hypervisor
-                                .get(process, instance_id)
-                                .await
-                                .and_then(|info| info.port);
-                            Some(ProxyTarget { socket, port })
+                            // Get listeners from the now-running instance
+                            match state.hypervisor.get(process, instance_id).await {
+                                Some(info) => Some(ProxyTarget::new(&info, &listener)),
+                                None => Some(ProxyTarget { socket, port: None }),
+                            }
                         }
                         Err(e) => {
                             tracing::error!(
@@ -1049,7 +1940,9 @@ async fn proxy_to_instance(
             // health-checker to restart it before forwarding the request.
             let initial = registered.unwrap();
             let deadline = std::time::Instant::now() + std::time::Duration::from_secs(15);
-            match wait_for_alive_target(state, process, instance_id, initial, deadline).await {
+            match wait_for_alive_target(state, process, instance_id, &listener, initial, deadline)
+                .await
+            {
                 Some(t) => t,
                 None => {
                     tracing::error!(
@@ -1070,14 +1963,33 @@ async fn proxy_to_instance(
             // select_weighted (respects configured weights). If that pick
             // is unreachable, fall back to a deterministic scan over the
             // remaining candidates so a dead backend can't burn the request.
+            // With an A/B experiment, an identified client only sees the
+            // instances of its assigned group.
+            // A sticky client goes back to the instance in its cookie while
+            // that one is still running and reachable.
+            assignment = experiment_assignment(state, process, req.headers());
             let mut chosen: Option<(ProxyTarget, String)> = None;
-            let mut tried: std::collections::HashSet<String> = std::collections::HashSet::new();
 
-            if let Some(info) = state.hypervisor.select_weighted(process).await {
-                let candidate = ProxyTarget {
-                    socket: info.socket.clone(),
-                    port: info.port,
-                };
+            if let Some(sticky_id) = &sticky_id {
+                let group = assignment.as_ref().map(|a| a.instances.as_slice());
+                chosen = sticky_target(state, process, sticky_id, group, &listener).await;
+                if chosen.is_none() {
+                    tried.insert(sticky_id.clone());
+                }
+            }
+
+            let pick = match (&chosen, &assignment) {
+                (Some(_), _) => None,
+                (None, Some(a)) => {
+                    state
+                        .hypervisor
+                        .select_weighted_among(process, &a.instances)
+                        .await
+                }
+                (None, None) => state.hypervisor.select_weighted(process).await,
+            };
+            if let Some(info) = pick {
+                let candidate = ProxyTarget::new(&info, &listener);
                 if candidate.probe().await {
                     state.hypervisor.touch_activity(process, &info.id.id).await;
                     chosen = Some((candidate, info.id.id.clone()));
@@ -1092,24 +2004,32 @@ async fn proxy_to_instance(
             }
 
             if chosen.is_none() {
-                for info in state.hypervisor.list_by_process(process).await {
-                    if !tried.insert(info.id.id.clone()) {
-                        continue;
-                    }
-                    let candidate = ProxyTarget {
-                        socket: info.socket.clone(),
-                        port: info.port,
-                    };
-                    if candidate.probe().await {
-                        state.hypervisor.touch_activity(process, &info.id.id).await;
-                        chosen = Some((candidate, info.id.id.clone()));
-                        break;
-                    }
+                let group = assignment.as_ref().map(|a| a.instances.as_slice());
+                chosen = scan_for_reachable(state, process, group, &listener, &mut tried).await;
+            }
+
+            if chosen.is_none() {
+                if let Some(a) = assignment.take() {
+                    tracing::warn!(
+                        "No reachable instance in group '{}' of experiment '{}'; routing outside the experiment",
+                        a.group,
+                        a.experiment
+                    );
+                    chosen = scan_for_reachable(state, process, None, &listener, &mut tried).await;
                 }
             }
 
             match chosen {
                 Some((target, id)) => {
+                    let by_instance = state
+                        .hypervisor
+                        .rate_limit(process)
+                        .is_some_and(|l| l.key == RateLimitKey::Instance);
+                    if by_instance {
+                        if let Some(throttled) = throttle(state, process, &id, Some(&id)).await {
+                            return throttled;
+                        }
+                    }
                     resolved_instance_id = Some(id);
                     target
                 }
@@ -1126,39 +2046,120 @@ async fn proxy_to_instance(
     };
 
     // Use the resolved instance ID (from weighted selection or direct routing)
-    let conn_instance_id = resolved_instance_id.as_deref().or(id).unwrap_or("unknown");
-    let _conn_guard = state
+    let mut instance_id = resolved_instance_id
+        .as_deref()
+        .or(id)
+        .unwrap_or("unknown")
+        .to_string();
+    set_headers(
+        req.headers_mut(),
+        &state.hypervisor.request_headers(process),
+    );
+    let mut conn_guard = state
         .hypervisor
-        .connection_start(process, conn_instance_id)
+        .connection_start(process, &instance_id)
         .await;
+    // Only bodiless requests are retried, so the body is this instance's
+    let request_meter = state.hypervisor.meter(process, &instance_id);
+    req = req.map(|inner| {
+        Body::new(MeteredBody {
+            inner,
+            meter: request_meter,
+            direction: Direction::In,
+        })
+    });
+
+    // A weighted GET or HEAD without a body can be replayed on another
+    // instance when the connection to this one fails
+    let replay = (id.is_none()
+        && (req.method() == axum::http::Method::GET || req.method() == axum::http::Method::HEAD)
+        && axum::body::HttpBody::is_end_stream(req.body()))
+    .then(|| {
+        (
+            req.method().clone(),
+            req.uri().clone(),
+            req.headers().clone(),
+        )
+    });
+    let mut retries = state.hypervisor.request_retries(process);
 
-    // Proxy with request timeout
+    // Proxy with request timeout, shared by all attempts
     let timeout = state.hypervisor.request_timeout(process);
-    let proxy_future: std::pin::Pin<Box<dyn std::future::Future<Output = Response> + Send>> =
-        if let Some(addr) = target.tcp_addr() {
-            let client = state.client.clone();
-            Box::pin(async move { proxy_to_tcp(&client, &addr, req).await })
-        } else {
-            let socket = target.socket.clone();
-            let unix_client = state.unix_client.clone();
-            Box::pin(async move { proxy_to_unix_socket(&unix_client, &socket, req).await })
+    let deadline = tokio::time::Instant::now() + timeout;
+    // gRPC only runs over HTTP/2, so it goes to the backend over h2c
+    let grpc = is_grpc(req.headers());
+    let mut target = target;
+    let mut req = req;
+    let response = loop {
+        let attempt = tokio::time::timeout_at(deadline, forward(state, &target, grpc, req)).await;
+        let error = match attempt {
+            Ok(Ok(resp)) => break resp,
+            Ok(Err(e)) => e,
+            Err(_) => {
+                tracing::error!(
+                    "Request timeout after {:?} for process {}",
+                    timeout,
+                    process
+                );
+                break (StatusCode::GATEWAY_TIMEOUT, "Gateway timeout").into_response();
+            }
         };
-
-    let response = match tokio::time::timeout(timeout, proxy_future).await {
-        Ok(resp) => resp,
-        Err(_) => {
-            tracing::error!(
-                "Request timeout after {:?} for process {}",
-                timeout,
-                process
+        if body_too_large.load(std::sync::atomic::Ordering::Relaxed) {
+            tracing::debug!(
+                "Request body for {}:{} grew past its limit",
+                process,
+                instance_id
             );
-            (StatusCode::GATEWAY_TIMEOUT, "Gateway timeout").into_response()
+            break (StatusCode::PAYLOAD_TOO_LARGE, "Payload too large").into_response();
         }
+        let next = match &replay {
+            Some(_) if retries > 0 => {
+                tried.insert(instance_id.clone());
+                let group = assignment.as_ref().map(|a| a.instances.as_slice());
+                scan_for_reachable(state, process, group, &listener, &mut tried).await
+            }
+            _ => None,
+        };
+        let (Some((method, uri, headers)), Some((next_target, next_id))) = (&replay, next) else {
+            tracing::error!("Proxy error to {}:{}: {}", process, instance_id, error);
+            break (StatusCode::BAD_GATEWAY, "Bad gateway".to_string()).into_response();
+        };
+        tracing::warn!(
+            "Proxy error to {}:{}: {}; retrying {} {} on {}",
+            process,
+            instance_id,
+            error,
+            method,
+            uri.path(),
+            next_id
+        );
+        retries -= 1;
+        let mut retry = Request::new(Body::empty());
+        *retry.method_mut() = method.clone();
+        *retry.uri_mut() = uri.clone();
+        *retry.headers_mut() = headers.clone();
+        req = retry;
+        target = next_target;
+        instance_id = next_id;
+        conn_guard = state
+            .hypervisor
+            .connection_start(process, &instance_id)
+            .await;
     };
+    let meter = state.hypervisor.meter(process, &instance_id);
+    meter.add_request();
+    let mut response = response;
+    response
+        .extensions_mut()
+        .insert(ProxiedInstance(instance_id.clone()));
+    set_headers(
+        response.headers_mut(),
+        &state.hypervisor.response_headers(process),
+    );
 
     // Record request metrics
     let duration_ms = start.elapsed().as_secs_f64() * 1000.0;
-    let instance_id = conn_instance_id;
+    let instance_id = instance_id.as_str();
     let metrics = state.hypervisor.metrics();
     let mut labels = std::collections::HashMap::new();
     labels.insert("process".to_string(), process.to_string());
@@ -1168,149 +2169,617 @@ async fn proxy_to_instance(
     let histogram = metrics.request_duration_ms.with_labels(&labels).await;
     histogram.observe(duration_ms);
 
-    response
+    tracing::debug!(
+        process = process,
+        instance = instance_id,
+        status = response.status().as_u16(),
+        duration_ms = duration_ms,
+        experiment = assignment.as_ref().map(|a| a.experiment.as_str()),
+        experiment_group = assignment.as_ref().map(|a| a.group.as_str()),
+        "proxy response"
+    );
+
+    let mut response = response;
+    if let Some(a) = assignment {
+        if let Ok(value) = format!("{}={}", a.experiment, a.group).parse() {
+            response
+                .headers_mut()
+                .insert(tenement::EXPERIMENT_HEADER, value);
+        }
+        response.extensions_mut().insert(ExperimentGroup {
+            experiment: a.experiment,
+            group: a.group,
+        });
+    }
+    if sticky && sticky_id.as_deref() != Some(instance_id) {
+        if let Some(cookie) = sticky_cookie(instance_id) {
+            response
+                .headers_mut()
+                .append(axum::http::header::SET_COOKIE, cookie);
+        }
+    }
+
+    // Streamed bodies (SSE, downloads) keep the connection counted until the
+    // client has them, so idle reaping and drains don't cut them off
+    response.map(|body| {
+        Body::new(TrackedBody {
+            inner: Body::new(MeteredBody {
+                inner: body,
+                meter,
+                direction: Direction::Out,
+            }),
+            _guard: conn_guard,
+        })
+    })
 }
 
-/// Proxy an HTTP request to a Unix socket (uses pooled client)
-async fn proxy_to_unix_socket(
-    client: &Client<UnixConnector, Body>,
-    socket_path: &Path,
-    req: Request<Body>,
-) -> Response {
-    // Build URI for Unix socket - hyperlocal requires a special URI format
-    let path_and_query = req
-        .uri()
-        .path_and_query()
-        .map(|pq| pq.as_str())
-        .unwrap_or("/");
-    let socket_uri = hyperlocal::Uri::new(socket_path, path_and_query);
+/// A proxied response body holding the instance's connection guard until it
+/// has been sent in full or the client goes away. Frames are passed through
+/// as they arrive, without buffering.
+struct TrackedBody {
+    inner: Body,
+    _guard: tenement::ConnectionGuard,
+}
 
-    // Build proxy request preserving method and headers
-    let mut proxy_req = Request::builder().method(req.method()).uri(socket_uri);
+impl axum::body::HttpBody for TrackedBody {
+    type Data = axum::body::Bytes;
+    type Error = axum::Error;
 
-    // Copy headers from original request
-    for (key, value) in req.headers() {
-        proxy_req = proxy_req.header(key, value);
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        std::pin::Pin::new(&mut self.inner).poll_frame(cx)
     }
 
-    let proxy_req = match proxy_req.body(req.into_body()) {
-        Ok(r) => r,
-        Err(e) => {
-            tracing::error!("Failed to build proxy request: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
-            )
-                .into_response();
-        }
-    };
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
 
-    // Forward request to Unix socket
-    match client.request(proxy_req).await {
-        Ok(response) => {
-            // Convert hyper Response to axum Response
-            let (parts, body) = response.into_parts();
-            Response::from_parts(parts, Body::new(body))
-        }
-        Err(e) => {
-            tracing::error!("Proxy error to {}: {}", socket_path.display(), e);
-            (StatusCode::BAD_GATEWAY, "Bad gateway".to_string()).into_response()
-        }
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
     }
 }
 
-/// Proxy an HTTP request to a TCP address
-async fn proxy_to_tcp(
-    client: &Client<hyper_util::client::legacy::connect::HttpConnector, Body>,
-    addr: &str,
-    req: Request<Body>,
-) -> Response {
-    // Build URI for TCP connection
-    let path_and_query = req
-        .uri()
-        .path_and_query()
-        .map(|pq| pq.as_str())
-        .unwrap_or("/");
-    let uri = format!("http://{}{}", addr, path_and_query);
+/// Which of an instance's byte counters a [`MeteredBody`] adds to
+#[derive(Clone, Copy)]
+enum Direction {
+    In,
+    Out,
+}
 
-    // Build proxy request preserving method and headers
-    let mut proxy_req = Request::builder().method(req.method()).uri(&uri);
+/// A proxied body counting its bytes in the instance's usage meter
+struct MeteredBody {
+    inner: Body,
+    meter: Arc<tenement::InstanceMeter>,
+    direction: Direction,
+}
 
-    // Copy headers from original request
-    for (key, value) in req.headers() {
-        proxy_req = proxy_req.header(key, value);
+impl axum::body::HttpBody for MeteredBody {
+    type Data = axum::body::Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(std::pin::Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()?.data_ref()) {
+            let len = data.len() as u64;
+            match self.direction {
+                Direction::In => self.meter.add_bytes_in(len),
+                Direction::Out => self.meter.add_bytes_out(len),
+            }
+        }
+        std::task::Poll::Ready(frame)
     }
 
-    let proxy_req = match proxy_req.body(req.into_body()) {
-        Ok(r) => r,
-        Err(e) => {
-            tracing::error!("Failed to build proxy request: {}", e);
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Internal server error".to_string(),
-            )
-                .into_response();
-        }
-    };
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
 
-    // Forward request to TCP address
-    match client.request(proxy_req).await {
-        Ok(response) => {
-            // Convert hyper Response to axum Response
-            let (parts, body) = response.into_parts();
-            Response::from_parts(parts, Body::new(body))
-        }
-        Err(e) => {
-            tracing::error!("Proxy error to {}: {}", addr, e);
-            (StatusCode::BAD_GATEWAY, "Bad gateway".to_string()).into_response()
-        }
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum_test::TestServer;
-    use tempfile::TempDir;
-    use tenement::{init_db, Config};
+/// Set configured `request_headers` or `response_headers`, replacing any
+/// already present with the same name
+fn set_headers(
+    headers: &mut axum::http::HeaderMap,
+    injected: &std::collections::BTreeMap<String, String>,
+) {
+    for (name, value) in injected {
+        let (Ok(name), Ok(value)) = (
+            axum::http::HeaderName::from_bytes(name.as_bytes()),
+            axum::http::HeaderValue::from_str(value),
+        ) else {
+            continue;
+        };
+        headers.insert(name, value);
+    }
+}
 
-    #[test]
-    fn test_parse_subdomain() {
-        // Direct routing patterns: :id.{process}.{domain}
-        match parse_subdomain("prod.api.example.com", "example.com") {
-            Some(SubdomainRoute::Direct { process, id }) => {
-                assert_eq!(process, "api");
-                assert_eq!(id, "prod");
-            }
-            _ => panic!("Expected Direct route"),
-        }
-        match parse_subdomain("user123.app.example.com", "example.com") {
-            Some(SubdomainRoute::Direct { process, id }) => {
-                assert_eq!(process, "app");
-                assert_eq!(id, "user123");
-            }
-            _ => panic!("Expected Direct route"),
-        }
-        match parse_subdomain("staging.web.example.com:8080", "example.com") {
-            Some(SubdomainRoute::Direct { process, id }) => {
-                assert_eq!(process, "web");
-                assert_eq!(id, "staging");
+/// The instance a proxied response came from, for the access log
+#[derive(Clone)]
+struct ProxiedInstance(String);
+
+/// The experiment group a proxied request was routed by, for the access log
+#[derive(Clone)]
+struct ExperimentGroup {
+    experiment: String,
+    group: String,
+}
+
+/// Marks a response sent by an instance, as opposed to one the proxy made
+#[derive(Clone)]
+struct FromInstance;
+
+/// A response body that writes its request's access log entry, with the
+/// bytes sent, when it ends or the client goes away
+struct AccessLogBody {
+    inner: Body,
+    store: Arc<tenement::AccessLogStore>,
+    entry: Option<tenement::AccessLogEntry>,
+}
+
+impl axum::body::HttpBody for AccessLogBody {
+    type Data = axum::body::Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(std::pin::Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()?.data_ref()) {
+            let len = data.len() as u64;
+            if let Some(entry) = self.entry.as_mut() {
+                entry.bytes += len;
             }
-            _ => panic!("Expected Direct route"),
         }
+        std::task::Poll::Ready(frame)
+    }
 
-        // Weighted routing patterns: {process}.{domain}
-        match parse_subdomain("api.example.com", "example.com") {
-            Some(SubdomainRoute::Weighted { process }) => {
-                assert_eq!(process, "api");
-            }
-            _ => panic!("Expected Weighted route"),
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for AccessLogBody {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            self.store.push(entry);
         }
-        match parse_subdomain("web.example.com:8080", "example.com") {
-            Some(SubdomainRoute::Weighted { process }) => {
-                assert_eq!(process, "web");
+    }
+}
+
+/// A request body that fails once more than `remaining` bytes have been
+/// read, flagging `exceeded` so the proxy can answer 413
+struct LimitedBody {
+    inner: Body,
+    remaining: u64,
+    exceeded: Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl axum::body::HttpBody for LimitedBody {
+    type Data = axum::body::Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<hyper::body::Frame<Self::Data>, Self::Error>>> {
+        let frame = std::task::ready!(std::pin::Pin::new(&mut self.inner).poll_frame(cx));
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()?.data_ref()) {
+            let len = data.len() as u64;
+            if len > self.remaining {
+                self.exceeded
+                    .store(true, std::sync::atomic::Ordering::Relaxed);
+                return std::task::Poll::Ready(Some(Err(axum::Error::new(
+                    "request body too large",
+                ))));
             }
-            _ => panic!("Expected Weighted route"),
+            self.remaining -= len;
+        }
+        std::task::Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> hyper::body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Whether a request is gRPC, which needs HTTP/2 to the backend. gRPC-Web
+/// works over HTTP/1.1 and is proxied like any other request.
+fn is_grpc(headers: &axum::http::HeaderMap) -> bool {
+    headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| {
+            ct.starts_with("application/grpc") && !ct.starts_with("application/grpc-web")
+        })
+}
+
+/// Turn an HTTP error answering a gRPC request into a trailers-only gRPC
+/// response, so clients get a `grpc-status` instead of a transport error.
+/// Responses from the backend itself (`application/grpc`) pass through.
+fn grpc_error_response(response: Response) -> Response {
+    if is_grpc(response.headers()) {
+        return response;
+    }
+    let status = response.status();
+    // https://github.com/grpc/grpc/blob/master/doc/http-grpc-status-mapping.md
+    let code = match status {
+        StatusCode::BAD_REQUEST => 13,      // INTERNAL
+        StatusCode::UNAUTHORIZED => 16,     // UNAUTHENTICATED
+        StatusCode::FORBIDDEN => 7,         // PERMISSION_DENIED
+        StatusCode::NOT_FOUND => 12,        // UNIMPLEMENTED
+        StatusCode::PAYLOAD_TOO_LARGE => 8, // RESOURCE_EXHAUSTED
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT => 14, // UNAVAILABLE
+        _ => 2,                             // UNKNOWN
+    };
+    let message = status.canonical_reason().unwrap_or("Unknown");
+    (
+        StatusCode::OK,
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                "application/grpc".to_string(),
+            ),
+            (
+                axum::http::HeaderName::from_static("grpc-status"),
+                code.to_string(),
+            ),
+            (
+                axum::http::HeaderName::from_static("grpc-message"),
+                message.to_string(),
+            ),
+        ],
+    )
+        .into_response()
+}
+
+/// Take the `X-Tenement-Instance` override (and its token) off a proxied
+/// request. Returns the instance ID to route to, or an error response if the
+/// header is malformed, names another service, or lacks a valid admin token.
+async fn instance_override(
+    state: &AppState,
+    process: &str,
+    headers: &mut axum::http::HeaderMap,
+) -> Result<Option<String>, Response> {
+    // Never forward the admin token to the backend
+    let token = headers.remove(TOKEN_HEADER);
+    let Some(value) = headers.remove(INSTANCE_HEADER) else {
+        return Ok(None);
+    };
+
+    let target = value
+        .to_str()
+        .ok()
+        .and_then(|v| v.split_once(':'))
+        .filter(|(p, id)| !p.is_empty() && !id.is_empty());
+    let Some((target_process, target_id)) = target else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "X-Tenement-Instance must be process:id",
+        )
+            .into_response());
+    };
+    if target_process != process {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "X-Tenement-Instance names another service",
+        )
+            .into_response());
+    }
+
+    let Some(token) = token.as_ref().and_then(|t| t.to_str().ok()) else {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+    {
+        let failures = state.auth_failures.read().await;
+        if let (count, Some(last)) = &*failures {
+            if *count >= 10 && last.elapsed() < std::time::Duration::from_secs(5) {
+                return Err(StatusCode::TOO_MANY_REQUESTS.into_response());
+            }
+        }
+    }
+    match TokenStore::new(&state.config_store).verify(token).await {
+        Ok(true) => {
+            *state.auth_failures.write().await = (0, None);
+            tracing::info!(
+                "Routing request to {}:{} (X-Tenement-Instance override)",
+                process,
+                target_id
+            );
+            Ok(Some(target_id.to_string()))
+        }
+        Ok(false) => {
+            let mut failures = state.auth_failures.write().await;
+            failures.0 += 1;
+            failures.1 = Some(std::time::Instant::now());
+            Err(StatusCode::UNAUTHORIZED.into_response())
+        }
+        Err(e) => {
+            tracing::error!("Token verification error: {}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// A client's A/B experiment group for one request
+struct ExperimentAssignment {
+    experiment: String,
+    group: String,
+    /// Instance IDs serving the group
+    instances: Vec<String>,
+}
+
+/// Assign the request's client to a group of the service's experiment, if the
+/// service has one and the request carries the configured client identifier.
+fn experiment_assignment(
+    state: &AppState,
+    process: &str,
+    headers: &axum::http::HeaderMap,
+) -> Option<ExperimentAssignment> {
+    let (name, experiment) = state.hypervisor.experiment_for(process)?;
+    let client_id = experiment.client_id_source()?.extract(
+        headers
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?))),
+    )?;
+    let (group, config) = experiment.assign(&name, &client_id)?;
+    Some(ExperimentAssignment {
+        experiment: name,
+        group: group.to_string(),
+        instances: config.instances.clone(),
+    })
+}
+
+/// The instance named by a sticky client's cookie, if it is running, takes
+/// traffic, isn't failing health checks, is in the client's experiment group
+/// (if any) and is reachable.
+async fn sticky_target(
+    state: &AppState,
+    process: &str,
+    id: &str,
+    group: Option<&[String]>,
+    listener: &str,
+) -> Option<(ProxyTarget, String)> {
+    let info = state.hypervisor.get(process, id).await?;
+    let route_unknown = state.hypervisor.config().settings.route_unknown_health;
+    if info.status != InstanceStatus::Running
+        || info.weight == 0
+        || !info.health.is_routable(route_unknown)
+        || info.id.is_warm()
+        || group.is_some_and(|ids| !ids.iter().any(|i| i == id))
+    {
+        tracing::debug!(
+            "Sticky instance {}:{} is gone; picking another",
+            process,
+            id
+        );
+        return None;
+    }
+    let candidate = ProxyTarget::new(&info, listener);
+    if !candidate.probe().await {
+        tracing::warn!(
+            "Sticky instance {}:{} is unreachable; picking another",
+            process,
+            id
+        );
+        return None;
+    }
+    state.hypervisor.touch_activity(process, id).await;
+    Some((candidate, info.id.id))
+}
+
+/// `Set-Cookie` value sticking a client to `id`, or None if the ID can't be
+/// a cookie value
+fn sticky_cookie(id: &str) -> Option<axum::http::HeaderValue> {
+    let valid = id
+        .bytes()
+        .all(|b| b.is_ascii_graphic() && !matches!(b, b'"' | b',' | b';' | b'\\'));
+    if !valid {
+        return None;
+    }
+    format!("{}={}; Path=/; HttpOnly; SameSite=Lax", STICKY_COOKIE, id)
+        .parse()
+        .ok()
+}
+
+/// Deterministic scan for a reachable running instance not yet tried,
+/// optionally restricted to the given instance IDs.
+async fn scan_for_reachable(
+    state: &AppState,
+    process: &str,
+    only: Option<&[String]>,
+    listener: &str,
+    tried: &mut std::collections::HashSet<String>,
+) -> Option<(ProxyTarget, String)> {
+    let route_unknown = state.hypervisor.config().settings.route_unknown_health;
+    for info in state.hypervisor.list_by_process(process).await {
+        if info.status != InstanceStatus::Running
+            || !info.health.is_routable(route_unknown)
+            || only.is_some_and(|ids| !ids.contains(&info.id.id))
+            || !tried.insert(info.id.id.clone())
+        {
+            continue;
+        }
+        let candidate = ProxyTarget::new(&info, listener);
+        if candidate.probe().await {
+            state.hypervisor.touch_activity(process, &info.id.id).await;
+            return Some((candidate, info.id.id.clone()));
+        }
+    }
+    None
+}
+
+/// Send a request to `target` over the right client. Errs only if the
+/// request never got a response (connection refused, reset, ...).
+async fn forward(
+    state: &AppState,
+    target: &ProxyTarget,
+    grpc: bool,
+    req: Request<Body>,
+) -> Result<Response, hyper_util::client::legacy::Error> {
+    let mut response = if let Some(addr) = target.tcp_addr() {
+        let client = if grpc {
+            &state.h2_client
+        } else {
+            &state.client
+        };
+        proxy_to_tcp(client, &addr, req).await?
+    } else {
+        let client = if grpc {
+            &state.unix_h2_client
+        } else {
+            &state.unix_client
+        };
+        proxy_to_unix_socket(client, &target.socket, req).await?
+    };
+    response.extensions_mut().insert(FromInstance);
+    Ok(response)
+}
+
+/// Proxy an HTTP request to a Unix socket (uses pooled client)
+async fn proxy_to_unix_socket(
+    client: &Client<UnixConnector, Body>,
+    socket_path: &Path,
+    req: Request<Body>,
+) -> Result<Response, hyper_util::client::legacy::Error> {
+    // Build URI for Unix socket - hyperlocal requires a special URI format
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let socket_uri = hyperlocal::Uri::new(socket_path, path_and_query);
+
+    // Build proxy request preserving method and headers
+    let mut proxy_req = Request::builder().method(req.method()).uri(socket_uri);
+
+    // Copy headers from original request
+    for (key, value) in req.headers() {
+        proxy_req = proxy_req.header(key, value);
+    }
+
+    let proxy_req = match proxy_req.body(req.into_body()) {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!("Failed to build proxy request: {}", e);
+            return Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            )
+                .into_response());
+        }
+    };
+
+    // Forward request to Unix socket
+    let response = client.request(proxy_req).await?;
+    // Convert hyper Response to axum Response
+    let (parts, body) = response.into_parts();
+    Ok(Response::from_parts(parts, Body::new(body)))
+}
+
+/// Proxy an HTTP request to a TCP address
+async fn proxy_to_tcp(
+    client: &Client<hyper_util::client::legacy::connect::HttpConnector, Body>,
+    addr: &str,
+    req: Request<Body>,
+) -> Result<Response, hyper_util::client::legacy::Error> {
+    // Build URI for TCP connection
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let uri = format!("http://{}{}", addr, path_and_query);
+
+    // Build proxy request preserving method and headers
+    let mut proxy_req = Request::builder().method(req.method()).uri(&uri);
+
+    // Copy headers from original request
+    for (key, value) in req.headers() {
+        proxy_req = proxy_req.header(key, value);
+    }
+
+    let proxy_req = match proxy_req.body(req.into_body()) {
+        Ok(r) => r,
+        Err(e) => {
+            tracing::error!("Failed to build proxy request: {}", e);
+            return Ok((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Internal server error".to_string(),
+            )
+                .into_response());
+        }
+    };
+
+    // Forward request to TCP address
+    let response = client.request(proxy_req).await?;
+    // Convert hyper Response to axum Response
+    let (parts, body) = response.into_parts();
+    Ok(Response::from_parts(parts, Body::new(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum_test::TestServer;
+    use tempfile::TempDir;
+    use tenement::{init_db, Config};
+
+    #[test]
+    fn test_parse_subdomain() {
+        // Direct routing patterns: :id.{process}.{domain}
+        match parse_subdomain("prod.api.example.com", "example.com") {
+            Some(SubdomainRoute::Direct { process, id }) => {
+                assert_eq!(process, "api");
+                assert_eq!(id, "prod");
+            }
+            _ => panic!("Expected Direct route"),
+        }
+        match parse_subdomain("user123.app.example.com", "example.com") {
+            Some(SubdomainRoute::Direct { process, id }) => {
+                assert_eq!(process, "app");
+                assert_eq!(id, "user123");
+            }
+            _ => panic!("Expected Direct route"),
+        }
+        match parse_subdomain("staging.web.example.com:8080", "example.com") {
+            Some(SubdomainRoute::Direct { process, id }) => {
+                assert_eq!(process, "web");
+                assert_eq!(id, "staging");
+            }
+            _ => panic!("Expected Direct route"),
+        }
+
+        // Weighted routing patterns: {process}.{domain}
+        match parse_subdomain("api.example.com", "example.com") {
+            Some(SubdomainRoute::Weighted { process }) => {
+                assert_eq!(process, "api");
+            }
+            _ => panic!("Expected Weighted route"),
+        }
+        match parse_subdomain("web.example.com:8080", "example.com") {
+            Some(SubdomainRoute::Weighted { process }) => {
+                assert_eq!(process, "web");
+            }
+            _ => panic!("Expected Weighted route"),
         }
 
         // Invalid patterns
@@ -1322,35 +2791,81 @@ mod tests {
     /// Create test state with auth token
     /// Returns (state, token, temp_dir) - temp_dir must be kept alive during test
     async fn create_test_state() -> (AppState, String, TempDir) {
+        create_test_state_with_config(Config::default()).await
+    }
+
+    async fn create_test_state_with_config(config: Config) -> (AppState, String, TempDir) {
         let dir = TempDir::new().unwrap();
         let db_path = dir.path().join("test.db");
         let pool = init_db(&db_path).await.unwrap();
         let config_store = Arc::new(ConfigStore::new(pool.clone()));
         let deploy_log = Arc::new(tenement::DeployLogStore::new(pool.clone()));
+        let access_log = tenement::AccessLogStore::new(pool.clone());
         let tenant_tokens = Arc::new(tenement::TenantTokenStore::new(pool));
 
         // Generate and store a test token
         let token_store = TokenStore::new(&config_store);
         let token = token_store.generate_and_store().await.unwrap();
 
-        let config = Config::default();
-        let hypervisor = Hypervisor::new(config);
+        let hypervisor = Hypervisor::new(config).with_access_log(access_log);
         let client = Client::builder(TokioExecutor::new()).build_http();
         let unix_client = Client::builder(TokioExecutor::new()).build(UnixConnector);
+        let h2_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http();
+        let unix_h2_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build(UnixConnector);
         let state = AppState {
             hypervisor,
             domain: "example.com".to_string(),
             client,
             unix_client,
+            h2_client,
+            unix_h2_client,
             config_store,
             deploy_log,
             tenant_tokens,
             tls_status: TlsStatus::default(),
+            dashboard: true,
+            serves: Serves::All,
             auth_failures: Arc::new(tokio::sync::RwLock::new((0, None))),
         };
         (state, token, dir)
     }
 
+    #[tokio::test]
+    async fn test_alerts_endpoint() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "./api"
+
+[alert.panics]
+service = "api"
+pattern = "panic"
+"#,
+        )
+        .unwrap();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        state
+            .hypervisor
+            .log_buffer()
+            .push_stderr("api", "prod", "thread 'main' panicked".to_string())
+            .await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .get("/api/alerts")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let alerts: Vec<serde_json::Value> = response.json();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0]["rule"], "panics");
+        assert_eq!(alerts[0]["instance_id"], "prod");
+    }
+
     #[tokio::test]
     async fn test_health_endpoint() {
         let (state, _token, _dir) = create_test_state().await;
@@ -1399,13 +2914,1177 @@ mod tests {
 
         // Simulate request to subdomain for unconfigured process
         let response = server
-            .get("/some-path")
-            .add_header("Host", "prod.api.example.com")
+            .get("/some-path")
+            .add_header("Host", "prod.api.example.com")
+            .await;
+
+        response.assert_status_not_found();
+        // Error message is sanitized (no internal details for unauthenticated users)
+        response.assert_text_contains("Not found");
+    }
+
+    #[tokio::test]
+    async fn test_grpc_errors_become_grpc_status() {
+        let (state, _token, _dir) = create_test_state().await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        // Unconfigured service: a trailers-only UNIMPLEMENTED, not an HTTP 404
+        let response = server
+            .post("/pkg.Echo/Say")
+            .add_header("Host", "prod.api.example.com")
+            .add_header("Content-Type", "application/grpc+proto")
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.header("content-type"), "application/grpc");
+        assert_eq!(response.header("grpc-status"), "12");
+
+        // gRPC-Web and plain HTTP keep their HTTP errors
+        let response = server
+            .post("/pkg.Echo/Say")
+            .add_header("Host", "prod.api.example.com")
+            .add_header("Content-Type", "application/grpc-web+proto")
+            .await;
+        response.assert_status_not_found();
+        assert!(response.maybe_header("grpc-status").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_auth_gate_returns_401() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "false"
+isolation = "process"
+startup_timeout = 1
+auth = { type = "bearer", token_env = "TENEMENT_TEST_PROXY_TOKEN" }
+"#,
+        )
+        .unwrap();
+        std::env::set_var("TENEMENT_TEST_PROXY_TOKEN", "let-me-in");
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .get("/")
+            .add_header("Host", "alice.api.example.com")
+            .await;
+        response.assert_status_unauthorized();
+        assert_eq!(response.header("www-authenticate"), "Bearer realm=\"api\"");
+        let response = server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header("Authorization", "Bearer wrong")
+            .await;
+        response.assert_status_unauthorized();
+        // Turned away before anything was woken
+        assert!(hypervisor.list().await.is_empty());
+
+        let response = server
+            .get("/")
+            .add_header("Host", "alice.api.example.com")
+            .add_header("Authorization", "Bearer let-me-in")
+            .await;
+        assert_ne!(response.status_code(), StatusCode::UNAUTHORIZED);
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_returns_429() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1", "--directory", "{data_dir}/{name}/{id}"]
+isolation = "process"
+
+[service.api.rate_limit]
+requests_per_second = 0.01
+burst = 2
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        hypervisor.spawn("api", "alice").await.unwrap();
+        let port = hypervisor.get("api", "alice").await.unwrap().port.unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        for _ in 0..2 {
+            let response = server
+                .get("/")
+                .add_header("Host", "alice.api.example.com")
+                .await;
+            response.assert_status_ok();
+        }
+        let response = server
+            .get("/")
+            .add_header("Host", "alice.api.example.com")
+            .await;
+        assert_eq!(response.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.header("retry-after"), "1");
+
+        // The weighted route is a different subdomain with its own bucket
+        let response = server.get("/").add_header("Host", "api.example.com").await;
+        response.assert_status_ok();
+
+        let metrics = server
+            .get("/metrics")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .text();
+        assert!(
+            metrics
+                .contains(r#"tenement_requests_throttled_total{instance="alice",process="api"} 1"#),
+            "got: {metrics}"
+        );
+
+        hypervisor.stop("api", "alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_max_body_size_returns_413() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "./api"
+max_body_size_mb = 1
+"#,
+        )
+        .unwrap();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .post("/upload")
+            .add_header("Host", "prod.api.example.com")
+            .bytes(vec![0u8; 1024 * 1024 + 1].into())
+            .await;
+        assert_eq!(response.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // A body without a declared length is cut off once it passes the limit
+        let exceeded = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let chunks = (0..2).map(|_| Ok::<_, std::io::Error>(vec![0u8; 600 * 1024]));
+        let body = Body::new(LimitedBody {
+            inner: Body::from_stream(futures::stream::iter(chunks)),
+            remaining: 1024 * 1024,
+            exceeded: exceeded.clone(),
+        });
+        assert!(axum::body::to_bytes(body, usize::MAX).await.is_err());
+        assert!(exceeded.load(std::sync::atomic::Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_injected_headers() {
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-c", '''
+import http.server, sys
+class H(http.server.BaseHTTPRequestHandler):
+    def do_GET(self):
+        body = (self.headers.get("X-Internal-Auth") or "none").encode()
+        self.send_response(200)
+        self.send_header("Cache-Control", "public")
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+http.server.HTTPServer(("127.0.0.1", int(sys.argv[1])), H).serve_forever()
+''', "{port}"]
+isolation = "process"
+
+[service.api.request_headers]
+X-Internal-Auth = "s3cret"
+
+[service.api.response_headers]
+Cache-Control = "no-store"
+Strict-Transport-Security = "max-age=31536000"
+"#,
+        )
+        .unwrap();
+        let data_dir = TempDir::new().unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let port = hypervisor.get("api", "prod").await.unwrap().port.unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server
+            .get("/")
+            .add_header("Host", "prod.api.example.com")
+            .add_header("X-Internal-Auth", "forged")
+            .await;
+        response.assert_status_ok();
+        assert_eq!(response.text(), "s3cret");
+        assert_eq!(response.header("cache-control"), "no-store");
+        assert_eq!(
+            response.header("strict-transport-security"),
+            "max-age=31536000"
+        );
+
+        hypervisor.stop("api", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_access_log_records_proxied_requests() {
+        let config = Config::from_str("[service.api]\ncommand = \"./missing-binary\"\n").unwrap();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        server
+            .get("/orders?page=2")
+            .add_header("Host", "prod.web.example.com")
+            .await
+            .assert_status_not_found();
+        server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .await
+            .assert_status_service_unavailable();
+        // API requests aren't proxied, so they aren't recorded
+        server
+            .get("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status_ok();
+
+        let mut entries: Vec<tenement::AccessLogEntry> = Vec::new();
+        for _ in 0..50 {
+            entries = server
+                .get("/api/access-logs")
+                .add_header("Authorization", format!("Bearer {}", token))
+                .await
+                .json();
+            if entries.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(entries.len(), 2);
+        let missing = entries.iter().find(|e| e.process == "web").unwrap();
+        assert_eq!(missing.host, "prod.web.example.com");
+        assert_eq!(missing.method, "GET");
+        assert_eq!(missing.path, "/orders");
+        assert_eq!(missing.status, 404);
+        assert_eq!(missing.bytes, "Not found".len() as u64);
+        assert_eq!(missing.instance_id, None);
+
+        let unavailable: Vec<tenement::AccessLogEntry> = server
+            .get("/api/access-logs?status=503")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .json();
+        assert_eq!(unavailable.len(), 1);
+        assert_eq!(unavailable[0].process, "api");
+
+        server
+            .get("/api/access-logs")
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    async fn test_usage_metering() {
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-c", '''
+import http.server, sys
+class H(http.server.BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers["Content-Length"]))
+        self.send_response(200)
+        self.send_header("Content-Length", str(len(body) * 2))
+        self.end_headers()
+        self.wfile.write(body * 2)
+http.server.HTTPServer(("127.0.0.1", int(sys.argv[1])), H).serve_forever()
+''', "{port}"]
+isolation = "process"
+"#,
+        )
+        .unwrap();
+        let data_dir = TempDir::new().unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, dir) = create_test_state_with_config(config.clone()).await;
+        let pool = init_db(&dir.path().join("test.db")).await.unwrap();
+        let usage = Arc::new(tenement::UsageStore::new(pool));
+        let hypervisor = Hypervisor::new(config).with_usage(usage.clone());
+        let state = AppState {
+            hypervisor: hypervisor.clone(),
+            ..state
+        };
+        let tenant_token = state
+            .tenant_tokens
+            .generate_and_store("alice", Some("test"))
+            .await
+            .unwrap();
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let port = hypervisor.get("api", "prod").await.unwrap().port.unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        let recorder = tenement::MeterRecorder::new(hypervisor.clone(), usage);
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        for body in ["hello", "hi"] {
+            let response = server
+                .post("/")
+                .add_header("Host", "prod.api.example.com")
+                .text(body)
+                .await;
+            response.assert_status_ok();
+            assert_eq!(response.text(), body.repeat(2));
+        }
+        assert_eq!(recorder.flush().await.unwrap(), 1);
+
+        let report: tenement::UsageReport = server
+            .get("/api/usage?instance=api:prod&range=1d")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .json();
+        assert_eq!(report.instances.len(), 1);
+        let usage = &report.instances[0];
+        assert_eq!(usage.instance_id, "prod");
+        assert_eq!(usage.total.requests, 2);
+        assert_eq!(usage.total.bytes_in, 7);
+        assert_eq!(usage.total.bytes_out, 14);
+        assert_eq!(usage.daily.len(), 1);
+
+        // Tenant tokens only see their own instance
+        let report: tenement::UsageReport = server
+            .get("/api/usage")
+            .add_header("Authorization", format!("Bearer {}", tenant_token))
+            .await
+            .json();
+        assert!(report.instances.is_empty());
+        server
+            .get("/api/usage?instance=api:prod")
+            .add_header("Authorization", format!("Bearer {}", tenant_token))
+            .await
+            .assert_status_forbidden();
+        server
+            .get("/api/usage?range=soon")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status_bad_request();
+
+        hypervisor.stop("api", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_api_on_its_own_listener() {
+        let config = Config::from_str("[service.api]\ncommand = \"./api\"\n").unwrap();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+
+        // The public listener only has instances and /health
+        let public = TestServer::new(create_router(AppState {
+            serves: Serves::Proxy,
+            ..state.clone()
+        }))
+        .unwrap();
+        public.get("/health").await.assert_status_ok();
+        public.get("/").await.assert_status_not_found();
+        public
+            .get("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status_not_found();
+        public
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .await
+            .assert_status_service_unavailable();
+
+        // The API listener never proxies, and can go without the dashboard
+        let api = TestServer::new(create_router(AppState {
+            serves: Serves::Api,
+            dashboard: false,
+            ..state
+        }))
+        .unwrap();
+        api.get("/").await.assert_status_not_found();
+        api.get("/assets/app.js").await.assert_status_not_found();
+        api.get("/api/instances")
+            .add_header("Host", "api.example.com")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_admin_socket_skips_token() {
+        let config = Config::from_str("[service.api]\ncommand = \"./api\"\n").unwrap();
+        let (state, _token, dir) = create_test_state_with_config(config).await;
+        let socket = dir.path().join("run").join("admin.sock");
+        serve_admin_socket(state.clone(), &socket).await.unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o660);
+        }
+
+        let client = crate::client::ApiClient::unix(&socket).unwrap();
+        assert!(client.list(&Default::default()).await.unwrap().is_empty());
+        assert_eq!(client.server(), format!("unix:{}", socket.display()));
+
+        // Instance hosts aren't proxied on it
+        let response = reqwest::Client::builder()
+            .unix_socket(socket.as_path())
+            .build()
+            .unwrap()
+            .get("http://prod.api.example.com/api/instances")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // A second daemon can't take over the socket
+        let err = serve_admin_socket(state, &socket).await.unwrap_err();
+        assert!(err.to_string().contains("in use"), "got: {err}");
+    }
+
+    /// Serve "ok" on `socket` until the returned task is aborted, counting
+    /// accepted connections
+    fn serve_unix_ok(
+        socket: &Path,
+        accepted: Arc<std::sync::atomic::AtomicUsize>,
+    ) -> tokio::task::JoinHandle<()> {
+        let listener = tokio::net::UnixListener::bind(socket).unwrap();
+        tokio::spawn(async move {
+            let mut connections = tokio::task::JoinSet::new();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                connections.spawn(async move {
+                    let service = hyper::service::service_fn(|_req| async {
+                        Ok::<_, Infallible>(hyper::Response::new(http_body_util::Full::new(
+                            axum::body::Bytes::from("ok"),
+                        )))
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(hyper_util::rt::TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_connections_are_reused() {
+        let dir = TempDir::new().unwrap();
+        let socket = dir.path().join("app.sock");
+        let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let server = serve_unix_ok(&socket, accepted.clone());
+        let client = Client::builder(TokioExecutor::new()).build(UnixConnector);
+
+        for _ in 0..5 {
+            let response = proxy_to_unix_socket(&client, &socket, Request::new(Body::empty()))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            // Let the connection go back to the pool
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        // The instance restarts on the same path: its old pooled connection
+        // is gone, and the next request opens a new one
+        server.abort();
+        let _ = server.await;
+        std::fs::remove_file(&socket).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let _server = serve_unix_ok(&socket, accepted.clone());
+        let response = proxy_to_unix_socket(&client, &socket, Request::new(Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compression() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1", "--directory", "{data_dir}/{name}/{id}"]
+isolation = "process"
+compression = true
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let page = "<p>hello</p>\n".repeat(1000);
+        let root = data_dir.path().join("api").join("prod");
+        std::fs::write(root.join("index.html"), &page).unwrap();
+        std::fs::write(root.join("logo.png"), &page).unwrap();
+        let port = hypervisor.get("api", "prod").await.unwrap().port.unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        for (accept, expected) in [("gzip", "gzip"), ("gzip;q=0.5, br", "br")] {
+            let response = server
+                .get("/")
+                .add_header("Host", "prod.api.example.com")
+                .add_header("Accept-Encoding", accept)
+                .await;
+            response.assert_status_ok();
+            assert_eq!(response.header("content-encoding"), expected);
+            assert!(response.as_bytes().len() < page.len());
+        }
+
+        // Not asked for, or not worth it: sent as is
+        let response = server
+            .get("/")
+            .add_header("Host", "prod.api.example.com")
+            .await;
+        assert!(response.maybe_header("content-encoding").is_none());
+        assert_eq!(response.text(), page);
+        let response = server
+            .get("/logo.png")
+            .add_header("Host", "prod.api.example.com")
+            .add_header("Accept-Encoding", "gzip")
+            .await;
+        assert!(response.maybe_header("content-encoding").is_none());
+
+        hypervisor.stop("api", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cors_preflight_and_headers() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "./missing-binary"
+
+[service.api.cors]
+allow_origins = ["https://app.example.com"]
+allow_methods = ["GET", "POST"]
+allow_headers = ["Content-Type"]
+expose_headers = ["X-Request-Id"]
+allow_credentials = true
+max_age = "10m"
+"#,
+        )
+        .unwrap();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        // Preflights are answered by the proxy, without an instance
+        let response = server
+            .method(axum::http::Method::OPTIONS, "/orders")
+            .add_header("Host", "api.example.com")
+            .add_header("Origin", "https://app.example.com")
+            .add_header("Access-Control-Request-Method", "POST")
+            .add_header("Access-Control-Request-Headers", "content-type")
+            .await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            "https://app.example.com"
+        );
+        assert_eq!(response.header("access-control-allow-methods"), "GET,POST");
+        assert_eq!(
+            response.header("access-control-allow-headers"),
+            "content-type"
+        );
+        assert_eq!(response.header("access-control-allow-credentials"), "true");
+        assert_eq!(response.header("access-control-max-age"), "600");
+
+        // Other origins get no CORS headers
+        let response = server
+            .method(axum::http::Method::OPTIONS, "/orders")
+            .add_header("Host", "api.example.com")
+            .add_header("Origin", "https://evil.example.net")
+            .add_header("Access-Control-Request-Method", "POST")
+            .await;
+        assert!(response
+            .maybe_header("access-control-allow-origin")
+            .is_none());
+
+        // Proxied responses, errors included, carry them for allowed origins
+        let response = server
+            .get("/orders")
+            .add_header("Host", "api.example.com")
+            .add_header("Origin", "https://app.example.com")
+            .await;
+        response.assert_status_service_unavailable();
+        assert_eq!(
+            response.header("access-control-allow-origin"),
+            "https://app.example.com"
+        );
+        assert_eq!(
+            response.header("access-control-expose-headers"),
+            "x-request-id"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_error_pages() {
+        let data_dir = TempDir::new().unwrap();
+        let pages = TempDir::new().unwrap();
+        std::fs::write(
+            pages.path().join("503.html"),
+            "<h1>{status} {reason}</h1><p>{service} is waking up</p>",
+        )
+        .unwrap();
+        std::fs::write(pages.path().join("404.html"), "<h1>Nothing here</h1>").unwrap();
+        let mut config = Config::from_str(&format!(
+            r#"
+[settings.error_pages]
+503 = "{pages}/503.html"
+
+[service.api]
+command = "./missing-binary"
+
+[service.files]
+command = "python3"
+args = ["-m", "http.server", "{{port}}", "--bind", "127.0.0.1", "--directory", "{{data_dir}}/{{name}}/{{id}}"]
+isolation = "process"
+
+[service.files.error_pages]
+404 = "{pages}/404.html"
+"#,
+            pages = pages.path().display()
+        ))
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+        hypervisor.spawn("files", "prod").await.unwrap();
+        let port = hypervisor.get("files", "prod").await.unwrap().port.unwrap();
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server.get("/").add_header("Host", "api.example.com").await;
+        response.assert_status_service_unavailable();
+        assert_eq!(response.header("content-type"), "text/html; charset=utf-8");
+        assert_eq!(
+            response.text(),
+            "<h1>503 Service Unavailable</h1><p>api is waking up</p>"
+        );
+
+        // An instance's own errors pass through untouched
+        let response = server
+            .get("/missing.txt")
+            .add_header("Host", "prod.files.example.com")
+            .await;
+        response.assert_status_not_found();
+        assert!(!response.text().contains("Nothing here"));
+
+        hypervisor.stop("files", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_instance_override_header() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1", "--directory", "{data_dir}/{name}/{id}"]
+isolation = "process"
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        for id in ["v1", "staging"] {
+            hypervisor.spawn("api", id).await.unwrap();
+            std::fs::write(data_dir.path().join("api").join(id).join("index.html"), id).unwrap();
+            let port = hypervisor.get("api", id).await.unwrap().port.unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+        hypervisor.set_weight("api", "staging", 0).await.unwrap();
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server.get("/").add_header("Host", "api.example.com").await;
+        assert_eq!(response.text(), "v1");
+
+        // Admin token: pinned regardless of weight
+        for _ in 0..3 {
+            let response = server
+                .get("/")
+                .add_header("Host", "api.example.com")
+                .add_header(INSTANCE_HEADER, "api:staging")
+                .add_header(TOKEN_HEADER, token.clone())
+                .await;
+            response.assert_status_ok();
+            assert_eq!(response.text(), "staging");
+        }
+
+        // Missing or wrong token, malformed value, other service
+        server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header(INSTANCE_HEADER, "api:staging")
+            .await
+            .assert_status_unauthorized();
+        server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header(INSTANCE_HEADER, "api:staging")
+            .add_header(TOKEN_HEADER, "not-the-token")
+            .await
+            .assert_status_unauthorized();
+        server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header(INSTANCE_HEADER, "staging")
+            .add_header(TOKEN_HEADER, token.clone())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+        server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header(INSTANCE_HEADER, "web:staging")
+            .add_header(TOKEN_HEADER, token.clone())
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_proxy_routes_paths_to_named_listener() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "sh"
+args = ["-c", "python3 -m http.server $PORT_GRPC --bind 127.0.0.1 --directory {data_dir}/{name}/{id}/grpc & exec python3 -m http.server $PORT --bind 127.0.0.1 --directory {data_dir}/{name}/{id}/main"]
+isolation = "process"
+
+[service.api.listeners.grpc]
+paths = ["/grpc/"]
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        let instance_dir = data_dir.path().join("api/prod");
+        std::fs::create_dir_all(instance_dir.join("main")).unwrap();
+        std::fs::create_dir_all(instance_dir.join("grpc/grpc")).unwrap();
+        std::fs::write(instance_dir.join("main/who"), "main").unwrap();
+        std::fs::write(instance_dir.join("grpc/grpc/who"), "grpc").unwrap();
+        hypervisor.spawn("api", "prod").await.unwrap();
+        for listener in hypervisor.get("api", "prod").await.unwrap().listeners {
+            let port = listener.addr.port().unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server
+            .get("/who")
+            .add_header("Host", "api.example.com")
+            .await;
+        assert_eq!(response.text(), "main");
+        let response = server
+            .get("/grpc/who")
+            .add_header("Host", "prod.api.example.com")
+            .await;
+        assert_eq!(response.text(), "grpc");
+
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_custom_domain_routes_to_instance() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "sh"
+args = ["-c", "exec python3 -m http.server $PORT --bind 127.0.0.1 --directory {data_dir}/{name}/{id}"]
+isolation = "process"
+
+[domains."app.customer.com"]
+service = "api"
+instance = "customer"
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        for id in ["prod", "customer"] {
+            let instance_dir = data_dir.path().join("api").join(id);
+            std::fs::create_dir_all(&instance_dir).unwrap();
+            std::fs::write(instance_dir.join("who"), id).unwrap();
+            hypervisor.spawn("api", id).await.unwrap();
+            let port = hypervisor.get("api", id).await.unwrap().listeners[0]
+                .addr
+                .port()
+                .unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server
+            .get("/who")
+            .add_header("Host", "App.Customer.com:443")
+            .await;
+        assert_eq!(response.text(), "customer");
+        let response = server
+            .get("/who")
+            .add_header("Host", "prod.api.example.com")
+            .await;
+        assert_eq!(response.text(), "prod");
+
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_requests_retried_on_another_instance() {
+        // "broken" accepts connections and closes them without answering
+        let script_dir = TempDir::new().unwrap();
+        let script = script_dir.path().join("app.py");
+        std::fs::write(
+            &script,
+            r#"
+import os, socket
+s = socket.socket()
+s.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+s.bind(("127.0.0.1", int(os.environ["PORT"])))
+s.listen(16)
+while True:
+    c, _ = s.accept()
+    if os.environ["MODE"] == "broken" or not c.recv(65536):
+        c.close()
+        continue
+    c.sendall(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+    c.close()
+"#,
+        )
+        .unwrap();
+        let mut config = Config::from_str(&format!(
+            r#"
+[service.api]
+command = "python3"
+args = ["{}"]
+isolation = "process"
+"#,
+            script.display()
+        ))
+        .unwrap();
+        config.settings.data_dir = script_dir.path().join("data");
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        for (id, weight) in [("broken", 100), ("good", 1)] {
+            let opts = tenement::SpawnOptions {
+                env: [("MODE".to_string(), id.to_string())].into(),
+                weight: Some(weight),
+                ..Default::default()
+            };
+            hypervisor.spawn_with_opts("api", id, opts).await.unwrap();
+            let port = hypervisor.get("api", id).await.unwrap().port.unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        let server = TestServer::new(create_router(state.clone())).unwrap();
+        for _ in 0..5 {
+            let response = server.get("/").add_header("Host", "api.example.com").await;
+            response.assert_status_ok();
+            assert_eq!(response.text(), "ok");
+        }
+
+        // Not idempotent: the failure is returned, not retried
+        let mut bad_gateway = false;
+        for _ in 0..10 {
+            let response = server.post("/").add_header("Host", "api.example.com").await;
+            bad_gateway |= response.status_code() == StatusCode::BAD_GATEWAY;
+        }
+        assert!(bad_gateway);
+
+        // No retry budget: the failure is returned
+        let mut config = (*hypervisor.config()).clone();
+        config.service.get_mut("api").unwrap().request_retries = 0;
+        hypervisor.reload(config).await.unwrap();
+        let mut bad_gateway = false;
+        for _ in 0..10 {
+            let response = server.get("/").add_header("Host", "api.example.com").await;
+            bad_gateway |= response.status_code() == StatusCode::BAD_GATEWAY;
+        }
+        assert!(bad_gateway);
+
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_sticky_sessions() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1", "--directory", "{data_dir}/{name}/{id}"]
+isolation = "process"
+sticky = true
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, _token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        for id in ["v1", "v2"] {
+            hypervisor.spawn("api", id).await.unwrap();
+            std::fs::write(data_dir.path().join("api").join(id).join("index.html"), id).unwrap();
+            let port = hypervisor.get("api", id).await.unwrap().port.unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let response = server.get("/").add_header("Host", "api.example.com").await;
+        response.assert_status_ok();
+        let first = response.text();
+        let cookie = response.header("set-cookie").to_str().unwrap().to_string();
+        assert!(cookie.starts_with(&format!("{}={};", STICKY_COOKIE, first)));
+
+        // The cookie keeps the client on its instance, without re-setting it
+        for _ in 0..10 {
+            let response = server
+                .get("/")
+                .add_header("Host", "api.example.com")
+                .add_header("Cookie", format!("theme=dark; {}={}", STICKY_COOKIE, first))
+                .await;
+            assert_eq!(response.text(), first);
+            assert!(response.maybe_header("set-cookie").is_none());
+        }
+
+        // Its instance is gone: routed elsewhere and given a new cookie
+        hypervisor.stop("api", &first).await.unwrap();
+        let other = if first == "v1" { "v2" } else { "v1" };
+        let response = server
+            .get("/")
+            .add_header("Host", "api.example.com")
+            .add_header("Cookie", format!("{}={}", STICKY_COOKIE, first))
             .await;
+        response.assert_status_ok();
+        assert_eq!(response.text(), other);
+        assert!(response
+            .header("set-cookie")
+            .to_str()
+            .unwrap()
+            .starts_with(&format!("{}={};", STICKY_COOKIE, other)));
+
+        assert!(sticky_cookie("has;semicolon").is_none());
+        hypervisor.stop_all().await;
+    }
 
-        response.assert_status_not_found();
-        // Error message is sanitized (no internal details for unauthenticated users)
-        response.assert_text_contains("Not found");
+    #[tokio::test]
+    async fn test_experiment_assignment_routes_by_group() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1", "--directory", "{data_dir}/{name}/{id}"]
+isolation = "process"
+
+[experiment.checkout]
+service = "api"
+client_id = "header:x-user-id"
+
+[experiment.checkout.groups.control]
+instances = ["v1"]
+percent = 50
+
+[experiment.checkout.groups.treatment]
+instances = ["v2"]
+percent = 50
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+
+        for id in ["v1", "v2"] {
+            hypervisor.spawn("api", id).await.unwrap();
+            std::fs::write(data_dir.path().join("api").join(id).join("index.html"), id).unwrap();
+            let port = hypervisor.get("api", id).await.unwrap().port.unwrap();
+            for _ in 0..100 {
+                if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        }
+
+        let server = TestServer::new(create_router(state)).unwrap();
+        let mut groups_seen = std::collections::HashSet::new();
+        for user in 0..20 {
+            let user = format!("user-{}", user);
+            let mut first: Option<(String, String)> = None;
+            // Same client, same group and backend every time
+            for _ in 0..3 {
+                let response = server
+                    .get("/")
+                    .add_header("Host", "api.example.com")
+                    .add_header("X-User-Id", user.clone())
+                    .await;
+                response.assert_status_ok();
+                let group = response
+                    .header(tenement::EXPERIMENT_HEADER)
+                    .to_str()
+                    .unwrap()
+                    .to_string();
+                let body = response.text();
+                let expected = if group == "checkout=control" {
+                    "v1"
+                } else {
+                    "v2"
+                };
+                assert_eq!(body, expected, "{} routed outside its group", user);
+                match &first {
+                    Some(prev) => assert_eq!(prev, &(group.clone(), body.clone())),
+                    None => first = Some((group.clone(), body.clone())),
+                }
+                groups_seen.insert(group);
+            }
+        }
+        assert_eq!(groups_seen.len(), 2);
+
+        // No client identifier: normal weighted routing, no assignment
+        let response = server.get("/").add_header("Host", "api.example.com").await;
+        response.assert_status_ok();
+        assert!(response.maybe_header(tenement::EXPERIMENT_HEADER).is_none());
+
+        // The access log records each request's group
+        let mut entries: Vec<tenement::AccessLogEntry> = Vec::new();
+        for _ in 0..50 {
+            entries = server
+                .get("/api/access-logs?limit=1000")
+                .add_header("Authorization", format!("Bearer {}", token))
+                .await
+                .json();
+            if entries.len() == 61 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(entries.len(), 61);
+        assert!(entries[0].experiment.is_none());
+        assert!(entries[0].experiment_group.is_none());
+        for entry in &entries[1..] {
+            assert_eq!(entry.experiment.as_deref(), Some("checkout"));
+            let expected = if entry.instance_id.as_deref() == Some("v1") {
+                "control"
+            } else {
+                "treatment"
+            };
+            assert_eq!(entry.experiment_group.as_deref(), Some(expected));
+        }
+
+        hypervisor.stop_all().await;
     }
 
     #[tokio::test]
@@ -1601,6 +4280,86 @@ mod tests {
         assert!(text.contains("# HELP tenement_instances_up"));
         assert!(text.contains("# TYPE tenement_instances_up gauge"));
         assert!(text.contains("tenement_instances_up 0"));
+        assert!(text.contains("# TYPE tenement_host_load_average gauge"));
+        assert!(text.contains("tenement_host_disk_available_bytes{mount="));
+    }
+
+    #[tokio::test]
+    async fn test_host_endpoint() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        server.get("/api/host").await.assert_status_unauthorized();
+        let response = server
+            .get("/api/host")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let host: tenement::HostStats = response.json();
+        assert!(!host.disks.is_empty());
+        assert!(data_dir
+            .path()
+            .canonicalize()
+            .unwrap()
+            .starts_with(&host.disks[0].mount_point));
+    }
+
+    #[tokio::test]
+    async fn test_capacity_endpoint() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::default();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (mut state, token, dir) = create_test_state_with_config(config.clone()).await;
+        let auth = format!("Bearer {}", token);
+
+        // No usage store attached: nothing to forecast from
+        let server = TestServer::new(create_router(state.clone())).unwrap();
+        server
+            .get("/api/capacity")
+            .add_header("Authorization", auth.clone())
+            .await
+            .assert_status(StatusCode::SERVICE_UNAVAILABLE);
+
+        let usage = Arc::new(tenement::UsageStore::new(
+            init_db(&dir.path().join("test.db")).await.unwrap(),
+        ));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        for (day, disk) in [(2, 1000), (1, 2000), (0, 3000)] {
+            usage
+                .record(&[tenement::UsageSample {
+                    timestamp: now - day * 86400,
+                    process: "api".to_string(),
+                    instance_id: "alice".to_string(),
+                    memory_bytes: 0,
+                    disk_bytes: disk,
+                }])
+                .await
+                .unwrap();
+        }
+        state.hypervisor = Hypervisor::new(config).with_usage(usage);
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        server
+            .get("/api/capacity")
+            .await
+            .assert_status_unauthorized();
+        let response = server
+            .get("/api/capacity?days=30&top=3")
+            .add_header("Authorization", auth)
+            .await;
+        response.assert_status_ok();
+        let report: tenement::CapacityReport = response.json();
+        assert_eq!(report.samples, 3);
+        assert_eq!(report.disk.used_bytes, 3000);
+        assert!((report.disk.growth_bytes_per_day - 1000.0).abs() < 1.0);
+        assert_eq!(report.migrate.len(), 1);
+        assert_eq!(report.migrate[0].id, "alice");
     }
 
     #[tokio::test]
@@ -1631,6 +4390,222 @@ mod tests {
         response.assert_status_unauthorized();
     }
 
+    #[tokio::test]
+    async fn test_api_requires_client_certificate() {
+        let (mut state, token, _dir) = create_test_state().await;
+        state.tls_status.client_auth = true;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        // A valid token isn't enough without a client certificate
+        let response = server
+            .get("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        // Public endpoints and tenant traffic don't need one
+        server.get("/health").await.assert_status_ok();
+    }
+
+    #[tokio::test]
+    async fn test_api_listener_ignores_client_ca() {
+        let (mut state, token, _dir) = create_test_state().await;
+        state.tls_status.client_auth = true;
+        let app = create_router(api_listener_state(state));
+        let server = TestServer::new(app).unwrap();
+
+        // Plain HTTP never has a client certificate; the token is enough
+        let response = server
+            .get("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        server
+            .get("/api/instances")
+            .await
+            .assert_status_unauthorized();
+    }
+
+    #[tokio::test]
+    async fn test_list_instances_label_filter() {
+        let (state, token, _dir) = create_test_state().await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .get("/api/instances")
+            .add_query_param("label", "team=payments")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let json: serde_json::Value = response.json();
+        assert_eq!(json, serde_json::json!([]));
+
+        let response = server
+            .get("/api/instances")
+            .add_query_param("label", "team")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_service_versions_endpoints() {
+        let config = Config::from_str("[service.api]\ncommand = \"./api\"\n").unwrap();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .get("/api/services/api/versions")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let versions: tenement::ServiceVersions = response.json();
+        assert_eq!(versions, tenement::ServiceVersions::default());
+
+        server
+            .get("/api/services/nope/versions")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        server
+            .post("/api/services/nope/deploy")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({ "version": "v2" }))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_token_cannot_deploy_service() {
+        let (state, _admin, tenant, _dir) = create_test_state_with_tenant().await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        server
+            .post("/api/services/api/deploy")
+            .add_header("Authorization", format!("Bearer {}", tenant))
+            .json(&serde_json::json!({ "version": "v2" }))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_services_endpoints() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1"]
+isolation = "process"
+env = { API_KEY = "hunter2" }
+
+[service.worker]
+command = "./worker"
+
+[instances]
+api = ["prod", "staging"]
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+        let server = TestServer::new(create_router(state)).unwrap();
+        hypervisor.spawn("api", "prod").await.unwrap();
+
+        let response = server
+            .get("/api/services")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let services: Vec<crate::api_routes::ServiceInfo> = response.json();
+        assert_eq!(services.len(), 2);
+        assert_eq!(services[0].name, "api");
+        assert_eq!(services[0].desired, 2);
+        assert_eq!(services[0].running, vec!["prod"]);
+        assert_eq!(services[1].name, "worker");
+        assert_eq!(services[1].desired, 0);
+        assert!(services[1].running.is_empty());
+
+        let response = server
+            .get("/api/services/api")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        assert!(!response.text().contains("hunter2"));
+        let api: crate::api_routes::ServiceInfo = response.json();
+        assert_eq!(api.autospawn, vec!["prod", "staging"]);
+        assert_eq!(api.config.command, "python3");
+        assert_eq!(api.config.env["API_KEY"], tenement::REDACTED);
+
+        server
+            .get("/api/services/nope")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        hypervisor.stop("api", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tenant_token_cannot_list_services() {
+        let (state, _admin, tenant, _dir) = create_test_state_with_tenant().await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        for path in ["/api/services", "/api/services/api"] {
+            server
+                .get(path)
+                .add_header("Authorization", format!("Bearer {}", tenant))
+                .await
+                .assert_status(StatusCode::FORBIDDEN);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_version_endpoint() {
+        let (state, token, _dir) = create_test_state().await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        server
+            .get("/api/version")
+            .await
+            .assert_status_unauthorized();
+
+        let response = server
+            .get("/api/version")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let body: crate::api_routes::VersionResponse = response.json();
+        assert_eq!(body.version, env!("CARGO_PKG_VERSION"));
+        assert!(!body.git_sha.is_empty());
+        assert_eq!(body.build_date.len(), "2026-01-01".len());
+        assert_eq!(body.runtimes.get("process"), Some(&true));
+        assert!(body.runtimes.contains_key("firecracker"));
+    }
+
+    #[tokio::test]
+    async fn test_sbom_endpoint() {
+        let (state, token, _dir) = create_test_state().await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .get("/api/sbom")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status_ok();
+        let body: crate::api_routes::SbomResponse = response.json();
+        let tokio = body.packages.iter().find(|p| p.name == "tokio").unwrap();
+        assert!(tokio.source.starts_with("registry+"));
+        assert!(body.packages.iter().any(|p| p.name == "tenement"));
+    }
+
     // ===================
     // MUTATION API TESTS (Phase My Way)
     // ===================
@@ -1665,6 +4640,133 @@ mod tests {
         assert!(json["error"].as_str().unwrap().contains("Unknown process"));
     }
 
+    #[tokio::test]
+    async fn test_spawn_rejects_bad_env_name() {
+        let (state, token, _dir) = create_test_state().await;
+        let app = create_router(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .post("/api/instances/spawn")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({"process": "api", "id": "prod", "env": {"": "x"}}))
+            .await;
+
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let json: serde_json::Value = response.json();
+        assert!(json["error"]
+            .as_str()
+            .unwrap()
+            .contains("Invalid environment variable name"));
+    }
+
+    #[tokio::test]
+    async fn test_post_instances_conflicts_on_duplicate() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1"]
+isolation = "process"
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        server
+            .post("/api/instances")
+            .json(&serde_json::json!({"process": "api", "id": "prod"}))
+            .await
+            .assert_status_unauthorized();
+
+        let response = server
+            .post("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({"process": "api", "id": "prod", "env": {"MODE": "x"}}))
+            .await;
+        response.assert_status(StatusCode::CREATED);
+        let spawned: crate::api_routes::SpawnResponse = response.json();
+        assert_eq!(spawned.instance, "api:prod");
+        let port = hypervisor.get("api", "prod").await.unwrap().port;
+        assert!(port.is_some());
+        assert_eq!(spawned.port, port);
+
+        let response = server
+            .post("/api/instances")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({"process": "api", "id": "prod"}))
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        let json: serde_json::Value = response.json();
+        assert!(json["error"].as_str().unwrap().contains("already running"));
+
+        hypervisor.stop("api", "prod").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_instance_lifecycle_over_http() {
+        let data_dir = TempDir::new().unwrap();
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "python3"
+args = ["-m", "http.server", "{port}", "--bind", "127.0.0.1"]
+isolation = "process"
+"#,
+        )
+        .unwrap();
+        config.settings.data_dir = data_dir.path().to_path_buf();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let hypervisor = state.hypervisor.clone();
+        let server = TestServer::new(create_router(state)).unwrap();
+        let auth = format!("Bearer {}", token);
+
+        server
+            .post("/api/instances")
+            .add_header("Authorization", &auth)
+            .json(&serde_json::json!({"process": "api", "id": "prod"}))
+            .await
+            .assert_status(StatusCode::CREATED);
+
+        // Both go through the auth middleware
+        server
+            .post("/api/instances/api:prod/restart")
+            .await
+            .assert_status_unauthorized();
+        server
+            .delete("/api/instances/api:prod")
+            .await
+            .assert_status_unauthorized();
+
+        let response = server
+            .post("/api/instances/api:prod/restart")
+            .add_header("Authorization", &auth)
+            .await;
+        response.assert_status_ok();
+        let restarted: crate::api_routes::SpawnResponse = response.json();
+        assert_eq!(restarted.instance, "api:prod");
+        assert_eq!(
+            restarted.port,
+            hypervisor.get("api", "prod").await.unwrap().port
+        );
+
+        server
+            .delete("/api/instances/api:prod")
+            .add_header("Authorization", &auth)
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        assert!(!hypervisor.is_running("api", "prod").await);
+        server
+            .delete("/api/instances/api:prod")
+            .add_header("Authorization", &auth)
+            .await
+            .assert_status_not_found();
+    }
+
     #[tokio::test]
     async fn test_stop_not_found() {
         let (state, token, _dir) = create_test_state().await;
@@ -1715,6 +4817,44 @@ mod tests {
         response.assert_status(StatusCode::NOT_FOUND);
     }
 
+    #[tokio::test]
+    async fn test_limits_endpoint() {
+        let (state, admin, tenant, _dir) = create_test_state_with_tenant().await;
+        let server = TestServer::new(create_router(state)).unwrap();
+        let body = serde_json::json!({"memory_limit_mb": 512});
+
+        server
+            .put("/api/instances/api:alice/limits")
+            .add_header("Authorization", format!("Bearer {}", tenant))
+            .json(&body)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        server
+            .put("/api/instances/api:alice/limits")
+            .add_header("Authorization", format!("Bearer {}", admin))
+            .json(&body)
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_config_reload_requires_admin() {
+        let (state, _admin, tenant, _dir) = create_test_state_with_tenant().await;
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        server
+            .post("/api/config/reload")
+            .add_header("Authorization", format!("Bearer {}", tenant))
+            .json(&serde_json::json!({}))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+        server
+            .get("/api/config/drift")
+            .add_header("Authorization", format!("Bearer {}", tenant))
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn test_health_check_unknown_instance() {
         let (state, token, _dir) = create_test_state().await;
@@ -1785,6 +4925,56 @@ mod tests {
         response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[tokio::test]
+    async fn test_maintenance_freeze_requires_force() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "./nonexistent-api"
+
+[service.api.maintenance]
+mode = "deny"
+windows = ["daily 00:00-24:00"]
+"#,
+        )
+        .unwrap();
+        let (state, token, _dir) = create_test_state_with_config(config).await;
+        let deploy_log = state.deploy_log.clone();
+        let server = TestServer::new(create_router(state)).unwrap();
+
+        let response = server
+            .post("/api/instances/api:prod/restart")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(response.text().contains("--force"));
+
+        let response = server
+            .post("/api/services/api/deploy")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .json(&serde_json::json!({ "version": "v2" }))
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(deploy_log.recent(10).await.unwrap().is_empty());
+
+        // Forced through: the restart itself fails (no such command), but the
+        // override is on record
+        let response = server
+            .post("/api/instances/api:prod/restart?force=true")
+            .add_header("Authorization", format!("Bearer {}", token))
+            .await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+        let entries = deploy_log.recent(10).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, "maintenance_override");
+        assert_eq!(entries[0].instance_id, "prod");
+        assert!(entries[0]
+            .details
+            .as_deref()
+            .unwrap()
+            .starts_with("restart: "));
+    }
+
     // ===================
     // TENANT TOKEN TESTS
     // ===================
@@ -1812,15 +5002,25 @@ mod tests {
         let hypervisor = Hypervisor::new(config);
         let client = Client::builder(TokioExecutor::new()).build_http();
         let unix_client = Client::builder(TokioExecutor::new()).build(UnixConnector);
+        let h2_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build_http();
+        let unix_h2_client = Client::builder(TokioExecutor::new())
+            .http2_only(true)
+            .build(UnixConnector);
         let state = AppState {
             hypervisor,
             domain: "example.com".to_string(),
             client,
             unix_client,
+            h2_client,
+            unix_h2_client,
             config_store,
             deploy_log,
             tenant_tokens,
             tls_status: TlsStatus::default(),
+            dashboard: true,
+            serves: Serves::All,
             auth_failures: Arc::new(tokio::sync::RwLock::new((0, None))),
         };
         (state, admin_token, tenant_token, dir)
@@ -1879,6 +5079,29 @@ mod tests {
         response.assert_status(StatusCode::FORBIDDEN);
     }
 
+    #[tokio::test]
+    async fn test_adopt_config_endpoint() {
+        let (state, admin, tenant, _dir) = create_test_state_with_tenant().await;
+        let server = TestServer::new(create_router(state)).unwrap();
+        let body = serde_json::json!({"from": "api", "to": "web"});
+
+        server
+            .post("/api/adopt-config")
+            .add_header("Authorization", format!("Bearer {}", tenant))
+            .json(&body)
+            .await
+            .assert_status(StatusCode::FORBIDDEN);
+
+        // Nothing is orphaned (and "web" isn't configured)
+        let response = server
+            .post("/api/adopt-config")
+            .add_header("Authorization", format!("Bearer {}", admin))
+            .json(&body)
+            .await;
+        response.assert_status(StatusCode::CONFLICT);
+        assert!(response.text().contains("Unknown process: web"));
+    }
+
     #[tokio::test]
     async fn test_tenant_token_scoped_to_own_instances() {
         let (state, _admin, tenant, _dir) = create_test_state_with_tenant().await;
@@ -0,0 +1,135 @@
+//! systemd socket activation
+//!
+//! With a `tenement.socket` unit, systemd owns the listening sockets and
+//! passes them to the daemon as fds 3.. (`LISTEN_PID`, `LISTEN_FDS`). The
+//! sockets outlive any one daemon, so while `systemctl restart tenement` runs
+//! new connections wait in the kernel's accept queue instead of being
+//! refused, and the next daemon accepts them.
+//!
+//! Inherited sockets are matched to listeners by port. A port systemd didn't
+//! pass is bound as usual.
+
+use anyhow::{Context, Result};
+use std::net::{SocketAddr, TcpListener};
+use std::ops::Range;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Mutex, OnceLock};
+
+/// First fd systemd passes (`SD_LISTEN_FDS_START`)
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Listeners passed in by systemd and not claimed yet
+struct Inherited(Mutex<Vec<TcpListener>>);
+
+impl Inherited {
+    fn new(listeners: Vec<TcpListener>) -> Self {
+        Self(Mutex::new(listeners))
+    }
+
+    /// Adopt the sockets systemd passed to this process, once
+    fn from_env() -> Self {
+        let fds = listen_fds(
+            std::env::var("LISTEN_PID").ok().as_deref(),
+            std::env::var("LISTEN_FDS").ok().as_deref(),
+            std::process::id(),
+        );
+        let listeners = fds
+            .filter_map(|fd| {
+                // SAFETY: LISTEN_PID names this process, so systemd passed
+                // these fds to us and nothing else in tenement owns them
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                // systemd clears close-on-exec; instances mustn't inherit them
+                unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+                let listener = TcpListener::from(fd);
+                match listener.local_addr() {
+                    Ok(addr) => {
+                        tracing::info!("Using socket {} from systemd", addr);
+                        Some(listener)
+                    }
+                    Err(_) => {
+                        tracing::warn!("Ignoring fd from systemd that isn't a TCP socket");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self::new(listeners)
+    }
+
+    /// Take the listener bound to `port`, if there is one
+    fn take(&self, port: u16) -> Option<TcpListener> {
+        let mut listeners = self.0.lock().unwrap();
+        let index = listeners
+            .iter()
+            .position(|l| l.local_addr().is_ok_and(|addr| addr.port() == port))?;
+        Some(listeners.swap_remove(index))
+    }
+}
+
+/// The fds systemd passed, if `LISTEN_PID` is this process
+fn listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> Range<RawFd> {
+    let count = match (listen_pid, listen_fds) {
+        (Some(listen_pid), Some(count)) if listen_pid.parse() == Ok(pid) => {
+            count.parse::<RawFd>().unwrap_or(0).max(0)
+        }
+        _ => 0,
+    };
+    LISTEN_FDS_START..LISTEN_FDS_START + count
+}
+
+fn inherited() -> &'static Inherited {
+    static INHERITED: OnceLock<Inherited> = OnceLock::new();
+    INHERITED.get_or_init(Inherited::from_env)
+}
+
+/// The socket systemd passed for `port`, ready for tokio
+pub fn take(port: u16) -> Result<Option<TcpListener>> {
+    let Some(listener) = inherited().take(port) else {
+        return Ok(None);
+    };
+    listener
+        .set_nonblocking(true)
+        .context("Failed to set socket from systemd non-blocking")?;
+    Ok(Some(listener))
+}
+
+/// Listen on `addr`, using the socket systemd passed for its port if any
+pub async fn bind(addr: SocketAddr) -> Result<tokio::net::TcpListener> {
+    match take(addr.port())? {
+        Some(listener) => Ok(tokio::net::TcpListener::from_std(listener)?),
+        None => Ok(tokio::net::TcpListener::bind(addr).await?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        assert_eq!(listen_fds(Some("42"), Some("2"), 42), 3..5);
+        // Meant for another process (e.g. our parent)
+        assert!(listen_fds(Some("41"), Some("2"), 42).is_empty());
+        assert!(listen_fds(None, Some("2"), 42).is_empty());
+        assert!(listen_fds(Some("42"), None, 42).is_empty());
+        assert!(listen_fds(Some("42"), Some("junk"), 42).is_empty());
+        assert!(listen_fds(Some("42"), Some("-1"), 42).is_empty());
+    }
+
+    #[test]
+    fn test_take_by_port() {
+        let a = TcpListener::bind("127.0.0.1:0").unwrap();
+        let b = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (port_a, port_b) = (
+            a.local_addr().unwrap().port(),
+            b.local_addr().unwrap().port(),
+        );
+        let inherited = Inherited::new(vec![a, b]);
+
+        let taken = inherited.take(port_b).unwrap();
+        assert_eq!(taken.local_addr().unwrap().port(), port_b);
+        // Each socket is handed out once
+        assert!(inherited.take(port_b).is_none());
+        assert!(inherited.take(port_a).is_some());
+    }
+}
@@ -0,0 +1,753 @@
+//! Certificates for the built-in HTTPS listener
+//!
+//! Each certificate (the main domain with its alt names, plus one per extra
+//! domain) has its own ACME order and resolver, so one customer's domain
+//! failing validation doesn't hold up the others. [`SniResolver`] picks the
+//! certificate for the name a client asks for in SNI, falling back to the
+//! main one.
+//!
+//! Certificates are validated with TLS-ALPN-01 on the HTTPS port, except
+//! those with a wildcard name, which Let's Encrypt only issues through
+//! DNS-01: [`Dns01`] publishes `_acme-challenge` TXT records through the
+//! `[settings.tls.dns]` provider and keeps the certificate renewed.
+//!
+//! With `client_ca` set, [`Acceptor`] asks for a client certificate on
+//! handshakes for the admin host and marks requests on connections that
+//! presented a valid one with [`ClientCertificate`]. Tenant subdomains get
+//! plain TLS.
+
+use anyhow::{Context, Result};
+use base64::prelude::*;
+use futures::future::BoxFuture;
+use rcgen::{CertificateParams, DistinguishedName, KeyPair, PKCS_ECDSA_P256_SHA256};
+use rustls::pki_types::PrivateKeyDer;
+use rustls::server::WebPkiClientVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ClientConfig;
+use rustls::{RootCertStore, ServerConfig};
+use rustls_acme::acme::{
+    Account, AuthStatus, ChallengeType, Directory, Identifier, OrderStatus,
+    LETS_ENCRYPT_PRODUCTION_DIRECTORY, LETS_ENCRYPT_STAGING_DIRECTORY,
+};
+use rustls_acme::caches::DirCache;
+use rustls_acme::{AccountCache, CertCache};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::task::{Context as TaskContext, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tenement::ChallengeRecords;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Whether certificate name `pattern` (possibly `*.example.com`) covers `host`
+pub fn name_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(parent) => host
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest.eq_ignore_ascii_case(parent)),
+        None => pattern.eq_ignore_ascii_case(host),
+    }
+}
+
+/// Index of the certificate to serve for `server_name`. An exact name beats
+/// a wildcard, and the first certificate is the default.
+pub fn select(certificates: &[Vec<String>], server_name: Option<&str>) -> usize {
+    let Some(host) = server_name else {
+        return 0;
+    };
+    let find = |wildcard: bool| {
+        certificates.iter().position(|names| {
+            names
+                .iter()
+                .any(|name| name.starts_with("*.") == wildcard && name_matches(name, host))
+        })
+    };
+    find(false).or_else(|| find(true)).unwrap_or(0)
+}
+
+/// Serves each ACME-managed certificate for the names on it
+#[derive(Debug)]
+pub struct SniResolver {
+    certificates: Vec<Vec<String>>,
+    resolvers: Vec<Arc<dyn ResolvesServerCert>>,
+}
+
+impl SniResolver {
+    /// `certificates` pairs each certificate's names with its resolver; the
+    /// first is the default. Panics if empty.
+    pub fn new(certificates: Vec<(Vec<String>, Arc<dyn ResolvesServerCert>)>) -> Self {
+        assert!(!certificates.is_empty(), "SniResolver needs a certificate");
+        let (certificates, resolvers) = certificates.into_iter().unzip();
+        Self {
+            certificates,
+            resolvers,
+        }
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        // TLS-ALPN-01 challenges carry the name being validated in SNI too,
+        // so they reach the resolver holding that name's challenge key
+        let index = select(&self.certificates, client_hello.server_name());
+        self.resolvers[index].resolve(client_hello)
+    }
+}
+
+/// How long a client gets to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Request extension: the connection presented a client certificate signed
+/// by the configured `client_ca`
+#[derive(Debug, Clone, Copy)]
+pub struct ClientCertificate;
+
+/// Accepts TLS connections for axum-server, asking for a client certificate
+/// only on handshakes for the admin host
+#[derive(Clone)]
+pub struct Acceptor {
+    config: Arc<ServerConfig>,
+    /// The admin host and the config used for its handshakes
+    client_auth: Option<(String, Arc<ServerConfig>)>,
+}
+
+impl Acceptor {
+    /// Serve certificates from `resolver`. With `client_ca`, handshakes for
+    /// `admin_host` also ask for a client certificate signed by one of the
+    /// bundle's CAs. It stays optional so the dashboard still loads without
+    /// one; `/api` requests check for [`ClientCertificate`].
+    pub fn new(
+        resolver: Arc<dyn ResolvesServerCert>,
+        alpn_protocols: Vec<Vec<u8>>,
+        admin_host: &str,
+        client_ca: Option<&Path>,
+    ) -> Result<Self> {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+        config.alpn_protocols = alpn_protocols.clone();
+
+        let client_auth = match client_ca {
+            Some(path) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_ca_bundle(path)?),
+                    provider.clone(),
+                )
+                .allow_unauthenticated()
+                .build()?;
+                let mut admin = ServerConfig::builder_with_provider(provider)
+                    .with_safe_default_protocol_versions()?
+                    .with_client_cert_verifier(verifier)
+                    .with_cert_resolver(resolver);
+                admin.alpn_protocols = alpn_protocols;
+                Some((admin_host.to_string(), Arc::new(admin)))
+            }
+            None => None,
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            client_auth,
+        })
+    }
+
+    fn config_for(&self, hello: &ClientHello) -> Arc<ServerConfig> {
+        match &self.client_auth {
+            // ACME validation handshakes don't carry client certificates
+            Some((host, config))
+                if hello
+                    .server_name()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(host))
+                    && !rustls_acme::is_tls_alpn_challenge(hello) =>
+            {
+                config.clone()
+            }
+            _ => self.config.clone(),
+        }
+    }
+}
+
+impl<I, S> axum_server::accept::Accept<I, S> for Acceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = tokio_rustls::server::TlsStream<I>;
+    type Service = WithClientCertificate<S>;
+    type Future = BoxFuture<'static, std::io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let acceptor = self.clone();
+        Box::pin(async move {
+            let handshake = async {
+                let start = tokio_rustls::LazyConfigAcceptor::new(
+                    rustls::server::Acceptor::default(),
+                    stream,
+                )
+                .await?;
+                let config = acceptor.config_for(&start.client_hello());
+                start.into_stream(config).await
+            };
+            let tls = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+                .await
+                .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+            // A certificate that didn't verify fails the handshake, so any
+            // certificate here is signed by the client CA
+            let verified = tls
+                .get_ref()
+                .1
+                .peer_certificates()
+                .is_some_and(|certs| !certs.is_empty());
+            Ok((
+                tls,
+                WithClientCertificate {
+                    inner: service,
+                    verified,
+                },
+            ))
+        })
+    }
+}
+
+/// Adds [`ClientCertificate`] to requests on a verified connection
+#[derive(Clone)]
+pub struct WithClientCertificate<S> {
+    inner: S,
+    verified: bool,
+}
+
+impl<S, B> tower::Service<axum::http::Request<B>> for WithClientCertificate<S>
+where
+    S: tower::Service<axum::http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: axum::http::Request<B>) -> Self::Future {
+        if self.verified {
+            req.extensions_mut().insert(ClientCertificate);
+        }
+        self.inner.call(req)
+    }
+}
+
+/// Load the CA certificates in a PEM bundle
+fn load_ca_bundle(path: &Path) -> Result<RootCertStore> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read client CA bundle {}", path.display()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_pemfile::certs(&mut &pem[..]) {
+        roots.add(cert?)?;
+    }
+    if roots.is_empty() {
+        anyhow::bail!("No certificates in client CA bundle {}", path.display());
+    }
+    Ok(roots)
+}
+
+/// Serves the current certificate from a [`Dns01`] order
+#[derive(Debug, Default)]
+pub struct DnsCertResolver {
+    cert: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for DnsCertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.cert.read().unwrap().clone()
+    }
+}
+
+/// Polls of a pending authorization before giving up
+const AUTH_POLLS: u32 = 10;
+
+/// Longest wait between attempts after failed orders
+const MAX_RETRY: Duration = Duration::from_secs(60 * 60);
+
+/// Obtains and renews one certificate through DNS-01 challenges
+pub struct Dns01 {
+    names: Vec<String>,
+    contact: Vec<String>,
+    directory_url: String,
+    cache: DirCache<PathBuf>,
+    records: Arc<dyn ChallengeRecords>,
+    propagation_delay: Duration,
+    client_config: Arc<ClientConfig>,
+    resolver: Arc<DnsCertResolver>,
+}
+
+impl Dns01 {
+    /// An order for `names` from Let's Encrypt (`staging` or production),
+    /// cached in `cache_dir` alongside the TLS-ALPN-01 certificates
+    pub fn new(
+        names: Vec<String>,
+        email: &str,
+        cache_dir: PathBuf,
+        staging: bool,
+        dns: &tenement::ChallengeDnsConfig,
+    ) -> Self {
+        let roots = rustls::RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let client_config = ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .expect("default TLS versions are supported")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+        Self {
+            names,
+            contact: vec![format!("mailto:{}", email)],
+            directory_url: if staging {
+                LETS_ENCRYPT_STAGING_DIRECTORY
+            } else {
+                LETS_ENCRYPT_PRODUCTION_DIRECTORY
+            }
+            .to_string(),
+            cache: DirCache::new(cache_dir),
+            records: tenement::dns::challenge_records(dns),
+            propagation_delay: Duration::from_secs(dns.propagation_delay),
+            client_config: Arc::new(client_config),
+            resolver: Arc::new(DnsCertResolver::default()),
+        }
+    }
+
+    pub fn resolver(&self) -> Arc<DnsCertResolver> {
+        self.resolver.clone()
+    }
+
+    /// Deploy the cached certificate, if any, then order a new one whenever
+    /// it is two thirds through its lifetime (or missing)
+    pub fn spawn(self) {
+        tokio::spawn(async move {
+            let label = self.names.join(", ");
+            let mut renew_at = match self.load_cached().await {
+                Ok(Some(renew_at)) => {
+                    tracing::info!("ACME (DNS-01): Deployed cached certificate for {}", label);
+                    renew_at
+                }
+                Ok(None) => SystemTime::now(),
+                Err(e) => {
+                    tracing::warn!(
+                        "ACME (DNS-01): Ignoring cached certificate for {}: {:#}",
+                        label,
+                        e
+                    );
+                    SystemTime::now()
+                }
+            };
+            let mut failures: u32 = 0;
+            loop {
+                if let Ok(wait) = renew_at.duration_since(SystemTime::now()) {
+                    tokio::time::sleep(wait).await;
+                }
+                match self.renew().await {
+                    Ok(next) => {
+                        failures = 0;
+                        renew_at = next;
+                        tracing::info!("ACME (DNS-01): Deployed new certificate for {}", label);
+                    }
+                    Err(e) => {
+                        failures += 1;
+                        let retry = Duration::from_secs(60 << failures.min(6)).min(MAX_RETRY);
+                        tracing::error!(
+                            "ACME (DNS-01) error (attempt {}) for {}: {:#}. Retrying in {}s",
+                            failures,
+                            label,
+                            e,
+                            retry.as_secs()
+                        );
+                        renew_at = SystemTime::now() + retry;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Deploy the cached certificate; returns when to renew it
+    async fn load_cached(&self) -> Result<Option<SystemTime>> {
+        let Some(pem) = self
+            .cache
+            .load_cert(&self.names, &self.directory_url)
+            .await?
+        else {
+            return Ok(None);
+        };
+        let (cert, renew_at) = parse_cert(&pem)?;
+        *self.resolver.cert.write().unwrap() = Some(Arc::new(cert));
+        Ok(Some(renew_at))
+    }
+
+    /// Order, cache and deploy a certificate; returns when to renew it
+    async fn renew(&self) -> Result<SystemTime> {
+        let pem = self.order().await?;
+        let (cert, renew_at) = parse_cert(&pem)?;
+        self.cache
+            .store_cert(&self.names, &self.directory_url, &pem)
+            .await
+            .context("Failed to cache certificate")?;
+        *self.resolver.cert.write().unwrap() = Some(Arc::new(cert));
+        Ok(renew_at)
+    }
+
+    /// The ACME account, registering (or re-finding) it with the account key
+    /// cached for this contact and directory
+    async fn account(&self) -> Result<Account> {
+        let directory = Directory::discover(&self.client_config, &self.directory_url).await?;
+        let key = match self
+            .cache
+            .load_account(&self.contact, &self.directory_url)
+            .await?
+        {
+            Some(key) => key,
+            None => {
+                let key = Account::generate_key_pair();
+                self.cache
+                    .store_account(&self.contact, &self.directory_url, &key)
+                    .await?;
+                key
+            }
+        };
+        Ok(
+            Account::create_with_keypair(&self.client_config, directory, &self.contact, &key)
+                .await?,
+        )
+    }
+
+    /// Run an order to completion; returns the key and certificate chain as PEM
+    async fn order(&self) -> Result<Vec<u8>> {
+        let account = self.account().await?;
+        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256)?;
+        let mut params = CertificateParams::new(self.names.clone())?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params.serialize_request(&key_pair)?;
+
+        let cc = &self.client_config;
+        let (order_url, mut order) = account.new_order(cc, self.names.clone()).await?;
+        let mut polls = 0;
+        loop {
+            match order.status {
+                OrderStatus::Pending => {
+                    self.authorize(&account, &order.authorizations).await?;
+                    order = account.order(cc, &order_url).await?;
+                }
+                OrderStatus::Processing => {
+                    polls += 1;
+                    if polls > AUTH_POLLS {
+                        anyhow::bail!("Order still processing after {} polls", AUTH_POLLS);
+                    }
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    order = account.order(cc, &order_url).await?;
+                }
+                OrderStatus::Ready => {
+                    order = account.finalize(cc, &order.finalize, csr.der()).await?;
+                }
+                OrderStatus::Valid { certificate } => {
+                    let chain = account.certificate(cc, certificate).await?;
+                    return Ok(format!("{}\n{}", key_pair.serialize_pem(), chain).into_bytes());
+                }
+                OrderStatus::Invalid => {
+                    anyhow::bail!("Order invalid: {:?}", order.error);
+                }
+            }
+        }
+    }
+
+    /// Publish TXT records for every pending authorization, wait for them to
+    /// propagate, then have them validated. Records are removed afterwards
+    /// either way.
+    async fn authorize(&self, account: &Account, authorizations: &[String]) -> Result<()> {
+        let cc = &self.client_config;
+        let mut records: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut pending = Vec::new();
+        for url in authorizations {
+            let auth = account.auth(cc, url).await?;
+            let Identifier::Dns(domain) = auth.identifier;
+            match auth.status {
+                AuthStatus::Valid => continue,
+                AuthStatus::Pending => {}
+                status => anyhow::bail!("Authorization for {} is {:?}", domain, status),
+            }
+            let challenge = auth
+                .challenges
+                .iter()
+                .find(|c| c.typ == ChallengeType::Dns01)
+                .with_context(|| format!("No dns-01 challenge offered for {}", domain))?;
+            records
+                .entry(tenement::dns::challenge_record_name(&domain))
+                .or_default()
+                .push(dns_txt_value(account, &challenge.token));
+            pending.push((domain, url.clone(), challenge.url.clone()));
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        for (name, values) in &records {
+            self.records
+                .publish(name, values)
+                .await
+                .with_context(|| format!("Failed to publish {}", name))?;
+        }
+        tokio::time::sleep(self.propagation_delay).await;
+
+        let result = self.validate(account, &pending).await;
+        for (name, values) in &records {
+            if let Err(e) = self.records.remove(name, values).await {
+                tracing::warn!("ACME (DNS-01): Failed to remove {}: {:#}", name, e);
+            }
+        }
+        result
+    }
+
+    /// Ask for each challenge to be checked and wait for the authorizations
+    async fn validate(
+        &self,
+        account: &Account,
+        pending: &[(String, String, String)],
+    ) -> Result<()> {
+        let cc = &self.client_config;
+        for (_, _, challenge_url) in pending {
+            account.challenge(cc, challenge_url).await?;
+        }
+        for (domain, auth_url, _) in pending {
+            let mut polls = 0;
+            loop {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+                let auth = account.auth(cc, auth_url).await?;
+                match auth.status {
+                    AuthStatus::Valid => break,
+                    AuthStatus::Pending if polls < AUTH_POLLS => polls += 1,
+                    AuthStatus::Pending => {
+                        anyhow::bail!("Authorization for {} still pending", domain)
+                    }
+                    status => {
+                        let errors: Vec<_> = auth
+                            .challenges
+                            .iter()
+                            .filter_map(|c| c.error.as_ref())
+                            .collect();
+                        anyhow::bail!("Authorization for {} is {:?}: {:?}", domain, status, errors);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The TXT record value for a DNS-01 challenge: the SHA-256 of the key
+/// authorization (`token.thumbprint`), base64url-encoded
+pub fn dns_txt_value(account: &Account, token: &str) -> String {
+    use aws_lc_rs::digest::{digest, SHA256};
+    use aws_lc_rs::signature::KeyPair as _;
+
+    let (x, y) = account.key_pair.public_key().as_ref()[1..].split_at(32);
+    // RFC 7638 thumbprint: required members in lexicographic order
+    let jwk = format!(
+        r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+        BASE64_URL_SAFE_NO_PAD.encode(x),
+        BASE64_URL_SAFE_NO_PAD.encode(y)
+    );
+    let thumbprint = BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, jwk.as_bytes()));
+    let key_authorization = format!("{}.{}", token, thumbprint);
+    BASE64_URL_SAFE_NO_PAD.encode(digest(&SHA256, key_authorization.as_bytes()))
+}
+
+/// Parse a cached key and certificate chain; returns the certificate and
+/// when to renew it (two thirds of the way through its lifetime)
+fn parse_cert(pem: &[u8]) -> Result<(CertifiedKey, SystemTime)> {
+    let mut key = None;
+    let mut chain = Vec::new();
+    for item in rustls_pemfile::read_all(&mut &pem[..]) {
+        match item? {
+            rustls_pemfile::Item::Pkcs8Key(k) => key = Some(PrivateKeyDer::Pkcs8(k)),
+            rustls_pemfile::Item::X509Certificate(cert) => chain.push(cert),
+            _ => {}
+        }
+    }
+    let key = key.context("No private key in certificate PEM")?;
+    let leaf = chain.first().context("No certificate in certificate PEM")?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(leaf)
+        .map_err(|e| anyhow::anyhow!("Invalid certificate: {}", e))?;
+    let validity = parsed.validity();
+    let [not_before, not_after] = [validity.not_before, validity.not_after]
+        .map(|t| UNIX_EPOCH + Duration::from_secs(t.timestamp().max(0) as u64));
+    let lifetime = not_after.duration_since(not_before).unwrap_or_default();
+    let renew_at = not_after - lifetime / 3;
+
+    let signing_key = rustls::crypto::aws_lc_rs::sign::any_ecdsa_type(&key)
+        .map_err(|e| anyhow::anyhow!("Invalid certificate key: {}", e))?;
+    Ok((CertifiedKey::new(chain, signing_key), renew_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("example.com", "example.com"));
+        assert!(name_matches("example.com", "Example.COM"));
+        assert!(!name_matches("example.com", "www.example.com"));
+        assert!(name_matches("*.example.com", "api.example.com"));
+        assert!(!name_matches("*.example.com", "example.com"));
+        assert!(!name_matches("*.example.com", "a.api.example.com"));
+    }
+
+    #[test]
+    fn test_select() {
+        let certificates = vec![
+            names(&["example.com", "www.example.com"]),
+            names(&["*.customer.com"]),
+            names(&["api.customer.com"]),
+        ];
+        assert_eq!(select(&certificates, Some("www.example.com")), 0);
+        assert_eq!(select(&certificates, Some("shop.customer.com")), 1);
+        // An exact name wins over an earlier wildcard
+        assert_eq!(select(&certificates, Some("api.customer.com")), 2);
+        // Unknown names and clients without SNI get the main certificate
+        assert_eq!(select(&certificates, Some("other.net")), 0);
+        assert_eq!(select(&certificates, None), 0);
+    }
+
+    #[test]
+    fn test_dns_txt_value() {
+        // Expected value computed independently from the same P-256 key
+        let pkcs8 = BASE64_STANDARD
+            .decode(
+                "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQgHy49TFtqeYgfLj1MW2p5iB8uPUxbanmIHy49\
+                 TFtqeYihRANCAAS9fHO4iy6bTO2mICKy2ovhMZOltW7cJuffeELiTNC16wYFrae9qDrGorgNfjFAQPpH/xa4\
+                 O6yFztsBRFG7fOca",
+            )
+            .unwrap();
+        let account = Account {
+            key_pair: aws_lc_rs::signature::EcdsaKeyPair::from_pkcs8(
+                &aws_lc_rs::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+                &pkcs8,
+            )
+            .unwrap(),
+            directory: Directory {
+                new_nonce: String::new(),
+                new_account: String::new(),
+                new_order: String::new(),
+            },
+            kid: String::new(),
+        };
+        assert_eq!(
+            dns_txt_value(&account, "evaGxfADs6pSRb2LAv9IZf17Dt3juxGJ-PCt92wr-oA"),
+            "gRomcc84Ty8HBSgr03k9Vnt-UgpwNhHtbnMcUnMiLok"
+        );
+    }
+
+    #[test]
+    fn test_parse_cert() {
+        let key_pair = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut params = CertificateParams::new(vec!["*.example.com".to_string()]).unwrap();
+        params.not_before = rcgen::date_time_ymd(2030, 1, 1);
+        params.not_after = rcgen::date_time_ymd(2030, 3, 31);
+        let cert = params.self_signed(&key_pair).unwrap();
+        let pem = format!("{}\n{}", key_pair.serialize_pem(), cert.pem());
+
+        let (certified, renew_at) = parse_cert(pem.as_bytes()).unwrap();
+        assert_eq!(certified.cert.len(), 1);
+        // 89 days, renewed with a third left
+        let not_before = UNIX_EPOCH + Duration::from_secs(1_893_456_000);
+        assert_eq!(
+            renew_at.duration_since(not_before).unwrap(),
+            Duration::from_secs(89 * 86_400 * 2 / 3)
+        );
+
+        assert!(parse_cert(cert.pem().as_bytes()).is_err());
+    }
+
+    /// Handshake through [`Acceptor`] and report whether the request got
+    /// [`ClientCertificate`]
+    async fn handshake(acceptor: &Acceptor, client: ClientConfig, host: &str) -> bool {
+        use axum_server::accept::Accept;
+        use tower::ServiceExt;
+
+        let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+        let service = tower::service_fn(|req: axum::http::Request<()>| async move {
+            Ok::<_, std::convert::Infallible>(req.extensions().get::<ClientCertificate>().is_some())
+        });
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client));
+        let server_name = rustls::pki_types::ServerName::try_from(host.to_string()).unwrap();
+        let (accepted, connected) = tokio::join!(
+            acceptor.accept(server_io, service),
+            connector.connect(server_name, client_io)
+        );
+        connected.unwrap();
+        let (_stream, service) = accepted.unwrap();
+        service.oneshot(axum::http::Request::new(())).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_on_admin_host() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let ca_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let ca_path = dir.path().join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+
+        let client_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let client_cert = CertificateParams::new(vec!["ops".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let server_key = KeyPair::generate_for(&PKCS_ECDSA_P256_SHA256).unwrap();
+        let server_cert =
+            CertificateParams::new(vec!["example.com".to_string(), "*.example.com".to_string()])
+                .unwrap()
+                .self_signed(&server_key)
+                .unwrap();
+        let pem = format!("{}\n{}", server_key.serialize_pem(), server_cert.pem());
+        let resolver = DnsCertResolver::default();
+        *resolver.cert.write().unwrap() = Some(Arc::new(parse_cert(pem.as_bytes()).unwrap().0));
+
+        let acceptor = Acceptor::new(
+            Arc::new(resolver),
+            vec![b"http/1.1".to_vec()],
+            "example.com",
+            Some(&ca_path),
+        )
+        .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(server_cert.der().clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+        let with_cert = builder
+            .clone()
+            .with_client_auth_cert(
+                vec![client_cert.der().clone()],
+                PrivateKeyDer::try_from(client_key.serialize_der()).unwrap(),
+            )
+            .unwrap();
+        let without_cert = builder.with_no_client_auth();
+
+        assert!(handshake(&acceptor, with_cert.clone(), "example.com").await);
+        // The certificate stays optional so the dashboard loads without one
+        assert!(!handshake(&acceptor, without_cert, "example.com").await);
+        // Tenant hosts never ask for one
+        assert!(!handshake(&acceptor, with_cert, "app.example.com").await);
+    }
+}
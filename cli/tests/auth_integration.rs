@@ -8,7 +8,7 @@ use hyper_util::{client::legacy::Client, rt::TokioExecutor};
 use std::sync::Arc;
 use tempfile::TempDir;
 use tenement::{generate_token, init_db, Config, ConfigStore, Hypervisor, TokenStore};
-use tenement_cli::server::{create_router, AppState, TlsStatus};
+use tenement_cli::server::{create_router, AppState, Serves, TlsStatus};
 
 /// Create test state with auth token.
 /// Returns (TestServer, token, config_store, temp_dir) - temp_dir must be kept alive during test.
@@ -28,15 +28,25 @@ async fn setup_test_server() -> (TestServer, String, Arc<ConfigStore>, TempDir)
     let hypervisor = Hypervisor::new(config);
     let client = Client::builder(TokioExecutor::new()).build_http();
     let unix_client = Client::builder(TokioExecutor::new()).build(hyperlocal::UnixConnector);
+    let h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+    let unix_h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(hyperlocal::UnixConnector);
     let state = AppState {
         hypervisor,
         domain: "example.com".to_string(),
         client,
         unix_client,
+        h2_client,
+        unix_h2_client,
         config_store: config_store.clone(),
         deploy_log: deploy_log.clone(),
         tenant_tokens: tenant_tokens.clone(),
         tls_status: TlsStatus::default(),
+        dashboard: true,
+        serves: Serves::All,
         auth_failures: std::sync::Arc::new(tokio::sync::RwLock::new((0, None))),
     };
 
@@ -777,15 +787,25 @@ async fn test_no_token_configured() {
     let hypervisor = Hypervisor::new(config);
     let client = Client::builder(TokioExecutor::new()).build_http();
     let unix_client = Client::builder(TokioExecutor::new()).build(hyperlocal::UnixConnector);
+    let h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+    let unix_h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(hyperlocal::UnixConnector);
     let state = AppState {
         hypervisor,
         domain: "example.com".to_string(),
         client,
         unix_client,
+        h2_client,
+        unix_h2_client,
         config_store,
         deploy_log,
         tenant_tokens,
         tls_status: TlsStatus::default(),
+        dashboard: true,
+        serves: Serves::All,
         auth_failures: std::sync::Arc::new(tokio::sync::RwLock::new((0, None))),
     };
 
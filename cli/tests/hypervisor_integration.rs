@@ -28,7 +28,7 @@ fn unique_id(prefix: &str) -> String {
 use tenement::config::ProcessConfig;
 use tenement::runtime::RuntimeType;
 use tenement::{init_db, Config, ConfigStore, Hypervisor, TokenStore};
-use tenement_cli::server::{create_router, AppState, TlsStatus};
+use tenement_cli::server::{create_router, AppState, Serves, TlsStatus};
 
 /// Create a simple script that touches the socket file and sleeps
 fn create_touch_socket_script(dir: &TempDir) -> std::path::PathBuf {
@@ -68,6 +68,58 @@ sleep 30
     script_path
 }
 
+/// Create a Server-Sent Events app listening on both $PORT and $SOCKET_PATH.
+/// Each response sends one event, then holds the stream open until
+/// `$SOCKET_PATH.release` exists before sending the second and ending.
+fn create_sse_script(dir: &TempDir) -> std::path::PathBuf {
+    let script_path = dir.path().join("sse_server.py");
+    let script = r#"#!/usr/bin/env python3
+import os, socket, threading, time
+
+path = os.environ["SOCKET_PATH"]
+
+def chunk(conn, data):
+    conn.sendall(b"%x\r\n%s\r\n" % (len(data), data))
+
+def handle(conn):
+    # Liveness probes connect and hang up without a request
+    if not conn.recv(65536):
+        conn.close()
+        return
+    conn.sendall(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\n\r\n")
+    chunk(conn, b"data: one\n\n")
+    while not os.path.exists(path + ".release"):
+        time.sleep(0.02)
+    chunk(conn, b"data: two\n\n")
+    conn.sendall(b"0\r\n\r\n")
+    conn.close()
+
+def serve(sock):
+    sock.listen(8)
+    while True:
+        conn, _ = sock.accept()
+        threading.Thread(target=handle, args=(conn,), daemon=True).start()
+
+tcp = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+tcp.setsockopt(socket.SOL_SOCKET, socket.SO_REUSEADDR, 1)
+tcp.bind(("127.0.0.1", int(os.environ["PORT"])))
+threading.Thread(target=serve, args=(tcp,), daemon=True).start()
+
+if os.path.exists(path):
+    os.remove(path)
+unix = socket.socket(socket.AF_UNIX, socket.SOCK_STREAM)
+unix.bind(path)
+serve(unix)
+"#;
+    std::fs::write(&script_path, script).expect("Failed to write SSE script");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    script_path
+}
+
 /// Create test config with a process configured
 fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> Config {
     let test_id = unique_id("test");
@@ -88,7 +140,7 @@ fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> Confi
         restart: "on-failure".to_string(),
         idle_timeout: None,
         startup_timeout: 5,
-        request_timeout: 30,
+        request_timeout: None,
         memory_limit_mb: None,
         cpu_shares: None,
         kernel: None,
@@ -98,6 +150,7 @@ fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> Confi
         vsock_port: 5000,
         storage_quota_mb: None,
         storage_persist: false,
+        ..Default::default()
     };
 
     config.service.insert(name.to_string(), process);
@@ -122,6 +175,16 @@ async fn setup_with_process(
     process_name: &str,
     script_path: &std::path::Path,
 ) -> (TestServer, String, Arc<Hypervisor>, TempDir) {
+    let config = test_config_with_process(process_name, script_path.to_str().unwrap(), vec![]);
+    let (app, token, hypervisor, db_dir) = setup_router(config).await;
+    let server = TestServer::new(app).unwrap();
+
+    (server, token, hypervisor, db_dir)
+}
+
+/// Build the server's router for `config`.
+/// Returns (Router, token, hypervisor, db_dir)
+async fn setup_router(config: Config) -> (axum::Router, String, Arc<Hypervisor>, TempDir) {
     let db_dir = TempDir::new().unwrap();
     let db_path = db_dir.path().join("test.db");
     let pool = init_db(&db_path).await.unwrap();
@@ -133,26 +196,32 @@ async fn setup_with_process(
     let token_store = TokenStore::new(&config_store);
     let token = token_store.generate_and_store().await.unwrap();
 
-    let config = test_config_with_process(process_name, script_path.to_str().unwrap(), vec![]);
     let hypervisor = Hypervisor::new(config);
     let client = Client::builder(TokioExecutor::new()).build_http();
     let unix_client = Client::builder(TokioExecutor::new()).build(hyperlocal::UnixConnector);
+    let h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http();
+    let unix_h2_client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(hyperlocal::UnixConnector);
     let state = AppState {
         hypervisor: hypervisor.clone(),
         domain: "example.com".to_string(),
         client,
         unix_client,
+        h2_client,
+        unix_h2_client,
         config_store,
         deploy_log,
         tenant_tokens,
         tls_status: TlsStatus::default(),
+        dashboard: true,
+        serves: Serves::All,
         auth_failures: std::sync::Arc::new(tokio::sync::RwLock::new((0, None))),
     };
 
-    let app = create_router(state);
-    let server = TestServer::new(app).unwrap();
-
-    (server, token, hypervisor, db_dir)
+    (create_router(state), token, hypervisor, db_dir)
 }
 
 // =============================================================================
@@ -686,7 +755,7 @@ async fn test_spawn_bad_command_fails() {
         restart: "on-failure".to_string(),
         idle_timeout: None,
         startup_timeout: 5,
-        request_timeout: 30,
+        request_timeout: None,
         memory_limit_mb: None,
         cpu_shares: None,
         kernel: None,
@@ -696,6 +765,7 @@ async fn test_spawn_bad_command_fails() {
         vsock_port: 5000,
         storage_quota_mb: None,
         storage_persist: false,
+        ..Default::default()
     };
     config.service.insert("badcmd".to_string(), process);
 
@@ -1002,6 +1072,190 @@ async fn test_weight_in_api_response() {
     hypervisor.stop("api", &inst_id).await.ok();
 }
 
+// =============================================================================
+// STREAMING TESTS
+// =============================================================================
+
+/// Test that SSE events reach the client as the backend sends them, over both
+/// TCP and a Unix socket listener, and the instance counts each open stream as
+/// an active connection until it ends
+#[tokio::test]
+async fn test_sse_response_streams_through_proxy() {
+    let script_dir = TempDir::new().unwrap();
+    let script = create_sse_script(&script_dir);
+    let mut config = test_config_with_process("api", "python3", vec![script.to_str().unwrap()]);
+    let service = config.service.get_mut("api").unwrap();
+    service.listeners.insert(
+        "events".to_string(),
+        tenement::ListenerConfig {
+            kind: tenement::ListenerKind::Socket,
+            paths: vec!["/socket/".to_string()],
+        },
+    );
+    let (app, _token, hypervisor, _db_dir) = setup_router(config).await;
+
+    let inst_id = unique_id("sse");
+    let socket = hypervisor.spawn("api", &inst_id).await.unwrap();
+    assert!(
+        wait_for_socket(&socket, 5000).await,
+        "Socket should be created"
+    );
+    let release = format!("{}.release", socket.display());
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let client = reqwest::Client::new();
+    let mut streams = Vec::new();
+    for path in ["/events", "/socket/events"] {
+        let mut response = client
+            .get(format!("http://{}{}", addr, path))
+            .header("Host", format!("{}.api.example.com", inst_id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+
+        // The first event arrives while the backend is still holding the stream
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), response.chunk())
+            .await
+            .unwrap_or_else(|_| panic!("First event on {} should arrive before the end", path))
+            .unwrap()
+            .unwrap();
+        assert_eq!(&first[..], b"data: one\n\n");
+        streams.push(response);
+    }
+    assert_eq!(hypervisor.active_connection_count("api", &inst_id).await, 2);
+
+    std::fs::write(&release, "").unwrap();
+    for mut response in streams {
+        let mut rest = Vec::new();
+        while let Some(chunk) = response.chunk().await.unwrap() {
+            rest.extend_from_slice(&chunk);
+        }
+        assert_eq!(rest, b"data: two\n\n");
+    }
+
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while hypervisor.active_connection_count("api", &inst_id).await > 0 {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "Connections should be released once the streams end"
+        );
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    hypervisor.stop("api", &inst_id).await.ok();
+    std::fs::remove_file(&release).ok();
+}
+
+/// Answer every request on `io` over HTTP/2 like a gRPC server: a
+/// `{label} {version} {path}` message, then `grpc-status: 0` in trailers
+async fn serve_grpc_echo<I>(io: I, label: &'static str)
+where
+    I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let service = hyper::service::service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+        let message = format!("{} {:?} {}", label, req.version(), req.uri().path());
+        let mut trailers = hyper::HeaderMap::new();
+        trailers.insert("grpc-status", "0".parse().unwrap());
+        let frames = vec![
+            Ok::<_, std::convert::Infallible>(hyper::body::Frame::data(axum::body::Bytes::from(
+                message,
+            ))),
+            Ok(hyper::body::Frame::trailers(trailers)),
+        ];
+        let body = http_body_util::StreamBody::new(futures::stream::iter(frames));
+        async move {
+            Ok::<_, std::convert::Infallible>(
+                hyper::Response::builder()
+                    .header("content-type", "application/grpc")
+                    .body(body)
+                    .unwrap(),
+            )
+        }
+    });
+    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+        .serve_connection(hyper_util::rt::TokioIo::new(io), service)
+        .await;
+}
+
+/// Test that gRPC requests reach TCP and Unix socket backends over h2c, with
+/// their status trailers passed back to the client
+#[tokio::test]
+async fn test_grpc_proxied_over_h2c() {
+    use http_body_util::BodyExt;
+
+    let script_dir = TempDir::new().unwrap();
+    let script = create_touch_socket_script(&script_dir);
+    let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+    config.service.get_mut("api").unwrap().listeners.insert(
+        "unix".to_string(),
+        tenement::ListenerConfig {
+            kind: tenement::ListenerKind::Socket,
+            paths: vec!["/unix.Echo/".to_string()],
+        },
+    );
+    let (app, _token, hypervisor, _db_dir) = setup_router(config).await;
+
+    let inst_id = unique_id("grpc");
+    let socket = hypervisor.spawn("api", &inst_id).await.unwrap();
+    assert!(
+        wait_for_socket(&socket, 5000).await,
+        "Socket should be created"
+    );
+
+    // Stand in for the instance's gRPC server on its port and its socket
+    let port = hypervisor.get("api", &inst_id).await.unwrap().port.unwrap();
+    let tcp = tokio::net::TcpListener::bind(("127.0.0.1", port))
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Ok((io, _)) = tcp.accept().await {
+            tokio::spawn(serve_grpc_echo(io, "tcp"));
+        }
+    });
+    std::fs::remove_file(&socket).unwrap();
+    let unix = tokio::net::UnixListener::bind(&socket).unwrap();
+    tokio::spawn(async move {
+        while let Ok((io, _)) = unix.accept().await {
+            tokio::spawn(serve_grpc_echo(io, "unix"));
+        }
+    });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    // The client speaks h2c to tenement too
+    let client = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build_http::<http_body_util::Full<axum::body::Bytes>>();
+    for (path, expected) in [
+        ("/tcp.Echo/Say", "tcp HTTP/2.0 /tcp.Echo/Say"),
+        ("/unix.Echo/Say", "unix HTTP/2.0 /unix.Echo/Say"),
+    ] {
+        let request = hyper::Request::post(format!("http://{}{}", addr, path))
+            .header("host", format!("{}.api.example.com", inst_id))
+            .header("content-type", "application/grpc")
+            .header("te", "trailers")
+            .body(http_body_util::Full::new(axum::body::Bytes::from_static(
+                b"\0\0\0\0\0",
+            )))
+            .unwrap();
+        let response = client.request(request).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "application/grpc");
+        let body = response.into_body().collect().await.unwrap();
+        assert_eq!(body.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(body.to_bytes(), expected);
+    }
+
+    hypervisor.stop("api", &inst_id).await.ok();
+}
+
 // =============================================================================
 // PORT ALLOCATION TESTS
 // =============================================================================
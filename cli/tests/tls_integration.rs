@@ -37,6 +37,10 @@ mod tls_options_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: dir.path().to_path_buf(),
             staging: false,
             https_port: 443,
@@ -58,6 +62,10 @@ mod tls_options_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "test.example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: dir.path().to_path_buf(),
             staging: true,
             https_port: 443,
@@ -74,6 +82,10 @@ mod tls_options_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: dir.path().to_path_buf(),
             staging: false,
             https_port: 8443,
@@ -92,6 +104,10 @@ mod tls_options_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: cache_path.clone(),
             staging: false,
             https_port: 443,
@@ -108,6 +124,10 @@ mod tls_options_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: dir.path().to_path_buf(),
             staging: true,
             https_port: 443,
@@ -120,6 +140,34 @@ mod tls_options_tests {
         assert_eq!(cloned.domain, opts.domain);
         assert_eq!(cloned.staging, opts.staging);
     }
+
+    #[test]
+    fn test_tls_options_certificates() {
+        let opts = TlsOptions {
+            enabled: true,
+            email: "test@example.com".to_string(),
+            domain: "example.com".to_string(),
+            alt_names: vec!["www.example.com".to_string()],
+            domains: vec!["customer.com".to_string(), "shop.other.net".to_string()],
+            dns: None,
+            client_ca: None,
+            cache_dir: PathBuf::from("/tmp"),
+            staging: false,
+            https_port: 443,
+            http_port: 80,
+        };
+
+        // The main domain shares a certificate with its alt names; each
+        // extra domain gets its own
+        assert_eq!(
+            opts.certificates(),
+            vec![
+                vec!["example.com".to_string(), "www.example.com".to_string()],
+                vec!["customer.com".to_string()],
+                vec!["shop.other.net".to_string()],
+            ]
+        );
+    }
 }
 
 // ============================================================================
@@ -595,6 +643,10 @@ mod edge_case_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 443,
@@ -612,6 +664,10 @@ mod edge_case_tests {
             enabled: true,
             email: "".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 443,
@@ -629,6 +685,10 @@ mod edge_case_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 0,
@@ -646,6 +706,10 @@ mod edge_case_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "example.com".to_string(),
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 8443,
@@ -662,6 +726,10 @@ mod edge_case_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain: "例え.jp".to_string(), // Unicode domain
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 443,
@@ -682,6 +750,10 @@ mod edge_case_tests {
             enabled: true,
             email: "test@example.com".to_string(),
             domain,
+            alt_names: Vec::new(),
+            domains: Vec::new(),
+            dns: None,
+            client_ca: None,
             cache_dir: PathBuf::from("/tmp"),
             staging: false,
             https_port: 443,
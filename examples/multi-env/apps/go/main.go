@@ -79,7 +79,6 @@ func main() {
 		if err != nil {
 			log.Fatalf("Failed to listen on socket: %v", err)
 		}
-		os.Chmod(socketPath, 0777)
 		log.Printf("[go-worker] Starting on %s", socketPath)
 	} else {
 		// Default to port 8080
@@ -43,7 +43,6 @@ if (port) {
     }
     server.listen(socketPath, () => {
         console.log(`[node-web] Starting on ${socketPath}`);
-        fs.chmodSync(socketPath, 0o777);
     });
 } else {
     // Default to port 8080
@@ -55,7 +55,6 @@ if __name__ == "__main__":
         # Unix socket mode
         print(f"[python-api] Starting on {socket_path}")
         server = UnixSocketHTTPServer(socket_path, Handler)
-        os.chmod(socket_path, 0o777)
     else:
         # Default to port 8080
         addr = ("127.0.0.1", 8080)
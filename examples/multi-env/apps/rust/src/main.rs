@@ -68,12 +68,6 @@ fn main() {
         let _ = fs::remove_file(&socket_path);
         let listener = UnixListener::bind(&socket_path).expect("Failed to bind socket");
 
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(&socket_path, fs::Permissions::from_mode(0o777)).ok();
-        }
-
         println!("[rust-cache] Starting on {}", socket_path);
 
         for stream in listener.incoming() {
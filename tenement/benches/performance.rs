@@ -29,7 +29,7 @@ fn test_config_with_process(name: &str, command: &str) -> Config {
         restart: "on-failure".to_string(),
         idle_timeout: None,
         startup_timeout: 5,
-        request_timeout: 30,
+        request_timeout: None,
         memory_limit_mb: None,
         cpu_shares: None,
         kernel: None,
@@ -39,6 +39,7 @@ fn test_config_with_process(name: &str, command: &str) -> Config {
         vsock_port: 5000,
         storage_quota_mb: None,
         storage_persist: false,
+        ..Default::default()
     };
 
     config.service.insert(name.to_string(), process);
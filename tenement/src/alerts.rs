@@ -0,0 +1,441 @@
+//! Log-based alert rules
+//!
+//! Rules are evaluated as each line enters the [`LogBuffer`], not by querying
+//! it afterwards: every matching line is counted per instance, and a rule fires
+//! once its threshold is reached within the window.
+//!
+//! ```toml
+//! [alert.panics]
+//! service = "api"              # optional; all services if unset
+//! pattern = "panic|OOM"        # regex, matched against each line
+//! stream = "stderr"            # "stdout", "stderr" or "any" (default)
+//! threshold = 3                # matching lines...
+//! window = 300                 # ...within this many seconds
+//! webhook = "https://hooks.example.com/tenement"
+//! ```
+//!
+//! After firing, a rule stays quiet for that instance for `cooldown` seconds
+//! (default: `window`). Fired alerts are logged, kept in a short history and
+//! broadcast to subscribers (the server POSTs them to `webhook`).
+//!
+//! [`LogBuffer`]: crate::logs::LogBuffer
+
+use crate::config::Config;
+use crate::logs::{LogEntry, LogLevel};
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+
+/// Number of fired alerts kept for `recent()`
+const ALERT_HISTORY: usize = 100;
+
+/// Which output stream a rule watches
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStream {
+    Stdout,
+    Stderr,
+    #[default]
+    Any,
+}
+
+impl AlertStream {
+    fn matches(self, level: LogLevel) -> bool {
+        match self {
+            AlertStream::Any => true,
+            AlertStream::Stdout => level == LogLevel::Stdout,
+            AlertStream::Stderr => level == LogLevel::Stderr,
+        }
+    }
+}
+
+/// An alert rule over the log stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AlertRule {
+    /// Service to watch (default: all)
+    #[serde(default)]
+    pub service: Option<String>,
+
+    /// Regular expression matched against each log line
+    pub pattern: String,
+
+    #[serde(default)]
+    pub stream: AlertStream,
+
+    /// Matching lines within `window` needed to fire (default: 1)
+    #[serde(default = "default_threshold")]
+    pub threshold: u32,
+
+    /// Window in seconds (default: 60)
+    #[serde(default = "default_window")]
+    pub window: u64,
+
+    /// Seconds to stay quiet after firing (default: `window`)
+    #[serde(default)]
+    pub cooldown: Option<u64>,
+
+    /// URL to POST fired alerts to as JSON
+    #[serde(default)]
+    pub webhook: Option<String>,
+}
+
+fn default_threshold() -> u32 {
+    1
+}
+
+fn default_window() -> u64 {
+    60
+}
+
+impl AlertRule {
+    /// Check the rule against the config it was loaded with
+    pub fn validate(&self, name: &str, config: &Config) -> Result<()> {
+        Regex::new(&self.pattern)
+            .with_context(|| format!("Alert '{}' has an invalid pattern", name))?;
+        if let Some(service) = &self.service {
+            if !config.service.contains_key(service) {
+                anyhow::bail!("Alert '{}' references unknown service '{}'", name, service);
+            }
+        }
+        if self.threshold == 0 {
+            anyhow::bail!("Alert '{}': threshold must be at least 1", name);
+        }
+        if self.window == 0 {
+            anyhow::bail!("Alert '{}': window must be at least 1 second", name);
+        }
+        if let Some(webhook) = &self.webhook {
+            if !webhook.starts_with("http://") && !webhook.starts_with("https://") {
+                anyhow::bail!("Alert '{}': webhook must be an http(s) URL", name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A fired alert
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertEvent {
+    /// Rule name
+    pub rule: String,
+    pub process: String,
+    pub instance_id: String,
+    /// Matching lines counted in the window
+    pub count: usize,
+    pub window_secs: u64,
+    /// The line that tipped it over
+    pub message: String,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    /// Where to deliver it (from the rule)
+    #[serde(skip)]
+    pub webhook: Option<String>,
+}
+
+struct CompiledRule {
+    name: String,
+    rule: AlertRule,
+    regex: Regex,
+}
+
+#[derive(Default)]
+struct RuleState {
+    /// Timestamps (ms) of matching lines still inside the window
+    hits: VecDeque<u64>,
+    last_fired: Option<u64>,
+}
+
+/// Evaluates alert rules against log entries as they arrive
+pub struct AlertEngine {
+    rules: Vec<CompiledRule>,
+    /// Per (rule index, process, instance) counters
+    state: Mutex<HashMap<(usize, String, String), RuleState>>,
+    recent: Mutex<VecDeque<AlertEvent>>,
+    sender: broadcast::Sender<AlertEvent>,
+}
+
+impl AlertEngine {
+    /// Compile the configured rules. Rules with invalid patterns are skipped
+    /// with a warning (`Config::from_str` already rejects them).
+    pub fn new(rules: &HashMap<String, AlertRule>) -> Arc<Self> {
+        let mut names: Vec<&String> = rules.keys().collect();
+        names.sort();
+        let rules = names
+            .into_iter()
+            .filter_map(|name| {
+                let rule = &rules[name];
+                match Regex::new(&rule.pattern) {
+                    Ok(regex) => Some(CompiledRule {
+                        name: name.clone(),
+                        rule: rule.clone(),
+                        regex,
+                    }),
+                    Err(e) => {
+                        warn!("Skipping alert '{}': invalid pattern: {}", name, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        let (sender, _) = broadcast::channel(256);
+        Arc::new(Self {
+            rules,
+            state: Mutex::new(HashMap::new()),
+            recent: Mutex::new(VecDeque::new()),
+            sender,
+        })
+    }
+
+    /// Count `entry` against every matching rule, firing any that reach
+    /// their threshold. Returns the alerts fired.
+    pub fn observe(&self, entry: &LogEntry) -> Vec<AlertEvent> {
+        let mut fired = Vec::new();
+        for (index, compiled) in self.rules.iter().enumerate() {
+            let rule = &compiled.rule;
+            if rule.service.as_ref().is_some_and(|s| s != &entry.process)
+                || !rule.stream.matches(entry.level)
+                || !compiled.regex.is_match(&entry.message)
+            {
+                continue;
+            }
+
+            let now = entry.timestamp;
+            let window_ms = rule.window * 1000;
+            let cooldown_ms = rule.cooldown.unwrap_or(rule.window) * 1000;
+
+            let mut state = self.state.lock().unwrap();
+            let counter = state
+                .entry((index, entry.process.clone(), entry.instance_id.clone()))
+                .or_default();
+            while counter
+                .hits
+                .front()
+                .is_some_and(|&t| now.saturating_sub(t) >= window_ms)
+            {
+                counter.hits.pop_front();
+            }
+            counter.hits.push_back(now);
+
+            let cooling_down = counter
+                .last_fired
+                .is_some_and(|t| now.saturating_sub(t) < cooldown_ms);
+            if counter.hits.len() < rule.threshold as usize || cooling_down {
+                continue;
+            }
+
+            fired.push(AlertEvent {
+                rule: compiled.name.clone(),
+                process: entry.process.clone(),
+                instance_id: entry.instance_id.clone(),
+                count: counter.hits.len(),
+                window_secs: rule.window,
+                message: entry.message.clone(),
+                timestamp: now,
+                webhook: rule.webhook.clone(),
+            });
+            counter.hits.clear();
+            counter.last_fired = Some(now);
+        }
+
+        for event in &fired {
+            warn!(
+                "Alert '{}' fired for {}:{} ({} matches in {}s): {}",
+                event.rule,
+                event.process,
+                event.instance_id,
+                event.count,
+                event.window_secs,
+                event.message
+            );
+            self.record(event.clone());
+        }
+        fired
+    }
+
+    /// Fire an alert raised by tenement itself rather than by a log rule
+    /// (e.g. the disk filling up). It is kept in the history and broadcast
+    /// like rule alerts.
+    pub fn emit(&self, event: AlertEvent) {
+        error!("Alert '{}': {}", event.rule, event.message);
+        self.record(event);
+    }
+
+    fn record(&self, event: AlertEvent) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() >= ALERT_HISTORY {
+                recent.pop_front();
+            }
+            recent.push_back(event.clone());
+        }
+        let _ = self.sender.send(event);
+    }
+
+    /// Recently fired alerts, oldest first
+    pub fn recent(&self) -> Vec<AlertEvent> {
+        self.recent.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Subscribe to fired alerts
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, threshold: u32, window: u64) -> AlertRule {
+        AlertRule {
+            service: Some("api".to_string()),
+            pattern: pattern.to_string(),
+            stream: AlertStream::Stderr,
+            threshold,
+            window,
+            cooldown: None,
+            webhook: None,
+        }
+    }
+
+    fn engine(rule: AlertRule) -> Arc<AlertEngine> {
+        AlertEngine::new(&HashMap::from([("panics".to_string(), rule)]))
+    }
+
+    fn entry(process: &str, id: &str, level: LogLevel, message: &str, ts_secs: u64) -> LogEntry {
+        LogEntry {
+            timestamp: ts_secs * 1000,
+            level,
+            process: process.to_string(),
+            instance_id: id.to_string(),
+            message: message.to_string(),
+            original_bytes: None,
+        }
+    }
+
+    #[test]
+    fn test_fires_at_threshold_within_window() {
+        let engine = engine(rule("panic|OOM", 3, 60));
+        let line = |ts| entry("api", "prod", LogLevel::Stderr, "thread panicked", ts);
+
+        assert!(engine.observe(&line(0)).is_empty());
+        assert!(engine.observe(&line(10)).is_empty());
+        let fired = engine.observe(&line(20));
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].rule, "panics");
+        assert_eq!(fired[0].instance_id, "prod");
+        assert_eq!(fired[0].count, 3);
+        assert_eq!(engine.recent().len(), 1);
+    }
+
+    #[test]
+    fn test_old_matches_fall_out_of_window() {
+        let engine = engine(rule("panic", 2, 60));
+        let line = |ts| entry("api", "prod", LogLevel::Stderr, "panic", ts);
+
+        assert!(engine.observe(&line(0)).is_empty());
+        // 61s later the first match no longer counts
+        assert!(engine.observe(&line(61)).is_empty());
+        assert_eq!(engine.observe(&line(70)).len(), 1);
+    }
+
+    #[test]
+    fn test_filters_service_stream_and_pattern() {
+        let engine = engine(rule("panic", 1, 60));
+
+        assert!(engine
+            .observe(&entry("web", "prod", LogLevel::Stderr, "panic", 0))
+            .is_empty());
+        assert!(engine
+            .observe(&entry("api", "prod", LogLevel::Stdout, "panic", 0))
+            .is_empty());
+        assert!(engine
+            .observe(&entry("api", "prod", LogLevel::Stderr, "all good", 0))
+            .is_empty());
+        assert_eq!(
+            engine
+                .observe(&entry("api", "prod", LogLevel::Stderr, "panic", 0))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_counts_per_instance() {
+        let engine = engine(rule("panic", 2, 60));
+
+        assert!(engine
+            .observe(&entry("api", "alice", LogLevel::Stderr, "panic", 0))
+            .is_empty());
+        assert!(engine
+            .observe(&entry("api", "bob", LogLevel::Stderr, "panic", 1))
+            .is_empty());
+        assert_eq!(
+            engine
+                .observe(&entry("api", "alice", LogLevel::Stderr, "panic", 2))
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_cooldown_suppresses_refiring() {
+        let engine = engine(AlertRule {
+            cooldown: Some(120),
+            ..rule("panic", 1, 60)
+        });
+        let line = |ts| entry("api", "prod", LogLevel::Stderr, "panic", ts);
+
+        assert_eq!(engine.observe(&line(0)).len(), 1);
+        assert!(engine.observe(&line(30)).is_empty());
+        assert!(engine.observe(&line(119)).is_empty());
+        assert_eq!(engine.observe(&line(120)).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fired_alerts_are_broadcast() {
+        let engine = engine(AlertRule {
+            webhook: Some("https://hooks.example.com/x".to_string()),
+            ..rule("OOM", 1, 60)
+        });
+        let mut rx = engine.subscribe();
+        engine.observe(&entry("api", "prod", LogLevel::Stderr, "OOM killed", 0));
+
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.message, "OOM killed");
+        assert_eq!(
+            event.webhook.as_deref(),
+            Some("https://hooks.example.com/x")
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let config = Config::from_str(
+            r#"
+[service.api]
+command = "./api"
+"#,
+        )
+        .unwrap();
+        assert!(rule("panic", 1, 60).validate("a", &config).is_ok());
+        assert!(rule("(unclosed", 1, 60).validate("a", &config).is_err());
+        assert!(rule("panic", 0, 60).validate("a", &config).is_err());
+        assert!(AlertRule {
+            service: Some("nope".to_string()),
+            ..rule("panic", 1, 60)
+        }
+        .validate("a", &config)
+        .is_err());
+        assert!(AlertRule {
+            webhook: Some("ftp://x".to_string()),
+            ..rule("panic", 1, 60)
+        }
+        .validate("a", &config)
+        .is_err());
+    }
+}
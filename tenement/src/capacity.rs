@@ -0,0 +1,443 @@
+//! Usage history and capacity forecasts
+//!
+//! While the server runs, [`UsageRecorder`] samples every instance's memory
+//! (resident set of its process group) and data dir size every
+//! [`SAMPLE_INTERVAL`] into the `usage_samples` table, keeping
+//! [`RETENTION`] of history. [`CapacityReport`] fits a straight line through
+//! that history to project when the host runs out of memory or data dir
+//! disk at the current growth rate, and lists the tenants to move elsewhere
+//! first: the fastest-growing users of whichever resource runs out soonest.
+//! `ten capacity --json` returns the same report for migration tooling.
+
+use crate::host::HostStats;
+use crate::hypervisor::Hypervisor;
+use crate::store::UsageStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// How often usage is sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long samples are kept
+pub const RETENTION: Duration = Duration::from_secs(90 * 24 * 3600);
+
+const DAY_SECS: f64 = 86400.0;
+
+/// One instance's usage at one sampling pass
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageSample {
+    /// Unix seconds; shared by every sample taken in the same pass
+    pub timestamp: i64,
+    pub process: String,
+    pub instance_id: String,
+    pub memory_bytes: u64,
+    pub disk_bytes: u64,
+}
+
+/// Samples running instances into a [`UsageStore`]
+pub struct UsageRecorder {
+    hypervisor: Arc<Hypervisor>,
+    store: Arc<UsageStore>,
+}
+
+impl UsageRecorder {
+    pub fn new(hypervisor: Arc<Hypervisor>, store: Arc<UsageStore>) -> Arc<Self> {
+        Arc::new(Self { hypervisor, store })
+    }
+
+    /// Record one sample per running instance and drop expired history.
+    /// Returns the number of samples recorded.
+    pub async fn sample(&self) -> Result<usize> {
+        let now = unix_now();
+        let pids = self.hypervisor.instance_pids().await;
+        // One walk of /proc covers every instance; it's blocking file IO, so
+        // keep it off the runtime's worker threads
+        let rss = tokio::task::spawn_blocking(process_group_rss)
+            .await?
+            .unwrap_or_default();
+        let samples: Vec<UsageSample> = self
+            .hypervisor
+            .list()
+            .await
+            .into_iter()
+            .map(|info| UsageSample {
+                timestamp: now,
+                memory_bytes: pids
+                    .get(&info.id)
+                    .and_then(|pid| rss.get(pid).copied())
+                    .unwrap_or(0),
+                disk_bytes: info.storage_used_bytes,
+                process: info.id.process,
+                instance_id: info.id.id,
+            })
+            .collect();
+        self.store.record(&samples).await?;
+        self.store.prune(now - RETENTION.as_secs() as i64).await?;
+        Ok(samples.len())
+    }
+
+    /// Sample every `interval` until the process exits
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = self.sample().await {
+                    warn!("Usage sampling failed: {:#}", e);
+                }
+            }
+        });
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Resident memory per process group, summed over its members (instances
+/// are spawned as group leaders, so this includes their children). None
+/// where /proc is unavailable.
+pub fn process_group_rss() -> Option<HashMap<u32, u64>> {
+    #[cfg(target_os = "linux")]
+    {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+        let mut groups = HashMap::new();
+        for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+            let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+                continue;
+            };
+            if let Some((group, rss_pages)) = parse_stat(&stat) {
+                *groups.entry(group).or_insert(0) += rss_pages * page_size;
+            }
+        }
+        Some(groups)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+/// Process group and resident pages from a `/proc/<pid>/stat` line
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_stat(stat: &str) -> Option<(u32, u64)> {
+    // The command name can contain spaces and parens; fields resume after
+    // the last ')', starting with field 3 (state)
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    Some((fields.get(2)?.parse().ok()?, fields.get(21)?.parse().ok()?))
+}
+
+/// Where one resource is headed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceForecast {
+    /// Used by all instances at the latest sample
+    pub used_bytes: u64,
+    /// Still free on the host (memory available, or the data dir's disk)
+    pub available_bytes: Option<u64>,
+    /// Growth of `used_bytes` over the window, per day
+    pub growth_bytes_per_day: f64,
+    /// Days until `available_bytes` is used up; None while not growing
+    pub days_left: Option<f64>,
+}
+
+impl ResourceForecast {
+    fn new(used_bytes: u64, available_bytes: Option<u64>, growth_bytes_per_day: f64) -> Self {
+        let days_left = match available_bytes {
+            Some(available) if growth_bytes_per_day > 0.0 => {
+                Some(available as f64 / growth_bytes_per_day)
+            }
+            _ => None,
+        };
+        Self {
+            used_bytes,
+            available_bytes,
+            growth_bytes_per_day,
+            days_left,
+        }
+    }
+}
+
+/// Which resource a report ranks tenants by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Memory,
+    Disk,
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Memory => write!(f, "memory"),
+            Resource::Disk => write!(f, "disk"),
+        }
+    }
+}
+
+/// One instance's current usage and growth
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TenantUsage {
+    pub process: String,
+    pub id: String,
+    pub memory_bytes: u64,
+    pub disk_bytes: u64,
+    pub memory_growth_bytes_per_day: f64,
+    pub disk_growth_bytes_per_day: f64,
+}
+
+impl TenantUsage {
+    fn growth(&self, resource: Resource) -> f64 {
+        match resource {
+            Resource::Memory => self.memory_growth_bytes_per_day,
+            Resource::Disk => self.disk_growth_bytes_per_day,
+        }
+    }
+
+    fn used(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::Memory => self.memory_bytes,
+            Resource::Disk => self.disk_bytes,
+        }
+    }
+}
+
+/// Capacity forecast built from the usage history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CapacityReport {
+    /// Sampling passes the forecast is based on
+    pub samples: usize,
+    /// Time between the first and latest pass
+    pub history_secs: u64,
+    pub memory: ResourceForecast,
+    pub disk: ResourceForecast,
+    /// The resource that runs out first (memory when neither is growing)
+    pub bottleneck: Resource,
+    /// Instances to move first, fastest-growing users of `bottleneck` first
+    pub migrate: Vec<TenantUsage>,
+}
+
+impl CapacityReport {
+    /// Forecast from the last `history` of `store`, against the host
+    /// stats of the disk holding `data_dir`
+    pub async fn load(
+        store: &UsageStore,
+        data_dir: &Path,
+        history: Duration,
+        top: usize,
+    ) -> Result<Self> {
+        let samples = store.since(unix_now() - history.as_secs() as i64).await?;
+        let host = HostStats::collect(data_dir).await?;
+        Ok(Self::build(&samples, &host, top))
+    }
+
+    /// Forecast from `samples` (any order) against the host's free memory
+    /// and data dir disk, suggesting up to `top` instances to migrate
+    pub fn build(samples: &[UsageSample], host: &HostStats, top: usize) -> Self {
+        let mut passes: BTreeMap<i64, (u64, u64)> = BTreeMap::new();
+        let mut series: HashMap<(&str, &str), Vec<&UsageSample>> = HashMap::new();
+        for sample in samples {
+            let pass = passes.entry(sample.timestamp).or_default();
+            pass.0 += sample.memory_bytes;
+            pass.1 += sample.disk_bytes;
+            series
+                .entry((&sample.process, &sample.instance_id))
+                .or_default()
+                .push(sample);
+        }
+        let (first, latest) = match (passes.keys().next(), passes.keys().next_back()) {
+            (Some(first), Some(latest)) => (*first, *latest),
+            _ => (0, 0),
+        };
+        let (memory_used, disk_used) = passes.get(&latest).copied().unwrap_or_default();
+        let memory_points: Vec<_> = passes.iter().map(|(t, (m, _))| (*t, *m)).collect();
+        let disk_points: Vec<_> = passes.iter().map(|(t, (_, d))| (*t, *d)).collect();
+
+        let memory = ResourceForecast::new(
+            memory_used,
+            host.memory_available_bytes,
+            slope_per_day(&memory_points),
+        );
+        let disk = ResourceForecast::new(
+            disk_used,
+            host.disks.first().map(|d| d.available_bytes),
+            slope_per_day(&disk_points),
+        );
+        let bottleneck = match (memory.days_left, disk.days_left) {
+            (Some(m), Some(d)) if d < m => Resource::Disk,
+            (None, Some(_)) => Resource::Disk,
+            _ => Resource::Memory,
+        };
+
+        // Only instances still running at the latest pass can be moved
+        let mut tenants: Vec<TenantUsage> = series
+            .into_iter()
+            .filter_map(|((process, id), mut samples)| {
+                samples.sort_by_key(|s| s.timestamp);
+                let current = samples.last().filter(|s| s.timestamp == latest)?;
+                let points = |f: fn(&UsageSample) -> u64| -> Vec<(i64, u64)> {
+                    samples.iter().map(|s| (s.timestamp, f(s))).collect()
+                };
+                Some(TenantUsage {
+                    process: process.to_string(),
+                    id: id.to_string(),
+                    memory_bytes: current.memory_bytes,
+                    disk_bytes: current.disk_bytes,
+                    memory_growth_bytes_per_day: slope_per_day(&points(|s| s.memory_bytes)),
+                    disk_growth_bytes_per_day: slope_per_day(&points(|s| s.disk_bytes)),
+                })
+            })
+            .collect();
+        tenants.sort_by(|a, b| {
+            b.growth(bottleneck)
+                .total_cmp(&a.growth(bottleneck))
+                .then_with(|| b.used(bottleneck).cmp(&a.used(bottleneck)))
+                .then_with(|| (&a.process, &a.id).cmp(&(&b.process, &b.id)))
+        });
+        tenants.truncate(top);
+
+        Self {
+            samples: passes.len(),
+            history_secs: (latest - first).max(0) as u64,
+            memory,
+            disk,
+            bottleneck,
+            migrate: tenants,
+        }
+    }
+}
+
+/// Least-squares slope of `(unix seconds, bytes)` points, in bytes per day
+fn slope_per_day(points: &[(i64, u64)]) -> f64 {
+    if points.len() < 2 {
+        return 0.0;
+    }
+    let n = points.len() as f64;
+    let t0 = points[0].0;
+    let xs: Vec<f64> = points
+        .iter()
+        .map(|(t, _)| (t - t0) as f64 / DAY_SECS)
+        .collect();
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| *y as f64).sum::<f64>() / n;
+    let (mut covariance, mut variance) = (0.0, 0.0);
+    for (x, (_, y)) in xs.iter().zip(points) {
+        covariance += (x - mean_x) * (*y as f64 - mean_y);
+        variance += (x - mean_x).powi(2);
+    }
+    if variance == 0.0 {
+        0.0
+    } else {
+        covariance / variance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::DiskUsage;
+
+    const GB: u64 = 1024 * 1024 * 1024;
+    const MB: u64 = 1024 * 1024;
+
+    fn host(memory_available: u64, disk_available: u64) -> HostStats {
+        HostStats {
+            load_average: [0.0; 3],
+            memory_total_bytes: Some(16 * GB),
+            memory_available_bytes: Some(memory_available),
+            disks: vec![DiskUsage {
+                mount_point: "/".into(),
+                total_bytes: 100 * GB,
+                available_bytes: disk_available,
+                inodes_total: 0,
+                inodes_free: 0,
+            }],
+        }
+    }
+
+    fn sample(day: i64, id: &str, memory: u64, disk: u64) -> UsageSample {
+        UsageSample {
+            timestamp: 1_700_000_000 + day * 86400,
+            process: "api".to_string(),
+            instance_id: id.to_string(),
+            memory_bytes: memory,
+            disk_bytes: disk,
+        }
+    }
+
+    #[test]
+    fn test_slope_per_day() {
+        assert_eq!(slope_per_day(&[]), 0.0);
+        assert_eq!(slope_per_day(&[(0, 5)]), 0.0);
+        let growing = [(0, 100), (43200, 150), (86400, 200)];
+        assert!((slope_per_day(&growing) - 100.0).abs() < 1e-9);
+        let shrinking = [(0, 300), (86400 * 2, 100)];
+        assert!((slope_per_day(&shrinking) + 100.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (my (odd) app) S 1 4240 4240 0 -1 4194560 100 0 0 0 1 2 0 0 20 0 3 0 123 456789 77 18446744073709551615";
+        assert_eq!(parse_stat(stat), Some((4240, 77)));
+        assert_eq!(parse_stat("garbage"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_process_group_rss_includes_own_group() {
+        let pgid = unsafe { libc::getpgrp() } as u32;
+        let groups = process_group_rss().unwrap();
+        assert!(groups.get(&pgid).copied().unwrap_or(0) > 0);
+    }
+
+    #[test]
+    fn test_report_forecasts_and_ranks_by_bottleneck() {
+        // alice's disk grows 1 GB/day, bob's memory 10 MB/day, carol is flat
+        // and dave stopped before the latest pass
+        let mut samples = Vec::new();
+        for day in 0..=4 {
+            samples.push(sample(day, "alice", 200 * MB, (2 + day as u64) * GB));
+            samples.push(sample(day, "bob", (100 + 10 * day as u64) * MB, GB));
+            samples.push(sample(day, "carol", 500 * MB, 3 * GB));
+        }
+        samples.push(sample(0, "dave", 0, 0));
+
+        let report = CapacityReport::build(&samples, &host(8 * GB, 20 * GB), 2);
+        assert_eq!(report.samples, 5);
+        assert_eq!(report.history_secs, 4 * 86400);
+        assert_eq!(report.disk.used_bytes, 10 * GB);
+        assert!((report.disk.growth_bytes_per_day - GB as f64).abs() < 1.0);
+        assert!((report.disk.days_left.unwrap() - 20.0).abs() < 1e-6);
+        assert!((report.memory.days_left.unwrap() - 8.0 * 1024.0 / 10.0).abs() < 1e-6);
+        assert_eq!(report.bottleneck, Resource::Disk);
+        let ids: Vec<&str> = report.migrate.iter().map(|t| t.id.as_str()).collect();
+        // Disk runs out first: alice grows fastest, then ties broken by size
+        assert_eq!(ids, ["alice", "carol"]);
+        assert_eq!(report.migrate[0].disk_bytes, 6 * GB);
+
+        // Nothing growing: no forecast, biggest memory users first
+        let flat: Vec<_> = samples
+            .iter()
+            .filter(|s| s.instance_id == "carol" || s.instance_id == "dave")
+            .cloned()
+            .collect();
+        let report = CapacityReport::build(&flat, &host(8 * GB, 20 * GB), 5);
+        assert_eq!(report.memory.days_left, None);
+        assert_eq!(report.disk.days_left, None);
+        assert_eq!(report.bottleneck, Resource::Memory);
+        assert_eq!(report.migrate.len(), 1);
+
+        let empty = CapacityReport::build(&[], &host(GB, GB), 5);
+        assert_eq!(empty.samples, 0);
+        assert!(empty.migrate.is_empty());
+    }
+}
@@ -1,14 +1,20 @@
 //! Cgroup v2 resource limits for Linux
 //!
-//! Provides memory and CPU limits via cgroups v2 unified hierarchy.
+//! Provides memory, CPU and block IO limits via cgroups v2 unified hierarchy.
 //! Requires Linux kernel 4.5+ with cgroups v2 enabled.
 //!
+//! As root, instance cgroups live under `/sys/fs/cgroup/tenement`. A non-root
+//! daemon started by the systemd user manager (`systemctl --user`,
+//! `systemd-run --user`) uses `tenement` in the subtree systemd delegates to
+//! that manager instead, e.g.
+//! `/sys/fs/cgroup/user.slice/user-1000.slice/user@1000.service/tenement`.
+//!
 //! **Linux only** - on other platforms, returns Ok() (no-op).
 
 #[cfg(target_os = "linux")]
 use anyhow::Context;
 use anyhow::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Base path for cgroups v2 unified hierarchy (Linux only)
 #[cfg(target_os = "linux")]
@@ -22,15 +28,190 @@ const TENEMENT_CGROUP: &str = "/sys/fs/cgroup/tenement";
 pub struct ResourceLimits {
     /// Memory limit in MB (None = unlimited)
     pub memory_limit_mb: Option<u32>,
+    /// Memory in MB past which the instance is throttled and reclaimed
+    /// (None = no soft limit)
+    pub memory_high_mb: Option<u32>,
+    /// Swap in MB the instance may use (None = unlimited, 0 = none)
+    pub memory_swap_max_mb: Option<u32>,
     /// CPU weight (1-10000, None = default 100)
     pub cpu_shares: Option<u32>,
+    /// Read bandwidth in bytes/s on `io_device` (None = unlimited)
+    pub io_read_bps: Option<u64>,
+    /// Write bandwidth in bytes/s on `io_device` (None = unlimited)
+    pub io_write_bps: Option<u64>,
+    /// Read and write IO operations/s, each, on `io_device` (None = unlimited)
+    pub io_iops: Option<u64>,
+    /// Block device (major, minor) the IO limits apply to
+    pub io_device: Option<(u32, u32)>,
+    /// Maximum number of processes and threads (None = unlimited)
+    pub max_pids: Option<u32>,
+    /// CPUs the instance may run on, as a list like `0-3,6` (None = all)
+    pub cpuset: Option<String>,
 }
 
 impl ResourceLimits {
     /// Check if any limits are configured
     pub fn has_limits(&self) -> bool {
-        self.memory_limit_mb.is_some() || self.cpu_shares.is_some()
+        self.memory_limit_mb.is_some()
+            || self.memory_high_mb.is_some()
+            || self.memory_swap_max_mb.is_some()
+            || self.cpu_shares.is_some()
+            || self.has_io_limits()
+            || self.max_pids.is_some()
+            || self.cpuset.is_some()
+    }
+
+    /// Check if any IO limits are configured
+    pub fn has_io_limits(&self) -> bool {
+        self.io_read_bps.is_some() || self.io_write_bps.is_some() || self.io_iops.is_some()
+    }
+
+    /// The `io.max` line for these limits, e.g. `8:0 rbps=1048576`. Limits
+    /// left unset keep their current value; 0 lifts one (`max`).
+    fn io_max(&self) -> Option<String> {
+        if !self.has_io_limits() {
+            return None;
+        }
+        let (major, minor) = self.io_device?;
+        let mut line = format!("{}:{}", major, minor);
+        for (key, limit) in [
+            ("rbps", self.io_read_bps),
+            ("wbps", self.io_write_bps),
+            ("riops", self.io_iops),
+            ("wiops", self.io_iops),
+        ] {
+            match limit {
+                Some(0) => line.push_str(&format!(" {}=max", key)),
+                Some(n) => line.push_str(&format!(" {}={}", key, n)),
+                None => {}
+            }
+        }
+        Some(line)
+    }
+}
+
+/// Check a CPU list as `cpuset.cpus` takes it: comma-separated CPU numbers
+/// and ascending ranges, e.g. `0-3,6`
+pub fn validate_cpuset(cpus: &str) -> Result<()> {
+    let valid = !cpus.is_empty()
+        && cpus.split(',').all(|item| {
+            let (first, last) = item.split_once('-').unwrap_or((item, item));
+            match (first.parse::<u32>(), last.parse::<u32>()) {
+                (Ok(first), Ok(last)) => first <= last,
+                _ => false,
+            }
+        });
+    if !valid {
+        anyhow::bail!(
+            "invalid cpuset {:?}; use CPU numbers and ranges like \"0-3,6\"",
+            cpus
+        );
     }
+    Ok(())
+}
+
+/// Split a Linux `dev_t` into (major, minor), as glibc's `gnu_dev_major`
+/// and `gnu_dev_minor` do
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn dev_major_minor(dev: u64) -> (u32, u32) {
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff);
+    (major as u32, minor as u32)
+}
+
+/// The whole-disk block device (major, minor) holding `path`, which is what
+/// `io.max` takes; a partition resolves to its disk
+#[cfg(target_os = "linux")]
+pub fn block_device(path: &Path) -> Result<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+
+    let dev = std::fs::metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?
+        .dev();
+    let (major, minor) = dev_major_minor(dev);
+    let sys = PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+    if !sys.exists() {
+        anyhow::bail!(
+            "{} is not on a block device ({}:{}), so IO limits can't be applied to it",
+            path.display(),
+            major,
+            minor
+        );
+    }
+    if !sys.join("partition").exists() {
+        return Ok((major, minor));
+    }
+    // /sys/dev/block/8:1 -> .../block/sda/sda1, whose parent is the disk
+    let disk = sys
+        .canonicalize()?
+        .parent()
+        .map(|p| p.join("dev"))
+        .context("partition has no parent disk")?;
+    let dev = std::fs::read_to_string(&disk)
+        .with_context(|| format!("Failed to read {}", disk.display()))?;
+    let (major, minor) = dev
+        .trim()
+        .split_once(':')
+        .context("malformed block device number")?;
+    Ok((major.parse()?, minor.parse()?))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn block_device(path: &Path) -> Result<(u32, u32)> {
+    anyhow::bail!(
+        "IO limits are only supported on Linux (data dir {})",
+        path.display()
+    )
+}
+
+/// The `oom_kill` count from a cgroup's `memory.events`: processes the
+/// kernel killed because the cgroup hit `memory.max`
+fn parse_oom_kills(events: &str) -> Option<u64> {
+    events.lines().find_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        (key == "oom_kill").then(|| value.trim().parse().ok())?
+    })
+}
+
+/// The tenement subtree for this daemon: [`TENEMENT_CGROUP`] as root, or
+/// the systemd user manager's delegated subtree when running as another user
+/// inside it
+#[cfg(target_os = "linux")]
+fn default_base_path() -> PathBuf {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let uid = unsafe { libc::geteuid() };
+    if uid != 0 {
+        let delegated = std::fs::read_to_string("/proc/self/cgroup")
+            .ok()
+            .and_then(|cgroups| delegated_base_path(&cgroups, uid));
+        if let Some(path) = delegated {
+            return path;
+        }
+    }
+    PathBuf::from(TENEMENT_CGROUP)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn default_base_path() -> PathBuf {
+    PathBuf::from(TENEMENT_CGROUP)
+}
+
+/// `tenement` inside `user@<uid>.service`, if the cgroup v2 entry of
+/// `cgroups` (the contents of `/proc/self/cgroup`) lies within it. systemd
+/// hands that subtree to the user's manager, so its processes can create
+/// cgroups there and move their children into them.
+#[cfg(target_os = "linux")]
+fn delegated_base_path(cgroups: &str, uid: u32) -> Option<PathBuf> {
+    let own = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let manager = format!("user@{}.service", uid);
+    let mut path = PathBuf::from(CGROUP_BASE);
+    for component in own.split('/').filter(|c| !c.is_empty()) {
+        path.push(component);
+        if component == manager {
+            return Some(path.join("tenement"));
+        }
+    }
+    None
 }
 
 /// Manages cgroup v2 resource limits for tenement instances
@@ -41,13 +222,25 @@ pub struct CgroupManager {
 }
 
 impl CgroupManager {
-    /// Create a new cgroup manager
+    /// Create a new cgroup manager for this daemon's user (see the module
+    /// docs for where its cgroups go)
     pub fn new() -> Self {
         Self {
-            base_path: PathBuf::from(TENEMENT_CGROUP),
+            base_path: default_base_path(),
         }
     }
 
+    /// The cgroup holding the instance cgroups
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// A manager rooted at `base_path` instead of the tenement subtree
+    #[cfg(test)]
+    pub(crate) fn with_base_path(base_path: PathBuf) -> Self {
+        Self { base_path }
+    }
+
     /// Check if cgroups v2 are available on this system
     #[cfg(target_os = "linux")]
     pub fn is_available(&self) -> bool {
@@ -100,27 +293,123 @@ impl CgroupManager {
         std::fs::create_dir_all(&cgroup_path).with_context(|| {
             format!(
                 "Failed to create cgroup directory: {}\n\
-                Try: sudo mkdir -p {} && sudo chown $(id -u):$(id -g) {}",
+                Try: sudo mkdir -p {} && sudo chown $(id -u):$(id -g) {}, \
+                or run tenement as a systemd user service",
                 cgroup_path.display(),
-                TENEMENT_CGROUP,
-                TENEMENT_CGROUP
+                self.base_path.display(),
+                self.base_path.display()
             )
         })?;
 
+        self.write_limits(instance_id, &cgroup_path, limits)?;
+
+        tracing::info!(
+            "Created cgroup for {} with limits: memory={}MB, cpu_weight={}",
+            instance_id,
+            limits.memory_limit_mb.unwrap_or(0),
+            limits.cpu_shares.unwrap_or(100)
+        );
+
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn create_cgroup(&self, _instance_id: &str, _limits: &ResourceLimits) -> Result<()> {
+        // No-op on non-Linux
+        Ok(())
+    }
+
+    /// Change the limits of a running instance in place. Only the limits set
+    /// in `limits` change, and 0 lifts a memory, IO or process limit. An
+    /// instance spawned without limits has no cgroup yet: one is created and
+    /// `pid` moved into it.
+    #[cfg(target_os = "linux")]
+    pub fn update_limits(
+        &self,
+        instance_id: &str,
+        pid: u32,
+        limits: &ResourceLimits,
+    ) -> Result<()> {
+        if !self.is_available() {
+            anyhow::bail!(
+                "cgroups v2 not available, can't change limits of {}",
+                instance_id
+            );
+        }
+        let cgroup_path = self.cgroup_path(instance_id);
+        if !cgroup_path.exists() {
+            self.create_cgroup(instance_id, limits)?;
+            return self.add_process(instance_id, pid, limits);
+        }
+        self.write_limits(instance_id, &cgroup_path, limits)?;
+        tracing::info!("Updated cgroup limits for {}", instance_id);
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn update_limits(
+        &self,
+        _instance_id: &str,
+        _pid: u32,
+        _limits: &ResourceLimits,
+    ) -> Result<()> {
+        anyhow::bail!("Resource limits are only supported on Linux")
+    }
+
+    /// Write the limits set in `limits` to the cgroup at `cgroup_path`
+    #[cfg(target_os = "linux")]
+    fn write_limits(
+        &self,
+        instance_id: &str,
+        cgroup_path: &Path,
+        limits: &ResourceLimits,
+    ) -> Result<()> {
         // Apply memory limit
         if let Some(memory_mb) = limits.memory_limit_mb {
-            if memory_mb > 0 {
-                let memory_bytes = (memory_mb as u64) * 1024 * 1024;
-                let memory_max_path = cgroup_path.join("memory.max");
-                std::fs::write(&memory_max_path, memory_bytes.to_string()).with_context(|| {
-                    format!(
-                        "Failed to set memory limit: {}\n\
-                        Ensure memory controller is enabled in parent cgroup",
-                        memory_max_path.display()
-                    )
-                })?;
-                tracing::debug!("Set memory limit for {}: {}MB", instance_id, memory_mb);
-            }
+            let memory_max = match memory_mb {
+                0 => "max".to_string(),
+                mb => (u64::from(mb) * 1024 * 1024).to_string(),
+            };
+            let memory_max_path = cgroup_path.join("memory.max");
+            std::fs::write(&memory_max_path, memory_max).with_context(|| {
+                format!(
+                    "Failed to set memory limit: {}\n\
+                    Ensure memory controller is enabled in parent cgroup",
+                    memory_max_path.display()
+                )
+            })?;
+            tracing::debug!("Set memory limit for {}: {}MB", instance_id, memory_mb);
+        }
+
+        // Apply memory soft limit
+        if let Some(high_mb) = limits.memory_high_mb {
+            let memory_high = match high_mb {
+                0 => "max".to_string(),
+                mb => (u64::from(mb) * 1024 * 1024).to_string(),
+            };
+            let memory_high_path = cgroup_path.join("memory.high");
+            std::fs::write(&memory_high_path, memory_high).with_context(|| {
+                format!(
+                    "Failed to set memory soft limit: {}\n\
+                    Ensure memory controller is enabled in parent cgroup",
+                    memory_high_path.display()
+                )
+            })?;
+            tracing::debug!("Set memory soft limit for {}: {}MB", instance_id, high_mb);
+        }
+
+        // Apply swap limit (0 means no swap, not unlimited)
+        if let Some(swap_mb) = limits.memory_swap_max_mb {
+            let swap_max_path = cgroup_path.join("memory.swap.max");
+            let swap_bytes = u64::from(swap_mb) * 1024 * 1024;
+            std::fs::write(&swap_max_path, swap_bytes.to_string()).with_context(|| {
+                format!(
+                    "Failed to set swap limit: {}\n\
+                    Ensure the kernel has swap accounting enabled",
+                    swap_max_path.display()
+                )
+            })?;
+            tracing::debug!("Set swap limit for {}: {}MB", instance_id, swap_mb);
         }
 
         // Apply CPU weight
@@ -146,19 +435,53 @@ impl CgroupManager {
             tracing::debug!("Set CPU weight for {}: {}", instance_id, weight);
         }
 
-        tracing::info!(
-            "Created cgroup for {} with limits: memory={}MB, cpu_weight={}",
-            instance_id,
-            limits.memory_limit_mb.unwrap_or(0),
-            limits.cpu_shares.unwrap_or(100)
-        );
+        // Apply IO limits
+        if limits.has_io_limits() {
+            let io_max = limits
+                .io_max()
+                .context("IO limits need the block device they apply to")?;
+            let io_max_path = cgroup_path.join("io.max");
+            std::fs::write(&io_max_path, &io_max).with_context(|| {
+                format!(
+                    "Failed to set IO limits: {}\n\
+                    Ensure io controller is enabled in parent cgroup",
+                    io_max_path.display()
+                )
+            })?;
+            tracing::debug!("Set IO limits for {}: {}", instance_id, io_max);
+        }
 
-        Ok(())
-    }
+        // Apply process limit
+        if let Some(max_pids) = limits.max_pids {
+            let pids_max = match max_pids {
+                0 => "max".to_string(),
+                n => n.to_string(),
+            };
+            let pids_max_path = cgroup_path.join("pids.max");
+            std::fs::write(&pids_max_path, pids_max).with_context(|| {
+                format!(
+                    "Failed to set process limit: {}\n\
+                    Ensure pids controller is enabled in parent cgroup",
+                    pids_max_path.display()
+                )
+            })?;
+            tracing::debug!("Set process limit for {}: {}", instance_id, max_pids);
+        }
+
+        // Apply CPU pinning
+        if let Some(cpus) = &limits.cpuset {
+            let cpuset_path = cgroup_path.join("cpuset.cpus");
+            std::fs::write(&cpuset_path, cpus).with_context(|| {
+                format!(
+                    "Failed to set cpuset {:?}: {}\n\
+                    Ensure cpuset controller is enabled in parent cgroup and the CPUs exist",
+                    cpus,
+                    cpuset_path.display()
+                )
+            })?;
+            tracing::debug!("Set cpuset for {}: {}", instance_id, cpus);
+        }
 
-    #[cfg(not(target_os = "linux"))]
-    pub fn create_cgroup(&self, _instance_id: &str, _limits: &ResourceLimits) -> Result<()> {
-        // No-op on non-Linux
         Ok(())
     }
 
@@ -202,6 +525,47 @@ impl CgroupManager {
         Ok(())
     }
 
+    /// Processes OOM-killed in the instance's cgroup since it was created
+    /// (None if it has no cgroup)
+    #[cfg(target_os = "linux")]
+    pub fn oom_kills(&self, instance_id: &str) -> Option<u64> {
+        let events = self.cgroup_path(instance_id).join("memory.events");
+        parse_oom_kills(&std::fs::read_to_string(events).ok()?)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn oom_kills(&self, _instance_id: &str) -> Option<u64> {
+        None
+    }
+
+    /// Move an instance's cgroup, processes and all, to another instance ID.
+    /// A no-op if it has none.
+    #[cfg(target_os = "linux")]
+    pub fn rename_cgroup(&self, from: &str, to: &str) -> Result<()> {
+        let from_path = self.cgroup_path(from);
+        if !from_path.exists() {
+            return Ok(());
+        }
+        let to_path = self.cgroup_path(to);
+        // An empty one left behind by an earlier instance of `to`
+        if to_path.exists() {
+            std::fs::remove_dir(&to_path)
+                .with_context(|| format!("Failed to remove stale cgroup {}", to_path.display()))?;
+        }
+        std::fs::rename(&from_path, &to_path).with_context(|| {
+            format!(
+                "Failed to move cgroup {} to {}",
+                from_path.display(),
+                to_path.display()
+            )
+        })
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn rename_cgroup(&self, _from: &str, _to: &str) -> Result<()> {
+        Ok(())
+    }
+
     /// Remove the cgroup for an instance
     #[cfg(target_os = "linux")]
     pub fn remove_cgroup(&self, instance_id: &str) -> Result<()> {
@@ -260,19 +624,23 @@ impl CgroupManager {
         std::fs::create_dir_all(&self.base_path).with_context(|| {
             format!(
                 "Failed to create tenement cgroup: {}\n\
-                Try: sudo mkdir -p {} && sudo chown $(id -u):$(id -g) {}",
+                Try: sudo mkdir -p {} && sudo chown $(id -u):$(id -g) {}, \
+                or run tenement as a systemd user service",
+                self.base_path.display(),
                 self.base_path.display(),
-                TENEMENT_CGROUP,
-                TENEMENT_CGROUP
+                self.base_path.display()
             )
         })?;
 
         // Enable controllers for child cgroups
-        // We need memory and cpu controllers
+        // We need memory, cpu, io, pids and cpuset controllers
         let subtree_control = self.base_path.join("cgroup.subtree_control");
         if subtree_control.exists() {
-            // Try to enable controllers (may fail if not available in parent)
-            std::fs::write(&subtree_control, "+memory +cpu").ok();
+            // Try to enable controllers (may fail if not available in parent).
+            // One at a time, so a missing one doesn't block the others
+            for controller in ["+memory", "+cpu", "+io", "+pids", "+cpuset"] {
+                std::fs::write(&subtree_control, controller).ok();
+            }
         }
 
         Ok(())
@@ -301,18 +669,21 @@ mod tests {
         let with_memory = ResourceLimits {
             memory_limit_mb: Some(256),
             cpu_shares: None,
+            ..Default::default()
         };
         assert!(with_memory.has_limits());
 
         let with_cpu = ResourceLimits {
             memory_limit_mb: None,
             cpu_shares: Some(200),
+            ..Default::default()
         };
         assert!(with_cpu.has_limits());
 
         let with_both = ResourceLimits {
             memory_limit_mb: Some(512),
             cpu_shares: Some(500),
+            ..Default::default()
         };
         assert!(with_both.has_limits());
     }
@@ -330,6 +701,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(512),
             cpu_shares: Some(200),
+            ..Default::default()
         };
         let cloned = limits.clone();
         assert_eq!(limits.memory_limit_mb, cloned.memory_limit_mb);
@@ -341,6 +713,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(256),
             cpu_shares: Some(100),
+            ..Default::default()
         };
         let debug = format!("{:?}", limits);
         assert!(debug.contains("256"));
@@ -352,6 +725,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(1024),
             cpu_shares: None,
+            ..Default::default()
         };
         assert!(limits.has_limits());
         assert_eq!(limits.memory_limit_mb, Some(1024));
@@ -362,6 +736,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: None,
             cpu_shares: Some(500),
+            ..Default::default()
         };
         assert!(limits.has_limits());
         assert_eq!(limits.cpu_shares, Some(500));
@@ -374,6 +749,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(0),
             cpu_shares: None,
+            ..Default::default()
         };
         assert!(limits.has_limits());
     }
@@ -383,6 +759,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: None,
             cpu_shares: Some(0),
+            ..Default::default()
         };
         assert!(limits.has_limits());
     }
@@ -392,26 +769,137 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(u32::MAX),
             cpu_shares: Some(10000),
+            ..Default::default()
         };
         assert!(limits.has_limits());
         assert_eq!(limits.memory_limit_mb, Some(u32::MAX));
         assert_eq!(limits.cpu_shares, Some(10000));
     }
 
+    #[test]
+    fn test_resource_limits_io_max() {
+        let mut limits = ResourceLimits {
+            io_write_bps: Some(1_048_576),
+            io_iops: Some(500),
+            ..Default::default()
+        };
+        assert!(limits.has_limits() && limits.has_io_limits());
+        // No device, no line
+        assert_eq!(limits.io_max(), None);
+        limits.io_device = Some((8, 0));
+        assert_eq!(
+            limits.io_max().unwrap(),
+            "8:0 wbps=1048576 riops=500 wiops=500"
+        );
+        limits.io_write_bps = Some(0);
+        limits.io_iops = None;
+        assert_eq!(limits.io_max().unwrap(), "8:0 wbps=max");
+        assert_eq!(ResourceLimits::default().io_max(), None);
+    }
+
+    #[test]
+    fn test_resource_limits_max_pids() {
+        let limits = ResourceLimits {
+            max_pids: Some(64),
+            ..Default::default()
+        };
+        assert!(limits.has_limits());
+        assert!(!limits.has_io_limits());
+    }
+
+    #[test]
+    fn test_dev_major_minor() {
+        // makedev(8, 1) and makedev(259, 65536)
+        assert_eq!(dev_major_minor(0x801), (8, 1));
+        assert_eq!(dev_major_minor(0x1001_0300), (259, 65536));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_write_limits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manager = CgroupManager::with_base_path(dir.path().to_path_buf());
+        let limits = ResourceLimits {
+            memory_limit_mb: Some(512),
+            memory_high_mb: Some(0),
+            memory_swap_max_mb: Some(0),
+            max_pids: Some(0),
+            cpuset: Some("0-1".to_string()),
+            ..Default::default()
+        };
+        manager
+            .write_limits("api:prod", dir.path(), &limits)
+            .unwrap();
+        let read = |file: &str| std::fs::read_to_string(dir.path().join(file)).unwrap();
+        assert_eq!(read("memory.max"), "536870912");
+        assert_eq!(read("memory.high"), "max");
+        assert_eq!(read("memory.swap.max"), "0");
+        assert_eq!(read("pids.max"), "max");
+        assert_eq!(read("cpuset.cpus"), "0-1");
+        // Unset limits are left alone
+        assert!(!dir.path().join("cpu.weight").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_rename_cgroup() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manager = CgroupManager::with_base_path(dir.path().to_path_buf());
+        manager.rename_cgroup("api:__warm-1", "api:alice").unwrap();
+
+        std::fs::create_dir(dir.path().join("api:__warm-1")).unwrap();
+        std::fs::create_dir(dir.path().join("api:alice")).unwrap();
+        manager.rename_cgroup("api:__warm-1", "api:alice").unwrap();
+        assert!(!dir.path().join("api:__warm-1").exists());
+        assert!(dir.path().join("api:alice").is_dir());
+    }
+
+    #[test]
+    fn test_validate_cpuset() {
+        for ok in ["0", "0-3", "0-3,6", "1,3,5-7"] {
+            assert!(validate_cpuset(ok).is_ok(), "{ok}");
+        }
+        for bad in ["", "3-0", "0-", "a", "0,,1", "0 - 3"] {
+            assert!(validate_cpuset(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_parse_oom_kills() {
+        let events = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(parse_oom_kills(events), Some(2));
+        assert_eq!(parse_oom_kills("low 0\nhigh 0\n"), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_oom_kills() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let manager = CgroupManager::with_base_path(dir.path().to_path_buf());
+        assert_eq!(manager.oom_kills("api:alice"), None);
+        std::fs::create_dir(dir.path().join("api:alice")).unwrap();
+        std::fs::write(
+            dir.path().join("api:alice/memory.events"),
+            "oom 1\noom_kill 1\n",
+        )
+        .unwrap();
+        assert_eq!(manager.oom_kills("api:alice"), Some(1));
+    }
+
     // ===================
     // CGROUP PATH TESTS
     // ===================
 
     #[test]
     fn test_cgroup_path() {
-        let manager = CgroupManager::new();
+        let manager = CgroupManager::with_base_path(PathBuf::from(TENEMENT_CGROUP));
         let path = manager.cgroup_path("api:user123");
         assert_eq!(path, PathBuf::from("/sys/fs/cgroup/tenement/api:user123"));
     }
 
     #[test]
     fn test_cgroup_path_with_special_chars() {
-        let manager = CgroupManager::new();
+        let manager = CgroupManager::with_base_path(PathBuf::from(TENEMENT_CGROUP));
         let path = manager.cgroup_path("api-v2:user_123");
         assert_eq!(
             path,
@@ -421,7 +909,7 @@ mod tests {
 
     #[test]
     fn test_cgroup_path_simple_id() {
-        let manager = CgroupManager::new();
+        let manager = CgroupManager::with_base_path(PathBuf::from(TENEMENT_CGROUP));
         let path = manager.cgroup_path("simple");
         assert_eq!(path, PathBuf::from("/sys/fs/cgroup/tenement/simple"));
     }
@@ -429,13 +917,32 @@ mod tests {
     #[test]
     fn test_cgroup_manager_new() {
         let manager = CgroupManager::new();
-        assert_eq!(manager.base_path, PathBuf::from(TENEMENT_CGROUP));
+        assert_eq!(manager.base_path(), default_base_path());
     }
 
     #[test]
     fn test_cgroup_manager_default() {
         let manager = CgroupManager::default();
-        assert_eq!(manager.base_path, PathBuf::from(TENEMENT_CGROUP));
+        assert_eq!(manager.base_path, default_base_path());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_delegated_base_path() {
+        let cgroups =
+            "0::/user.slice/user-1000.slice/user@1000.service/app.slice/tenement.service\n";
+        assert_eq!(
+            delegated_base_path(cgroups, 1000),
+            Some(PathBuf::from(
+                "/sys/fs/cgroup/user.slice/user-1000.slice/user@1000.service/tenement"
+            ))
+        );
+        // Another user's manager, or a login session outside the delegation
+        assert_eq!(delegated_base_path(cgroups, 1001), None);
+        let session = "0::/user.slice/user-1000.slice/session-3.scope\n";
+        assert_eq!(delegated_base_path(session, 1000), None);
+        // cgroup v1 only
+        assert_eq!(delegated_base_path("4:memory:/user.slice\n", 1000), None);
     }
 
     // ===================
@@ -456,6 +963,7 @@ mod tests {
         let limits = ResourceLimits {
             memory_limit_mb: Some(256),
             cpu_shares: Some(100),
+            ..Default::default()
         };
 
         // All operations should succeed as no-ops
@@ -520,6 +1028,7 @@ mod tests {
             let limits = ResourceLimits {
                 memory_limit_mb: Some(256),
                 cpu_shares: Some(100),
+                ..Default::default()
             };
 
             let instance_id = format!("test-{}", std::process::id());
@@ -545,6 +1054,7 @@ mod tests {
             let limits = ResourceLimits {
                 memory_limit_mb: Some(256),
                 cpu_shares: None,
+                ..Default::default()
             };
 
             let instance_id = format!("test-mem-{}", std::process::id());
@@ -570,6 +1080,7 @@ mod tests {
             let limits = ResourceLimits {
                 memory_limit_mb: None,
                 cpu_shares: Some(500),
+                ..Default::default()
             };
 
             let instance_id = format!("test-cpu-{}", std::process::id());
@@ -594,6 +1105,7 @@ mod tests {
             let limits = ResourceLimits {
                 memory_limit_mb: None,
                 cpu_shares: Some(0), // Below minimum, should clamp to 1
+                ..Default::default()
             };
 
             let instance_id = format!("test-cpu-min-{}", std::process::id());
@@ -616,6 +1128,7 @@ mod tests {
             let limits = ResourceLimits {
                 memory_limit_mb: None,
                 cpu_shares: Some(50000), // Above maximum, should clamp to 10000
+                ..Default::default()
             };
 
             let instance_id = format!("test-cpu-max-{}", std::process::id());
@@ -653,6 +1166,18 @@ mod tests {
             assert!(manager.add_process("test", 12345, &limits).is_ok());
         }
 
+        #[test]
+        fn test_block_device() {
+            // Whatever disk holds the temp dir, the result is a whole disk
+            let dir = tempfile::TempDir::new().unwrap();
+            if let Ok((major, minor)) = block_device(dir.path()) {
+                let sys = PathBuf::from(format!("/sys/dev/block/{}:{}", major, minor));
+                assert!(sys.exists());
+                assert!(!sys.join("partition").exists());
+            }
+            assert!(block_device(Path::new("/proc/self")).is_err());
+        }
+
         #[test]
         fn test_remove_nonexistent_cgroup() {
             let manager = CgroupManager::new();
@@ -0,0 +1,43 @@
+//! Time source for the hypervisor
+//!
+//! The health monitor interval, idle reaping, restart backoff, the restart
+//! window and instance uptime/idle times all read time through a [`Clock`],
+//! so tests can swap in a fake one (see `testing::FakeClock`, behind the
+//! `testing` feature) and advance virtual time instantly instead of waiting.
+//!
+//! Waits on I/O (readiness polling, connection draining, stop grace periods)
+//! and health check latency stay on real time: they measure how long a real
+//! backend takes to respond.
+
+use async_trait::async_trait;
+use std::time::{Duration, Instant};
+
+/// A source of monotonic time
+#[async_trait]
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    /// Current instant
+    fn now(&self) -> Instant;
+
+    /// Wait until `duration` has passed on this clock
+    async fn sleep(&self, duration: Duration);
+
+    /// Time elapsed since `earlier` (zero if `earlier` is in the future)
+    fn since(&self, earlier: Instant) -> Duration {
+        self.now().saturating_duration_since(earlier)
+    }
+}
+
+/// The real clock: `Instant::now()` and `tokio::time::sleep`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
@@ -0,0 +1,531 @@
+//! Structured diff between two configs
+//!
+//! Used to preview what a config reload would change (services added,
+//! removed, or modified, settings changed) before anything is applied, and
+//! to report running instances spawned from an out-of-date definition.
+
+use crate::config::{Config, ProcessConfig};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+/// A single changed field, with old and new values rendered as strings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldChange {
+    /// Dotted field path (e.g. "idle_timeout", "tls.enabled")
+    pub field: String,
+    /// Previous value ("(none)" if unset)
+    pub old: String,
+    /// New value ("(none)" if unset)
+    pub new: String,
+}
+
+/// Changes to a single service definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceDiff {
+    pub name: String,
+    pub changes: Vec<FieldChange>,
+}
+
+/// Difference between the running config and a candidate config
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigDiff {
+    /// Services only present in the new config
+    pub services_added: Vec<String>,
+    /// Services only present in the old config
+    pub services_removed: Vec<String>,
+    /// Services present in both with at least one changed field
+    pub services_modified: Vec<ServiceDiff>,
+    /// Changed [settings] fields
+    pub settings_changed: Vec<FieldChange>,
+    /// Changed [routing] fields
+    pub routing_changed: Vec<FieldChange>,
+    /// Auto-spawn entries ("service:id") added to [instances]
+    pub instances_added: Vec<String>,
+    /// Auto-spawn entries ("service:id") removed from [instances]
+    pub instances_removed: Vec<String>,
+    /// Experiments added, removed, or changed
+    #[serde(default)]
+    pub experiments_changed: Vec<String>,
+    /// Alert rules added, removed, or changed
+    #[serde(default)]
+    pub alerts_changed: Vec<String>,
+    /// Changed [dns] fields
+    #[serde(default)]
+    pub dns_changed: Vec<FieldChange>,
+}
+
+/// Why a running instance no longer matches the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftReason {
+    /// Its service definition changed since it was spawned
+    Modified,
+    /// Its service is no longer configured
+    Removed,
+    /// It was re-adopted from state that didn't record its definition
+    Unknown,
+}
+
+/// A running instance that needs a restart to pick up the config
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceDrift {
+    /// Instance ("process:id")
+    pub instance: String,
+    pub reason: DriftReason,
+    /// Fields changed since spawn (only for `Modified`)
+    pub changes: Vec<FieldChange>,
+}
+
+impl ConfigDiff {
+    /// Field-level changes from one service definition to another
+    pub fn service_changes(old: &ProcessConfig, new: &ProcessConfig) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+        diff_values("", &to_value(old), &to_value(new), &mut changes);
+        changes
+    }
+
+    /// Compute the diff from `old` to `new`
+    pub fn between(old: &Config, new: &Config) -> Self {
+        let old_names: BTreeSet<&String> = old.service.keys().collect();
+        let new_names: BTreeSet<&String> = new.service.keys().collect();
+
+        let services_added = new_names
+            .difference(&old_names)
+            .map(|s| s.to_string())
+            .collect();
+        let services_removed = old_names
+            .difference(&new_names)
+            .map(|s| s.to_string())
+            .collect();
+
+        let mut services_modified = Vec::new();
+        for name in old_names.intersection(&new_names) {
+            let changes = Self::service_changes(&old.service[*name], &new.service[*name]);
+            if !changes.is_empty() {
+                services_modified.push(ServiceDiff {
+                    name: name.to_string(),
+                    changes,
+                });
+            }
+        }
+
+        let mut settings_changed = Vec::new();
+        diff_values(
+            "",
+            &to_value(&old.settings),
+            &to_value(&new.settings),
+            &mut settings_changed,
+        );
+
+        let mut routing_changed = Vec::new();
+        diff_values(
+            "",
+            &to_value(&old.routing),
+            &to_value(&new.routing),
+            &mut routing_changed,
+        );
+
+        let experiment_names: BTreeSet<&String> =
+            old.experiment.keys().chain(new.experiment.keys()).collect();
+        let experiments_changed = experiment_names
+            .into_iter()
+            .filter(|name| old.experiment.get(*name) != new.experiment.get(*name))
+            .map(|name| name.to_string())
+            .collect();
+
+        let alert_names: BTreeSet<&String> = old.alert.keys().chain(new.alert.keys()).collect();
+        let alerts_changed = alert_names
+            .into_iter()
+            .filter(|name| old.alert.get(*name) != new.alert.get(*name))
+            .map(|name| name.to_string())
+            .collect();
+
+        // Adding or removing [dns] lists each of its fields
+        let dns_value = |config: &Config| match &config.dns {
+            Some(dns) => to_value(dns),
+            None => Value::Object(Default::default()),
+        };
+        let mut dns_changed = Vec::new();
+        diff_values("", &dns_value(old), &dns_value(new), &mut dns_changed);
+
+        let old_instances = instance_set(old);
+        let new_instances = instance_set(new);
+
+        Self {
+            services_added,
+            services_removed,
+            services_modified,
+            settings_changed,
+            routing_changed,
+            instances_added: new_instances.difference(&old_instances).cloned().collect(),
+            instances_removed: old_instances.difference(&new_instances).cloned().collect(),
+            experiments_changed,
+            alerts_changed,
+            dns_changed,
+        }
+    }
+
+    /// True if the two configs are equivalent
+    pub fn is_empty(&self) -> bool {
+        self.services_added.is_empty()
+            && self.services_removed.is_empty()
+            && self.services_modified.is_empty()
+            && self.settings_changed.is_empty()
+            && self.routing_changed.is_empty()
+            && self.instances_added.is_empty()
+            && self.instances_removed.is_empty()
+            && self.experiments_changed.is_empty()
+            && self.alerts_changed.is_empty()
+            && self.dns_changed.is_empty()
+    }
+
+    /// Services whose running instances would be affected by this diff
+    /// (removed or modified services).
+    pub fn affected_services(&self) -> Vec<&str> {
+        self.services_removed
+            .iter()
+            .map(|s| s.as_str())
+            .chain(self.services_modified.iter().map(|s| s.name.as_str()))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes");
+        }
+        for name in &self.services_added {
+            writeln!(f, "+ service.{}", name)?;
+        }
+        for name in &self.services_removed {
+            writeln!(f, "- service.{}", name)?;
+        }
+        for service in &self.services_modified {
+            writeln!(f, "~ service.{}", service.name)?;
+            for change in &service.changes {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        if !self.settings_changed.is_empty() {
+            writeln!(f, "~ settings")?;
+            for change in &self.settings_changed {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        if !self.routing_changed.is_empty() {
+            writeln!(f, "~ routing")?;
+            for change in &self.routing_changed {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        for instance in &self.instances_added {
+            writeln!(f, "+ instances.{}", instance)?;
+        }
+        for instance in &self.instances_removed {
+            writeln!(f, "- instances.{}", instance)?;
+        }
+        for name in &self.experiments_changed {
+            writeln!(f, "~ experiment.{}", name)?;
+        }
+        for name in &self.alerts_changed {
+            writeln!(f, "~ alert.{}", name)?;
+        }
+        if !self.dns_changed.is_empty() {
+            writeln!(f, "~ dns")?;
+            for change in &self.dns_changed {
+                writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for InstanceDrift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.reason {
+            DriftReason::Modified => {
+                writeln!(f, "~ {}", self.instance)?;
+                for change in &self.changes {
+                    writeln!(f, "    {}: {} -> {}", change.field, change.old, change.new)?;
+                }
+                Ok(())
+            }
+            DriftReason::Removed => writeln!(f, "- {} (service removed)", self.instance),
+            DriftReason::Unknown => writeln!(f, "? {} (spawn config not recorded)", self.instance),
+        }
+    }
+}
+
+fn to_value<T: Serialize>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+fn instance_set(config: &Config) -> BTreeSet<String> {
+    config
+        .get_instances_to_spawn()
+        .into_iter()
+        .map(|(service, id)| format!("{}:{}", service, id))
+        .collect()
+}
+
+/// Recursively compare two JSON values, recording leaf differences.
+/// Objects are walked field by field; everything else is compared whole.
+fn diff_values(prefix: &str, old: &Value, new: &Value, out: &mut Vec<FieldChange>) {
+    match (old, new) {
+        (Value::Object(old_map), Value::Object(new_map)) => {
+            let keys: BTreeSet<&String> = old_map.keys().chain(new_map.keys()).collect();
+            for key in keys {
+                let path = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                diff_values(
+                    &path,
+                    old_map.get(key).unwrap_or(&Value::Null),
+                    new_map.get(key).unwrap_or(&Value::Null),
+                    out,
+                );
+            }
+        }
+        _ if old != new => out.push(FieldChange {
+            field: prefix.to_string(),
+            old: render(old),
+            new: render(new),
+        }),
+        _ => {}
+    }
+}
+
+fn render(value: &Value) -> String {
+    match value {
+        Value::Null => "(none)".to_string(),
+        Value::String(s) => format!("{:?}", s),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Config {
+        Config::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_diff_identical_configs_is_empty() {
+        let config = parse(
+            r#"
+[service.api]
+command = "./api"
+"#,
+        );
+        let diff = ConfigDiff::between(&config, &config.clone());
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "No changes\n");
+    }
+
+    #[test]
+    fn test_diff_services_added_and_removed() {
+        let old = parse(
+            r#"
+[service.api]
+command = "./api"
+
+[service.worker]
+command = "./worker"
+"#,
+        );
+        let new = parse(
+            r#"
+[service.api]
+command = "./api"
+
+[service.web]
+command = "./web"
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.services_added, vec!["web"]);
+        assert_eq!(diff.services_removed, vec!["worker"]);
+        assert!(diff.services_modified.is_empty());
+        assert_eq!(diff.affected_services(), vec!["worker"]);
+    }
+
+    #[test]
+    fn test_diff_service_modified_fields() {
+        let old = parse(
+            r#"
+[service.api]
+command = "./api"
+idle_timeout = 300
+"#,
+        );
+        let new = parse(
+            r#"
+[service.api]
+command = "./api --fast"
+
+[service.api.env]
+LOG_LEVEL = "debug"
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.services_modified.len(), 1);
+        let api = &diff.services_modified[0];
+        assert_eq!(api.name, "api");
+
+        let fields: Vec<&str> = api.changes.iter().map(|c| c.field.as_str()).collect();
+        assert_eq!(fields, vec!["command", "env.LOG_LEVEL", "idle_timeout"]);
+
+        let idle = &api.changes[2];
+        assert_eq!(idle.old, "300");
+        assert_eq!(idle.new, "(none)");
+        assert_eq!(diff.affected_services(), vec!["api"]);
+    }
+
+    #[test]
+    fn test_diff_settings_and_nested_tls() {
+        let old = parse(
+            r#"
+[settings]
+health_check_interval = 10
+"#,
+        );
+        let new = parse(
+            r#"
+[settings]
+health_check_interval = 30
+
+[settings.tls]
+enabled = true
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        let fields: Vec<&str> = diff
+            .settings_changed
+            .iter()
+            .map(|c| c.field.as_str())
+            .collect();
+        assert_eq!(fields, vec!["health_check_interval", "tls.enabled"]);
+        assert!(diff.affected_services().is_empty());
+    }
+
+    #[test]
+    fn test_diff_instances_section() {
+        let old = parse(
+            r#"
+[service.api]
+command = "./api"
+
+[instances]
+api = ["prod"]
+"#,
+        );
+        let new = parse(
+            r#"
+[service.api]
+command = "./api"
+
+[instances]
+api = ["prod", "staging"]
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.instances_added, vec!["api:staging"]);
+        assert!(diff.instances_removed.is_empty());
+        assert!(diff.to_string().contains("+ instances.api:staging"));
+    }
+
+    #[test]
+    fn test_diff_experiment_changed() {
+        let old = parse(
+            r#"
+[service.api]
+command = "./api"
+
+[experiment.checkout]
+service = "api"
+client_id = "header:x-user-id"
+
+[experiment.checkout.groups.control]
+instances = ["v1"]
+percent = 100
+"#,
+        );
+        let mut new = old.clone();
+        assert!(ConfigDiff::between(&old, &new).is_empty());
+
+        new.experiment.clear();
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.experiments_changed, vec!["checkout"]);
+        assert!(diff.to_string().contains("~ experiment.checkout"));
+    }
+
+    #[test]
+    fn test_diff_alert_changed() {
+        let old = parse(
+            r#"
+[alert.panics]
+pattern = "panic"
+"#,
+        );
+        let new = parse(
+            r#"
+[alert.panics]
+pattern = "panic|OOM"
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(diff.alerts_changed, vec!["panics"]);
+        assert!(diff.to_string().contains("~ alert.panics"));
+    }
+
+    #[test]
+    fn test_diff_dns_changed() {
+        let dns = r#"
+[dns]
+provider = "cloudflare"
+domain = "example.com"
+target = "203.0.113.10"
+zone = "abc"
+"#;
+        let diff = ConfigDiff::between(&parse(""), &parse(dns));
+        assert!(diff
+            .dns_changed
+            .iter()
+            .any(|c| c.field == "domain" && c.old == "(none)" && c.new == "\"example.com\""));
+
+        let diff = ConfigDiff::between(
+            &parse(dns),
+            &parse(&dns.replace("203.0.113.10", "203.0.113.11")),
+        );
+        assert_eq!(diff.dns_changed.len(), 1);
+        assert_eq!(diff.dns_changed[0].field, "target");
+        assert!(diff.to_string().contains("~ dns\n    target:"));
+    }
+
+    #[test]
+    fn test_diff_display_format() {
+        let old = parse(
+            r#"
+[service.api]
+command = "./api"
+"#,
+        );
+        let new = parse(
+            r#"
+[service.api]
+command = "./api2"
+"#,
+        );
+        let diff = ConfigDiff::between(&old, &new);
+        assert_eq!(
+            diff.to_string(),
+            "~ service.api\n    command: \"./api\" -> \"./api2\"\n"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! CORS policy for hosted services
+//!
+//! A service with a `[service.X.cors]` table has CORS handled by the proxy:
+//! preflight `OPTIONS` requests are answered without reaching an instance,
+//! and other responses get `Access-Control-Allow-Origin` and friends for
+//! allowed origins.
+//!
+//! ```toml
+//! [service.api.cors]
+//! allow_origins = ["https://app.example.com"]   # or ["*"]
+//! allow_methods = ["GET", "POST"]               # default: GET, HEAD, POST, PUT, PATCH, DELETE
+//! allow_headers = ["Content-Type", "Authorization"]  # default: whatever the preflight asks for
+//! expose_headers = ["X-Request-Id"]
+//! allow_credentials = true
+//! max_age = "1h"
+//! ```
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// CORS policy for a service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins (`scheme://host[:port]`) allowed to make requests, or `"*"`
+    pub allow_origins: Vec<String>,
+
+    /// Methods allowed in preflights
+    #[serde(default = "default_allow_methods")]
+    pub allow_methods: Vec<String>,
+
+    /// Request headers allowed in preflights, or `"*"`. Empty allows the
+    /// headers each preflight asks for.
+    #[serde(default)]
+    pub allow_headers: Vec<String>,
+
+    /// Response headers scripts may read
+    #[serde(default)]
+    pub expose_headers: Vec<String>,
+
+    /// Allow cookies and HTTP auth (can't be combined with `"*"`)
+    #[serde(default)]
+    pub allow_credentials: bool,
+
+    /// How long browsers may cache a preflight, in seconds
+    #[serde(default, deserialize_with = "crate::config::deserialize_opt_duration")]
+    pub max_age: Option<u64>,
+}
+
+fn default_allow_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// Whether `s` is an HTTP token (a valid method or header name)
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c))
+}
+
+impl CorsConfig {
+    /// Check origins, methods and headers are well-formed
+    pub fn validate(&self, service: &str) -> Result<()> {
+        if self.allow_origins.is_empty() {
+            anyhow::bail!("Service '{}' cors.allow_origins is empty", service);
+        }
+        for origin in &self.allow_origins {
+            if origin == "*" {
+                continue;
+            }
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"));
+            if !host.is_some_and(|h| !h.is_empty() && !h.contains('/')) {
+                anyhow::bail!(
+                    "Service '{}' cors origin '{}' must be \"*\" or scheme://host[:port]",
+                    service,
+                    origin
+                );
+            }
+        }
+        for method in &self.allow_methods {
+            if !is_token(method) || method == "*" {
+                anyhow::bail!("Service '{}' cors method '{}' is invalid", service, method);
+            }
+        }
+        for header in self.allow_headers.iter().chain(&self.expose_headers) {
+            if !is_token(header) {
+                anyhow::bail!("Service '{}' cors header '{}' is invalid", service, header);
+            }
+        }
+        if self.allow_credentials {
+            let wildcard = self
+                .allow_origins
+                .iter()
+                .chain(&self.allow_headers)
+                .chain(&self.expose_headers)
+                .any(|v| v == "*");
+            if wildcard {
+                anyhow::bail!(
+                    "Service '{}' sets cors.allow_credentials, which browsers reject with \"*\"",
+                    service
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cors(origins: &[&str]) -> CorsConfig {
+        CorsConfig {
+            allow_origins: origins.iter().map(|s| s.to_string()).collect(),
+            allow_methods: default_allow_methods(),
+            allow_headers: Vec::new(),
+            expose_headers: Vec::new(),
+            allow_credentials: false,
+            max_age: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(cors(&["*"]).validate("api").is_ok());
+        assert!(cors(&["https://app.example.com", "http://localhost:3000"])
+            .validate("api")
+            .is_ok());
+        assert!(cors(&[]).validate("api").is_err());
+        assert!(cors(&["app.example.com"]).validate("api").is_err());
+        assert!(cors(&["https://app.example.com/"]).validate("api").is_err());
+
+        let mut bad_method = cors(&["*"]);
+        bad_method.allow_methods = vec!["GET POST".to_string()];
+        assert!(bad_method.validate("api").is_err());
+
+        let mut credentials = cors(&["https://app.example.com"]);
+        credentials.allow_credentials = true;
+        assert!(credentials.validate("api").is_ok());
+        credentials.allow_headers = vec!["*".to_string()];
+        assert!(credentials.validate("api").is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! Disk-full protection for the data dir
+//!
+//! Logs are the one thing tenement writes without bound, so when the data
+//! dir's filesystem gets past `settings.disk_full_percent` (space or inodes),
+//! log persistence to SQLite pauses until usage drops back below it. The
+//! in-memory log buffer, live streams and alert rules keep working. Crossing
+//! the threshold emits a `disk_full` alert, and with
+//! `settings.disk_full_log_retention` set, persisted logs older than that are
+//! deleted on every check while the disk stays full.
+
+use crate::alerts::{AlertEngine, AlertEvent};
+use crate::config::Config;
+use crate::host::HostStats;
+use crate::store::LogStore;
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, info, warn};
+
+/// Rule name of the alert emitted when the disk fills up
+pub const DISK_FULL_ALERT: &str = "disk_full";
+
+/// Watches the data dir's filesystem and pauses log persistence when full
+pub struct DiskGuard {
+    data_dir: PathBuf,
+    /// 0 = never pause
+    threshold_percent: u8,
+    log_retention: Option<Duration>,
+    log_store: Arc<LogStore>,
+    alerts: Option<Arc<AlertEngine>>,
+}
+
+impl DiskGuard {
+    pub fn new(
+        config: &Config,
+        log_store: Arc<LogStore>,
+        alerts: Option<Arc<AlertEngine>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            data_dir: config.settings.data_dir.clone(),
+            threshold_percent: config.settings.disk_full_percent,
+            log_retention: config
+                .settings
+                .disk_full_log_retention
+                .map(Duration::from_secs),
+            log_store,
+            alerts,
+        })
+    }
+
+    /// Measure the data dir's filesystem and pause or resume persistence
+    pub async fn check(&self) -> Result<()> {
+        if self.threshold_percent == 0 {
+            return Ok(());
+        }
+        let stats = HostStats::collect(&self.data_dir).await?;
+        let disk = stats
+            .disks
+            .first()
+            .context("No filesystem found for the data dir")?;
+        self.apply(disk.used_percent()).await;
+        Ok(())
+    }
+
+    /// Act on the data dir's filesystem being `used_percent` full
+    pub async fn apply(&self, used_percent: f64) {
+        if self.threshold_percent == 0 {
+            return;
+        }
+        let full = used_percent >= f64::from(self.threshold_percent);
+        let was_paused = self.log_store.set_paused(full);
+
+        if full && !was_paused {
+            let message = format!(
+                "data dir filesystem is {:.1}% full (threshold {}%), log persistence paused",
+                used_percent, self.threshold_percent
+            );
+            match self.alerts {
+                Some(ref alerts) => alerts.emit(AlertEvent {
+                    rule: DISK_FULL_ALERT.to_string(),
+                    process: "tenement".to_string(),
+                    instance_id: String::new(),
+                    count: 1,
+                    window_secs: 0,
+                    message,
+                    timestamp: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_millis() as u64,
+                    webhook: None,
+                }),
+                None => error!("{}", message),
+            }
+        } else if !full && was_paused {
+            info!(
+                "Data dir filesystem is {:.1}% full, log persistence resumed",
+                used_percent
+            );
+        }
+
+        if let (true, Some(retention)) = (full, self.log_retention) {
+            match self.log_store.rotate(retention).await {
+                Ok(0) => {}
+                Ok(deleted) => warn!(
+                    "Disk full: deleted {} persisted log line(s) older than {:?}",
+                    deleted, retention
+                ),
+                Err(e) => error!("Disk full: failed to rotate logs: {}", e),
+            }
+        }
+    }
+
+    /// Check now and then every `interval`
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        if self.threshold_percent == 0 {
+            return;
+        }
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.check().await {
+                    warn!("Disk usage check failed: {:#}", e);
+                }
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logs::{LogEntry, LogLevel};
+    use std::collections::HashMap;
+
+    async fn wait_for_count(store: &LogStore, expected: i64) {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while store.count().await.unwrap() != expected {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "timed out waiting for {} log rows",
+                expected
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pauses_and_resumes_log_persistence() {
+        let pool = crate::store::init_memory_db().await.unwrap();
+        let log_store = LogStore::new(pool);
+        let alerts = AlertEngine::new(&HashMap::new());
+        let mut config = Config::default();
+        config.settings.disk_full_percent = 90;
+        config.settings.disk_full_log_retention = Some(3600);
+        let guard = DiskGuard::new(&config, log_store.clone(), Some(alerts.clone()));
+
+        let mut old = LogEntry::new("api", "a", LogLevel::Stdout, "old".to_string());
+        old.timestamp -= 2 * 3600 * 1000;
+        log_store.push(old).await;
+        log_store
+            .push(LogEntry::new(
+                "api",
+                "a",
+                LogLevel::Stdout,
+                "new".to_string(),
+            ))
+            .await;
+        wait_for_count(&log_store, 2).await;
+
+        guard.apply(89.9).await;
+        assert!(!log_store.is_paused());
+        assert!(alerts.recent().is_empty());
+
+        // Over the threshold: paused, alerted once, old lines rotated away
+        guard.apply(93.0).await;
+        guard.apply(95.0).await;
+        assert!(log_store.is_paused());
+        let fired = alerts.recent();
+        assert_eq!(fired.len(), 1);
+        assert_eq!(fired[0].rule, DISK_FULL_ALERT);
+        assert!(fired[0].message.contains("93.0% full"));
+        assert_eq!(log_store.count().await.unwrap(), 1);
+
+        log_store
+            .push(LogEntry::new(
+                "api",
+                "a",
+                LogLevel::Stdout,
+                "dropped".to_string(),
+            ))
+            .await;
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(log_store.count().await.unwrap(), 1);
+
+        // Back under it: persisted again
+        guard.apply(80.0).await;
+        assert!(!log_store.is_paused());
+        log_store
+            .push(LogEntry::new(
+                "api",
+                "a",
+                LogLevel::Stdout,
+                "kept".to_string(),
+            ))
+            .await;
+        wait_for_count(&log_store, 2).await;
+    }
+
+    #[tokio::test]
+    async fn test_zero_threshold_never_pauses() {
+        let pool = crate::store::init_memory_db().await.unwrap();
+        let log_store = LogStore::new(pool);
+        let mut config = Config::default();
+        config.settings.disk_full_percent = 0;
+        let guard = DiskGuard::new(&config, log_store.clone(), None);
+
+        guard.apply(100.0).await;
+        assert!(!log_store.is_paused());
+        guard.check().await.unwrap();
+    }
+}
@@ -0,0 +1,947 @@
+//! Per-instance DNS records
+//!
+//! For DNS setups that can't serve a single `*.{process}.{domain}` wildcard,
+//! a `[dns]` table gives every instance its own `{id}.{process}.{domain}`
+//! record when it is spawned and removes it when the instance is stopped.
+//!
+//! ```toml
+//! [dns]
+//! provider = "cloudflare"                    # or "route53", "command"
+//! domain = "example.com"
+//! target = "203.0.113.10"                    # IPv4 -> A, IPv6 -> AAAA, name -> CNAME
+//! zone = "023e105f4ecef8ad9ca31a8372d0c353"  # Cloudflare zone ID / Route53 hosted zone ID
+//! ttl = 300                                  # default
+//! token_env = "CLOUDFLARE_API_TOKEN"         # default; cloudflare only
+//! ```
+//!
+//! `provider = "cloudflare"` calls the Cloudflare API with `curl`; the token
+//! is read from `token_env` and handed to curl on stdin (never on the command
+//! line). `provider = "route53"` runs `aws route53
+//! change-resource-record-sets`, with credentials from the usual AWS
+//! environment or profile. `provider = "command"` runs `register` and
+//! `unregister` through `sh -c` instead, with `TENEMENT_DNS_NAME`,
+//! `TENEMENT_DNS_TARGET`, `TENEMENT_SERVICE` and `TENEMENT_TENANT` in their
+//! environment. Embedders can plug in their own [`DnsProvider`] with
+//! [`Hypervisor::with_dns`](crate::Hypervisor::with_dns).
+//!
+//! Changes are applied in order by a background task, so spawning never
+//! waits on the DNS API and a record removed by a stop is never re-created
+//! by a slow earlier registration. Failures are logged; they don't fail the
+//! spawn or stop. Warm instances get a record once they are claimed.
+//!
+//! The same providers publish ACME DNS-01 challenge records for wildcard
+//! certificates, configured under `[settings.tls.dns]` (see
+//! [`ChallengeDnsConfig`]).
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+
+/// Where records are registered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsProviderKind {
+    Cloudflare,
+    Route53,
+    /// Custom register/unregister hooks
+    Command,
+}
+
+/// `[dns]` table
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsConfig {
+    pub provider: DnsProviderKind,
+
+    /// Records are `{id}.{process}.{domain}`
+    pub domain: String,
+
+    /// Record value: an IP address (A/AAAA) or a host name (CNAME).
+    /// Required for cloudflare/route53.
+    #[serde(default)]
+    pub target: Option<String>,
+
+    /// Cloudflare zone ID or Route53 hosted zone ID
+    #[serde(default)]
+    pub zone: Option<String>,
+
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+
+    /// Environment variable holding the Cloudflare API token
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+
+    /// Hook run to create a record (provider = "command")
+    #[serde(default)]
+    pub register: Option<String>,
+
+    /// Hook run to remove a record (provider = "command")
+    #[serde(default)]
+    pub unregister: Option<String>,
+}
+
+fn default_ttl() -> u32 {
+    300
+}
+
+fn default_token_env() -> String {
+    "CLOUDFLARE_API_TOKEN".to_string()
+}
+
+impl DnsConfig {
+    /// Check required fields for the configured provider
+    pub fn validate(&self) -> Result<()> {
+        if self.domain.trim_matches('.').is_empty() {
+            anyhow::bail!("dns.domain must not be empty");
+        }
+        match self.provider {
+            DnsProviderKind::Cloudflare | DnsProviderKind::Route53 => {
+                if self.target.as_deref().unwrap_or("").is_empty() {
+                    anyhow::bail!(
+                        "dns.target is required for the cloudflare and route53 providers"
+                    );
+                }
+                if self.zone.as_deref().unwrap_or("").is_empty() {
+                    anyhow::bail!("dns.zone is required for the cloudflare and route53 providers");
+                }
+            }
+            DnsProviderKind::Command => {
+                for (field, hook) in [
+                    ("register", &self.register),
+                    ("unregister", &self.unregister),
+                ] {
+                    if hook.as_deref().unwrap_or("").trim().is_empty() {
+                        anyhow::bail!("dns.{} is required for provider = \"command\"", field);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record type for `target`
+    pub fn record_type(&self) -> &'static str {
+        match self.target.as_deref().map(str::parse::<IpAddr>) {
+            Some(Ok(IpAddr::V4(_))) => "A",
+            Some(Ok(IpAddr::V6(_))) => "AAAA",
+            _ => "CNAME",
+        }
+    }
+}
+
+/// `[settings.tls.dns]` table: where ACME DNS-01 challenge records go
+///
+/// ```toml
+/// [settings.tls.dns]
+/// provider = "cloudflare"                    # or "route53", "command"
+/// zone = "023e105f4ecef8ad9ca31a8372d0c353"  # Cloudflare zone ID / Route53 hosted zone ID
+/// token_env = "CLOUDFLARE_API_TOKEN"         # default; cloudflare only
+/// propagation_delay = "30s"                  # default
+/// ```
+///
+/// `provider = "command"` runs `present` and `cleanup` through `sh -c`
+/// instead, once per record value, with `TENEMENT_DNS_NAME` and
+/// `TENEMENT_DNS_VALUE` in their environment.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChallengeDnsConfig {
+    pub provider: DnsProviderKind,
+
+    /// Cloudflare zone ID or Route53 hosted zone ID
+    #[serde(default)]
+    pub zone: Option<String>,
+
+    /// Environment variable holding the Cloudflare API token
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+
+    /// Hook run to publish a TXT record (provider = "command")
+    #[serde(default)]
+    pub present: Option<String>,
+
+    /// Hook run to remove it again (provider = "command")
+    #[serde(default)]
+    pub cleanup: Option<String>,
+
+    /// How long to wait after publishing records, for them to reach the
+    /// zone's nameservers, before asking for validation
+    #[serde(
+        default = "default_propagation_delay",
+        deserialize_with = "crate::config::deserialize_duration"
+    )]
+    pub propagation_delay: u64,
+}
+
+fn default_propagation_delay() -> u64 {
+    30
+}
+
+impl ChallengeDnsConfig {
+    /// Check required fields for the configured provider
+    pub fn validate(&self) -> Result<()> {
+        match self.provider {
+            DnsProviderKind::Cloudflare | DnsProviderKind::Route53 => {
+                if self.zone.as_deref().unwrap_or("").is_empty() {
+                    anyhow::bail!(
+                        "settings.tls.dns.zone is required for the cloudflare and route53 providers"
+                    );
+                }
+            }
+            DnsProviderKind::Command => {
+                for (field, hook) in [("present", &self.present), ("cleanup", &self.cleanup)] {
+                    if hook.as_deref().unwrap_or("").trim().is_empty() {
+                        anyhow::bail!(
+                            "settings.tls.dns.{} is required for provider = \"command\"",
+                            field
+                        );
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// As a `[dns]` table, to drive the provider's API the same way
+    fn as_dns_config(&self) -> DnsConfig {
+        DnsConfig {
+            provider: self.provider,
+            domain: String::new(),
+            target: None,
+            zone: self.zone.clone(),
+            ttl: CHALLENGE_TTL,
+            token_env: self.token_env.clone(),
+            register: self.present.clone(),
+            unregister: self.cleanup.clone(),
+        }
+    }
+}
+
+/// TTL of challenge records, short so retries aren't served stale values
+const CHALLENGE_TTL: u32 = 60;
+
+/// Name of the TXT record proving control of `domain` for DNS-01. Wildcard
+/// names are validated on their parent domain.
+pub fn challenge_record_name(domain: &str) -> String {
+    format!(
+        "_acme-challenge.{}",
+        domain.strip_prefix("*.").unwrap_or(domain)
+    )
+}
+
+/// A record for one instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    /// Fully qualified name, `{id}.{process}.{domain}`
+    pub name: String,
+    pub process: String,
+    pub id: String,
+}
+
+impl DnsRecord {
+    pub fn new(domain: &str, process: &str, id: &str) -> Self {
+        Self {
+            name: format!("{}.{}.{}", id, process, domain.trim_matches('.')).to_lowercase(),
+            process: process.to_string(),
+            id: id.to_string(),
+        }
+    }
+}
+
+/// Creates and removes instance records in an external DNS service.
+/// Both calls should be idempotent: registering an existing record or
+/// removing a missing one is not an error.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    async fn register(&self, record: &DnsRecord) -> Result<()>;
+    async fn unregister(&self, record: &DnsRecord) -> Result<()>;
+}
+
+/// The built-in provider for `config.provider`
+pub fn provider(config: &DnsConfig) -> Arc<dyn DnsProvider> {
+    match config.provider {
+        DnsProviderKind::Cloudflare => Arc::new(Cloudflare(config.clone())),
+        DnsProviderKind::Route53 => Arc::new(Route53(config.clone())),
+        DnsProviderKind::Command => Arc::new(CommandHooks(config.clone())),
+    }
+}
+
+/// Publishes and removes the TXT records ACME DNS-01 challenges look for
+#[async_trait]
+pub trait ChallengeRecords: Send + Sync {
+    /// Publish `values` as the TXT records at `name`
+    async fn publish(&self, name: &str, values: &[String]) -> Result<()>;
+    /// Remove the records [`publish`](Self::publish) created
+    async fn remove(&self, name: &str, values: &[String]) -> Result<()>;
+}
+
+/// The built-in challenge publisher for `config.provider`
+pub fn challenge_records(config: &ChallengeDnsConfig) -> Arc<dyn ChallengeRecords> {
+    let dns = config.as_dns_config();
+    match config.provider {
+        DnsProviderKind::Cloudflare => Arc::new(Cloudflare(dns)),
+        DnsProviderKind::Route53 => Arc::new(Route53(dns)),
+        DnsProviderKind::Command => Arc::new(CommandHooks(dns)),
+    }
+}
+
+enum Change {
+    Register(DnsRecord),
+    Unregister(DnsRecord),
+}
+
+/// Queues record changes and applies them in order on a background task
+pub struct DnsRegistrar {
+    domain: String,
+    provider: Arc<dyn DnsProvider>,
+    /// Names registered by this daemon, so respawns don't re-register
+    registered: Mutex<HashSet<String>>,
+    /// Started on first use, so the registrar can be built outside a runtime
+    tx: OnceLock<mpsc::UnboundedSender<Change>>,
+}
+
+impl DnsRegistrar {
+    pub fn new(domain: &str, provider: Arc<dyn DnsProvider>) -> Arc<Self> {
+        Arc::new(Self {
+            domain: domain.to_string(),
+            provider,
+            registered: Mutex::new(HashSet::new()),
+            tx: OnceLock::new(),
+        })
+    }
+
+    /// Registrar for a `[dns]` table, using its built-in provider
+    pub fn from_config(config: &DnsConfig) -> Arc<Self> {
+        Self::new(&config.domain, provider(config))
+    }
+
+    /// Record for `process:id`
+    pub fn record(&self, process: &str, id: &str) -> DnsRecord {
+        DnsRecord::new(&self.domain, process, id)
+    }
+
+    /// Queue a record for `process:id`, unless this daemon already did
+    pub fn register(&self, process: &str, id: &str) {
+        let record = self.record(process, id);
+        if self.registered.lock().unwrap().insert(record.name.clone()) {
+            self.send(Change::Register(record));
+        }
+    }
+
+    /// Queue removal of the record for `process:id`. Always sent, since the
+    /// record may have been registered before a daemon restart.
+    pub fn unregister(&self, process: &str, id: &str) {
+        let record = self.record(process, id);
+        self.registered.lock().unwrap().remove(&record.name);
+        self.send(Change::Unregister(record));
+    }
+
+    fn send(&self, change: Change) {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let provider = self.provider.clone();
+            tokio::spawn(async move {
+                while let Some(change) = rx.recv().await {
+                    match change {
+                        Change::Register(record) => match provider.register(&record).await {
+                            Ok(()) => info!("Registered DNS record {}", record.name),
+                            Err(e) => {
+                                warn!("Failed to register DNS record {}: {:#}", record.name, e)
+                            }
+                        },
+                        Change::Unregister(record) => match provider.unregister(&record).await {
+                            Ok(()) => info!("Removed DNS record {}", record.name),
+                            Err(e) => {
+                                warn!("Failed to remove DNS record {}: {:#}", record.name, e)
+                            }
+                        },
+                    }
+                }
+            });
+            tx
+        });
+        let _ = tx.send(change);
+    }
+}
+
+struct Cloudflare(DnsConfig);
+
+impl Cloudflare {
+    fn records_url(&self) -> String {
+        format!(
+            "{}/zones/{}/dns_records",
+            CLOUDFLARE_API,
+            self.0.zone.as_deref().unwrap_or("")
+        )
+    }
+
+    /// IDs of the records named `name` (any type, since a changed target can
+    /// change A to CNAME)
+    async fn existing(&self, name: &str) -> Result<Vec<(String, String)>> {
+        let result = self
+            .request(
+                "GET",
+                &format!("{}?name={}", self.records_url(), name),
+                None,
+            )
+            .await?;
+        Ok(result
+            .as_array()
+            .map(|records| {
+                records
+                    .iter()
+                    .filter_map(|r| {
+                        Some((
+                            r["id"].as_str()?.to_string(),
+                            r["type"].as_str().unwrap_or("").to_string(),
+                        ))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Call the API; returns the response's `result`
+    async fn request(&self, method: &str, url: &str, body: Option<&Value>) -> Result<Value> {
+        let token = std::env::var(&self.0.token_env)
+            .with_context(|| format!("Cloudflare API token not set (${})", self.0.token_env))?;
+        let mut args = vec![
+            "-sS".to_string(),
+            "-K".to_string(),
+            "-".to_string(),
+            "-X".to_string(),
+            method.to_string(),
+            url.to_string(),
+        ];
+        if let Some(body) = body {
+            args.extend([
+                "-H".to_string(),
+                "Content-Type: application/json".to_string(),
+                "--data".to_string(),
+                body.to_string(),
+            ]);
+        }
+        let curl_config = format!("header = \"Authorization: Bearer {}\"\n", token);
+        let out = run_with_stdin("curl", &args, &curl_config).await?;
+        let response: Value = serde_json::from_slice(&out)
+            .with_context(|| format!("Unexpected Cloudflare response to {} {}", method, url))?;
+        if response["success"] != json!(true) {
+            anyhow::bail!("Cloudflare API error: {}", response["errors"]);
+        }
+        Ok(response["result"].clone())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Cloudflare {
+    async fn register(&self, record: &DnsRecord) -> Result<()> {
+        let record_type = self.0.record_type();
+        let body = json!({
+            "type": record_type,
+            "name": record.name,
+            "content": self.0.target,
+            "ttl": self.0.ttl,
+            "proxied": false,
+        });
+        let existing = self.existing(&record.name).await?;
+        match existing.iter().find(|(_, t)| t == record_type) {
+            Some((id, _)) => {
+                let url = format!("{}/{}", self.records_url(), id);
+                self.request("PUT", &url, Some(&body)).await?;
+            }
+            None => {
+                // A record of another type would conflict (e.g. CNAME vs A)
+                for (id, _) in &existing {
+                    let url = format!("{}/{}", self.records_url(), id);
+                    self.request("DELETE", &url, None).await?;
+                }
+                self.request("POST", &self.records_url(), Some(&body))
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn unregister(&self, record: &DnsRecord) -> Result<()> {
+        for (id, _) in self.existing(&record.name).await? {
+            let url = format!("{}/{}", self.records_url(), id);
+            self.request("DELETE", &url, None).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChallengeRecords for Cloudflare {
+    async fn publish(&self, name: &str, values: &[String]) -> Result<()> {
+        for value in values {
+            let body = json!({
+                "type": "TXT",
+                "name": name,
+                "content": value,
+                "ttl": self.0.ttl,
+            });
+            self.request("POST", &self.records_url(), Some(&body))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str, _values: &[String]) -> Result<()> {
+        // Nothing else lives at _acme-challenge names
+        for (id, record_type) in self.existing(name).await? {
+            if record_type == "TXT" {
+                let url = format!("{}/{}", self.records_url(), id);
+                self.request("DELETE", &url, None).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Route53(DnsConfig);
+
+impl Route53 {
+    fn change_batch(&self, action: &str, record: &DnsRecord) -> Value {
+        json!({
+            "Comment": format!("tenement {}:{}", record.process, record.id),
+            "Changes": [{
+                "Action": action,
+                "ResourceRecordSet": {
+                    "Name": record.name,
+                    "Type": self.0.record_type(),
+                    "TTL": self.0.ttl,
+                    "ResourceRecords": [{"Value": self.0.target}],
+                },
+            }],
+        })
+    }
+
+    /// Change batch setting the TXT record set `name` to `values`. TXT
+    /// values are quoted, as Route53 expects.
+    fn txt_change_batch(&self, action: &str, name: &str, values: &[String]) -> Value {
+        let records: Vec<Value> = values
+            .iter()
+            .map(|v| json!({"Value": format!("\"{}\"", v)}))
+            .collect();
+        json!({
+            "Comment": "tenement ACME challenge",
+            "Changes": [{
+                "Action": action,
+                "ResourceRecordSet": {
+                    "Name": name,
+                    "Type": "TXT",
+                    "TTL": self.0.ttl,
+                    "ResourceRecords": records,
+                },
+            }],
+        })
+    }
+
+    async fn change(&self, action: &str, record: &DnsRecord) -> Result<()> {
+        self.apply(self.change_batch(action, record)).await
+    }
+
+    async fn apply(&self, batch: Value) -> Result<()> {
+        let out = tokio::process::Command::new("aws")
+            .args(["route53", "change-resource-record-sets", "--hosted-zone-id"])
+            .arg(self.0.zone.as_deref().unwrap_or(""))
+            .arg("--change-batch")
+            .arg(batch.to_string())
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("Failed to run aws (is the AWS CLI installed?)")?;
+        if !out.status.success() {
+            anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for Route53 {
+    async fn register(&self, record: &DnsRecord) -> Result<()> {
+        self.change("UPSERT", record).await
+    }
+
+    async fn unregister(&self, record: &DnsRecord) -> Result<()> {
+        match self.change("DELETE", record).await {
+            // Already gone
+            Err(e) if e.to_string().contains("not found") => Ok(()),
+            result => result,
+        }
+    }
+}
+
+#[async_trait]
+impl ChallengeRecords for Route53 {
+    /// Route53 holds all of a name's TXT values in one record set, so a
+    /// name's values are published together
+    async fn publish(&self, name: &str, values: &[String]) -> Result<()> {
+        self.apply(self.txt_change_batch("UPSERT", name, values))
+            .await
+    }
+
+    async fn remove(&self, name: &str, values: &[String]) -> Result<()> {
+        match self
+            .apply(self.txt_change_batch("DELETE", name, values))
+            .await
+        {
+            Err(e) if e.to_string().contains("not found") => Ok(()),
+            result => result,
+        }
+    }
+}
+
+struct CommandHooks(DnsConfig);
+
+impl CommandHooks {
+    async fn run(&self, hook: &Option<String>, record: &DnsRecord) -> Result<()> {
+        let command = crate::config::shell_placeholders(
+            hook.as_deref().unwrap_or(""),
+            &[("name", "TENEMENT_SERVICE"), ("id", "TENEMENT_TENANT")],
+        );
+        let out = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .env("TENEMENT_DNS_NAME", &record.name)
+            .env(
+                "TENEMENT_DNS_TARGET",
+                self.0.target.as_deref().unwrap_or(""),
+            )
+            .env("TENEMENT_SERVICE", &record.process)
+            .env("TENEMENT_TENANT", &record.id)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .with_context(|| format!("Failed to run DNS hook: {}", command))?;
+        if !out.status.success() {
+            anyhow::bail!(
+                "DNS hook failed ({}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CommandHooks {
+    async fn register(&self, record: &DnsRecord) -> Result<()> {
+        self.run(&self.0.register, record).await
+    }
+
+    async fn unregister(&self, record: &DnsRecord) -> Result<()> {
+        self.run(&self.0.unregister, record).await
+    }
+}
+
+impl CommandHooks {
+    async fn run_challenge(&self, hook: &Option<String>, name: &str, value: &str) -> Result<()> {
+        let command = hook.as_deref().unwrap_or("");
+        let out = tokio::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("TENEMENT_DNS_NAME", name)
+            .env("TENEMENT_DNS_VALUE", value)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .with_context(|| format!("Failed to run DNS hook: {}", command))?;
+        if !out.status.success() {
+            anyhow::bail!(
+                "DNS hook failed ({}): {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ChallengeRecords for CommandHooks {
+    async fn publish(&self, name: &str, values: &[String]) -> Result<()> {
+        for value in values {
+            self.run_challenge(&self.0.register, name, value).await?;
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, name: &str, values: &[String]) -> Result<()> {
+        for value in values {
+            self.run_challenge(&self.0.unregister, name, value).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `program` with `input` on stdin; returns its stdout
+async fn run_with_stdin(program: &str, args: &[String], input: &str) -> Result<Vec<u8>> {
+    let mut child = tokio::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {} (is it installed?)", program))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes()).await?;
+    }
+    let out = child.wait_with_output().await?;
+    if !out.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn cloudflare() -> DnsConfig {
+        DnsConfig {
+            provider: DnsProviderKind::Cloudflare,
+            domain: "example.com".to_string(),
+            target: Some("203.0.113.10".to_string()),
+            zone: Some("abc123".to_string()),
+            ttl: default_ttl(),
+            token_env: default_token_env(),
+            register: None,
+            unregister: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(cloudflare().validate().is_ok());
+
+        let mut config = cloudflare();
+        config.zone = None;
+        assert!(config.validate().unwrap_err().to_string().contains("zone"));
+
+        let mut config = cloudflare();
+        config.provider = DnsProviderKind::Command;
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("dns.register"));
+        config.register = Some("./add.sh".to_string());
+        config.unregister = Some("./remove.sh".to_string());
+        config.target = None;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_record_name_and_type() {
+        let record = DnsRecord::new("Example.com.", "api", "Alice");
+        assert_eq!(record.name, "alice.api.example.com");
+        assert_eq!(
+            (record.process.as_str(), record.id.as_str()),
+            ("api", "Alice")
+        );
+
+        let mut config = cloudflare();
+        assert_eq!(config.record_type(), "A");
+        config.target = Some("2001:db8::1".to_string());
+        assert_eq!(config.record_type(), "AAAA");
+        config.target = Some("lb.example.net".to_string());
+        assert_eq!(config.record_type(), "CNAME");
+    }
+
+    #[test]
+    fn test_route53_change_batch() {
+        let mut config = cloudflare();
+        config.provider = DnsProviderKind::Route53;
+        let batch =
+            Route53(config).change_batch("UPSERT", &DnsRecord::new("example.com", "api", "a"));
+        let change = &batch["Changes"][0];
+        assert_eq!(change["Action"], "UPSERT");
+        assert_eq!(change["ResourceRecordSet"]["Name"], "a.api.example.com");
+        assert_eq!(change["ResourceRecordSet"]["Type"], "A");
+        assert_eq!(change["ResourceRecordSet"]["TTL"], 300);
+        assert_eq!(
+            change["ResourceRecordSet"]["ResourceRecords"][0]["Value"],
+            "203.0.113.10"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_command_hooks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log = dir.path().join("dns.log");
+        let config = DnsConfig {
+            provider: DnsProviderKind::Command,
+            register: Some(format!(
+                "echo \"add $TENEMENT_DNS_NAME $TENEMENT_DNS_TARGET {{name}}\" >> {}",
+                log.display()
+            )),
+            unregister: Some(format!(
+                "echo \"del $TENEMENT_DNS_NAME\" >> {}",
+                log.display()
+            )),
+            ..cloudflare()
+        };
+        let hooks = provider(&config);
+        let record = DnsRecord::new("example.com", "api", "alice");
+        hooks.register(&record).await.unwrap();
+        hooks.unregister(&record).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "add alice.api.example.com 203.0.113.10 api\ndel alice.api.example.com\n"
+        );
+
+        // Placeholders are never parsed by the shell
+        let quoted = DnsConfig {
+            register: Some(format!("echo {{id}} >> {}", log.display())),
+            ..config.clone()
+        };
+        let hostile = DnsRecord::new("example.com", "api", "$(echo pwned)");
+        provider(&quoted).register(&hostile).await.unwrap();
+        assert!(std::fs::read_to_string(&log)
+            .unwrap()
+            .ends_with("\n$(echo pwned)\n"));
+
+        let failing = DnsConfig {
+            register: Some("echo nope >&2; exit 3".to_string()),
+            ..config
+        };
+        let err = provider(&failing).register(&record).await.unwrap_err();
+        assert!(err.to_string().contains("nope"), "got: {err}");
+    }
+
+    fn challenge_dns(provider: DnsProviderKind) -> ChallengeDnsConfig {
+        ChallengeDnsConfig {
+            provider,
+            zone: Some("Z123".to_string()),
+            token_env: default_token_env(),
+            present: None,
+            cleanup: None,
+            propagation_delay: default_propagation_delay(),
+        }
+    }
+
+    #[test]
+    fn test_challenge_dns() {
+        assert!(challenge_dns(DnsProviderKind::Route53).validate().is_ok());
+        let mut config = challenge_dns(DnsProviderKind::Cloudflare);
+        config.zone = None;
+        assert!(config.validate().unwrap_err().to_string().contains("zone"));
+        let config = challenge_dns(DnsProviderKind::Command);
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("dns.present"));
+
+        assert_eq!(
+            challenge_record_name("example.com"),
+            "_acme-challenge.example.com"
+        );
+        assert_eq!(
+            challenge_record_name("*.api.example.com"),
+            "_acme-challenge.api.example.com"
+        );
+
+        let route53 = Route53(challenge_dns(DnsProviderKind::Route53).as_dns_config());
+        let batch = route53.txt_change_batch(
+            "UPSERT",
+            "_acme-challenge.example.com",
+            &["a".to_string(), "b".to_string()],
+        );
+        let set = &batch["Changes"][0]["ResourceRecordSet"];
+        assert_eq!(set["Type"], "TXT");
+        assert_eq!(set["TTL"], CHALLENGE_TTL);
+        assert_eq!(
+            set["ResourceRecords"],
+            json!([{"Value": "\"a\""}, {"Value": "\"b\""}])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_challenge_hooks() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let log = dir.path().join("dns.log");
+        let config = ChallengeDnsConfig {
+            present: Some(format!(
+                "echo \"add $TENEMENT_DNS_NAME $TENEMENT_DNS_VALUE\" >> {}",
+                log.display()
+            )),
+            cleanup: Some(format!(
+                "echo \"del $TENEMENT_DNS_NAME $TENEMENT_DNS_VALUE\" >> {}",
+                log.display()
+            )),
+            ..challenge_dns(DnsProviderKind::Command)
+        };
+        let records = challenge_records(&config);
+        let name = challenge_record_name("*.example.com");
+        let values = ["v1".to_string(), "v2".to_string()];
+        records.publish(&name, &values).await.unwrap();
+        records.remove(&name, &values).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "add _acme-challenge.example.com v1\n\
+             add _acme-challenge.example.com v2\n\
+             del _acme-challenge.example.com v1\n\
+             del _acme-challenge.example.com v2\n"
+        );
+    }
+
+    /// Records every change; registrations are slow, to catch reordering
+    #[derive(Default)]
+    struct Recording(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl DnsProvider for Recording {
+        async fn register(&self, record: &DnsRecord) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.0.lock().unwrap().push(format!("+{}", record.name));
+            Ok(())
+        }
+
+        async fn unregister(&self, record: &DnsRecord) -> Result<()> {
+            self.0.lock().unwrap().push(format!("-{}", record.name));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registrar_applies_changes_in_order() {
+        let provider = Arc::new(Recording::default());
+        let registrar = DnsRegistrar::new("example.com", provider.clone());
+        registrar.register("api", "a");
+        // Already registered: a respawn doesn't touch DNS again
+        registrar.register("api", "a");
+        registrar.unregister("api", "a");
+        registrar.register("api", "b");
+
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while provider.0.lock().unwrap().len() < 3 {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "changes not applied"
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            *provider.0.lock().unwrap(),
+            [
+                "+a.api.example.com",
+                "-a.api.example.com",
+                "+b.api.example.com"
+            ]
+        );
+    }
+}
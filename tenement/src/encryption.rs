@@ -0,0 +1,257 @@
+//! Encrypted instance data directories (gocryptfs)
+//!
+//! With `encrypt_data = true`, an instance's data dir at
+//! `{data_dir}/{process}/{id}/` is a gocryptfs FUSE mount over a ciphertext
+//! dir at `{data_dir}/{process}/.{id}.enc/`. Only ciphertext ever hits the disk.
+//!
+//! Each tenant (instance ID) gets its own key, derived with Argon2id from the
+//! master key file and a random per-tenant salt kept in the [`TenantKeyStore`].
+//! The master key is expected to live off the data disk, so a stolen disk (or a
+//! copy of the database) doesn't expose any tenant's data.
+//!
+//! Linux only; requires `gocryptfs` and `fusermount` on PATH.
+//!
+//! [`TenantKeyStore`]: crate::store::TenantKeyStore
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use std::path::{Path, PathBuf};
+
+/// Minimum master key length in bytes
+const MIN_MASTER_KEY_LEN: usize = 32;
+
+/// Read the master key file. Rejects short keys and, on Unix, files that
+/// are readable by group or others.
+pub fn load_master_key(path: &Path) -> Result<Vec<u8>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(path)
+            .with_context(|| format!("Failed to read encryption key file: {}", path.display()))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            anyhow::bail!(
+                "Encryption key file {} is accessible by group/others (mode {:o}); chmod 600 it",
+                path.display(),
+                mode & 0o777
+            );
+        }
+    }
+
+    let key = std::fs::read(path)
+        .with_context(|| format!("Failed to read encryption key file: {}", path.display()))?;
+    let key = key.trim_ascii().to_vec();
+    if key.len() < MIN_MASTER_KEY_LEN {
+        anyhow::bail!(
+            "Encryption key file {} is too short ({} bytes, need at least {})",
+            path.display(),
+            key.len(),
+            MIN_MASTER_KEY_LEN
+        );
+    }
+    Ok(key)
+}
+
+/// Derive a tenant's data dir passphrase from the master key and its salt.
+/// CPU- and memory-hard (Argon2id); call from a blocking context.
+pub fn derive_tenant_key(master_key: &[u8], salt: &str) -> Result<String> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(master_key, salt.as_bytes(), &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive tenant key: {}", e))?;
+    Ok(URL_SAFE_NO_PAD.encode(key))
+}
+
+/// Ciphertext directory backing the data dir of `process:id`
+pub fn cipher_dir(data_dir: &Path, process: &str, id: &str) -> PathBuf {
+    data_dir.join(process).join(format!(".{}.enc", id))
+}
+
+/// True if `path` is a mount point (per /proc/self/mountinfo)
+pub fn is_mounted(path: &Path) -> bool {
+    let path = match path.canonicalize() {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+    std::fs::read_to_string("/proc/self/mountinfo")
+        .map(|info| {
+            info.lines()
+                .filter_map(|line| line.split(' ').nth(4))
+                .any(|mount_point| Path::new(&unescape_mount_path(mount_point)) == path)
+        })
+        .unwrap_or(false)
+}
+
+/// mountinfo escapes space, tab, newline and backslash as octal (`\040`)
+fn unescape_mount_path(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('\\') {
+        out.push_str(&rest[..pos]);
+        let code = rest.get(pos + 1..pos + 4);
+        match code.and_then(|c| u8::from_str_radix(c, 8).ok()) {
+            Some(byte) => {
+                out.push(byte as char);
+                rest = &rest[pos + 4..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[pos + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Mount `cipher` at `plain` with the given passphrase, initializing the
+/// ciphertext dir on first use. A no-op if `plain` is already mounted
+/// (e.g. left mounted by a previous daemon).
+pub async fn mount(cipher: &Path, plain: &Path, passphrase: &str) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        if is_mounted(plain) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(cipher)
+            .with_context(|| format!("Failed to create cipher dir: {:?}", cipher))?;
+        std::fs::create_dir_all(plain)
+            .with_context(|| format!("Failed to create data dir: {:?}", plain))?;
+
+        if !cipher.join("gocryptfs.conf").exists() {
+            gocryptfs(&["-init", "-q", "--"], &[cipher], passphrase)
+                .await
+                .context("gocryptfs -init failed")?;
+        }
+        gocryptfs(&["-q", "--"], &[cipher, plain], passphrase)
+            .await
+            .with_context(|| format!("Failed to mount encrypted data dir {:?}", plain))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (cipher, plain, passphrase);
+        anyhow::bail!("encrypt_data requires Linux (gocryptfs)")
+    }
+}
+
+/// Unmount an encrypted data dir. A no-op if it isn't mounted.
+pub async fn unmount(plain: &Path) -> Result<()> {
+    if !is_mounted(plain) {
+        return Ok(());
+    }
+    let out = tokio::process::Command::new("fusermount")
+        .arg("-u")
+        .arg(plain)
+        .output()
+        .await
+        .context("Failed to run fusermount")?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "fusermount -u {:?} failed: {}",
+            plain,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Run gocryptfs with the passphrase on stdin (never on the command line
+/// or in a file)
+#[cfg(target_os = "linux")]
+async fn gocryptfs(flags: &[&str], paths: &[&Path], passphrase: &str) -> Result<()> {
+    use std::process::Stdio;
+    use tokio::io::AsyncWriteExt;
+
+    let mut child = tokio::process::Command::new("gocryptfs")
+        .args(flags)
+        .args(paths)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run gocryptfs (is it installed?)")?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(passphrase.as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+    }
+    let out = child.wait_with_output().await?;
+    if !out.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write_key(dir: &Path, contents: &[u8], mode: u32) -> PathBuf {
+        let path = dir.join("master.key");
+        std::fs::write(&path, contents).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        path
+    }
+
+    #[test]
+    fn test_load_master_key() {
+        let dir = TempDir::new().unwrap();
+        let path = write_key(dir.path(), &[b'k'; 40], 0o600);
+        assert_eq!(load_master_key(&path).unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_load_master_key_rejects_short_key() {
+        let dir = TempDir::new().unwrap();
+        let path = write_key(dir.path(), b"too-short\n", 0o600);
+        let err = load_master_key(&path).unwrap_err();
+        assert!(err.to_string().contains("too short"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_load_master_key_rejects_world_readable() {
+        let dir = TempDir::new().unwrap();
+        let path = write_key(dir.path(), &[b'k'; 40], 0o644);
+        let err = load_master_key(&path).unwrap_err();
+        assert!(err.to_string().contains("chmod 600"));
+    }
+
+    #[test]
+    fn test_derive_tenant_key() {
+        let master = [7u8; 32];
+        let alice = derive_tenant_key(&master, "salt-for-alice").unwrap();
+        assert_eq!(derive_tenant_key(&master, "salt-for-alice").unwrap(), alice);
+        assert_ne!(derive_tenant_key(&master, "salt-for-bob").unwrap(), alice);
+        assert_ne!(
+            derive_tenant_key(&[8u8; 32], "salt-for-alice").unwrap(),
+            alice
+        );
+    }
+
+    #[test]
+    fn test_cipher_dir_is_hidden_sibling() {
+        let dir = cipher_dir(Path::new("/var/lib/tenement"), "api", "alice");
+        assert_eq!(dir, PathBuf::from("/var/lib/tenement/api/.alice.enc"));
+    }
+
+    #[test]
+    fn test_unescape_mount_path() {
+        assert_eq!(unescape_mount_path("/data/my\\040dir"), "/data/my dir");
+        assert_eq!(unescape_mount_path("/plain"), "/plain");
+    }
+
+    #[test]
+    fn test_plain_dir_is_not_mounted() {
+        let dir = TempDir::new().unwrap();
+        assert!(!is_mounted(dir.path()));
+    }
+}
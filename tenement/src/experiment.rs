@@ -0,0 +1,260 @@
+//! A/B experiment assignment at the proxy
+//!
+//! An experiment splits the weighted traffic of one service between groups of
+//! instances. Each client is put in a group by a stable hash of its identifier
+//! (a request header or cookie), so the same client keeps seeing the same group
+//! across requests and daemon restarts.
+//!
+//! ```toml
+//! [experiment.checkout]
+//! service = "api"
+//! client_id = "cookie:uid"
+//!
+//! [experiment.checkout.groups.control]
+//! instances = ["v1"]
+//! percent = 90
+//!
+//! [experiment.checkout.groups.new-flow]
+//! instances = ["v2"]
+//! percent = 10
+//! ```
+
+use crate::config::Config;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Response header carrying the assignment as `{experiment}={group}`
+pub const EXPERIMENT_HEADER: &str = "x-tenement-experiment";
+
+/// An experiment over the instances of one service
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExperimentConfig {
+    /// Service whose weighted traffic is split
+    pub service: String,
+
+    /// Where the client identifier comes from: "header:<name>" or "cookie:<name>".
+    /// Requests without it are routed normally and not assigned.
+    pub client_id: String,
+
+    /// Groups by name. Percentages must add up to 100.
+    pub groups: BTreeMap<String, ExperimentGroup>,
+}
+
+/// One arm of an experiment
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExperimentGroup {
+    /// Instance IDs that serve this group (weighted between themselves)
+    pub instances: Vec<String>,
+
+    /// Share of clients assigned to this group (0-100)
+    pub percent: u8,
+}
+
+/// Where to read the client identifier from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientIdSource {
+    Header(String),
+    Cookie(String),
+}
+
+impl std::str::FromStr for ClientIdSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("header", name)) if !name.is_empty() => Ok(Self::Header(name.to_string())),
+            Some(("cookie", name)) if !name.is_empty() => Ok(Self::Cookie(name.to_string())),
+            _ => anyhow::bail!(
+                "Invalid client_id '{}'. Use \"header:<name>\" or \"cookie:<name>\"",
+                s
+            ),
+        }
+    }
+}
+
+impl ClientIdSource {
+    /// Extract the client identifier from request headers, given as
+    /// (name, value) pairs. Header names match case-insensitively.
+    pub fn extract<'a>(
+        &self,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Option<String> {
+        let mut headers = headers.into_iter();
+        match self {
+            Self::Header(name) => headers
+                .find(|(k, v)| k.eq_ignore_ascii_case(name) && !v.is_empty())
+                .map(|(_, v)| v.to_string()),
+            Self::Cookie(name) => headers
+                .filter(|(k, _)| k.eq_ignore_ascii_case("cookie"))
+                .flat_map(|(_, v)| v.split(';'))
+                .filter_map(|pair| pair.trim().split_once('='))
+                .find(|(k, v)| k == name && !v.is_empty())
+                .map(|(_, v)| v.to_string()),
+        }
+    }
+}
+
+impl ExperimentConfig {
+    /// Check the experiment against the rest of the config
+    pub fn validate(&self, name: &str, config: &Config) -> Result<()> {
+        if !config.service.contains_key(&self.service) {
+            anyhow::bail!(
+                "Experiment '{}' references undefined service '{}'",
+                name,
+                self.service
+            );
+        }
+        self.client_id
+            .parse::<ClientIdSource>()
+            .map_err(|e| anyhow::anyhow!("Experiment '{}': {}", name, e))?;
+        if self.groups.is_empty() {
+            anyhow::bail!("Experiment '{}' has no groups", name);
+        }
+        let total: u32 = self.groups.values().map(|g| g.percent as u32).sum();
+        if total != 100 {
+            anyhow::bail!(
+                "Experiment '{}' group percentages add up to {}, expected 100",
+                name,
+                total
+            );
+        }
+        for (group_name, group) in &self.groups {
+            if group.instances.is_empty() {
+                anyhow::bail!(
+                    "Experiment '{}' group '{}' has no instances",
+                    name,
+                    group_name
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Client identifier source (validated at config load)
+    pub fn client_id_source(&self) -> Option<ClientIdSource> {
+        self.client_id.parse().ok()
+    }
+
+    /// Assign a client to a group. The same experiment name and client ID
+    /// always map to the same group as long as the groups are unchanged.
+    pub fn assign(&self, experiment: &str, client_id: &str) -> Option<(&str, &ExperimentGroup)> {
+        let bucket = (bucket_hash(experiment, client_id) % 100) as u32;
+        let mut cumulative = 0u32;
+        for (name, group) in &self.groups {
+            cumulative += group.percent as u32;
+            if bucket < cumulative {
+                return Some((name.as_str(), group));
+            }
+        }
+        None
+    }
+}
+
+/// FNV-1a over "{experiment}:{client_id}"
+fn bucket_hash(experiment: &str, client_id: &str) -> u64 {
+    crate::hash::fnv1a(
+        experiment
+            .bytes()
+            .chain(std::iter::once(b':'))
+            .chain(client_id.bytes()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Config> {
+        Config::from_str(s)
+    }
+
+    const CONFIG: &str = r#"
+[service.api]
+command = "./api"
+
+[experiment.checkout]
+service = "api"
+client_id = "header:x-user-id"
+
+[experiment.checkout.groups.control]
+instances = ["v1"]
+percent = 70
+
+[experiment.checkout.groups.treatment]
+instances = ["v2", "v3"]
+percent = 30
+"#;
+
+    #[test]
+    fn test_parse_experiment() {
+        let config = parse(CONFIG).unwrap();
+        let exp = &config.experiment["checkout"];
+        assert_eq!(exp.service, "api");
+        assert_eq!(
+            exp.client_id_source(),
+            Some(ClientIdSource::Header("x-user-id".to_string()))
+        );
+        assert_eq!(exp.groups["treatment"].instances, vec!["v2", "v3"]);
+    }
+
+    #[test]
+    fn test_assignment_is_stable_and_roughly_proportional() {
+        let config = parse(CONFIG).unwrap();
+        let exp = &config.experiment["checkout"];
+
+        let mut control = 0;
+        for i in 0..10_000 {
+            let client = format!("user-{}", i);
+            let (group, _) = exp.assign("checkout", &client).unwrap();
+            assert_eq!(exp.assign("checkout", &client).unwrap().0, group);
+            if group == "control" {
+                control += 1;
+            }
+        }
+        assert!((6500..7500).contains(&control), "control got {}", control);
+    }
+
+    #[test]
+    fn test_bucket_hash_is_fixed() {
+        // Changing the hash would reshuffle every client between groups
+        assert_eq!(bucket_hash("checkout", "user-1"), 0x76a6ee17cb597ee0);
+    }
+
+    #[test]
+    fn test_percentages_must_sum_to_100() {
+        let bad = CONFIG.replace("percent = 30", "percent = 20");
+        let err = parse(&bad).unwrap_err();
+        assert!(err.to_string().contains("add up to 90"));
+    }
+
+    #[test]
+    fn test_unknown_service_rejected() {
+        let bad = CONFIG.replace(
+            "service = \"api\"\nclient_id",
+            "service = \"web\"\nclient_id",
+        );
+        let err = parse(&bad).unwrap_err();
+        assert!(err.to_string().contains("undefined service 'web'"));
+    }
+
+    #[test]
+    fn test_invalid_client_id_rejected() {
+        let bad = CONFIG.replace("header:x-user-id", "ip");
+        assert!(parse(&bad).is_err());
+    }
+
+    #[test]
+    fn test_extract_client_id() {
+        let headers = [("X-User-Id", "alice"), ("cookie", "theme=dark; uid=bob")];
+
+        let header = ClientIdSource::Header("x-user-id".to_string());
+        assert_eq!(header.extract(headers), Some("alice".to_string()));
+
+        let cookie = ClientIdSource::Cookie("uid".to_string());
+        assert_eq!(cookie.extract(headers), Some("bob".to_string()));
+
+        let missing = ClientIdSource::Cookie("session".to_string());
+        assert_eq!(missing.extract(headers), None);
+    }
+}
@@ -0,0 +1,357 @@
+//! Login gate for hosted services
+//!
+//! A service with an `auth` table only gets requests that carry valid
+//! credentials; others are answered 401 by the proxy without reaching (or
+//! waking) an instance. Meant for internal tools that have no login of their
+//! own.
+//!
+//! ```toml
+//! [service.admin]
+//! command = "./admin"
+//! auth = { type = "basic", users_file = "/etc/tenement/admin.users" }
+//!
+//! [service.docs]
+//! command = "./docs"
+//! auth = { type = "bearer", token_env = "DOCS_TOKEN" }
+//! ```
+//!
+//! `users_file` holds one `user:hash` per line, the hash an argon2 PHC
+//! string (`ten hash-password` prints one). The file is re-read when it
+//! changes. A bearer token is read from the daemon's environment.
+
+use crate::auth::verify_token;
+use anyhow::Result;
+use base64::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// How a service's requests are authenticated
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuthGateConfig {
+    /// HTTP basic auth against the users in `users_file`
+    Basic {
+        users_file: PathBuf,
+        /// Shown by browsers in the login prompt (default: the service name)
+        #[serde(default)]
+        realm: Option<String>,
+    },
+    /// `Authorization: Bearer <token>` with one token shared by all clients
+    Bearer {
+        /// Environment variable holding the token
+        token_env: String,
+    },
+}
+
+impl AuthGateConfig {
+    /// Check the gate is usable
+    pub fn validate(&self, service: &str) -> Result<()> {
+        match self {
+            AuthGateConfig::Basic { users_file, .. } if users_file.as_os_str().is_empty() => {
+                anyhow::bail!("Service '{}' auth.users_file is empty", service)
+            }
+            AuthGateConfig::Bearer { token_env } if token_env.is_empty() => {
+                anyhow::bail!("Service '{}' auth.token_env is empty", service)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `WWW-Authenticate` value for requests turned away
+    pub fn challenge(&self, service: &str) -> String {
+        match self {
+            AuthGateConfig::Basic { realm, .. } => format!(
+                "Basic realm=\"{}\", charset=\"UTF-8\"",
+                realm.as_deref().unwrap_or(service).replace(['"', '\\'], "")
+            ),
+            AuthGateConfig::Bearer { .. } => format!("Bearer realm=\"{}\"", service),
+        }
+    }
+}
+
+/// Users of one `users_file`, and passwords already checked against them
+#[derive(Default)]
+struct Users {
+    modified: Option<SystemTime>,
+    hashes: HashMap<String, String>,
+    /// Keyed digests of passwords that matched a user's hash. Argon2 is
+    /// deliberately slow, so each is only verified once; the password
+    /// itself isn't kept.
+    verified: HashMap<String, [u8; 32]>,
+}
+
+/// Checks credentials for services with an `auth` gate
+pub struct AuthGate {
+    users: Mutex<HashMap<PathBuf, Users>>,
+    /// Random per-process key for the verified-credential digests
+    key: [u8; 32],
+}
+
+impl Default for AuthGate {
+    fn default() -> Self {
+        Self {
+            users: Mutex::new(HashMap::new()),
+            key: rand::random(),
+        }
+    }
+}
+
+impl AuthGate {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Digest of a credential under this gate's key
+    fn digest(&self, user: &str, password: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(self.key);
+        hasher.update((user.len() as u64).to_le_bytes());
+        hasher.update(user);
+        hasher.update(password);
+        hasher.finalize().into()
+    }
+
+    /// Whether `authorization` (the request's `Authorization` header) gets
+    /// past `gate`
+    pub async fn allows(&self, gate: &AuthGateConfig, authorization: Option<&str>) -> bool {
+        let Some(authorization) = authorization else {
+            return false;
+        };
+        match gate {
+            AuthGateConfig::Bearer { token_env } => {
+                let Some(token) = strip_scheme(authorization, "Bearer") else {
+                    return false;
+                };
+                match std::env::var(token_env) {
+                    Ok(expected) if !expected.is_empty() => {
+                        constant_time_eq(token.as_bytes(), expected.as_bytes())
+                    }
+                    _ => {
+                        tracing::warn!("auth token_env {} is not set; denying requests", token_env);
+                        false
+                    }
+                }
+            }
+            AuthGateConfig::Basic { users_file, .. } => {
+                let Some((user, password)) = basic_credentials(authorization) else {
+                    return false;
+                };
+                self.check_basic(users_file, &user, &password).await
+            }
+        }
+    }
+
+    async fn check_basic(&self, users_file: &Path, user: &str, password: &str) -> bool {
+        let hash = {
+            let mut files = self.users.lock().unwrap();
+            let users = files.entry(users_file.to_path_buf()).or_default();
+            if let Err(e) = refresh(users_file, users) {
+                tracing::warn!(
+                    "Can't read auth users_file {}: {:#}",
+                    users_file.display(),
+                    e
+                );
+                return false;
+            }
+            if let Some(known) = users.verified.get(user) {
+                if constant_time_eq(known, &self.digest(user, password)) {
+                    return true;
+                }
+            }
+            match users.hashes.get(user) {
+                Some(hash) => hash.clone(),
+                None => return false,
+            }
+        };
+
+        let candidate = password.to_string();
+        let matched = tokio::task::spawn_blocking(move || verify_token(&candidate, &hash))
+            .await
+            .unwrap_or(false);
+        if matched {
+            let mut files = self.users.lock().unwrap();
+            if let Some(users) = files.get_mut(users_file) {
+                users
+                    .verified
+                    .insert(user.to_string(), self.digest(user, password));
+            }
+        }
+        matched
+    }
+}
+
+/// Re-read a users file if it changed since it was last loaded
+fn refresh(path: &Path, users: &mut Users) -> Result<()> {
+    let modified = std::fs::metadata(path)?.modified().ok();
+    if modified.is_some() && modified == users.modified {
+        return Ok(());
+    }
+    *users = Users {
+        modified,
+        hashes: parse_users(&std::fs::read_to_string(path)?),
+        verified: HashMap::new(),
+    };
+    Ok(())
+}
+
+/// `user:hash` lines; blank lines and `#` comments are skipped
+fn parse_users(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .map(|(user, hash)| (user.to_string(), hash.to_string()))
+        .collect()
+}
+
+/// The credentials after `scheme` in an `Authorization` value
+fn strip_scheme<'a>(authorization: &'a str, scheme: &str) -> Option<&'a str> {
+    let (given, credentials) = authorization.trim().split_once(' ')?;
+    given
+        .eq_ignore_ascii_case(scheme)
+        .then(|| credentials.trim())
+}
+
+/// User and password of a `Basic` `Authorization` value
+fn basic_credentials(authorization: &str) -> Option<(String, String)> {
+    let decoded = BASE64_STANDARD
+        .decode(strip_scheme(authorization, "Basic")?)
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// Compare secrets without returning early on the first difference
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::hash_token;
+
+    fn basic(user: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}:{}", user, password))
+        )
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        assert_eq!(
+            basic_credentials(&basic("alice", "pa:ss")),
+            Some(("alice".to_string(), "pa:ss".to_string()))
+        );
+        assert_eq!(
+            basic_credentials("basic YWxpY2U6c2VjcmV0"),
+            Some(("alice".to_string(), "secret".to_string()))
+        );
+        assert_eq!(basic_credentials("Bearer YWxpY2U6c2VjcmV0"), None);
+        assert_eq!(basic_credentials("Basic not-base64!"), None);
+    }
+
+    #[test]
+    fn test_parse_users() {
+        let users = parse_users("# admins\nalice:$argon2id$x\n\n  bob:$argon2id$y  \nbad line\n");
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["alice"], "$argon2id$x");
+        assert_eq!(users["bob"], "$argon2id$y");
+    }
+
+    #[tokio::test]
+    async fn test_basic_gate() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let users_file = dir.path().join("users");
+        std::fs::write(
+            &users_file,
+            format!("alice:{}\n", hash_token("secret").unwrap()),
+        )
+        .unwrap();
+        let config = AuthGateConfig::Basic {
+            users_file: users_file.clone(),
+            realm: None,
+        };
+        let gate = AuthGate::new();
+
+        assert!(gate.allows(&config, Some(&basic("alice", "secret"))).await);
+        // Served from the cache the second time
+        assert!(gate.allows(&config, Some(&basic("alice", "secret"))).await);
+        // which holds a keyed digest, not the password
+        let cached = gate.users.lock().unwrap()[&users_file].verified["alice"];
+        assert_eq!(cached, gate.digest("alice", "secret"));
+        assert_ne!(cached, AuthGate::new().digest("alice", "secret"));
+        assert!(!gate.allows(&config, Some(&basic("alice", "wrong"))).await);
+        assert!(!gate.allows(&config, Some(&basic("bob", "secret"))).await);
+        assert!(!gate.allows(&config, None).await);
+
+        // Removing a user takes effect without a restart
+        std::fs::write(
+            &users_file,
+            format!("bob:{}\n", hash_token("other").unwrap()),
+        )
+        .unwrap();
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&users_file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(!gate.allows(&config, Some(&basic("alice", "secret"))).await);
+        assert!(gate.allows(&config, Some(&basic("bob", "other"))).await);
+
+        let missing = AuthGateConfig::Basic {
+            users_file: dir.path().join("missing"),
+            realm: None,
+        };
+        assert!(!gate.allows(&missing, Some(&basic("bob", "other"))).await);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[tokio::test]
+    async fn test_bearer_gate() {
+        let config = AuthGateConfig::Bearer {
+            token_env: "TENEMENT_TEST_GATE_TOKEN".to_string(),
+        };
+        let gate = AuthGate::new();
+        assert!(!gate.allows(&config, Some("Bearer s3cret")).await);
+
+        std::env::set_var("TENEMENT_TEST_GATE_TOKEN", "s3cret");
+        assert!(gate.allows(&config, Some("Bearer s3cret")).await);
+        assert!(gate.allows(&config, Some("bearer  s3cret")).await);
+        assert!(!gate.allows(&config, Some("Bearer s3cre")).await);
+        assert!(!gate.allows(&config, Some("Basic s3cret")).await);
+        assert!(!gate.allows(&config, None).await);
+    }
+
+    #[test]
+    fn test_challenge() {
+        let basic = AuthGateConfig::Basic {
+            users_file: PathBuf::from("/etc/users"),
+            realm: Some("Ops \"tools\"".to_string()),
+        };
+        assert_eq!(
+            basic.challenge("admin"),
+            "Basic realm=\"Ops tools\", charset=\"UTF-8\""
+        );
+        let bearer = AuthGateConfig::Bearer {
+            token_env: "TOKEN".to_string(),
+        };
+        assert_eq!(bearer.challenge("docs"), "Bearer realm=\"docs\"");
+    }
+}
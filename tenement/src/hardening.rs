@@ -0,0 +1,1537 @@
+//! Security hardening presets for services
+//!
+//! `profile = "strict" | "standard" | "permissive"` bundles the individual
+//! hardening knobs, so a service gets good isolation without configuring
+//! each one:
+//!
+//! | | permissive | standard | strict |
+//! |---|---|---|---|
+//! | no-new-privs | - | yes | yes |
+//! | seccomp default profile | - | yes | yes |
+//! | capabilities | runtime default | docker's default set | `CAP_NET_BIND_SERVICE` only |
+//! | private `/tmp` | - | yes | yes |
+//! | read-only root | - | - | yes |
+//!
+//! The namespace runtime applies them itself (mounts in the child's mount
+//! namespace, then capabilities, no-new-privs and the seccomp filter right
+//! before exec); the container runtimes pass the equivalent engine flags, and
+//! the OCI runtime writes them into the bundle's spec. The seccomp profile is
+//! a denylist modelled on what docker's default profile blocks for
+//! unprivileged containers (module loading, `mount` and the new mount API,
+//! `ptrace`, `bpf`, `kexec`, `io_uring`, ...); they fail with `EPERM`. `clone`
+//! can't create namespaces, and `clone3`, whose flags a filter can't read,
+//! fails with `ENOSYS` so libc falls back to `clone`. Docker's profile is an
+//! allowlist, so it still blocks things this one doesn't (e.g. new syscalls).
+//!
+//! `seccomp = "<path>"` swaps that profile for one loaded from a file in the
+//! docker/OCI JSON format (a default action plus per-syscall actions;
+//! argument filters aren't supported), and `seccomp = "unconfined"` drops it.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// docker's default capability set
+pub const DEFAULT_CAPABILITIES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_FSETID",
+    "CAP_FOWNER",
+    "CAP_MKNOD",
+    "CAP_NET_RAW",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETFCAP",
+    "CAP_SETPCAP",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_SYS_CHROOT",
+    "CAP_KILL",
+    "CAP_AUDIT_WRITE",
+];
+
+/// Capabilities left under the strict profile
+pub const STRICT_CAPABILITIES: &[&str] = &["CAP_NET_BIND_SERVICE"];
+
+/// Capability names by number (linux/capability.h)
+const CAPABILITY_NAMES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+/// Syscalls the seccomp profile denies, on every architecture
+const DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "add_key",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "delete_module",
+    "finit_module",
+    "fsconfig",
+    "fsmount",
+    "fsopen",
+    "fspick",
+    "init_module",
+    "io_uring_enter",
+    "io_uring_register",
+    "io_uring_setup",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mount",
+    "mount_setattr",
+    "move_mount",
+    "open_by_handle_at",
+    "open_tree",
+    "perf_event_open",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "setns",
+    "settimeofday",
+    "swapoff",
+    "swapon",
+    "umount2",
+    "unshare",
+    "userfaultfd",
+];
+
+/// `clone` flags that create namespaces, which the seccomp profile denies:
+/// `CLONE_NEWNS`, `CLONE_NEWCGROUP`, `CLONE_NEWUTS`, `CLONE_NEWIPC`,
+/// `CLONE_NEWUSER`, `CLONE_NEWPID` and `CLONE_NEWNET`
+pub const CLONE_NAMESPACE_FLAGS: [u32; 7] = [
+    0x0002_0000,
+    0x0200_0000,
+    0x0400_0000,
+    0x0800_0000,
+    0x1000_0000,
+    0x2000_0000,
+    0x4000_0000,
+];
+
+/// errno of `clone3` under the seccomp profile
+pub const ENOSYS: u32 = 38;
+
+/// Legacy x86_64-only syscalls the seccomp profile also denies
+#[cfg(target_arch = "x86_64")]
+const DENIED_SYSCALLS_ARCH: &[&str] = &[
+    "_sysctl",
+    "create_module",
+    "get_kernel_syms",
+    "ioperm",
+    "iopl",
+    "nfsservctl",
+    "query_module",
+    "sysfs",
+    "uselib",
+    "ustat",
+];
+#[cfg(not(target_arch = "x86_64"))]
+const DENIED_SYSCALLS_ARCH: &[&str] = &[];
+
+/// Security hardening preset for a service
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityProfile {
+    /// The runtime's own defaults, nothing added
+    #[default]
+    Permissive,
+    /// no-new-privs, seccomp, docker's capability set and a private /tmp
+    Standard,
+    /// `standard` plus a read-only root and no capabilities but binding
+    /// low ports
+    Strict,
+}
+
+impl std::fmt::Display for SecurityProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecurityProfile::Permissive => write!(f, "permissive"),
+            SecurityProfile::Standard => write!(f, "standard"),
+            SecurityProfile::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+impl SecurityProfile {
+    /// The hardening this preset turns on
+    pub fn hardening(self) -> Hardening {
+        match self {
+            SecurityProfile::Permissive => Hardening::default(),
+            SecurityProfile::Standard => Hardening {
+                no_new_privs: true,
+                seccomp: SeccompProfile::Default,
+                capabilities: Some(DEFAULT_CAPABILITIES),
+                private_tmp: true,
+                ..Default::default()
+            },
+            SecurityProfile::Strict => Hardening {
+                no_new_privs: true,
+                seccomp: SeccompProfile::Default,
+                capabilities: Some(STRICT_CAPABILITIES),
+                private_tmp: true,
+                read_only_root: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Hardening applied to an instance by its runtime
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Hardening {
+    /// Set no-new-privs, so setuid binaries and file capabilities can't
+    /// raise privileges
+    pub no_new_privs: bool,
+    /// Seccomp filter installed right before exec
+    pub seccomp: SeccompProfile,
+    /// Capabilities to keep (`CAP_*` names); None keeps the runtime's defaults
+    pub capabilities: Option<&'static [&'static str]>,
+    /// Give the instance its own empty `/tmp` (and `/var/tmp`)
+    pub private_tmp: bool,
+    /// Mount the root filesystem read-only
+    pub read_only_root: bool,
+    /// Extra tmpfs mounts (`tmpfs = [...]`), private to the instance
+    pub tmpfs: Vec<Tmpfs>,
+    /// User and groups to switch to before exec
+    pub run_as: Option<RunAs>,
+    /// Paths that stay writable under a read-only root or private /tmp
+    /// (the instance's data dir and socket dir)
+    pub writable: Vec<PathBuf>,
+}
+
+impl Hardening {
+    /// Whether anything beyond the runtime's defaults is turned on
+    pub fn is_enabled(&self) -> bool {
+        self.no_new_privs
+            || self.seccomp != SeccompProfile::Unconfined
+            || self.capabilities.is_some()
+            || self.private_tmp
+            || self.read_only_root
+            || !self.tmpfs.is_empty()
+            || self.run_as.is_some()
+    }
+
+    /// Whether a mount namespace is needed to apply it
+    pub fn needs_mounts(&self) -> bool {
+        self.private_tmp || self.read_only_root || !self.tmpfs.is_empty()
+    }
+}
+
+/// A private tmpfs mounted over `path` inside the instance
+///
+/// Written as a bare path (`"/run"`) or a table with a size limit
+/// (`{ path = "/tmp", size_mb = 64 }`). Without a limit the kernel caps it
+/// at half of RAM.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "TmpfsConfig")]
+pub struct Tmpfs {
+    pub path: PathBuf,
+    pub size_mb: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmpfsConfig {
+    Path(PathBuf),
+    Table {
+        path: PathBuf,
+        #[serde(default)]
+        size_mb: Option<u64>,
+    },
+}
+
+impl From<TmpfsConfig> for Tmpfs {
+    fn from(config: TmpfsConfig) -> Self {
+        match config {
+            TmpfsConfig::Path(path) => Tmpfs {
+                path,
+                size_mb: None,
+            },
+            TmpfsConfig::Table { path, size_mb } => Tmpfs { path, size_mb },
+        }
+    }
+}
+
+impl Tmpfs {
+    /// tmpfs mount options (`mode=1777,size=64m`)
+    pub fn options(&self) -> String {
+        match self.size_mb {
+            Some(size) => format!("mode=1777,size={}m", size),
+            None => "mode=1777".to_string(),
+        }
+    }
+}
+
+/// Which seccomp filter an instance gets: `"default"`, `"unconfined"` or the
+/// path of a profile file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum SeccompProfile {
+    /// No filter
+    #[default]
+    Unconfined,
+    /// The built-in denylist (see [`denied_syscalls`])
+    Default,
+    /// A docker/OCI-format JSON profile
+    File(PathBuf),
+}
+
+impl From<String> for SeccompProfile {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "default" => SeccompProfile::Default,
+            "unconfined" => SeccompProfile::Unconfined,
+            _ => SeccompProfile::File(PathBuf::from(s)),
+        }
+    }
+}
+
+impl From<SeccompProfile> for String {
+    fn from(profile: SeccompProfile) -> Self {
+        profile.to_string()
+    }
+}
+
+impl std::fmt::Display for SeccompProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeccompProfile::Unconfined => write!(f, "unconfined"),
+            SeccompProfile::Default => write!(f, "default"),
+            SeccompProfile::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl SeccompProfile {
+    /// The rules to install, None when unconfined
+    pub fn rules(&self) -> Result<Option<SeccompRules>> {
+        Ok(match self {
+            SeccompProfile::Unconfined => None,
+            SeccompProfile::Default => Some(SeccompRules::builtin()),
+            SeccompProfile::File(path) => Some(SeccompRules::load(path)?),
+        })
+    }
+}
+
+/// errno of a denied syscall unless a profile says otherwise
+const EPERM: u32 = 1;
+
+/// What a seccomp filter does with a syscall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    Allow,
+    /// Fail with this errno
+    Errno(u32),
+    /// Allow, but log to the audit log
+    Log,
+    /// Send `SIGSYS`
+    Trap,
+    KillThread,
+    KillProcess,
+}
+
+impl SeccompAction {
+    /// Parse an `SCMP_ACT_*` name; `errno` is the profile's `errnoRet`
+    fn parse(action: &str, errno: Option<u32>) -> Result<Self> {
+        Ok(match action {
+            "SCMP_ACT_ALLOW" => SeccompAction::Allow,
+            "SCMP_ACT_ERRNO" => SeccompAction::Errno(errno.unwrap_or(EPERM)),
+            "SCMP_ACT_LOG" => SeccompAction::Log,
+            "SCMP_ACT_TRAP" => SeccompAction::Trap,
+            "SCMP_ACT_KILL" | "SCMP_ACT_KILL_THREAD" => SeccompAction::KillThread,
+            "SCMP_ACT_KILL_PROCESS" => SeccompAction::KillProcess,
+            _ => bail!("unsupported seccomp action {:?}", action),
+        })
+    }
+}
+
+/// A seccomp profile: a default action and per-syscall exceptions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeccompRules {
+    pub default_action: SeccompAction,
+    /// Syscall names with their action; the first match wins
+    pub syscalls: Vec<(String, SeccompAction)>,
+    /// `clone` flags that fail with `EPERM`, checked before `syscalls`
+    pub denied_clone_flags: u32,
+}
+
+/// docker/OCI seccomp profile JSON
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileFile {
+    default_action: String,
+    #[serde(default)]
+    default_errno_ret: Option<u32>,
+    #[serde(default)]
+    syscalls: Vec<ProfileRule>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProfileRule {
+    #[serde(default)]
+    names: Vec<String>,
+    /// Older docker profiles name one syscall per rule
+    #[serde(default)]
+    name: Option<String>,
+    action: String,
+    #[serde(default)]
+    errno_ret: Option<u32>,
+    #[serde(default)]
+    args: Vec<serde_json::Value>,
+    #[serde(default)]
+    includes: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    excludes: serde_json::Map<String, serde_json::Value>,
+}
+
+impl SeccompRules {
+    /// The built-in profile: allow everything but [`denied_syscalls`],
+    /// `clone3` and namespace-creating `clone`s
+    pub fn builtin() -> Self {
+        let deny = SeccompAction::Errno(EPERM);
+        let mut syscalls: Vec<_> = denied_syscalls().map(|n| (n.to_string(), deny)).collect();
+        syscalls.push(("clone3".to_string(), SeccompAction::Errno(ENOSYS)));
+        Self {
+            default_action: SeccompAction::Allow,
+            syscalls,
+            denied_clone_flags: CLONE_NAMESPACE_FLAGS.iter().fold(0, |mask, f| mask | f),
+        }
+    }
+
+    /// Parse a docker/OCI-format JSON profile
+    pub fn parse(json: &str) -> Result<Self> {
+        let file: ProfileFile = serde_json::from_str(json)?;
+        let mut rules = Self {
+            default_action: SeccompAction::parse(&file.default_action, file.default_errno_ret)?,
+            syscalls: Vec::new(),
+            denied_clone_flags: 0,
+        };
+        for rule in file.syscalls {
+            if !rule.args.is_empty() {
+                bail!("seccomp argument filters are not supported");
+            }
+            if !rule.includes.is_empty() || !rule.excludes.is_empty() {
+                bail!("seccomp rule includes/excludes are not supported");
+            }
+            let action = SeccompAction::parse(&rule.action, rule.errno_ret)?;
+            for name in rule.names.into_iter().chain(rule.name) {
+                rules.syscalls.push((name, action));
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Load a profile file
+    pub fn load(path: &Path) -> Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read seccomp profile {}", path.display()))?;
+        Self::parse(&json).with_context(|| format!("Invalid seccomp profile {}", path.display()))
+    }
+}
+
+/// Credentials an instance drops to before exec (`user`/`group`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups, the user's memberships when it has a passwd
+    /// entry
+    pub groups: Vec<u32>,
+}
+
+impl RunAs {
+    /// Resolve `user` and `group` (names or numeric ids) against the host's
+    /// user database. Without `group`, the user's primary group is used.
+    #[cfg(unix)]
+    pub fn resolve(user: &str, group: Option<&str>) -> Result<Self> {
+        let entry = match lookup_user(user)? {
+            Some(entry) => Some(entry),
+            None => match user.parse::<u32>() {
+                Ok(uid) => lookup_uid(uid)?.or(Some((String::new(), uid, u32::MAX))),
+                Err(_) => bail!("unknown user {:?}", user),
+            },
+        };
+        let (name, uid, primary) = entry.expect("set above");
+        let gid = match group {
+            Some(group) => match lookup_group(group)? {
+                Some(gid) => gid,
+                None => group
+                    .parse::<u32>()
+                    .map_err(|_| anyhow::anyhow!("unknown group {:?}", group))?,
+            },
+            None if primary == u32::MAX => {
+                bail!("user {} has no passwd entry, so set a group too", uid)
+            }
+            None => primary,
+        };
+        let groups = if name.is_empty() {
+            vec![gid]
+        } else {
+            group_list(&name, gid)?
+        };
+        Ok(Self { uid, gid, groups })
+    }
+}
+
+/// uid of `user` (a name or numeric id)
+#[cfg(unix)]
+pub fn resolve_uid(user: &str) -> Result<u32> {
+    match lookup_user(user)? {
+        Some((_, uid, _)) => Ok(uid),
+        None => user
+            .parse::<u32>()
+            .map_err(|_| anyhow::anyhow!("unknown user {:?}", user)),
+    }
+}
+
+/// gid of `group` (a name or numeric id)
+#[cfg(unix)]
+pub fn resolve_gid(group: &str) -> Result<u32> {
+    match lookup_group(group)? {
+        Some(gid) => Ok(gid),
+        None => group
+            .parse::<u32>()
+            .map_err(|_| anyhow::anyhow!("unknown group {:?}", group)),
+    }
+}
+
+/// `(name, uid, primary gid)` of the user called `name`
+#[cfg(unix)]
+fn lookup_user(name: &str) -> Result<Option<(String, u32, u32)>> {
+    let name = std::ffi::CString::new(name)?;
+    passwd_entry(|pwd, buf, result| unsafe {
+        libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result)
+    })
+}
+
+/// `(name, uid, primary gid)` of the user with `uid`
+#[cfg(unix)]
+fn lookup_uid(uid: u32) -> Result<Option<(String, u32, u32)>> {
+    passwd_entry(|pwd, buf, result| unsafe {
+        libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result)
+    })
+}
+
+/// Run a `getpw*_r` lookup, growing its buffer as needed
+#[cfg(unix)]
+fn passwd_entry(
+    mut lookup: impl FnMut(
+        &mut libc::passwd,
+        &mut Vec<libc::c_char>,
+        &mut *mut libc::passwd,
+    ) -> libc::c_int,
+) -> Result<Option<(String, u32, u32)>> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    loop {
+        match lookup(&mut pwd, &mut buf, &mut result) {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if result.is_null() => return Ok(None),
+            0 => break,
+            errno => return Err(std::io::Error::from_raw_os_error(errno).into()),
+        }
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(pwd.pw_name) };
+    Ok(Some((
+        name.to_string_lossy().into_owned(),
+        pwd.pw_uid,
+        pwd.pw_gid,
+    )))
+}
+
+/// gid of the group called `name`
+#[cfg(unix)]
+fn lookup_group(name: &str) -> Result<Option<u32>> {
+    let name = std::ffi::CString::new(name)?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = std::ptr::null_mut();
+    loop {
+        let rc = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut grp,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            0 if result.is_null() => return Ok(None),
+            0 => return Ok(Some(grp.gr_gid)),
+            errno => return Err(std::io::Error::from_raw_os_error(errno).into()),
+        }
+    }
+}
+
+/// The groups `user` belongs to, with `gid` first
+#[cfg(target_os = "linux")]
+fn group_list(user: &str, gid: u32) -> Result<Vec<u32>> {
+    let name = std::ffi::CString::new(user)?;
+    let mut groups = vec![0; 64];
+    loop {
+        let mut n = groups.len() as libc::c_int;
+        let rc = unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut n) };
+        if rc >= 0 {
+            groups.truncate(n as usize);
+            break;
+        }
+        groups.resize(n.max(groups.len() as libc::c_int * 2) as usize, 0);
+    }
+    groups.retain(|g| *g != gid);
+    groups.insert(0, gid);
+    Ok(groups)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn group_list(_user: &str, gid: u32) -> Result<Vec<u32>> {
+    Ok(vec![gid])
+}
+
+/// Hand `path` and everything below it to `uid`/`gid`. Symlinks are changed
+/// themselves, never followed.
+#[cfg(unix)]
+pub fn chown_tree(path: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid))?;
+    if path.symlink_metadata()?.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            chown_tree(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Names of the syscalls the seccomp profile denies on this architecture
+pub fn denied_syscalls() -> impl Iterator<Item = &'static str> {
+    DENIED_SYSCALLS.iter().chain(DENIED_SYSCALLS_ARCH).copied()
+}
+
+/// Number of the capability called `name` (`CAP_` prefix optional)
+pub fn capability_number(name: &str) -> Option<u32> {
+    let name = name.to_ascii_uppercase();
+    let name = if name.starts_with("CAP_") {
+        name
+    } else {
+        format!("CAP_{}", name)
+    };
+    CAPABILITY_NAMES
+        .iter()
+        .position(|c| *c == name)
+        .map(|n| n as u32)
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::ChildHardening;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{Hardening, SeccompAction, SeccompRules};
+    use anyhow::{bail, Result};
+
+    // linux/filter.h and linux/seccomp.h
+    /// BPF_LD | BPF_W | BPF_ABS
+    const BPF_LD_W_ABS: u16 = 0x20;
+    /// BPF_JMP | BPF_JEQ | BPF_K
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    /// BPF_JMP | BPF_JGE | BPF_K
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    /// BPF_JMP | BPF_JSET | BPF_K
+    const BPF_JMP_JSET_K: u16 = 0x45;
+    /// BPF_RET | BPF_K
+    const BPF_RET_K: u16 = 0x06;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_KILL_THREAD: u32 = 0;
+    const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+    /// Longest program the kernel accepts
+    const BPF_MAXINSNS: usize = 4096;
+    const SECCOMP_MODE_FILTER: libc::c_ulong = 2;
+    /// Offsets into `struct seccomp_data`
+    const SECCOMP_DATA_NR: u32 = 0;
+    const SECCOMP_DATA_ARCH: u32 = 4;
+    /// Low word of the first argument (both architectures are little-endian)
+    const SECCOMP_DATA_ARG0: u32 = 16;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    /// x32 ABI syscalls share the x86_64 audit arch; deny them wholesale
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// `(name, number)` pairs from the `libc::SYS_*` constants
+    macro_rules! syscalls {
+        ($($sys:ident),* $(,)?) => {
+            &[$((stringify!($sys), libc::$sys)),*]
+        };
+    }
+
+    /// Syscalls both supported architectures have
+    const SYSCALLS: &[(&str, libc::c_long)] = syscalls![
+        SYS_accept,
+        SYS_accept4,
+        SYS_acct,
+        SYS_add_key,
+        SYS_adjtimex,
+        SYS_bind,
+        SYS_bpf,
+        SYS_brk,
+        SYS_capget,
+        SYS_capset,
+        SYS_chdir,
+        SYS_chroot,
+        SYS_clock_adjtime,
+        SYS_clock_getres,
+        SYS_clock_gettime,
+        SYS_clock_nanosleep,
+        SYS_clock_settime,
+        SYS_clone,
+        SYS_clone3,
+        SYS_close,
+        SYS_close_range,
+        SYS_connect,
+        SYS_copy_file_range,
+        SYS_delete_module,
+        SYS_dup,
+        SYS_dup3,
+        SYS_epoll_create1,
+        SYS_epoll_ctl,
+        SYS_epoll_pwait,
+        SYS_epoll_pwait2,
+        SYS_eventfd2,
+        SYS_execve,
+        SYS_execveat,
+        SYS_exit,
+        SYS_exit_group,
+        SYS_faccessat,
+        SYS_faccessat2,
+        SYS_fadvise64,
+        SYS_fallocate,
+        SYS_fanotify_init,
+        SYS_fanotify_mark,
+        SYS_fchdir,
+        SYS_fchmod,
+        SYS_fchmodat,
+        SYS_fchown,
+        SYS_fchownat,
+        SYS_fcntl,
+        SYS_fdatasync,
+        SYS_fgetxattr,
+        SYS_finit_module,
+        SYS_flistxattr,
+        SYS_flock,
+        SYS_fremovexattr,
+        SYS_fsconfig,
+        SYS_fsetxattr,
+        SYS_fsmount,
+        SYS_fsopen,
+        SYS_fspick,
+        SYS_fstat,
+        SYS_fstatfs,
+        SYS_fsync,
+        SYS_ftruncate,
+        SYS_futex,
+        SYS_futex_waitv,
+        SYS_get_mempolicy,
+        SYS_get_robust_list,
+        SYS_getcpu,
+        SYS_getcwd,
+        SYS_getdents64,
+        SYS_getegid,
+        SYS_geteuid,
+        SYS_getgid,
+        SYS_getgroups,
+        SYS_getitimer,
+        SYS_getpeername,
+        SYS_getpgid,
+        SYS_getpid,
+        SYS_getppid,
+        SYS_getpriority,
+        SYS_getrandom,
+        SYS_getresgid,
+        SYS_getresuid,
+        SYS_getrusage,
+        SYS_getsid,
+        SYS_getsockname,
+        SYS_getsockopt,
+        SYS_gettid,
+        SYS_gettimeofday,
+        SYS_getuid,
+        SYS_getxattr,
+        SYS_init_module,
+        SYS_inotify_add_watch,
+        SYS_inotify_init1,
+        SYS_inotify_rm_watch,
+        SYS_io_cancel,
+        SYS_io_destroy,
+        SYS_io_getevents,
+        SYS_io_setup,
+        SYS_io_submit,
+        SYS_io_uring_enter,
+        SYS_io_uring_register,
+        SYS_io_uring_setup,
+        SYS_ioctl,
+        SYS_ioprio_get,
+        SYS_ioprio_set,
+        SYS_kcmp,
+        SYS_kexec_load,
+        SYS_keyctl,
+        SYS_kill,
+        SYS_landlock_add_rule,
+        SYS_landlock_create_ruleset,
+        SYS_landlock_restrict_self,
+        SYS_lgetxattr,
+        SYS_linkat,
+        SYS_listen,
+        SYS_listxattr,
+        SYS_llistxattr,
+        SYS_lookup_dcookie,
+        SYS_lremovexattr,
+        SYS_lseek,
+        SYS_lsetxattr,
+        SYS_madvise,
+        SYS_mbind,
+        SYS_membarrier,
+        SYS_memfd_create,
+        SYS_memfd_secret,
+        SYS_migrate_pages,
+        SYS_mincore,
+        SYS_mkdirat,
+        SYS_mknodat,
+        SYS_mlock,
+        SYS_mlock2,
+        SYS_mlockall,
+        SYS_mmap,
+        SYS_mount,
+        SYS_mount_setattr,
+        SYS_move_mount,
+        SYS_move_pages,
+        SYS_mprotect,
+        SYS_mq_getsetattr,
+        SYS_mq_notify,
+        SYS_mq_open,
+        SYS_mq_timedreceive,
+        SYS_mq_timedsend,
+        SYS_mq_unlink,
+        SYS_mremap,
+        SYS_mseal,
+        SYS_msgctl,
+        SYS_msgget,
+        SYS_msgrcv,
+        SYS_msgsnd,
+        SYS_msync,
+        SYS_munlock,
+        SYS_munlockall,
+        SYS_munmap,
+        SYS_name_to_handle_at,
+        SYS_nanosleep,
+        SYS_newfstatat,
+        SYS_nfsservctl,
+        SYS_open_by_handle_at,
+        SYS_open_tree,
+        SYS_openat,
+        SYS_openat2,
+        SYS_perf_event_open,
+        SYS_personality,
+        SYS_pidfd_getfd,
+        SYS_pidfd_open,
+        SYS_pidfd_send_signal,
+        SYS_pipe2,
+        SYS_pivot_root,
+        SYS_pkey_alloc,
+        SYS_pkey_free,
+        SYS_pkey_mprotect,
+        SYS_ppoll,
+        SYS_prctl,
+        SYS_pread64,
+        SYS_preadv,
+        SYS_preadv2,
+        SYS_prlimit64,
+        SYS_process_madvise,
+        SYS_process_mrelease,
+        SYS_process_vm_readv,
+        SYS_process_vm_writev,
+        SYS_pselect6,
+        SYS_ptrace,
+        SYS_pwrite64,
+        SYS_pwritev,
+        SYS_pwritev2,
+        SYS_quotactl,
+        SYS_quotactl_fd,
+        SYS_read,
+        SYS_readahead,
+        SYS_readlinkat,
+        SYS_readv,
+        SYS_reboot,
+        SYS_recvfrom,
+        SYS_recvmmsg,
+        SYS_recvmsg,
+        SYS_remap_file_pages,
+        SYS_removexattr,
+        SYS_renameat2,
+        SYS_request_key,
+        SYS_restart_syscall,
+        SYS_rseq,
+        SYS_rt_sigaction,
+        SYS_rt_sigpending,
+        SYS_rt_sigprocmask,
+        SYS_rt_sigqueueinfo,
+        SYS_rt_sigreturn,
+        SYS_rt_sigsuspend,
+        SYS_rt_sigtimedwait,
+        SYS_rt_tgsigqueueinfo,
+        SYS_sched_get_priority_max,
+        SYS_sched_get_priority_min,
+        SYS_sched_getaffinity,
+        SYS_sched_getattr,
+        SYS_sched_getparam,
+        SYS_sched_getscheduler,
+        SYS_sched_rr_get_interval,
+        SYS_sched_setaffinity,
+        SYS_sched_setattr,
+        SYS_sched_setparam,
+        SYS_sched_setscheduler,
+        SYS_sched_yield,
+        SYS_seccomp,
+        SYS_semctl,
+        SYS_semget,
+        SYS_semop,
+        SYS_semtimedop,
+        SYS_sendfile,
+        SYS_sendmmsg,
+        SYS_sendmsg,
+        SYS_sendto,
+        SYS_set_mempolicy,
+        SYS_set_mempolicy_home_node,
+        SYS_set_robust_list,
+        SYS_set_tid_address,
+        SYS_setdomainname,
+        SYS_setfsgid,
+        SYS_setfsuid,
+        SYS_setgid,
+        SYS_setgroups,
+        SYS_sethostname,
+        SYS_setitimer,
+        SYS_setns,
+        SYS_setpgid,
+        SYS_setpriority,
+        SYS_setregid,
+        SYS_setresgid,
+        SYS_setresuid,
+        SYS_setreuid,
+        SYS_setsid,
+        SYS_setsockopt,
+        SYS_settimeofday,
+        SYS_setuid,
+        SYS_setxattr,
+        SYS_shmat,
+        SYS_shmctl,
+        SYS_shmdt,
+        SYS_shmget,
+        SYS_shutdown,
+        SYS_sigaltstack,
+        SYS_signalfd4,
+        SYS_socket,
+        SYS_socketpair,
+        SYS_splice,
+        SYS_statfs,
+        SYS_statx,
+        SYS_swapoff,
+        SYS_swapon,
+        SYS_symlinkat,
+        SYS_sync,
+        SYS_syncfs,
+        SYS_sysinfo,
+        SYS_syslog,
+        SYS_tee,
+        SYS_tgkill,
+        SYS_timer_create,
+        SYS_timer_delete,
+        SYS_timer_getoverrun,
+        SYS_timer_gettime,
+        SYS_timer_settime,
+        SYS_timerfd_create,
+        SYS_timerfd_gettime,
+        SYS_timerfd_settime,
+        SYS_times,
+        SYS_tkill,
+        SYS_truncate,
+        SYS_umask,
+        SYS_umount2,
+        SYS_uname,
+        SYS_unlinkat,
+        SYS_unshare,
+        SYS_userfaultfd,
+        SYS_utimensat,
+        SYS_vhangup,
+        SYS_vmsplice,
+        SYS_wait4,
+        SYS_waitid,
+        SYS_write,
+        SYS_writev,
+    ];
+
+    /// x86_64-only syscalls
+    #[cfg(target_arch = "x86_64")]
+    const SYSCALLS_ARCH: &[(&str, libc::c_long)] = syscalls![
+        SYS__sysctl,
+        SYS_access,
+        SYS_afs_syscall,
+        SYS_alarm,
+        SYS_arch_prctl,
+        SYS_chmod,
+        SYS_chown,
+        SYS_creat,
+        SYS_dup2,
+        SYS_epoll_create,
+        SYS_epoll_ctl_old,
+        SYS_epoll_wait,
+        SYS_epoll_wait_old,
+        SYS_eventfd,
+        SYS_fchmodat2,
+        SYS_fork,
+        SYS_futimesat,
+        SYS_get_thread_area,
+        SYS_getdents,
+        SYS_getpgrp,
+        SYS_getpmsg,
+        SYS_getrlimit,
+        SYS_inotify_init,
+        SYS_ioperm,
+        SYS_iopl,
+        SYS_kexec_file_load,
+        SYS_lchown,
+        SYS_link,
+        SYS_lstat,
+        SYS_mkdir,
+        SYS_mknod,
+        SYS_modify_ldt,
+        SYS_open,
+        SYS_pause,
+        SYS_pipe,
+        SYS_poll,
+        SYS_putpmsg,
+        SYS_readlink,
+        SYS_rename,
+        SYS_renameat,
+        SYS_rmdir,
+        SYS_security,
+        SYS_select,
+        SYS_set_thread_area,
+        SYS_setrlimit,
+        SYS_signalfd,
+        SYS_stat,
+        SYS_symlink,
+        SYS_sync_file_range,
+        SYS_sysfs,
+        SYS_time,
+        SYS_tuxcall,
+        SYS_unlink,
+        SYS_uselib,
+        SYS_ustat,
+        SYS_utime,
+        SYS_utimes,
+        SYS_vfork,
+        SYS_vserver,
+    ];
+    #[cfg(not(target_arch = "x86_64"))]
+    const SYSCALLS_ARCH: &[(&str, libc::c_long)] = &[];
+
+    /// Syscall number of `name` on this architecture
+    fn syscall_number(name: &str) -> Option<libc::c_long> {
+        // Removed syscalls libc has no constants for
+        #[cfg(target_arch = "x86_64")]
+        match name {
+            "create_module" => return Some(174),
+            "get_kernel_syms" => return Some(177),
+            "query_module" => return Some(178),
+            "nfsservctl" => return Some(180),
+            _ => {}
+        }
+        SYSCALLS
+            .iter()
+            .chain(SYSCALLS_ARCH)
+            .find(|(sys, _)| sys.strip_prefix("SYS_") == Some(name))
+            .map(|(_, nr)| *nr)
+    }
+
+    fn stmt(code: u16, k: u32) -> libc::sock_filter {
+        libc::sock_filter {
+            code,
+            jt: 0,
+            jf: 0,
+            k,
+        }
+    }
+
+    fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    /// The filter's return value for `action`
+    fn ret(action: SeccompAction) -> u32 {
+        match action {
+            SeccompAction::Allow => SECCOMP_RET_ALLOW,
+            SeccompAction::Errno(errno) => SECCOMP_RET_ERRNO | (errno & SECCOMP_RET_DATA),
+            SeccompAction::Log => SECCOMP_RET_LOG,
+            SeccompAction::Trap => SECCOMP_RET_TRAP,
+            SeccompAction::KillThread => SECCOMP_RET_KILL_THREAD,
+            SeccompAction::KillProcess => SECCOMP_RET_KILL_PROCESS,
+        }
+    }
+
+    /// The seccomp program for `rules`. Syscalls of a foreign architecture
+    /// fail with EPERM; names this architecture doesn't have are skipped.
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    pub(super) fn seccomp_filter(rules: &SeccompRules) -> Result<Vec<libc::sock_filter>> {
+        let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+        let mut prog = vec![
+            stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH),
+            jump(BPF_JMP_JEQ_K, AUDIT_ARCH, 1, 0),
+            stmt(BPF_RET_K, deny),
+            stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        {
+            prog.push(jump(BPF_JMP_JGE_K, X32_SYSCALL_BIT, 0, 1));
+            prog.push(stmt(BPF_RET_K, deny));
+        }
+        if rules.denied_clone_flags != 0 {
+            // Not clone: skip to reloading the syscall number
+            prog.push(jump(BPF_JMP_JEQ_K, libc::SYS_clone as u32, 0, 3));
+            prog.push(stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARG0));
+            prog.push(jump(BPF_JMP_JSET_K, rules.denied_clone_flags, 0, 1));
+            prog.push(stmt(BPF_RET_K, deny));
+            prog.push(stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR));
+        }
+        let mut seen = Vec::new();
+        for (name, action) in &rules.syscalls {
+            let Some(nr) = syscall_number(name) else {
+                continue;
+            };
+            // The first rule for a syscall wins; later ones are dead code
+            if *action == rules.default_action || seen.contains(&nr) {
+                seen.push(nr);
+                continue;
+            }
+            seen.push(nr);
+            prog.push(jump(BPF_JMP_JEQ_K, nr as u32, 0, 1));
+            prog.push(stmt(BPF_RET_K, ret(*action)));
+        }
+        prog.push(stmt(BPF_RET_K, ret(rules.default_action)));
+        if prog.len() > BPF_MAXINSNS {
+            bail!("seccomp profile has too many rules");
+        }
+        Ok(prog)
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    pub(super) fn seccomp_filter(_rules: &SeccompRules) -> Result<Vec<libc::sock_filter>> {
+        bail!("seccomp filters are only available on x86_64 and aarch64")
+    }
+
+    /// Capabilities, credentials, no-new-privs and seccomp for a forked
+    /// child, prepared before the fork so [`ChildHardening::apply`] doesn't
+    /// allocate.
+    pub struct ChildHardening {
+        no_new_privs: bool,
+        /// Capabilities to drop from the bounding set
+        drop_caps: Vec<libc::c_ulong>,
+        /// (uid, gid, supplementary groups) to switch to
+        run_as: Option<(libc::uid_t, libc::gid_t, Vec<libc::gid_t>)>,
+        filter: Option<Vec<libc::sock_filter>>,
+    }
+
+    impl ChildHardening {
+        pub fn prepare(hardening: &Hardening) -> Result<Self> {
+            let mut drop_caps = Vec::new();
+            // Only root can shrink the bounding set; other users' children
+            // start without capabilities anyway
+            if let Some(keep) = hardening.capabilities {
+                if unsafe { libc::geteuid() } == 0 {
+                    let keep = keep
+                        .iter()
+                        .map(|name| match super::capability_number(name) {
+                            Some(n) => Ok(n),
+                            None => bail!("Unknown capability: {}", name),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let last_cap = std::fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+                        .ok()
+                        .and_then(|s| s.trim().parse::<u32>().ok())
+                        .unwrap_or(super::CAPABILITY_NAMES.len() as u32 - 1);
+                    drop_caps = (0..=last_cap)
+                        .filter(|cap| !keep.contains(cap))
+                        .map(libc::c_ulong::from)
+                        .collect();
+                }
+            }
+            let run_as = hardening
+                .run_as
+                .as_ref()
+                .map(|r| (r.uid, r.gid, r.groups.clone()));
+            if let Some((uid, ..)) = &run_as {
+                let euid = unsafe { libc::geteuid() };
+                if euid != 0 && euid != *uid {
+                    bail!("Switching to uid {} needs the daemon to run as root", uid);
+                }
+            }
+            let filter = match hardening.seccomp.rules()? {
+                Some(rules) => Some(seccomp_filter(&rules)?),
+                None => None,
+            };
+            Ok(Self {
+                no_new_privs: hardening.no_new_privs,
+                drop_caps,
+                run_as,
+                filter,
+            })
+        }
+
+        /// Apply in the forked child, after any mounts and right before exec
+        /// (the seccomp filter denies `mount` and `unshare`).
+        pub fn apply(&self) -> std::io::Result<()> {
+            unsafe {
+                for cap in &self.drop_caps {
+                    if libc::prctl(libc::PR_CAPBSET_DROP, *cap, 0, 0, 0) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if !self.drop_caps.is_empty() {
+                    // Not fatal on kernels without ambient capabilities
+                    libc::prctl(
+                        libc::PR_CAP_AMBIENT,
+                        libc::PR_CAP_AMBIENT_CLEAR_ALL as libc::c_ulong,
+                        0,
+                        0,
+                        0,
+                    );
+                }
+                // After the bounding set (which needs CAP_SETPCAP); the
+                // switch away from uid 0 clears the remaining capabilities
+                if let Some((uid, gid, groups)) = &self.run_as {
+                    if libc::getuid() == 0 && libc::setgroups(groups.len(), groups.as_ptr()) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    if libc::setresgid(*gid, *gid, *gid) != 0
+                        || libc::setresuid(*uid, *uid, *uid) != 0
+                    {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                // Unprivileged processes can only install a filter under
+                // no-new-privs
+                if (self.no_new_privs || self.filter.is_some())
+                    && libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+                {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if let Some(filter) = &self.filter {
+                    let prog = libc::sock_fprog {
+                        len: filter.len() as libc::c_ushort,
+                        filter: filter.as_ptr() as *mut libc::sock_filter,
+                    };
+                    if libc::prctl(
+                        libc::PR_SET_SECCOMP,
+                        SECCOMP_MODE_FILTER,
+                        &prog as *const libc::sock_fprog,
+                        0,
+                        0,
+                    ) != 0
+                    {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[cfg(all(test, any(target_arch = "x86_64", target_arch = "aarch64")))]
+    mod tests {
+        use super::*;
+
+        /// Run the filter against one syscall, like the kernel would
+        fn run(prog: &[libc::sock_filter], arch: u32, nr: u32) -> u32 {
+            run_with_arg(prog, arch, nr, 0)
+        }
+
+        /// [`run`] with the low word of the first argument
+        fn run_with_arg(prog: &[libc::sock_filter], arch: u32, nr: u32, arg0: u32) -> u32 {
+            let mut acc = 0;
+            let mut pc = 0;
+            loop {
+                let ins = prog[pc];
+                match ins.code {
+                    BPF_LD_W_ABS => {
+                        acc = match ins.k {
+                            SECCOMP_DATA_ARCH => arch,
+                            SECCOMP_DATA_ARG0 => arg0,
+                            _ => nr,
+                        };
+                        pc += 1;
+                    }
+                    BPF_JMP_JEQ_K | BPF_JMP_JGE_K | BPF_JMP_JSET_K => {
+                        let hit = match ins.code {
+                            BPF_JMP_JEQ_K => acc == ins.k,
+                            BPF_JMP_JGE_K => acc >= ins.k,
+                            _ => acc & ins.k != 0,
+                        };
+                        pc += 1 + usize::from(if hit { ins.jt } else { ins.jf });
+                    }
+                    BPF_RET_K => return ins.k,
+                    code => panic!("unexpected instruction {code:#x}"),
+                }
+            }
+        }
+
+        #[test]
+        fn test_seccomp_filter() {
+            let prog = seccomp_filter(&SeccompRules::builtin()).unwrap();
+            let deny = SECCOMP_RET_ERRNO | libc::EPERM as u32;
+            for nr in [libc::SYS_ptrace, libc::SYS_mount, libc::SYS_userfaultfd] {
+                assert_eq!(run(&prog, AUDIT_ARCH, nr as u32), deny);
+            }
+            for nr in [libc::SYS_read, libc::SYS_write, libc::SYS_socket] {
+                assert_eq!(run(&prog, AUDIT_ARCH, nr as u32), SECCOMP_RET_ALLOW);
+            }
+            for nr in [
+                libc::SYS_fsopen,
+                libc::SYS_open_tree,
+                libc::SYS_io_uring_setup,
+            ] {
+                assert_eq!(run(&prog, AUDIT_ARCH, nr as u32), deny);
+            }
+
+            // Threads and forks work, new namespaces don't
+            let clone = libc::SYS_clone as u32;
+            let thread = (libc::CLONE_VM | libc::CLONE_THREAD | libc::CLONE_SIGHAND) as u32;
+            assert_eq!(
+                run_with_arg(&prog, AUDIT_ARCH, clone, thread),
+                SECCOMP_RET_ALLOW
+            );
+            assert_eq!(
+                run_with_arg(&prog, AUDIT_ARCH, clone, libc::SIGCHLD as u32),
+                SECCOMP_RET_ALLOW
+            );
+            for flag in [libc::CLONE_NEWUSER, libc::CLONE_NEWNS, libc::CLONE_NEWNET] {
+                let flags = libc::SIGCHLD as u32 | flag as u32;
+                assert_eq!(run_with_arg(&prog, AUDIT_ARCH, clone, flags), deny);
+            }
+            // clone3 looks unimplemented, so libc falls back to clone
+            assert_eq!(
+                run(&prog, AUDIT_ARCH, libc::SYS_clone3 as u32),
+                SECCOMP_RET_ERRNO | super::super::ENOSYS
+            );
+            // The syscall number is reloaded after the clone check
+            assert_eq!(
+                run_with_arg(&prog, AUDIT_ARCH, libc::SYS_ptrace as u32, u32::MAX),
+                deny
+            );
+            // Another ABI can't be used to get around the list
+            assert_eq!(run(&prog, 0x4000_0003, libc::SYS_read as u32), deny);
+            #[cfg(target_arch = "x86_64")]
+            assert_eq!(
+                run(&prog, AUDIT_ARCH, X32_SYSCALL_BIT | libc::SYS_ptrace as u32),
+                deny
+            );
+        }
+
+        #[test]
+        fn test_seccomp_filter_from_profile() {
+            let rules = SeccompRules::parse(
+                r#"{
+                    "defaultAction": "SCMP_ACT_ERRNO",
+                    "defaultErrnoRet": 38,
+                    "syscalls": [
+                        {"names": ["read", "write", "no_such_syscall"], "action": "SCMP_ACT_ALLOW"},
+                        {"name": "ptrace", "action": "SCMP_ACT_KILL_PROCESS"},
+                        {"names": ["read"], "action": "SCMP_ACT_KILL_PROCESS"}
+                    ]
+                }"#,
+            )
+            .unwrap();
+            let prog = seccomp_filter(&rules).unwrap();
+            let read = libc::SYS_read as u32;
+            assert_eq!(run(&prog, AUDIT_ARCH, read), SECCOMP_RET_ALLOW);
+            assert_eq!(
+                run(&prog, AUDIT_ARCH, libc::SYS_write as u32),
+                SECCOMP_RET_ALLOW
+            );
+            assert_eq!(
+                run(&prog, AUDIT_ARCH, libc::SYS_ptrace as u32),
+                SECCOMP_RET_KILL_PROCESS
+            );
+            assert_eq!(
+                run(&prog, AUDIT_ARCH, libc::SYS_socket as u32),
+                SECCOMP_RET_ERRNO | 38
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_presets() {
+        assert!(!SecurityProfile::Permissive.hardening().is_enabled());
+
+        let standard = SecurityProfile::Standard.hardening();
+        assert!(standard.no_new_privs && standard.private_tmp);
+        assert_eq!(standard.seccomp, SeccompProfile::Default);
+        assert!(!standard.read_only_root);
+        assert_eq!(standard.capabilities, Some(DEFAULT_CAPABILITIES));
+
+        let strict = SecurityProfile::Strict.hardening();
+        assert!(strict.read_only_root && strict.needs_mounts());
+        assert_eq!(strict.capabilities, Some(STRICT_CAPABILITIES));
+        assert_eq!(SecurityProfile::Strict.to_string(), "strict");
+    }
+
+    #[test]
+    fn test_seccomp_profile() {
+        assert_eq!(
+            SeccompProfile::from("default".to_string()),
+            SeccompProfile::Default
+        );
+        assert_eq!(
+            SeccompProfile::from("/etc/tenement/api.json".to_string()),
+            SeccompProfile::File(PathBuf::from("/etc/tenement/api.json"))
+        );
+        assert!(SeccompProfile::Unconfined.rules().unwrap().is_none());
+        let builtin = SeccompProfile::Default.rules().unwrap().unwrap();
+        assert_eq!(builtin.default_action, SeccompAction::Allow);
+        assert!(builtin
+            .syscalls
+            .contains(&("ptrace".to_string(), SeccompAction::Errno(1))));
+
+        let rules = SeccompRules::parse(
+            r#"{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [
+                {"names": ["mount"], "action": "SCMP_ACT_ERRNO", "errnoRet": 13}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.syscalls,
+            [("mount".to_string(), SeccompAction::Errno(13))]
+        );
+        let args = r#"{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [
+            {"names": ["personality"], "action": "SCMP_ACT_ALLOW",
+             "args": [{"index": 0, "value": 0, "op": "SCMP_CMP_EQ"}]}
+        ]}"#;
+        assert!(SeccompRules::parse(args)
+            .unwrap_err()
+            .to_string()
+            .contains("argument"));
+        assert!(SeccompRules::parse(r#"{"defaultAction": "SCMP_ACT_NOTIFY"}"#).is_err());
+        assert!(
+            SeccompProfile::File(PathBuf::from("/nonexistent/profile.json"))
+                .rules()
+                .is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_as_resolve() {
+        let root = RunAs::resolve("root", None).unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(root.groups[0], 0);
+        assert_eq!(RunAs::resolve("0", None).unwrap().uid, 0);
+
+        // A uid without a passwd entry needs an explicit group
+        let unknown = RunAs::resolve("4000000", Some("4000001")).unwrap();
+        assert_eq!((unknown.uid, unknown.gid), (4_000_000, 4_000_001));
+        assert_eq!(unknown.groups, [4_000_001]);
+        assert!(RunAs::resolve("4000000", None).is_err());
+
+        assert!(RunAs::resolve("no-such-user-tenement", None).is_err());
+        assert!(RunAs::resolve("root", Some("no-such-group-tenement")).is_err());
+    }
+
+    #[test]
+    fn test_tmpfs_config() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            tmpfs: Vec<Tmpfs>,
+        }
+        let w: Wrapper =
+            toml::from_str(r#"tmpfs = ["/run", { path = "/tmp", size_mb = 64 }]"#).unwrap();
+        assert_eq!(w.tmpfs[0].path, PathBuf::from("/run"));
+        assert_eq!(w.tmpfs[0].options(), "mode=1777");
+        assert_eq!(w.tmpfs[1].size_mb, Some(64));
+        assert_eq!(w.tmpfs[1].options(), "mode=1777,size=64m");
+
+        let hardening = Hardening {
+            tmpfs: w.tmpfs,
+            ..Default::default()
+        };
+        assert!(hardening.is_enabled() && hardening.needs_mounts());
+    }
+
+    #[test]
+    fn test_capability_number() {
+        assert_eq!(capability_number("CAP_CHOWN"), Some(0));
+        assert_eq!(capability_number("net_bind_service"), Some(10));
+        assert_eq!(capability_number("CAP_SYS_ADMIN"), Some(21));
+        assert_eq!(capability_number("CAP_NOPE"), None);
+        for name in DEFAULT_CAPABILITIES {
+            assert!(capability_number(name).is_some(), "{name}");
+        }
+    }
+}
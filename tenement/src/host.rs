@@ -0,0 +1,348 @@
+//! Host-level stats: load average, memory, and disk/inode usage of the
+//! filesystems holding the data dir
+//!
+//! Single-server operators often have no other monitoring, so these are
+//! exported alongside the instance metrics and at `/api/host`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// A snapshot of the host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostStats {
+    /// 1, 5 and 15 minute load averages
+    pub load_average: [f64; 3],
+    /// Total memory (None where /proc/meminfo is unavailable)
+    pub memory_total_bytes: Option<u64>,
+    /// Memory available for new work without swapping
+    pub memory_available_bytes: Option<u64>,
+    /// The mount holding the data dir, then any mounted below it
+    pub disks: Vec<DiskUsage>,
+}
+
+/// Space and inode usage of one mounted filesystem
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub mount_point: PathBuf,
+    pub total_bytes: u64,
+    /// Bytes available to unprivileged users
+    pub available_bytes: u64,
+    pub inodes_total: u64,
+    pub inodes_free: u64,
+}
+
+impl DiskUsage {
+    /// How full the filesystem is, in percent: the higher of space used
+    /// (counting reserved blocks) and inodes used
+    pub fn used_percent(&self) -> f64 {
+        let used = |total: u64, free: u64| {
+            if total == 0 {
+                0.0
+            } else {
+                total.saturating_sub(free) as f64 * 100.0 / total as f64
+            }
+        };
+        used(self.total_bytes, self.available_bytes).max(used(self.inodes_total, self.inodes_free))
+    }
+}
+
+impl HostStats {
+    /// Collect stats for the filesystems used by `data_dir`.
+    /// Runs on a blocking thread: statvfs on a hung network mount can block.
+    pub async fn collect(data_dir: &Path) -> Result<Self> {
+        let data_dir = data_dir.to_path_buf();
+        tokio::task::spawn_blocking(move || Self::collect_blocking(&data_dir))
+            .await
+            .context("Host stats task failed")?
+    }
+
+    fn collect_blocking(data_dir: &Path) -> Result<Self> {
+        // Before first use the data dir may not exist yet: use the nearest
+        // existing ancestor, which is on the filesystem it will be created on
+        let absolute = std::path::absolute(data_dir)
+            .with_context(|| format!("Invalid data dir: {:?}", data_dir))?;
+        let data_dir = absolute
+            .ancestors()
+            .find_map(|dir| dir.canonicalize().ok())
+            .unwrap_or(absolute);
+        let (memory_total_bytes, memory_available_bytes) =
+            match std::fs::read_to_string("/proc/meminfo") {
+                Ok(meminfo) => parse_meminfo(&meminfo),
+                Err(_) => (None, None),
+            };
+        let mount_points = match std::fs::read_to_string("/proc/self/mounts") {
+            Ok(mounts) => mounts_for(&data_dir, &parse_mounts(&mounts)),
+            Err(_) => vec![data_dir.clone()],
+        };
+        let mut disks = Vec::new();
+        for mount_point in mount_points {
+            // Nested mounts we can't stat (permissions, gone) are skipped;
+            // the data dir's own filesystem is not optional
+            match disk_usage(&mount_point) {
+                Ok(disk) => disks.push(disk),
+                Err(e) if disks.is_empty() => return Err(e),
+                Err(e) => tracing::debug!("Skipping mount {:?}: {}", mount_point, e),
+            }
+        }
+        Ok(Self {
+            load_average: load_average(),
+            memory_total_bytes,
+            memory_available_bytes,
+            disks,
+        })
+    }
+
+    /// Prometheus text format, in the style of [`crate::Metrics::format_prometheus`]
+    pub fn format_prometheus(&self) -> String {
+        let mut output = String::new();
+
+        output.push_str("\n# HELP tenement_host_load_average Host load average\n");
+        output.push_str("# TYPE tenement_host_load_average gauge\n");
+        for (window, load) in ["1m", "5m", "15m"].iter().zip(self.load_average) {
+            output.push_str(&format!(
+                "tenement_host_load_average{{window=\"{}\"}} {:.2}\n",
+                window, load
+            ));
+        }
+
+        if let Some(total) = self.memory_total_bytes {
+            output.push_str("\n# HELP tenement_host_memory_total_bytes Total host memory\n");
+            output.push_str("# TYPE tenement_host_memory_total_bytes gauge\n");
+            output.push_str(&format!("tenement_host_memory_total_bytes {}\n", total));
+        }
+        if let Some(available) = self.memory_available_bytes {
+            output.push_str(
+                "\n# HELP tenement_host_memory_available_bytes Host memory available without swapping\n",
+            );
+            output.push_str("# TYPE tenement_host_memory_available_bytes gauge\n");
+            output.push_str(&format!(
+                "tenement_host_memory_available_bytes {}\n",
+                available
+            ));
+        }
+
+        let gauges: [DiskGauge; 4] = [
+            ("disk_total_bytes", "Filesystem size", |d| d.total_bytes),
+            (
+                "disk_available_bytes",
+                "Filesystem bytes available to unprivileged users",
+                |d| d.available_bytes,
+            ),
+            ("inodes_total", "Filesystem inodes", |d| d.inodes_total),
+            ("inodes_free", "Free filesystem inodes", |d| d.inodes_free),
+        ];
+        for (name, help, value) in gauges {
+            output.push_str(&format!(
+                "\n# HELP tenement_host_{} {}\n# TYPE tenement_host_{} gauge\n",
+                name, help, name
+            ));
+            for disk in &self.disks {
+                output.push_str(&format!(
+                    "tenement_host_{}{{mount=\"{}\"}} {}\n",
+                    name,
+                    escape_label(&disk.mount_point.to_string_lossy()),
+                    value(disk)
+                ));
+            }
+        }
+
+        output
+    }
+}
+
+/// Metric name suffix, help text and value of a per-mount gauge
+type DiskGauge = (&'static str, &'static str, fn(&DiskUsage) -> u64);
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(unix)]
+fn load_average() -> [f64; 3] {
+    let mut load = [0f64; 3];
+    // SAFETY: getloadavg writes at most 3 values into the buffer
+    if unsafe { libc::getloadavg(load.as_mut_ptr(), 3) } < 0 {
+        return [0.0; 3];
+    }
+    load
+}
+
+#[cfg(not(unix))]
+fn load_average() -> [f64; 3] {
+    [0.0; 3]
+}
+
+#[cfg(unix)]
+fn disk_usage(path: &Path) -> Result<DiskUsage> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .with_context(|| format!("Invalid path: {:?}", path))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: c_path is NUL-terminated and stat is a valid out pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("statvfs failed for {:?}", path));
+    }
+    let fragment = stat.f_frsize as u64;
+    Ok(DiskUsage {
+        mount_point: path.to_path_buf(),
+        total_bytes: stat.f_blocks as u64 * fragment,
+        available_bytes: stat.f_bavail as u64 * fragment,
+        inodes_total: stat.f_files as u64,
+        inodes_free: stat.f_ffree as u64,
+    })
+}
+
+#[cfg(not(unix))]
+fn disk_usage(path: &Path) -> Result<DiskUsage> {
+    anyhow::bail!("Disk usage is not supported on this platform: {:?}", path)
+}
+
+/// (MemTotal, MemAvailable) in bytes from /proc/meminfo
+fn parse_meminfo(meminfo: &str) -> (Option<u64>, Option<u64>) {
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let rest = line.strip_prefix(name)?.strip_prefix(':')?;
+            let kb: u64 = rest.split_whitespace().next()?.parse().ok()?;
+            Some(kb * 1024)
+        })
+    };
+    (field("MemTotal"), field("MemAvailable"))
+}
+
+/// Mount points from /proc/self/mounts, with octal escapes (`\040`) decoded
+fn parse_mounts(mounts: &str) -> Vec<PathBuf> {
+    mounts
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|field| {
+            let mut decoded = Vec::new();
+            let bytes = field.as_bytes();
+            let mut i = 0;
+            while i < bytes.len() {
+                let escape = bytes
+                    .get(i + 1..i + 4)
+                    .and_then(|oct| u8::from_str_radix(std::str::from_utf8(oct).ok()?, 8).ok());
+                match escape {
+                    Some(byte) if bytes[i] == b'\\' => {
+                        decoded.push(byte);
+                        i += 4;
+                    }
+                    _ => {
+                        decoded.push(bytes[i]);
+                        i += 1;
+                    }
+                }
+            }
+            PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+        })
+        .collect()
+}
+
+/// The mount holding `dir` (the longest mount point it's under), then the
+/// mounts below `dir`, without duplicates
+fn mounts_for(dir: &Path, mount_points: &[PathBuf]) -> Vec<PathBuf> {
+    let containing = mount_points
+        .iter()
+        .filter(|mp| dir.starts_with(mp))
+        .max_by_key(|mp| mp.components().count())
+        .cloned()
+        .unwrap_or_else(|| dir.to_path_buf());
+    let mut result = vec![containing];
+    for mp in mount_points {
+        if mp.starts_with(dir) && !result.contains(mp) {
+            result.push(mp.clone());
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_meminfo() {
+        let meminfo = "MemTotal:       16318412 kB\nMemFree:         1022828 kB\nMemAvailable:    9563120 kB\n";
+        assert_eq!(
+            parse_meminfo(meminfo),
+            (Some(16318412 * 1024), Some(9563120 * 1024))
+        );
+        assert_eq!(parse_meminfo("MemTotal: 10 kB\n"), (Some(10240), None));
+    }
+
+    #[test]
+    fn test_mounts_for_data_dir() {
+        let mounts = parse_mounts(
+            "/dev/sda1 / ext4 rw 0 0\n\
+             /dev/sdb1 /var/lib ext4 rw 0 0\n\
+             /dev/sdc1 /var/lib/tenement/my\\040data xfs rw 0 0\n\
+             tmpfs /var/lib/tenementx tmpfs rw 0 0\n",
+        );
+        assert_eq!(mounts[2], PathBuf::from("/var/lib/tenement/my data"));
+
+        assert_eq!(
+            mounts_for(Path::new("/var/lib/tenement"), &mounts),
+            vec![
+                PathBuf::from("/var/lib"),
+                PathBuf::from("/var/lib/tenement/my data")
+            ]
+        );
+        // A data dir that is itself a mount point is listed once
+        assert_eq!(
+            mounts_for(Path::new("/var/lib"), &mounts)[..1],
+            [PathBuf::from("/var/lib")]
+        );
+        assert_eq!(
+            mounts_for(Path::new("/srv/data"), &mounts),
+            vec![PathBuf::from("/")]
+        );
+    }
+
+    #[test]
+    fn test_disk_used_percent() {
+        let disk = |total_bytes, available_bytes, inodes_total, inodes_free| DiskUsage {
+            mount_point: PathBuf::from("/"),
+            total_bytes,
+            available_bytes,
+            inodes_total,
+            inodes_free,
+        };
+        assert_eq!(disk(1000, 250, 100, 90).used_percent(), 75.0);
+        // Running out of inodes is as full as running out of space
+        assert_eq!(disk(1000, 900, 100, 2).used_percent(), 98.0);
+        // Filesystems without inode counts (e.g. some FUSE mounts)
+        assert_eq!(disk(1000, 1000, 0, 0).used_percent(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_collect_and_format() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let stats = HostStats::collect(dir.path()).await.unwrap();
+        assert!(!stats.disks.is_empty());
+        assert!(stats.disks[0].total_bytes > 0);
+        assert!(stats.disks[0].available_bytes <= stats.disks[0].total_bytes);
+        #[cfg(target_os = "linux")]
+        assert!(stats.memory_total_bytes.unwrap() > 0);
+
+        // A data dir that doesn't exist yet reports the filesystem it will be on
+        let missing = HostStats::collect(&dir.path().join("not/yet"))
+            .await
+            .unwrap();
+        assert_eq!(missing.disks[0].mount_point, stats.disks[0].mount_point);
+
+        let output = stats.format_prometheus();
+        assert!(output.contains("tenement_host_load_average{window=\"1m\"}"));
+        assert!(output.contains("# TYPE tenement_host_disk_available_bytes gauge"));
+        assert!(output.contains(&format!(
+            "tenement_host_inodes_total{{mount=\"{}\"}} {}",
+            stats.disks[0].mount_point.display(),
+            stats.disks[0].inodes_total
+        )));
+    }
+}
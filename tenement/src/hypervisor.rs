@@ -1,30 +1,35 @@
 //! Process hypervisor - spawns and supervises instances
 
 use crate::cgroup::{CgroupManager, ResourceLimits};
-use crate::config::Config;
+use crate::clock::{Clock, SystemClock};
+use crate::config::{Config, ListenAddr, Listener, ListenerKind, PortConfig, ProcessConfig};
+use crate::config_diff::{ConfigDiff, DriftReason, InstanceDrift};
+use crate::hardening::RunAs;
 use crate::instance::{HealthStatus, Instance, InstanceId, InstanceInfo};
-use crate::logs::LogBuffer;
+use crate::logs::{LineReader, LogBuffer, LogLevel};
 use crate::metrics::Metrics;
 use crate::port_allocator::PortAllocator;
-use crate::runtime::LiteBoxRuntime;
 #[cfg(feature = "quark")]
 use crate::runtime::QuarkRuntime;
 #[cfg(feature = "sandbox")]
 use crate::runtime::SandboxRuntime;
+use crate::runtime::{ContainerRuntime, LiteBoxRuntime, OciRuntime, SystemdRuntime, WasmRuntime};
 use crate::runtime::{
     Mount, NamespaceRuntime, ProcessRuntime, Runtime, RuntimeHandle, RuntimeType, SpawnConfig,
 };
 use crate::storage::{calculate_dir_size, StorageInfo};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::BufReader;
 use tokio::sync::RwLock;
 use tracing::{error, info, warn};
 
-const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long `stop()` waits for active connections before killing an instance
+const STOP_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// RAII guard that decrements the active connection count when dropped.
 pub struct ConnectionGuard {
@@ -38,24 +43,145 @@ impl Drop for ConnectionGuard {
     }
 }
 
+/// Label `deploy` puts on every instance it spawns
+pub const VERSION_LABEL: &str = "version";
+
+/// Per-spawn options for [`Hypervisor::spawn_with_opts`]
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    /// Env vars merged over the service's `env` for this instance only
+    pub env: HashMap<String, String>,
+    /// Labels merged over the service's `labels` for this instance only
+    pub labels: std::collections::BTreeMap<String, String>,
+    /// Command run instead of the service's `command` and `args`
+    pub command: Option<String>,
+    /// Initial traffic weight (default 100). Only applies to this spawn;
+    /// restarts and wakes come back at 100.
+    pub weight: Option<u8>,
+}
+
+impl SpawnOptions {
+    /// Check env and label names; the spawn methods refuse options that
+    /// fail this
+    pub fn validate(&self) -> Result<()> {
+        for key in self.env.keys() {
+            if key.is_empty() || key.contains('=') || key.contains('\0') {
+                anyhow::bail!("Invalid environment variable name: {:?}", key);
+            }
+        }
+        for key in self.labels.keys() {
+            if key.is_empty() || key.contains('=') || key.contains(',') {
+                anyhow::bail!("Invalid label name: {:?}", key);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One spawn's resolved service config and paths
+struct SpawnPlan {
+    instance_id: InstanceId,
+    /// With any one-off command override applied
+    process_config: ProcessConfig,
+    /// As configured, recorded on the instance for drift checks
+    spawned_config: ProcessConfig,
+    socket: PathBuf,
+    instance_data_dir: PathBuf,
+    /// `env_file` entries, interpolated once the port is known
+    env_file: Vec<(String, String)>,
+    tcp_only: bool,
+}
+
+/// What a spawn has taken so far, given back if it fails before the
+/// instance is registered
+#[derive(Default)]
+struct SpawnResources {
+    port: Option<u16>,
+    listener_ports: BTreeMap<String, u16>,
+    /// The instance's encrypted data dir is mounted
+    encrypted: bool,
+}
+
+/// [`Hypervisor::create`] found the instance already running or being
+/// spawned
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceExists(pub InstanceId);
+
+impl std::fmt::Display for InstanceExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Instance {} is already running", self.0)
+    }
+}
+
+impl std::error::Error for InstanceExists {}
+
+/// Versions of a service rolled out with [`Hypervisor::deploy`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceVersions {
+    /// Version currently receiving traffic
+    pub current: Option<String>,
+    /// Version it replaced
+    pub previous: Option<String>,
+    /// Running instance IDs of the current version
+    pub instances: Vec<String>,
+}
+
+/// What [`Hypervisor::reload`] changed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReloadReport {
+    pub diff: crate::config_diff::ConfigDiff,
+    /// Running instances flagged for a rolling restart
+    pub restarting: Vec<String>,
+    /// Instances of removed services that were stopped
+    pub stopped: Vec<String>,
+    /// Newly listed `[instances]` that were spawned
+    pub spawned: Vec<String>,
+    /// Sections that were not applied and need a daemon restart
+    pub needs_restart: Vec<String>,
+}
+
+/// Outcome of a wake, shared with every request waiting on it: the socket,
+/// or why the instance didn't come up. `None` while the wake is running.
+type WakeOutcome = Option<std::result::Result<PathBuf, String>>;
+
 /// The hypervisor manages all running instances
 pub struct Hypervisor {
-    config: Config,
-    instances: RwLock<HashMap<InstanceId, Instance>>,
+    /// Swapped whole by `reload`
+    config: std::sync::RwLock<Arc<Config>>,
+    instances: Arc<RwLock<HashMap<InstanceId, Instance>>>,
     /// Guard against concurrent spawns of the same instance.
     /// An instance ID is added before spawn begins and removed after it completes.
     spawning: RwLock<std::collections::HashSet<InstanceId>>,
-    /// Wake-once notifications: when an instance is being woken, other requests
-    /// wait on the Notify instead of spawning duplicate processes.
-    waking: RwLock<HashMap<InstanceId, Arc<tokio::sync::Notify>>>,
+    /// Wakes in progress: requests for an instance being woken wait for the
+    /// one wake's outcome instead of spawning duplicate processes.
+    waking: RwLock<HashMap<InstanceId, tokio::sync::watch::Receiver<WakeOutcome>>>,
     /// Active connection count per instance (for connection-aware idle timeout and draining)
     active_connections: RwLock<HashMap<InstanceId, Arc<std::sync::atomic::AtomicU32>>>,
     /// Restart history that persists across stop/spawn cycles.
     /// Maps instance ID to (restart_count, restart_times).
     restart_history: RwLock<HashMap<InstanceId, (u32, Vec<Instant>)>>,
+    /// Options from the last `spawn_with_opts`, reapplied when the
+    /// instance is restarted or woken and dropped when it's stopped
+    spawn_options: RwLock<HashMap<InstanceId, SpawnOptions>>,
+    /// Current/previous version per service, updated by `deploy`
+    versions: RwLock<HashMap<String, ServiceVersions>>,
+    /// Services with a `deploy` in progress
+    deploying: RwLock<std::collections::HashSet<String>>,
+    /// Weights other than the default 100, reapplied when an instance is
+    /// respawned and kept in the state store across daemon restarts
+    weights: RwLock<HashMap<InstanceId, u8>>,
+    /// Token buckets for services with a `rate_limit`
+    rate_limiter: crate::rate_limit::RateLimiter,
+    /// Credential checks for services with an `auth` gate
+    auth_gate: crate::gate::AuthGate,
+    /// Per-instance request metering, flushed by `metering::MeterRecorder`
+    meter: crate::metering::Meter,
+    /// When compute-seconds were last credited to the meter; `None` until
+    /// metering starts
+    metered_at: std::sync::Mutex<Option<Instant>>,
     log_buffer: Arc<LogBuffer>,
     metrics: Arc<Metrics>,
-    /// Port allocator for TCP ports (30000-40000)
+    /// Port allocator for TCP ports (`settings.port_range`)
     port_allocator: Arc<PortAllocator>,
     /// Process runtime (always available, fallback)
     process_runtime: ProcessRuntime,
@@ -63,6 +189,14 @@ pub struct Hypervisor {
     namespace_runtime: NamespaceRuntime,
     /// LiteBox runtime - supervised external runner
     litebox_runtime: LiteBoxRuntime,
+    /// Container runtime - images run attached by Docker or Podman
+    container_runtime: ContainerRuntime,
+    /// OCI runtime - generated bundles run by runc
+    oci_runtime: OciRuntime,
+    /// WASM runtime - modules and components run by wasmtime
+    wasm_runtime: WasmRuntime,
+    /// systemd runtime - transient scopes started by systemd-run
+    systemd_runtime: SystemdRuntime,
     /// Sandbox runtime (gVisor) - requires runsc
     #[cfg(feature = "sandbox")]
     sandbox_runtime: SandboxRuntime,
@@ -73,6 +207,36 @@ pub struct Hypervisor {
     cgroup_manager: CgroupManager,
     /// Optional state store for crash recovery persistence
     state_store: Option<Arc<crate::store::StateStore>>,
+    /// Optional per-tenant key salts for encrypted data dirs
+    tenant_keys: Option<Arc<crate::store::TenantKeyStore>>,
+    /// Values for `{secret:NAME}`, loaded on first use and dropped by
+    /// `reload` so the next spawn reads them again
+    secrets: RwLock<Option<Arc<crate::secrets::Secrets>>>,
+    /// Optional per-tenant secrets (provisioned database connection strings)
+    tenant_secrets: Option<Arc<crate::store::TenantSecretStore>>,
+    /// Held while a tenant database is provisioned, so two tenants can't
+    /// both claim a database name
+    provisioning: tokio::sync::Mutex<()>,
+    /// Held for a whole [`Hypervisor::reload`], so two reloads can't
+    /// interleave their diffs and restarts
+    reloading: tokio::sync::Mutex<()>,
+    /// Optional usage history, sampled for capacity forecasts
+    usage_store: Option<Arc<crate::store::UsageStore>>,
+    /// Optional proxy access log, written by the server
+    access_log: Option<Arc<crate::store::AccessLogStore>>,
+    /// Set by `shutdown_all`; the monitor stops restarting and refilling
+    shutting_down: std::sync::atomic::AtomicBool,
+    /// Runtime used for every spawn instead of the one `isolation` selects
+    /// (set by embedders and tests via `with_runtime`)
+    custom_runtime: Option<Arc<dyn Runtime>>,
+    /// Time source for the monitor, idle reaping, restart backoff and the
+    /// restart window
+    clock: Arc<dyn Clock>,
+    /// Seeded RNG for weighted selection (set via `with_routing_seed`);
+    /// `None` uses the thread RNG
+    routing_rng: Option<std::sync::Mutex<rand::rngs::StdRng>>,
+    /// Per-instance DNS records (`[dns]`, or set via `with_dns`)
+    dns: Option<Arc<crate::dns::DnsRegistrar>>,
 }
 
 impl Hypervisor {
@@ -80,27 +244,62 @@ impl Hypervisor {
     pub fn new(config: Config) -> Arc<Self> {
         let namespace_runtime = NamespaceRuntime::new();
         let cgroup_manager = CgroupManager::new();
-        let port_allocator = Arc::new(PortAllocator::new());
+        let [port_min, port_max] = config.settings.port_range;
+        let port_allocator = Arc::new(PortAllocator::with_range(port_min, port_max));
+        let metrics = Metrics::with_limits(config.settings.metrics_limits());
+        let log_buffer = LogBuffer::new();
+        if !config.alert.is_empty() {
+            log_buffer.set_alerts(crate::alerts::AlertEngine::new(&config.alert));
+        }
+        log_buffer.set_capacities(config.log_buffer_lines());
+        let dns = config
+            .dns
+            .as_ref()
+            .map(crate::dns::DnsRegistrar::from_config);
 
         Arc::new(Self {
-            config,
-            instances: RwLock::new(HashMap::new()),
+            config: std::sync::RwLock::new(Arc::new(config)),
+            instances: Arc::new(RwLock::new(HashMap::new())),
             spawning: RwLock::new(std::collections::HashSet::new()),
             waking: RwLock::new(HashMap::new()),
             active_connections: RwLock::new(HashMap::new()),
             restart_history: RwLock::new(HashMap::new()),
-            log_buffer: LogBuffer::new(),
-            metrics: Metrics::new(),
+            spawn_options: RwLock::new(HashMap::new()),
+            versions: RwLock::new(HashMap::new()),
+            deploying: RwLock::new(std::collections::HashSet::new()),
+            weights: RwLock::new(HashMap::new()),
+            rate_limiter: crate::rate_limit::RateLimiter::new(),
+            auth_gate: crate::gate::AuthGate::new(),
+            meter: crate::metering::Meter::new(),
+            metered_at: std::sync::Mutex::new(None),
+            log_buffer,
+            metrics,
             port_allocator,
             process_runtime: ProcessRuntime::new(),
             namespace_runtime,
             litebox_runtime: LiteBoxRuntime::new(),
+            container_runtime: ContainerRuntime::new(),
+            oci_runtime: OciRuntime::new(),
+            wasm_runtime: WasmRuntime::new(),
+            systemd_runtime: SystemdRuntime::new(),
             #[cfg(feature = "sandbox")]
             sandbox_runtime: SandboxRuntime::new(),
             #[cfg(feature = "quark")]
             quark_runtime: QuarkRuntime::new(),
             cgroup_manager,
             state_store: None,
+            tenant_keys: None,
+            secrets: RwLock::new(None),
+            tenant_secrets: None,
+            provisioning: tokio::sync::Mutex::new(()),
+            reloading: tokio::sync::Mutex::new(()),
+            usage_store: None,
+            access_log: None,
+            shutting_down: std::sync::atomic::AtomicBool::new(false),
+            custom_runtime: None,
+            clock: Arc::new(SystemClock),
+            routing_rng: None,
+            dns,
         })
     }
 
@@ -108,27 +307,61 @@ impl Hypervisor {
     pub fn with_log_buffer(config: Config, log_buffer: Arc<LogBuffer>) -> Arc<Self> {
         let namespace_runtime = NamespaceRuntime::new();
         let cgroup_manager = CgroupManager::new();
-        let port_allocator = Arc::new(PortAllocator::new());
+        let [port_min, port_max] = config.settings.port_range;
+        let port_allocator = Arc::new(PortAllocator::with_range(port_min, port_max));
+        let metrics = Metrics::with_limits(config.settings.metrics_limits());
+        if !config.alert.is_empty() {
+            log_buffer.set_alerts(crate::alerts::AlertEngine::new(&config.alert));
+        }
+        log_buffer.set_capacities(config.log_buffer_lines());
+        let dns = config
+            .dns
+            .as_ref()
+            .map(crate::dns::DnsRegistrar::from_config);
 
         Arc::new(Self {
-            config,
-            instances: RwLock::new(HashMap::new()),
+            config: std::sync::RwLock::new(Arc::new(config)),
+            instances: Arc::new(RwLock::new(HashMap::new())),
             spawning: RwLock::new(std::collections::HashSet::new()),
             waking: RwLock::new(HashMap::new()),
             active_connections: RwLock::new(HashMap::new()),
             restart_history: RwLock::new(HashMap::new()),
+            spawn_options: RwLock::new(HashMap::new()),
+            versions: RwLock::new(HashMap::new()),
+            deploying: RwLock::new(std::collections::HashSet::new()),
+            weights: RwLock::new(HashMap::new()),
+            rate_limiter: crate::rate_limit::RateLimiter::new(),
+            auth_gate: crate::gate::AuthGate::new(),
+            meter: crate::metering::Meter::new(),
+            metered_at: std::sync::Mutex::new(None),
             log_buffer,
-            metrics: Metrics::new(),
+            metrics,
             port_allocator,
             process_runtime: ProcessRuntime::new(),
             namespace_runtime,
             litebox_runtime: LiteBoxRuntime::new(),
+            container_runtime: ContainerRuntime::new(),
+            oci_runtime: OciRuntime::new(),
+            wasm_runtime: WasmRuntime::new(),
+            systemd_runtime: SystemdRuntime::new(),
             #[cfg(feature = "sandbox")]
             sandbox_runtime: SandboxRuntime::new(),
             #[cfg(feature = "quark")]
             quark_runtime: QuarkRuntime::new(),
             cgroup_manager,
             state_store: None,
+            tenant_keys: None,
+            secrets: RwLock::new(None),
+            tenant_secrets: None,
+            provisioning: tokio::sync::Mutex::new(()),
+            reloading: tokio::sync::Mutex::new(()),
+            usage_store: None,
+            access_log: None,
+            shutting_down: std::sync::atomic::AtomicBool::new(false),
+            custom_runtime: None,
+            clock: Arc::new(SystemClock),
+            routing_rng: None,
+            dns,
         })
     }
 
@@ -145,25 +378,449 @@ impl Hypervisor {
         hyp
     }
 
+    /// Attach a tenant key store (required by services with `encrypt_data`)
+    pub fn with_tenant_keys(
+        mut self: Arc<Self>,
+        tenant_keys: Arc<crate::store::TenantKeyStore>,
+    ) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_tenant_keys must be called before the hypervisor is shared")
+            .tenant_keys = Some(tenant_keys);
+        self
+    }
+
+    /// Attach a tenant secret store (required by services with a `database`)
+    pub fn with_secrets(
+        mut self: Arc<Self>,
+        tenant_secrets: Arc<crate::store::TenantSecretStore>,
+    ) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_secrets must be called before the hypervisor is shared")
+            .tenant_secrets = Some(tenant_secrets);
+        self
+    }
+
+    /// Attach a usage history store (read by capacity reports)
+    pub fn with_usage(
+        mut self: Arc<Self>,
+        usage_store: Arc<crate::store::UsageStore>,
+    ) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_usage must be called before the hypervisor is shared")
+            .usage_store = Some(usage_store);
+        self
+    }
+
+    /// Attach a store for the proxy access log
+    pub fn with_access_log(
+        mut self: Arc<Self>,
+        access_log: Arc<crate::store::AccessLogStore>,
+    ) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_access_log must be called before the hypervisor is shared")
+            .access_log = Some(access_log);
+        self
+    }
+
+    /// Spawn every instance with `runtime`, whatever its `isolation`.
+    /// Cgroup limits are not applied to these instances.
+    pub fn with_runtime(mut self: Arc<Self>, runtime: Arc<dyn Runtime>) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_runtime must be called before the hypervisor is shared")
+            .custom_runtime = Some(runtime);
+        self
+    }
+
+    /// Read time from `clock` instead of the system clock
+    pub fn with_clock(mut self: Arc<Self>, clock: Arc<dyn Clock>) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_clock must be called before the hypervisor is shared")
+            .clock = clock;
+        self
+    }
+
+    /// Register instance DNS records with `registrar` instead of the
+    /// provider configured in `[dns]`
+    pub fn with_dns(mut self: Arc<Self>, registrar: Arc<crate::dns::DnsRegistrar>) -> Arc<Self> {
+        Arc::get_mut(&mut self)
+            .expect("with_dns must be called before the hypervisor is shared")
+            .dns = Some(registrar);
+        self
+    }
+
+    /// Make weighted selection deterministic: picks are drawn from an RNG
+    /// seeded with `seed`, so the same calls against the same instances pick
+    /// the same sequence of instances
+    pub fn with_routing_seed(mut self: Arc<Self>, seed: u64) -> Arc<Self> {
+        use rand::SeedableRng;
+        Arc::get_mut(&mut self)
+            .expect("with_routing_seed must be called before the hypervisor is shared")
+            .routing_rng = Some(std::sync::Mutex::new(rand::rngs::StdRng::seed_from_u64(
+            seed,
+        )));
+        self
+    }
+
     /// Get the log buffer
     pub fn log_buffer(&self) -> Arc<LogBuffer> {
         self.log_buffer.clone()
     }
 
+    /// Alert rules evaluated over this hypervisor's logs (None if no
+    /// `[alert.*]` rules are configured)
+    pub fn alerts(&self) -> Option<Arc<crate::alerts::AlertEngine>> {
+        self.log_buffer.alerts()
+    }
+
+    /// Usage history store, if one is attached
+    pub fn usage_store(&self) -> Option<Arc<crate::store::UsageStore>> {
+        self.usage_store.clone()
+    }
+
+    /// The proxy access log, if one is attached and recording is enabled
+    pub fn access_log(&self) -> Option<Arc<crate::store::AccessLogStore>> {
+        if self.config().settings.access_log_retention == 0 {
+            return None;
+        }
+        self.access_log.clone()
+    }
+
     /// Get the metrics
     pub fn metrics(&self) -> Arc<Metrics> {
         self.metrics.clone()
     }
 
+    /// Get the config the hypervisor is running with
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// The experiment (name and config) splitting traffic for a service, if any
+    pub fn experiment_for(
+        &self,
+        process_name: &str,
+    ) -> Option<(String, crate::experiment::ExperimentConfig)> {
+        self.config()
+            .experiment
+            .iter()
+            .find(|(_, e)| e.service == process_name)
+            .map(|(name, e)| (name.clone(), e.clone()))
+    }
+
+    /// Running instances that a config change would affect
+    /// (instances of services that were removed or modified).
+    pub async fn affected_instances(&self, diff: &crate::config_diff::ConfigDiff) -> Vec<String> {
+        let services = diff.affected_services();
+        let instances = self.instances.read().await;
+        let mut affected: Vec<String> = instances
+            .keys()
+            .filter(|id| !id.is_warm() && services.contains(&id.process.as_str()))
+            .map(|id| id.to_string())
+            .collect();
+        affected.sort();
+        affected
+    }
+
+    /// Running instances whose service definition in `config` differs
+    /// from the one they were spawned with, i.e. that need a restart to
+    /// pick it up. Pass the on-disk config to see what a reload would
+    /// restart, or the running config to see what is still pending.
+    pub async fn config_drift(&self, config: &Config) -> Vec<InstanceDrift> {
+        let instances = self.instances.read().await;
+        let mut drift: Vec<InstanceDrift> = instances
+            .values()
+            .filter(|i| !i.id.is_warm())
+            .filter_map(|i| {
+                let (reason, changes) = match (config.get_service(&i.id.process), &i.spawned_config)
+                {
+                    (None, _) => (DriftReason::Removed, Vec::new()),
+                    (Some(_), None) => (DriftReason::Unknown, Vec::new()),
+                    (Some(current), Some(spawned)) => {
+                        let changes = ConfigDiff::service_changes(spawned, current);
+                        if changes.is_empty() {
+                            return None;
+                        }
+                        (DriftReason::Modified, changes)
+                    }
+                };
+                Some(InstanceDrift {
+                    instance: i.id.to_string(),
+                    reason,
+                    changes,
+                })
+            })
+            .collect();
+        drift.sort_by(|a, b| a.instance.cmp(&b.instance));
+        drift
+    }
+
+    /// Apply a new config to the running hypervisor.
+    ///
+    /// Services and `[instances]`, `[routing]` and `[experiment.*]` are
+    /// swapped in. Added services can be spawned right away and newly listed
+    /// instances are spawned. Running instances of modified services are
+    /// flagged and restarted by the monitor one per service per pass (see
+    /// `recycle_expired_instances`); their warm instances are stopped and
+    /// refilled from the new definition. Instances of removed services are
+    /// stopped. `[settings]`, `[alert.*]` and `[dns]` are wired up at
+    /// startup, so they keep their running values and are reported in
+    /// `needs_restart`. Concurrent reloads (a SIGHUP racing `ten reload`)
+    /// run one after the other.
+    pub async fn reload(&self, mut config: Config) -> Result<ReloadReport> {
+        let _reloading = self.reloading.lock().await;
+        let running = self.config();
+        let diff = crate::config_diff::ConfigDiff::between(&running, &config);
+        let mut report = ReloadReport::default();
+        if !diff.settings_changed.is_empty() {
+            report.needs_restart.push("settings".to_string());
+        }
+        if !diff.alerts_changed.is_empty() {
+            report.needs_restart.push("alert".to_string());
+        }
+        if !diff.dns_changed.is_empty() {
+            report.needs_restart.push("dns".to_string());
+        }
+        config.settings = running.settings.clone();
+        config.alert = running.alert.clone();
+        config.dns = running.dns.clone();
+        self.log_buffer.set_capacities(config.log_buffer_lines());
+        *self.config.write().unwrap() = Arc::new(config);
+        *self.secrets.write().await = None;
+
+        let modified: Vec<&str> = diff
+            .services_modified
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        let mut warm = Vec::new();
+        let mut removed = Vec::new();
+        {
+            let mut instances = self.instances.write().await;
+            for instance in instances.values_mut() {
+                let process = instance.id.process.as_str();
+                if diff.services_removed.iter().any(|s| s == process) {
+                    removed.push(instance.id.clone());
+                } else if modified.contains(&process) {
+                    if instance.id.is_warm() {
+                        warm.push(instance.id.clone());
+                    } else if instance.exit_code.is_none() {
+                        instance.stale_config = true;
+                        report.restarting.push(instance.id.to_string());
+                    }
+                }
+            }
+        }
+        report.restarting.sort();
+
+        for instance_id in warm {
+            if let Err(e) = self.stop(&instance_id.process, &instance_id.id).await {
+                error!("Failed to stop warm instance {}: {}", instance_id, e);
+            }
+        }
+        removed.sort_by_key(|id| id.to_string());
+        for instance_id in removed {
+            match self.stop(&instance_id.process, &instance_id.id).await {
+                Ok(()) => report.stopped.push(instance_id.to_string()),
+                Err(e) => error!("Failed to stop removed instance {}: {}", instance_id, e),
+            }
+        }
+        for instance in &diff.instances_added {
+            let Some((process, id)) = instance.split_once(':') else {
+                continue;
+            };
+            if self.is_running(process, id).await {
+                continue;
+            }
+            match self.spawn(process, id).await {
+                Ok(_) => report.spawned.push(instance.clone()),
+                Err(e) => error!("Failed to spawn {}: {}", instance, e),
+            }
+        }
+
+        report.diff = diff;
+        Ok(report)
+    }
+
+    /// Port for a new instance: the service's fixed `port`, or a free one
+    /// from `settings.port_range`
+    async fn allocate_port(
+        &self,
+        process_config: &ProcessConfig,
+        instance_id: &InstanceId,
+    ) -> Result<u16> {
+        match process_config.port {
+            PortConfig::Auto => self
+                .port_allocator
+                .allocate()
+                .await
+                .with_context(|| format!("Failed to allocate port for {}", instance_id)),
+            PortConfig::Fixed(port) => {
+                if !self.port_allocator.reserve(port).await {
+                    anyhow::bail!(
+                        "Port {} for {} is already held by another instance",
+                        port,
+                        instance_id
+                    );
+                }
+                Ok(port)
+            }
+        }
+    }
+
+    /// Give back the ports of a spawn that failed
+    async fn release_ports(&self, port: Option<u16>, listener_ports: &BTreeMap<String, u16>) {
+        for port in port.iter().chain(listener_ports.values()) {
+            self.port_allocator.release(*port).await;
+        }
+    }
+
+    /// Replace `{secret:NAME}` in env values, loading the secrets if this is
+    /// the first spawn to need them. Runs after `{id}` is substituted, so a
+    /// secret can be keyed by the instance (`{secret:db_{id}}`); IDs can't
+    /// contain braces, so an ID can't name a secret itself.
+    async fn resolve_secrets(&self, env: &mut HashMap<String, String>) -> Result<()> {
+        if !env.values().any(|v| crate::secrets::has_secrets(v)) {
+            return Ok(());
+        }
+        let secrets = {
+            let mut cached = self.secrets.write().await;
+            match cached.as_ref() {
+                Some(secrets) => secrets.clone(),
+                None => {
+                    let loaded =
+                        Arc::new(crate::secrets::Secrets::load(&self.config().settings).await?);
+                    *cached = Some(loaded.clone());
+                    loaded
+                }
+            }
+        };
+        for (key, value) in env.iter_mut() {
+            if crate::secrets::has_secrets(value) {
+                *value = secrets
+                    .resolve(value)
+                    .with_context(|| format!("env {}", key))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether each isolation runtime can be used on this host. Runtimes
+    /// whose Cargo feature isn't compiled in are reported unavailable.
+    pub fn runtime_availability(&self) -> std::collections::BTreeMap<String, bool> {
+        #[cfg(feature = "sandbox")]
+        let sandbox = self.sandbox_runtime.is_available();
+        #[cfg(not(feature = "sandbox"))]
+        let sandbox = false;
+        #[cfg(feature = "quark")]
+        let quark = self.quark_runtime.is_available();
+        #[cfg(not(feature = "quark"))]
+        let quark = false;
+        #[cfg(feature = "firecracker")]
+        let firecracker = crate::runtime::FirecrackerRuntime::new().is_available();
+        #[cfg(not(feature = "firecracker"))]
+        let firecracker = false;
+        #[cfg(feature = "qemu")]
+        let qemu = crate::runtime::QemuRuntime::new().is_available();
+        #[cfg(not(feature = "qemu"))]
+        let qemu = false;
+
+        [
+            (RuntimeType::Process, self.process_runtime.is_available()),
+            (
+                RuntimeType::Namespace,
+                self.namespace_runtime.is_available(),
+            ),
+            (RuntimeType::Litebox, self.litebox_runtime.is_available()),
+            (RuntimeType::Sandbox, sandbox),
+            (RuntimeType::Quark, quark),
+            (
+                RuntimeType::Container,
+                self.container_runtime.is_available(),
+            ),
+            (RuntimeType::Oci, self.oci_runtime.is_available()),
+            (RuntimeType::Wasm, self.wasm_runtime.is_available()),
+            (RuntimeType::Systemd, self.systemd_runtime.is_available()),
+            (RuntimeType::Firecracker, firecracker),
+            (RuntimeType::Qemu, qemu),
+        ]
+        .into_iter()
+        .map(|(runtime, available)| (runtime.to_string(), available))
+        .collect()
+    }
+
     /// Load config from tenement.toml and create hypervisor
     pub fn from_config_file() -> Result<Arc<Self>> {
         let config = Config::load()?;
         Ok(Self::new(config))
     }
 
-    /// Spawn a new instance of a process
+    /// Spawn a new instance of a process.
+    /// Env and labels from an earlier `spawn_with_opts` are reapplied unless
+    /// the instance has been stopped since.
     pub async fn spawn(&self, process_name: &str, id: &str) -> Result<PathBuf> {
-        self.spawn_with_env(process_name, id, HashMap::new()).await
+        let opts = self
+            .spawn_options
+            .read()
+            .await
+            .get(&InstanceId::new(process_name, id))
+            .cloned()
+            .unwrap_or_default();
+        self.spawn_instance(process_name, id, opts, false).await
+    }
+
+    /// Spawn a new instance with per-spawn options.
+    ///
+    /// `opts.env` and `opts.labels` are merged over the service's `env` and
+    /// `labels` for this instance and remembered along with `opts.command`,
+    /// so restarts and wakes keep them. Spawning again with different options
+    /// replaces them; stopping (or draining) the instance forgets them.
+    pub async fn spawn_with_opts(
+        &self,
+        process_name: &str,
+        id: &str,
+        opts: SpawnOptions,
+    ) -> Result<PathBuf> {
+        self.spawn_remembering_opts(process_name, id, opts, false)
+            .await
+    }
+
+    /// Like [`Self::spawn_with_opts`], but fails with [`InstanceExists`] if
+    /// the instance is already running or being spawned, instead of
+    /// answering with it. The check and the claim are one atomic step.
+    pub async fn create(
+        &self,
+        process_name: &str,
+        id: &str,
+        opts: SpawnOptions,
+    ) -> Result<PathBuf> {
+        self.spawn_remembering_opts(process_name, id, opts, true)
+            .await
+    }
+
+    async fn spawn_remembering_opts(
+        &self,
+        process_name: &str,
+        id: &str,
+        opts: SpawnOptions,
+        exclusive: bool,
+    ) -> Result<PathBuf> {
+        opts.validate()?;
+        let socket = self
+            .spawn_instance(process_name, id, opts.clone(), exclusive)
+            .await?;
+
+        let instance_id = InstanceId::new(process_name, id);
+        let opts = SpawnOptions {
+            weight: None,
+            ..opts
+        };
+        let mut stored = self.spawn_options.write().await;
+        if opts.env.is_empty() && opts.labels.is_empty() && opts.command.is_none() {
+            stored.remove(&instance_id);
+        } else {
+            stored.insert(instance_id, opts);
+        }
+        Ok(socket)
     }
 
     /// Spawn a new instance with additional environment variables
@@ -173,47 +830,301 @@ impl Hypervisor {
         id: &str,
         extra_env: HashMap<String, String>,
     ) -> Result<PathBuf> {
-        let process_config = self
-            .config
+        let opts = SpawnOptions {
+            env: extra_env,
+            ..Default::default()
+        };
+        self.spawn_instance(process_name, id, opts, false).await
+    }
+
+    /// Spawn an instance. One that is already running or being spawned is
+    /// answered with its socket, or an [`InstanceExists`] error if
+    /// `exclusive`.
+    async fn spawn_instance(
+        &self,
+        process_name: &str,
+        id: &str,
+        opts: SpawnOptions,
+        exclusive: bool,
+    ) -> Result<PathBuf> {
+        if self.is_shutting_down() {
+            anyhow::bail!(
+                "Not spawning {}:{}: daemon is shutting down",
+                process_name,
+                id
+            );
+        }
+        let plan = self.plan_spawn(process_name, id, opts.command.as_deref())?;
+        if !self.claim_spawn(&plan.instance_id, exclusive).await? {
+            return Ok(plan.socket);
+        }
+
+        // Everything up to registering the instance; on any error the guard
+        // and the ports are given back so the next spawn can try again, and
+        // encrypted data is locked again
+        let mut taken = SpawnResources::default();
+        let instance = match self.start_instance(&plan, opts, &mut taken).await {
+            Ok(instance) => instance,
+            Err(e) => {
+                self.abandon_spawn(&plan, taken).await;
+                return Err(e);
+            }
+        };
+        self.register_instance(instance).await;
+        self.wait_until_up(&plan, taken.port).await;
+        Ok(plan.socket)
+    }
+
+    /// Resolve the service config, socket and env file for a spawn, and
+    /// create the instance's data and socket dirs
+    fn plan_spawn(&self, process_name: &str, id: &str, command: Option<&str>) -> Result<SpawnPlan> {
+        let mut process_config = self
+            .config()
             .get_service(process_name)
             .with_context(|| format!("Unknown process: {}", process_name))?
             .clone();
+        crate::paths::validate_instance_id(id)?;
+        // As configured, before any one-off command override
+        let spawned_config = process_config.clone();
+        if let Some(command) = command {
+            process_config.command = command.to_string();
+            process_config.args.clear();
+        }
 
-        let instance_id = InstanceId::new(process_name, id);
-        let data_dir = &self.config.settings.data_dir;
+        let data_dir = &self.config().settings.data_dir;
         let socket = process_config.socket_path(process_name, id);
+        let env_file = process_config.read_env_file(process_name, id, data_dir)?;
 
         // Create instance data directory
         let instance_data_dir = data_dir.join(process_name).join(id);
         std::fs::create_dir_all(&instance_data_dir)
             .with_context(|| format!("Failed to create data dir: {:?}", instance_data_dir))?;
 
+        let tcp_only = self.config().settings.tcp_only;
+
         // Create socket parent directory if needed
-        if let Some(socket_parent) = socket.parent() {
+        if let Some(socket_parent) = socket.parent().filter(|_| !tcp_only) {
             std::fs::create_dir_all(socket_parent)
                 .with_context(|| format!("Failed to create socket dir: {:?}", socket_parent))?;
         }
 
+        Ok(SpawnPlan {
+            instance_id: InstanceId::new(process_name, id),
+            process_config,
+            spawned_config,
+            socket,
+            instance_data_dir,
+            env_file,
+            tcp_only,
+        })
+    }
+
+    /// Mark an instance as spawning. False if it is already running or
+    /// being spawned, or an [`InstanceExists`] error then if `exclusive`.
+    async fn claim_spawn(&self, instance_id: &InstanceId, exclusive: bool) -> Result<bool> {
         // Atomically check if running/spawning and mark as spawning (prevents race condition).
         // Both checks and the insert happen under one write lock.
-        {
-            let instances = self.instances.read().await;
-            if instances.contains_key(&instance_id) {
-                info!("Instance {} already running", instance_id);
-                return Ok(socket);
+        let instances = self.instances.read().await;
+        if instances.contains_key(instance_id) {
+            if exclusive {
+                return Err(InstanceExists(instance_id.clone()).into());
             }
-            let mut spawning = self.spawning.write().await;
-            if !spawning.insert(instance_id.clone()) {
-                info!("Instance {} is already being spawned", instance_id);
-                return Ok(socket);
+            info!("Instance {} already running", instance_id);
+            return Ok(false);
+        }
+        let mut spawning = self.spawning.write().await;
+        if spawning.contains(instance_id) {
+            if exclusive {
+                return Err(InstanceExists(instance_id.clone()).into());
             }
+            info!("Instance {} is already being spawned", instance_id);
+            return Ok(false);
         }
+        spawning.insert(instance_id.clone());
+        Ok(true)
+    }
 
-        let data_dir = &self.config.settings.data_dir;
+    /// Start a claimed instance's runtime and build its [`Instance`].
+    /// Ports and the encrypted mount are recorded in `taken` as they are
+    /// acquired, for [`Self::abandon_spawn`] to give back on failure.
+    async fn start_instance(
+        &self,
+        plan: &SpawnPlan,
+        opts: SpawnOptions,
+        taken: &mut SpawnResources,
+    ) -> Result<Instance> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            ..
+        } = plan;
+
+        let run_as = self.prepare_instance_dirs(plan, taken).await?;
+
+        let database_env = match &process_config.database {
+            Some(database) => {
+                let url = self
+                    .tenant_database_url(database, &instance_id.process, &instance_id.id)
+                    .await?;
+                Some((database.env.clone(), url))
+            }
+            None => None,
+        };
 
-        // Validate isolation level is available - fail loudly if not
         let isolation = process_config.isolation;
+        self.check_runtime_available(isolation, instance_id)?;
+
+        info!(
+            "Spawning instance {} (isolation: {})",
+            instance_id, isolation
+        );
+
+        self.allocate_instance_ports(plan, taken).await?;
+
+        let env = self
+            .instance_env(plan, taken, database_env, opts.env)
+            .await?;
+
+        let spawn_config = self.spawn_config(plan, taken.port, env, run_as)?;
+        let mut handle = self.start_runtime(isolation, &spawn_config).await?;
+        self.apply_resource_limits(plan, &mut handle).await?;
+        self.capture_logs(plan, &mut handle);
+
+        let runtime_type = handle.runtime_type();
+        let now = self.clock.now();
+
+        // Restore restart history from persistent storage (survives stop/spawn cycles)
+        let (restarts, restart_times) = {
+            let history = self.restart_history.read().await;
+            history.get(instance_id).cloned().unwrap_or((0, Vec::new()))
+        };
+
+        Ok(Instance {
+            id: instance_id.clone(),
+            handle,
+            runtime_type,
+            socket: plan.socket.clone(),
+            port: taken.port,
+            listeners: process_config.listen_addrs(
+                &instance_id.process,
+                &instance_id.id,
+                taken.port,
+                &taken.listener_ports,
+            ),
+            started_at: now,
+            restarts,
+            consecutive_failures: 0,
+            last_health_check: None,
+            health_status: HealthStatus::Unknown,
+            restart_times,
+            last_activity: now,
+            idle_timeout: process_config.idle_timeout,
+            storage_quota_mb: process_config.storage_quota_mb,
+            storage_persist: process_config.storage_persist,
+            storage_used_bytes: 0,
+            data_dir: plan.instance_data_dir.clone(),
+            weight: match opts.weight {
+                Some(weight) => weight.min(100),
+                None => self.stored_weight(instance_id).await,
+            },
+            draining: false,
+            // Without a health endpoint, routable as soon as it's up
+            ready: !process_config.has_health_check(),
+            exit_code: None,
+            labels: process_config
+                .labels
+                .clone()
+                .into_iter()
+                .chain(opts.labels)
+                .collect(),
+            orphaned: false,
+            oom_kills: self
+                .cgroup_manager
+                .oom_kills(&instance_id.to_string())
+                .unwrap_or(0),
+            stale_config: false,
+            spawned_config: Some(plan.spawned_config.clone()),
+        })
+    }
+
+    /// Clear a stale socket, unlock encrypted data and hand the instance's
+    /// dirs to the user it runs as, which is returned
+    async fn prepare_instance_dirs(
+        &self,
+        plan: &SpawnPlan,
+        taken: &mut SpawnResources,
+    ) -> Result<Option<RunAs>> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            socket,
+            instance_data_dir,
+            tcp_only,
+            ..
+        } = plan;
+
+        // A crashed instance can leave its socket behind; binding over it fails
+        let stale_socket = if *tcp_only {
+            Ok(false)
+        } else {
+            crate::paths::remove_stale_socket(socket).await
+        };
+        match stale_socket {
+            Ok(true) => {
+                let message = format!("[tenement] removed stale socket {}", socket.display());
+                info!("Instance {}: {}", instance_id, message);
+                self.log_buffer
+                    .push_stderr(&instance_id.process, &instance_id.id, message)
+                    .await;
+            }
+            Ok(false) => {}
+            Err(e) => return Err(e).with_context(|| format!("Not spawning {}", instance_id)),
+        }
+
+        if process_config.encrypt_data {
+            self.mount_encrypted_data_dir(&instance_id.process, &instance_id.id, instance_data_dir)
+                .await?;
+            taken.encrypted = true;
+        }
+
+        let run_as = match &process_config.user {
+            Some(user) => Some(
+                RunAs::resolve(user, process_config.group.as_deref()).with_context(|| {
+                    format!("Service '{}' has an unusable user", instance_id.process)
+                })?,
+            ),
+            None => None,
+        };
+
+        // Root inside a user namespace is an unprivileged host uid, and so is
+        // `user`: hand it the instance's data dir and (per-instance) socket dir
+        let owner = match (&process_config.user_namespace, &run_as) {
+            (Some(userns), _) => Some((userns.uid, userns.gid)),
+            (None, Some(run_as)) => Some((run_as.uid, run_as.gid)),
+            (None, None) => None,
+        };
+        if let Some((uid, gid)) = owner {
+            let mut dirs = vec![instance_data_dir.as_path()];
+            let per_instance = Path::new(&process_config.socket)
+                .parent()
+                .is_some_and(|dir| dir.to_string_lossy().contains("{id}"));
+            dirs.extend(socket.parent().filter(|_| per_instance && !tcp_only));
+            for dir in dirs {
+                crate::hardening::chown_tree(dir, uid, gid)
+                    .with_context(|| format!("Failed to hand {:?} to uid {}", dir, uid))?;
+            }
+        }
+        Ok(run_as)
+    }
+
+    /// Validate isolation level is available - fail loudly if not
+    fn check_runtime_available(
+        &self,
+        isolation: RuntimeType,
+        instance_id: &InstanceId,
+    ) -> Result<()> {
         match isolation {
+            _ if self.custom_runtime.is_some() => {}
             RuntimeType::Namespace => {
                 if !self.namespace_runtime.is_available() {
                     anyhow::bail!(
@@ -279,37 +1190,173 @@ impl Hypervisor {
                     );
                 }
             }
-            RuntimeType::Firecracker | RuntimeType::Qemu => {
-                anyhow::bail!(
-                    "Instance {}: {} isolation not yet supported in hypervisor",
-                    instance_id,
-                    isolation
-                );
+            RuntimeType::Container => {
+                if !self.container_runtime.is_available() {
+                    anyhow::bail!(
+                        "Instance {}: container isolation requires docker or podman.\n\
+                         Install one of them, or set TENEMENT_CONTAINER_ENGINE=/path/to/engine.",
+                        instance_id
+                    );
+                }
+            }
+            RuntimeType::Oci => {
+                if !self.oci_runtime.is_available() {
+                    anyhow::bail!(
+                        "Instance {}: oci isolation requires runc on Linux.\n\
+                         Install runc, or set TENEMENT_RUNC=/path/to/runc.",
+                        instance_id
+                    );
+                }
+            }
+            RuntimeType::Wasm => {
+                if !self.wasm_runtime.is_available() {
+                    anyhow::bail!(
+                        "Instance {}: wasm isolation requires wasmtime.\n\
+                         Install it from https://wasmtime.dev, or set \
+                         TENEMENT_WASMTIME=/path/to/wasmtime.",
+                        instance_id
+                    );
+                }
+            }
+            RuntimeType::Systemd => {
+                if !self.systemd_runtime.is_available() {
+                    anyhow::bail!(
+                        "Instance {}: systemd isolation requires a host booted with systemd \
+                         and systemd-run.\n\
+                         Or set TENEMENT_SYSTEMD_RUN=/path/to/systemd-run.",
+                        instance_id
+                    );
+                }
+            }
+            // Snapshot restore and tap networking live in these runtimes
+            // and stay unreachable from the hypervisor until this is wired
+            RuntimeType::Firecracker | RuntimeType::Qemu => {
+                anyhow::bail!(
+                    "Instance {}: the hypervisor can't start {} services yet; \
+                     the runtime can only be used directly from Rust",
+                    instance_id,
+                    isolation
+                );
             }
         }
+        Ok(())
+    }
 
-        info!(
-            "Spawning instance {} (isolation: {})",
-            instance_id, isolation
-        );
+    /// Allocate the instance's TCP port and one per named `port` listener
+    async fn allocate_instance_ports(
+        &self,
+        plan: &SpawnPlan,
+        taken: &mut SpawnResources,
+    ) -> Result<()> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            ..
+        } = plan;
 
         // Allocate a TCP port for process/namespace/sandbox runtimes
         // VMs (Firecracker/QEMU) use vsock, so they don't need TCP ports
-        let port = match isolation {
+        taken.port = match process_config.isolation {
             RuntimeType::Process
             | RuntimeType::Namespace
             | RuntimeType::Litebox
             | RuntimeType::Sandbox
-            | RuntimeType::Quark => Some(
-                self.port_allocator
-                    .allocate()
-                    .await
-                    .with_context(|| format!("Failed to allocate port for {}", instance_id))?,
-            ),
+            | RuntimeType::Quark
+            | RuntimeType::Container
+            | RuntimeType::Oci
+            | RuntimeType::Wasm
+            | RuntimeType::Systemd => Some(self.allocate_port(process_config, instance_id).await?),
             RuntimeType::Firecracker | RuntimeType::Qemu => None,
         };
 
-        // Build environment
+        // Each named `port` listener gets another port from the range
+        for (listener, listener_config) in &process_config.listeners {
+            if listener_config.kind != ListenerKind::Port || taken.port.is_none() {
+                continue;
+            }
+            let p = self.port_allocator.allocate().await.with_context(|| {
+                format!(
+                    "Failed to allocate port for listener '{}' of {}",
+                    listener, instance_id
+                )
+            })?;
+            taken.listener_ports.insert(listener.clone(), p);
+        }
+        Ok(())
+    }
+
+    /// The instance's environment: its `env_file`, `env`, secrets, database
+    /// URL and per-spawn env, then the socket path and ports it was given
+    async fn instance_env(
+        &self,
+        plan: &SpawnPlan,
+        taken: &SpawnResources,
+        database_env: Option<(String, String)>,
+        extra_env: HashMap<String, String>,
+    ) -> Result<HashMap<String, String>> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            ..
+        } = plan;
+        let (process_name, id) = (instance_id.process.as_str(), instance_id.id.as_str());
+        let data_dir = &self.config().settings.data_dir;
+        let port = taken.port;
+
+        let mut env: HashMap<String, String> = plan
+            .env_file
+            .iter()
+            .map(|(k, v)| {
+                let v = process_config.interpolate(v, process_name, id, data_dir, port);
+                (k.clone(), v)
+            })
+            .collect();
+        env.extend(process_config.env_interpolated(process_name, id, data_dir, port));
+        self.resolve_secrets(&mut env)
+            .await
+            .with_context(|| format!("Not spawning {}", instance_id))?;
+        env.extend(database_env);
+
+        // Merge extra env vars
+        env.extend(extra_env);
+
+        // Set SOCKET_PATH for backwards compatibility and test scripts
+        if !plan.tcp_only {
+            env.insert(
+                "SOCKET_PATH".to_string(),
+                plan.socket.to_string_lossy().to_string(),
+            );
+        }
+
+        // Also set PORT for TCP-based runtimes (Process/Namespace/Sandbox)
+        if let Some(port) = port {
+            env.insert("PORT".to_string(), port.to_string());
+        }
+        for (listener, port) in &taken.listener_ports {
+            env.insert(ProcessConfig::listener_port_env(listener), port.to_string());
+        }
+        Ok(env)
+    }
+
+    /// Build the runtime's [`SpawnConfig`]: command line, hardening and mounts
+    fn spawn_config(
+        &self,
+        plan: &SpawnPlan,
+        port: Option<u16>,
+        env: HashMap<String, String>,
+        run_as: Option<RunAs>,
+    ) -> Result<SpawnConfig> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            socket,
+            instance_data_dir,
+            tcp_only,
+            ..
+        } = plan;
+        let (process_name, id) = (instance_id.process.as_str(), instance_id.id.as_str());
+        let data_dir = &self.config().settings.data_dir;
+
         // If the user wrote `command = "uv run python app.py"` with no args,
         // shell-split the command string into executable + arguments.
         let raw_command = process_config.command_interpolated(process_name, id, data_dir, port);
@@ -325,176 +1372,255 @@ impl Hypervisor {
         } else {
             (raw_command, explicit_args)
         };
-        let mut env = process_config.env_interpolated(process_name, id, data_dir, port);
-
-        // Merge extra env vars
-        env.extend(extra_env);
 
-        // Always set SOCKET_PATH for backwards compatibility and test scripts
-        env.insert(
-            "SOCKET_PATH".to_string(),
-            socket.to_string_lossy().to_string(),
-        );
+        // The instance's data dir and socket dir stay writable under a
+        // read-only root or a tmpfs
+        let mut hardening = process_config.profile.hardening();
+        hardening.read_only_root |= process_config.readonly_root;
+        hardening.tmpfs = process_config.tmpfs.clone();
+        if let Some(seccomp) = &process_config.seccomp {
+            hardening.seccomp = seccomp.clone();
+        }
+        hardening.run_as = run_as;
+        if hardening.is_enabled() {
+            hardening.writable.push(instance_data_dir.clone());
+            if !tcp_only {
+                if let Some(dir) = socket.parent() {
+                    hardening.writable.push(dir.to_path_buf());
+                }
+            }
+        }
 
-        // Also set PORT for TCP-based runtimes (Process/Namespace/Sandbox)
-        if let Some(port) = port {
-            env.insert("PORT".to_string(), port.to_string());
+        let mut mounts: Vec<Mount> = process_config
+            .mounts
+            .iter()
+            .map(|m| Mount {
+                source: m.source.clone(),
+                destination: m.destination.clone(),
+                readonly: m.readonly,
+            })
+            .collect();
+        // A namespaced instance with an allowlist sees only its mounts, so
+        // add its own data and socket dirs
+        if process_config.isolation == RuntimeType::Namespace
+            && !mounts.is_empty()
+            && process_config.rootfs.is_none()
+        {
+            let mut dirs = vec![instance_data_dir.clone()];
+            dirs.extend(socket.parent().filter(|_| !tcp_only).map(Path::to_path_buf));
+            for dir in dirs {
+                mounts.push(Mount {
+                    source: dir.clone(),
+                    destination: dir,
+                    readonly: false,
+                });
+            }
         }
 
-        // Build spawn config
-        let spawn_config = SpawnConfig {
+        Ok(SpawnConfig {
             command,
             args,
             env,
             socket: socket.clone(),
-            workdir: process_config.workdir.clone(),
+            workdir: process_config.workdir_interpolated(process_name, id, data_dir),
             rootfs: process_config.rootfs.clone(),
             vm_config: None,
-            mounts: process_config
-                .mounts
-                .iter()
-                .map(|m| Mount {
-                    source: m.source.clone(),
-                    destination: m.destination.clone(),
-                    readonly: m.readonly,
-                })
-                .collect(),
+            mounts,
             image: process_config.image.clone(),
             memory_limit_mb: process_config.memory_limit_mb,
+            memory_high_mb: process_config.memory_high_mb,
+            memory_swap_max_mb: process_config.memory_swap_max_mb,
             cpu_shares: process_config.cpu_shares,
-        };
+            max_pids: process_config.max_pids,
+            cpuset: process_config.cpuset.clone(),
+            systemd_properties: process_config.systemd_properties.clone(),
+            hardening,
+            user_namespace: process_config.user_namespace.clone(),
+            network: process_config.network.clone(),
+            priority: process_config.priority(),
+        })
+    }
 
-        // Spawn using the selected isolation level (we already validated it's available above)
-        let mut handle = match isolation {
-            RuntimeType::Namespace => self.namespace_runtime.spawn(&spawn_config).await?,
-            RuntimeType::Process => self.process_runtime.spawn(&spawn_config).await?,
-            RuntimeType::Litebox => self.litebox_runtime.spawn(&spawn_config).await?,
+    /// Spawn using the selected isolation level, which
+    /// [`Self::check_runtime_available`] has already validated
+    async fn start_runtime(
+        &self,
+        isolation: RuntimeType,
+        spawn_config: &SpawnConfig,
+    ) -> Result<RuntimeHandle> {
+        if let Some(runtime) = &self.custom_runtime {
+            return runtime.spawn(spawn_config).await;
+        }
+        match isolation {
+            RuntimeType::Namespace => self.namespace_runtime.spawn(spawn_config).await,
+            RuntimeType::Process => self.process_runtime.spawn(spawn_config).await,
+            RuntimeType::Litebox => self.litebox_runtime.spawn(spawn_config).await,
+            RuntimeType::Container => self.container_runtime.spawn(spawn_config).await,
+            RuntimeType::Oci => self.oci_runtime.spawn(spawn_config).await,
+            RuntimeType::Wasm => self.wasm_runtime.spawn(spawn_config).await,
+            RuntimeType::Systemd => self.systemd_runtime.spawn(spawn_config).await,
             #[cfg(feature = "sandbox")]
-            RuntimeType::Sandbox => self.sandbox_runtime.spawn(&spawn_config).await?,
+            RuntimeType::Sandbox => self.sandbox_runtime.spawn(spawn_config).await,
             #[cfg(not(feature = "sandbox"))]
             RuntimeType::Sandbox => unreachable!("sandbox feature not enabled"),
             #[cfg(feature = "quark")]
-            RuntimeType::Quark => self.quark_runtime.spawn(&spawn_config).await?,
+            RuntimeType::Quark => self.quark_runtime.spawn(spawn_config).await,
             #[cfg(not(feature = "quark"))]
             RuntimeType::Quark => unreachable!("quark feature not enabled"),
-            // Firecracker/Qemu already rejected above
+            // Firecracker/Qemu already rejected
             _ => unreachable!(),
-        };
+        }
+    }
+
+    /// Apply resource limits via cgroups v2 (Linux only). The spawned child
+    /// is killed if limits are configured but can't be enforced.
+    async fn apply_resource_limits(
+        &self,
+        plan: &SpawnPlan,
+        handle: &mut RuntimeHandle,
+    ) -> Result<()> {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            ..
+        } = plan;
 
-        // Apply resource limits via cgroups v2 (Linux only)
-        let resource_limits = ResourceLimits {
+        let mut resource_limits = ResourceLimits {
             memory_limit_mb: process_config.memory_limit_mb,
+            memory_high_mb: process_config.memory_high_mb,
+            memory_swap_max_mb: process_config.memory_swap_max_mb,
             cpu_shares: process_config.cpu_shares,
+            io_read_bps: process_config.io_read_bps,
+            io_write_bps: process_config.io_write_bps,
+            io_iops: process_config.io_iops,
+            io_device: None,
+            max_pids: process_config.max_pids,
+            cpuset: process_config.cpuset.clone(),
         };
-        if resource_limits.has_limits()
-            && !matches!(isolation, RuntimeType::Sandbox | RuntimeType::Quark)
+        // IO limits apply to the disk holding the instance's data dir
+        if resource_limits.has_io_limits() && self.custom_runtime.is_none() {
+            match crate::cgroup::block_device(&plan.instance_data_dir) {
+                Ok(device) => resource_limits.io_device = Some(device),
+                Err(e) => {
+                    let _ = handle.kill().await;
+                    return Err(e).with_context(|| {
+                        format!("IO limits for {} can't be enforced", instance_id)
+                    });
+                }
+            }
+        }
+        if !resource_limits.has_limits()
+            || self.custom_runtime.is_some()
+            || !uses_cgroup_manager(process_config.isolation)
         {
-            // Create cgroup and add process. Fail loudly if resource limits are
-            // configured but can't be applied (process would run unrestricted).
+            return Ok(());
+        }
+
+        // Create cgroup and add process. Fail loudly if resource limits are
+        // configured but can't be applied (process would run unrestricted).
+        let cgroup = instance_id.to_string();
+        if let Err(e) = self.cgroup_manager.create_cgroup(&cgroup, &resource_limits) {
+            // Kill the already-spawned child
+            let _ = handle.kill().await;
+            return Err(e).with_context(|| {
+                format!(
+                    "Failed to create cgroup for {}. Resource limits will not be enforced.",
+                    instance_id
+                )
+            });
+        }
+
+        if let Some(pid) = handle.pid() {
             if let Err(e) = self
                 .cgroup_manager
-                .create_cgroup(&instance_id.to_string(), &resource_limits)
+                .add_process(&cgroup, pid, &resource_limits)
             {
-                // Kill the already-spawned child and clean up spawning guard
                 let _ = handle.kill().await;
-                self.spawning.write().await.remove(&instance_id);
                 return Err(e).with_context(|| {
                     format!(
-                        "Failed to create cgroup for {}. Resource limits will not be enforced.",
+                        "Failed to add process to cgroup for {}. Resource limits will not be enforced.",
                         instance_id
                     )
                 });
             }
-
-            if let Some(pid) = handle.pid() {
-                if let Err(e) =
-                    self.cgroup_manager
-                        .add_process(&instance_id.to_string(), pid, &resource_limits)
-                {
-                    let _ = handle.kill().await;
-                    self.spawning.write().await.remove(&instance_id);
-                    return Err(e).with_context(|| format!(
-                        "Failed to add process to cgroup for {}. Resource limits will not be enforced.", instance_id
-                    ));
-                }
-            }
         }
+        Ok(())
+    }
 
-        // Set up log capture for runtimes where Tenement owns a child process.
-        match &mut handle {
-            RuntimeHandle::Process { ref mut child, .. }
-            | RuntimeHandle::Namespace { ref mut child, .. }
-            | RuntimeHandle::Litebox { ref mut child, .. } => {
-                // Take stdout/stderr handles and spawn capture tasks
-                let stdout = child.stdout.take();
-                let stderr = child.stderr.take();
+    /// Set up log capture for runtimes where Tenement owns a child process.
+    fn capture_logs(&self, plan: &SpawnPlan, handle: &mut RuntimeHandle) {
+        let child = match handle {
+            RuntimeHandle::Process { child, .. }
+            | RuntimeHandle::Namespace { child, .. }
+            | RuntimeHandle::Litebox { child, .. }
+            | RuntimeHandle::Wasm { child, .. }
+            | RuntimeHandle::Container { child, .. }
+            | RuntimeHandle::Oci { child, .. }
+            | RuntimeHandle::Systemd { child, .. } => child,
+            // VM runtimes handle logging differently
+            _ => return,
+        };
+        let max_line_bytes = plan.process_config.log_max_line_bytes;
 
-                // Spawn stdout capture task
-                if let Some(stdout) = stdout {
-                    let log_buffer = self.log_buffer.clone();
-                    let process = process_name.to_string();
-                    let inst_id = id.to_string();
-                    tokio::spawn(async move {
-                        let reader = BufReader::new(stdout);
-                        let mut lines = reader.lines();
-                        while let Ok(Some(line)) = lines.next_line().await {
-                            log_buffer.push_stdout(&process, &inst_id, line).await;
-                        }
-                    });
+        // Spawn stdout capture task
+        if let Some(stdout) = child.stdout.take() {
+            let log_buffer = self.log_buffer.clone();
+            let instance_id = plan.instance_id.clone();
+            tokio::spawn(async move {
+                let mut lines = LineReader::new(BufReader::new(stdout), max_line_bytes);
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log_buffer
+                        .push_line(
+                            &instance_id.process,
+                            &instance_id.id,
+                            LogLevel::Stdout,
+                            line,
+                        )
+                        .await;
                 }
+            });
+        }
 
-                // Spawn stderr capture task
-                if let Some(stderr) = stderr {
-                    let log_buffer = self.log_buffer.clone();
-                    let process = process_name.to_string();
-                    let inst_id = id.to_string();
-                    tokio::spawn(async move {
-                        let reader = BufReader::new(stderr);
-                        let mut lines = reader.lines();
-                        while let Ok(Some(line)) = lines.next_line().await {
-                            log_buffer.push_stderr(&process, &inst_id, line).await;
-                        }
-                    });
+        // Spawn stderr capture task
+        if let Some(stderr) = child.stderr.take() {
+            let log_buffer = self.log_buffer.clone();
+            let instance_id = plan.instance_id.clone();
+            tokio::spawn(async move {
+                let mut lines = LineReader::new(BufReader::new(stderr), max_line_bytes);
+                while let Ok(Some(line)) = lines.next_line().await {
+                    log_buffer
+                        .push_line(
+                            &instance_id.process,
+                            &instance_id.id,
+                            LogLevel::Stderr,
+                            line,
+                        )
+                        .await;
                 }
-            }
-            _ => {
-                // VM runtimes handle logging differently
-            }
+            });
         }
+    }
 
-        let runtime_type = handle.runtime_type();
-        let now = Instant::now();
-
-        // Restore restart history from persistent storage (survives stop/spawn cycles)
-        let (restarts, restart_times) = {
-            let history = self.restart_history.read().await;
-            history
-                .get(&instance_id)
-                .cloned()
-                .unwrap_or((0, Vec::new()))
-        };
-
-        let instance = Instance {
-            id: instance_id.clone(),
-            handle,
-            runtime_type,
-            socket: socket.clone(),
-            port,
-            started_at: now,
-            restarts,
-            consecutive_failures: 0,
-            last_health_check: None,
-            health_status: HealthStatus::Unknown,
-            restart_times,
-            last_activity: now,
-            idle_timeout: process_config.idle_timeout,
-            storage_quota_mb: process_config.storage_quota_mb,
-            storage_persist: process_config.storage_persist,
-            storage_used_bytes: 0,
-            data_dir: instance_data_dir.clone(),
-            weight: 100, // Default weight - receives full traffic
-        };
+    /// Undo a spawn that failed before its instance was registered: lock
+    /// encrypted data again, drop the spawning guard and free the ports
+    async fn abandon_spawn(&self, plan: &SpawnPlan, taken: SpawnResources) {
+        if taken.encrypted {
+            if let Err(e) = crate::encryption::unmount(&plan.instance_data_dir).await {
+                warn!(
+                    "Failed to unmount encrypted data dir for {}: {}",
+                    plan.instance_id, e
+                );
+            }
+        }
+        self.spawning.write().await.remove(&plan.instance_id);
+        self.release_ports(taken.port, &taken.listener_ports).await;
+    }
 
+    /// Make a started instance visible: add it to the table, persist it,
+    /// publish its DNS record and watch for it exiting
+    async fn register_instance(&self, instance: Instance) {
+        let instance_id = instance.id.clone();
+        let pid = instance.handle.pid();
         {
             let mut instances = self.instances.write().await;
             instances.insert(instance_id.clone(), instance);
@@ -509,77 +1635,44 @@ impl Hypervisor {
         // Update metrics
         self.metrics.instances_up.inc();
 
-        // Persist instance state for crash recovery (only if we have a PID to track)
-        if let Some(ref store) = self.state_store {
-            let pid = {
-                let instances = self.instances.read().await;
-                instances.get(&instance_id).and_then(|i| i.handle.pid())
-            };
-            if let Some(pid) = pid {
-                let state = crate::store::InstanceState {
-                    instance_id: instance_id.to_string(),
-                    process_name: process_name.to_string(),
-                    id: id.to_string(),
-                    pid,
-                    port,
-                    started_at: chrono::Utc::now().to_rfc3339(),
-                };
-                if let Err(e) = store.save(&state).await {
-                    error!(
-                        "Failed to persist instance state for {}: {}",
-                        instance_id, e
-                    );
-                }
+        // Persist instance state for crash recovery
+        self.persist_state(&instance_id).await;
+
+        // Warm instances get their record once claimed
+        if !instance_id.is_warm() {
+            if let Some(ref dns) = self.dns {
+                dns.register(&instance_id.process, &instance_id.id);
             }
         }
 
         // Spawn exit monitor: detects process exit within 1s instead of
         // waiting for the next health check cycle (up to 10s).
-        if let Some(pid) = {
-            let instances = self.instances.read().await;
-            instances.get(&instance_id).and_then(|i| i.handle.pid())
-        } {
-            let exit_instance_id = instance_id.clone();
-            let log_buffer = self.log_buffer.clone();
-            // Reference to the instances map so the monitor can check
-            // if the instance was intentionally stopped (removed from map).
-            let instances_ref = unsafe {
-                // SAFETY: The RwLock<HashMap> lives as long as the Arc<Hypervisor>,
-                // which outlives all spawned instances.
-                &*(&self.instances as *const RwLock<HashMap<InstanceId, Instance>>)
-            };
-            tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(Duration::from_secs(1)).await;
-
-                    #[cfg(unix)]
-                    let alive = unsafe { libc::kill(pid as i32, 0) } == 0;
-                    #[cfg(not(unix))]
-                    let alive = true;
-
-                    if !alive {
-                        // Check if instance was intentionally stopped (removed from map)
-                        let still_tracked = {
-                            let map = instances_ref.read().await;
-                            map.contains_key(&exit_instance_id)
-                        };
-                        if still_tracked {
-                            error!(
-                                "Instance {} (pid {}) exited unexpectedly",
-                                exit_instance_id, pid
-                            );
-                            log_buffer
-                                .push_stderr(
-                                    &exit_instance_id.process,
-                                    &exit_instance_id.id,
-                                    format!("Process exited unexpectedly (pid {})", pid),
-                                )
-                                .await;
-                        }
-                        break;
-                    }
-                }
-            });
+        if let Some(pid) = pid {
+            self.spawn_exit_monitor(instance_id, pid);
+        }
+    }
+
+    /// Wait for a registered instance to come up. With a health check it
+    /// only becomes routable once that passes; otherwise wait briefly for
+    /// its port or socket to accept connections.
+    async fn wait_until_up(&self, plan: &SpawnPlan, port: Option<u16>) {
+        let SpawnPlan {
+            instance_id,
+            process_config,
+            socket,
+            ..
+        } = plan;
+
+        // Readiness gate: with a health check, the instance only becomes
+        // routable once it passes.
+        if process_config.has_health_check() {
+            self.wait_for_readiness(
+                instance_id,
+                process_config,
+                Duration::from_secs(process_config.readiness_timeout),
+            )
+            .await;
+            return;
         }
 
         // Wait for service to be ready
@@ -588,8 +1681,9 @@ impl Hypervisor {
             let addr = format!("127.0.0.1:{}", port);
             for _ in 0..50 {
                 if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+                    self.enforce_socket_permissions(instance_id, process_config, socket);
                     info!("Instance {} ready at {}", instance_id, addr);
-                    return Ok(socket);
+                    return;
                 }
                 tokio::time::sleep(Duration::from_millis(10)).await;
             }
@@ -601,95 +1695,564 @@ impl Hypervisor {
             // Socket mode: check if file exists (VMs use vsock)
             for _ in 0..50 {
                 if socket.exists() {
+                    self.enforce_socket_permissions(instance_id, process_config, socket);
                     info!("Instance {} ready at {:?}", instance_id, socket);
-                    return Ok(socket);
+                    return;
                 }
                 tokio::time::sleep(Duration::from_millis(10)).await;
             }
             warn!("Instance {} socket not ready after 500ms", instance_id);
         }
-
-        Ok(socket)
     }
 
-    /// Stop all running instances. Called on graceful shutdown.
-    pub async fn stop_all(&self) {
-        let instance_ids: Vec<InstanceId> = {
-            let instances = self.instances.read().await;
-            instances.keys().cloned().collect()
-        };
-
-        if instance_ids.is_empty() {
+    /// Apply `socket_mode`, `socket_owner` and `socket_group` to an
+    /// instance's socket once it has created one. Failures are logged and
+    /// retried on the next health check.
+    fn enforce_socket_permissions(
+        &self,
+        instance_id: &InstanceId,
+        process_config: &ProcessConfig,
+        socket: &Path,
+    ) {
+        if !process_config.has_socket_permissions() || self.config().settings.tcp_only {
             return;
         }
-
-        info!("Stopping {} instance(s) for shutdown", instance_ids.len());
-        for instance_id in instance_ids {
-            if let Err(e) = self.stop(&instance_id.process, &instance_id.id).await {
-                error!("Failed to stop {} during shutdown: {}", instance_id, e);
-            }
+        if let Err(e) = crate::paths::apply_socket_permissions(socket, process_config) {
+            warn!("Instance {}: {:#}", instance_id, e);
         }
-        info!("All instances stopped");
     }
 
-    /// Stop an instance. Waits up to 5 seconds for active connections to drain.
-    pub async fn stop(&self, process_name: &str, id: &str) -> Result<()> {
-        let instance_id = InstanceId::new(process_name, id);
-
-        // Clear spawning guard if present (in case spawn failed and left it)
-        {
-            let mut spawning = self.spawning.write().await;
-            spawning.remove(&instance_id);
+    /// Poll the health check of a freshly spawned instance, starting after
+    /// its `health_initial_delay`, until it passes, then mark it ready.
+    /// Gives up after `timeout` more (or if the instance exits
+    /// or is stopped), leaving it unroutable until a later health check
+    /// passes.
+    async fn wait_for_readiness(
+        &self,
+        instance_id: &InstanceId,
+        process_config: &ProcessConfig,
+        timeout: Duration,
+    ) {
+        if process_config.health_initial_delay > 0 {
+            self.clock
+                .sleep(Duration::from_secs(process_config.health_initial_delay))
+                .await;
         }
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Only hold the lock for a snapshot: asking docker whether a
+            // container is still up happens after it's released
+            let target = {
+                let mut instances = self.instances.write().await;
+                instances.get_mut(instance_id).map(|instance| {
+                    (
+                        instance.handle.try_is_running(),
+                        instance.handle.container_name().map(str::to_string),
+                        instance.handle.socket().clone(),
+                        instance.handle.vsock_port(),
+                        instance.port,
+                    )
+                })
+            };
+            let running = match &target {
+                Some((Some(running), ..)) => *running,
+                Some((None, Some(container), ..)) => {
+                    crate::runtime::container_running(container).await
+                }
+                _ => false,
+            };
+            let Some((_, _, socket, vsock_port, port)) = target.filter(|_| running) else {
+                warn!("Instance {} exited before becoming ready", instance_id);
+                return;
+            };
 
-        // Wait for active connections to drain (up to 5 seconds)
-        let active = self.active_connection_count(process_name, id).await;
-        if active > 0 {
-            info!(
-                "Instance {} has {} active connection(s), draining...",
-                instance_id, active
-            );
-            for _ in 0..50 {
-                if self.active_connection_count(process_name, id).await == 0 {
-                    break;
+            let result = self
+                .probe_health(instance_id, process_config, &socket, vsock_port, port)
+                .await;
+            if result.is_ok() {
+                self.enforce_socket_permissions(instance_id, process_config, &socket);
+                let mut instances = self.instances.write().await;
+                if let Some(instance) = instances.get_mut(instance_id) {
+                    instance.ready = true;
+                    instance.health_status = HealthStatus::Healthy;
+                    instance.last_health_check = Some(self.clock.now());
                 }
-                tokio::time::sleep(Duration::from_millis(100)).await;
+                info!("Instance {} ready", instance_id);
+                return;
             }
-            let remaining = self.active_connection_count(process_name, id).await;
-            if remaining > 0 {
+
+            if Instant::now() >= deadline {
                 warn!(
-                    "Instance {} still has {} connection(s) after drain timeout, force stopping",
-                    instance_id, remaining
+                    "Instance {} not ready after {}s; it receives no traffic until a health check passes",
+                    instance_id,
+                    timeout.as_secs()
                 );
+                return;
             }
+            tokio::time::sleep(Duration::from_millis(200)).await;
         }
+    }
 
-        let mut instances = self.instances.write().await;
+    /// Mount the tenant's encrypted data dir over `plain`, deriving its key
+    /// from the master key file and the tenant's salt.
+    async fn mount_encrypted_data_dir(
+        &self,
+        process_name: &str,
+        id: &str,
+        plain: &std::path::Path,
+    ) -> Result<()> {
+        let key_file = self
+            .config()
+            .settings
+            .encryption_key_file
+            .clone()
+            .context("encrypt_data requires settings.encryption_key_file")?;
+        let tenant_keys = self
+            .tenant_keys
+            .as_ref()
+            .context("encrypt_data requires the daemon's tenant key store")?;
+        let salt = tenant_keys.salt(id).await?;
+        let passphrase = tokio::task::spawn_blocking(move || {
+            let master = crate::encryption::load_master_key(&key_file)?;
+            crate::encryption::derive_tenant_key(&master, &salt)
+        })
+        .await??;
 
-        if let Some(mut instance) = instances.remove(&instance_id) {
-            info!("Stopping instance {}", instance_id);
+        let cipher =
+            crate::encryption::cipher_dir(&self.config().settings.data_dir, process_name, id);
+        crate::encryption::mount(&cipher, plain, &passphrase).await
+    }
 
-            instance
-                .handle
-                .kill()
-                .await
-                .with_context(|| format!("Failed to kill process: {}", instance_id))?;
+    /// Connection string for the tenant's database, provisioning it on first
+    /// use. Secrets are keyed by tenant ID and database name, so services that
+    /// resolve to the same database name share it.
+    async fn tenant_database_url(
+        &self,
+        database: &crate::database::DatabaseConfig,
+        process_name: &str,
+        id: &str,
+    ) -> Result<String> {
+        let secrets = self
+            .tenant_secrets
+            .as_ref()
+            .context("database provisioning requires the daemon's tenant secret store")?;
+        let name = database.database_name(process_name, id);
+        let secret_name = format!("db:{}", name);
+        if let Some(url) = secrets.get(id, &secret_name).await? {
+            return Ok(url);
+        }
+
+        // IDs that differ only in case or punctuation, or past the name length
+        // limit, map to the same name. Provisioning again would hand the
+        // first tenant's database (and a new password for it) to this one.
+        let _provisioning = self.provisioning.lock().await;
+        if let Some(owner) = secrets
+            .owners(&secret_name)
+            .await?
+            .into_iter()
+            .find(|owner| owner != id)
+        {
+            anyhow::bail!(
+                "Database '{}' for {}:{} already belongs to tenant '{}'; \
+                 set database.name to tell them apart",
+                name,
+                process_name,
+                id,
+                owner
+            );
+        }
+        info!("Provisioning database for {}:{}", process_name, id);
+        let url = crate::database::provision(database, process_name, id).await?;
+        secrets.set(id, &secret_name, &url).await?;
+        Ok(url)
+    }
+
+    /// Record a running instance in the state store (if any) so it can be
+    /// re-adopted after a daemon restart. Instances without a PID are skipped.
+    async fn persist_state(&self, instance_id: &InstanceId) {
+        let store = match &self.state_store {
+            Some(s) => s,
+            None => return,
+        };
+        let state = {
+            let instances = self.instances.read().await;
+            let instance = match instances.get(instance_id) {
+                Some(i) => i,
+                None => return,
+            };
+            let pid = match instance.handle.pid() {
+                Some(pid) => pid,
+                None => return,
+            };
+            let started_at = chrono::Utc::now()
+                - chrono::Duration::from_std(self.clock.since(instance.started_at))
+                    .unwrap_or_default();
+            crate::store::InstanceState {
+                instance_id: instance_id.to_string(),
+                process_name: instance_id.process.clone(),
+                id: instance_id.id.clone(),
+                pid,
+                port: instance.port,
+                started_at: started_at.to_rfc3339(),
+                socket: instance.socket.to_string_lossy().to_string(),
+                runtime: instance.runtime_type.to_string(),
+                listeners: serde_json::to_string(&instance.listeners).unwrap_or_default(),
+                config: instance
+                    .spawned_config
+                    .as_ref()
+                    .and_then(|c| serde_json::to_string(c).ok())
+                    .unwrap_or_default(),
+            }
+        };
+        if let Err(e) = store.save(&state).await {
+            error!(
+                "Failed to persist instance state for {}: {}",
+                instance_id, e
+            );
+        }
+    }
+
+    /// Watch a spawned or adopted process and log when it exits while
+    /// still tracked (i.e. it was not stopped intentionally).
+    fn spawn_exit_monitor(&self, instance_id: InstanceId, pid: u32) {
+        let log_buffer = self.log_buffer.clone();
+        // Weak reference to the instances map so the monitor can check if the
+        // instance was intentionally stopped (removed from map), and exits
+        // once the hypervisor itself has been dropped.
+        let instances_ref = Arc::downgrade(&self.instances);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+
+                #[cfg(unix)]
+                let alive = unsafe { libc::kill(pid as i32, 0) } == 0;
+                #[cfg(not(unix))]
+                let alive = true;
+
+                if !alive {
+                    let Some(instances) = instances_ref.upgrade() else {
+                        break;
+                    };
+                    // Check if instance was intentionally stopped (removed from map)
+                    let still_tracked = instances.read().await.contains_key(&instance_id);
+                    if still_tracked {
+                        error!("Instance {} (pid {}) exited unexpectedly", instance_id, pid);
+                        log_buffer
+                            .push_stderr(
+                                &instance_id.process,
+                                &instance_id.id,
+                                format!("Process exited unexpectedly (pid {})", pid),
+                            )
+                            .await;
+                    }
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Stop all running instances. Called on graceful shutdown.
+    pub async fn stop_all(&self) {
+        let instance_ids: Vec<InstanceId> = {
+            let instances = self.instances.read().await;
+            instances.keys().cloned().collect()
+        };
+
+        if instance_ids.is_empty() {
+            return;
+        }
+
+        info!("Stopping {} instance(s) for shutdown", instance_ids.len());
+        for instance_id in instance_ids {
+            if let Err(e) = self
+                .stop_instance(&instance_id.process, &instance_id.id)
+                .await
+            {
+                error!("Failed to stop {} during shutdown: {}", instance_id, e);
+            }
+        }
+        info!("All instances stopped");
+    }
+
+    /// Stop every instance for daemon shutdown, gracefully.
+    ///
+    /// All instances are marked draining (and the monitor stops restarting or
+    /// waking anything), in-flight connections get up to 5 seconds to finish,
+    /// then every process is sent SIGTERM at once and given its service's
+    /// `stop_grace_period` to exit before it is killed.
+    pub async fn shutdown_all(&self) {
+        self.shutting_down
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+
+        let instance_ids: Vec<InstanceId> = {
+            let mut instances = self.instances.write().await;
+            for instance in instances.values_mut() {
+                instance.draining = true;
+            }
+            instances.keys().cloned().collect()
+        };
+        if instance_ids.is_empty() {
+            return;
+        }
+        info!("Shutting down {} instance(s)", instance_ids.len());
+
+        let drain_deadline = Instant::now() + STOP_DRAIN_TIMEOUT;
+        for instance_id in &instance_ids {
+            let remaining = drain_deadline.saturating_duration_since(Instant::now());
+            self.wait_for_connections(instance_id, remaining).await;
+        }
+
+        // SIGTERM everything, then reap as processes exit or their grace runs out
+        let mut pending: Vec<(InstanceId, Instant)> = Vec::new();
+        {
+            let instances = self.instances.read().await;
+            for instance_id in instance_ids {
+                let grace = self
+                    .config()
+                    .get_service(&instance_id.process)
+                    .map(|c| c.stop_grace_period)
+                    .unwrap_or(0);
+                let signalled = instances
+                    .get(&instance_id)
+                    .is_some_and(|i| i.handle.terminate());
+                let grace = if signalled {
+                    Duration::from_secs(grace)
+                } else {
+                    Duration::ZERO
+                };
+                pending.push((instance_id, Instant::now() + grace));
+            }
+        }
+
+        while !pending.is_empty() {
+            let mut still_pending = Vec::new();
+            for (instance_id, deadline) in pending {
+                let running = {
+                    let mut instances = self.instances.write().await;
+                    match instances.get_mut(&instance_id) {
+                        Some(instance) => instance.handle.is_running().await,
+                        None => continue,
+                    }
+                };
+                if running && Instant::now() < deadline {
+                    still_pending.push((instance_id, deadline));
+                    continue;
+                }
+                if running {
+                    warn!(
+                        "Instance {} did not exit within its grace period, killing",
+                        instance_id
+                    );
+                }
+                if let Err(e) = self.stop_now(&instance_id).await {
+                    error!("Failed to stop {} during shutdown: {}", instance_id, e);
+                }
+            }
+            pending = still_pending;
+            if !pending.is_empty() {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+        info!("All instances stopped");
+    }
+
+    /// True once `shutdown_all` has started
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    /// Stop an instance. Waits up to 5 seconds for active connections to drain.
+    /// Its DNS record (with `[dns]`) and spawn options are removed.
+    pub async fn stop(&self, process_name: &str, id: &str) -> Result<()> {
+        let result = self.stop_instance(process_name, id).await;
+        self.forget_instance(process_name, id).await;
+        result
+    }
+
+    /// Stop an instance that is coming back (restart, idle stop, daemon
+    /// shutdown), keeping its DNS record
+    async fn stop_instance(&self, process_name: &str, id: &str) -> Result<()> {
+        let instance_id = InstanceId::new(process_name, id);
+
+        // Clear spawning guard if present (in case spawn failed and left it)
+        {
+            let mut spawning = self.spawning.write().await;
+            spawning.remove(&instance_id);
+        }
+
+        self.wait_for_connections(&instance_id, STOP_DRAIN_TIMEOUT)
+            .await;
+        self.stop_now(&instance_id).await
+    }
+
+    /// Drain an instance, then stop it.
+    ///
+    /// Marks the instance as draining so weighted selection and subdomain
+    /// routing stop sending it new requests, waits up to `timeout` for
+    /// in-flight proxy connections to finish, and then stops it.
+    pub async fn drain(&self, process_name: &str, id: &str, timeout: Duration) -> Result<()> {
+        let instance_id = InstanceId::new(process_name, id);
+
+        {
+            let mut instances = self.instances.write().await;
+            match instances.get_mut(&instance_id) {
+                Some(instance) => instance.draining = true,
+                None => anyhow::bail!("Instance not found: {}", instance_id),
+            }
+        }
+        info!("Draining instance {} (timeout: {:?})", instance_id, timeout);
+
+        self.wait_for_connections(&instance_id, timeout).await;
+        let result = self.stop_now(&instance_id).await;
+        self.forget_instance(process_name, id).await;
+        result
+    }
+
+    /// Drop what an instance that isn't coming back leaves behind: its DNS
+    /// record and the options it was spawned with
+    async fn forget_instance(&self, process_name: &str, id: &str) {
+        self.spawn_options
+            .write()
+            .await
+            .remove(&InstanceId::new(process_name, id));
+        self.unregister_dns(process_name, id);
+    }
+
+    fn unregister_dns(&self, process_name: &str, id: &str) {
+        if let Some(ref dns) = self.dns {
+            if !InstanceId::new(process_name, id).is_warm() {
+                dns.unregister(process_name, id);
+            }
+        }
+    }
+
+    /// Check if an instance is currently draining
+    pub async fn is_draining(&self, process_name: &str, id: &str) -> bool {
+        let instance_id = InstanceId::new(process_name, id);
+        let instances = self.instances.read().await;
+        instances
+            .get(&instance_id)
+            .map(|i| i.draining)
+            .unwrap_or(false)
+    }
+
+    /// Wait for an instance to pass its readiness gate, up to the service's
+    /// `health_initial_delay` plus `readiness_timeout`. Returns false if it
+    /// isn't ready by then (or is gone).
+    pub async fn wait_until_ready(&self, process_name: &str, id: &str) -> bool {
+        let instance_id = InstanceId::new(process_name, id);
+        let timeout = self
+            .config()
+            .get_service(process_name)
+            .map(|p| p.health_initial_delay + p.readiness_timeout)
+            .unwrap_or(30);
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        loop {
+            match self.instances.read().await.get(&instance_id) {
+                Some(i) if i.ready => return true,
+                Some(_) => {}
+                None => return false,
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    /// Wait for active connections to an instance to reach zero, up to `timeout`.
+    /// Logs a warning if connections remain when the timeout expires.
+    async fn wait_for_connections(&self, instance_id: &InstanceId, timeout: Duration) {
+        let active = self
+            .active_connection_count(&instance_id.process, &instance_id.id)
+            .await;
+        if active == 0 {
+            return;
+        }
+
+        info!(
+            "Instance {} has {} active connection(s), draining...",
+            instance_id, active
+        );
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self
+                .active_connection_count(&instance_id.process, &instance_id.id)
+                .await
+                == 0
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        let remaining = self
+            .active_connection_count(&instance_id.process, &instance_id.id)
+            .await;
+        if remaining > 0 {
+            warn!(
+                "Instance {} still has {} connection(s) after drain timeout, force stopping",
+                instance_id, remaining
+            );
+        }
+    }
+
+    /// Kill an instance and clean up its resources without waiting for connections.
+    async fn stop_now(&self, instance_id: &InstanceId) -> Result<()> {
+        let mut instances = self.instances.write().await;
+
+        if let Some(mut instance) = instances.remove(instance_id) {
+            info!("Stopping instance {}", instance_id);
+            if let Some(time) = self.unmetered_time(&instance, self.clock.now()) {
+                self.meter.instance(instance_id).add_compute(time);
+            }
 
-            // Release allocated port back to the pool
-            if let Some(port) = instance.port {
-                self.port_allocator.release(port).await;
+            instance
+                .handle
+                .kill()
+                .await
+                .with_context(|| format!("Failed to kill process: {}", instance_id))?;
+
+            // Release allocated ports back to the pool
+            for listener in &instance.listeners {
+                if let Some(port) = listener.addr.port() {
+                    self.port_allocator.release(port).await;
+                }
             }
 
             // Clean up cgroup (if one was created)
             if let Err(e) = self.cgroup_manager.remove_cgroup(&instance_id.to_string()) {
                 warn!("Failed to remove cgroup for {}: {}", instance_id, e);
             }
+            self.log_buffer
+                .forget_relabels(&instance_id.process, &instance_id.id);
 
-            // Clean up socket
-            if instance.socket.exists() {
+            // Clean up socket (for containers, a symlink into `<socket>.d`)
+            if std::fs::symlink_metadata(&instance.socket).is_ok() {
                 std::fs::remove_file(&instance.socket).ok();
             }
 
+            // Lock encrypted data at rest while the instance is down
+            let encrypted = self
+                .config()
+                .get_service(&instance_id.process)
+                .is_some_and(|c| c.encrypt_data);
+            if encrypted {
+                if let Err(e) = crate::encryption::unmount(&instance.data_dir).await {
+                    warn!(
+                        "Failed to unmount encrypted data dir for {}: {}",
+                        instance_id, e
+                    );
+                }
+                if !instance.storage_persist {
+                    let cipher = crate::encryption::cipher_dir(
+                        &self.config().settings.data_dir,
+                        &instance_id.process,
+                        &instance_id.id,
+                    );
+                    std::fs::remove_dir_all(&cipher).ok();
+                }
+            }
+
             // Clean up data directory if storage_persist is false
             if !instance.storage_persist && instance.data_dir.exists() {
                 if let Err(e) = std::fs::remove_dir_all(&instance.data_dir) {
@@ -735,7 +2298,7 @@ impl Hypervisor {
         };
 
         // Stop if running
-        let _ = self.stop(process_name, id).await;
+        let _ = self.stop_instance(process_name, id).await;
 
         // Calculate and apply exponential backoff delay
         let backoff_delay = self.calculate_backoff(restarts);
@@ -746,22 +2309,25 @@ impl Hypervisor {
                 instance_id,
                 restarts + 1
             );
-            tokio::time::sleep(backoff_delay).await;
+            self.clock.sleep(backoff_delay).await;
         }
 
         // Spawn again
         let socket = self.spawn(process_name, id).await?;
 
         // Update persistent restart history
-        let window = Duration::from_secs(self.config.settings.restart_window);
+        let window = Duration::from_secs(self.config().settings.restart_window);
         {
             let mut history = self.restart_history.write().await;
             let entry = history
                 .entry(instance_id.clone())
                 .or_insert((0, Vec::new()));
             entry.0 = restarts + 1;
-            entry.1.push(Instant::now());
-            entry.1.retain(|t| t.elapsed() < window);
+            let now = self.clock.now();
+            entry.1.push(now);
+            entry
+                .1
+                .retain(|t| now.saturating_duration_since(*t) < window);
         }
 
         // Also update the instance's restart count for display
@@ -794,8 +2360,8 @@ impl Hypervisor {
             return Duration::ZERO;
         }
 
-        let base_ms = self.config.settings.backoff_base_ms;
-        let max_ms = self.config.settings.backoff_max_ms;
+        let base_ms = self.config().settings.backoff_base_ms;
+        let max_ms = self.config().settings.backoff_max_ms;
 
         // Calculate delay: base * 2^(restarts - 1)
         // Cap the shift amount to prevent overflow (max 63 bits for u64)
@@ -803,7 +2369,16 @@ impl Hypervisor {
         let multiplier = 1u64.checked_shl(shift).unwrap_or(u64::MAX);
         let delay_ms = base_ms.saturating_mul(multiplier).min(max_ms);
 
-        Duration::from_millis(delay_ms)
+        // Jitter: shorten by a random amount within backoff_jitter percent
+        let jitter = self.config().settings.backoff_jitter.min(100) as u128;
+        let max_reduction = (delay_ms as u128 * jitter / 100) as u64;
+        let reduction = if max_reduction > 0 {
+            rand::Rng::gen_range(&mut rand::thread_rng(), 0..=max_reduction)
+        } else {
+            0
+        };
+
+        Duration::from_millis(delay_ms - reduction)
     }
 
     /// Check if an instance is running
@@ -816,8 +2391,8 @@ impl Hypervisor {
     /// Spawn if not already running
     pub async fn spawn_if_not_running(&self, process_name: &str, id: &str) -> Result<PathBuf> {
         if self.is_running(process_name, id).await {
-            let process_config = self
-                .config
+            let config = self.config();
+            let process_config = config
                 .get_service(process_name)
                 .context("Unknown process")?;
             Ok(process_config.socket_path(process_name, id))
@@ -829,14 +2404,42 @@ impl Hypervisor {
     /// List all running instances
     pub async fn list(&self) -> Vec<InstanceInfo> {
         let instances = self.instances.read().await;
-        instances.values().map(|i| i.info()).collect()
+        instances
+            .values()
+            .filter(|i| !i.id.is_warm())
+            .map(|i| i.info_at(self.clock.now()))
+            .collect()
+    }
+
+    /// PIDs (process group leaders) of running instances that have one
+    pub async fn instance_pids(&self) -> HashMap<InstanceId, u32> {
+        let instances = self.instances.read().await;
+        instances
+            .values()
+            .filter_map(|i| Some((i.id.clone(), i.handle.pid()?)))
+            .collect()
+    }
+
+    /// List instances carrying every label in `selector`
+    /// (an empty selector lists everything, like `list`)
+    pub async fn list_matching(
+        &self,
+        selector: &std::collections::BTreeMap<String, String>,
+    ) -> Vec<InstanceInfo> {
+        self.list()
+            .await
+            .into_iter()
+            .filter(|i| i.matches_labels(selector))
+            .collect()
     }
 
     /// Get info for a specific instance
     pub async fn get(&self, process_name: &str, id: &str) -> Option<InstanceInfo> {
         let instance_id = InstanceId::new(process_name, id);
         let instances = self.instances.read().await;
-        instances.get(&instance_id).map(|i| i.info())
+        instances
+            .get(&instance_id)
+            .map(|i| i.info_at(self.clock.now()))
     }
 
     /// Get storage information for a specific instance
@@ -865,8 +2468,8 @@ impl Hypervisor {
         let instance_id = InstanceId::new(process_name, id);
         let mut instances = self.instances.write().await;
         if let Some(instance) = instances.get_mut(&instance_id) {
-            instance.touch();
-            Some(instance.info())
+            instance.touch_at(self.clock.now());
+            Some(instance.info_at(self.clock.now()))
         } else {
             None
         }
@@ -874,7 +2477,7 @@ impl Hypervisor {
 
     /// Check if a process is configured (can be spawned)
     pub fn has_process(&self, process_name: &str) -> bool {
-        self.config.get_service(process_name).is_some()
+        self.config().get_service(process_name).is_some()
     }
 
     /// Increment active connection count for an instance. Returns a guard
@@ -904,90 +2507,288 @@ impl Hypervisor {
 
     /// Get the request timeout for a process (in seconds)
     pub fn request_timeout(&self, process_name: &str) -> Duration {
-        let secs = self
-            .config
+        let config = self.config();
+        let secs = config
             .get_service(process_name)
-            .map(|p| p.request_timeout)
-            .unwrap_or(30);
+            .map(|p| config.request_timeout(p))
+            .unwrap_or(config.settings.request_timeout);
         Duration::from_secs(secs)
     }
 
-    /// Check health of an instance
-    pub async fn check_health(&self, process_name: &str, id: &str) -> HealthStatus {
-        let instance_id = InstanceId::new(process_name, id);
+    /// How many times a failed idempotent request to `process_name` is
+    /// retried on another instance
+    pub fn request_retries(&self, process_name: &str) -> u32 {
+        self.config()
+            .get_service(process_name)
+            .map(|p| p.request_retries)
+            .unwrap_or(1)
+    }
 
-        let process_config = match self.config.get_service(process_name) {
-            Some(c) => c,
-            None => return HealthStatus::Unknown,
-        };
+    /// Largest request body in bytes proxied to `process_name`, if limited
+    pub fn max_body_size(&self, process_name: &str) -> Option<u64> {
+        let config = self.config();
+        config
+            .get_service(process_name)
+            .and_then(|p| config.max_body_size(p))
+    }
 
-        // If no health endpoint configured, assume healthy if socket exists
-        let health_endpoint = match &process_config.health {
-            Some(h) => h,
-            None => {
-                let socket = process_config.socket_path(process_name, id);
-                return if socket.exists() {
-                    HealthStatus::Healthy
-                } else {
-                    HealthStatus::Unhealthy
-                };
-            }
-        };
+    /// Headers set on requests proxied to `process_name`
+    pub fn request_headers(&self, process_name: &str) -> BTreeMap<String, String> {
+        self.config()
+            .get_service(process_name)
+            .map(|p| p.request_headers.clone())
+            .unwrap_or_default()
+    }
 
-        // Get socket, vsock port, and TCP port from the running instance
-        let (socket, vsock_port, tcp_port) = {
-            let instances = self.instances.read().await;
-            match instances.get(&instance_id) {
-                Some(instance) => (
-                    instance.handle.socket().clone(),
-                    instance.handle.vsock_port(),
-                    instance.port,
-                ),
-                None => return HealthStatus::Unknown,
-            }
-        };
+    /// Headers set on responses proxied from `process_name`
+    pub fn response_headers(&self, process_name: &str) -> BTreeMap<String, String> {
+        self.config()
+            .get_service(process_name)
+            .map(|p| p.response_headers.clone())
+            .unwrap_or_default()
+    }
 
-        // Use TCP health check for process/namespace/sandbox runtimes,
-        // fall back to Unix socket for VMs
-        let result = if let Some(port) = tcp_port {
-            self.ping_health_tcp(port, health_endpoint).await
-        } else {
-            self.ping_health_with_vsock(&socket, health_endpoint, vsock_port)
-                .await
-        };
+    /// Error page file for a proxy-generated `status` on `process_name`
+    pub fn error_page(&self, process_name: &str, status: u16) -> Option<PathBuf> {
+        self.config().error_page(process_name, status).cloned()
+    }
 
-        let mut instances = self.instances.write().await;
-        let instance = match instances.get_mut(&instance_id) {
-            Some(i) => i,
-            None => return HealthStatus::Unknown,
-        };
+    /// The `cors` policy of `process_name`, if it has one
+    pub fn cors(&self, process_name: &str) -> Option<crate::cors::CorsConfig> {
+        self.config()
+            .get_service(process_name)
+            .and_then(|p| p.cors.clone())
+    }
 
-        instance.last_health_check = Some(Instant::now());
+    /// Where a custom domain routes, if `host` is one from `[domains]`
+    pub fn custom_domain(&self, host: &str) -> Option<crate::config::DomainConfig> {
+        self.config()
+            .domains
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+    }
 
-        match result {
-            Ok(()) => {
-                instance.consecutive_failures = 0;
-                instance.health_status = HealthStatus::Healthy;
-                HealthStatus::Healthy
-            }
-            Err(e) => {
-                instance.consecutive_failures += 1;
-                warn!(
+    /// Whether responses from `process_name` are compressed at the proxy
+    pub fn compresses(&self, process_name: &str) -> bool {
+        self.config()
+            .get_service(process_name)
+            .is_some_and(|p| p.compression)
+    }
+
+    /// Whether weighted requests to `process_name` stick to one instance
+    pub fn is_sticky(&self, process_name: &str) -> bool {
+        self.config()
+            .get_service(process_name)
+            .is_some_and(|p| p.sticky)
+    }
+
+    /// The `rate_limit` of `process_name`, if it has one
+    pub fn rate_limit(&self, process_name: &str) -> Option<crate::rate_limit::RateLimitConfig> {
+        self.config()
+            .get_service(process_name)
+            .and_then(|p| p.rate_limit.clone())
+    }
+
+    /// Take a token from the `process_name` bucket for `key` (a subdomain or
+    /// instance ID). Always true for services without a `rate_limit`.
+    pub fn allow_request(&self, process_name: &str, key: &str) -> bool {
+        match self.rate_limit(process_name) {
+            Some(limit) => self.rate_limiter.check(
+                &format!("{}/{}", process_name, key),
+                &limit,
+                Instant::now(),
+            ),
+            None => true,
+        }
+    }
+
+    /// Request counters of an instance
+    pub fn meter(&self, process_name: &str, id: &str) -> Arc<crate::metering::InstanceMeter> {
+        self.meter.instance(&InstanceId::new(process_name, id))
+    }
+
+    /// Start crediting instances' run time as compute-seconds. Time before
+    /// this (e.g. already flushed by a previous daemon) is not counted.
+    pub fn start_metering(&self) {
+        self.metered_at
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| self.clock.now());
+    }
+
+    /// Run time of an instance not yet credited to the meter
+    fn unmetered_time(&self, instance: &Instance, now: Instant) -> Option<Duration> {
+        let metered_at = (*self.metered_at.lock().unwrap())?;
+        if instance.id.is_warm() || instance.exit_code.is_some() {
+            return None;
+        }
+        Some(now.saturating_duration_since(metered_at.max(instance.started_at)))
+    }
+
+    /// Read and reset the meter, with compute-seconds credited up to now
+    pub async fn take_meter_readings(&self) -> HashMap<InstanceId, crate::metering::MeterReading> {
+        // Holding the instances lock keeps `stop_now` from crediting the
+        // same time again
+        let instances = self.instances.read().await;
+        let now = self.clock.now();
+        for instance in instances.values() {
+            if let Some(time) = self.unmetered_time(instance, now) {
+                self.meter.instance(&instance.id).add_compute(time);
+            }
+        }
+        if let Some(metered_at) = self.metered_at.lock().unwrap().as_mut() {
+            *metered_at = now;
+        }
+        self.meter.take()
+    }
+
+    /// The `auth` gate of `process_name`, if it has one
+    pub fn auth_gate(&self, process_name: &str) -> Option<crate::gate::AuthGateConfig> {
+        self.config()
+            .get_service(process_name)
+            .and_then(|p| p.auth.clone())
+    }
+
+    /// Whether a request with `authorization` (its `Authorization` header)
+    /// gets past the `auth` gate of `process_name`. Always true for services
+    /// without one.
+    pub async fn authorize(&self, process_name: &str, authorization: Option<&str>) -> bool {
+        match self.auth_gate(process_name) {
+            Some(gate) => self.auth_gate.allows(&gate, authorization).await,
+            None => true,
+        }
+    }
+
+    /// The listener of `process_name` a request for `path` goes to
+    pub fn listener_for(&self, process_name: &str, path: &str) -> String {
+        self.config()
+            .get_service(process_name)
+            .map(|p| p.listener_for_path(path))
+            .unwrap_or(crate::config::MAIN_LISTENER)
+            .to_string()
+    }
+
+    /// Check health of an instance
+    pub async fn check_health(&self, process_name: &str, id: &str) -> HealthStatus {
+        let instance_id = InstanceId::new(process_name, id);
+
+        let config = self.config();
+        let process_config = match config.get_service(process_name) {
+            Some(c) => c,
+            None => return HealthStatus::Unknown,
+        };
+
+        // Re-applied every pass, in case the socket appeared late or the
+        // instance recreated it
+        self.enforce_socket_permissions(
+            &instance_id,
+            process_config,
+            &process_config.socket_path(process_name, id),
+        );
+
+        // If no health check configured, assume healthy if the instance is
+        // listening: its socket exists, or with tcp_only its port accepts
+        let timeout = Duration::from_secs(process_config.health_timeout);
+        if !process_config.has_health_check() {
+            let listening = if self.config().settings.tcp_only {
+                let port = self.get(process_name, id).await.and_then(|i| i.port);
+                match port {
+                    Some(port) => tokio::time::timeout(
+                        timeout,
+                        tokio::net::TcpStream::connect(("127.0.0.1", port)),
+                    )
+                    .await
+                    .is_ok_and(|r| r.is_ok()),
+                    None => return HealthStatus::Unknown,
+                }
+            } else {
+                process_config.socket_path(process_name, id).exists()
+            };
+            return if listening {
+                HealthStatus::Healthy
+            } else {
+                HealthStatus::Unhealthy
+            };
+        }
+
+        // Get socket, vsock port, and TCP port from the running instance
+        let (socket, vsock_port, tcp_port) = {
+            let instances = self.instances.read().await;
+            match instances.get(&instance_id) {
+                Some(instance) => (
+                    instance.handle.socket().clone(),
+                    instance.handle.vsock_port(),
+                    instance.port,
+                ),
+                None => return HealthStatus::Unknown,
+            }
+        };
+
+        // Intervals count from the start of a check, so a slow one doesn't
+        // push the next past its tick
+        let checked_at = self.clock.now();
+        let check_started = Instant::now();
+        let result = self
+            .probe_health(&instance_id, process_config, &socket, vsock_port, tcp_port)
+            .await;
+
+        let mut instances = self.instances.write().await;
+        let instance = match instances.get_mut(&instance_id) {
+            Some(i) => i,
+            None => return HealthStatus::Unknown,
+        };
+
+        instance.last_health_check = Some(checked_at);
+
+        match result {
+            Ok(()) => {
+                instance.consecutive_failures = 0;
+                let latency = check_started.elapsed();
+                let slow = self
+                    .config()
+                    .settings
+                    .degraded_latency_ms
+                    .is_some_and(|limit| latency > Duration::from_millis(limit));
+                if !instance.ready {
+                    info!(
+                        "Instance {} passed its first health check, now ready",
+                        instance_id
+                    );
+                    instance.ready = true;
+                }
+                let status = if slow {
+                    warn!(
+                        "Health check for {} took {}ms, marking degraded",
+                        instance_id,
+                        latency.as_millis()
+                    );
+                    HealthStatus::Degraded
+                } else {
+                    HealthStatus::Healthy
+                };
+                instance.health_status = status;
+                status
+            }
+            Err(e) => {
+                instance.consecutive_failures += 1;
+                warn!(
                     "Health check failed for {}: {} (failures: {})",
                     instance_id, e, instance.consecutive_failures
                 );
 
                 let status = match instance.consecutive_failures {
-                    1..=2 => HealthStatus::Degraded,
+                    n if n < process_config.health_failures_before_restart => {
+                        HealthStatus::Degraded
+                    }
                     _ => {
-                        let window = Duration::from_secs(self.config.settings.restart_window);
+                        let window = Duration::from_secs(self.config().settings.restart_window);
                         let recent_restarts = instance
                             .restart_times
                             .iter()
-                            .filter(|t| t.elapsed() < window)
+                            .filter(|t| self.clock.since(**t) < window)
                             .count() as u32;
 
-                        if recent_restarts >= self.config.settings.max_restarts {
+                        if recent_restarts >= self.config().settings.max_restarts {
                             HealthStatus::Failed
                         } else {
                             HealthStatus::Unhealthy
@@ -1000,13 +2801,99 @@ impl Hypervisor {
         }
     }
 
+    /// Run one health check: `health_cmd` if set, otherwise an HTTP GET of
+    /// `health` over TCP (process/namespace/sandbox) or the Unix socket (VMs)
+    async fn probe_health(
+        &self,
+        instance_id: &InstanceId,
+        process_config: &ProcessConfig,
+        socket: &PathBuf,
+        vsock_port: Option<u32>,
+        port: Option<u16>,
+    ) -> Result<()> {
+        let timeout = Duration::from_secs(process_config.health_timeout);
+        if let Some(command) = &process_config.health_cmd {
+            return self
+                .exec_health(instance_id, process_config, command, socket, port)
+                .await;
+        }
+        let endpoint = process_config.health.as_deref().unwrap_or("/");
+        match port {
+            Some(port) => self.ping_health_tcp(port, endpoint, timeout).await,
+            None => {
+                self.ping_health_with_vsock(socket, endpoint, vsock_port, timeout)
+                    .await
+            }
+        }
+    }
+
+    /// Run `health_cmd` through `sh -c`. Exit code 0 is healthy; anything
+    /// else, or running longer than `health_timeout`, is a failure.
+    async fn exec_health(
+        &self,
+        instance_id: &InstanceId,
+        process_config: &ProcessConfig,
+        command: &str,
+        socket: &PathBuf,
+        port: Option<u16>,
+    ) -> Result<()> {
+        let (name, id) = (&instance_id.process, &instance_id.id);
+        let data_dir = &self.config().settings.data_dir;
+        // Placeholders come from the environment: IDs arrive in Host headers
+        let command = crate::config::shell_placeholders(
+            &crate::config::expand_host_env(command),
+            &[
+                ("name", "TENEMENT_SERVICE"),
+                ("id", "TENEMENT_INSTANCE"),
+                ("data_dir", "TENEMENT_DATA_DIR"),
+                ("socket", "TENEMENT_SOCKET"),
+                ("port", "PORT"),
+            ],
+        );
+
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c")
+            .arg(&command)
+            .env("TENEMENT_SERVICE", name)
+            .env("TENEMENT_INSTANCE", id)
+            .env("TENEMENT_DATA_DIR", data_dir)
+            .env("TENEMENT_SOCKET", process_config.socket_path(name, id))
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(port) = port {
+            cmd.env("PORT", port.to_string());
+        }
+        if !self.config().settings.tcp_only {
+            cmd.env("SOCKET_PATH", socket);
+        }
+        if let Some(workdir) = process_config.workdir_interpolated(name, id, data_dir) {
+            cmd.current_dir(workdir);
+        }
+
+        let timeout = Duration::from_secs(process_config.health_timeout);
+        let out = tokio::time::timeout(timeout, cmd.output())
+            .await
+            .context("Health command timed out")?
+            .with_context(|| format!("Failed to run health command: {}", command))?;
+        if !out.status.success() {
+            anyhow::bail!(
+                "Health command exited with {}: {}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
     /// Ping a health endpoint via TCP (for process/namespace/sandbox runtimes)
-    async fn ping_health_tcp(&self, port: u16, endpoint: &str) -> Result<()> {
+    async fn ping_health_tcp(&self, port: u16, endpoint: &str, timeout: Duration) -> Result<()> {
         use tokio::io::{AsyncReadExt, AsyncWriteExt};
         use tokio::net::TcpStream;
 
         let addr = format!("127.0.0.1:{}", port);
-        let mut stream = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, TcpStream::connect(&addr))
+        let mut stream = tokio::time::timeout(timeout, TcpStream::connect(&addr))
             .await
             .context("TCP connection timeout")?
             .context("Failed to connect")?;
@@ -1021,7 +2908,7 @@ impl Hypervisor {
             .context("Failed to write request")?;
 
         let mut response = vec![0u8; 1024];
-        let n = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, stream.read(&mut response))
+        let n = tokio::time::timeout(timeout, stream.read(&mut response))
             .await
             .context("Read timeout")?
             .context("Failed to read response")?;
@@ -1046,11 +2933,12 @@ impl Hypervisor {
         socket_path: &PathBuf,
         endpoint: &str,
         vsock_port: Option<u32>,
+        timeout: Duration,
     ) -> Result<()> {
         use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
         use tokio::net::UnixStream;
 
-        let stream = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, UnixStream::connect(socket_path))
+        let stream = tokio::time::timeout(timeout, UnixStream::connect(socket_path))
             .await
             .context("Connection timeout")?
             .context("Failed to connect")?;
@@ -1069,7 +2957,7 @@ impl Hypervisor {
 
             // Read response line
             let mut response_line = String::new();
-            tokio::time::timeout(HEALTH_CHECK_TIMEOUT, reader.read_line(&mut response_line))
+            tokio::time::timeout(timeout, reader.read_line(&mut response_line))
                 .await
                 .context("CONNECT response timeout")?
                 .context("Failed to read CONNECT response")?;
@@ -1096,7 +2984,7 @@ impl Hypervisor {
             .context("Failed to write request")?;
 
         let mut response = vec![0u8; 1024];
-        let n = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, reader.read(&mut response))
+        let n = tokio::time::timeout(timeout, reader.read(&mut response))
             .await
             .context("Read timeout")?
             .context("Failed to read response")?;
@@ -1109,11 +2997,168 @@ impl Hypervisor {
         }
     }
 
+    /// Report processes the kernel OOM-killed in instance cgroups since the
+    /// last check: an error log, a `[tenement]` line in the instance's logs
+    /// and `tenement_instance_oom_kills_total`. Returns the instances with new
+    /// kills.
+    async fn check_oom_kills(&self) -> Vec<InstanceId> {
+        let killed: Vec<(InstanceId, u64)> = {
+            let mut instances = self.instances.write().await;
+            instances
+                .values_mut()
+                .filter_map(|instance| {
+                    let count = self.cgroup_manager.oom_kills(&instance.id.to_string())?;
+                    let new = count.checked_sub(instance.oom_kills).filter(|n| *n > 0)?;
+                    instance.oom_kills = count;
+                    Some((instance.id.clone(), new))
+                })
+                .collect()
+        };
+
+        for (instance_id, kills) in &killed {
+            let limit = self
+                .config()
+                .get_service(&instance_id.process)
+                .and_then(|c| c.memory_limit_mb)
+                .map_or("its memory limit".to_string(), |mb| {
+                    format!("its memory limit ({} MB)", mb)
+                });
+            let message = format!(
+                "[tenement] OOM-killed: {} process(es) killed at {}",
+                kills, limit
+            );
+            error!("Instance {}: {}", instance_id, message);
+            self.log_buffer
+                .push_stderr(&instance_id.process, &instance_id.id, message)
+                .await;
+
+            let labels = HashMap::from([
+                ("process".to_string(), instance_id.process.clone()),
+                ("id".to_string(), instance_id.id.clone()),
+            ]);
+            self.metrics
+                .instance_oom_kills
+                .with_labels(&labels)
+                .await
+                .inc_by(*kills);
+        }
+        killed
+            .into_iter()
+            .map(|(instance_id, _)| instance_id)
+            .collect()
+    }
+
+    /// Apply the restart policy to instances whose process has exited.
+    /// Returns the IDs of instances that were left stopped.
+    pub async fn handle_exited_instances(&self) -> Vec<InstanceId> {
+        // Before restarts remove the cgroups holding the counts
+        let oom_killed = self.check_oom_kills().await;
+        let exited: Vec<(InstanceId, i32)> = {
+            let mut instances = self.instances.write().await;
+            instances
+                .values_mut()
+                // Draining instances are on their way out; don't restart them
+                .filter(|i| i.exit_code.is_none() && !i.draining)
+                .filter_map(|i| i.handle.exit_code().map(|code| (i.id.clone(), code)))
+                .collect()
+        };
+
+        let mut kept_down = Vec::new();
+        for (instance_id, code) in exited {
+            let cause = if oom_killed.contains(&instance_id) {
+                " after an OOM kill"
+            } else {
+                ""
+            };
+            let policy_allows = self
+                .config()
+                .get_service(&instance_id.process)
+                .is_some_and(|c| c.should_restart(Some(code)));
+
+            let window = Duration::from_secs(self.config().settings.restart_window);
+            let recent_restarts = {
+                let history = self.restart_history.read().await;
+                history
+                    .get(&instance_id)
+                    .map(|(_, times)| {
+                        times
+                            .iter()
+                            .filter(|t| self.clock.since(**t) < window)
+                            .count()
+                    })
+                    .unwrap_or(0) as u32
+            };
+
+            if policy_allows && recent_restarts < self.config().settings.max_restarts {
+                info!(
+                    "Instance {} exited with code {}{}, restarting",
+                    instance_id, code, cause
+                );
+                if let Err(e) = self.restart(&instance_id.process, &instance_id.id).await {
+                    error!("Failed to restart {}: {}", instance_id, e);
+                }
+                continue;
+            }
+
+            let reason = if policy_allows {
+                "too many restarts"
+            } else {
+                "restart policy"
+            };
+            error!(
+                "Instance {} exited with code {}{}, not restarting ({})",
+                instance_id, code, cause, reason
+            );
+            {
+                let mut instances = self.instances.write().await;
+                if let Some(instance) = instances.get_mut(&instance_id) {
+                    instance.exit_code = Some(code);
+                    instance.health_status = HealthStatus::Failed;
+                    instance.ready = false;
+                }
+            }
+            self.log_buffer
+                .push_stderr(
+                    &instance_id.process,
+                    &instance_id.id,
+                    format!(
+                        "Process exited with code {}{}, not restarting ({})",
+                        code, cause, reason
+                    ),
+                )
+                .await;
+            kept_down.push(instance_id);
+        }
+        kept_down
+    }
+
     /// Run health checks on all instances and handle unhealthy ones
     pub async fn run_health_checks(&self) {
+        self.handle_exited_instances().await;
+
+        // Instances left stopped by their restart policy aren't checked, nor
+        // are ones still in their `health_initial_delay` or checked more
+        // recently than their `health_interval`
         let instance_ids: Vec<InstanceId> = {
+            let config = self.config();
             let instances = self.instances.read().await;
-            instances.keys().cloned().collect()
+            instances
+                .values()
+                .filter(|i| i.exit_code.is_none())
+                .filter(|i| {
+                    let (interval, initial_delay) = match config.get_service(&i.id.process) {
+                        Some(service) => (
+                            config.health_interval(service),
+                            service.health_initial_delay,
+                        ),
+                        None => (config.settings.health_check_interval, 0),
+                    };
+                    self.clock.since(i.started_at) >= Duration::from_secs(initial_delay)
+                        && i.last_health_check
+                            .is_none_or(|t| self.clock.since(t) >= Duration::from_secs(interval))
+                })
+                .map(|i| i.id.clone())
+                .collect()
         };
 
         for instance_id in instance_ids {
@@ -1123,7 +3168,7 @@ impl Hypervisor {
 
             match status {
                 HealthStatus::Unhealthy => {
-                    info!("Instance {} is unhealthy, restarting", instance_id);
+                    self.record_health_restart(&instance_id).await;
                     if let Err(e) = self.restart(&instance_id.process, &instance_id.id).await {
                         error!("Failed to restart {}: {}", instance_id, e);
                     }
@@ -1136,17 +3181,68 @@ impl Hypervisor {
         }
     }
 
+    /// Note a health-triggered restart in the instance's logs and metrics
+    async fn record_health_restart(&self, instance_id: &InstanceId) {
+        let failures = self
+            .instances
+            .read()
+            .await
+            .get(instance_id)
+            .map_or(0, |i| i.consecutive_failures);
+        // Without a health endpoint, a missing socket is unhealthy outright
+        let message = if failures > 0 {
+            format!(
+                "[tenement] restarting after {} consecutive failed health checks",
+                failures
+            )
+        } else {
+            "[tenement] restarting unhealthy instance".to_string()
+        };
+        info!("Instance {}: {}", instance_id, message);
+        self.log_buffer
+            .push_stderr(&instance_id.process, &instance_id.id, message)
+            .await;
+
+        let labels = HashMap::from([
+            ("process".to_string(), instance_id.process.clone()),
+            ("id".to_string(), instance_id.id.clone()),
+        ]);
+        self.metrics
+            .health_restarts
+            .with_labels(&labels)
+            .await
+            .inc();
+    }
+
+    /// One pass of the health monitor: restart exited and unhealthy
+    /// instances, reap idle ones, recycle ones past their `max_lifetime`,
+    /// check storage quotas, refill warm pools.
+    /// `start_monitor` runs this every `Config::monitor_interval`; tests can
+    /// call it directly after advancing a fake clock.
+    pub async fn monitor_tick(&self) {
+        self.run_health_checks().await;
+        self.reap_idle_instances().await;
+        self.recycle_expired_instances().await;
+        self.check_storage_quotas().await;
+        self.fill_warm_pools().await;
+    }
+
     /// Start the background health monitor loop
     pub fn start_monitor(self: Arc<Self>) {
-        let interval = Duration::from_secs(self.config.settings.health_check_interval);
         let hyp = self.clone();
         tokio::spawn(async move {
-            info!("Starting health monitor (interval: {:?})", interval);
+            info!(
+                "Starting health monitor (interval: {}s)",
+                hyp.config().monitor_interval()
+            );
             loop {
-                tokio::time::sleep(interval).await;
-                hyp.run_health_checks().await;
-                hyp.reap_idle_instances().await;
-                hyp.check_storage_quotas().await;
+                // Re-read each pass so reloaded intervals take effect
+                let interval = Duration::from_secs(hyp.config().monitor_interval());
+                hyp.clock.sleep(interval).await;
+                if hyp.is_shutting_down() {
+                    break;
+                }
+                hyp.monitor_tick().await;
             }
         });
     }
@@ -1157,7 +3253,7 @@ impl Hypervisor {
         let instance_id = InstanceId::new(process_name, id);
         let mut instances = self.instances.write().await;
         if let Some(instance) = instances.get_mut(&instance_id) {
-            instance.touch();
+            instance.touch_at(self.clock.now());
         }
     }
 
@@ -1167,14 +3263,133 @@ impl Hypervisor {
     /// Returns Err if the instance is not found.
     pub async fn set_weight(&self, process_name: &str, id: &str, weight: u8) -> Result<()> {
         let instance_id = InstanceId::new(process_name, id);
-        let mut instances = self.instances.write().await;
-        if let Some(instance) = instances.get_mut(&instance_id) {
-            instance.weight = weight.min(100); // Cap at 100
-            info!("Set weight for {} to {}", instance_id, instance.weight);
-            Ok(())
-        } else {
-            anyhow::bail!("Instance not found: {}", instance_id)
+        let weight = weight.min(100); // Cap at 100
+        {
+            let mut instances = self.instances.write().await;
+            match instances.get_mut(&instance_id) {
+                Some(instance) => instance.weight = weight,
+                None => anyhow::bail!("Instance not found: {}", instance_id),
+            }
+        }
+        info!("Set weight for {} to {}", instance_id, weight);
+        self.record_weight(&instance_id, weight).await;
+        Ok(())
+    }
+
+    /// Change a running instance's resource limits without restarting it.
+    /// Only the limits set in `limits` change, and 0 lifts a memory, IO or
+    /// process limit. Restarts and respawns go back to the service's
+    /// configured limits.
+    pub async fn update_limits(
+        &self,
+        process_name: &str,
+        id: &str,
+        mut limits: ResourceLimits,
+    ) -> Result<()> {
+        let instance_id = InstanceId::new(process_name, id);
+        let (runtime_type, pid, data_dir) = {
+            let instances = self.instances.read().await;
+            let instance = instances
+                .get(&instance_id)
+                .with_context(|| format!("Instance not found: {}", instance_id))?;
+            (
+                instance.runtime_type,
+                instance.handle.pid(),
+                instance.data_dir.clone(),
+            )
+        };
+        if !limits.has_limits() {
+            anyhow::bail!("No limits given for {}", instance_id);
+        }
+        if let Some(cpus) = &limits.cpuset {
+            crate::cgroup::validate_cpuset(cpus)?;
+        }
+        if self.custom_runtime.is_some() || !uses_cgroup_manager(runtime_type) {
+            anyhow::bail!(
+                "{} uses {} isolation, whose limits Tenement's cgroups don't manage",
+                instance_id,
+                runtime_type
+            );
+        }
+        let pid = pid.with_context(|| format!("{} has no process", instance_id))?;
+        if limits.has_io_limits() {
+            limits.io_device = Some(crate::cgroup::block_device(&data_dir)?);
+        }
+        self.cgroup_manager
+            .update_limits(&instance_id.to_string(), pid, &limits)?;
+        info!("Updated resource limits for {}", instance_id);
+        Ok(())
+    }
+
+    /// Weight a (re)spawned instance starts with: the last one set, or 100
+    async fn stored_weight(&self, instance_id: &InstanceId) -> u8 {
+        self.weights
+            .read()
+            .await
+            .get(instance_id)
+            .copied()
+            .unwrap_or(100)
+    }
+
+    /// Remember an instance's weight for respawns and, with a state store,
+    /// daemon restarts. The default (100) is forgotten rather than stored.
+    async fn record_weight(&self, instance_id: &InstanceId, weight: u8) {
+        {
+            let mut weights = self.weights.write().await;
+            if weight == 100 {
+                weights.remove(instance_id);
+            } else {
+                weights.insert(instance_id.clone(), weight);
+            }
+        }
+        if let Some(ref store) = self.state_store {
+            let key = instance_id.to_string();
+            let result = if weight == 100 {
+                store.remove_weight(&key).await
+            } else {
+                store.save_weight(&key, weight).await
+            };
+            if let Err(e) = result {
+                error!("Failed to persist weight for {}: {}", instance_id, e);
+            }
+        }
+    }
+
+    /// Load weights and deployed versions from the state store.
+    /// Called on startup, before instances are re-adopted or spawned.
+    pub async fn load_routing_state(&self) -> Result<()> {
+        let store = match &self.state_store {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+        let weights: HashMap<InstanceId, u8> = store
+            .weights()
+            .await?
+            .into_iter()
+            .filter_map(|(id, weight)| Some((InstanceId::parse(&id)?, weight)))
+            .collect();
+        {
+            let mut instances = self.instances.write().await;
+            for (id, weight) in &weights {
+                if let Some(instance) = instances.get_mut(id) {
+                    instance.weight = *weight;
+                }
+            }
         }
+        *self.weights.write().await = weights;
+
+        let mut versions = self.versions.write().await;
+        for (service, current, previous) in store.versions().await? {
+            versions.insert(
+                service,
+                ServiceVersions {
+                    current,
+                    previous,
+                    instances: Vec::new(),
+                },
+            );
+        }
+        Ok(())
     }
 
     /// List all running instances for a specific process.
@@ -1183,42 +3398,76 @@ impl Hypervisor {
         let instances = self.instances.read().await;
         instances
             .values()
-            .filter(|i| i.id.process == process_name)
-            .map(|i| i.info())
+            .filter(|i| i.id.process == process_name && !i.id.is_warm())
+            .map(|i| i.info_at(self.clock.now()))
             .collect()
     }
 
     /// Select an instance for a process using weighted random selection.
+    /// Draining, not-yet-ready, unhealthy and failed instances are never selected,
+    /// nor are ones not health checked yet unless `settings.route_unknown_health`;
+    /// degraded instances are picked with a reduced weight (see `Instance::effective_weight`).
     /// Returns None if no instances are available or all have weight 0.
     pub async fn select_weighted(&self, process_name: &str) -> Option<InstanceInfo> {
-        use rand::Rng;
+        self.select_weighted_filtered(process_name, None).await
+    }
 
-        let instances = self.instances.read().await;
-        let candidates: Vec<_> = instances
-            .values()
-            .filter(|i| i.id.process == process_name && i.weight > 0)
+    /// Like `select_weighted`, but only among the given instance IDs
+    /// (e.g. the instances of an experiment group).
+    pub async fn select_weighted_among(
+        &self,
+        process_name: &str,
+        ids: &[String],
+    ) -> Option<InstanceInfo> {
+        self.select_weighted_filtered(process_name, Some(ids)).await
+    }
+
+    async fn select_weighted_filtered(
+        &self,
+        process_name: &str,
+        ids: Option<&[String]>,
+    ) -> Option<InstanceInfo> {
+        use rand::Rng;
+
+        let settings = &self.config().settings;
+        let degraded_percent = settings.degraded_weight_percent;
+        let route_unknown = settings.route_unknown_health;
+        let instances = self.instances.read().await;
+        let candidates: Vec<_> = instances
+            .values()
+            .filter(|i| i.id.process == process_name && i.ready && !i.draining)
+            .filter(|i| i.health_status.is_routable(route_unknown))
+            .filter(|i| !i.id.is_warm())
+            .filter(|i| ids.is_none_or(|ids| ids.contains(&i.id.id)))
+            .map(|i| (i, i.effective_weight(degraded_percent)))
+            .filter(|(_, weight)| *weight > 0)
             .collect();
 
         if candidates.is_empty() {
             return None;
         }
+        // Fixed order, so a seeded RNG picks the same instances every run
+        let mut candidates = candidates;
+        candidates.sort_by(|a, b| a.0.id.id.cmp(&b.0.id.id));
 
         // Calculate total weight
-        let total_weight: u32 = candidates.iter().map(|i| i.weight as u32).sum();
+        let total_weight: u32 = candidates.iter().map(|(_, w)| *w as u32).sum();
         if total_weight == 0 {
             return None;
         }
 
         // Pick a random point in the weight space
-        let mut rng = rand::thread_rng();
-        let point = rng.gen_range(0..total_weight);
+        let point = match &self.routing_rng {
+            Some(rng) => rng.lock().unwrap().gen_range(0..total_weight),
+            None => rand::thread_rng().gen_range(0..total_weight),
+        };
 
         // Find the instance at that point
         let mut cumulative = 0u32;
-        for instance in candidates {
-            cumulative += instance.weight as u32;
+        for (instance, weight) in candidates {
+            cumulative += weight as u32;
             if point < cumulative {
-                return Some(instance.info());
+                return Some(instance.info_at(self.clock.now()));
             }
         }
 
@@ -1228,12 +3477,13 @@ impl Hypervisor {
 
     /// Stop idle instances that have exceeded their idle_timeout.
     /// Called periodically by the health monitor.
-    async fn reap_idle_instances(&self) {
+    pub async fn reap_idle_instances(&self) {
+        let now = self.clock.now();
         let idle_instances: Vec<InstanceId> = {
             let instances = self.instances.read().await;
             instances
                 .values()
-                .filter(|i| i.is_idle())
+                .filter(|i| i.is_idle_at(now) && !i.id.is_warm())
                 .map(|i| i.id.clone())
                 .collect()
         };
@@ -1255,7 +3505,7 @@ impl Hypervisor {
                 let instances = self.instances.read().await;
                 instances
                     .get(&instance_id)
-                    .map(|i| i.last_activity.elapsed().as_secs())
+                    .map(|i| self.clock.since(i.last_activity).as_secs())
                     .unwrap_or(0)
             };
 
@@ -1264,12 +3514,118 @@ impl Hypervisor {
                 instance_id, idle_secs
             );
 
-            if let Err(e) = self.stop(&instance_id.process, &instance_id.id).await {
+            if let Err(e) = self
+                .stop_instance(&instance_id.process, &instance_id.id)
+                .await
+            {
                 error!("Failed to stop idle instance {}: {}", instance_id, e);
             }
         }
     }
 
+    /// Why a restart, deploy or recycle of `process` (one instance, or the
+    /// whole service with `None`) isn't allowed now under the service's
+    /// maintenance windows. `None` if it's allowed or no windows are set.
+    pub fn maintenance_blocked(&self, process: &str, id: Option<&str>) -> Option<String> {
+        self.maintenance_blocked_at(process, id, chrono::Utc::now())
+    }
+
+    /// [`Self::maintenance_blocked`] at a given time
+    pub fn maintenance_blocked_at(
+        &self,
+        process: &str,
+        id: Option<&str>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Option<String> {
+        let config = self.config();
+        let maintenance = config.get_service(process)?.maintenance.as_ref()?;
+        let reason = maintenance.blocked(id, now)?;
+        Some(match id {
+            Some(id) => format!("{}:{} is {}", process, id, reason),
+            None => format!("{} is {}", process, reason),
+        })
+    }
+
+    /// Drain and restart instances that have outlived their service's
+    /// `max_lifetime`, or that a config reload flagged as stale. Each
+    /// instance's deadline is pushed back by a stable offset of up to a tenth
+    /// of the lifetime, and at most one instance per service is recycled per
+    /// pass, so replicas started together don't all go down at once. Stale
+    /// instances go first. Instances whose maintenance windows don't allow a
+    /// restart right now wait for one. Returns the recycled instance IDs.
+    pub async fn recycle_expired_instances(&self) -> Vec<InstanceId> {
+        let deploying = self.deploying.read().await.clone();
+        // Lifetime is None for instances restarted for a config reload
+        let expired: Vec<(InstanceId, Option<u64>)> = {
+            let instances = self.instances.read().await;
+            let mut overdue: Vec<(InstanceId, Option<u64>, Duration)> = instances
+                .values()
+                .filter(|i| i.exit_code.is_none() && !i.draining && !i.id.is_warm())
+                .filter(|i| !deploying.contains(&i.id.process))
+                .filter(|i| {
+                    self.maintenance_blocked(&i.id.process, Some(&i.id.id))
+                        .is_none()
+                })
+                .filter_map(|i| {
+                    if i.stale_config {
+                        return Some((i.id.clone(), None, Duration::MAX));
+                    }
+                    let lifetime = self
+                        .config()
+                        .get_service(&i.id.process)?
+                        .max_lifetime
+                        .filter(|l| *l > 0)?;
+                    let deadline = Duration::from_secs(lifetime + lifetime_offset(&i.id, lifetime));
+                    let over = self.clock.since(i.started_at).checked_sub(deadline)?;
+                    Some((i.id.clone(), Some(lifetime), over))
+                })
+                .collect();
+            // Most overdue first, then one per service
+            overdue.sort_by(|a, b| b.2.cmp(&a.2).then_with(|| a.0.id.cmp(&b.0.id)));
+            let mut seen = std::collections::HashSet::new();
+            overdue
+                .into_iter()
+                .filter(|(id, _, _)| seen.insert(id.process.clone()))
+                .map(|(id, lifetime, _)| (id, lifetime))
+                .collect()
+        };
+
+        let mut recycled = Vec::new();
+        for (instance_id, lifetime) in expired {
+            {
+                let mut instances = self.instances.write().await;
+                match instances.get_mut(&instance_id) {
+                    Some(instance) => instance.draining = true,
+                    None => continue,
+                }
+            }
+            let message = match lifetime {
+                Some(lifetime) => format!(
+                    "[tenement] recycling instance after max_lifetime ({}s)",
+                    lifetime
+                ),
+                None => "[tenement] restarting instance to apply the reloaded config".to_string(),
+            };
+            info!("Instance {}: {}", instance_id, message);
+            self.log_buffer
+                .push_stderr(&instance_id.process, &instance_id.id, message)
+                .await;
+
+            self.wait_for_connections(&instance_id, STOP_DRAIN_TIMEOUT)
+                .await;
+            if let Err(e) = self.stop_now(&instance_id).await {
+                error!("Failed to stop {} for recycling: {}", instance_id, e);
+                continue;
+            }
+            if let Err(e) = self.spawn(&instance_id.process, &instance_id.id).await {
+                error!("Failed to respawn recycled instance {}: {}", instance_id, e);
+                continue;
+            }
+            recycled.push(instance_id);
+        }
+        recycled
+    }
+
     /// Check storage quotas for all instances and update metrics.
     /// Logs warnings at 80% and errors at 100% usage.
     async fn check_storage_quotas(&self) {
@@ -1351,32 +3707,35 @@ impl Hypervisor {
         }
     }
 
-    /// Recover orphaned instances from a previous crash.
-    /// Checks persisted state, kills any still-running orphans, and cleans up.
-    /// Called on startup before spawning configured instances.
-    pub async fn recover_orphans(&self) {
+    /// Recover instances left running by a previous daemon.
+    ///
+    /// Still-running process-based instances whose socket/port is reachable
+    /// are re-adopted: registered again under their old PID, socket and port
+    /// instead of being respawned. If their service was renamed or removed
+    /// from config they are kept as orphaned (see `adopt_config`). Anything
+    /// else still alive is killed. Called on startup before spawning configured
+    /// instances. Returns the number of re-adopted instances.
+    pub async fn recover_orphans(&self) -> usize {
         let store = match &self.state_store {
             Some(s) => s,
-            None => return,
+            None => return 0,
         };
 
         let states = match store.list().await {
             Ok(s) => s,
             Err(e) => {
                 error!("Failed to read instance state for recovery: {}", e);
-                return;
+                return 0;
             }
         };
 
         if states.is_empty() {
-            return;
+            return 0;
         }
 
-        info!(
-            "Found {} orphaned instance(s) from previous run",
-            states.len()
-        );
+        info!("Found {} instance(s) from previous run", states.len());
 
+        let mut adopted = 0;
         for state in &states {
             // Check if process is still alive
             #[cfg(unix)]
@@ -1384,32 +3743,443 @@ impl Hypervisor {
             #[cfg(not(unix))]
             let alive = false;
 
-            if alive {
+            if !alive {
                 info!(
-                    "Killing orphaned process {} (pid {})",
+                    "Orphaned process {} (pid {}) already exited",
                     state.instance_id, state.pid
                 );
-                #[cfg(unix)]
-                unsafe {
-                    libc::kill(state.pid as i32, libc::SIGKILL);
+                if let Err(e) = store.remove(&state.instance_id).await {
+                    error!(
+                        "Failed to remove instance state for {}: {}",
+                        state.instance_id, e
+                    );
                 }
-            } else {
-                info!(
-                    "Orphaned process {} (pid {}) already exited",
-                    state.instance_id, state.pid
+                continue;
+            }
+
+            if self.adopt(state).await {
+                adopted += 1;
+                continue;
+            }
+
+            info!(
+                "Killing orphaned process {} (pid {})",
+                state.instance_id, state.pid
+            );
+            #[cfg(unix)]
+            unsafe {
+                libc::kill(-(state.pid as i32), libc::SIGKILL);
+                libc::kill(state.pid as i32, libc::SIGKILL);
+            }
+            if let Err(e) = store.remove(&state.instance_id).await {
+                error!(
+                    "Failed to remove instance state for {}: {}",
+                    state.instance_id, e
+                );
+            }
+        }
+
+        info!("Orphan recovery complete ({} re-adopted)", adopted);
+        adopted
+    }
+
+    /// Re-register a still-running instance from persisted state.
+    /// Returns false if it can't be adopted and should be killed instead.
+    async fn adopt(&self, state: &crate::store::InstanceState) -> bool {
+        // Unclaimed warm instances are cheap to replace; start a fresh pool
+        if state.id.starts_with(crate::instance::WARM_ID_PREFIX) {
+            return false;
+        }
+        let runtime: RuntimeType = match state.runtime.parse() {
+            Ok(r) => r,
+            // Rows written before the runtime was recorded
+            Err(_) => return false,
+        };
+        // Only runtimes we supervise by PID can be re-adopted
+        if !matches!(
+            runtime,
+            RuntimeType::Process
+                | RuntimeType::Namespace
+                | RuntimeType::Litebox
+                | RuntimeType::Wasm
+                | RuntimeType::Systemd
+        ) {
+            return false;
+        }
+
+        // A renamed or removed service: keep the instance (and its data)
+        // manageable until it's mapped onto a configured service
+        let config = self.config();
+        let process_config = config.get_service(&state.process_name);
+
+        // Guard against PID reuse: the instance must still be serving
+        let socket = PathBuf::from(&state.socket);
+        let reachable = match state.port {
+            Some(port) => tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok(),
+            None => socket.exists(),
+        };
+        if !reachable {
+            info!(
+                "Not re-adopting {}: {} is not reachable",
+                state.instance_id,
+                state
+                    .port
+                    .map(|p| format!("port {}", p))
+                    .unwrap_or_else(|| format!("socket {:?}", socket))
+            );
+            return false;
+        }
+
+        if let Some(port) = state.port {
+            if !self.port_allocator.reserve(port).await {
+                warn!(
+                    "Not re-adopting {}: port {} already allocated",
+                    state.instance_id, port
                 );
+                return false;
+            }
+        }
+
+        // Rows from before listeners were recorded only have the main one
+        let main = Listener {
+            name: crate::config::MAIN_LISTENER.to_string(),
+            addr: match state.port {
+                Some(port) => ListenAddr::Tcp(format!("127.0.0.1:{}", port), port),
+                None => ListenAddr::Socket(socket.clone()),
+            },
+        };
+        let mut listeners = vec![main.clone()];
+        let recorded: Vec<Listener> = serde_json::from_str(&state.listeners).unwrap_or_default();
+        for listener in recorded.into_iter().filter(|l| *l != main) {
+            if let Some(port) = listener.addr.port() {
+                if !self.port_allocator.reserve(port).await {
+                    warn!(
+                        "Re-adopting {} without listener '{}': port {} already allocated",
+                        state.instance_id, listener.name, port
+                    );
+                    continue;
+                }
+            }
+            listeners.push(listener);
+        }
+
+        // Translate the persisted wall-clock start time to an Instant so
+        // uptime keeps counting from the original spawn.
+        let now = self.clock.now();
+        let started_at = chrono::DateTime::parse_from_rfc3339(&state.started_at)
+            .ok()
+            .and_then(|t| {
+                (chrono::Utc::now() - t.with_timezone(&chrono::Utc))
+                    .to_std()
+                    .ok()
+            })
+            .and_then(|age| now.checked_sub(age))
+            .unwrap_or(now);
+
+        let instance_id = InstanceId::new(&state.process_name, &state.id);
+        let instance = Instance {
+            id: instance_id.clone(),
+            handle: RuntimeHandle::Adopted {
+                pid: state.pid,
+                runtime,
+                socket: socket.clone(),
+            },
+            runtime_type: runtime,
+            socket,
+            port: state.port,
+            listeners,
+            started_at,
+            restarts: 0,
+            consecutive_failures: 0,
+            last_health_check: None,
+            health_status: HealthStatus::Unknown,
+            restart_times: Vec::new(),
+            last_activity: now,
+            idle_timeout: process_config.and_then(|c| c.idle_timeout),
+            storage_quota_mb: process_config.and_then(|c| c.storage_quota_mb),
+            storage_persist: process_config.is_none_or(|c| c.storage_persist),
+            storage_used_bytes: 0,
+            data_dir: self
+                .config()
+                .settings
+                .data_dir
+                .join(&state.process_name)
+                .join(&state.id),
+            weight: self.stored_weight(&instance_id).await,
+            draining: false,
+            // It was serving before the restart (checked above)
+            ready: true,
+            exit_code: None,
+            labels: process_config.map(|c| c.labels.clone()).unwrap_or_default(),
+            orphaned: process_config.is_none(),
+            // Kills before the daemon restarted were reported (or missed) then
+            oom_kills: self
+                .cgroup_manager
+                .oom_kills(&instance_id.to_string())
+                .unwrap_or(0),
+            stale_config: false,
+            spawned_config: serde_json::from_str(&state.config).ok(),
+        };
+
+        self.instances
+            .write()
+            .await
+            .insert(instance_id.clone(), instance);
+        self.metrics.instances_up.inc();
+        self.spawn_exit_monitor(instance_id.clone(), state.pid);
+
+        if process_config.is_none() {
+            warn!(
+                "Re-adopted instance {} (pid {}) as orphaned: service '{}' is no longer configured; \
+                map it with `ten adopt-config {} <service>` or stop it",
+                instance_id, state.pid, state.process_name, state.process_name
+            );
+        } else {
+            info!(
+                "Re-adopted instance {} (pid {}, output not captured)",
+                instance_id, state.pid
+            );
+        }
+        self.log_buffer
+            .push_stderr(
+                &state.process_name,
+                &state.id,
+                format!(
+                    "Re-adopted after daemon restart (pid {}); earlier output is not captured",
+                    state.pid
+                ),
+            )
+            .await;
+        true
+    }
+
+    /// Map orphaned instances of `from` (a service renamed or removed from
+    /// config) onto the configured service `to`. Each instance keeps running
+    /// and is re-registered as `to:<id>` with `to`'s idle, storage and label
+    /// settings, and its data dir is moved to `to`'s. The old data dir path
+    /// is left as a symlink so paths the running process holds still resolve.
+    ///
+    /// Fails if `to:<id>` is already running or its data dir exists, unless
+    /// `replace` is set: then that instance is stopped and its data dir moved
+    /// aside to `<id>.replaced-<unix time>`. Returns the remapped instances.
+    pub async fn adopt_config(
+        &self,
+        from: &str,
+        to: &str,
+        replace: bool,
+    ) -> Result<Vec<InstanceId>> {
+        let config = self.config();
+        let process_config = config
+            .get_service(to)
+            .with_context(|| format!("Unknown process: {}", to))?;
+        let orphans: Vec<InstanceId> = {
+            let instances = self.instances.read().await;
+            let mut orphans: Vec<InstanceId> = instances
+                .values()
+                .filter(|i| i.orphaned && i.id.process == from)
+                .map(|i| i.id.clone())
+                .collect();
+            orphans.sort_by(|a, b| a.id.cmp(&b.id));
+            orphans
+        };
+        if orphans.is_empty() {
+            anyhow::bail!("No orphaned instances of '{}'", from);
+        }
+
+        let data_root = &self.config().settings.data_dir;
+        if !replace {
+            for old in &orphans {
+                let target = InstanceId::new(to, &old.id);
+                if self.instances.read().await.contains_key(&target) {
+                    anyhow::bail!("{} is already running; stop it first or replace it", target);
+                }
+                let target_dir = data_root.join(to).join(&old.id);
+                if target_dir.exists() {
+                    anyhow::bail!(
+                        "Data dir {} already exists; move it away first or replace it",
+                        target_dir.display()
+                    );
+                }
+            }
+        }
+
+        let mut remapped = Vec::new();
+        for old in orphans {
+            let target = InstanceId::new(to, &old.id);
+            if replace && self.instances.read().await.contains_key(&target) {
+                self.stop(to, &old.id).await?;
+            }
+            let target_dir = data_root.join(to).join(&old.id);
+            if target_dir.exists() {
+                let aside = target_dir.with_file_name(format!(
+                    "{}.replaced-{}",
+                    old.id,
+                    chrono::Utc::now().timestamp()
+                ));
+                std::fs::rename(&target_dir, &aside)
+                    .with_context(|| format!("Failed to move {} aside", target_dir.display()))?;
+                info!("Moved {} to {}", target_dir.display(), aside.display());
             }
 
-            // Note: port allocator is in-memory and starts fresh on each run,
-            // so no port release needed during recovery.
+            let weight = {
+                let mut instances = self.instances.write().await;
+                let Some(mut instance) = instances.remove(&old) else {
+                    // Stopped while we were working
+                    continue;
+                };
+                if instance.data_dir.exists() {
+                    if let Err(e) = move_data_dir(&instance.data_dir, &target_dir) {
+                        instances.insert(old.clone(), instance);
+                        return Err(e);
+                    }
+                }
+                instance.id = target.clone();
+                instance.orphaned = false;
+                instance.idle_timeout = process_config.idle_timeout;
+                instance.storage_quota_mb = process_config.storage_quota_mb;
+                instance.storage_persist = process_config.storage_persist;
+                instance.labels = process_config.labels.clone();
+                instance.data_dir = target_dir;
+                let weight = instance.weight;
+                instances.insert(target.clone(), instance);
+                weight
+            };
+
+            if let Some(ref store) = self.state_store {
+                if let Err(e) = store.remove(&old.to_string()).await {
+                    error!("Failed to remove instance state for {}: {}", old, e);
+                }
+            }
+            self.persist_state(&target).await;
+            self.record_weight(&old, 100).await;
+            self.record_weight(&target, weight).await;
+            self.unregister_dns(from, &old.id);
+            if let Some(ref dns) = self.dns {
+                dns.register(to, &old.id);
+            }
+            info!("Remapped orphaned instance {} to {}", old, target);
+            self.log_buffer
+                .push_stderr(
+                    to,
+                    &old.id,
+                    format!("Remapped from {} after a config rename", old),
+                )
+                .await;
+            remapped.push(target);
+        }
+        Ok(remapped)
+    }
+
+    /// Top up every service's warm pool to its configured `warm_pool` size.
+    /// Called on startup and by the health monitor (which also refills pools
+    /// after instances are claimed). Returns the number of instances spawned.
+    pub async fn fill_warm_pools(&self) -> usize {
+        let config = self.config();
+        let mut services: Vec<(&String, usize)> = config
+            .service
+            .iter()
+            .filter(|(_, c)| c.warm_pool > 0)
+            .map(|(name, c)| (name, c.warm_pool))
+            .collect();
+        services.sort();
+
+        let mut spawned = 0;
+        for (service, size) in services {
+            let current = self.warm_count(service).await;
+            for _ in current..size {
+                let warm_id = format!(
+                    "{}{:08x}",
+                    crate::instance::WARM_ID_PREFIX,
+                    rand::random::<u32>()
+                );
+                match self.spawn(service, &warm_id).await {
+                    Ok(_) => spawned += 1,
+                    Err(e) => {
+                        warn!("Failed to spawn warm instance for {}: {}", service, e);
+                        break;
+                    }
+                }
+            }
         }
+        spawned
+    }
+
+    /// Number of unclaimed warm instances for a service
+    pub async fn warm_count(&self, process_name: &str) -> usize {
+        let instances = self.instances.read().await;
+        instances
+            .keys()
+            .filter(|id| id.process == process_name && id.is_warm())
+            .count()
+    }
 
-        // Clear all persisted state
-        if let Err(e) = store.clear_all().await {
-            error!("Failed to clear instance state after recovery: {}", e);
+    /// Hand a ready warm instance of `process_name` over to `id`, re-keying
+    /// it in place along with its cgroup and logs. Returns its socket, or
+    /// None if no warm instance is ready (or `id` is already running). An
+    /// `id` with its own spawn options (env, labels, command) is never
+    /// claimed, since a warm instance was started without them.
+    async fn claim_warm(&self, process_name: &str, id: &str) -> Option<PathBuf> {
+        let instance_id = InstanceId::new(process_name, id);
+        if self.spawn_options.read().await.contains_key(&instance_id) {
+            return None;
         }
+        let (warm_id, pid, socket) = {
+            let mut instances = self.instances.write().await;
+            if instances.contains_key(&instance_id) {
+                return None;
+            }
+            let warm_id = instances
+                .values()
+                .filter(|i| i.id.process == process_name && i.id.is_warm() && i.ready)
+                .map(|i| i.id.clone())
+                .min_by(|a, b| a.id.cmp(&b.id))?;
+            let mut instance = instances.remove(&warm_id)?;
+
+            // The process never learned its data dir path (no {id} allowed),
+            // so just move the directory under the claimed ID. Reuse an
+            // existing persisted directory for that ID if there is one.
+            let target = self.config().settings.data_dir.join(process_name).join(id);
+            if target.exists() {
+                std::fs::remove_dir_all(&instance.data_dir).ok();
+            } else if let Err(e) = std::fs::rename(&instance.data_dir, &target) {
+                warn!(
+                    "Failed to move warm data dir {:?} to {:?}: {}",
+                    instance.data_dir, target, e
+                );
+                std::fs::create_dir_all(&target).ok();
+            }
+            instance.data_dir = target;
+            if let Err(e) = self
+                .cgroup_manager
+                .rename_cgroup(&warm_id.to_string(), &instance_id.to_string())
+            {
+                warn!("Instance {}: {:#}", instance_id, e);
+            }
+            instance.id = instance_id.clone();
+            instance.touch_at(self.clock.now());
+            let pid = instance.handle.pid();
+            let socket = instance.socket.clone();
+            instances.insert(instance_id.clone(), instance);
+            (warm_id, pid, socket)
+        };
 
-        info!("Orphan recovery complete");
+        info!("Claimed warm instance {} as {}", warm_id, instance_id);
+        self.log_buffer.relabel(process_name, &warm_id.id, id).await;
+        if let Some(ref store) = self.state_store {
+            if let Err(e) = store.remove(&warm_id.to_string()).await {
+                error!("Failed to remove instance state for {}: {}", warm_id, e);
+            }
+        }
+        self.persist_state(&instance_id).await;
+        if let Some(ref dns) = self.dns {
+            dns.register(process_name, id);
+        }
+        if let Some(pid) = pid {
+            self.spawn_exit_monitor(instance_id, pid);
+        }
+        Some(socket)
     }
 
     /// Spawn all instances configured in [instances] section.
@@ -1417,7 +4187,7 @@ impl Hypervisor {
     /// Continues spawning even if some fail, logs errors for failures.
     /// Returns the number of successfully spawned instances.
     pub async fn spawn_configured_instances(&self) -> (usize, usize) {
-        let instances_to_spawn = self.config.get_instances_to_spawn();
+        let instances_to_spawn = self.config().get_instances_to_spawn();
 
         if instances_to_spawn.is_empty() {
             return (0, 0);
@@ -1464,54 +4234,91 @@ impl Hypervisor {
     /// Spawn instance if not running, and wait for it to be ready.
     /// Returns the socket path. Use this for wake-on-request.
     /// Uses the process's configured startup_timeout (default: 10s).
-    pub async fn spawn_and_wait(&self, process_name: &str, id: &str) -> Result<PathBuf> {
+    ///
+    /// Concurrent calls for the same instance share one wake, which runs on
+    /// its own task so it finishes even if the request that started it goes
+    /// away. With `wake_queue_timeout`, callers give up waiting after that long.
+    pub async fn spawn_and_wait(self: &Arc<Self>, process_name: &str, id: &str) -> Result<PathBuf> {
         let instance_id = InstanceId::new(process_name, id);
 
-        // Wake-once pattern: if another request is already waking this instance,
-        // wait for it to finish instead of spawning a duplicate.
-        // Wake-once: if another request is already waking this instance, wait for it.
-        let maybe_notify = {
-            let waking = self.waking.read().await;
-            waking.get(&instance_id).cloned()
-        };
-        if let Some(notify) = maybe_notify {
-            // Register for notification BEFORE checking if spawn is done.
-            // This avoids missing the signal if it fires between our check and await.
-            let notified = notify.notified();
-            info!("Instance {} is already being woken, waiting", instance_id);
-            // Check if instance became available while we were setting up
-            if self.is_running(process_name, id).await {
-                self.touch_activity(process_name, id).await;
-                let info = self
-                    .get(process_name, id)
-                    .await
-                    .ok_or_else(|| anyhow::anyhow!("Instance {} disappeared", instance_id))?;
-                return Ok(info.socket);
+        let mut outcome = {
+            let mut waking = self.waking.write().await;
+            match waking.get(&instance_id) {
+                Some(outcome) => {
+                    info!("Instance {} is already being woken, waiting", instance_id);
+                    outcome.clone()
+                }
+                None => {
+                    let (tx, rx) = tokio::sync::watch::channel(None);
+                    waking.insert(instance_id.clone(), rx.clone());
+                    let hypervisor = self.clone();
+                    let (process_name, id) = (process_name.to_string(), id.to_string());
+                    tokio::spawn(async move {
+                        let result = hypervisor
+                            .wake(&process_name, &id)
+                            .await
+                            .map_err(|e| format!("{:#}", e));
+                        let mut waking = hypervisor.waking.write().await;
+                        waking.remove(&InstanceId::new(&process_name, &id));
+                        let _ = tx.send(Some(result));
+                    });
+                    rx
+                }
             }
-            notified.await;
-            if let Some(info) = self.get(process_name, id).await {
+        };
+
+        let queue_timeout = self
+            .config()
+            .get_service(process_name)
+            .and_then(|p| p.wake_queue_timeout)
+            .filter(|secs| *secs > 0);
+        let done = async {
+            outcome
+                .wait_for(Option::is_some)
+                .await
+                .ok()
+                .and_then(|outcome| outcome.clone())
+        };
+        let result = match queue_timeout {
+            Some(secs) => tokio::time::timeout(Duration::from_secs(secs), done)
+                .await
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "Gave up after {}s waiting for instance {} to wake",
+                        secs,
+                        instance_id
+                    )
+                })?,
+            None => done.await,
+        };
+        match result {
+            Some(Ok(socket)) => {
                 self.touch_activity(process_name, id).await;
-                return Ok(info.socket);
+                Ok(socket)
             }
-            anyhow::bail!("Instance {} failed to wake", instance_id);
+            Some(Err(e)) => Err(anyhow::anyhow!(e)),
+            None => anyhow::bail!("Instance {} failed to wake", instance_id),
         }
+    }
 
-        // We're the first to wake this instance. Register a Notify.
-        let notify = Arc::new(tokio::sync::Notify::new());
-        {
-            let mut waking = self.waking.write().await;
-            waking.insert(instance_id.clone(), notify.clone());
-        }
+    /// The wake behind `spawn_and_wait`: claim a warm instance or cold-start
+    /// one, then wait for it to accept connections
+    async fn wake(&self, process_name: &str, id: &str) -> Result<PathBuf> {
+        let instance_id = InstanceId::new(process_name, id);
 
         // Get the startup timeout from process config
         let timeout_secs = self
-            .config
+            .config()
             .get_service(process_name)
             .map(|p| p.startup_timeout)
             .unwrap_or(10);
 
+        // Claim a warm instance if one is ready; otherwise cold-start.
         // spawn_if_not_running already waits for TCP/socket readiness via spawn()
-        let socket = self.spawn_if_not_running(process_name, id).await?;
+        let socket = match self.claim_warm(process_name, id).await {
+            Some(socket) => socket,
+            None => self.spawn_if_not_running(process_name, id).await?,
+        };
 
         // Get port info to determine readiness check method
         let port = self.get(process_name, id).await.and_then(|info| info.port);
@@ -1544,13 +4351,6 @@ impl Hypervisor {
             tokio::time::sleep(Duration::from_millis(100)).await;
         }
 
-        // Notify all waiters and remove the Notify
-        notify.notify_waiters();
-        {
-            let mut waking = self.waking.write().await;
-            waking.remove(&instance_id);
-        }
-
         if ready {
             Ok(socket)
         } else {
@@ -1648,6 +4448,9 @@ impl Hypervisor {
         if let Some(to_instance) = instances.get_mut(&to_id) {
             to_instance.weight = 100;
         }
+        drop(instances);
+        self.record_weight(&from_id, 0).await;
+        self.record_weight(&to_id, 100).await;
 
         info!(
             "Traffic swap complete: {} weight=0, {} weight=100",
@@ -1656,24 +4459,277 @@ impl Hypervisor {
 
         Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::config::ProcessConfig;
-    use std::collections::HashMap;
-    use std::path::Path;
-    use tempfile::TempDir;
+    /// Blue/green deploy of a new version of a service.
+    ///
+    /// - Spawns a "green" set of instances labeled `version=<version>`, as many
+    ///   as the running "blue" set (instances of the service labeled with another
+    ///   version), at least one. A single instance gets `version` as its ID,
+    ///   several get `<version>-1`, `<version>-2`, ...
+    /// - `command`, if given, replaces the service's command for the green set
+    /// - Green instances get weight 0 until every one of them is ready; then
+    ///   all weights are switched under one lock (green 100, blue 0)
+    /// - The blue set is drained and stopped, and the version becomes current
+    ///
+    /// If any green instance fails to spawn or become ready, the green set is
+    /// stopped and the blue set keeps serving untouched.
+    pub async fn deploy(
+        &self,
+        process_name: &str,
+        version: &str,
+        command: Option<String>,
+    ) -> Result<ServiceVersions> {
+        if !self.has_process(process_name) {
+            anyhow::bail!("Unknown process: {}", process_name);
+        }
+        crate::paths::validate_instance_id(version)?;
+        if !self
+            .deploying
+            .write()
+            .await
+            .insert(process_name.to_string())
+        {
+            anyhow::bail!("A deploy of {} is already in progress", process_name);
+        }
+        let result = self.deploy_inner(process_name, version, command).await;
+        self.deploying.write().await.remove(process_name);
+        result
+    }
 
-    // Helper to create a test config with a simple echo server process
-    fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> Config {
-        let test_id = format!("{}-{}", std::process::id(), rand::random::<u32>());
-        let mut config = Config::default();
-        config.settings.data_dir = std::env::temp_dir().join(format!("tenement-test-{}", test_id));
+    async fn deploy_inner(
+        &self,
+        process_name: &str,
+        version: &str,
+        command: Option<String>,
+    ) -> Result<ServiceVersions> {
+        let blue: Vec<InstanceInfo> = self
+            .list_by_process(process_name)
+            .await
+            .into_iter()
+            .filter(|i| i.labels.contains_key(VERSION_LABEL))
+            .collect();
+        if blue
+            .iter()
+            .any(|i| i.labels.get(VERSION_LABEL).map(String::as_str) == Some(version))
+        {
+            anyhow::bail!("{} is already running version {}", process_name, version);
+        }
+        let previous = self
+            .versions
+            .read()
+            .await
+            .get(process_name)
+            .and_then(|v| v.current.clone())
+            .or_else(|| {
+                blue.iter()
+                    .find_map(|i| i.labels.get(VERSION_LABEL).cloned())
+            });
 
-        let process = ProcessConfig {
-            command: command.to_string(),
+        let replicas = blue.len().max(1);
+        let green: Vec<String> = if replicas == 1 {
+            vec![version.to_string()]
+        } else {
+            (1..=replicas)
+                .map(|n| format!("{}-{}", version, n))
+                .collect()
+        };
+        info!(
+            "Deploying {} version {} ({} instance(s))",
+            process_name, version, replicas
+        );
+
+        let opts = SpawnOptions {
+            env: HashMap::from([("TENEMENT_VERSION".to_string(), version.to_string())]),
+            labels: [(VERSION_LABEL.to_string(), version.to_string())].into(),
+            command,
+            weight: Some(0),
+        };
+        // A rejected version mustn't stick to these IDs for later wakes
+        let previous_opts: Vec<(InstanceId, Option<SpawnOptions>)> = {
+            let stored = self.spawn_options.read().await;
+            green
+                .iter()
+                .map(|id| {
+                    let id = InstanceId::new(process_name, id);
+                    let opts = stored.get(&id).cloned();
+                    (id, opts)
+                })
+                .collect()
+        };
+        let mut spawned = Vec::new();
+        let mut failure = None;
+        for id in &green {
+            match self.spawn_with_opts(process_name, id, opts.clone()).await {
+                Ok(_) => spawned.push(id.clone()),
+                Err(e) => {
+                    failure = Some(e.context(format!("Failed to spawn {}:{}", process_name, id)));
+                    break;
+                }
+            }
+            // spawn_with_opts already waited out the readiness gate
+            let ready = self
+                .instances
+                .read()
+                .await
+                .get(&InstanceId::new(process_name, id))
+                .is_some_and(|i| i.ready);
+            if !ready {
+                failure = Some(anyhow::anyhow!(
+                    "Instance {}:{} did not become ready",
+                    process_name,
+                    id
+                ));
+                break;
+            }
+        }
+        if let Some(e) = failure {
+            for id in &spawned {
+                let _ = self.stop(process_name, id).await;
+            }
+            let mut stored = self.spawn_options.write().await;
+            for (id, opts) in previous_opts {
+                match opts {
+                    Some(opts) => stored.insert(id, opts),
+                    None => stored.remove(&id),
+                };
+            }
+            return Err(e);
+        }
+
+        // Switch every weight under one lock so no request finds neither set routable
+        {
+            let mut instances = self.instances.write().await;
+            for id in &green {
+                if let Some(instance) = instances.get_mut(&InstanceId::new(process_name, id)) {
+                    instance.weight = 100;
+                }
+            }
+            for info in &blue {
+                if let Some(instance) = instances.get_mut(&info.id) {
+                    instance.weight = 0;
+                }
+            }
+        }
+        info!("Switched {} traffic to version {}", process_name, version);
+        for id in &green {
+            self.record_weight(&InstanceId::new(process_name, id), 100)
+                .await;
+        }
+
+        for info in &blue {
+            if let Err(e) = self
+                .drain(process_name, &info.id.id, STOP_DRAIN_TIMEOUT)
+                .await
+            {
+                warn!("Failed to retire {}: {}", info.id, e);
+            }
+            // Retired for good: a later instance with this ID starts fresh
+            self.record_weight(&info.id, 100).await;
+        }
+
+        if let Some(ref store) = self.state_store {
+            if let Err(e) = store
+                .save_versions(process_name, Some(version), previous.as_deref())
+                .await
+            {
+                error!("Failed to persist versions of {}: {}", process_name, e);
+            }
+        }
+        self.versions.write().await.insert(
+            process_name.to_string(),
+            ServiceVersions {
+                current: Some(version.to_string()),
+                previous,
+                instances: Vec::new(),
+            },
+        );
+        Ok(self.service_versions(process_name).await)
+    }
+
+    /// Current and previous version of a service (see [`Hypervisor::deploy`])
+    pub async fn service_versions(&self, process_name: &str) -> ServiceVersions {
+        let mut versions = self
+            .versions
+            .read()
+            .await
+            .get(process_name)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(current) = &versions.current {
+            let mut ids: Vec<String> = self
+                .list_by_process(process_name)
+                .await
+                .into_iter()
+                .filter(|i| i.labels.get(VERSION_LABEL) == Some(current))
+                .map(|i| i.id.id)
+                .collect();
+            ids.sort();
+            versions.instances = ids;
+        }
+        versions
+    }
+}
+
+/// Whether instances of `runtime` get their limits from Tenement's cgroup
+/// manager; the others are limited by their engine or service manager
+fn uses_cgroup_manager(runtime: RuntimeType) -> bool {
+    !matches!(
+        runtime,
+        RuntimeType::Sandbox
+            | RuntimeType::Quark
+            | RuntimeType::Container
+            | RuntimeType::Oci
+            | RuntimeType::Systemd
+    )
+}
+
+/// Stable per-instance delay (0 to a tenth of `lifetime` seconds) added to
+/// `max_lifetime`, so instances spawned together recycle at different times
+fn lifetime_offset(instance_id: &InstanceId, lifetime: u64) -> u64 {
+    crate::hash::fnv1a(instance_id.to_string().bytes()) % (lifetime / 10 + 1)
+}
+
+/// Move an instance's data dir to `to`, leaving a symlink at the old path
+fn move_data_dir(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::rename(from, to).with_context(|| {
+        format!(
+            "Failed to move data dir {} to {}",
+            from.display(),
+            to.display()
+        )
+    })?;
+    #[cfg(unix)]
+    if let Err(e) = std::os::unix::fs::symlink(to, from) {
+        warn!(
+            "Failed to link {} to {}: {}",
+            from.display(),
+            to.display(),
+            e
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ProcessConfig;
+    use std::collections::HashMap;
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    // Helper to create a test config with a simple echo server process
+    fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> Config {
+        let test_id = format!("{}-{}", std::process::id(), rand::random::<u32>());
+        let mut config = Config::default();
+        config.settings.data_dir = std::env::temp_dir().join(format!("tenement-test-{}", test_id));
+
+        let process = ProcessConfig {
+            command: command.to_string(),
             args: args.into_iter().map(|s| s.to_string()).collect(),
             socket: format!("/tmp/tenement-test-{}/{{name}}-{{id}}.sock", test_id),
             isolation: RuntimeType::Process,
@@ -1685,7 +4741,7 @@ mod tests {
             restart: "on-failure".to_string(),
             idle_timeout: None,
             startup_timeout: 5,
-            request_timeout: 30,
+            request_timeout: None,
             memory_limit_mb: None,
             cpu_shares: None,
             kernel: None,
@@ -1695,6 +4751,7 @@ mod tests {
             vsock_port: 5000,
             storage_quota_mb: None,
             storage_persist: false,
+            ..Default::default()
         };
 
         config.service.insert(name.to_string(), process);
@@ -1854,6 +4911,27 @@ sleep 30
         assert_eq!(hypervisor.calculate_backoff(5), Duration::ZERO);
     }
 
+    #[test]
+    fn test_calculate_backoff_jitter() {
+        let mut config = Config::default();
+        config.settings.backoff_base_ms = 1000;
+        config.settings.backoff_jitter = 50;
+        let hypervisor = Hypervisor::new(config);
+
+        let delays: std::collections::HashSet<Duration> =
+            (0..100).map(|_| hypervisor.calculate_backoff(3)).collect();
+        for delay in &delays {
+            assert!(
+                (Duration::from_millis(2000)..=Duration::from_millis(4000)).contains(delay),
+                "delay {:?} outside jitter window",
+                delay
+            );
+        }
+        // Randomized, not a single fixed delay
+        assert!(delays.len() > 1);
+        assert_eq!(hypervisor.calculate_backoff(0), Duration::ZERO);
+    }
+
     // ===================
     // LIFECYCLE TESTS
     // ===================
@@ -2456,6 +5534,83 @@ sleep 30
             .any(|l| l.message.contains("MY_CUSTOM_VAR=custom_value")));
     }
 
+    #[tokio::test]
+    async fn test_spawn_with_opts_env_survives_respawn() {
+        let config = test_config_with_process("api", "env", vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        let opts = SpawnOptions {
+            env: HashMap::from([("FEATURE_X".to_string(), "on".to_string())]),
+            ..Default::default()
+        };
+        hypervisor
+            .spawn_with_opts("api", "test", opts)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let _ = hypervisor.stop_instance("api", "test").await;
+
+        // A plain spawn (as restarts and wakes do) reapplies the override
+        hypervisor.spawn("api", "test").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let hits = |logs: Vec<crate::logs::LogEntry>| {
+            logs.iter()
+                .filter(|l| l.message.contains("FEATURE_X=on"))
+                .count()
+        };
+        let logs = hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery::default())
+            .await;
+        assert_eq!(hits(logs), 2);
+
+        // An explicit stop forgets it
+        let _ = hypervisor.stop("api", "test").await;
+        hypervisor.spawn("api", "test").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let logs = hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery::default())
+            .await;
+        assert_eq!(hits(logs), 2);
+        assert!(!hypervisor
+            .spawn_options
+            .read()
+            .await
+            .contains_key(&InstanceId::new("api", "test")));
+
+        // Other instances of the service don't get it
+        hypervisor.spawn("api", "other").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let logs = hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery::default())
+            .await;
+        assert!(!logs
+            .iter()
+            .any(|l| l.instance_id == "other" && l.message.contains("FEATURE_X")));
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_opts_rejects_bad_env_name() {
+        let config = test_config_with_process("api", "env", vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        let opts = SpawnOptions {
+            env: HashMap::from([("A=B".to_string(), "x".to_string())]),
+            ..Default::default()
+        };
+        let err = hypervisor
+            .spawn_with_opts("api", "test", opts)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Invalid environment variable name"));
+        assert!(hypervisor.get("api", "test").await.is_none());
+    }
+
     #[tokio::test]
     async fn test_spawn_sets_port_env() {
         let config = test_config_with_process("api", "env", vec![]);
@@ -2554,7 +5709,7 @@ sleep 30
                 restart: "on-failure".to_string(),
                 idle_timeout: None,
                 startup_timeout: 5,
-                request_timeout: 30,
+                request_timeout: None,
                 memory_limit_mb: None,
                 cpu_shares: None,
                 kernel: None,
@@ -2564,6 +5719,7 @@ sleep 30
                 vsock_port: 5000,
                 storage_quota_mb: None,
                 storage_persist: false,
+                ..Default::default()
             },
         );
 
@@ -2590,6 +5746,86 @@ sleep 30
         hypervisor.stop("api", "prod").await.ok();
     }
 
+    #[tokio::test]
+    async fn test_affected_instances() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config.clone());
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+
+        // Unchanged config affects nothing
+        let diff = crate::config_diff::ConfigDiff::between(&config, &config);
+        assert!(hypervisor.affected_instances(&diff).await.is_empty());
+
+        // Modifying the service affects its running instances
+        let mut modified = config.clone();
+        modified.service.get_mut("api").unwrap().idle_timeout = Some(60);
+        let diff = crate::config_diff::ConfigDiff::between(&config, &modified);
+        assert_eq!(hypervisor.affected_instances(&diff).await, vec!["api:prod"]);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_socket_permissions_enforced() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.service.get_mut("api").unwrap().socket_mode = Some(0o640);
+        let hypervisor = Hypervisor::new(config.clone());
+
+        let socket = hypervisor.spawn("api", "prod").await.unwrap();
+        hypervisor.check_health("api", "prod").await;
+        let metadata = std::fs::metadata(&socket).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+
+        // An instance loosening it again is corrected on the next check
+        std::fs::set_permissions(&socket, std::fs::Permissions::from_mode(0o777)).unwrap();
+        hypervisor.check_health("api", "prod").await;
+        let metadata = std::fs::metadata(&socket).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o640);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_config_drift() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config.clone());
+        hypervisor.spawn("api", "prod").await.unwrap();
+        assert!(hypervisor.config_drift(&config).await.is_empty());
+
+        let mut modified = config.clone();
+        modified.service.get_mut("api").unwrap().idle_timeout = Some(60);
+        let drift = hypervisor.config_drift(&modified).await;
+        assert_eq!(drift.len(), 1);
+        assert_eq!(drift[0].instance, "api:prod");
+        assert_eq!(drift[0].reason, DriftReason::Modified);
+        assert_eq!(drift[0].changes[0].field, "idle_timeout");
+        assert_eq!(drift[0].changes[0].new, "60");
+        // Still drifted after the new config is live, until restarted
+        *hypervisor.config.write().unwrap() = Arc::new(modified.clone());
+        assert_eq!(hypervisor.config_drift(&modified).await.len(), 1);
+        hypervisor.restart("api", "prod").await.unwrap();
+        assert!(hypervisor.config_drift(&modified).await.is_empty());
+
+        let mut removed = modified.clone();
+        removed.service.clear();
+        let drift = hypervisor.config_drift(&removed).await;
+        assert_eq!(drift[0].reason, DriftReason::Removed);
+        assert!(drift[0].changes.is_empty());
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
     // ===================
     // WEIGHTED ROUTING TESTS
     // ===================
@@ -2746,18 +5982,7 @@ sleep 30
     }
 
     #[tokio::test]
-    async fn test_select_weighted_no_instances() {
-        let config = Config::default();
-        let hypervisor = Hypervisor::new(config);
-
-        // No instances -> None
-        let selected = hypervisor.select_weighted("api").await;
-        assert!(selected.is_none());
-    }
-
-    #[tokio::test]
-    async fn test_select_weighted_distribution() {
-        // Test that weighted selection roughly follows the weights
+    async fn test_select_weighted_excludes_draining() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
@@ -2767,283 +5992,2250 @@ sleep 30
         hypervisor.spawn("api", "v1").await.unwrap();
         hypervisor.spawn("api", "v2").await.unwrap();
 
-        // Set v1=90, v2=10
-        hypervisor.set_weight("api", "v1", 90).await.unwrap();
-        hypervisor.set_weight("api", "v2", 10).await.unwrap();
-
-        let mut v1_count = 0;
-        let mut v2_count = 0;
-        let iterations = 1000;
+        // Hold a connection open on v1 so drain() blocks
+        let guard = hypervisor.connection_start("api", "v1").await;
+        let hyp = hypervisor.clone();
+        let drain =
+            tokio::spawn(async move { hyp.drain("api", "v1", Duration::from_secs(10)).await });
 
-        for _ in 0..iterations {
-            if let Some(selected) = hypervisor.select_weighted("api").await {
-                match selected.id.id.as_str() {
-                    "v1" => v1_count += 1,
-                    "v2" => v2_count += 1,
-                    _ => panic!("Unexpected instance"),
-                }
+        // Wait for v1 to be marked as draining
+        for _ in 0..50 {
+            if hypervisor.is_draining("api", "v1").await {
+                break;
             }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
+        assert!(hypervisor.is_draining("api", "v1").await);
+        let info = hypervisor.get("api", "v1").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Stopping);
 
-        // v1 should get roughly 90% of selections (with some variance)
-        // Allow 15% margin for statistical variance
-        let v1_ratio = v1_count as f64 / iterations as f64;
-        let v2_ratio = v2_count as f64 / iterations as f64;
-        assert!(v1_ratio > 0.75, "v1 ratio {} should be > 0.75", v1_ratio);
-        assert!(v1_ratio < 0.98, "v1 ratio {} should be < 0.98", v1_ratio);
-        assert!(v2_ratio > 0.02, "v2 ratio {} should be > 0.02", v2_ratio);
-
-        hypervisor.stop("api", "v1").await.ok();
-        hypervisor.stop("api", "v2").await.ok();
-    }
-
-    // ===================
-    // DEPLOY COMMAND TESTS
-    // ===================
-
-    #[tokio::test]
-    async fn test_deploy_and_wait_healthy_no_health_endpoint() {
-        // When no health endpoint is configured, instance is healthy if socket exists
-        let dir = TempDir::new().unwrap();
-        let script = create_touch_socket_script(dir.path());
-
-        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
-        let hypervisor = Hypervisor::new(config);
-
-        // Deploy v2 with default weight
-        let result = hypervisor
-            .deploy_and_wait_healthy("api", "v2", 100, 5)
-            .await;
-        assert!(result.is_ok(), "Deploy should succeed: {:?}", result.err());
+        // v2 should always be selected while v1 drains
+        for _ in 0..10 {
+            let selected = hypervisor.select_weighted("api").await;
+            assert_eq!(selected.unwrap().id.id, "v2");
+        }
 
-        // Verify instance is running with correct weight
-        let info = hypervisor.get("api", "v2").await.unwrap();
-        assert_eq!(info.weight, 100);
+        // Releasing the connection lets the drain finish and stop v1
+        drop(guard);
+        drain.await.unwrap().unwrap();
+        assert!(!hypervisor.is_running("api", "v1").await);
+        assert!(hypervisor.is_running("api", "v2").await);
 
         hypervisor.stop("api", "v2").await.ok();
     }
 
     #[tokio::test]
-    async fn test_deploy_with_initial_weight() {
-        // Deploy with a low initial weight (canary style)
+    async fn test_drain_timeout_force_stops() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
         let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
         let hypervisor = Hypervisor::new(config);
 
-        // Deploy v2 with 10% weight
-        let result = hypervisor.deploy_and_wait_healthy("api", "v2", 10, 5).await;
-        assert!(result.is_ok());
-
-        // Verify weight was set
-        let info = hypervisor.get("api", "v2").await.unwrap();
-        assert_eq!(info.weight, 10);
+        hypervisor.spawn("api", "prod").await.unwrap();
 
-        hypervisor.stop("api", "v2").await.ok();
+        // Connection is never released; drain must give up after the timeout
+        let _guard = hypervisor.connection_start("api", "prod").await;
+        let start = Instant::now();
+        hypervisor
+            .drain("api", "prod", Duration::from_millis(300))
+            .await
+            .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!hypervisor.is_running("api", "prod").await);
     }
 
     #[tokio::test]
-    async fn test_deploy_unknown_process() {
+    async fn test_drain_nonexistent_instance() {
         let config = Config::default();
         let hypervisor = Hypervisor::new(config);
 
         let result = hypervisor
-            .deploy_and_wait_healthy("nonexistent", "v1", 100, 5)
+            .drain("api", "nonexistent", Duration::from_secs(1))
             .await;
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("Unknown process"));
+        assert!(result.unwrap_err().to_string().contains("not found"));
     }
 
     // ===================
-    // ROUTE SWAP TESTS
+    // RE-ADOPTION TESTS
     // ===================
 
-    #[tokio::test]
-    async fn test_route_swap() {
-        let dir = TempDir::new().unwrap();
+    async fn test_state_store(dir: &Path) -> Arc<crate::store::StateStore> {
+        let pool = crate::store::init_db(&dir.join("state.db")).await.unwrap();
+        Arc::new(crate::store::StateStore::new(pool))
+    }
+
+    async fn wait_for_port(port: u16) {
+        for _ in 0..100 {
+            if tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok()
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("port {} never became reachable", port);
+    }
+
+    /// Wait for the child still owned by `hypervisor` to be killed elsewhere
+    async fn wait_for_exit(hypervisor: &Hypervisor, process_name: &str, id: &str) {
+        let mut instances = hypervisor.instances.write().await;
+        let instance = instances
+            .get_mut(&InstanceId::new(process_name, id))
+            .unwrap();
+        for _ in 0..100 {
+            if !instance.handle.is_running().await {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("process {}:{} was not killed", process_name, id);
+    }
+
+    #[tokio::test]
+    async fn test_recover_orphans_readopts_running_instance() {
+        let dir = TempDir::new().unwrap();
+        let store = test_state_store(dir.path()).await;
+        let config = test_config_with_process(
+            "api",
+            "python3",
+            vec!["-m", "http.server", "{port}", "--bind", "127.0.0.1"],
+        );
+
+        // First daemon spawns the instance, then "crashes" (never stops it)
+        let first = Hypervisor::with_state_store(config.clone(), store.clone());
+        first.spawn("api", "prod").await.unwrap();
+        let (pid, port) = {
+            let instances = first.instances.read().await;
+            let instance = instances.get(&InstanceId::new("api", "prod")).unwrap();
+            (instance.handle.pid().unwrap(), instance.port.unwrap())
+        };
+        wait_for_port(port).await;
+
+        // Second daemon re-adopts it instead of killing it
+        let second = Hypervisor::with_state_store(config, store.clone());
+        assert_eq!(second.recover_orphans().await, 1);
+        {
+            let instances = second.instances.read().await;
+            let instance = instances.get(&InstanceId::new("api", "prod")).unwrap();
+            assert_eq!(instance.handle.pid(), Some(pid));
+            assert_eq!(instance.port, Some(port));
+            assert_eq!(instance.runtime_type, RuntimeType::Process);
+        }
+        assert!(second.port_allocator.is_allocated(port).await);
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        // The spawn-time definition survives the restart
+        assert!(second.config_drift(&second.config()).await.is_empty());
+
+        // Spawning the same instance again is a no-op
+        second.spawn("api", "prod").await.unwrap();
+        assert_eq!(second.list().await.len(), 1);
+
+        second.stop("api", "prod").await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+        wait_for_exit(&first, "api", "prod").await;
+    }
+
+    #[tokio::test]
+    async fn test_recover_orphans_keeps_unconfigured_service_orphaned() {
+        let dir = TempDir::new().unwrap();
+        let store = test_state_store(dir.path()).await;
+        let args = vec!["-m", "http.server", "{port}", "--bind", "127.0.0.1"];
+        let config = test_config_with_process("api", "python3", args.clone());
+        let data_root = config.settings.data_dir.clone();
+
+        let first = Hypervisor::with_state_store(config, store.clone());
+        first.spawn("api", "prod").await.unwrap();
+        wait_for_port(first.get("api", "prod").await.unwrap().port.unwrap()).await;
+        std::fs::write(data_root.join("api/prod/db.sqlite"), "data").unwrap();
+
+        // The restarted daemon has the service renamed to "web"
+        let mut renamed = test_config_with_process("web", "python3", args);
+        renamed.settings.data_dir = data_root.clone();
+        let second = Hypervisor::with_state_store(renamed, store.clone());
+        assert_eq!(second.recover_orphans().await, 1);
+        let info = second.get("api", "prod").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Orphaned);
+        assert_eq!(
+            second.check_health("api", "prod").await,
+            HealthStatus::Unknown
+        );
+        assert_eq!(store.list().await.unwrap()[0].instance_id, "api:prod");
+
+        assert!(second.adopt_config("api", "nope", false).await.is_err());
+        let remapped = second.adopt_config("api", "web", false).await.unwrap();
+        assert_eq!(remapped, vec![InstanceId::new("web", "prod")]);
+        assert!(second.get("api", "prod").await.is_none());
+        let info = second.get("web", "prod").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+        assert_eq!(info.data_dir, data_root.join("web/prod"));
+        assert_eq!(
+            std::fs::read_to_string(data_root.join("web/prod/db.sqlite")).unwrap(),
+            "data"
+        );
+        // The running process still finds its files at the old path
+        assert!(data_root.join("api/prod/db.sqlite").exists());
+        assert_eq!(store.list().await.unwrap()[0].instance_id, "web:prod");
+        let err = second.adopt_config("api", "web", false).await.unwrap_err();
+        assert!(err.to_string().contains("No orphaned instances"));
+
+        second.stop("web", "prod").await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+        wait_for_exit(&first, "api", "prod").await;
+        std::fs::remove_dir_all(&data_root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_adopt_config_replace() {
+        let dir = TempDir::new().unwrap();
+        let store = test_state_store(dir.path()).await;
+        let args = vec!["-m", "http.server", "{port}", "--bind", "127.0.0.1"];
+        let config = test_config_with_process("api", "python3", args.clone());
+        let data_root = config.settings.data_dir.clone();
+
+        let first = Hypervisor::with_state_store(config, store.clone());
+        first.spawn("api", "prod").await.unwrap();
+        wait_for_port(first.get("api", "prod").await.unwrap().port.unwrap()).await;
+
+        // Renamed service also listed under [instances]: a fresh web:prod
+        // was spawned next to the orphan
+        let mut renamed = test_config_with_process("web", "python3", args);
+        renamed.settings.data_dir = data_root.clone();
+        renamed.service.get_mut("web").unwrap().storage_persist = true;
+        let second = Hypervisor::with_state_store(renamed, store.clone());
+        assert_eq!(second.recover_orphans().await, 1);
+        second.spawn("web", "prod").await.unwrap();
+        let fresh_pid = second.instances.read().await[&InstanceId::new("web", "prod")]
+            .handle
+            .pid();
+
+        let err = second.adopt_config("api", "web", false).await.unwrap_err();
+        assert!(err.to_string().contains("already running"), "got: {err}");
+        assert!(second.get("api", "prod").await.is_some());
+
+        second.adopt_config("api", "web", true).await.unwrap();
+        let pid = second.instances.read().await[&InstanceId::new("web", "prod")]
+            .handle
+            .pid();
+        assert_ne!(pid, fresh_pid);
+        assert_eq!(second.list().await.len(), 1);
+        let replaced = std::fs::read_dir(data_root.join("web"))
+            .unwrap()
+            .flatten()
+            .any(|e| {
+                e.file_name()
+                    .to_string_lossy()
+                    .starts_with("prod.replaced-")
+            });
+        assert!(replaced);
+
+        second.stop("web", "prod").await.unwrap();
+        wait_for_exit(&first, "api", "prod").await;
+        std::fs::remove_dir_all(&data_root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_select_weighted_no_instances() {
+        let config = Config::default();
+        let hypervisor = Hypervisor::new(config);
+
+        // No instances -> None
+        let selected = hypervisor.select_weighted("api").await;
+        assert!(selected.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_select_weighted_distribution() {
+        // Test that weighted selection roughly follows the weights
+        let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
         let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
         let hypervisor = Hypervisor::new(config);
 
-        // Spawn v1 and v2
         hypervisor.spawn("api", "v1").await.unwrap();
         hypervisor.spawn("api", "v2").await.unwrap();
 
-        // Initially both have weight 100
-        let v1_info = hypervisor.get("api", "v1").await.unwrap();
-        let v2_info = hypervisor.get("api", "v2").await.unwrap();
-        assert_eq!(v1_info.weight, 100);
-        assert_eq!(v2_info.weight, 100);
+        // Set v1=90, v2=10
+        hypervisor.set_weight("api", "v1", 90).await.unwrap();
+        hypervisor.set_weight("api", "v2", 10).await.unwrap();
 
-        // Swap traffic from v1 to v2
-        let result = hypervisor.route_swap("api", "v1", "v2").await;
-        assert!(result.is_ok());
+        let mut v1_count = 0;
+        let mut v2_count = 0;
+        let iterations = 1000;
 
-        // Verify weights swapped atomically
-        let v1_info = hypervisor.get("api", "v1").await.unwrap();
-        let v2_info = hypervisor.get("api", "v2").await.unwrap();
-        assert_eq!(v1_info.weight, 0);
-        assert_eq!(v2_info.weight, 100);
+        for _ in 0..iterations {
+            if let Some(selected) = hypervisor.select_weighted("api").await {
+                match selected.id.id.as_str() {
+                    "v1" => v1_count += 1,
+                    "v2" => v2_count += 1,
+                    _ => panic!("Unexpected instance"),
+                }
+            }
+        }
+
+        // v1 should get roughly 90% of selections (with some variance)
+        // Allow 15% margin for statistical variance
+        let v1_ratio = v1_count as f64 / iterations as f64;
+        let v2_ratio = v2_count as f64 / iterations as f64;
+        assert!(v1_ratio > 0.75, "v1 ratio {} should be > 0.75", v1_ratio);
+        assert!(v1_ratio < 0.98, "v1 ratio {} should be < 0.98", v1_ratio);
+        assert!(v2_ratio > 0.02, "v2 ratio {} should be > 0.02", v2_ratio);
 
         hypervisor.stop("api", "v1").await.ok();
         hypervisor.stop("api", "v2").await.ok();
     }
 
     #[tokio::test]
-    async fn test_route_swap_from_not_found() {
+    async fn test_select_weighted_seeded_is_deterministic() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
-        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
-        let hypervisor = Hypervisor::new(config);
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+            let hypervisor = Hypervisor::new(config).with_routing_seed(42);
+            // Spawn order must not matter
+            for id in if runs.is_empty() {
+                ["v1", "v2"]
+            } else {
+                ["v2", "v1"]
+            } {
+                hypervisor.spawn("api", id).await.unwrap();
+            }
+            hypervisor.set_weight("api", "v1", 70).await.unwrap();
+            hypervisor.set_weight("api", "v2", 30).await.unwrap();
 
-        // Only spawn v2
-        hypervisor.spawn("api", "v2").await.unwrap();
+            let mut picks = Vec::new();
+            for _ in 0..1000 {
+                picks.push(hypervisor.select_weighted("api").await.unwrap().id.id);
+            }
+            hypervisor.stop_all().await;
+            runs.push(picks);
+        }
 
-        // Try to swap from non-existent v1
-        let result = hypervisor.route_swap("api", "v1", "v2").await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("not found"));
+        assert_eq!(runs[0], runs[1]);
+        let v1 = runs[0].iter().filter(|id| *id == "v1").count();
+        assert!((650..750).contains(&v1), "v1 picked {} of 1000", v1);
+    }
 
-        hypervisor.stop("api", "v2").await.ok();
+    async fn set_health(
+        hypervisor: &Hypervisor,
+        process_name: &str,
+        id: &str,
+        status: HealthStatus,
+    ) {
+        let mut instances = hypervisor.instances.write().await;
+        instances
+            .get_mut(&InstanceId::new(process_name, id))
+            .unwrap()
+            .health_status = status;
     }
 
     #[tokio::test]
-    async fn test_route_swap_to_not_found() {
+    async fn test_select_weighted_reduces_degraded_weight() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
         let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
         let hypervisor = Hypervisor::new(config);
 
-        // Only spawn v1
         hypervisor.spawn("api", "v1").await.unwrap();
+        hypervisor.spawn("api", "v2").await.unwrap();
 
-        // Try to swap to non-existent v2
-        let result = hypervisor.route_swap("api", "v1", "v2").await;
-        assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("not found"));
+        // Default 25%: degraded v1 (100 -> 25) vs healthy v2 (100) => ~20% for v1
+        set_health(&hypervisor, "api", "v1", HealthStatus::Degraded).await;
+        set_health(&hypervisor, "api", "v2", HealthStatus::Healthy).await;
+
+        let iterations = 1000;
+        let mut v1_count = 0;
+        for _ in 0..iterations {
+            if hypervisor.select_weighted("api").await.unwrap().id.id == "v1" {
+                v1_count += 1;
+            }
+        }
+        let v1_ratio = v1_count as f64 / iterations as f64;
+        assert!(v1_ratio > 0.1, "v1 ratio {} should be > 0.1", v1_ratio);
+        assert!(v1_ratio < 0.3, "v1 ratio {} should be < 0.3", v1_ratio);
+
+        // Back to healthy restores the full weight
+        set_health(&hypervisor, "api", "v1", HealthStatus::Healthy).await;
+        let mut v1_count = 0;
+        for _ in 0..iterations {
+            if hypervisor.select_weighted("api").await.unwrap().id.id == "v1" {
+                v1_count += 1;
+            }
+        }
+        let v1_ratio = v1_count as f64 / iterations as f64;
+        assert!(v1_ratio > 0.4, "v1 ratio {} should be > 0.4", v1_ratio);
 
         hypervisor.stop("api", "v1").await.ok();
+        hypervisor.stop("api", "v2").await.ok();
     }
 
     #[tokio::test]
-    async fn test_route_swap_weighted_routing() {
-        // Verify that after route swap, only the target version receives traffic
+    async fn test_select_weighted_zero_degraded_percent_excludes() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
-        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.settings.degraded_weight_percent = 0;
         let hypervisor = Hypervisor::new(config);
 
         hypervisor.spawn("api", "v1").await.unwrap();
         hypervisor.spawn("api", "v2").await.unwrap();
+        set_health(&hypervisor, "api", "v1", HealthStatus::Degraded).await;
 
-        // Route all traffic from v1 to v2
-        hypervisor.route_swap("api", "v1", "v2").await.unwrap();
-
-        // All weighted selections should now go to v2 (v1 has weight 0)
-        for _ in 0..10 {
+        for _ in 0..20 {
             let selected = hypervisor.select_weighted("api").await;
-            assert!(selected.is_some());
             assert_eq!(selected.unwrap().id.id, "v2");
         }
 
+        // All degraded -> nothing selectable
+        set_health(&hypervisor, "api", "v2", HealthStatus::Degraded).await;
+        assert!(hypervisor.select_weighted("api").await.is_none());
+
         hypervisor.stop("api", "v1").await.ok();
         hypervisor.stop("api", "v2").await.ok();
     }
 
     #[tokio::test]
-    async fn test_blue_green_workflow() {
-        // Full blue/green deployment workflow
+    async fn test_select_weighted_skips_failing_health() {
         let dir = TempDir::new().unwrap();
         let script = create_touch_socket_script(dir.path());
 
         let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
         let hypervisor = Hypervisor::new(config);
 
-        // 1. Deploy v1 (blue) initially
-        hypervisor
-            .deploy_and_wait_healthy("api", "v1", 100, 5)
-            .await
-            .unwrap();
-
-        // 2. Deploy v2 (green) with weight 0 (no traffic yet)
-        hypervisor
-            .deploy_and_wait_healthy("api", "v2", 0, 5)
-            .await
-            .unwrap();
+        hypervisor.spawn("api", "v1").await.unwrap();
+        hypervisor.spawn("api", "v2").await.unwrap();
+        set_health(&hypervisor, "api", "v2", HealthStatus::Healthy).await;
 
-        // At this point, all traffic goes to v1
-        for _ in 0..5 {
-            let selected = hypervisor.select_weighted("api").await;
-            assert_eq!(selected.unwrap().id.id, "v1");
+        for status in [HealthStatus::Unhealthy, HealthStatus::Failed] {
+            set_health(&hypervisor, "api", "v1", status).await;
+            for _ in 0..20 {
+                let selected = hypervisor.select_weighted("api").await;
+                assert_eq!(selected.unwrap().id.id, "v2");
+            }
         }
 
-        // 3. Atomic swap: route all traffic from v1 to v2
-        hypervisor.route_swap("api", "v1", "v2").await.unwrap();
+        // Not checked yet is routable by default
+        set_health(&hypervisor, "api", "v1", HealthStatus::Unknown).await;
+        set_health(&hypervisor, "api", "v2", HealthStatus::Unhealthy).await;
+        assert_eq!(hypervisor.select_weighted("api").await.unwrap().id.id, "v1");
 
-        // Now all traffic goes to v2
-        for _ in 0..5 {
-            let selected = hypervisor.select_weighted("api").await;
-            assert_eq!(selected.unwrap().id.id, "v2");
-        }
+        let mut config = (*hypervisor.config()).clone();
+        config.settings.route_unknown_health = false;
+        *hypervisor.config.write().unwrap() = Arc::new(config);
+        assert!(hypervisor.select_weighted("api").await.is_none());
 
-        // 4. Stop old version v1
-        hypervisor.stop("api", "v1").await.unwrap();
+        hypervisor.stop("api", "v1").await.ok();
+        hypervisor.stop("api", "v2").await.ok();
+    }
 
-        // Only v2 remains
-        let instances = hypervisor.list_by_process("api").await;
-        assert_eq!(instances.len(), 1);
-        assert_eq!(instances[0].id.id, "v2");
+    #[tokio::test]
+    async fn test_check_health_slow_response_is_degraded() {
+        let mut config = test_config_with_process(
+            "api",
+            "python3",
+            vec!["-m", "http.server", "{port}", "--bind", "127.0.0.1"],
+        );
+        config.service.get_mut("api").unwrap().health = Some("/".to_string());
+        // Any real response takes longer than 0ms
+        config.settings.degraded_latency_ms = Some(0);
+        let hypervisor = Hypervisor::new(config);
 
-        hypervisor.stop("api", "v2").await.ok();
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let port = hypervisor.get("api", "prod").await.unwrap().port.unwrap();
+        wait_for_port(port).await;
+
+        let status = hypervisor.check_health("api", "prod").await;
+        assert_eq!(status, HealthStatus::Degraded);
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.health, HealthStatus::Degraded);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    fn http_server_config(health: &str, readiness_timeout: u64) -> Config {
+        let mut config = test_config_with_process(
+            "api",
+            "python3",
+            vec!["-m", "http.server", "{port}", "--bind", "127.0.0.1"],
+        );
+        let service = config.service.get_mut("api").unwrap();
+        service.health = Some(health.to_string());
+        service.readiness_timeout = readiness_timeout;
+        config
     }
 
     #[tokio::test]
-    async fn test_canary_workflow() {
-        // Full canary deployment workflow
-        let dir = TempDir::new().unwrap();
-        let script = create_touch_socket_script(dir.path());
+    async fn test_spawn_waits_for_readiness() {
+        let hypervisor = Hypervisor::new(http_server_config("/", 10));
 
-        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        hypervisor.spawn("api", "prod").await.unwrap();
+
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+        assert_eq!(info.health, HealthStatus::Healthy);
+        assert!(hypervisor.select_weighted("api").await.is_some());
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_unready_instance_not_routable() {
+        // python's http.server returns 404 for this path, so it never gets ready
+        let hypervisor = Hypervisor::new(http_server_config("/not-found", 1));
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Starting);
+        assert!(hypervisor.select_weighted("api").await.is_none());
+        assert!(!hypervisor.wait_until_ready("api", "prod").await);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_health_check_marks_late_instance_ready() {
+        // Zero timeout: spawn gives up after one probe, before the server is up
+        let hypervisor = Hypervisor::new(http_server_config("/", 0));
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        wait_for_port(info.port.unwrap()).await;
+
+        assert_eq!(
+            hypervisor.check_health("api", "prod").await,
+            HealthStatus::Healthy
+        );
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+        assert!(hypervisor.wait_until_ready("api", "prod").await);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_fill_warm_pools_hides_warm_instances() {
+        let mut config = http_server_config("/", 10);
+        config.service.get_mut("api").unwrap().warm_pool = 2;
         let hypervisor = Hypervisor::new(config);
 
-        // 1. v1 is running with full traffic
-        hypervisor
-            .deploy_and_wait_healthy("api", "v1", 100, 5)
-            .await
-            .unwrap();
+        assert_eq!(hypervisor.fill_warm_pools().await, 2);
+        assert_eq!(hypervisor.warm_count("api").await, 2);
+        // Already full
+        assert_eq!(hypervisor.fill_warm_pools().await, 0);
 
-        // 2. Deploy v2 with 10% traffic (canary)
-        hypervisor
-            .deploy_and_wait_healthy("api", "v2", 10, 5)
-            .await
-            .unwrap();
+        assert!(hypervisor.list().await.is_empty());
+        assert!(hypervisor.list_by_process("api").await.is_empty());
+        assert!(hypervisor.select_weighted("api").await.is_none());
 
-        // Traffic split is now roughly 91% v1, 9% v2 (100:10 ratio)
-        let v1_info = hypervisor.get("api", "v1").await.unwrap();
-        let v2_info = hypervisor.get("api", "v2").await.unwrap();
-        assert_eq!(v1_info.weight, 100);
-        assert_eq!(v2_info.weight, 10);
+        hypervisor.stop_all().await;
+    }
 
-        // 3. Increase v2 weight to 50%
-        hypervisor.set_weight("api", "v2", 50).await.unwrap();
+    #[tokio::test]
+    async fn test_spawn_and_wait_claims_warm_instance() {
+        let mut config = http_server_config("/", 10);
+        config.service.get_mut("api").unwrap().warm_pool = 1;
+        let hypervisor = Hypervisor::new(config);
+        hypervisor.fill_warm_pools().await;
 
-        // 4. Full rollout: set v1=0, v2=100
-        hypervisor.set_weight("api", "v1", 0).await.unwrap();
-        hypervisor.set_weight("api", "v2", 100).await.unwrap();
+        let warm_pid = {
+            let instances = hypervisor.instances.read().await;
+            instances.values().next().unwrap().handle.pid()
+        };
 
-        // All traffic now goes to v2
-        for _ in 0..5 {
-            let selected = hypervisor.select_weighted("api").await;
-            assert_eq!(selected.unwrap().id.id, "v2");
-        }
+        hypervisor.spawn_and_wait("api", "alice").await.unwrap();
 
-        // 5. Stop v1
-        hypervisor.stop("api", "v1").await.ok();
-        hypervisor.stop("api", "v2").await.ok();
+        let info = hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+        assert_eq!(hypervisor.list().await.len(), 1);
+        assert_eq!(hypervisor.warm_count("api").await, 0);
+        {
+            let instances = hypervisor.instances.read().await;
+            let claimed = &instances[&InstanceId::new("api", "alice")];
+            assert_eq!(claimed.handle.pid(), warm_pid);
+            assert!(claimed.data_dir.ends_with("api/alice"));
+        }
+        // Its output is filed under the claimed ID
+        let warm_lines = hypervisor
+            .log_buffer
+            .query(&crate::logs::LogQuery {
+                process: Some("api".to_string()),
+                ..Default::default()
+            })
+            .await
+            .into_iter()
+            .filter(|e| e.instance_id != "alice")
+            .count();
+        assert_eq!(warm_lines, 0);
+
+        // Monitor refills the pool
+        assert_eq!(hypervisor.fill_warm_pools().await, 1);
+
+        // An instance with its own options is cold-started with them
+        hypervisor.spawn_options.write().await.insert(
+            InstanceId::new("api", "bob"),
+            SpawnOptions {
+                env: HashMap::from([("PLAN".to_string(), "pro".to_string())]),
+                ..Default::default()
+            },
+        );
+        assert!(hypervisor.claim_warm("api", "bob").await.is_none());
+        assert_eq!(hypervisor.warm_count("api").await, 1);
+
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_spawn_encrypted_without_key_store_fails() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.settings.encryption_key_file = Some(dir.path().join("master.key"));
+        config.service.get_mut("api").unwrap().encrypt_data = true;
+        let hypervisor = Hypervisor::new(config);
+
+        let err = hypervisor.spawn("api", "alice").await.unwrap_err();
+        assert!(err.to_string().contains("tenant key store"));
+        assert!(hypervisor.get("api", "alice").await.is_none());
+        // The spawning guard was released, so a retry fails the same way
+        // instead of reporting the instance as already being spawned
+        assert!(hypervisor.spawn("api", "alice").await.is_err());
+    }
+
+    // Like create_touch_socket_script, but exits with `code` shortly after
+    fn create_exiting_script(dir: &Path, code: i32) -> PathBuf {
+        let script_path = dir.join(format!("exit_{}.sh", code));
+        let script = format!(
+            r#"#!/bin/bash
+SOCKET_PATH="${{SOCKET_PATH:-/tmp/test.sock}}"
+rm -f "$SOCKET_PATH"
+touch "$SOCKET_PATH"
+sleep 0.5
+exit {}
+"#,
+            code
+        );
+        std::fs::write(&script_path, script).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        script_path
+    }
+
+    async fn wait_for_exit_code(hypervisor: &Hypervisor, process_name: &str, id: &str) {
+        let instance_id = InstanceId::new(process_name, id);
+        for _ in 0..50 {
+            {
+                let mut instances = hypervisor.instances.write().await;
+                let instance = instances.get_mut(&instance_id).unwrap();
+                if !instance.handle.is_running().await {
+                    return;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("{} did not exit", instance_id);
+    }
+
+    #[tokio::test]
+    async fn test_exit_code_outside_filter_is_permanent_failure() {
+        let dir = TempDir::new().unwrap();
+        let script = create_exiting_script(dir.path(), 3);
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.service.get_mut("api").unwrap().restart_exit_codes = vec![1, 137];
+        let hypervisor = Hypervisor::new(config);
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        wait_for_exit_code(&hypervisor, "api", "prod").await;
+
+        let kept_down = hypervisor.handle_exited_instances().await;
+        assert_eq!(kept_down, vec![InstanceId::new("api", "prod")]);
+
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.exit_code, Some(3));
+        assert_eq!(info.status, crate::instance::InstanceStatus::Stopped);
+        assert_eq!(info.health, HealthStatus::Failed);
+        assert_eq!(info.restarts, 0);
+        assert!(hypervisor.select_weighted("api").await.is_none());
+
+        // Already handled: not reported again
+        assert!(hypervisor.handle_exited_instances().await.is_empty());
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_exit_code_in_filter_restarts() {
+        let dir = TempDir::new().unwrap();
+        let script = create_exiting_script(dir.path(), 1);
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.settings.backoff_base_ms = 0;
+        config.service.get_mut("api").unwrap().restart_exit_codes = vec![1, 137];
+        let hypervisor = Hypervisor::new(config);
+
+        hypervisor.spawn("api", "prod").await.unwrap();
+        wait_for_exit_code(&hypervisor, "api", "prod").await;
+
+        assert!(hypervisor.handle_exited_instances().await.is_empty());
+        let info = hypervisor.get("api", "prod").await.unwrap();
+        assert_eq!(info.restarts, 1);
+        assert_eq!(info.exit_code, None);
+
+        hypervisor.stop("api", "prod").await.ok();
+    }
+
+    // Touches the socket, then runs until signalled; `on_term` is the TERM trap
+    fn create_trap_script(dir: &Path, on_term: &str) -> PathBuf {
+        let script_path = dir.join("trap.sh");
+        let script = format!(
+            r#"#!/bin/bash
+SOCKET_PATH="${{SOCKET_PATH:-/tmp/test.sock}}"
+rm -f "$SOCKET_PATH"
+touch "$SOCKET_PATH"
+trap '{}' TERM
+while true; do sleep 0.1; done
+"#,
+            on_term
+        );
+        std::fs::write(&script_path, script).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        script_path
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_all_sends_sigterm_first() {
+        let dir = TempDir::new().unwrap();
+        let marker = dir.path().join("terminated");
+        let script = create_trap_script(dir.path(), &format!("touch {}; exit 0", marker.display()));
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+        hypervisor.spawn("api", "prod").await.unwrap();
+
+        hypervisor.shutdown_all().await;
+
+        assert!(marker.exists(), "process should have handled SIGTERM");
+        assert!(hypervisor.list().await.is_empty());
+        assert!(hypervisor.is_shutting_down());
+        assert!(hypervisor.spawn("api", "prod").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_all_kills_after_grace_period() {
+        let dir = TempDir::new().unwrap();
+        let script = create_trap_script(dir.path(), "");
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        config.service.get_mut("api").unwrap().stop_grace_period = 1;
+        let hypervisor = Hypervisor::new(config);
+        hypervisor.spawn("api", "prod").await.unwrap();
+        let pid = hypervisor.instances.read().await[&InstanceId::new("api", "prod")]
+            .handle
+            .pid()
+            .unwrap();
+
+        let started = Instant::now();
+        hypervisor.shutdown_all().await;
+
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        assert!(hypervisor.list().await.is_empty());
+        assert_ne!(unsafe { libc::kill(pid as i32, 0) }, 0);
+    }
+
+    // ===================
+    // DEPLOY COMMAND TESTS
+    // ===================
+
+    #[tokio::test]
+    async fn test_deploy_and_wait_healthy_no_health_endpoint() {
+        // When no health endpoint is configured, instance is healthy if socket exists
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // Deploy v2 with default weight
+        let result = hypervisor
+            .deploy_and_wait_healthy("api", "v2", 100, 5)
+            .await;
+        assert!(result.is_ok(), "Deploy should succeed: {:?}", result.err());
+
+        // Verify instance is running with correct weight
+        let info = hypervisor.get("api", "v2").await.unwrap();
+        assert_eq!(info.weight, 100);
+
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_deploy_with_initial_weight() {
+        // Deploy with a low initial weight (canary style)
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // Deploy v2 with 10% weight
+        let result = hypervisor.deploy_and_wait_healthy("api", "v2", 10, 5).await;
+        assert!(result.is_ok());
+
+        // Verify weight was set
+        let info = hypervisor.get("api", "v2").await.unwrap();
+        assert_eq!(info.weight, 10);
+
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_deploy_unknown_process() {
+        let config = Config::default();
+        let hypervisor = Hypervisor::new(config);
+
+        let result = hypervisor
+            .deploy_and_wait_healthy("nonexistent", "v1", 100, 5)
+            .await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Unknown process"));
+    }
+
+    // ===================
+    // ROUTE SWAP TESTS
+    // ===================
+
+    #[tokio::test]
+    async fn test_route_swap() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // Spawn v1 and v2
+        hypervisor.spawn("api", "v1").await.unwrap();
+        hypervisor.spawn("api", "v2").await.unwrap();
+
+        // Initially both have weight 100
+        let v1_info = hypervisor.get("api", "v1").await.unwrap();
+        let v2_info = hypervisor.get("api", "v2").await.unwrap();
+        assert_eq!(v1_info.weight, 100);
+        assert_eq!(v2_info.weight, 100);
+
+        // Swap traffic from v1 to v2
+        let result = hypervisor.route_swap("api", "v1", "v2").await;
+        assert!(result.is_ok());
+
+        // Verify weights swapped atomically
+        let v1_info = hypervisor.get("api", "v1").await.unwrap();
+        let v2_info = hypervisor.get("api", "v2").await.unwrap();
+        assert_eq!(v1_info.weight, 0);
+        assert_eq!(v2_info.weight, 100);
+
+        hypervisor.stop("api", "v1").await.ok();
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_route_swap_from_not_found() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // Only spawn v2
+        hypervisor.spawn("api", "v2").await.unwrap();
+
+        // Try to swap from non-existent v1
+        let result = hypervisor.route_swap("api", "v1", "v2").await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("not found"));
+
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_route_swap_to_not_found() {
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // Only spawn v1
+        hypervisor.spawn("api", "v1").await.unwrap();
+
+        // Try to swap to non-existent v2
+        let result = hypervisor.route_swap("api", "v1", "v2").await;
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("not found"));
+
+        hypervisor.stop("api", "v1").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_route_swap_weighted_routing() {
+        // Verify that after route swap, only the target version receives traffic
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        hypervisor.spawn("api", "v1").await.unwrap();
+        hypervisor.spawn("api", "v2").await.unwrap();
+
+        // Route all traffic from v1 to v2
+        hypervisor.route_swap("api", "v1", "v2").await.unwrap();
+
+        // All weighted selections should now go to v2 (v1 has weight 0)
+        for _ in 0..10 {
+            let selected = hypervisor.select_weighted("api").await;
+            assert!(selected.is_some());
+            assert_eq!(selected.unwrap().id.id, "v2");
+        }
+
+        hypervisor.stop("api", "v1").await.ok();
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_blue_green_workflow() {
+        // Full blue/green deployment workflow
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // 1. Deploy v1 (blue) initially
+        hypervisor
+            .deploy_and_wait_healthy("api", "v1", 100, 5)
+            .await
+            .unwrap();
+
+        // 2. Deploy v2 (green) with weight 0 (no traffic yet)
+        hypervisor
+            .deploy_and_wait_healthy("api", "v2", 0, 5)
+            .await
+            .unwrap();
+
+        // At this point, all traffic goes to v1
+        for _ in 0..5 {
+            let selected = hypervisor.select_weighted("api").await;
+            assert_eq!(selected.unwrap().id.id, "v1");
+        }
+
+        // 3. Atomic swap: route all traffic from v1 to v2
+        hypervisor.route_swap("api", "v1", "v2").await.unwrap();
+
+        // Now all traffic goes to v2
+        for _ in 0..5 {
+            let selected = hypervisor.select_weighted("api").await;
+            assert_eq!(selected.unwrap().id.id, "v2");
+        }
+
+        // 4. Stop old version v1
+        hypervisor.stop("api", "v1").await.unwrap();
+
+        // Only v2 remains
+        let instances = hypervisor.list_by_process("api").await;
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].id.id, "v2");
+
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    #[tokio::test]
+    async fn test_canary_workflow() {
+        // Full canary deployment workflow
+        let dir = TempDir::new().unwrap();
+        let script = create_touch_socket_script(dir.path());
+
+        let config = test_config_with_process("api", script.to_str().unwrap(), vec![]);
+        let hypervisor = Hypervisor::new(config);
+
+        // 1. v1 is running with full traffic
+        hypervisor
+            .deploy_and_wait_healthy("api", "v1", 100, 5)
+            .await
+            .unwrap();
+
+        // 2. Deploy v2 with 10% traffic (canary)
+        hypervisor
+            .deploy_and_wait_healthy("api", "v2", 10, 5)
+            .await
+            .unwrap();
+
+        // Traffic split is now roughly 91% v1, 9% v2 (100:10 ratio)
+        let v1_info = hypervisor.get("api", "v1").await.unwrap();
+        let v2_info = hypervisor.get("api", "v2").await.unwrap();
+        assert_eq!(v1_info.weight, 100);
+        assert_eq!(v2_info.weight, 10);
+
+        // 3. Increase v2 weight to 50%
+        hypervisor.set_weight("api", "v2", 50).await.unwrap();
+
+        // 4. Full rollout: set v1=0, v2=100
+        hypervisor.set_weight("api", "v1", 0).await.unwrap();
+        hypervisor.set_weight("api", "v2", 100).await.unwrap();
+
+        // All traffic now goes to v2
+        for _ in 0..5 {
+            let selected = hypervisor.select_weighted("api").await;
+            assert_eq!(selected.unwrap().id.id, "v2");
+        }
+
+        // 5. Stop v1
+        hypervisor.stop("api", "v1").await.ok();
+        hypervisor.stop("api", "v2").await.ok();
+    }
+
+    // ===================
+    // DATABASE PROVISIONING TESTS
+    // ===================
+
+    #[tokio::test]
+    async fn test_database_provisioned_once_and_injected() {
+        let dir = TempDir::new().unwrap();
+        let script = dir.path().join("record_env.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/bash\necho \"$DATABASE_URL\" > {}/env-$1\ntouch \"$SOCKET_PATH\"\nsleep 30\n",
+                dir.path().display()
+            ),
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let mut config = test_config_with_process("api", script.to_str().unwrap(), vec!["{id}"]);
+        config.service.get_mut("api").unwrap().database = Some(crate::database::DatabaseConfig {
+            kind: crate::database::DatabaseKind::Command,
+            admin_url: None,
+            command: Some(format!(
+                "echo x >> {}/hook-runs; echo postgres://{{id}}@db/$TENEMENT_DATABASE",
+                dir.path().display()
+            )),
+            name: "{name}_{id}".to_string(),
+            env: "DATABASE_URL".to_string(),
+        });
+
+        let pool = crate::store::init_db(&dir.path().join("state.db"))
+            .await
+            .unwrap();
+        let secrets = Arc::new(crate::store::TenantSecretStore::new(pool));
+        let hypervisor = Hypervisor::new(config).with_secrets(secrets.clone());
+
+        hypervisor.spawn("api", "alice").await.unwrap();
+        hypervisor.stop("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "alice").await.unwrap();
+
+        let env_file = dir.path().join("env-alice");
+        for _ in 0..100 {
+            if env_file.exists() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(
+            std::fs::read_to_string(&env_file).unwrap().trim(),
+            "postgres://alice@db/api_alice"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("hook-runs"))
+                .unwrap()
+                .lines()
+                .count(),
+            1
+        );
+        assert_eq!(
+            secrets
+                .get("alice", "db:api_alice")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("postgres://alice@db/api_alice")
+        );
+
+        // "Alice" maps to alice's database name, which stays hers
+        let err = hypervisor.spawn("api", "Alice").await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("already belongs to tenant 'alice'"),
+            "{:#}",
+            err
+        );
+
+        hypervisor.stop("api", "alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dns_records_follow_instance_lifetime() {
+        let dir = TempDir::new().unwrap();
+        let log = dir.path().join("dns.log");
+        let (mut config, _dir) = harness_config("idle_timeout = 60\n");
+        config.dns = Some(crate::dns::DnsConfig {
+            provider: crate::dns::DnsProviderKind::Command,
+            domain: "example.com".to_string(),
+            target: None,
+            zone: None,
+            ttl: 300,
+            token_env: "CLOUDFLARE_API_TOKEN".to_string(),
+            register: Some(format!("echo \"+$TENEMENT_DNS_NAME\" >> {}", log.display())),
+            unregister: Some(format!("echo \"-$TENEMENT_DNS_NAME\" >> {}", log.display())),
+        });
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        let read_log = || std::fs::read_to_string(&log).unwrap_or_default();
+        let wait_for = |expected: &'static str| {
+            let read_log = &read_log;
+            async move {
+                for _ in 0..100 {
+                    if read_log() == expected {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                panic!("DNS log {:?}, expected {:?}", read_log(), expected);
+            }
+        };
+
+        hypervisor.spawn("api", "alice").await.unwrap();
+        wait_for("+alice.api.example.com\n").await;
+
+        // Restarts and idle stops keep the record (wake-on-request needs it)
+        hypervisor.restart("api", "alice").await.unwrap();
+        harness.tick(Duration::from_secs(120)).await;
+        assert!(hypervisor.get("api", "alice").await.is_none());
+        hypervisor.spawn("api", "alice").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(read_log(), "+alice.api.example.com\n");
+
+        // Stopping through the API removes it
+        hypervisor.stop("api", "alice").await.unwrap();
+        wait_for("+alice.api.example.com\n-alice.api.example.com\n").await;
+    }
+
+    #[tokio::test]
+    async fn test_database_requires_secret_store() {
+        let mut config = test_config_with_process("api", "sleep", vec!["30"]);
+        config.service.get_mut("api").unwrap().database = Some(crate::database::DatabaseConfig {
+            kind: crate::database::DatabaseKind::Command,
+            admin_url: None,
+            command: Some("echo postgres://db/x".to_string()),
+            name: "{name}_{id}".to_string(),
+            env: "DATABASE_URL".to_string(),
+        });
+        let hypervisor = Hypervisor::new(config);
+
+        let err = hypervisor.spawn("api", "alice").await.unwrap_err();
+        assert!(err.to_string().contains("secret store"));
+        // The spawning guard was released
+        assert!(!hypervisor
+            .spawning
+            .read()
+            .await
+            .contains(&InstanceId::new("api", "alice")));
+    }
+
+    #[tokio::test]
+    async fn test_create_refuses_existing_instances() {
+        let hypervisor = Hypervisor::new(test_config_with_process("api", "sleep", vec!["30"]));
+        let alice = InstanceId::new("api", "alice");
+
+        // Being spawned by someone else
+        hypervisor.spawning.write().await.insert(alice.clone());
+        let err = hypervisor
+            .create("api", "alice", SpawnOptions::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<InstanceExists>(),
+            Some(&InstanceExists(alice.clone()))
+        );
+        // A plain spawn answers with the socket
+        assert!(hypervisor.spawn("api", "alice").await.is_ok());
+        hypervisor.spawning.write().await.remove(&alice);
+
+        hypervisor
+            .create("api", "alice", SpawnOptions::default())
+            .await
+            .unwrap();
+        let err = hypervisor
+            .create("api", "alice", SpawnOptions::default())
+            .await
+            .unwrap_err();
+        assert!(err.is::<InstanceExists>());
+        hypervisor.stop("api", "alice").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_spawn_releases_guard_and_ports() {
+        let config = test_config_with_process("api", "sleep '30", vec![]);
+        let hypervisor = Hypervisor::new(config);
+        let guarded = |hypervisor: &Hypervisor| {
+            hypervisor
+                .spawning
+                .try_read()
+                .unwrap()
+                .contains(&InstanceId::new("api", "alice"))
+        };
+
+        // Fails after its port is allocated
+        let err = hypervisor.spawn("api", "alice").await.unwrap_err();
+        assert!(
+            err.to_string().contains("Failed to parse command"),
+            "{}",
+            err
+        );
+        assert!(!guarded(&hypervisor));
+        assert_eq!(hypervisor.port_allocator.allocated_count().await, 0);
+
+        // Fails on an unavailable runtime, before allocating one
+        let mut config = (*hypervisor.config()).clone();
+        config.service.get_mut("api").unwrap().isolation = RuntimeType::Firecracker;
+        let hypervisor = Hypervisor::new(config);
+        assert!(hypervisor.spawn("api", "alice").await.is_err());
+        assert!(!guarded(&hypervisor));
+    }
+
+    // ===================
+    // MOCK RUNTIME / FAKE CLOCK TESTS
+    // ===================
+
+    /// Config for the mock runtime harness, with a data dir that is removed
+    /// when the returned `TempDir` drops
+    fn harness_config(extra: &str) -> (Config, TempDir) {
+        let mut config = Config::from_str(&format!(
+            "[service.api]\ncommand = \"./api\"\nisolation = \"process\"\nhealth = \"/health\"\n{}",
+            extra
+        ))
+        .unwrap();
+        let dir = TempDir::new().unwrap();
+        config.settings.data_dir = dir.path().to_path_buf();
+        // Mock processes listen on SOCKET_PATH; keep parallel tests apart
+        let api = config.service.get_mut("api").unwrap();
+        if api.socket == ProcessConfig::default().socket {
+            api.socket = format!(
+                "{}/{{name}}-{{id}}.sock",
+                config.settings.data_dir.display()
+            );
+        }
+        (config, dir)
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_wakes_share_one_spawn() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let wakes: Vec<_> = (0..20)
+            .map(|_| {
+                let hypervisor = harness.hypervisor.clone();
+                tokio::spawn(async move { hypervisor.spawn_and_wait("api", "alice").await })
+            })
+            .collect();
+        for wake in wakes {
+            wake.await.unwrap().unwrap();
+        }
+        assert_eq!(harness.runtime.spawn_count(), 1);
+        assert!(harness.hypervisor.waking.read().await.is_empty());
+
+        // A failed wake fails every request waiting on it, and the next
+        // request tries again
+        harness.hypervisor.stop("api", "alice").await.unwrap();
+        harness.runtime.fail_next_spawn("no capacity");
+        let wakes: Vec<_> = (0..5)
+            .map(|_| {
+                let hypervisor = harness.hypervisor.clone();
+                tokio::spawn(async move { hypervisor.spawn_and_wait("api", "alice").await })
+            })
+            .collect();
+        for wake in wakes {
+            let err = wake.await.unwrap().unwrap_err();
+            assert!(format!("{:#}", err).contains("no capacity"), "got: {err:#}");
+        }
+        harness
+            .hypervisor
+            .spawn_and_wait("api", "alice")
+            .await
+            .unwrap();
+        assert_eq!(harness.runtime.spawn_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wake_queue_timeout() {
+        let mut config = test_config_with_process("api", "sleep", vec!["30"]);
+        let api = config.service.get_mut("api").unwrap();
+        api.startup_timeout = 3;
+        api.wake_queue_timeout = Some(1);
+        let hypervisor = Hypervisor::new(config);
+
+        let start = std::time::Instant::now();
+        let err = hypervisor.spawn_and_wait("api", "alice").await.unwrap_err();
+        assert!(err.to_string().contains("Gave up after 1s"), "got: {err:#}");
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        // The wake itself carries on in the background
+        tokio::time::sleep(Duration::from_secs(3)).await;
+        hypervisor.stop_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_harness_spawns_mock_instances() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        assert_eq!(harness.runtime.spawn_count(), 1);
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+        assert_eq!(
+            harness.hypervisor.check_health("api", "alice").await,
+            HealthStatus::Healthy
+        );
+
+        let process = harness.process("api", "alice").await.unwrap();
+        process.set_healthy(false);
+        assert_ne!(
+            harness.hypervisor.check_health("api", "alice").await,
+            HealthStatus::Healthy
+        );
+
+        harness.hypervisor.stop("api", "alice").await.unwrap();
+        assert!(!process.is_running());
+    }
+
+    #[tokio::test]
+    async fn test_ports_from_configured_range() {
+        let (mut config, _dir) = harness_config("");
+        config.settings.port_range = [45200, 45201];
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        hypervisor.spawn("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "bob").await.unwrap();
+
+        let alice = hypervisor.get("api", "alice").await.unwrap();
+        let bob = hypervisor.get("api", "bob").await.unwrap();
+        assert_eq!(alice.port, Some(45200));
+        assert_eq!(bob.port, Some(45201));
+        let err = hypervisor.spawn("api", "carol").await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("45200-45201"),
+            "got: {:#}",
+            err
+        );
+
+        hypervisor.stop("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "carol").await.unwrap();
+        let carol = hypervisor.get("api", "carol").await.unwrap();
+        assert_eq!(carol.port, Some(45200));
+    }
+
+    #[tokio::test]
+    async fn test_fixed_port() {
+        let (config, _dir) = harness_config("port = 45300");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        hypervisor.spawn("api", "alice").await.unwrap();
+        let alice = hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(alice.port, Some(45300));
+
+        let err = hypervisor.spawn("api", "bob").await.unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("already held"),
+            "got: {:#}",
+            err
+        );
+
+        hypervisor.stop("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "bob").await.unwrap();
+        let bob = hypervisor.get("api", "bob").await.unwrap();
+        assert_eq!(bob.port, Some(45300));
+    }
+
+    #[tokio::test]
+    async fn test_named_listeners() {
+        let (mut config, _dir) = harness_config(
+            "[service.api.listeners.grpc]\npaths = [\"/grpc\"]\n\
+             [service.api.listeners.internal]\nkind = \"socket\"\n",
+        );
+        config.settings.port_range = [45400, 45401];
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        hypervisor.spawn("api", "alice").await.unwrap();
+
+        let info = hypervisor.get("api", "alice").await.unwrap();
+        let names: Vec<&str> = info.listeners.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["main", "grpc", "internal"]);
+        assert_eq!(info.listeners[0].addr.port(), Some(45400));
+        assert_eq!(info.listeners[1].addr.port(), Some(45401));
+        assert_eq!(
+            info.listeners[2].addr,
+            ListenAddr::Socket(info.socket.clone())
+        );
+        let process = harness.process("api", "alice").await.unwrap();
+        assert_eq!(process.env()["PORT_GRPC"], "45401");
+        assert_eq!(hypervisor.listener_for("api", "/grpc/Say"), "grpc");
+        assert_eq!(hypervisor.listener_for("api", "/"), "main");
+
+        // Both ports are in use until the instance stops
+        let err = hypervisor.spawn("api", "bob").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("45400-45401"), "{:#}", err);
+        hypervisor.stop("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "bob").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fake_clock_drives_idle_reaping() {
+        let (config, _dir) = harness_config("idle_timeout = 60");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        harness.clock.advance(Duration::from_secs(30));
+        harness.hypervisor.reap_idle_instances().await;
+        assert!(harness.hypervisor.is_running("api", "alice").await);
+
+        harness.clock.advance(Duration::from_secs(31));
+        harness.hypervisor.reap_idle_instances().await;
+        assert!(!harness.hypervisor.is_running("api", "alice").await);
+    }
+
+    #[tokio::test]
+    async fn test_max_lifetime_recycles_staggered() {
+        let (config, _dir) = harness_config("max_lifetime = \"1h\"");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "a").await.unwrap();
+        harness.hypervisor.spawn("api", "b").await.unwrap();
+        harness.hypervisor.spawn("api", "c").await.unwrap();
+
+        // Offsets stay within a tenth of the lifetime
+        for id in ["a", "b", "c"] {
+            assert!(lifetime_offset(&InstanceId::new("api", id), 3600) <= 360);
+        }
+
+        harness.clock.advance(Duration::from_secs(3599));
+        assert!(harness
+            .hypervisor
+            .recycle_expired_instances()
+            .await
+            .is_empty());
+
+        // All three are past their deadline, but only one goes per pass
+        harness.clock.advance(Duration::from_secs(362));
+        let mut recycled = Vec::new();
+        for _ in 0..3 {
+            let pass = harness.hypervisor.recycle_expired_instances().await;
+            assert_eq!(pass.len(), 1);
+            recycled.extend(pass);
+        }
+        recycled.sort_by(|a, b| a.id.cmp(&b.id));
+        let ids: Vec<&str> = recycled.iter().map(|i| i.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b", "c"]);
+        assert_eq!(harness.runtime.spawn_count(), 6);
+
+        // Fresh instances aren't due again, and recycling isn't a restart
+        assert!(harness
+            .hypervisor
+            .recycle_expired_instances()
+            .await
+            .is_empty());
+        let info = harness.hypervisor.get("api", "a").await.unwrap();
+        assert_eq!(info.restarts, 0);
+        assert!(harness.hypervisor.is_running("api", "a").await);
+    }
+
+    #[tokio::test]
+    async fn test_env_file_under_env() {
+        let (mut config, _dir) = harness_config("env = { MODE = \"explicit\" }");
+        let env_file = config.settings.data_dir.join("api.env");
+        std::fs::create_dir_all(&config.settings.data_dir).unwrap();
+        std::fs::write(&env_file, "MODE=file\nDB={data_dir}/{id}.db\n").unwrap();
+        config.service.get_mut("api").unwrap().env_file = Some(env_file.clone());
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        let data_dir = harness.hypervisor.config().settings.data_dir.clone();
+        let env = harness.runtime.processes()[0].config().env.clone();
+        assert_eq!(env["MODE"], "explicit");
+        assert_eq!(env["DB"], format!("{}/alice.db", data_dir.display()));
+
+        // A missing file fails the spawn instead of starting without it
+        std::fs::remove_file(&env_file).unwrap();
+        let err = harness.hypervisor.spawn("api", "bob").await.unwrap_err();
+        assert!(err.to_string().contains("env_file"), "got: {}", err);
+        assert_eq!(harness.runtime.spawn_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_secret_interpolation() {
+        let (mut config, _dir) = harness_config("");
+        config.service.get_mut("api").unwrap().env.insert(
+            "DB".to_string(),
+            "postgres://{id}:{secret:db_{id}}@db".to_string(),
+        );
+        let secrets = config.settings.data_dir.join("secrets.env");
+        std::fs::create_dir_all(&config.settings.data_dir).unwrap();
+        std::fs::write(&secrets, "db_alice=one\nADMIN_PASS=hunter2\n").unwrap();
+        config.settings.secrets_cmd = Some(format!("cat {}", secrets.display()));
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+
+        // An ID can't pull in another secret through `{id}`
+        let err = harness
+            .hypervisor
+            .spawn("api", "{secret:ADMIN_PASS}")
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("Invalid instance ID"),
+            "got: {}",
+            err
+        );
+        assert_eq!(harness.runtime.spawn_count(), 0);
+
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        let env = harness.runtime.processes()[0].config().env.clone();
+        assert_eq!(env["DB"], "postgres://alice:one@db");
+
+        // Secrets are cached until a reload
+        std::fs::write(&secrets, "db_alice=one\ndb_bob=two\n").unwrap();
+        let err = harness.hypervisor.spawn("api", "bob").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("db_bob"), "got: {:#}", err);
+        assert_eq!(harness.runtime.spawn_count(), 1);
+
+        let config = (*harness.hypervisor.config()).clone();
+        harness.hypervisor.reload(config).await.unwrap();
+        harness.hypervisor.spawn("api", "bob").await.unwrap();
+        let env = harness.runtime.processes()[1].config().env.clone();
+        assert_eq!(env["DB"], "postgres://bob:two@db");
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "a").await.unwrap();
+        harness.hypervisor.spawn("api", "b").await.unwrap();
+
+        let running = harness.hypervisor.config();
+        let mut config = (*running).clone();
+        let api = config.service.get_mut("api").unwrap();
+        api.env.insert("MODE".to_string(), "v2".to_string());
+        let mut worker = api.clone();
+        worker.command = "./worker".to_string();
+        config.service.insert("worker".to_string(), worker);
+        config
+            .instances
+            .insert("worker".to_string(), vec!["w1".to_string()]);
+        config.settings.health_check_interval += 1;
+
+        let report = harness.hypervisor.reload(config).await.unwrap();
+        assert_eq!(report.diff.services_added, ["worker"]);
+        assert_eq!(report.restarting, ["api:a", "api:b"]);
+        assert_eq!(report.spawned, ["worker:w1"]);
+        assert_eq!(report.needs_restart, ["settings"]);
+        assert!(harness.hypervisor.is_running("worker", "w1").await);
+        assert_eq!(
+            harness.hypervisor.config().settings.health_check_interval,
+            running.settings.health_check_interval
+        );
+
+        // Flagged instances are restarted one per pass on the new definition
+        assert_eq!(
+            harness.hypervisor.recycle_expired_instances().await.len(),
+            1
+        );
+        assert_eq!(
+            harness.hypervisor.recycle_expired_instances().await.len(),
+            1
+        );
+        assert!(harness
+            .hypervisor
+            .recycle_expired_instances()
+            .await
+            .is_empty());
+        let processes = harness.runtime.processes();
+        assert_eq!(processes.len(), 5);
+        assert!(processes[3..]
+            .iter()
+            .all(|p| p.config().env.get("MODE").map(String::as_str) == Some("v2")));
+
+        // Removing a service stops its instances
+        let mut config = (*harness.hypervisor.config()).clone();
+        config.service.remove("worker");
+        config.instances.clear();
+        let report = harness.hypervisor.reload(config).await.unwrap();
+        assert_eq!(report.stopped, ["worker:w1"]);
+        assert!(report.restarting.is_empty());
+        assert!(!harness.hypervisor.is_running("worker", "w1").await);
+        assert!(!harness.hypervisor.has_process("worker"));
+
+        // data_dir can't move under running instances: like other settings
+        // it waits for a restart
+        let running = harness.hypervisor.config().settings.data_dir.clone();
+        let mut config = (*harness.hypervisor.config()).clone();
+        config.settings.data_dir = config.settings.data_dir.join("moved");
+        let report = harness.hypervisor.reload(config).await.unwrap();
+        assert_eq!(report.needs_restart, ["settings"]);
+        assert_eq!(harness.hypervisor.config().settings.data_dir, running);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_freeze_holds_recycling_but_not_crash_restarts() {
+        let (config, _dir) = harness_config(
+            "max_lifetime = \"1h\"\n\
+             [service.api.maintenance]\n\
+             mode = \"deny\"\n\
+             windows = [\"daily 00:00-24:00\"]\n\
+             instances = { b = [] }\n",
+        );
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "a").await.unwrap();
+        harness.hypervisor.spawn("api", "b").await.unwrap();
+
+        let reason = harness
+            .hypervisor
+            .maintenance_blocked("api", Some("a"))
+            .unwrap();
+        assert!(
+            reason.contains("api:a") && reason.contains("freeze"),
+            "got: {reason}"
+        );
+        assert!(harness
+            .hypervisor
+            .maintenance_blocked("api", Some("b"))
+            .is_none());
+        assert!(harness
+            .hypervisor
+            .maintenance_blocked("web", None)
+            .is_none());
+
+        // Only b is outside the freeze
+        harness.clock.advance(Duration::from_secs(4000));
+        let recycled = harness.hypervisor.recycle_expired_instances().await;
+        assert_eq!(recycled, vec![InstanceId::new("api", "b")]);
+        assert!(harness
+            .hypervisor
+            .recycle_expired_instances()
+            .await
+            .is_empty());
+
+        // Crashes still restart inside the freeze
+        harness.process("api", "a").await.unwrap().exit(1);
+        harness.hypervisor.handle_exited_instances().await;
+        assert!(harness.hypervisor.is_running("api", "a").await);
+        assert_eq!(
+            harness.hypervisor.get("api", "a").await.unwrap().restarts,
+            1
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_oom_kill_is_reported() {
+        let cgroups = TempDir::new().unwrap();
+        let stores = crate::testing::MemoryStores::new().await.unwrap();
+        let runtime = Arc::new(crate::testing::MockRuntime::new());
+        let (mut config, _dir) = harness_config("memory_limit_mb = 64");
+        config.settings.backoff_base_ms = 0;
+        let mut hypervisor = Hypervisor::with_state_store(config, stores.state.clone())
+            .with_tenant_keys(stores.tenant_keys.clone());
+        Arc::get_mut(&mut hypervisor).unwrap().cgroup_manager =
+            CgroupManager::with_base_path(cgroups.path().to_path_buf());
+        let hypervisor = hypervisor.with_runtime(runtime.clone());
+        hypervisor.spawn("api", "alice").await.unwrap();
+
+        let cgroup = cgroups.path().join("api:alice");
+        std::fs::create_dir(&cgroup).unwrap();
+        std::fs::write(cgroup.join("memory.events"), "oom 1\noom_kill 1\n").unwrap();
+        let info = hypervisor.get("api", "alice").await.unwrap();
+        runtime.process(&info.socket).unwrap().exit(137);
+        hypervisor.handle_exited_instances().await;
+        // Counted once, even though the restarted instance finds it again
+        hypervisor.handle_exited_instances().await;
+
+        let logs = hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery::default())
+            .await;
+        let oom: Vec<_> = logs
+            .iter()
+            .filter(|l| l.message.contains("OOM-killed"))
+            .collect();
+        assert_eq!(oom.len(), 1);
+        assert!(oom[0].message.contains("(64 MB)"));
+        let metrics = hypervisor.metrics().format_prometheus().await;
+        assert!(
+            metrics.contains("tenement_instance_oom_kills_total{id=\"alice\",process=\"api\"} 1")
+        );
+        assert_eq!(hypervisor.get("api", "alice").await.unwrap().restarts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_update_limits() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let memory = ResourceLimits {
+            memory_limit_mb: Some(512),
+            ..Default::default()
+        };
+        let err = harness
+            .hypervisor
+            .update_limits("api", "alice", memory.clone())
+            .await;
+        assert!(err.unwrap_err().to_string().contains("not found"));
+
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        let err = harness
+            .hypervisor
+            .update_limits("api", "alice", ResourceLimits::default())
+            .await;
+        assert!(err.unwrap_err().to_string().contains("No limits"));
+        // The mock runtime stands in for the real ones, so no cgroups
+        let err = harness
+            .hypervisor
+            .update_limits("api", "alice", memory)
+            .await;
+        assert!(err.unwrap_err().to_string().contains("don't manage"));
+        assert!(harness.hypervisor.is_running("api", "alice").await);
+    }
+
+    #[tokio::test]
+    async fn test_fake_clock_drives_restart_backoff() {
+        let (mut config, _dir) = harness_config("");
+        config.settings.backoff_base_ms = 5_000;
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        // First restart has no backoff
+        harness.process("api", "alice").await.unwrap().exit(1);
+        harness.hypervisor.handle_exited_instances().await;
+        assert_eq!(harness.runtime.spawn_count(), 2);
+
+        // Second one waits 5s of clock time
+        harness.process("api", "alice").await.unwrap().exit(1);
+        let hypervisor = harness.hypervisor.clone();
+        let restart = tokio::spawn(async move { hypervisor.handle_exited_instances().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!restart.is_finished());
+        assert_eq!(harness.runtime.spawn_count(), 2);
+
+        harness.clock.advance(Duration::from_secs(5));
+        tokio::time::timeout(Duration::from_secs(5), restart)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(harness.runtime.spawn_count(), 3);
+        assert!(harness.hypervisor.is_running("api", "alice").await);
+    }
+
+    #[tokio::test]
+    async fn test_virtual_time_uptime_and_idle() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        harness.tick(Duration::from_secs(90)).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.uptime_secs, 90);
+        assert_eq!(info.idle_secs, 90);
+
+        harness.hypervisor.touch_activity("api", "alice").await;
+        harness.tick(Duration::from_secs(5)).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.uptime_secs, 95);
+        assert_eq!(info.idle_secs, 5);
+    }
+
+    #[tokio::test]
+    async fn test_tick_restarts_until_max_restarts() {
+        let (mut config, _dir) = harness_config("");
+        config.settings.backoff_base_ms = 0;
+        config.settings.max_restarts = 2;
+        config.settings.restart_window = 60;
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        for expected_spawns in [2, 3] {
+            harness.process("api", "alice").await.unwrap().exit(1);
+            harness.tick(Duration::from_secs(10)).await;
+            assert_eq!(harness.runtime.spawn_count(), expected_spawns);
+        }
+
+        // Two restarts inside the window: the third crash keeps it down
+        harness.process("api", "alice").await.unwrap().exit(1);
+        harness.tick(Duration::from_secs(10)).await;
+        assert_eq!(harness.runtime.spawn_count(), 3);
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.exit_code, Some(1));
+
+        // Once the window has passed, a manual restart is allowed to crash
+        // and be restarted again
+        harness.hypervisor.restart("api", "alice").await.unwrap();
+        harness.clock.advance(Duration::from_secs(61));
+        harness.process("api", "alice").await.unwrap().exit(1);
+        harness.tick(Duration::from_secs(1)).await;
+        assert_eq!(harness.runtime.spawn_count(), 5);
+        assert!(harness
+            .hypervisor
+            .get("api", "alice")
+            .await
+            .unwrap()
+            .exit_code
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_rejects_path_like_ids() {
+        let (config, _dir) = harness_config("workdir = \"{data_dir}/{name}/{id}\"");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let err = harness
+            .hypervisor
+            .spawn("api", "../escape")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid instance ID"));
+        assert_eq!(harness.runtime.spawn_count(), 0);
+
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        let data_dir = harness.hypervisor.config().settings.data_dir.clone();
+        assert_eq!(
+            harness.runtime.processes()[0].config().workdir,
+            Some(data_dir.join("api").join("alice"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_health_cmd_exit_code_drives_health() {
+        let mut config = Config::from_str(
+            r#"
+[service.api]
+command = "./api"
+isolation = "process"
+health_cmd = "test -e {data_dir}/{name}/{id}/ok && test -n \"$PORT\""
+readiness_timeout = 0
+"#,
+        )
+        .unwrap();
+        let dir = TempDir::new().unwrap();
+        config.settings.data_dir = dir.path().to_path_buf();
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+
+        // Not ready until the command succeeds
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.status, crate::instance::InstanceStatus::Starting);
+        harness.tick(Duration::from_secs(10)).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.health, HealthStatus::Degraded);
+
+        let data_dir = harness.hypervisor.config().settings.data_dir.clone();
+        std::fs::write(data_dir.join("api").join("alice").join("ok"), "").unwrap();
+        harness.tick(Duration::from_secs(10)).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.health, HealthStatus::Healthy);
+        assert_eq!(info.status, crate::instance::InstanceStatus::Running);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_clears_stale_socket_but_not_live_one() {
+        let dir = TempDir::new().unwrap();
+        let (config, _dir) = harness_config(&format!(
+            "socket = \"{}/{{name}}-{{id}}.sock\"",
+            dir.path().display()
+        ));
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+
+        // Left behind by a crash: nothing listens on it
+        let stale = dir.path().join("api-alice.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        // Replaced by the new instance's live socket
+        assert!(std::os::unix::net::UnixStream::connect(&stale).is_ok());
+        let logs = harness
+            .hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery::default())
+            .await;
+        assert!(logs
+            .iter()
+            .any(|e| e.message.starts_with("[tenement] removed stale socket")));
+
+        // Someone else is serving on it
+        let live = dir.path().join("api-bob.sock");
+        let _listener = tokio::net::UnixListener::bind(&live).unwrap();
+        let err = harness.hypervisor.spawn("api", "bob").await.unwrap_err();
+        assert!(format!("{:#}", err).contains("in use"), "{:#}", err);
+        assert!(live.exists());
+        assert!(!harness.hypervisor.is_running("api", "bob").await);
+    }
+
+    /// Same lifecycle over both transports: with and without Unix sockets
+    #[tokio::test]
+    async fn test_tcp_only_parity() {
+        for tcp_only in [false, true] {
+            // No `health` endpoint: liveness is the socket or the port
+            let mut config = Config::from_str(
+                "[service.api]\ncommand = \"./api\"\nisolation = \"process\"\nidle_timeout = 60\n",
+            )
+            .unwrap();
+            let dir = TempDir::new().unwrap();
+            let data_dir = dir.path().to_path_buf();
+            config.service.get_mut("api").unwrap().socket =
+                format!("{}/{{name}}-{{id}}.sock", data_dir.display());
+            config.settings.data_dir = data_dir;
+            config.settings.tcp_only = tcp_only;
+            config.settings.backoff_base_ms = 0;
+            let harness = crate::testing::TestHarness::new(config).await.unwrap();
+
+            let socket = harness.hypervisor.spawn("api", "alice").await.unwrap();
+            let process = harness.process("api", "alice").await.unwrap();
+            assert_eq!(process.env().contains_key("SOCKET_PATH"), !tcp_only);
+            assert_eq!(socket.exists(), !tcp_only, "tcp_only = {}", tcp_only);
+            assert!(process.env().contains_key("PORT"));
+
+            // Health checks pass without restarts
+            assert_eq!(
+                harness.hypervisor.check_health("api", "alice").await,
+                HealthStatus::Healthy,
+                "tcp_only = {}",
+                tcp_only
+            );
+            harness.tick(Duration::from_secs(10)).await;
+            assert_eq!(harness.runtime.spawn_count(), 1);
+
+            // A crashed process fails the check and is restarted
+            process.exit(1);
+            assert_eq!(
+                harness.hypervisor.check_health("api", "alice").await,
+                HealthStatus::Unhealthy
+            );
+            harness.tick(Duration::from_secs(1)).await;
+            assert_eq!(harness.runtime.spawn_count(), 2);
+
+            // Activity keeps it awake; idleness reaps it
+            harness.clock.advance(Duration::from_secs(50));
+            harness.hypervisor.touch_activity("api", "alice").await;
+            harness.tick(Duration::from_secs(50)).await;
+            assert!(harness.hypervisor.is_running("api", "alice").await);
+            harness.tick(Duration::from_secs(11)).await;
+            assert!(!harness.hypervisor.is_running("api", "alice").await);
+
+            // Waking it again serves on a port either way
+            harness.hypervisor.spawn("api", "alice").await.unwrap();
+            let port = harness.hypervisor.get("api", "alice").await.unwrap().port;
+            let port = port.unwrap();
+            assert!(tokio::net::TcpStream::connect(("127.0.0.1", port))
+                .await
+                .is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tick_restarts_after_health_failures() {
+        let (mut config, _dir) = harness_config("health_failures_before_restart = 2");
+        config.settings.backoff_base_ms = 0;
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        harness
+            .process("api", "alice")
+            .await
+            .unwrap()
+            .set_healthy(false);
+
+        // The first failure only degrades it
+        harness.tick(Duration::from_secs(10)).await;
+        assert_eq!(harness.runtime.spawn_count(), 1);
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.health, HealthStatus::Degraded);
+
+        harness.tick(Duration::from_secs(10)).await;
+        assert_eq!(harness.runtime.spawn_count(), 2);
+        assert!(!harness.runtime.processes()[0].is_running());
+
+        let logs = harness
+            .hypervisor
+            .log_buffer()
+            .query(&crate::logs::LogQuery {
+                instance_id: Some("alice".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert!(
+            logs.iter()
+                .any(|e| e.message
+                    == "[tenement] restarting after 2 consecutive failed health checks")
+        );
+        let metrics = harness.hypervisor.metrics().format_prometheus().await;
+        assert!(metrics.contains("tenement_health_restarts_total{id=\"alice\",process=\"api\"} 1"));
+    }
+
+    #[tokio::test]
+    async fn test_per_service_health_interval_and_initial_delay() {
+        let (config, _dir) = harness_config(
+            "health_interval = 30\nhealth_initial_delay = 60\nreadiness_timeout = 0",
+        );
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let spawn = {
+            let hypervisor = harness.hypervisor.clone();
+            tokio::spawn(async move { hypervisor.spawn("api", "alice").await })
+        };
+        // Readiness probes wait out the initial delay on the (fake) clock
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!spawn.is_finished());
+        harness
+            .process("api", "alice")
+            .await
+            .unwrap()
+            .set_healthy(false);
+
+        // Still inside the initial delay: not checked
+        harness.tick(Duration::from_secs(50)).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_ne!(info.health, HealthStatus::Degraded);
+
+        harness.clock.advance(Duration::from_secs(10));
+        spawn.await.unwrap().unwrap();
+        harness.tick(Duration::ZERO).await;
+        let info = harness.hypervisor.get("api", "alice").await.unwrap();
+        assert_eq!(info.health, HealthStatus::Degraded);
+
+        // Next check is due 30s later, not at the global 10s
+        harness.tick(Duration::from_secs(10)).await;
+        assert_eq!(
+            harness.hypervisor.instances.read().await[&InstanceId::new("api", "alice")]
+                .consecutive_failures,
+            1
+        );
+        harness.tick(Duration::from_secs(20)).await;
+        assert_eq!(
+            harness.hypervisor.instances.read().await[&InstanceId::new("api", "alice")]
+                .consecutive_failures,
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_monitor_sleeps_on_virtual_time() {
+        let (mut config, _dir) = harness_config("");
+        config.settings.health_check_interval = 3600;
+        config.settings.backoff_base_ms = 0;
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        harness.hypervisor.spawn("api", "alice").await.unwrap();
+        harness.hypervisor.clone().start_monitor();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        harness.process("api", "alice").await.unwrap().exit(1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(harness.runtime.spawn_count(), 1);
+
+        // An hour of virtual time passes instantly
+        harness.clock.advance(Duration::from_secs(3600));
+        for _ in 0..100 {
+            if harness.runtime.spawn_count() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(harness.runtime.spawn_count(), 2);
+        harness.hypervisor.shutdown_all().await;
+    }
+
+    #[tokio::test]
+    async fn test_labels_from_config_and_spawn() {
+        let (config, _dir) =
+            harness_config("[service.api.labels]\nteam = \"payments\"\ntier = \"free\"");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        hypervisor.spawn("api", "alice").await.unwrap();
+        hypervisor
+            .spawn_with_opts(
+                "api",
+                "bob",
+                SpawnOptions {
+                    labels: [("tier".to_string(), "pro".to_string())].into(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let bob = hypervisor.get("api", "bob").await.unwrap();
+        assert_eq!(bob.labels["team"], "payments");
+        assert_eq!(bob.labels["tier"], "pro");
+
+        let selector = crate::instance::parse_label_selector("team=payments").unwrap();
+        assert_eq!(hypervisor.list_matching(&selector).await.len(), 2);
+        let selector = crate::instance::parse_label_selector("team=payments,tier=pro").unwrap();
+        let matched = hypervisor.list_matching(&selector).await;
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].id.id, "bob");
+        let selector = crate::instance::parse_label_selector("team=search").unwrap();
+        assert!(hypervisor.list_matching(&selector).await.is_empty());
+
+        // Spawn-time labels survive a restart
+        hypervisor.restart("api", "bob").await.unwrap();
+        let bob = hypervisor.get("api", "bob").await.unwrap();
+        assert_eq!(bob.labels["tier"], "pro");
+    }
+
+    #[tokio::test]
+    async fn test_blue_green_deploy() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+
+        let versions = hypervisor.deploy("api", "v1", None).await.unwrap();
+        assert_eq!(versions.current.as_deref(), Some("v1"));
+        assert_eq!(versions.previous, None);
+        assert_eq!(versions.instances, vec!["v1"]);
+        let v1 = harness.process("api", "v1").await.unwrap();
+        assert_eq!(v1.env()["TENEMENT_VERSION"], "v1");
+        assert_eq!(hypervisor.select_weighted("api").await.unwrap().id.id, "v1");
+
+        // A failed rollout leaves the blue set serving
+        harness.runtime.fail_next_spawn("bad build");
+        assert!(hypervisor.deploy("api", "v2", None).await.is_err());
+        assert!(!hypervisor.is_running("api", "v2").await);
+        let v1_info = hypervisor.get("api", "v1").await.unwrap();
+        assert_eq!(v1_info.weight, 100);
+        assert_eq!(
+            hypervisor.service_versions("api").await.current.as_deref(),
+            Some("v1")
+        );
+
+        let versions = hypervisor
+            .deploy("api", "v2", Some("./api-v2 --fast".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(versions.current.as_deref(), Some("v2"));
+        assert_eq!(versions.previous.as_deref(), Some("v1"));
+        assert!(!hypervisor.is_running("api", "v1").await);
+        assert!(!v1.is_running());
+        let v2 = harness.process("api", "v2").await.unwrap();
+        assert_eq!(v2.config().command, "./api-v2");
+        assert_eq!(v2.config().args, vec!["--fast"]);
+        assert_eq!(hypervisor.get("api", "v2").await.unwrap().weight, 100);
+
+        // The same version can't be deployed twice
+        assert!(hypervisor.deploy("api", "v2", None).await.is_err());
+        // Untracked instances (tenants) are left alone
+        hypervisor.spawn("api", "alice").await.unwrap();
+        hypervisor.deploy("api", "v3", None).await.unwrap();
+        assert!(hypervisor.is_running("api", "alice").await);
+        assert!(!hypervisor.is_running("api", "v2").await);
+    }
+
+    #[tokio::test]
+    async fn test_failed_deploy_restores_spawn_options() {
+        let mut config = Config::from_str(
+            "[service.api]\ncommand = \"./api\"\nisolation = \"process\"\nhealth_cmd = \"false\"\nreadiness_timeout = 0\n",
+        )
+        .unwrap();
+        let dir = TempDir::new().unwrap();
+        config.settings.data_dir = dir.path().to_path_buf();
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+
+        // v1 from an earlier deploy, idle-stopped and waiting to be woken
+        let v1 = InstanceId::new("api", "v1");
+        hypervisor.spawn_options.write().await.insert(
+            v1.clone(),
+            SpawnOptions {
+                command: Some("./api-v1".to_string()),
+                ..Default::default()
+            },
+        );
+
+        // Redeploying it with a build that never gets ready fails
+        let err = hypervisor
+            .deploy("api", "v1", Some("./api-broken".to_string()))
+            .await
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("did not become ready"),
+            "{:#}",
+            err
+        );
+        assert!(!hypervisor.is_running("api", "v1").await);
+        let stored = hypervisor.spawn_options.read().await;
+        assert_eq!(stored[&v1].command.as_deref(), Some("./api-v1"));
+        assert!(stored[&v1].labels.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_blue_green_deploy_matches_replica_count() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config).await.unwrap();
+        let hypervisor = &harness.hypervisor;
+        let labeled = SpawnOptions {
+            labels: [(VERSION_LABEL.to_string(), "v1".to_string())].into(),
+            ..Default::default()
+        };
+        for id in ["a", "b", "c"] {
+            hypervisor
+                .spawn_with_opts("api", id, labeled.clone())
+                .await
+                .unwrap();
+        }
+
+        let versions = hypervisor.deploy("api", "v2", None).await.unwrap();
+        assert_eq!(versions.previous.as_deref(), Some("v1"));
+        assert_eq!(versions.instances, vec!["v2-1", "v2-2", "v2-3"]);
+        for id in ["a", "b", "c"] {
+            assert!(!hypervisor.is_running("api", id).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routing_state_survives_respawn_and_daemon_restart() {
+        let (config, _dir) = harness_config("");
+        let harness = crate::testing::TestHarness::new(config.clone())
+            .await
+            .unwrap();
+        let hypervisor = &harness.hypervisor;
+
+        hypervisor.spawn("api", "alice").await.unwrap();
+        hypervisor.set_weight("api", "alice", 30).await.unwrap();
+        hypervisor.deploy("api", "v1", None).await.unwrap();
+        hypervisor.deploy("api", "v2", None).await.unwrap();
+
+        // A respawned instance keeps its weight
+        hypervisor.stop("api", "alice").await.unwrap();
+        hypervisor.spawn("api", "alice").await.unwrap();
+        assert_eq!(hypervisor.get("api", "alice").await.unwrap().weight, 30);
+        hypervisor.stop_all().await;
+
+        // So does a new daemon on the same store
+        let runtime = Arc::new(crate::runtime::MockRuntime::new());
+        let restarted = Hypervisor::with_state_store(config, harness.stores.state.clone())
+            .with_runtime(runtime.clone());
+        restarted.load_routing_state().await.unwrap();
+        restarted.spawn("api", "alice").await.unwrap();
+        restarted.spawn("api", "v2").await.unwrap();
+        assert_eq!(restarted.get("api", "alice").await.unwrap().weight, 30);
+        assert_eq!(restarted.get("api", "v2").await.unwrap().weight, 100);
+        let versions = restarted.service_versions("api").await;
+        assert_eq!(versions.current.as_deref(), Some("v2"));
+        assert_eq!(versions.previous.as_deref(), Some("v1"));
+
+        // Back to the default: nothing left to persist
+        restarted.set_weight("api", "alice", 100).await.unwrap();
+        assert!(harness.stores.state.weights().await.unwrap().is_empty());
     }
 }
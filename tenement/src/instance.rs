@@ -1,10 +1,15 @@
 //! Process instance management
 
+use crate::config::{Listener, ProcessConfig};
 use crate::runtime::{RuntimeHandle, RuntimeType};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::time::Instant;
 
+/// ID prefix reserved for warm pool instances (pre-spawned, not yet claimed)
+pub const WARM_ID_PREFIX: &str = "__warm-";
+
 /// Unique identifier for an instance: "process_name:id"
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct InstanceId {
@@ -20,6 +25,12 @@ impl InstanceId {
         }
     }
 
+    /// True for unclaimed warm pool instances, which are hidden from
+    /// listings and never routed to until claimed under a real ID.
+    pub fn is_warm(&self) -> bool {
+        self.id.starts_with(WARM_ID_PREFIX)
+    }
+
     pub fn parse(s: &str) -> Option<Self> {
         let parts: Vec<&str> = s.splitn(2, ':').collect();
         if parts.len() == 2 {
@@ -47,6 +58,19 @@ pub enum HealthStatus {
     Failed,
 }
 
+impl HealthStatus {
+    /// Whether weighted routing may send new requests to an instance in this
+    /// state. Unhealthy and failed instances never get any; ones not checked
+    /// yet only with `route_unknown`.
+    pub fn is_routable(self, route_unknown: bool) -> bool {
+        match self {
+            HealthStatus::Healthy | HealthStatus::Degraded => true,
+            HealthStatus::Unknown => route_unknown,
+            HealthStatus::Unhealthy | HealthStatus::Failed => false,
+        }
+    }
+}
+
 impl std::fmt::Display for HealthStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -69,6 +93,9 @@ pub enum InstanceStatus {
     Stopping,
     /// Instance was auto-stopped due to idle timeout, can be auto-woken on request
     Sleeping,
+    /// Re-adopted after its service was renamed or removed from config.
+    /// Gets no traffic until `adopt-config` maps it to a configured service.
+    Orphaned,
 }
 
 impl std::fmt::Display for InstanceStatus {
@@ -79,6 +106,7 @@ impl std::fmt::Display for InstanceStatus {
             InstanceStatus::Starting => write!(f, "starting"),
             InstanceStatus::Stopping => write!(f, "stopping"),
             InstanceStatus::Sleeping => write!(f, "sleeping"),
+            InstanceStatus::Orphaned => write!(f, "orphaned"),
         }
     }
 }
@@ -92,6 +120,8 @@ pub struct Instance {
     pub socket: PathBuf,
     /// TCP port (when Some, service listens on 127.0.0.1:{port} instead of socket)
     pub port: Option<u16>,
+    /// Every listen address, the main one (`port` or `socket`) first
+    pub listeners: Vec<Listener>,
     pub started_at: Instant,
     pub restarts: u32,
     pub consecutive_failures: u32,
@@ -114,6 +144,28 @@ pub struct Instance {
     /// Traffic weight for load balancing (0-100, default 100)
     /// Weight 0 means instance receives no traffic
     pub weight: u8,
+    /// Set while the instance is being drained before stop.
+    /// Draining instances receive no new requests.
+    pub draining: bool,
+    /// False until the health endpoint first returns 200 (readiness gate).
+    /// Instances that aren't ready receive no traffic.
+    pub ready: bool,
+    /// Set when the process exited and the restart policy kept it down.
+    /// The instance stays listed (status stopped, health failed) until it
+    /// is stopped or restarted by hand.
+    pub exit_code: Option<i32>,
+    /// Labels from the service config, overridden by spawn-time labels
+    pub labels: BTreeMap<String, String>,
+    /// Re-adopted while its service is missing from config
+    pub orphaned: bool,
+    /// OOM kills in the instance's cgroup already reported
+    pub oom_kills: u64,
+    /// Spawned from a service definition that a config reload has since
+    /// changed; the monitor restarts it to pick up the new one
+    pub stale_config: bool,
+    /// Service definition the instance was spawned from (None if it was
+    /// re-adopted from state that didn't record it)
+    pub spawned_config: Option<ProcessConfig>,
 }
 
 impl Instance {
@@ -138,6 +190,9 @@ pub struct InstanceInfo {
     /// TCP port (when Some, service listens on 127.0.0.1:{port})
     #[serde(skip_serializing_if = "Option::is_none")]
     pub port: Option<u16>,
+    /// Every listen address, the main one first
+    #[serde(default)]
+    pub listeners: Vec<Listener>,
     pub uptime_secs: u64,
     pub restarts: u32,
     pub health: HealthStatus,
@@ -154,9 +209,22 @@ pub struct InstanceInfo {
     pub data_dir: PathBuf,
     /// Traffic weight for load balancing (0-100)
     pub weight: u8,
+    /// Exit code of a process the restart policy left stopped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// Key/value labels (service config plus spawn-time labels)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
 }
 
 impl InstanceInfo {
+    /// True if every label in `selector` is set to the same value here
+    pub fn matches_labels(&self, selector: &BTreeMap<String, String>) -> bool {
+        selector
+            .iter()
+            .all(|(key, value)| self.labels.get(key) == Some(value))
+    }
+
     /// Check if this instance uses TCP port instead of Unix socket
     pub fn uses_port(&self) -> bool {
         self.port.is_some()
@@ -177,25 +245,74 @@ impl InstanceInfo {
     }
 }
 
+/// Parse a `key=value` label (as used by `--label` and label filters)
+pub fn parse_label(s: &str) -> anyhow::Result<(String, String)> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => anyhow::bail!("Invalid label '{}'. Use key=value", s),
+    }
+}
+
+/// Parse a comma-separated label selector (`team=payments,env=prod`)
+pub fn parse_label_selector(s: &str) -> anyhow::Result<BTreeMap<String, String>> {
+    s.split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(parse_label)
+        .collect()
+}
+
 use std::time::Duration;
 
 impl Instance {
     pub fn info(&self) -> InstanceInfo {
+        self.info_at(Instant::now())
+    }
+
+    /// [`Instance::info`] with uptime and idle time measured at `now`
+    pub fn info_at(&self, now: Instant) -> InstanceInfo {
         InstanceInfo {
             id: self.id.clone(),
             runtime: self.runtime_type,
             socket: self.socket.clone(),
             port: self.port,
-            uptime_secs: self.started_at.elapsed().as_secs(),
+            listeners: self.listeners.clone(),
+            uptime_secs: now.saturating_duration_since(self.started_at).as_secs(),
             restarts: self.restarts,
             health: self.health_status,
-            status: InstanceStatus::Running,
-            idle_secs: self.last_activity.elapsed().as_secs(),
+            status: if self.exit_code.is_some() {
+                InstanceStatus::Stopped
+            } else if self.orphaned {
+                InstanceStatus::Orphaned
+            } else if self.draining {
+                InstanceStatus::Stopping
+            } else if !self.ready {
+                InstanceStatus::Starting
+            } else {
+                InstanceStatus::Running
+            },
+            idle_secs: now.saturating_duration_since(self.last_activity).as_secs(),
             idle_timeout: self.idle_timeout,
             storage_used_bytes: self.storage_used_bytes,
             storage_quota_bytes: self.storage_quota_mb.map(|mb| (mb as u64) * 1024 * 1024),
             data_dir: self.data_dir.clone(),
             weight: self.weight,
+            exit_code: self.exit_code,
+            labels: self.labels.clone(),
+        }
+    }
+
+    /// Weight used for weighted routing. Degraded instances keep only
+    /// `degraded_percent` of their configured weight (but never drop to 0
+    /// unless the percentage itself is 0); all other states use full weight.
+    pub fn effective_weight(&self, degraded_percent: u8) -> u8 {
+        match self.health_status {
+            HealthStatus::Degraded if self.weight > 0 && degraded_percent > 0 => {
+                ((self.weight as u32 * degraded_percent.min(100) as u32) / 100).max(1) as u8
+            }
+            HealthStatus::Degraded => 0,
+            _ => self.weight,
         }
     }
 
@@ -208,9 +325,14 @@ impl Instance {
     /// Only returns true when idle_timeout > 0 AND the instance has been
     /// idle for longer than that duration.
     pub fn is_idle(&self) -> bool {
+        self.is_idle_at(Instant::now())
+    }
+
+    /// [`Instance::is_idle`] as of `now`
+    pub fn is_idle_at(&self, now: Instant) -> bool {
         match self.idle_timeout {
             Some(timeout) if timeout > 0 => {
-                self.last_activity.elapsed() > Duration::from_secs(timeout)
+                now.saturating_duration_since(self.last_activity) > Duration::from_secs(timeout)
             }
             _ => false,
         }
@@ -218,7 +340,12 @@ impl Instance {
 
     /// Update the last activity timestamp (call on real requests, NOT health checks)
     pub fn touch(&mut self) {
-        self.last_activity = Instant::now();
+        self.touch_at(Instant::now());
+    }
+
+    /// [`Instance::touch`] with an explicit timestamp
+    pub fn touch_at(&mut self, now: Instant) {
+        self.last_activity = now;
     }
 
     pub fn uptime_human(&self) -> String {
@@ -407,6 +534,7 @@ mod tests {
         assert_eq!(InstanceStatus::Starting.to_string(), "starting");
         assert_eq!(InstanceStatus::Stopping.to_string(), "stopping");
         assert_eq!(InstanceStatus::Sleeping.to_string(), "sleeping");
+        assert_eq!(InstanceStatus::Orphaned.to_string(), "orphaned");
     }
 
     #[test]
@@ -470,6 +598,7 @@ mod tests {
             (InstanceStatus::Starting, "\"starting\""),
             (InstanceStatus::Stopping, "\"stopping\""),
             (InstanceStatus::Sleeping, "\"sleeping\""),
+            (InstanceStatus::Orphaned, "\"orphaned\""),
         ];
 
         for (status, expected) in variants {
@@ -520,6 +649,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 3600,
             restarts: 2,
             health: HealthStatus::Healthy,
@@ -530,6 +660,8 @@ mod tests {
             storage_quota_bytes: Some(536870912),
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         let json = serde_json::to_string(&info).unwrap();
@@ -557,6 +689,7 @@ mod tests {
             runtime: RuntimeType::Namespace,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Unknown,
@@ -567,6 +700,8 @@ mod tests {
             storage_quota_bytes: None,
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         let json = serde_json::to_string(&info).unwrap();
@@ -744,6 +879,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 1,
             health: HealthStatus::Healthy,
@@ -754,6 +890,8 @@ mod tests {
             storage_quota_bytes: Some(2048),
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         let cloned = info.clone();
@@ -773,6 +911,7 @@ mod tests {
             runtime: RuntimeType::Namespace,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Unknown,
@@ -783,6 +922,8 @@ mod tests {
             storage_quota_bytes: None,
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         let debug = format!("{:?}", info);
@@ -801,6 +942,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Healthy,
@@ -811,6 +953,8 @@ mod tests {
             storage_quota_bytes: None,             // No quota
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         assert_eq!(info.storage_used_bytes, 104857600);
@@ -824,6 +968,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Healthy,
@@ -834,6 +979,8 @@ mod tests {
             storage_quota_bytes: Some(536870912), // 512MB
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 100,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         assert_eq!(info.storage_used_bytes, 134217728);
@@ -851,6 +998,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Healthy,
@@ -861,6 +1009,8 @@ mod tests {
             storage_quota_bytes: None,
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 50,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         assert_eq!(info.weight, 50);
@@ -873,6 +1023,7 @@ mod tests {
             runtime: RuntimeType::Process,
             socket: PathBuf::from("/tmp/test.sock"),
             port: None,
+            listeners: Vec::new(),
             uptime_secs: 100,
             restarts: 0,
             health: HealthStatus::Healthy,
@@ -883,6 +1034,8 @@ mod tests {
             storage_quota_bytes: None,
             data_dir: PathBuf::from("/data/api/user1"),
             weight: 75,
+            exit_code: None,
+            labels: BTreeMap::new(),
         };
 
         let json = serde_json::to_string(&info).unwrap();
@@ -891,4 +1044,16 @@ mod tests {
         let deserialized: InstanceInfo = serde_json::from_str(&json).unwrap();
         assert_eq!(deserialized.weight, 75);
     }
+
+    #[test]
+    fn test_parse_label_selector() {
+        let selector = parse_label_selector("team=payments, env=prod").unwrap();
+        assert_eq!(selector.len(), 2);
+        assert_eq!(selector["team"], "payments");
+        assert_eq!(selector["env"], "prod");
+        assert_eq!(parse_label("note=a=b").unwrap().1, "a=b");
+        assert!(parse_label("team").is_err());
+        assert!(parse_label("=x").is_err());
+        assert!(parse_label_selector("").unwrap().is_empty());
+    }
 }
@@ -3,31 +3,91 @@
 //! Spawn and supervise processes with Unix socket communication,
 //! health checks, and automatic restarts.
 
+pub mod alerts;
 pub mod auth;
+pub mod capacity;
 pub mod cgroup;
+pub mod clock;
 pub mod config;
+pub mod config_diff;
+pub mod cors;
+pub mod database;
+pub mod disk_guard;
+pub mod dns;
+pub mod encryption;
+pub mod experiment;
+pub mod gate;
+pub mod hardening;
+pub mod hash;
+pub mod host;
 pub mod hypervisor;
 pub mod instance;
 pub mod logs;
+pub mod maintenance;
+pub mod metering;
 pub mod metrics;
+pub mod paths;
 pub mod port_allocator;
+pub mod rate_limit;
 pub mod runtime;
+pub mod secrets;
 pub mod storage;
 pub mod store;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+pub use alerts::{AlertEngine, AlertEvent, AlertRule};
 pub use auth::{generate_token, hash_token, verify_token, TokenStore};
+pub use capacity::{CapacityReport, UsageRecorder, UsageSample};
 pub use cgroup::{CgroupManager, ResourceLimits};
-pub use config::{Config, TlsConfig};
-pub use hypervisor::{ConnectionGuard, Hypervisor};
+pub use clock::{Clock, SystemClock};
+pub use config::{
+    Config, DomainConfig, ListenAddr, Listener, ListenerConfig, ListenerKind, PortConfig,
+    TlsConfig, MAIN_LISTENER, REDACTED,
+};
+pub use config_diff::{ConfigDiff, DriftReason, InstanceDrift};
+pub use cors::CorsConfig;
+pub use disk_guard::DiskGuard;
+pub use dns::{
+    ChallengeDnsConfig, ChallengeRecords, DnsConfig, DnsProvider, DnsRecord, DnsRegistrar,
+};
+pub use experiment::{ExperimentConfig, EXPERIMENT_HEADER};
+pub use gate::{AuthGate, AuthGateConfig};
+pub use hardening::{Hardening, SecurityProfile};
+pub use host::{DiskUsage, HostStats};
+pub use hypervisor::{
+    ConnectionGuard, Hypervisor, InstanceExists, ReloadReport, ServiceVersions, SpawnOptions,
+    VERSION_LABEL,
+};
 pub use instance::{Instance, InstanceId, InstanceStatus};
 pub use logs::{LogBuffer, LogEntry, LogLevel, LogQuery};
+pub use maintenance::{MaintenanceConfig, MaintenanceMode};
+pub use metering::{
+    InstanceMeter, InstanceUsage, Meter, MeterReading, MeterRecorder, UsageReport, UsageRollup,
+};
 pub use metrics::Metrics;
 pub use port_allocator::PortAllocator;
+pub use rate_limit::{RateLimitConfig, RateLimitKey, RateLimiter};
 #[cfg(feature = "sandbox")]
 pub use runtime::SandboxRuntime;
 pub use runtime::{ProcessRuntime, Runtime, RuntimeHandle, RuntimeType, SpawnConfig, VmConfig};
 pub use storage::{calculate_dir_size, format_bytes, StorageInfo};
 pub use store::{
-    init_db, ConfigStore, DbPool, DeployLogEntry, DeployLogStore, InstanceState, LogStore,
-    StateStore, TenantToken, TenantTokenStore,
+    init_db, init_memory_db, AccessLogEntry, AccessLogQuery, AccessLogStore, ConfigStore, DbPool,
+    DeployLogEntry, DeployLogStore, InstanceState, LogStore, StateStore, TenantKeyStore,
+    TenantSecretStore, TenantToken, TenantTokenStore, UsageStore,
 };
+
+/// Optional Cargo features (isolation backends) this build was compiled with
+pub fn enabled_features() -> Vec<&'static str> {
+    [
+        ("firecracker", cfg!(feature = "firecracker")),
+        ("qemu", cfg!(feature = "qemu")),
+        ("sandbox", cfg!(feature = "sandbox")),
+        ("quark", cfg!(feature = "quark")),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect()
+}
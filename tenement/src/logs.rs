@@ -4,11 +4,14 @@
 //! Provides real-time streaming via broadcast channel.
 
 use serde::Serialize;
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, OnceLock};
 use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
 use tokio::sync::{broadcast, RwLock};
 
+use crate::alerts::AlertEngine;
+
 /// Default capacity for the ring buffer (per instance)
 const DEFAULT_BUFFER_CAPACITY: usize = 10_000;
 
@@ -42,6 +45,9 @@ pub struct LogEntry {
     pub instance_id: String,
     /// Log message
     pub message: String,
+    /// Size in bytes of the original line, when `message` was truncated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_bytes: Option<usize>,
 }
 
 impl LogEntry {
@@ -58,8 +64,102 @@ impl LogEntry {
             process: process.to_string(),
             instance_id: instance_id.to_string(),
             message,
+            original_bytes: None,
         }
     }
+
+    /// Build an entry from a captured line, marking it if it was truncated
+    pub fn from_line(process: &str, instance_id: &str, level: LogLevel, line: LogLine) -> Self {
+        let mut entry = Self::new(process, instance_id, level, line.text);
+        if let Some(original) = line.original_bytes {
+            entry
+                .message
+                .push_str(&format!(" [truncated: {} bytes]", original));
+            entry.original_bytes = Some(original);
+        }
+        entry
+    }
+}
+
+/// One line of captured output
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine {
+    /// Line text (without the newline), cut to the reader's limit
+    pub text: String,
+    /// Full size in bytes when `text` was cut short
+    pub original_bytes: Option<usize>,
+}
+
+/// Splits process output into lines, keeping at most `max_bytes` of each
+///
+/// Unlike `AsyncBufReadExt::lines`, the rest of an over-long line is skipped
+/// as it is read rather than buffered, and invalid UTF-8 is replaced instead
+/// of ending the capture.
+pub struct LineReader<R> {
+    reader: R,
+    max_bytes: usize,
+}
+
+impl<R: AsyncBufRead + Unpin> LineReader<R> {
+    /// `max_bytes` of 0 keeps whole lines
+    pub fn new(reader: R, max_bytes: usize) -> Self {
+        Self { reader, max_bytes }
+    }
+
+    /// Next line, or None at end of output
+    pub async fn next_line(&mut self) -> std::io::Result<Option<LogLine>> {
+        let limit = if self.max_bytes == 0 {
+            usize::MAX
+        } else {
+            self.max_bytes
+        };
+        let mut kept = Vec::new();
+        let mut total = 0;
+        let mut read_any = false;
+
+        loop {
+            let available = self.reader.fill_buf().await?;
+            if available.is_empty() {
+                if !read_any {
+                    return Ok(None);
+                }
+                break;
+            }
+            read_any = true;
+
+            let newline = available.iter().position(|&b| b == b'\n');
+            let line_part = &available[..newline.unwrap_or(available.len())];
+            let room = limit.saturating_sub(kept.len());
+            kept.extend_from_slice(&line_part[..line_part.len().min(room)]);
+            total += line_part.len();
+
+            let consumed = newline.map_or(available.len(), |i| i + 1);
+            self.reader.consume(consumed);
+            if newline.is_some() {
+                break;
+            }
+        }
+
+        let original_bytes = if total > kept.len() {
+            // Don't leave half a character at the cut
+            if let Err(e) = std::str::from_utf8(&kept) {
+                if e.error_len().is_none() {
+                    kept.truncate(e.valid_up_to());
+                }
+            }
+            Some(total)
+        } else {
+            if kept.last() == Some(&b'\r') {
+                kept.pop();
+            }
+            None
+        };
+
+        Ok(Some(LogLine {
+            text: String::from_utf8_lossy(&kept).into_owned(),
+            original_bytes,
+        }))
+    }
 }
 
 /// Query parameters for filtering logs
@@ -146,12 +246,103 @@ impl RingBuffer {
     fn len(&self) -> usize {
         self.entries.len()
     }
+
+    /// Move `process`'s entries from instance `from` to `to`
+    fn relabel(&mut self, process: &str, from: &str, to: &str) {
+        for entry in self.entries.iter_mut() {
+            if entry.process == process && entry.instance_id == from {
+                entry.instance_id = to.to_string();
+            }
+        }
+    }
+
+    /// Change the capacity, dropping the oldest entries that no longer fit
+    fn resize(&mut self, capacity: usize) {
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+        self.capacity = capacity;
+    }
+}
+
+/// The shared ring buffer plus one per service with its own `log_buffer_lines`
+#[derive(Debug)]
+struct Rings {
+    shared: RingBuffer,
+    services: HashMap<String, RingBuffer>,
+}
+
+impl Rings {
+    fn new(capacity: usize) -> Self {
+        Self {
+            shared: RingBuffer::new(capacity),
+            services: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, entry: LogEntry, capacity: Option<usize>) {
+        match capacity {
+            Some(capacity) => {
+                let ring = self
+                    .services
+                    .entry(entry.process.clone())
+                    .or_insert_with(|| RingBuffer::new(capacity));
+                if ring.capacity != capacity {
+                    ring.resize(capacity);
+                }
+                ring.push(entry);
+            }
+            None => {
+                // The service went back to the shared buffer
+                self.services.remove(&entry.process);
+                self.shared.push(entry);
+            }
+        }
+    }
+
+    fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
+        if self.services.is_empty() {
+            return self.shared.query(query);
+        }
+        let mut results = self.shared.query(query);
+        for (process, ring) in &self.services {
+            if query.process.as_ref().is_none_or(|p| p == process) {
+                results.extend(ring.query(query));
+            }
+        }
+        // Stable, so entries from one ring keep their order
+        results.sort_by_key(|e| e.timestamp);
+        if let Some(limit) = query.limit {
+            if results.len() > limit {
+                results = results.split_off(results.len() - limit);
+            }
+        }
+        results
+    }
+
+    fn len(&self) -> usize {
+        self.shared.len() + self.services.values().map(RingBuffer::len).sum::<usize>()
+    }
+
+    fn relabel(&mut self, process: &str, from: &str, to: &str) {
+        self.shared.relabel(process, from, to);
+        if let Some(ring) = self.services.get_mut(process) {
+            ring.relabel(process, from, to);
+        }
+    }
 }
 
 /// Log buffer with broadcast channel for streaming
 pub struct LogBuffer {
-    buffer: RwLock<RingBuffer>,
+    buffer: RwLock<Rings>,
+    /// Services with their own ring buffer size (`log_buffer_lines`)
+    capacities: std::sync::RwLock<HashMap<String, usize>>,
     sender: broadcast::Sender<LogEntry>,
+    /// Alert rules evaluated on every pushed entry
+    alerts: OnceLock<Arc<AlertEngine>>,
+    /// `(process, instance)` pairs whose lines are filed under another
+    /// instance ID (a claimed warm instance's output)
+    aliases: std::sync::RwLock<HashMap<(String, String), String>>,
 }
 
 impl LogBuffer {
@@ -164,17 +355,34 @@ impl LogBuffer {
     pub fn with_capacity(capacity: usize) -> Arc<Self> {
         let (sender, _) = broadcast::channel(1024);
         Arc::new(Self {
-            buffer: RwLock::new(RingBuffer::new(capacity)),
+            buffer: RwLock::new(Rings::new(capacity)),
+            capacities: std::sync::RwLock::new(HashMap::new()),
             sender,
+            alerts: OnceLock::new(),
+            aliases: std::sync::RwLock::new(HashMap::new()),
         })
     }
 
     /// Push a log entry to the buffer and broadcast it
-    pub async fn push(&self, entry: LogEntry) {
+    pub async fn push(&self, mut entry: LogEntry) {
+        {
+            let aliases = self.aliases.read().unwrap();
+            if !aliases.is_empty() {
+                let key = (entry.process.clone(), entry.instance_id.clone());
+                if let Some(id) = aliases.get(&key) {
+                    entry.instance_id = id.clone();
+                }
+            }
+        }
+        if let Some(alerts) = self.alerts.get() {
+            alerts.observe(&entry);
+        }
+
         // Store in ring buffer
         {
+            let capacity = self.capacities.read().unwrap().get(&entry.process).copied();
             let mut buffer = self.buffer.write().await;
-            buffer.push(entry.clone());
+            buffer.push(entry.clone(), capacity);
         }
 
         // Broadcast to subscribers (ignore if no receivers)
@@ -193,6 +401,18 @@ impl LogBuffer {
         self.push(entry).await;
     }
 
+    /// Push a captured line, marking it if it was truncated
+    pub async fn push_line(
+        &self,
+        process: &str,
+        instance_id: &str,
+        level: LogLevel,
+        line: LogLine,
+    ) {
+        self.push(LogEntry::from_line(process, instance_id, level, line))
+            .await;
+    }
+
     /// Query logs with filters
     pub async fn query(&self, query: &LogQuery) -> Vec<LogEntry> {
         let buffer = self.buffer.read().await;
@@ -210,18 +430,62 @@ impl LogBuffer {
         self.len().await == 0
     }
 
+    /// File `process`'s lines from instance `from` under `to` instead, both
+    /// those already buffered and those still to come (a claimed warm
+    /// instance keeps writing as its warm ID)
+    pub async fn relabel(&self, process: &str, from: &str, to: &str) {
+        let mut buffer = self.buffer.write().await;
+        self.aliases
+            .write()
+            .unwrap()
+            .insert((process.to_string(), from.to_string()), to.to_string());
+        buffer.relabel(process, from, to);
+    }
+
+    /// Drop the aliases filing lines under `process`'s instance `id`, once
+    /// it is gone
+    pub fn forget_relabels(&self, process: &str, id: &str) {
+        let mut aliases = self.aliases.write().unwrap();
+        if !aliases.is_empty() {
+            aliases.retain(|(p, _), to| p != process || to != id);
+        }
+    }
+
+    /// Give each listed service its own ring buffer of that many lines
+    ///
+    /// Other services share the default buffer. A service's buffer is
+    /// created or resized on its next line; one that is no longer listed
+    /// goes back to the shared buffer, dropping the lines it had.
+    pub fn set_capacities(&self, capacities: HashMap<String, usize>) {
+        *self.capacities.write().unwrap() = capacities;
+    }
+
     /// Subscribe to the log stream
     pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
         self.sender.subscribe()
     }
+
+    /// Evaluate `engine`'s rules on every entry pushed from now on. Only the
+    /// first engine attached is kept; returns false if one already was.
+    pub fn set_alerts(&self, engine: Arc<AlertEngine>) -> bool {
+        self.alerts.set(engine).is_ok()
+    }
+
+    /// The attached alert engine, if any
+    pub fn alerts(&self) -> Option<Arc<AlertEngine>> {
+        self.alerts.get().cloned()
+    }
 }
 
 impl Default for LogBuffer {
     fn default() -> Self {
         let (sender, _) = broadcast::channel(1024);
         Self {
-            buffer: RwLock::new(RingBuffer::new(DEFAULT_BUFFER_CAPACITY)),
+            buffer: RwLock::new(Rings::new(DEFAULT_BUFFER_CAPACITY)),
+            capacities: std::sync::RwLock::new(HashMap::new()),
             sender,
+            alerts: OnceLock::new(),
+            aliases: std::sync::RwLock::new(HashMap::new()),
         }
     }
 }
@@ -846,6 +1110,106 @@ mod tests {
         assert_eq!(results[4].message, "msg9");
     }
 
+    #[tokio::test]
+    async fn test_log_buffer_per_service_capacity() {
+        let buffer = LogBuffer::with_capacity(3);
+        buffer.set_capacities(HashMap::from([("chatty".to_string(), 6)]));
+
+        for i in 0..10 {
+            for process in ["chatty", "api"] {
+                let mut entry = LogEntry::new(
+                    process,
+                    "prod",
+                    LogLevel::Stdout,
+                    format!("{}{}", process, i),
+                );
+                entry.timestamp = i;
+                buffer.push(entry).await;
+            }
+        }
+
+        // The chatty service neither evicts nor shares the default buffer
+        let chatty = buffer
+            .query(&LogQuery {
+                process: Some("chatty".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(chatty.len(), 6);
+        assert_eq!(chatty[0].message, "chatty4");
+        let api = buffer
+            .query(&LogQuery {
+                process: Some("api".to_string()),
+                ..Default::default()
+            })
+            .await;
+        assert_eq!(api.len(), 3);
+        assert_eq!(buffer.len().await, 9);
+
+        // Unfiltered queries merge both, oldest first, limit from the end
+        let all = buffer
+            .query(&LogQuery {
+                limit: Some(4),
+                ..Default::default()
+            })
+            .await;
+        let messages: Vec<_> = all.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, ["api8", "chatty8", "api9", "chatty9"]);
+
+        // Shrinking drops the oldest lines on the next push
+        buffer.set_capacities(HashMap::from([("chatty".to_string(), 2)]));
+        buffer
+            .push_stdout("chatty", "prod", "chatty10".to_string())
+            .await;
+        assert_eq!(buffer.len().await, 5);
+
+        // Removing it sends the service back to the shared buffer
+        buffer.set_capacities(HashMap::new());
+        buffer
+            .push_stdout("chatty", "prod", "chatty11".to_string())
+            .await;
+        assert_eq!(buffer.len().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_log_buffer_relabel() {
+        let buffer = LogBuffer::new();
+        buffer
+            .push_stdout("api", "__warm-1", "booting".to_string())
+            .await;
+        buffer
+            .push_stdout("web", "__warm-1", "other".to_string())
+            .await;
+        buffer.relabel("api", "__warm-1", "alice").await;
+        buffer
+            .push_stdout("api", "__warm-1", "serving".to_string())
+            .await;
+
+        let alice = LogQuery {
+            instance_id: Some("alice".to_string()),
+            ..Default::default()
+        };
+        let messages: Vec<_> = buffer
+            .query(&alice)
+            .await
+            .into_iter()
+            .map(|e| (e.process, e.message))
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                ("api".to_string(), "booting".to_string()),
+                ("api".to_string(), "serving".to_string())
+            ]
+        );
+
+        buffer.forget_relabels("api", "alice");
+        buffer
+            .push_stdout("api", "__warm-1", "late".to_string())
+            .await;
+        assert_eq!(buffer.query(&alice).await.len(), 2);
+    }
+
     #[tokio::test]
     async fn test_log_buffer_push_stdout() {
         let buffer = LogBuffer::new();
@@ -921,4 +1285,63 @@ mod tests {
         let debug = format!("{:?}", query);
         assert!(debug.contains("api"));
     }
+
+    // ===================
+    // LINE READER TESTS
+    // ===================
+
+    async fn read_lines(input: &[u8], max_bytes: usize) -> Vec<LogLine> {
+        // A tiny buffer so long lines span several reads
+        let reader = tokio::io::BufReader::with_capacity(4, input);
+        let mut lines = LineReader::new(reader, max_bytes);
+        let mut out = Vec::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            out.push(line);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_truncates_long_lines() {
+        let lines = read_lines(b"short\r\n0123456789abcdef\nlast", 10).await;
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].text, "short");
+        assert_eq!(lines[0].original_bytes, None);
+        assert_eq!(lines[1].text, "0123456789");
+        assert_eq!(lines[1].original_bytes, Some(16));
+        assert_eq!(lines[2].text, "last");
+        assert_eq!(lines[2].original_bytes, None);
+    }
+
+    #[tokio::test]
+    async fn test_line_reader_unlimited_and_utf8_boundary() {
+        let lines = read_lines("0123456789abcdef\n".as_bytes(), 0).await;
+        assert_eq!(lines[0].text, "0123456789abcdef");
+        assert_eq!(lines[0].original_bytes, None);
+
+        // "é" is two bytes; a cut through it drops the partial character
+        let lines = read_lines("aé€b\n".as_bytes(), 2).await;
+        assert_eq!(lines[0].text, "a");
+        assert_eq!(lines[0].original_bytes, Some(7));
+    }
+
+    #[test]
+    fn test_log_entry_from_truncated_line() {
+        let line = LogLine {
+            text: "abc".to_string(),
+            original_bytes: Some(5_000_000),
+        };
+        let entry = LogEntry::from_line("api", "prod", LogLevel::Stdout, line);
+        assert_eq!(entry.message, "abc [truncated: 5000000 bytes]");
+        assert_eq!(entry.original_bytes, Some(5_000_000));
+
+        let json = serde_json::to_value(LogEntry::new(
+            "api",
+            "prod",
+            LogLevel::Stdout,
+            "ok".to_string(),
+        ))
+        .unwrap();
+        assert!(json.get("original_bytes").is_none());
+    }
 }
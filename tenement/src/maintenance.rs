@@ -0,0 +1,286 @@
+//! Scheduled maintenance windows
+//!
+//! A service with a `[service.X.maintenance]` table limits when disruptive
+//! actions may run: restarts and deploys requested through the API, and
+//! `max_lifetime` recycling. Crash restarts are never held back.
+//!
+//! ```toml
+//! [service.api.maintenance]
+//! mode = "allow"                      # default; "deny" freezes changes inside the windows
+//! windows = ["Sun 02:00-04:00", "Mon-Fri 22:00-23:00"]
+//!
+//! [service.api.maintenance.instances]
+//! alice = ["Sat 01:00-03:00"]         # replaces `windows` for api:alice
+//! ```
+//!
+//! Windows are `<days> HH:MM-HH:MM` in UTC. Days are `daily`, a day (`Sun`),
+//! a range (`Mon-Fri`) or a list (`Sat,Sun`). A window whose end is before
+//! its start runs past midnight into the next day; `24:00` ends at midnight.
+//!
+//! With `mode = "allow"`, actions may only run inside a window; with
+//! `mode = "deny"`, never inside one. An admin can override a blocked
+//! restart or deploy with `--force`, which is recorded in the audit log.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Whether windows permit or forbid disruptive actions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MaintenanceMode {
+    /// Only inside a window
+    #[default]
+    Allow,
+    /// Never inside a window
+    Deny,
+}
+
+/// Maintenance windows for a service
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    #[serde(default)]
+    pub mode: MaintenanceMode,
+
+    /// Windows for every instance of the service
+    #[serde(default)]
+    pub windows: Vec<String>,
+
+    /// Per-instance windows, replacing `windows` for that instance ID
+    #[serde(default)]
+    pub instances: HashMap<String, Vec<String>>,
+}
+
+impl MaintenanceConfig {
+    /// Check every window parses
+    pub fn validate(&self, service: &str) -> Result<()> {
+        for window in self.windows.iter().chain(self.instances.values().flatten()) {
+            Window::parse(window)
+                .with_context(|| format!("Service '{}': invalid maintenance window", service))?;
+        }
+        Ok(())
+    }
+
+    /// Why a disruptive action on instance `id` (or the whole service, with
+    /// `None`) can't run at `now`, if it can't
+    pub fn blocked(&self, id: Option<&str>, now: DateTime<Utc>) -> Option<String> {
+        let windows = id
+            .and_then(|id| self.instances.get(id))
+            .unwrap_or(&self.windows);
+        let inside = windows
+            .iter()
+            .filter_map(|w| Window::parse(w).ok())
+            .any(|w| w.contains(now));
+        match (self.mode, inside) {
+            (MaintenanceMode::Allow, false) => Some(if windows.is_empty() {
+                "no maintenance window is configured".to_string()
+            } else {
+                format!("outside the maintenance windows ({})", windows.join(", "))
+            }),
+            (MaintenanceMode::Deny, true) => Some(format!(
+                "inside a maintenance freeze ({})",
+                windows.join(", ")
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed `<days> HH:MM-HH:MM` window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    /// Indexed by days from Monday
+    days: [bool; 7],
+    /// Minutes after midnight
+    start: u32,
+    end: u32,
+}
+
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+impl Window {
+    pub fn parse(s: &str) -> Result<Self> {
+        let (days, times) = s
+            .trim()
+            .split_once(char::is_whitespace)
+            .with_context(|| format!("expected `<days> HH:MM-HH:MM`, got {:?}", s))?;
+        let (start, end) = times
+            .trim()
+            .split_once('-')
+            .with_context(|| format!("expected a HH:MM-HH:MM time range in {:?}", s))?;
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            anyhow::bail!("window {:?} is empty", s);
+        }
+        if start == 24 * 60 {
+            anyhow::bail!("window {:?} starts at 24:00", s);
+        }
+        Ok(Self {
+            days: parse_days(days)?,
+            start,
+            end,
+        })
+    }
+
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        let day = now.weekday().num_days_from_monday() as usize;
+        let minute = now.hour() * 60 + now.minute();
+        if self.start < self.end {
+            self.days[day] && (self.start..self.end).contains(&minute)
+        } else {
+            // Runs past midnight: the tail belongs to the previous day
+            (self.days[day] && minute >= self.start)
+                || (self.days[(day + 6) % 7] && minute < self.end)
+        }
+    }
+}
+
+fn parse_time(s: &str) -> Result<u32> {
+    let (h, m) = s
+        .trim()
+        .split_once(':')
+        .with_context(|| format!("expected HH:MM, got {:?}", s))?;
+    let (h, m): (u32, u32) = (
+        h.parse().with_context(|| format!("bad hour in {:?}", s))?,
+        m.parse()
+            .with_context(|| format!("bad minute in {:?}", s))?,
+    );
+    if m > 59 || h > 24 || (h == 24 && m > 0) {
+        anyhow::bail!("{:?} is not a time of day", s);
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_day(s: &str) -> Result<usize> {
+    let s = s.trim().to_lowercase();
+    DAYS.iter()
+        .position(|d| s.len() >= 3 && d.starts_with(&s))
+        .with_context(|| format!("unknown day {:?}", s))
+}
+
+fn parse_days(s: &str) -> Result<[bool; 7]> {
+    if s.eq_ignore_ascii_case("daily") {
+        return Ok([true; 7]);
+    }
+    let mut days = [false; 7];
+    for part in s.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (parse_day(from)?, parse_day(to)?);
+                let mut day = from;
+                loop {
+                    days[day] = true;
+                    if day == to {
+                        break;
+                    }
+                    day = (day + 1) % 7;
+                }
+            }
+            None => days[parse_day(part)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// 2024-06-02 was a Sunday
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_parse_windows() {
+        let w = Window::parse("Sun 02:00-04:00").unwrap();
+        assert!(w.contains(at(2, 2, 0)));
+        assert!(w.contains(at(2, 3, 59)));
+        assert!(!w.contains(at(2, 4, 0)));
+        assert!(!w.contains(at(3, 2, 30)));
+
+        let weekdays = Window::parse("Mon-Fri 09:00-17:00").unwrap();
+        assert!(weekdays.contains(at(3, 9, 0)));
+        assert!(weekdays.contains(at(7, 16, 59)));
+        assert!(!weekdays.contains(at(8, 12, 0)));
+
+        let weekend = Window::parse("sat,Sunday 00:00-24:00").unwrap();
+        assert!(weekend.contains(at(1, 0, 0)));
+        assert!(weekend.contains(at(2, 23, 59)));
+        assert!(!weekend.contains(at(3, 0, 0)));
+
+        // Fri-Mon wraps around the week
+        let long = Window::parse("Fri-Mon 12:00-13:00").unwrap();
+        assert!(long.contains(at(3, 12, 30)));
+        assert!(!long.contains(at(4, 12, 30)));
+
+        for bad in [
+            "02:00-04:00",
+            "Sun 02:00",
+            "Sun 25:00-26:00",
+            "Sun 02:60-03:00",
+            "Sun 02:00-02:00",
+            "Funday 02:00-03:00",
+            "Sundae 02:00-03:00",
+            "Sun 24:00-01:00",
+        ] {
+            assert!(Window::parse(bad).is_err(), "{bad} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_window_past_midnight() {
+        let w = Window::parse("Sat 22:00-02:00").unwrap();
+        assert!(w.contains(at(1, 23, 0)));
+        assert!(w.contains(at(2, 1, 59)));
+        assert!(!w.contains(at(2, 2, 0)));
+        assert!(!w.contains(at(1, 1, 0)));
+        assert!(!w.contains(at(2, 23, 0)));
+    }
+
+    #[test]
+    fn test_blocked_allow_and_deny() {
+        let config = MaintenanceConfig {
+            mode: MaintenanceMode::Allow,
+            windows: vec!["Sun 02:00-04:00".to_string()],
+            instances: HashMap::from([("alice".to_string(), vec!["Mon 02:00-04:00".to_string()])]),
+        };
+        assert!(config.validate("api").is_ok());
+        assert!(config.blocked(None, at(2, 3, 0)).is_none());
+        assert!(config.blocked(Some("bob"), at(2, 3, 0)).is_none());
+        let reason = config.blocked(None, at(2, 5, 0)).unwrap();
+        assert!(reason.contains("Sun 02:00-04:00"), "got: {reason}");
+        // alice's own windows replace the service's
+        assert!(config.blocked(Some("alice"), at(2, 3, 0)).is_some());
+        assert!(config.blocked(Some("alice"), at(3, 3, 0)).is_none());
+
+        let freeze = MaintenanceConfig {
+            mode: MaintenanceMode::Deny,
+            ..config
+        };
+        assert!(freeze
+            .blocked(None, at(2, 3, 0))
+            .unwrap()
+            .contains("freeze"));
+        assert!(freeze.blocked(None, at(2, 5, 0)).is_none());
+
+        let empty = MaintenanceConfig::default();
+        assert!(empty.blocked(None, at(2, 3, 0)).is_some());
+
+        let bad = MaintenanceConfig {
+            windows: vec!["whenever".to_string()],
+            ..Default::default()
+        };
+        assert!(bad.validate("api").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+//! Request metering per instance, for usage-based billing
+//!
+//! The proxy counts each instance's requests and request and response body
+//! bytes in the hypervisor's [`Meter`]. Compute-seconds are the time an
+//! instance was running, warm pool spares excluded. Every
+//! [`FLUSH_INTERVAL`], [`MeterRecorder`] adds what was counted since the
+//! last flush to the hour's row in the `usage_rollups` table, which keeps
+//! [`ROLLUP_RETENTION`] of history for `GET /api/usage`.
+
+use crate::hypervisor::Hypervisor;
+use crate::instance::InstanceId;
+use crate::store::UsageStore;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// How often counters are written to SQLite
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Width of one rollup row
+pub const ROLLUP_PERIOD: Duration = Duration::from_secs(3600);
+
+/// How long rollups are kept
+pub const ROLLUP_RETENTION: Duration = Duration::from_secs(400 * 24 * 3600);
+
+/// Live counters for one instance, updated by the proxy
+#[derive(Debug, Default)]
+pub struct InstanceMeter {
+    pub requests: AtomicU64,
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    /// Run time not yet flushed, in milliseconds
+    pub compute_millis: AtomicU64,
+}
+
+impl InstanceMeter {
+    pub fn add_request(&self) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_in(&self, bytes: u64) {
+        self.bytes_in.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes_out(&self, bytes: u64) {
+        self.bytes_out.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_compute(&self, duration: Duration) {
+        self.compute_millis
+            .fetch_add(duration.as_millis() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counters for every instance that has had traffic since the last flush
+#[derive(Debug, Default)]
+pub struct Meter {
+    instances: Mutex<HashMap<InstanceId, Arc<InstanceMeter>>>,
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counters of an instance, created on first use
+    pub fn instance(&self, id: &InstanceId) -> Arc<InstanceMeter> {
+        self.instances
+            .lock()
+            .unwrap()
+            .entry(id.clone())
+            .or_default()
+            .clone()
+    }
+
+    /// Read and reset every counter. Requests still streaming keep adding
+    /// to the counters they hold, which are read again next time.
+    pub fn take(&self) -> HashMap<InstanceId, MeterReading> {
+        let mut instances = self.instances.lock().unwrap();
+        let readings = instances
+            .iter()
+            .map(|(id, meter)| {
+                let reading = MeterReading {
+                    requests: meter.requests.swap(0, Ordering::Relaxed),
+                    bytes_in: meter.bytes_in.swap(0, Ordering::Relaxed),
+                    bytes_out: meter.bytes_out.swap(0, Ordering::Relaxed),
+                    compute_seconds: meter.compute_millis.swap(0, Ordering::Relaxed) as f64
+                        / 1000.0,
+                };
+                (id.clone(), reading)
+            })
+            .collect();
+        // Counters nobody else holds can't change any more
+        instances.retain(|_, meter| Arc::strong_count(meter) > 1);
+        readings
+    }
+}
+
+/// Usage counted over some period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MeterReading {
+    pub requests: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub compute_seconds: f64,
+}
+
+impl MeterReading {
+    pub fn is_empty(&self) -> bool {
+        self.requests == 0
+            && self.bytes_in == 0
+            && self.bytes_out == 0
+            && self.compute_seconds == 0.0
+    }
+
+    pub fn add(&mut self, other: &MeterReading) {
+        self.requests += other.requests;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.compute_seconds += other.compute_seconds;
+    }
+}
+
+/// One instance's usage in one [`ROLLUP_PERIOD`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRollup {
+    /// Unix seconds at the start of the period
+    pub period_start: i64,
+    pub process: String,
+    pub instance_id: String,
+    #[serde(flatten)]
+    pub usage: MeterReading,
+}
+
+/// Usage of one instance over a report's range
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstanceUsage {
+    pub process: String,
+    pub instance_id: String,
+    #[serde(flatten)]
+    pub total: MeterReading,
+    /// Per UTC day (`YYYY-MM-DD`), days without usage left out
+    pub daily: Vec<DailyUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DailyUsage {
+    pub day: String,
+    #[serde(flatten)]
+    pub usage: MeterReading,
+}
+
+/// Usage per instance since `since`, as served by `GET /api/usage`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageReport {
+    /// Unix seconds at the start of the first hour included
+    pub since: i64,
+    pub instances: Vec<InstanceUsage>,
+}
+
+impl UsageReport {
+    /// Load the rollups since `range` ago, optionally of one service or one
+    /// instance of it. Usage counted since the last flush is not included.
+    pub async fn load(
+        store: &UsageStore,
+        range: Duration,
+        process: Option<&str>,
+        instance_id: Option<&str>,
+    ) -> Result<Self> {
+        let now = unix_now();
+        let period = ROLLUP_PERIOD.as_secs() as i64;
+        let since = now - range.as_secs() as i64;
+        let since = since - since.rem_euclid(period);
+        let rollups = store.rollups(since, process, instance_id).await?;
+        Ok(Self::from_rollups(since, &rollups))
+    }
+
+    /// Sum rollups per instance and per day
+    pub fn from_rollups(since: i64, rollups: &[UsageRollup]) -> Self {
+        let mut instances: BTreeMap<(&str, &str), BTreeMap<String, MeterReading>> = BTreeMap::new();
+        for rollup in rollups {
+            let day = chrono::DateTime::from_timestamp(rollup.period_start, 0)
+                .unwrap_or_default()
+                .format("%Y-%m-%d")
+                .to_string();
+            instances
+                .entry((&rollup.process, &rollup.instance_id))
+                .or_default()
+                .entry(day)
+                .or_default()
+                .add(&rollup.usage);
+        }
+        let instances = instances
+            .into_iter()
+            .map(|((process, instance_id), days)| {
+                let mut total = MeterReading::default();
+                for usage in days.values() {
+                    total.add(usage);
+                }
+                InstanceUsage {
+                    process: process.to_string(),
+                    instance_id: instance_id.to_string(),
+                    total,
+                    daily: days
+                        .into_iter()
+                        .map(|(day, usage)| DailyUsage { day, usage })
+                        .collect(),
+                }
+            })
+            .collect();
+        Self { since, instances }
+    }
+}
+
+/// Flushes a hypervisor's [`Meter`] into a [`UsageStore`]
+pub struct MeterRecorder {
+    hypervisor: Arc<Hypervisor>,
+    store: Arc<UsageStore>,
+}
+
+impl MeterRecorder {
+    pub fn new(hypervisor: Arc<Hypervisor>, store: Arc<UsageStore>) -> Arc<Self> {
+        hypervisor.start_metering();
+        Arc::new(Self { hypervisor, store })
+    }
+
+    /// Write everything counted since the last flush and drop expired
+    /// rollups. Returns the number of instances written.
+    pub async fn flush(&self) -> Result<usize> {
+        let now = unix_now();
+        let readings = self.hypervisor.take_meter_readings().await;
+        let period_start = now - now.rem_euclid(ROLLUP_PERIOD.as_secs() as i64);
+        let rollups: Vec<UsageRollup> = readings
+            .into_iter()
+            .filter(|(_, usage)| !usage.is_empty())
+            .map(|(id, usage)| UsageRollup {
+                period_start,
+                process: id.process,
+                instance_id: id.id,
+                usage,
+            })
+            .collect();
+        self.store.add_rollups(&rollups).await?;
+        self.store
+            .prune_rollups(now - ROLLUP_RETENTION.as_secs() as i64)
+            .await?;
+        Ok(rollups.len())
+    }
+
+    /// Flush every `interval` until the process exits
+    pub fn start(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = self.flush().await {
+                    warn!("Usage metering flush failed: {:#}", e);
+                }
+            }
+        });
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter_take_resets() {
+        let meter = Meter::new();
+        let id = InstanceId::new("api", "alice");
+        let alice = meter.instance(&id);
+        alice.add_request();
+        alice.add_bytes_in(10);
+        alice.add_bytes_out(200);
+        alice.add_compute(Duration::from_millis(1500));
+        meter.instance(&InstanceId::new("api", "bob")).add_request();
+
+        let readings = meter.take();
+        assert_eq!(
+            readings[&id],
+            MeterReading {
+                requests: 1,
+                bytes_in: 10,
+                bytes_out: 200,
+                compute_seconds: 1.5,
+            }
+        );
+        assert_eq!(readings.len(), 2);
+
+        // Bob's counters were dropped; alice's are still held and keep counting
+        alice.add_bytes_out(5);
+        let readings = meter.take();
+        assert_eq!(readings.len(), 1);
+        assert_eq!(readings[&id].bytes_out, 5);
+        assert_eq!(readings[&id].requests, 0);
+    }
+
+    #[test]
+    fn test_usage_report_sums_per_day() {
+        let rollup = |period_start: i64, id: &str| UsageRollup {
+            period_start,
+            process: "api".to_string(),
+            instance_id: id.to_string(),
+            usage: MeterReading {
+                requests: 2,
+                bytes_in: 10,
+                bytes_out: 100,
+                compute_seconds: 3600.0,
+            },
+        };
+        // 2024-01-01 00:00 and 23:00 UTC, then 2024-01-02 00:00
+        let day = 1_704_067_200;
+        let report = UsageReport::from_rollups(
+            day,
+            &[
+                rollup(day, "alice"),
+                rollup(day, "bob"),
+                rollup(day + 23 * 3600, "alice"),
+                rollup(day + 24 * 3600, "alice"),
+            ],
+        );
+
+        assert_eq!(report.instances.len(), 2);
+        let alice = &report.instances[0];
+        assert_eq!(alice.instance_id, "alice");
+        assert_eq!(alice.total.requests, 6);
+        assert_eq!(alice.total.compute_seconds, 3.0 * 3600.0);
+        assert_eq!(alice.daily.len(), 2);
+        assert_eq!(alice.daily[0].day, "2024-01-01");
+        assert_eq!(alice.daily[0].usage.bytes_out, 200);
+        assert_eq!(alice.daily[1].day, "2024-01-02");
+        assert_eq!(report.instances[1].total.requests, 2);
+    }
+}
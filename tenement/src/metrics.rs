@@ -1,6 +1,13 @@
 //! Metrics collection and Prometheus export
 //!
 //! Simple in-memory metrics with Prometheus text format export.
+//!
+//! Labeled metrics are bounded by [`CardinalityLimits`]. Once a process has
+//! `max_instances` series in a metric, further instances of it are folded
+//! into a single `instance="_other"` (or `id="_other"`) series per process, so
+//! counters and histograms still add up to the per-process total. Past
+//! `max_series`, new series fold into one `overflow="true"` series. Gauges
+//! can't be summed that way, so gauges past a limit are simply not exported.
 
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicU64, Ordering};
@@ -140,10 +147,83 @@ impl Default for Histogram {
 /// Labels for a metric
 pub type Labels = HashMap<String, String>;
 
+/// Label value instances past `max_instances` are folded into
+pub const OTHER_INSTANCE: &str = "_other";
+
+/// Labels that identify an instance (requests use "instance", lifecycle
+/// metrics use "id")
+const INSTANCE_LABELS: [&str; 2] = ["instance", "id"];
+
+/// Series key everything past `max_series` is folded into
+const OVERFLOW_KEY: &str = "overflow=\"true\"";
+
+/// Bounds on the number of series a labeled metric keeps (0 = unlimited)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CardinalityLimits {
+    /// Distinct instances per process before folding into `_other`
+    pub max_instances: usize,
+    /// Total series per metric before folding into `overflow`
+    pub max_series: usize,
+}
+
+/// Where a new series for `labels` goes under `limits`
+enum SeriesSlot {
+    /// Its own (possibly instance-folded) series
+    Key(String),
+    /// Over a limit: counters/histograms fold into this key, gauges drop it
+    Folded(String),
+}
+
+/// Pick the series key for labels that have no series yet
+fn bounded_slot<T>(
+    series: &HashMap<String, T>,
+    labels: &Labels,
+    limits: &CardinalityLimits,
+) -> SeriesSlot {
+    let mut labels = labels.clone();
+    let mut folded = false;
+
+    if limits.max_instances > 0 {
+        if let (Some(process), Some(label)) = (
+            labels.get("process").cloned(),
+            INSTANCE_LABELS.iter().find(|l| labels.contains_key(**l)),
+        ) {
+            if labels[*label] != OTHER_INSTANCE {
+                let mut instances = std::collections::HashSet::new();
+                for key in series.keys() {
+                    let existing = key_to_labels(key);
+                    if existing.get("process") == Some(&process) {
+                        if let Some(id) = existing.get(*label) {
+                            if id != OTHER_INSTANCE {
+                                instances.insert(id.clone());
+                            }
+                        }
+                    }
+                }
+                if instances.len() >= limits.max_instances {
+                    labels.insert(label.to_string(), OTHER_INSTANCE.to_string());
+                    folded = true;
+                }
+            }
+        }
+    }
+
+    let key = labels_to_key(&labels);
+    if limits.max_series > 0 && !series.contains_key(&key) && series.len() >= limits.max_series {
+        return SeriesSlot::Folded(OVERFLOW_KEY.to_string());
+    }
+    if folded {
+        SeriesSlot::Folded(key)
+    } else {
+        SeriesSlot::Key(key)
+    }
+}
+
 /// A labeled counter (counter per label combination)
 #[derive(Debug, Default)]
 pub struct LabeledCounter {
     counters: RwLock<HashMap<String, Arc<Counter>>>,
+    limits: CardinalityLimits,
 }
 
 impl LabeledCounter {
@@ -151,6 +231,14 @@ impl LabeledCounter {
         Self::default()
     }
 
+    /// A labeled counter bounded by `limits`
+    pub fn with_limits(limits: CardinalityLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
     /// Get or create a counter for the given labels
     pub async fn with_labels(&self, labels: &Labels) -> Arc<Counter> {
         let key = labels_to_key(labels);
@@ -165,6 +253,9 @@ impl LabeledCounter {
 
         // Need to create
         let mut counters = self.counters.write().await;
+        let key = match bounded_slot(&counters, labels, &self.limits) {
+            SeriesSlot::Key(key) | SeriesSlot::Folded(key) => key,
+        };
         counters
             .entry(key)
             .or_insert_with(|| Arc::new(Counter::new()))
@@ -182,6 +273,7 @@ impl LabeledCounter {
 #[derive(Debug, Default)]
 pub struct LabeledGauge {
     gauges: RwLock<HashMap<String, Arc<Gauge>>>,
+    limits: CardinalityLimits,
 }
 
 impl LabeledGauge {
@@ -189,6 +281,14 @@ impl LabeledGauge {
         Self::default()
     }
 
+    /// A labeled gauge bounded by `limits`
+    pub fn with_limits(limits: CardinalityLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
     /// Get or create a gauge for the given labels
     pub async fn with_labels(&self, labels: &Labels) -> Arc<Gauge> {
         let key = labels_to_key(labels);
@@ -201,8 +301,12 @@ impl LabeledGauge {
             }
         }
 
-        // Need to create
+        // Need to create. Over a limit, hand out a gauge that isn't exported.
         let mut gauges = self.gauges.write().await;
+        let key = match bounded_slot(&gauges, labels, &self.limits) {
+            SeriesSlot::Key(key) => key,
+            SeriesSlot::Folded(_) => return Arc::new(Gauge::new()),
+        };
         gauges
             .entry(key)
             .or_insert_with(|| Arc::new(Gauge::new()))
@@ -227,6 +331,7 @@ impl LabeledGauge {
 #[derive(Debug, Default)]
 pub struct LabeledHistogram {
     histograms: RwLock<HashMap<String, Arc<Histogram>>>,
+    limits: CardinalityLimits,
 }
 
 impl LabeledHistogram {
@@ -234,6 +339,14 @@ impl LabeledHistogram {
         Self::default()
     }
 
+    /// A labeled histogram bounded by `limits`
+    pub fn with_limits(limits: CardinalityLimits) -> Self {
+        Self {
+            limits,
+            ..Self::default()
+        }
+    }
+
     pub async fn with_labels(&self, labels: &Labels) -> Arc<Histogram> {
         let key = labels_to_key(labels);
 
@@ -245,6 +358,9 @@ impl LabeledHistogram {
         }
 
         let mut histograms = self.histograms.write().await;
+        let key = match bounded_slot(&histograms, labels, &self.limits) {
+            SeriesSlot::Key(key) | SeriesSlot::Folded(key) => key,
+        };
         histograms
             .entry(key)
             .or_insert_with(|| Arc::new(Histogram::new()))
@@ -272,7 +388,6 @@ fn labels_to_key(labels: &Labels) -> String {
 }
 
 /// Parse a label key back to labels
-#[allow(dead_code)]
 fn key_to_labels(key: &str) -> Labels {
     if key.is_empty() {
         return HashMap::new();
@@ -297,6 +412,12 @@ pub struct Metrics {
     pub instances_up: Gauge,
     /// Total instance restarts
     pub instance_restarts: LabeledCounter,
+    /// Restarts triggered by consecutive failed health checks
+    pub health_restarts: LabeledCounter,
+    /// Processes killed by the kernel at an instance's memory limit
+    pub instance_oom_kills: LabeledCounter,
+    /// Requests answered 429 by a service's `rate_limit`
+    pub requests_throttled: LabeledCounter,
     /// Current storage usage in bytes per instance
     pub instance_storage_bytes: LabeledGauge,
     /// Configured storage quota in bytes per instance (0 = unlimited)
@@ -308,14 +429,22 @@ pub struct Metrics {
 
 impl Metrics {
     pub fn new() -> Arc<Self> {
+        Self::with_limits(CardinalityLimits::default())
+    }
+
+    /// A registry whose labeled metrics are bounded by `limits`
+    pub fn with_limits(limits: CardinalityLimits) -> Arc<Self> {
         Arc::new(Self {
-            requests_total: LabeledCounter::new(),
-            request_duration_ms: LabeledHistogram::new(),
+            requests_total: LabeledCounter::with_limits(limits),
+            request_duration_ms: LabeledHistogram::with_limits(limits),
             instances_up: Gauge::new(),
-            instance_restarts: LabeledCounter::new(),
-            instance_storage_bytes: LabeledGauge::new(),
-            instance_storage_quota_bytes: LabeledGauge::new(),
-            instance_storage_usage_ratio: LabeledGauge::new(),
+            instance_restarts: LabeledCounter::with_limits(limits),
+            health_restarts: LabeledCounter::with_limits(limits),
+            instance_oom_kills: LabeledCounter::with_limits(limits),
+            requests_throttled: LabeledCounter::with_limits(limits),
+            instance_storage_bytes: LabeledGauge::with_limits(limits),
+            instance_storage_quota_bytes: LabeledGauge::with_limits(limits),
+            instance_storage_usage_ratio: LabeledGauge::with_limits(limits),
         })
     }
 
@@ -395,6 +524,54 @@ impl Metrics {
             }
         }
 
+        // tenement_health_restarts_total
+        output.push_str(
+            "\n# HELP tenement_health_restarts_total Restarts triggered by failed health checks\n",
+        );
+        output.push_str("# TYPE tenement_health_restarts_total counter\n");
+        for (labels, value) in self.health_restarts.all().await {
+            if labels.is_empty() {
+                output.push_str(&format!("tenement_health_restarts_total {}\n", value));
+            } else {
+                output.push_str(&format!(
+                    "tenement_health_restarts_total{{{}}} {}\n",
+                    labels, value
+                ));
+            }
+        }
+
+        // tenement_instance_oom_kills_total
+        output.push_str(
+            "\n# HELP tenement_instance_oom_kills_total Processes OOM-killed at the instance memory limit\n",
+        );
+        output.push_str("# TYPE tenement_instance_oom_kills_total counter\n");
+        for (labels, value) in self.instance_oom_kills.all().await {
+            if labels.is_empty() {
+                output.push_str(&format!("tenement_instance_oom_kills_total {}\n", value));
+            } else {
+                output.push_str(&format!(
+                    "tenement_instance_oom_kills_total{{{}}} {}\n",
+                    labels, value
+                ));
+            }
+        }
+
+        // tenement_requests_throttled_total
+        output.push_str(
+            "\n# HELP tenement_requests_throttled_total Requests rejected by a service rate limit\n",
+        );
+        output.push_str("# TYPE tenement_requests_throttled_total counter\n");
+        for (labels, value) in self.requests_throttled.all().await {
+            if labels.is_empty() {
+                output.push_str(&format!("tenement_requests_throttled_total {}\n", value));
+            } else {
+                output.push_str(&format!(
+                    "tenement_requests_throttled_total{{{}}} {}\n",
+                    labels, value
+                ));
+            }
+        }
+
         // tenement_instance_storage_bytes
         output
             .push_str("\n# HELP tenement_instance_storage_bytes Current storage usage in bytes\n");
@@ -459,6 +636,9 @@ impl Default for Metrics {
             request_duration_ms: LabeledHistogram::new(),
             instances_up: Gauge::new(),
             instance_restarts: LabeledCounter::new(),
+            health_restarts: LabeledCounter::new(),
+            instance_oom_kills: LabeledCounter::new(),
+            requests_throttled: LabeledCounter::new(),
             instance_storage_bytes: LabeledGauge::new(),
             instance_storage_quota_bytes: LabeledGauge::new(),
             instance_storage_usage_ratio: LabeledGauge::new(),
@@ -589,4 +769,115 @@ mod tests {
         assert!(output.contains("status=\"200\""));
         assert!(output.contains("tenement_instances_up 3"));
     }
+
+    fn instance_labels(process: &str, instance: &str) -> Labels {
+        HashMap::from([
+            ("process".to_string(), process.to_string()),
+            ("instance".to_string(), instance.to_string()),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_counter_folds_instances_past_limit() {
+        let labeled = LabeledCounter::with_limits(CardinalityLimits {
+            max_instances: 2,
+            max_series: 0,
+        });
+        for id in ["a", "b", "c", "d"] {
+            labeled.with_labels(&instance_labels("api", id)).await.inc();
+        }
+        // Existing series keep their own counter
+        labeled
+            .with_labels(&instance_labels("api", "a"))
+            .await
+            .inc();
+        // Other processes have their own budget
+        labeled
+            .with_labels(&instance_labels("web", "x"))
+            .await
+            .inc();
+
+        let all: HashMap<String, u64> = labeled.all().await.into_iter().collect();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all["instance=\"a\",process=\"api\""], 2);
+        assert_eq!(all["instance=\"b\",process=\"api\""], 1);
+        assert_eq!(all["instance=\"_other\",process=\"api\""], 2);
+        assert_eq!(all["instance=\"x\",process=\"web\""], 1);
+        // Per-process total is preserved
+        let api_total: u64 = all
+            .iter()
+            .filter(|(k, _)| k.contains("process=\"api\""))
+            .map(|(_, v)| v)
+            .sum();
+        assert_eq!(api_total, 5);
+    }
+
+    #[tokio::test]
+    async fn test_histogram_folds_id_label() {
+        let labeled = LabeledHistogram::with_limits(CardinalityLimits {
+            max_instances: 1,
+            max_series: 0,
+        });
+        let labels = |id: &str| {
+            HashMap::from([
+                ("process".to_string(), "api".to_string()),
+                ("id".to_string(), id.to_string()),
+            ])
+        };
+        labeled.with_labels(&labels("a")).await.observe(1.0);
+        labeled.with_labels(&labels("b")).await.observe(2.0);
+        labeled.with_labels(&labels("c")).await.observe(3.0);
+
+        let all: HashMap<String, Arc<Histogram>> = labeled.all().await.into_iter().collect();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all["id=\"_other\",process=\"api\""].get_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_series_overflow() {
+        let labeled = LabeledCounter::with_limits(CardinalityLimits {
+            max_instances: 0,
+            max_series: 2,
+        });
+        for status in ["200", "404", "500", "503"] {
+            let labels = HashMap::from([("status".to_string(), status.to_string())]);
+            labeled.with_labels(&labels).await.inc();
+        }
+        let all: HashMap<String, u64> = labeled.all().await.into_iter().collect();
+        // Two real series plus the overflow series
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[OVERFLOW_KEY], 2);
+    }
+
+    #[tokio::test]
+    async fn test_gauge_past_limit_is_not_exported() {
+        let labeled = LabeledGauge::with_limits(CardinalityLimits {
+            max_instances: 1,
+            max_series: 0,
+        });
+        labeled
+            .with_labels(&instance_labels("api", "a"))
+            .await
+            .set(10);
+        labeled
+            .with_labels(&instance_labels("api", "b"))
+            .await
+            .set(20);
+
+        let all = labeled.all().await;
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].1, 10);
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_by_default() {
+        let labeled = LabeledCounter::new();
+        for i in 0..50 {
+            labeled
+                .with_labels(&instance_labels("api", &i.to_string()))
+                .await
+                .inc();
+        }
+        assert_eq!(labeled.all().await.len(), 50);
+    }
 }
@@ -0,0 +1,495 @@
+//! Validation of interpolated instance paths
+//!
+//! Socket and working directory templates are checked when the config is
+//! loaded, so a typo in a placeholder or a stray `..` is a startup error
+//! rather than a socket quietly written somewhere unexpected:
+//!
+//! - socket and workdir templates only use placeholders that are filled in
+//!   (`{name}`, `{id}`, plus `{data_dir}` in `workdir`)
+//! - sockets are absolute and contain `{id}`, so instances never share one
+//! - no two services resolve to the same socket path
+//! - paths under `{data_dir}` stay under it after `..` is resolved
+//! - with `settings.allowed_roots` set, sockets and working directories must
+//!   resolve inside one of those roots (or the data dir)
+//!
+//! Socket checks are skipped with `settings.tcp_only`, which never uses them.
+//! `${VAR}` references are expanded from the daemon's environment first, as
+//! they are at spawn time.
+//!
+//! Instance IDs are checked at spawn time so that substituting one can't add
+//! path components, and a socket file left behind by a crashed instance is
+//! removed before its replacement binds (see [`remove_stale_socket`]). Once
+//! an instance has created its socket, the service's `socket_mode`,
+//! `socket_owner` and `socket_group` are applied to it (see
+//! [`apply_socket_permissions`]).
+
+use crate::config::{expand_host_env, Config, ProcessConfig};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Placeholders `socket` may use
+const SOCKET_PLACEHOLDERS: &[&str] = &["name", "id"];
+
+/// Placeholders `workdir` may use
+const WORKDIR_PLACEHOLDERS: &[&str] = &["name", "id", "data_dir"];
+
+/// Check every service's path templates, and sockets across services
+pub fn validate(config: &Config) -> Result<()> {
+    let roots = &config.settings.allowed_roots;
+    for root in roots {
+        if !root.is_absolute() {
+            anyhow::bail!(
+                "settings.allowed_roots entry '{}' must be an absolute path",
+                root.display()
+            );
+        }
+    }
+
+    let mut sockets: HashMap<PathBuf, &str> = HashMap::new();
+    let mut names: Vec<&String> = config.service.keys().collect();
+    names.sort();
+    for name in names {
+        let service = &config.service[name];
+        // With tcp_only, socket paths are never used
+        if !config.settings.tcp_only {
+            let socket = validate_socket(name, service, roots)?;
+            if let Some(other) = sockets.insert(socket.clone(), name) {
+                anyhow::bail!(
+                    "Services '{}' and '{}' both put instance sockets at '{}'. Use {{name}} in socket",
+                    other,
+                    name,
+                    socket.display()
+                );
+            }
+        }
+        if let Some(workdir) = &service.workdir {
+            validate_workdir(name, &expand_host_env(&workdir.to_string_lossy()), roots)?;
+        }
+        for value in std::iter::once(&service.command)
+            .chain(&service.args)
+            .chain(service.env.values())
+        {
+            check_data_dir_suffix(name, "command, args or env", &expand_host_env(value))?;
+        }
+    }
+    Ok(())
+}
+
+/// An instance ID must be a single, ordinary path component. IDs come from
+/// Host headers and end up in paths, commands and templates, so only
+/// letters, digits, `.`, `_` and `-` are allowed.
+pub fn validate_instance_id(id: &str) -> Result<()> {
+    if id.is_empty()
+        || id == "."
+        || id == ".."
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        anyhow::bail!(
+            "Invalid instance ID {:?}: it can't be empty, '.' or '..', and may only \
+             contain letters, digits, '.', '_' and '-'",
+            id
+        );
+    }
+    Ok(())
+}
+
+/// Remove a socket file nothing is listening on any more.
+///
+/// Returns whether a stale socket was removed. A path that isn't a socket
+/// is left for the process to deal with. Refuses (with an error) to touch a
+/// socket owned by another user or one that still accepts connections.
+pub async fn remove_stale_socket(path: &Path) -> Result<bool> {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to inspect {}", path.display()));
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        return Ok(false);
+    }
+    // SAFETY: geteuid has no preconditions and cannot fail
+    let euid = unsafe { libc::geteuid() };
+    if metadata.uid() != euid {
+        anyhow::bail!(
+            "Socket {} is owned by uid {}, not tenement (uid {}); refusing to remove it",
+            path.display(),
+            metadata.uid(),
+            euid
+        );
+    }
+
+    let connect = tokio::time::timeout(
+        std::time::Duration::from_secs(1),
+        tokio::net::UnixStream::connect(path),
+    )
+    .await;
+    match connect {
+        Ok(Ok(_)) => anyhow::bail!(
+            "Socket {} is in use by another process; refusing to remove it",
+            path.display()
+        ),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::ConnectionRefused => {}
+        Ok(Err(e)) => {
+            return Err(e).with_context(|| format!("Failed to probe socket {}", path.display()));
+        }
+        Err(_) => anyhow::bail!(
+            "Socket {} did not answer or refuse within 1s; refusing to remove it",
+            path.display()
+        ),
+    }
+
+    std::fs::remove_file(path)
+        .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    Ok(true)
+}
+
+/// Apply a service's `socket_mode`, `socket_owner` and `socket_group` to
+/// the socket at `path`, changing only what differs.
+///
+/// Returns false if nothing is there yet. A symlink is refused, so an
+/// instance can't point tenement's chmod or chown at some other file.
+pub fn apply_socket_permissions(path: &Path, service: &ProcessConfig) -> Result<bool> {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(m) => m,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to inspect {}", path.display()));
+        }
+    };
+    if metadata.file_type().is_symlink() {
+        anyhow::bail!(
+            "Socket {} is a symlink; refusing to change its permissions",
+            path.display()
+        );
+    }
+
+    let uid = match &service.socket_owner {
+        Some(owner) => Some(crate::hardening::resolve_uid(owner)?),
+        None => None,
+    };
+    let gid = match &service.socket_group {
+        Some(group) => Some(crate::hardening::resolve_gid(group)?),
+        None => None,
+    };
+    if uid.is_some_and(|uid| uid != metadata.uid()) || gid.is_some_and(|gid| gid != metadata.gid())
+    {
+        std::os::unix::fs::lchown(path, uid, gid)
+            .with_context(|| format!("Failed to chown {}", path.display()))?;
+    }
+    if let Some(mode) = service.socket_mode {
+        if metadata.mode() & 0o7777 != mode {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to chmod {}", path.display()))?;
+        }
+    }
+    Ok(true)
+}
+
+/// Resolve `.` and `..` without touching the filesystem
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match out.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    out.pop();
+                }
+                // Nothing is above the root
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => out.push(".."),
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// The socket template with `{name}` filled in, normalized
+fn validate_socket(name: &str, service: &ProcessConfig, roots: &[PathBuf]) -> Result<PathBuf> {
+    let template = &expand_host_env(&service.socket);
+    check_placeholders(name, "socket", template, SOCKET_PLACEHOLDERS)?;
+    if !template.contains("{id}") {
+        anyhow::bail!(
+            "Service '{}' socket '{}' doesn't contain {{id}}, so every instance would share one socket",
+            name,
+            template
+        );
+    }
+    let socket = Path::new(template);
+    if !socket.is_absolute() {
+        anyhow::bail!(
+            "Service '{}' socket '{}' must be an absolute path",
+            name,
+            template
+        );
+    }
+    let resolved = normalize(Path::new(&template.replace("{name}", name)));
+    if !roots.is_empty()
+        && !roots
+            .iter()
+            .any(|root| resolved.starts_with(normalize(root)))
+    {
+        anyhow::bail!(
+            "Service '{}' socket resolves to '{}', outside settings.allowed_roots ({})",
+            name,
+            resolved.display(),
+            display_roots(roots)
+        );
+    }
+    Ok(resolved)
+}
+
+fn validate_workdir(name: &str, template: &str, roots: &[PathBuf]) -> Result<()> {
+    check_placeholders(name, "workdir", template, WORKDIR_PLACEHOLDERS)?;
+    if check_data_dir_suffix(name, "workdir", template)? {
+        return Ok(());
+    }
+    if roots.is_empty() {
+        return Ok(());
+    }
+    let resolved = normalize(Path::new(&template.replace("{name}", name)));
+    if !resolved.is_absolute()
+        || !roots
+            .iter()
+            .any(|root| resolved.starts_with(normalize(root)))
+    {
+        anyhow::bail!(
+            "Service '{}' workdir resolves to '{}', outside settings.allowed_roots ({}) and {{data_dir}}",
+            name,
+            resolved.display(),
+            display_roots(roots)
+        );
+    }
+    Ok(())
+}
+
+/// For a value starting with `{data_dir}`, check the rest can't climb out of
+/// it. Returns whether the value is under `{data_dir}`.
+fn check_data_dir_suffix(name: &str, field: &str, value: &str) -> Result<bool> {
+    let Some(rest) = value.strip_prefix("{data_dir}") else {
+        return Ok(false);
+    };
+    // Only the path part of e.g. "{data_dir}/bin/app --serve" matters
+    let rest = rest.split(char::is_whitespace).next().unwrap_or_default();
+    let relative = normalize(Path::new(rest.trim_start_matches('/')));
+    if relative.starts_with("..") {
+        anyhow::bail!(
+            "Service '{}' {} value '{}' escapes {{data_dir}}",
+            name,
+            field,
+            value
+        );
+    }
+    Ok(true)
+}
+
+/// Reject `{...}` placeholders that interpolation wouldn't replace
+fn check_placeholders(name: &str, field: &str, value: &str, allowed: &[&str]) -> Result<()> {
+    let mut rest = value;
+    while let Some(start) = rest.find('{') {
+        let after = &rest[start + 1..];
+        let Some(end) = after.find('}') else {
+            break;
+        };
+        let placeholder = &after[..end];
+        let looks_like_placeholder = !placeholder.is_empty()
+            && placeholder
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if looks_like_placeholder && !allowed.contains(&placeholder) {
+            anyhow::bail!(
+                "Service '{}' {} '{}' uses unknown placeholder {{{}}}. Supported: {}",
+                name,
+                field,
+                value,
+                placeholder,
+                allowed
+                    .iter()
+                    .map(|p| format!("{{{}}}", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        rest = &after[end + 1..];
+    }
+    Ok(())
+}
+
+fn display_roots(roots: &[PathBuf]) -> String {
+    roots
+        .iter()
+        .map(|r| r.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Result<Config> {
+        Config::from_str(toml)
+    }
+
+    fn err(toml: &str) -> String {
+        config(toml).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+        assert_eq!(normalize(Path::new("/../x")), PathBuf::from("/x"));
+        assert_eq!(normalize(Path::new("a/../../b")), PathBuf::from("../b"));
+        assert_eq!(normalize(Path::new("a/../../..")), PathBuf::from("../.."));
+    }
+
+    #[test]
+    fn test_socket_templates() {
+        assert!(
+            err("[service.api]\ncommand = \"x\"\nsocket = \"/tmp/api-{ID}.sock\"\n")
+                .contains("unknown placeholder {ID}")
+        );
+        assert!(
+            err("[service.api]\ncommand = \"x\"\nsocket = \"/tmp/api.sock\"\n")
+                .contains("doesn't contain {id}")
+        );
+        assert!(
+            err("[service.api]\ncommand = \"x\"\nsocket = \"api-{id}.sock\"\n")
+                .contains("must be an absolute path")
+        );
+    }
+
+    #[test]
+    fn test_socket_collision_across_services() {
+        let msg = err(r#"
+[service.api]
+command = "./api"
+socket = "/tmp/app-{id}.sock"
+
+[service.worker]
+command = "./worker"
+socket = "/tmp/x/../app-{id}.sock"
+"#);
+        assert!(msg.contains("Services 'api' and 'worker'"), "{}", msg);
+
+        // The default template includes {name}
+        config("[service.api]\ncommand = \"a\"\n[service.worker]\ncommand = \"w\"\n").unwrap();
+    }
+
+    #[test]
+    fn test_allowed_roots() {
+        let toml = |socket: &str, workdir: &str| {
+            format!(
+                "[settings]\nallowed_roots = [\"/run/tenement\", \"/srv\"]\n\n\
+                 [service.api]\ncommand = \"x\"\nsocket = \"{}\"\nworkdir = \"{}\"\n",
+                socket, workdir
+            )
+        };
+        config(&toml("/run/tenement/{name}-{id}.sock", "/srv/api")).unwrap();
+        config(&toml("/run/tenement/{id}.sock", "{data_dir}/{id}")).unwrap();
+
+        let msg = err(&toml("/run/tenement/../../tmp/{id}.sock", "/srv/api"));
+        assert!(
+            msg.contains("socket resolves to '/tmp/{id}.sock'"),
+            "{}",
+            msg
+        );
+        let msg = err(&toml("/run/tenement/{id}.sock", "/srv/../etc"));
+        assert!(msg.contains("workdir resolves to '/etc'"), "{}", msg);
+        assert!(err(&toml("/run/tenement/{id}.sock", "app")).contains("outside"));
+    }
+
+    #[test]
+    fn test_data_dir_escape() {
+        assert!(err(
+            "[service.api]\ncommand = \"x\"\n[service.api.env]\nDB = \"{data_dir}/../../etc/db\"\n"
+        )
+        .contains("escapes {data_dir}"));
+        assert!(
+            err("[service.api]\ncommand = \"x\"\nworkdir = \"{data_dir}/{id}/../../..\"\n")
+                .contains("escapes {data_dir}")
+        );
+        config(
+            "[service.api]\ncommand = \"x\"\n[service.api.env]\nDB = \"{data_dir}/{id}/app.db\"\n",
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_remove_stale_socket() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("api-alice.sock");
+        assert!(!remove_stale_socket(&path).await.unwrap());
+
+        // A listening socket is left alone
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        let err = remove_stale_socket(&path).await.unwrap_err();
+        assert!(err.to_string().contains("in use"), "{}", err);
+        assert!(path.exists());
+
+        // Once the listener is gone the file is stale
+        drop(listener);
+        assert!(remove_stale_socket(&path).await.unwrap());
+        assert!(!path.exists());
+
+        // Not a socket: not ours to judge
+        std::fs::write(&path, "data").unwrap();
+        assert!(!remove_stale_socket(&path).await.unwrap());
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_apply_socket_permissions() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("api-alice.sock");
+        // SAFETY: getgid has no preconditions and cannot fail
+        let gid = unsafe { libc::getgid() };
+        let service = ProcessConfig {
+            socket_mode: Some(0o660),
+            socket_group: Some(gid.to_string()),
+            ..Default::default()
+        };
+        assert!(!apply_socket_permissions(&path, &service).unwrap());
+
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(apply_socket_permissions(&path, &service).unwrap());
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.mode() & 0o7777, 0o660);
+        assert_eq!(metadata.gid(), gid);
+
+        let link = dir.path().join("link.sock");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        let err = apply_socket_permissions(&link, &service).unwrap_err();
+        assert!(err.to_string().contains("symlink"), "{}", err);
+
+        let unknown = ProcessConfig {
+            socket_owner: Some("no-such-user-tenement".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_socket_permissions(&path, &unknown).is_err());
+    }
+
+    #[test]
+    fn test_validate_instance_id() {
+        validate_instance_id("alice").unwrap();
+        validate_instance_id("v1.2-beta_3").unwrap();
+        for id in [
+            "", ".", "..", "../etc", "a/b", "a\\b", "a\nb", "$(id)", "a;b", "`id`", "a b",
+        ] {
+            assert!(validate_instance_id(id).is_err(), "{:?}", id);
+        }
+    }
+}
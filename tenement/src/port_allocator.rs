@@ -1,19 +1,21 @@
 //! Port allocator for auto-assigning TCP ports to instances
 //!
-//! Manages a pool of ports in the range 30000-40000.
-//! Automatically assigns free ports to instances and tracks allocations.
+//! Manages a pool of ports, by default in the range 30000-40000
+//! (`settings.port_range`). Automatically assigns free ports to instances
+//! and tracks allocations.
 
 use std::collections::HashSet;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-/// Port range for auto-allocation
-const PORT_MIN: u16 = 30000;
-const PORT_MAX: u16 = 40000;
+/// Default port range for auto-allocation
+pub(crate) const PORT_MIN: u16 = 30000;
+pub(crate) const PORT_MAX: u16 = 40000;
 
 /// Port allocator that manages a pool of TCP ports
 ///
-/// Ports are allocated from the range 30000-40000 on a first-available basis.
+/// Ports are allocated from a range (default 30000-40000) on a
+/// first-available basis.
 /// Released ports are returned to the pool and can be reused.
 ///
 /// Thread-safe: uses RwLock for concurrent access.
@@ -23,14 +25,25 @@ pub struct PortAllocator {
     allocated: Arc<RwLock<HashSet<u16>>>,
     /// Next port to try allocating (optimization to avoid scanning from start)
     next_port: Arc<RwLock<u16>>,
+    /// Lowest port handed out
+    min: u16,
+    /// Highest port handed out
+    max: u16,
 }
 
 impl PortAllocator {
-    /// Create a new port allocator
+    /// Create a new port allocator for the default range
     pub fn new() -> Self {
+        Self::with_range(PORT_MIN, PORT_MAX)
+    }
+
+    /// Create a port allocator handing out ports `min..=max`
+    pub fn with_range(min: u16, max: u16) -> Self {
         Self {
             allocated: Arc::new(RwLock::new(HashSet::new())),
-            next_port: Arc::new(RwLock::new(PORT_MIN)),
+            next_port: Arc::new(RwLock::new(min)),
+            min,
+            max,
         }
     }
 
@@ -59,8 +72,8 @@ impl PortAllocator {
             if !allocated.contains(&current_port) {
                 // Found a free port
                 allocated.insert(current_port);
-                *next_port = if current_port == PORT_MAX {
-                    PORT_MIN
+                *next_port = if current_port == self.max {
+                    self.min
                 } else {
                     current_port + 1
                 };
@@ -68,8 +81,8 @@ impl PortAllocator {
             }
 
             // Move to next port, wrapping around
-            current_port = if current_port == PORT_MAX {
-                PORT_MIN
+            current_port = if current_port == self.max {
+                self.min
             } else {
                 current_port + 1
             };
@@ -78,14 +91,23 @@ impl PortAllocator {
             if current_port == start_port {
                 anyhow::bail!(
                     "No free ports available in range {}-{}. {} ports allocated.",
-                    PORT_MIN,
-                    PORT_MAX,
+                    self.min,
+                    self.max,
                     allocated.len()
                 );
             }
         }
     }
 
+    /// Mark a specific port as allocated (e.g. a port held by an instance
+    /// re-adopted after a restart).
+    ///
+    /// Returns false if the port was already allocated.
+    pub async fn reserve(&self, port: u16) -> bool {
+        let mut allocated = self.allocated.write().await;
+        allocated.insert(port)
+    }
+
     /// Release a port back to the pool
     ///
     /// The port becomes available for future allocations.
@@ -113,10 +135,15 @@ impl PortAllocator {
         allocated.len()
     }
 
-    /// Get the number of available ports
+    /// Get the number of available ports in the range
     pub async fn available_count(&self) -> usize {
-        let total = (PORT_MAX - PORT_MIN + 1) as usize;
-        total - self.allocated_count().await
+        let total = (self.max - self.min) as usize + 1;
+        let allocated = self.allocated.read().await;
+        let in_range = allocated
+            .iter()
+            .filter(|p| (self.min..=self.max).contains(*p))
+            .count();
+        total - in_range
     }
 
     /// Check if a specific port is currently allocated
@@ -373,4 +400,22 @@ mod tests {
         let allocated = allocator.allocated.read().await;
         assert!(allocated.contains(&PORT_MAX));
     }
+
+    #[tokio::test]
+    async fn test_custom_range() {
+        let allocator = PortAllocator::with_range(31000, 31002);
+        assert_eq!(allocator.available_count().await, 3);
+        // Ports reserved outside the range don't count against it
+        assert!(allocator.reserve(8080).await);
+        assert_eq!(allocator.available_count().await, 3);
+
+        assert_eq!(allocator.allocate().await.unwrap(), 31000);
+        assert_eq!(allocator.allocate().await.unwrap(), 31001);
+        assert_eq!(allocator.allocate().await.unwrap(), 31002);
+        let err = allocator.allocate().await.unwrap_err();
+        assert!(err.to_string().contains("31000-31002"));
+
+        allocator.release(31001).await;
+        assert_eq!(allocator.allocate().await.unwrap(), 31001);
+    }
 }
@@ -0,0 +1,202 @@
+//! Per-tenant rate limiting at the proxy
+//!
+//! A service with a `[service.X.rate_limit]` table gets token buckets holding
+//! up to `burst` requests, refilled at `requests_per_second`. A request takes
+//! one token from its bucket, or is answered with 429 Too Many Requests when
+//! the bucket is empty.
+//!
+//! ```toml
+//! [service.api.rate_limit]
+//! requests_per_second = 10
+//! burst = 20                          # default: requests_per_second, at least 1
+//! key = "subdomain"                   # default; or "instance"
+//! ```
+//!
+//! With `key = "subdomain"`, each subdomain has its own bucket: every
+//! `<id>.api` direct route, plus one for the weighted `api` route. With
+//! `key = "instance"`, each instance has one, and weighted requests count
+//! against the instance they are routed to.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before full ones are dropped
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// What a service's requests are counted by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitKey {
+    /// The request's subdomain (`alice.api`, or `api` for weighted routes)
+    #[default]
+    Subdomain,
+    /// The instance serving the request
+    Instance,
+}
+
+/// Rate limit for a service
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second per key
+    pub requests_per_second: f64,
+
+    /// Requests a key can make at once after being idle
+    /// (default: `requests_per_second`, at least 1)
+    #[serde(default)]
+    pub burst: Option<u32>,
+
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+impl RateLimitConfig {
+    /// Check the rate and burst are usable
+    pub fn validate(&self, service: &str) -> Result<()> {
+        if !self.requests_per_second.is_finite() || self.requests_per_second <= 0.0 {
+            anyhow::bail!(
+                "Service '{}' rate_limit.requests_per_second must be greater than 0",
+                service
+            );
+        }
+        if self.burst == Some(0) {
+            anyhow::bail!("Service '{}' rate_limit.burst must be at least 1", service);
+        }
+        Ok(())
+    }
+
+    /// Bucket size
+    pub fn capacity(&self) -> f64 {
+        match self.burst {
+            Some(burst) => f64::from(burst),
+            None => self.requests_per_second.ceil().max(1.0),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket will be full again, so it can be forgotten
+    full_at: Instant,
+}
+
+/// Token buckets for every rate-limited key
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a token from `key`'s bucket at `now`. Returns false, taking
+    /// nothing, if the bucket is empty.
+    pub fn check(&self, key: &str, limit: &RateLimitConfig, now: Instant) -> bool {
+        let capacity = limit.capacity();
+        let rate = limit.requests_per_second;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_IDLE_BUCKETS && !buckets.contains_key(key) {
+            buckets.retain(|_, b| b.full_at > now);
+        }
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        bucket.full_at = now + Duration::from_secs_f64((capacity - bucket.tokens) / rate);
+        allowed
+    }
+
+    /// Number of buckets held
+    pub fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+
+    /// Whether no bucket is held
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(requests_per_second: f64, burst: Option<u32>) -> RateLimitConfig {
+        RateLimitConfig {
+            requests_per_second,
+            burst,
+            key: RateLimitKey::Subdomain,
+        }
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let limiter = RateLimiter::new();
+        let limit = limit(2.0, Some(3));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check("api/alice", &limit, start));
+        }
+        assert!(!limiter.check("api/alice", &limit, start));
+
+        // Other keys have their own bucket
+        assert!(limiter.check("api/bob", &limit, start));
+
+        // 2 per second: one token back after half a second
+        let later = start + Duration::from_millis(500);
+        assert!(limiter.check("api/alice", &limit, later));
+        assert!(!limiter.check("api/alice", &limit, later));
+
+        // Never more than the burst, however long it was idle
+        let much_later = later + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.check("api/alice", &limit, much_later));
+        }
+        assert!(!limiter.check("api/alice", &limit, much_later));
+    }
+
+    #[test]
+    fn test_default_burst() {
+        assert_eq!(limit(10.0, None).capacity(), 10.0);
+        assert_eq!(limit(0.5, None).capacity(), 1.0);
+        assert_eq!(limit(0.5, Some(4)).capacity(), 4.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(limit(1.0, None).validate("api").is_ok());
+        assert!(limit(0.0, None).validate("api").is_err());
+        assert!(limit(f64::NAN, None).validate("api").is_err());
+        assert!(limit(1.0, Some(0)).validate("api").is_err());
+    }
+
+    #[test]
+    fn test_full_buckets_are_dropped() {
+        let limiter = RateLimiter::new();
+        let limit = limit(1.0, Some(1));
+        let start = Instant::now();
+        for i in 0..MAX_IDLE_BUCKETS {
+            limiter.check(&format!("api/{}", i), &limit, start);
+        }
+        assert_eq!(limiter.len(), MAX_IDLE_BUCKETS);
+
+        // Every bucket has refilled, so a new key clears them out
+        limiter.check("api/new", &limit, start + Duration::from_secs(2));
+        assert_eq!(limiter.len(), 1);
+    }
+}
@@ -12,8 +12,12 @@
 //!
 //! Per-app network isolation (per-app bridge + sidecar-as-container) is a
 //! hardening follow-up.
+//!
+//! The plain `container` runtime ([`super::ContainerRuntime`]) shares the
+//! image/command/limits part of the argument list via [`image_run_args`].
 
 /// docker present on PATH (or the usual location)?
+#[cfg(any(feature = "quark", feature = "sandbox"))]
 pub fn docker_available() -> bool {
     #[cfg(target_os = "linux")]
     {
@@ -31,6 +35,7 @@ pub fn docker_available() -> bool {
     }
 }
 
+#[cfg(any(feature = "quark", feature = "sandbox"))]
 #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
 fn docker_run_args(
     runtime: &str,
@@ -48,11 +53,24 @@ fn docker_run_args(
         "--network".to_string(),
         "host".to_string(),
     ];
+    args.extend(image_run_args(image, config));
+    args
+}
+
+/// The `run` arguments after the engine-specific options: resource limits,
+/// hardening, mounts, env, workdir, then the image and command
+pub fn image_run_args(image: &str, config: &crate::runtime::SpawnConfig) -> Vec<String> {
+    let mut args = Vec::new();
 
     if let Some(memory_mb) = config.memory_limit_mb {
         if memory_mb > 0 {
             args.push("--memory".to_string());
             args.push(format!("{memory_mb}m"));
+            // The engine takes memory plus swap
+            if let Some(swap_mb) = config.memory_swap_max_mb {
+                args.push("--memory-swap".to_string());
+                args.push(format!("{}m", u64::from(memory_mb) + u64::from(swap_mb)));
+            }
         }
     }
 
@@ -61,6 +79,47 @@ fn docker_run_args(
         args.push(cpu_shares.clamp(2, 10000).to_string());
     }
 
+    if let Some(max_pids) = config.max_pids {
+        args.push("--pids-limit".to_string());
+        args.push(max_pids.to_string());
+    }
+
+    if let Some(cpus) = &config.cpuset {
+        args.push("--cpuset-cpus".to_string());
+        args.push(cpus.clone());
+    }
+
+    // The engine applies its own default seccomp profile, so `seccomp` needs
+    // no flag here
+    let hardening = &config.hardening;
+    if hardening.no_new_privs {
+        args.push("--security-opt".to_string());
+        args.push("no-new-privileges".to_string());
+    }
+    if let Some(capabilities) = hardening.capabilities {
+        args.push("--cap-drop".to_string());
+        args.push("ALL".to_string());
+        for cap in capabilities {
+            args.push("--cap-add".to_string());
+            args.push(cap.to_string());
+        }
+    }
+    if hardening.read_only_root {
+        args.push("--read-only".to_string());
+    }
+    let custom_tmp = hardening
+        .tmpfs
+        .iter()
+        .any(|t| t.path == std::path::Path::new("/tmp"));
+    if hardening.private_tmp && !custom_tmp {
+        args.push("--tmpfs".to_string());
+        args.push("/tmp".to_string());
+    }
+    for tmpfs in &hardening.tmpfs {
+        args.push("--tmpfs".to_string());
+        args.push(format!("{}:{}", tmpfs.path.display(), tmpfs.options()));
+    }
+
     // Neutralize any image ENTRYPOINT (railpack bakes `/bin/bash -c`) so the
     // explicit command runs directly, not as args to the entrypoint.
     // Harmless for entrypoint-less images (e.g. `docker import`ed rootfs).
@@ -79,9 +138,13 @@ fn docker_run_args(
         ));
     }
 
-    for (k, v) in &config.env {
+    // Names only: the engine takes the values from its own environment, set
+    // by the caller, so they never show up in `ps`
+    let mut names: Vec<&String> = config.env.keys().collect();
+    names.sort();
+    for name in names {
         args.push("-e".to_string());
-        args.push(format!("{k}={v}"));
+        args.push(name.clone());
     }
 
     if let Some(wd) = &config.workdir {
@@ -98,7 +161,7 @@ fn docker_run_args(
     args
 }
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", any(feature = "quark", feature = "sandbox")))]
 pub mod linux {
     use crate::runtime::SpawnConfig;
     use anyhow::{bail, Context, Result};
@@ -116,7 +179,8 @@ pub mod linux {
         let name = format!("ten-{}", uuid::Uuid::new_v4().simple());
 
         let mut cmd = Command::new("docker");
-        cmd.args(super::docker_run_args(runtime, &name, &image, config));
+        cmd.args(super::docker_run_args(runtime, &name, &image, config))
+            .envs(&config.env);
 
         let out = cmd
             .output()
@@ -132,7 +196,7 @@ pub mod linux {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, any(feature = "quark", feature = "sandbox")))]
 mod tests {
     use super::docker_run_args;
     use crate::runtime::SpawnConfig;
@@ -146,7 +210,10 @@ mod tests {
             workdir: Some(PathBuf::from("/app")),
             image: Some("tinyhost/app:abc".into()),
             memory_limit_mb: Some(256),
+            memory_swap_max_mb: Some(128),
             cpu_shares: Some(500),
+            max_pids: Some(64),
+            cpuset: Some("0-3".into()),
             ..Default::default()
         };
 
@@ -154,9 +221,18 @@ mod tests {
         assert!(args
             .windows(2)
             .any(|w| w[0] == "--memory" && w[1] == "256m"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--memory-swap" && w[1] == "384m"));
         assert!(args
             .windows(2)
             .any(|w| w[0] == "--cpu-shares" && w[1] == "500"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--pids-limit" && w[1] == "64"));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--cpuset-cpus" && w[1] == "0-3"));
         assert!(args.contains(&"--entrypoint".to_string()));
         assert_eq!(args.last(), Some(&"app.py".to_string()));
     }
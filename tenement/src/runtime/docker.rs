@@ -0,0 +1,385 @@
+//! Container runtime - runs a service's OCI image with Docker or Podman.
+//!
+//! For existing containerized apps: `isolation = "container"` plus an `image`.
+//! Unlike the quark/sandbox runtimes, the container is not detached. Tenement
+//! runs `<engine> run --rm` as an ordinary child and supervises that client,
+//! which relays the container's stdout/stderr (captured like any process),
+//! forwards SIGTERM (`--sig-proxy`) and exits with the container's exit code.
+//!
+//! ```text
+//! <engine> run --rm --name ten-<socket stem>-<hash> --network host --sig-proxy=true \
+//!   -v <socket>.d:<socket dir> \
+//!   [--memory ..] [--cpu-shares ..] [--pids-limit ..] [-v mounts] [-e KEY ...] [-w workdir] \
+//!   <image> [command args...]
+//! ```
+//!
+//! The app binds the allocated `PORT` on the host network, as with the other
+//! runtimes. Env values are passed in the engine's environment, with only the
+//! names on its command line. The socket directory is usually shared by every
+//! instance, so the container gets its own directory `<socket>.d` mounted in
+//! its place: `SOCKET_PATH` works inside the container, the socket appears in
+//! `<socket>.d` on the host with a symlink at `SOCKET_PATH` pointing to it,
+//! and no other instance's socket is visible (skipped with
+//! `settings.tcp_only`). The container name is derived from the socket path,
+//! so a container left behind by a crashed daemon is removed before the
+//! instance is spawned again.
+//!
+//! ## Engine discovery (first match wins)
+//!
+//! 1. explicit path passed to [`ContainerRuntime::with_engine`];
+//! 2. `TENEMENT_CONTAINER_ENGINE` environment variable (a path, or a name
+//!    looked up on `PATH`, e.g. `podman`);
+//! 3. `docker`, then `podman`, on `PATH`.
+
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that selects the container engine binary.
+pub const ENGINE_ENV: &str = "TENEMENT_CONTAINER_ENGINE";
+
+/// Runtime that runs images attached via `docker run` or `podman run`.
+pub struct ContainerRuntime {
+    /// Explicit engine path. When `None`, discovered from env/PATH at use time.
+    engine: Option<PathBuf>,
+}
+
+impl ContainerRuntime {
+    pub fn new() -> Self {
+        Self { engine: None }
+    }
+
+    /// Construct with an explicit engine binary (highest precedence).
+    pub fn with_engine(path: PathBuf) -> Self {
+        Self { engine: Some(path) }
+    }
+
+    /// Resolve the engine binary: explicit path -> env var -> docker/podman on PATH.
+    fn find_engine(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.engine {
+            return path.exists().then(|| path.clone());
+        }
+        if let Ok(engine) = std::env::var(ENGINE_ENV) {
+            // An override that can't be found is a config error, not a
+            // reason to silently use another engine
+            return if engine.contains('/') {
+                let path = PathBuf::from(engine);
+                path.exists().then_some(path)
+            } else {
+                find_on_path(&engine)
+            };
+        }
+        find_on_path("docker").or_else(|| find_on_path("podman"))
+    }
+}
+
+impl Default for ContainerRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(super) fn find_on_path(name: &str) -> Option<PathBuf> {
+    std::env::var("PATH").ok()?.split(':').find_map(|dir| {
+        let path = Path::new(dir).join(name);
+        path.exists().then_some(path)
+    })
+}
+
+/// Container name for the instance using `socket`: readable, and stable so a
+/// leftover container from an earlier run can be found and removed
+pub fn container_name(socket: &Path) -> String {
+    let stem: String = socket
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(40)
+        .collect();
+    // FNV-1a keeps instances with the same socket file name in different
+    // directories apart
+    let hash = crate::hash::fnv1a(socket.as_os_str().as_encoded_bytes().iter().copied());
+    format!("ten-{}-{:08x}", stem, hash as u32)
+}
+
+/// Directory an isolated instance creates its socket in: `<socket>.d`,
+/// mounted over the socket's directory inside the container
+pub fn socket_dir(socket: &Path) -> PathBuf {
+    let mut dir = socket.as_os_str().to_owned();
+    dir.push(".d");
+    PathBuf::from(dir)
+}
+
+/// Create an empty [`socket_dir`] and link `socket` to the socket the
+/// instance will create in it
+pub(super) fn prepare_socket_dir(socket: &Path) -> Result<PathBuf> {
+    let dir = socket_dir(socket);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)
+            .with_context(|| format!("Failed to clear socket directory {:?}", dir))?;
+    }
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create socket directory {:?}", dir))?;
+    let (Some(file), Some(dir_name)) = (socket.file_name(), dir.file_name()) else {
+        anyhow::bail!("Socket path {:?} has no file name", socket);
+    };
+    if std::fs::symlink_metadata(socket).is_ok() {
+        std::fs::remove_file(socket).with_context(|| format!("Failed to replace {:?}", socket))?;
+    }
+    std::os::unix::fs::symlink(Path::new(dir_name).join(file), socket)
+        .with_context(|| format!("Failed to link {:?}", socket))?;
+    Ok(dir)
+}
+
+/// Arguments for `<engine> run` (everything after the binary)
+fn run_args(name: &str, image: &str, config: &SpawnConfig) -> Vec<String> {
+    let mut args = vec![
+        "run".to_string(),
+        "--rm".to_string(),
+        "--name".to_string(),
+        name.to_string(),
+        "--network".to_string(),
+        "host".to_string(),
+        "--sig-proxy=true".to_string(),
+    ];
+    if config.env.contains_key("SOCKET_PATH") {
+        if let Some(dir) = config.socket.parent() {
+            args.push("-v".to_string());
+            args.push(format!(
+                "{}:{}",
+                socket_dir(&config.socket).display(),
+                dir.display()
+            ));
+        }
+    }
+    args.extend(super::container::image_run_args(image, config));
+    args
+}
+
+#[async_trait]
+impl Runtime for ContainerRuntime {
+    async fn spawn(&self, config: &SpawnConfig) -> Result<RuntimeHandle> {
+        use std::process::Stdio;
+        use tokio::process::Command;
+
+        let engine = self.find_engine().with_context(|| {
+            format!(
+                "No container engine found. Install docker or podman, or set \
+                 {ENGINE_ENV}=/path/to/engine."
+            )
+        })?;
+        let image = config.image.as_deref().context(
+            "container isolation needs an `image` (OCI image ref) in the service config",
+        )?;
+        let name = container_name(&config.socket);
+
+        // A container from a daemon that crashed would hold the name
+        let _ = Command::new(&engine)
+            .args(["rm", "-f", name.as_str()])
+            .output()
+            .await;
+        if let Some(dir) = config.socket.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create socket directory {:?}", dir))?;
+        }
+        if config.env.contains_key("SOCKET_PATH") {
+            prepare_socket_dir(&config.socket)?;
+        }
+
+        let mut cmd = Command::new(&engine);
+        cmd.args(run_args(&name, image, config))
+            .envs(&config.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Own process group, like the process runtime
+        #[cfg(unix)]
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to run {:?} for image {}", engine, image))?;
+
+        Ok(RuntimeHandle::Container {
+            child,
+            name,
+            engine,
+            socket: config.socket.clone(),
+        })
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Container
+    }
+
+    fn is_available(&self) -> bool {
+        self.find_engine().is_some()
+    }
+
+    fn name(&self) -> &'static str {
+        "container"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn spawn_config() -> SpawnConfig {
+        let env = HashMap::from([
+            ("PORT".to_string(), "31000".to_string()),
+            (
+                "SOCKET_PATH".to_string(),
+                "/run/tenement/api-alice.sock".to_string(),
+            ),
+        ]);
+        SpawnConfig {
+            command: "./server".to_string(),
+            args: vec!["--port".to_string(), "31000".to_string()],
+            env,
+            socket: PathBuf::from("/run/tenement/api-alice.sock"),
+            image: Some("ghcr.io/example/api:1".to_string()),
+            memory_limit_mb: Some(128),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_container_runtime_type_and_name() {
+        let rt = ContainerRuntime::new();
+        assert_eq!(rt.runtime_type(), RuntimeType::Container);
+        assert_eq!(rt.name(), "container");
+    }
+
+    #[test]
+    fn test_container_name_is_stable_and_safe() {
+        let a = container_name(Path::new("/run/tenement/api-alice.sock"));
+        assert_eq!(a, container_name(Path::new("/run/tenement/api-alice.sock")));
+        assert!(a.starts_with("ten-api-alice-"));
+        assert_ne!(a, container_name(Path::new("/srv/other/api-alice.sock")));
+        let odd = container_name(Path::new("/tmp/api.bob smith.sock"));
+        assert!(odd.starts_with("ten-api-bob-smith-"));
+    }
+
+    #[test]
+    fn test_run_args() {
+        let config = spawn_config();
+        let args = run_args("ten-api-alice-1", "ghcr.io/example/api:1", &config);
+        assert_eq!(&args[..4], ["run", "--rm", "--name", "ten-api-alice-1"]);
+        assert!(!args.contains(&"-d".to_string()));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-v" && w[1] == "/run/tenement/api-alice.sock.d:/run/tenement"));
+        // Values stay off the command line
+        assert!(args.windows(2).any(|w| w[0] == "-e" && w[1] == "PORT"));
+        assert!(!args
+            .iter()
+            .any(|a| a.contains("31000") && a.starts_with("PORT")));
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--memory" && w[1] == "128m"));
+        let image = args
+            .iter()
+            .position(|a| a == "ghcr.io/example/api:1")
+            .unwrap();
+        assert_eq!(&args[image + 1..], ["./server", "--port", "31000"]);
+
+        // tcp_only: no SOCKET_PATH, so no socket mount
+        let mut config = spawn_config();
+        config.env.remove("SOCKET_PATH");
+        let args = run_args("ten-api-alice-1", "ghcr.io/example/api:1", &config);
+        assert!(!args.iter().any(|a| a.ends_with(":/run/tenement")));
+        assert!(!args.contains(&"--read-only".to_string()));
+
+        // Strict profile: no-new-privs, one capability, read-only root
+        config.hardening = crate::hardening::SecurityProfile::Strict.hardening();
+        let args = run_args("ten-api-alice-1", "ghcr.io/example/api:1", &config);
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "--security-opt" && w[1] == "no-new-privileges"));
+        assert!(args
+            .windows(4)
+            .any(|w| w == ["--cap-drop", "ALL", "--cap-add", "CAP_NET_BIND_SERVICE"]));
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(args.windows(2).any(|w| w[0] == "--tmpfs" && w[1] == "/tmp"));
+
+        // A sized /tmp replaces the profile's plain one
+        config.hardening.tmpfs = vec![
+            crate::hardening::Tmpfs {
+                path: PathBuf::from("/tmp"),
+                size_mb: Some(64),
+            },
+            crate::hardening::Tmpfs {
+                path: PathBuf::from("/run"),
+                size_mb: None,
+            },
+        ];
+        let args = run_args("ten-api-alice-1", "ghcr.io/example/api:1", &config);
+        let tmpfs: Vec<_> = args
+            .windows(2)
+            .filter(|w| w[0] == "--tmpfs")
+            .map(|w| w[1].as_str())
+            .collect();
+        assert_eq!(tmpfs, ["/tmp:mode=1777,size=64m", "/run:mode=1777"]);
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_explicit_engine() {
+        // A stand-in engine that prints its arguments and PORT, and exits
+        let dir = tempfile::TempDir::new().unwrap();
+        let engine = dir.path().join("engine");
+        std::fs::write(&engine, "#!/bin/sh\necho \"$@\"\necho \"PORT=$PORT\"\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&engine, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let rt = ContainerRuntime::with_engine(engine);
+        assert!(rt.is_available());
+
+        let mut config = spawn_config();
+        config.socket = dir.path().join("sock/api-alice.sock");
+        let mut handle = rt.spawn(&config).await.unwrap();
+        assert_eq!(handle.runtime_type(), RuntimeType::Container);
+        assert!(dir.path().join("sock/api-alice.sock.d").is_dir());
+        assert_eq!(
+            std::fs::read_link(&config.socket).unwrap(),
+            Path::new("api-alice.sock.d/api-alice.sock")
+        );
+
+        let RuntimeHandle::Container { child, name, .. } = &mut handle else {
+            panic!("expected a container handle");
+        };
+        assert_eq!(name, &container_name(&config.socket));
+        let output = child.stdout.take().unwrap();
+        use tokio::io::AsyncBufReadExt;
+        let mut lines = tokio::io::BufReader::new(output).lines();
+        let line = lines.next_line().await.unwrap().unwrap();
+        assert!(line.starts_with("run --rm --name ten-api-alice-"));
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "PORT=31000");
+        handle.kill().await.unwrap();
+        assert!(!dir.path().join("sock/api-alice.sock.d").exists());
+
+        // Missing image is an error, not a bare `run`
+        config.image = None;
+        assert!(rt.spawn(&config).await.is_err());
+    }
+}
@@ -13,11 +13,29 @@
 //! - Windows (no KVM)
 //! - Fly.io (nested virt explicitly disabled)
 //! - Most cloud VMs without nested virtualization
+//!
+//! ## Snapshots
+//! With `VmConfig::snapshot_dir` set, a cold-booted VM is snapshotted once
+//! its app accepts vsock connections: the VM is paused, its state and memory
+//! are written to `<snapshot_dir>/<instance>/` and it resumes. The next spawn
+//! of that instance loads the snapshot and resumes the VM where it left off
+//! (milliseconds instead of a boot). A snapshot older than the kernel or
+//! rootfs, or one that fails to load, is discarded and the VM boots
+//! normally. Full snapshots take as much disk as the VM has memory.
+//!
+//! This is a runtime feature only: `Hypervisor` refuses to start firecracker
+//! services, so idle stops and wakes never take or restore a snapshot. It
+//! applies when `FirecrackerRuntime::spawn` is called directly.
+//!
+//! ## Networking
+//! vsock only carries traffic into the guest. With `VmConfig::network` set,
+//! the VM also gets a host tap device as `eth0` (see [`super::TapDevice`]),
+//! so the guest can make outbound calls.
 
-use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig, TapDevice, VmConfig};
 use anyhow::Result;
 use async_trait::async_trait;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[cfg(target_os = "linux")]
 use anyhow::Context;
@@ -32,12 +50,53 @@ use tokio::net::UnixStream;
 #[cfg(target_os = "linux")]
 use tokio::process::{Child, Command};
 #[cfg(target_os = "linux")]
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 /// Global CID counter for vsock (starts at 3, as 0/1/2 are reserved)
 #[cfg(target_os = "linux")]
 static NEXT_CID: AtomicU32 = AtomicU32::new(3);
 
+/// How long a freshly booted guest gets to accept connections before it is
+/// left unsnapshotted
+#[cfg(target_os = "linux")]
+const SNAPSHOT_READY_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Snapshot files of one instance under the snapshot dir
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SnapshotFiles {
+    /// Device and vCPU state
+    vmstate: PathBuf,
+    /// Guest memory
+    memory: PathBuf,
+}
+
+impl SnapshotFiles {
+    fn new(snapshot_dir: &Path, instance_name: &str) -> Self {
+        let dir = snapshot_dir.join(instance_name);
+        Self {
+            vmstate: dir.join("vmstate"),
+            memory: dir.join("memory"),
+        }
+    }
+
+    /// Whether a complete snapshot exists that is newer than the kernel and
+    /// rootfs it was taken from
+    fn is_usable(&self, vm_config: &VmConfig) -> bool {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let (Some(vmstate), Some(_)) = (modified(&self.vmstate), modified(&self.memory)) else {
+            return false;
+        };
+        [&vm_config.kernel, &vm_config.rootfs]
+            .iter()
+            .all(|source| modified(source).is_some_and(|m| m <= vmstate))
+    }
+
+    fn remove(&self) {
+        std::fs::remove_file(&self.vmstate).ok();
+        std::fs::remove_file(&self.memory).ok();
+    }
+}
+
 /// Runtime that spawns Firecracker microVMs
 ///
 /// This runtime uses Firecracker's HTTP API over a Unix socket to configure
@@ -113,17 +172,29 @@ impl FirecrackerRuntime {
     /// Send an HTTP PUT request to Firecracker's API socket
     #[cfg(target_os = "linux")]
     async fn api_put(socket_path: &PathBuf, endpoint: &str, body: &str) -> Result<()> {
+        Self::api_request(socket_path, "PUT", endpoint, body).await
+    }
+
+    /// Send an HTTP request with a JSON body to Firecracker's API socket
+    #[cfg(target_os = "linux")]
+    async fn api_request(
+        socket_path: &PathBuf,
+        method: &str,
+        endpoint: &str,
+        body: &str,
+    ) -> Result<()> {
         let mut stream = UnixStream::connect(socket_path).await.with_context(|| {
             format!("Failed to connect to Firecracker API at {:?}", socket_path)
         })?;
 
         let request = format!(
-            "PUT {} HTTP/1.1\r\n\
+            "{} {} HTTP/1.1\r\n\
              Host: localhost\r\n\
              Content-Type: application/json\r\n\
              Content-Length: {}\r\n\
              \r\n\
              {}",
+            method,
             endpoint,
             body.len(),
             body
@@ -183,6 +254,202 @@ impl FirecrackerRuntime {
         )
     }
 
+    /// Start a firecracker process serving its API on `api_socket`
+    #[cfg(target_os = "linux")]
+    async fn start_firecracker(firecracker_bin: &PathBuf, api_socket: &PathBuf) -> Result<Child> {
+        let mut child = Command::new(firecracker_bin)
+            .arg("--api-sock")
+            .arg(api_socket)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to spawn firecracker at {:?}", firecracker_bin))?;
+
+        if let Err(e) = Self::wait_for_api_socket(api_socket, Duration::from_secs(5)).await {
+            // Kill the process if API socket never became ready
+            child.start_kill().ok();
+            std::fs::remove_file(api_socket).ok();
+            return Err(e);
+        }
+        Ok(child)
+    }
+
+    /// Start a VM from `snapshot` and resume it
+    #[cfg(target_os = "linux")]
+    async fn restore_snapshot(
+        firecracker_bin: &PathBuf,
+        api_socket: &PathBuf,
+        snapshot: &SnapshotFiles,
+    ) -> Result<Child> {
+        let mut child = Self::start_firecracker(firecracker_bin, api_socket).await?;
+        let load = serde_json::json!({
+            "snapshot_path": snapshot.vmstate,
+            "mem_backend": {"backend_type": "File", "backend_path": snapshot.memory},
+            "resume_vm": true,
+        });
+        if let Err(e) = Self::api_put(api_socket, "/snapshot/load", &load.to_string()).await {
+            child.start_kill().ok();
+            let _ = child.wait().await;
+            std::fs::remove_file(api_socket).ok();
+            return Err(e.context("Failed to load snapshot"));
+        }
+        Ok(child)
+    }
+
+    /// Pause the VM, write a full snapshot to `snapshot` and resume it
+    #[cfg(target_os = "linux")]
+    async fn create_snapshot(api_socket: &PathBuf, snapshot: &SnapshotFiles) -> Result<()> {
+        if let Some(dir) = snapshot.vmstate.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create snapshot dir {:?}", dir))?;
+        }
+        // Written under temporary names so a half-written snapshot is never
+        // loaded
+        let vmstate = snapshot.vmstate.with_extension("tmp");
+        let memory = snapshot.memory.with_extension("tmp");
+
+        Self::api_request(api_socket, "PATCH", "/vm", r#"{"state": "Paused"}"#)
+            .await
+            .context("Failed to pause VM")?;
+        let create = serde_json::json!({
+            "snapshot_type": "Full",
+            "snapshot_path": vmstate,
+            "mem_file_path": memory,
+        });
+        let created = Self::api_put(api_socket, "/snapshot/create", &create.to_string()).await;
+        Self::api_request(api_socket, "PATCH", "/vm", r#"{"state": "Resumed"}"#)
+            .await
+            .context("Failed to resume VM")?;
+        created.context("Failed to create snapshot")?;
+
+        // The memory file goes first: a vmstate without it isn't usable
+        std::fs::rename(&memory, &snapshot.memory)?;
+        std::fs::rename(&vmstate, &snapshot.vmstate)?;
+        Ok(())
+    }
+
+    /// Whether the guest app accepts a vsock connection on `port`
+    #[cfg(target_os = "linux")]
+    async fn guest_accepts(vsock_socket: &PathBuf, port: u32) -> bool {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let Ok(stream) = UnixStream::connect(vsock_socket).await else {
+            return false;
+        };
+        let (reader, mut writer) = stream.into_split();
+        if writer
+            .write_all(format!("CONNECT {}\n", port).as_bytes())
+            .await
+            .is_err()
+        {
+            return false;
+        }
+        let mut line = String::new();
+        let mut reader = BufReader::new(reader);
+        matches!(
+            tokio::time::timeout(Duration::from_secs(1), reader.read_line(&mut line)).await,
+            Ok(Ok(_))
+        ) && line.starts_with("OK ")
+    }
+
+    /// Snapshot a freshly booted VM once its app accepts connections
+    #[cfg(target_os = "linux")]
+    async fn snapshot_when_ready(
+        api_socket: PathBuf,
+        vsock_socket: PathBuf,
+        port: u32,
+        snapshot: SnapshotFiles,
+    ) {
+        let start = std::time::Instant::now();
+        while !Self::guest_accepts(&vsock_socket, port).await {
+            // The VM was stopped, or the app never came up
+            if start.elapsed() > SNAPSHOT_READY_TIMEOUT || !api_socket.exists() {
+                debug!(
+                    "Not snapshotting {:?}: guest never became ready",
+                    api_socket
+                );
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        match Self::create_snapshot(&api_socket, &snapshot).await {
+            Ok(()) => info!("Snapshot written to {:?}", snapshot.vmstate),
+            Err(e) => warn!("Failed to snapshot VM {:?}: {:#}", api_socket, e),
+        }
+    }
+
+    /// Configure a started firecracker process and boot the VM
+    #[cfg(target_os = "linux")]
+    async fn boot(
+        api_socket: &PathBuf,
+        vsock_socket: &Path,
+        vm_config: &VmConfig,
+        cid: u32,
+        tap: Option<&TapDevice>,
+    ) -> Result<()> {
+        // 3. Configure boot source
+        let mut boot_args = "console=ttyS0 reboot=k panic=1 pci=off".to_string();
+        if let Some(tap) = tap {
+            boot_args.push(' ');
+            boot_args.push_str(&tap.boot_arg());
+        }
+        let boot_source = format!(
+            r#"{{"kernel_image_path": "{}", "boot_args": "{}"}}"#,
+            vm_config.kernel.display(),
+            boot_args
+        );
+        Self::api_put(api_socket, "/boot-source", &boot_source)
+            .await
+            .context("Failed to configure boot source")?;
+
+        // 4. Configure root drive
+        let drive_config = format!(
+            r#"{{"drive_id": "rootfs", "path_on_host": "{}", "is_root_device": true, "is_read_only": false}}"#,
+            vm_config.rootfs.display()
+        );
+        Self::api_put(api_socket, "/drives/rootfs", &drive_config)
+            .await
+            .context("Failed to configure root drive")?;
+
+        // 5. Configure machine (vcpus and memory)
+        let machine_config = format!(
+            r#"{{"vcpu_count": {}, "mem_size_mib": {}}}"#,
+            vm_config.vcpus, vm_config.memory_mb
+        );
+        Self::api_put(api_socket, "/machine-config", &machine_config)
+            .await
+            .context("Failed to configure machine")?;
+
+        // 6. Configure vsock device
+        let vsock_config = format!(
+            r#"{{"guest_cid": {}, "uds_path": "{}"}}"#,
+            cid,
+            vsock_socket.display()
+        );
+        Self::api_put(api_socket, "/vsock", &vsock_config)
+            .await
+            .context("Failed to configure vsock")?;
+
+        // 7. Attach the tap as eth0
+        if let Some(tap) = tap {
+            let iface = serde_json::json!({
+                "iface_id": "eth0",
+                "host_dev_name": tap.name,
+                "guest_mac": tap.guest_mac(),
+            });
+            Self::api_put(api_socket, "/network-interfaces/eth0", &iface.to_string())
+                .await
+                .context("Failed to configure network interface")?;
+        }
+
+        // 8. Start the VM
+        let start_action = r#"{"action_type": "InstanceStart"}"#;
+        Self::api_put(api_socket, "/actions", start_action)
+            .await
+            .context("Failed to start VM")
+    }
+
     /// Get detailed availability status
     pub fn availability_details(&self) -> String {
         let mut issues = Vec::new();
@@ -248,7 +515,7 @@ impl Runtime for FirecrackerRuntime {
                 );
             }
 
-            let _firecracker_bin = self.find_firecracker().context(
+            let firecracker_bin = self.find_firecracker().context(
                 "Firecracker binary not found.\n\
                 Install from: https://github.com/firecracker-microvm/firecracker/releases\n\
                 Place in /usr/local/bin/firecracker or add to PATH.",
@@ -298,83 +565,80 @@ impl Runtime for FirecrackerRuntime {
                 vm_config.vcpus
             );
 
-            // 1. Spawn firecracker process with API socket
-            let child = Command::new(&firecracker_bin)
-                .arg("--api-sock")
-                .arg(&api_socket)
-                .stdin(std::process::Stdio::null())
-                .stdout(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::piped())
-                .spawn()
-                .with_context(|| format!("Failed to spawn firecracker at {:?}", firecracker_bin))?;
-
-            info!("Firecracker process started, waiting for API socket...");
-
-            // 2. Wait for API socket to be ready
-            if let Err(e) = Self::wait_for_api_socket(&api_socket, Duration::from_secs(5)).await {
-                // Kill the process if API socket never became ready
-                drop(child);
-                std::fs::remove_file(&api_socket).ok();
-                return Err(e);
-            }
-
-            info!("Firecracker API socket ready, configuring VM...");
-
-            // Helper to clean up on error
-            let cleanup = |child: Child, api_socket: &PathBuf, vsock_socket: &PathBuf| {
-                drop(child);
-                std::fs::remove_file(api_socket).ok();
-                std::fs::remove_file(vsock_socket).ok();
+            // The tap has to exist before a snapshot that uses it is loaded
+            let tap = match &vm_config.network {
+                Some(network) => {
+                    let tap = TapDevice::allocate(network, instance_name)?;
+                    tap.create().await?;
+                    info!(
+                        "Tap {} for {}: host {}, guest {}",
+                        tap.name, instance_name, tap.host_ip, tap.guest_ip
+                    );
+                    Some(tap)
+                }
+                None => None,
             };
 
-            // 3. Configure boot source
-            let boot_args = "console=ttyS0 reboot=k panic=1 pci=off";
-            let boot_source = format!(
-                r#"{{"kernel_image_path": "{}", "boot_args": "{}"}}"#,
-                vm_config.kernel.display(),
-                boot_args
-            );
-            if let Err(e) = Self::api_put(&api_socket, "/boot-source", &boot_source).await {
-                cleanup(child, &api_socket, &vsock_socket);
-                return Err(e.context("Failed to configure boot source"));
-            }
-
-            // 4. Configure root drive
-            let drive_config = format!(
-                r#"{{"drive_id": "rootfs", "path_on_host": "{}", "is_root_device": true, "is_read_only": false}}"#,
-                vm_config.rootfs.display()
-            );
-            if let Err(e) = Self::api_put(&api_socket, "/drives/rootfs", &drive_config).await {
-                cleanup(child, &api_socket, &vsock_socket);
-                return Err(e.context("Failed to configure root drive"));
+            let snapshot = vm_config
+                .snapshot_dir
+                .as_ref()
+                .map(|dir| SnapshotFiles::new(dir, instance_name));
+            // Only reached by direct callers of this runtime: the hypervisor
+            // doesn't start firecracker services, so wakes never restore.
+            // A snapshot has its tap and addresses baked in
+            let restorable = |s: &&SnapshotFiles| {
+                s.is_usable(vm_config) && tap.as_ref().is_none_or(TapDevice::is_preferred)
+            };
+            if let Some(snapshot) = snapshot.as_ref().filter(restorable) {
+                let started = std::time::Instant::now();
+                match Self::restore_snapshot(&firecracker_bin, &api_socket, snapshot).await {
+                    Ok(_child) => {
+                        info!(
+                            "Firecracker VM restored from {:?} in {:?}",
+                            snapshot.vmstate,
+                            started.elapsed()
+                        );
+                        return Ok(RuntimeHandle::Firecracker {
+                            api_socket,
+                            vsock_socket,
+                            cid,
+                            port: vm_config.vsock_port,
+                            tap,
+                        });
+                    }
+                    Err(e) => {
+                        // Boot normally; a fresh snapshot replaces this one
+                        warn!("Discarding snapshot {:?}: {:#}", snapshot.vmstate, e);
+                        snapshot.remove();
+                        std::fs::remove_file(&vsock_socket).ok();
+                    }
+                }
             }
 
-            // 5. Configure machine (vcpus and memory)
-            let machine_config = format!(
-                r#"{{"vcpu_count": {}, "mem_size_mib": {}}}"#,
-                vm_config.vcpus, vm_config.memory_mb
-            );
-            if let Err(e) = Self::api_put(&api_socket, "/machine-config", &machine_config).await {
-                cleanup(child, &api_socket, &vsock_socket);
-                return Err(e.context("Failed to configure machine"));
-            }
+            // 1-2. Spawn firecracker and wait for its API socket
+            let mut child = match Self::start_firecracker(&firecracker_bin, &api_socket).await {
+                Ok(child) => child,
+                Err(e) => {
+                    if let Some(tap) = &tap {
+                        tap.destroy().await;
+                    }
+                    return Err(e);
+                }
+            };
 
-            // 6. Configure vsock device
-            let vsock_config = format!(
-                r#"{{"guest_cid": {}, "uds_path": "{}"}}"#,
-                cid,
-                vsock_socket.display()
-            );
-            if let Err(e) = Self::api_put(&api_socket, "/vsock", &vsock_config).await {
-                cleanup(child, &api_socket, &vsock_socket);
-                return Err(e.context("Failed to configure vsock"));
-            }
+            info!("Firecracker API socket ready, configuring VM...");
 
-            // 7. Start the VM
-            let start_action = r#"{"action_type": "InstanceStart"}"#;
-            if let Err(e) = Self::api_put(&api_socket, "/actions", start_action).await {
-                cleanup(child, &api_socket, &vsock_socket);
-                return Err(e.context("Failed to start VM"));
+            // 3-8. Configure and boot the VM
+            if let Err(e) =
+                Self::boot(&api_socket, &vsock_socket, vm_config, cid, tap.as_ref()).await
+            {
+                child.start_kill().ok();
+                std::fs::remove_file(&api_socket).ok();
+                std::fs::remove_file(&vsock_socket).ok();
+                if let Some(tap) = &tap {
+                    tap.destroy().await;
+                }
+                return Err(e);
             }
 
             info!(
@@ -383,11 +647,21 @@ impl Runtime for FirecrackerRuntime {
                 vsock_socket.display()
             );
 
+            if let Some(snapshot) = snapshot {
+                tokio::spawn(Self::snapshot_when_ready(
+                    api_socket.clone(),
+                    vsock_socket.clone(),
+                    vm_config.vsock_port,
+                    snapshot,
+                ));
+            }
+
             Ok(RuntimeHandle::Firecracker {
                 api_socket,
                 vsock_socket,
                 cid,
                 port: vm_config.vsock_port,
+                tap,
             })
         }
     }
@@ -437,6 +711,45 @@ mod tests {
         assert!(!details.is_empty());
     }
 
+    #[test]
+    fn test_snapshot_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let kernel = dir.path().join("vmlinux");
+        let rootfs = dir.path().join("rootfs.ext4");
+        std::fs::write(&kernel, "kernel").unwrap();
+        std::fs::write(&rootfs, "rootfs").unwrap();
+        let vm_config = VmConfig {
+            kernel,
+            rootfs: rootfs.clone(),
+            snapshot_dir: Some(dir.path().join("snapshots")),
+            ..Default::default()
+        };
+
+        let snapshot = SnapshotFiles::new(dir.path().join("snapshots").as_path(), "worker-1");
+        assert!(snapshot.vmstate.ends_with("snapshots/worker-1/vmstate"));
+        assert!(!snapshot.is_usable(&vm_config));
+
+        // Memory alone isn't a snapshot
+        std::fs::create_dir_all(snapshot.memory.parent().unwrap()).unwrap();
+        std::fs::write(&snapshot.memory, "memory").unwrap();
+        assert!(!snapshot.is_usable(&vm_config));
+        std::fs::write(&snapshot.vmstate, "vmstate").unwrap();
+        assert!(snapshot.is_usable(&vm_config));
+
+        // A rebuilt rootfs invalidates it
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&rootfs)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(!snapshot.is_usable(&vm_config));
+
+        snapshot.remove();
+        assert!(!snapshot.memory.exists() && !snapshot.vmstate.exists());
+    }
+
     #[test]
     fn test_with_binary() {
         let runtime = FirecrackerRuntime::with_binary(PathBuf::from("/custom/firecracker"));
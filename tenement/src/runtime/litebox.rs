@@ -204,7 +204,16 @@ mod tests {
             mounts: Vec::new(),
             image: None,
             memory_limit_mb: None,
+            memory_high_mb: None,
+            memory_swap_max_mb: None,
             cpu_shares: None,
+            max_pids: None,
+            cpuset: None,
+            systemd_properties: Vec::new(),
+            hardening: Default::default(),
+            priority: Default::default(),
+            user_namespace: None,
+            network: None,
         }
     }
 
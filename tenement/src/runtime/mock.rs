@@ -0,0 +1,295 @@
+//! Mock runtime - records spawns and fakes processes in memory
+//!
+//! Nothing is executed. Each spawn gets a [`MockProcess`] that answers HTTP
+//! on the instance's port and on its `SOCKET_PATH` (or on the Unix socket
+//! alone when it has no port) with 200, or 503 once marked unhealthy, so
+//! health checks, readiness gating and the proxy behave as they would
+//! against a real backend. Tests make it crash with [`MockProcess::exit`].
+
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+/// Runtime that fakes processes instead of running them
+#[derive(Debug, Default)]
+pub struct MockRuntime {
+    processes: Mutex<Vec<Arc<MockProcess>>>,
+    fail_next: Mutex<Option<String>>,
+}
+
+impl MockRuntime {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the next spawn fail with `message`
+    pub fn fail_next_spawn(&self, message: impl Into<String>) {
+        *self.fail_next.lock().unwrap() = Some(message.into());
+    }
+
+    /// Every process spawned so far, oldest first
+    pub fn processes(&self) -> Vec<Arc<MockProcess>> {
+        self.processes.lock().unwrap().clone()
+    }
+
+    /// Number of spawns so far (including restarts)
+    pub fn spawn_count(&self) -> usize {
+        self.processes.lock().unwrap().len()
+    }
+
+    /// Most recent process spawned on `socket`
+    pub fn process(&self, socket: &Path) -> Option<Arc<MockProcess>> {
+        self.processes
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|p| p.config.socket == socket)
+            .cloned()
+    }
+}
+
+/// Fake processes don't outlive their runtime, so a finished test frees its
+/// ports and sockets for the next one
+impl Drop for MockRuntime {
+    fn drop(&mut self) {
+        for process in self.processes.lock().unwrap().iter() {
+            process.exit(0);
+        }
+    }
+}
+
+#[async_trait]
+impl Runtime for MockRuntime {
+    async fn spawn(&self, config: &SpawnConfig) -> Result<RuntimeHandle> {
+        if let Some(message) = self.fail_next.lock().unwrap().take() {
+            anyhow::bail!(message);
+        }
+
+        let process = Arc::new(MockProcess {
+            config: config.clone(),
+            exit_code: Mutex::new(None),
+            healthy: AtomicBool::new(true),
+            servers: Mutex::new(Vec::new()),
+        });
+        let servers = serve(process.clone()).await?;
+        *process.servers.lock().unwrap() = servers;
+
+        self.processes.lock().unwrap().push(process.clone());
+        Ok(RuntimeHandle::Mock {
+            process,
+            socket: config.socket.clone(),
+        })
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Process
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+}
+
+/// A fake process spawned by [`MockRuntime`]
+#[derive(Debug)]
+pub struct MockProcess {
+    config: SpawnConfig,
+    exit_code: Mutex<Option<i32>>,
+    healthy: AtomicBool,
+    servers: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl MockProcess {
+    /// The spawn config it was started with
+    pub fn config(&self) -> &SpawnConfig {
+        &self.config
+    }
+
+    /// Environment it was started with
+    pub fn env(&self) -> &HashMap<String, String> {
+        &self.config.env
+    }
+
+    /// TCP port it listens on
+    pub fn port(&self) -> Option<u16> {
+        self.config.env.get("PORT").and_then(|p| p.parse().ok())
+    }
+
+    /// Unix socket it listens on: `SOCKET_PATH`, or the spawn socket when
+    /// it has no port
+    pub fn socket(&self) -> Option<PathBuf> {
+        match (self.config.env.get("SOCKET_PATH"), self.port()) {
+            (Some(path), _) => Some(PathBuf::from(path)),
+            (None, None) => Some(self.config.socket.clone()),
+            (None, Some(_)) => None,
+        }
+    }
+
+    /// Make requests return 503 (false) or 200 (true)
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    /// Simulate the process exiting with `code`. Its listener closes.
+    /// Does nothing if it already exited.
+    pub fn exit(&self, code: i32) {
+        let mut exit_code = self.exit_code.lock().unwrap();
+        if exit_code.is_some() {
+            return;
+        }
+        *exit_code = Some(code);
+        for server in self.servers.lock().unwrap().drain(..) {
+            server.abort();
+        }
+        if let Some(socket) = self.socket() {
+            std::fs::remove_file(socket).ok();
+        }
+    }
+
+    /// Exit code, once it has exited
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.exit_code.lock().unwrap()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.exit_code().is_none()
+    }
+}
+
+/// Listen where the instance is expected to and answer every request
+async fn serve(process: Arc<MockProcess>) -> Result<Vec<JoinHandle<()>>> {
+    let mut servers = Vec::new();
+    if let Some(port) = process.port() {
+        let listener = bind_tcp(port)
+            .await
+            .with_context(|| format!("Mock process failed to bind port {}", port))?;
+        let process = process.clone();
+        servers.push(tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(respond(stream, process.clone()));
+            }
+        }));
+    }
+    if let Some(socket) = process.socket() {
+        std::fs::remove_file(&socket).ok();
+        let listener = tokio::net::UnixListener::bind(&socket)
+            .with_context(|| format!("Mock process failed to bind {}", socket.display()))?;
+        servers.push(tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(respond(stream, process.clone()));
+            }
+        }));
+    }
+    Ok(servers)
+}
+
+/// Bind `port`, waiting briefly for the aborted listener of an exited
+/// process that had the port before to be dropped
+async fn bind_tcp(port: u16) -> std::io::Result<tokio::net::TcpListener> {
+    let mut attempts = 0;
+    loop {
+        match tokio::net::TcpListener::bind(("127.0.0.1", port)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && attempts < 100 => {
+                attempts += 1;
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Read one request head and reply 200 or 503
+async fn respond<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, process: Arc<MockProcess>) {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buf[..n]),
+        }
+    }
+    let (status, body) = if process.healthy.load(Ordering::SeqCst) {
+        ("200 OK", "ok")
+    } else {
+        ("503 Service Unavailable", "unhealthy")
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_config(port: u16) -> SpawnConfig {
+        SpawnConfig {
+            command: "./server".to_string(),
+            env: HashMap::from([("PORT".to_string(), port.to_string())]),
+            socket: PathBuf::from(format!("/tmp/tenement-mock-{}.sock", port)),
+            ..Default::default()
+        }
+    }
+
+    async fn get(port: u16) -> Option<String> {
+        let mut stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+            .await
+            .ok()?;
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.ok()?;
+        Some(response)
+    }
+
+    #[tokio::test]
+    async fn test_mock_process_serves_and_exits() {
+        let port = crate::PortAllocator::new().allocate().await.unwrap();
+        let runtime = MockRuntime::new();
+        let mut handle = runtime.spawn(&spawn_config(port)).await.unwrap();
+        assert_eq!(runtime.spawn_count(), 1);
+        assert!(handle.is_running().await);
+        assert!(get(port).await.unwrap().starts_with("HTTP/1.1 200"));
+
+        let process = runtime.processes()[0].clone();
+        process.set_healthy(false);
+        assert!(get(port).await.unwrap().starts_with("HTTP/1.1 503"));
+
+        process.exit(3);
+        assert!(!handle.is_running().await);
+        assert_eq!(handle.exit_code(), Some(3));
+        assert!(get(port).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_mock_runtime_fail_next_spawn() {
+        let port = crate::PortAllocator::new().allocate().await.unwrap();
+        let runtime = MockRuntime::new();
+        runtime.fail_next_spawn("boom");
+        let err = runtime.spawn(&spawn_config(port)).await.unwrap_err();
+        assert_eq!(err.to_string(), "boom");
+        assert_eq!(runtime.spawn_count(), 0);
+
+        let mut handle = runtime.spawn(&spawn_config(port)).await.unwrap();
+        handle.kill().await.unwrap();
+        assert_eq!(handle.exit_code(), Some(137));
+    }
+}
@@ -19,13 +19,31 @@ mod sandbox;
 #[cfg(feature = "quark")]
 mod quark;
 
-// Shared docker/containerd helper for the container runtimes (quark, sandbox).
-#[cfg(any(feature = "quark", feature = "sandbox"))]
-mod container;
+#[cfg(any(test, feature = "testing"))]
+mod mock;
 
+// Shared docker/containerd helper for the container runtimes (quark, sandbox,
+// container).
+mod container;
+mod docker;
+mod oci;
+mod priority;
+mod systemd;
+mod tap;
+mod wasm;
+
+pub use docker::ContainerRuntime;
 pub use litebox::LiteBoxRuntime;
-pub use namespace::NamespaceRuntime;
+pub use namespace::{NamespaceRuntime, UserNamespace};
+pub use oci::OciRuntime;
+pub use priority::{IoClass, IoNice, Priority};
 pub use process::ProcessRuntime;
+pub use systemd::SystemdRuntime;
+pub use tap::{Network, NetworkMode, PrivateNetwork, TapDevice, VethLink};
+pub use wasm::WasmRuntime;
+
+#[cfg(any(test, feature = "testing"))]
+pub use mock::{MockProcess, MockRuntime};
 
 #[cfg(feature = "firecracker")]
 pub use firecracker::FirecrackerRuntime;
@@ -39,6 +57,7 @@ pub use sandbox::SandboxRuntime;
 #[cfg(feature = "quark")]
 pub use quark::QuarkRuntime;
 
+use crate::hardening::Hardening;
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
@@ -61,6 +80,14 @@ pub enum RuntimeType {
     Litebox,
     /// Quark - KVM-backed OCI runtime that boots the bundle rootfs as the guest /
     Quark,
+    /// An OCI image run by Docker or Podman, supervised like a process
+    Container,
+    /// A rootfs run as a container by plain runc, from a generated OCI bundle
+    Oci,
+    /// A WASI module or component run by wasmtime
+    Wasm,
+    /// A process in a transient systemd scope, limited by systemd
+    Systemd,
     Firecracker,
     Qemu,
 }
@@ -73,6 +100,10 @@ impl std::fmt::Display for RuntimeType {
             RuntimeType::Sandbox => write!(f, "sandbox"),
             RuntimeType::Litebox => write!(f, "litebox"),
             RuntimeType::Quark => write!(f, "quark"),
+            RuntimeType::Container => write!(f, "container"),
+            RuntimeType::Oci => write!(f, "oci"),
+            RuntimeType::Wasm => write!(f, "wasm"),
+            RuntimeType::Systemd => write!(f, "systemd"),
             RuntimeType::Firecracker => write!(f, "firecracker"),
             RuntimeType::Qemu => write!(f, "qemu"),
         }
@@ -89,9 +120,13 @@ impl std::str::FromStr for RuntimeType {
             "sandbox" | "gvisor" => Ok(RuntimeType::Sandbox),
             "litebox" => Ok(RuntimeType::Litebox),
             "quark" => Ok(RuntimeType::Quark),
+            "container" => Ok(RuntimeType::Container),
+            "oci" => Ok(RuntimeType::Oci),
+            "wasm" => Ok(RuntimeType::Wasm),
+            "systemd" => Ok(RuntimeType::Systemd),
             "firecracker" => Ok(RuntimeType::Firecracker),
             "qemu" => Ok(RuntimeType::Qemu),
-            _ => anyhow::bail!("Unknown runtime type: {}. Use 'process', 'namespace', 'sandbox', 'litebox', 'quark', 'firecracker', or 'qemu'", s),
+            _ => anyhow::bail!("Unknown runtime type: {}. Use 'process', 'namespace', 'sandbox', 'litebox', 'quark', 'container', 'oci', 'wasm', 'systemd', 'firecracker', or 'qemu'", s),
         }
     }
 }
@@ -105,9 +140,16 @@ pub enum RuntimeHandle {
     /// A bare process
     Process { child: Child, socket: PathBuf },
     /// A namespaced process (Linux PID + Mount namespaces)
-    Namespace { child: Child, socket: PathBuf },
+    Namespace {
+        child: Child,
+        socket: PathBuf,
+        /// Uplink of a private network, if any
+        veth: Option<VethLink>,
+    },
     /// A LiteBox-sandboxed process, supervised via an external runner binary
     Litebox { child: Child, socket: PathBuf },
+    /// A WASI module or component, supervised via the wasmtime CLI
+    Wasm { child: Child, socket: PathBuf },
     /// A Firecracker microVM
     #[allow(dead_code)]
     Firecracker {
@@ -119,6 +161,8 @@ pub enum RuntimeHandle {
         cid: u32,
         /// Guest vsock port
         port: u32,
+        /// Host tap device, when the VM has networking
+        tap: Option<TapDevice>,
     },
     /// A QEMU microVM
     #[allow(dead_code)]
@@ -129,6 +173,8 @@ pub enum RuntimeHandle {
         qmp_socket: PathBuf,
         /// Path to virtio-serial socket for guest communication
         serial_socket: PathBuf,
+        /// virtiofsd serving the shared data dir, if any
+        virtiofsd: Option<Child>,
     },
     /// A gVisor (runsc) container, run via docker/containerd
     /// (`docker run -d --runtime=runsc ...`). Tracked by container name, like
@@ -151,6 +197,59 @@ pub enum RuntimeHandle {
         /// Socket path (unused for TCP routing; kept for the trait)
         socket: PathBuf,
     },
+    /// A container run attached by Docker or Podman (`<engine> run --rm ...`).
+    /// `child` is the engine client, which relays signals, output and the
+    /// exit code; the container itself is removed by name.
+    Container {
+        child: Child,
+        /// Container name
+        name: String,
+        /// docker or podman binary
+        engine: PathBuf,
+        socket: PathBuf,
+    },
+    /// A container run attached by runc (`runc run --bundle ...`) from a
+    /// generated bundle. `child` is the runc process; the container is
+    /// deleted by id and the bundle removed when it is killed.
+    Oci {
+        child: Child,
+        /// runc container id
+        id: String,
+        /// runc binary
+        runc: PathBuf,
+        /// Bundle directory holding `config.json`
+        bundle: PathBuf,
+        socket: PathBuf,
+    },
+    /// A process run in a transient scope by `systemd-run --scope`, which
+    /// execs it in place: `child` is the app itself. Stopping the unit kills
+    /// everything in the scope.
+    Systemd {
+        child: Child,
+        /// Unit name, without the `.scope` suffix
+        unit: String,
+        /// systemctl binary
+        systemctl: PathBuf,
+        /// Whether the unit belongs to the user's service manager
+        user: bool,
+        socket: PathBuf,
+    },
+    /// A process re-adopted from persisted state after a daemon restart.
+    /// Its `Child` belonged to the previous daemon, so it is tracked and
+    /// signalled by PID (and process group) only; output is not captured.
+    Adopted {
+        /// PID of the process (also its process group leader)
+        pid: u32,
+        /// Runtime the process was originally spawned with
+        runtime: RuntimeType,
+        socket: PathBuf,
+    },
+    /// A fake process from [`MockRuntime`] (tests only)
+    #[cfg(any(test, feature = "testing"))]
+    Mock {
+        process: std::sync::Arc<MockProcess>,
+        socket: PathBuf,
+    },
 }
 
 impl RuntimeHandle {
@@ -160,10 +259,17 @@ impl RuntimeHandle {
             RuntimeHandle::Process { socket, .. } => socket,
             RuntimeHandle::Namespace { socket, .. } => socket,
             RuntimeHandle::Litebox { socket, .. } => socket,
+            RuntimeHandle::Wasm { socket, .. } => socket,
             RuntimeHandle::Firecracker { vsock_socket, .. } => vsock_socket,
             RuntimeHandle::Qemu { serial_socket, .. } => serial_socket,
             RuntimeHandle::Sandbox { socket, .. } => socket,
             RuntimeHandle::Quark { socket, .. } => socket,
+            RuntimeHandle::Container { socket, .. } => socket,
+            RuntimeHandle::Oci { socket, .. } => socket,
+            RuntimeHandle::Systemd { socket, .. } => socket,
+            RuntimeHandle::Adopted { socket, .. } => socket,
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { socket, .. } => socket,
         }
     }
 
@@ -173,10 +279,17 @@ impl RuntimeHandle {
             RuntimeHandle::Process { .. } => RuntimeType::Process,
             RuntimeHandle::Namespace { .. } => RuntimeType::Namespace,
             RuntimeHandle::Litebox { .. } => RuntimeType::Litebox,
+            RuntimeHandle::Wasm { .. } => RuntimeType::Wasm,
             RuntimeHandle::Sandbox { .. } => RuntimeType::Sandbox,
             RuntimeHandle::Quark { .. } => RuntimeType::Quark,
+            RuntimeHandle::Container { .. } => RuntimeType::Container,
+            RuntimeHandle::Oci { .. } => RuntimeType::Oci,
+            RuntimeHandle::Systemd { .. } => RuntimeType::Systemd,
             RuntimeHandle::Firecracker { .. } => RuntimeType::Firecracker,
             RuntimeHandle::Qemu { .. } => RuntimeType::Qemu,
+            RuntimeHandle::Adopted { runtime, .. } => *runtime,
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { .. } => RuntimeType::Process,
         }
     }
 
@@ -198,36 +311,74 @@ impl RuntimeHandle {
         match self {
             RuntimeHandle::Process { child, .. }
             | RuntimeHandle::Namespace { child, .. }
-            | RuntimeHandle::Litebox { child, .. } => child.id(),
+            | RuntimeHandle::Litebox { child, .. }
+            | RuntimeHandle::Wasm { child, .. }
+            | RuntimeHandle::Container { child, .. }
+            | RuntimeHandle::Oci { child, .. }
+            | RuntimeHandle::Systemd { child, .. } => child.id(),
             RuntimeHandle::Qemu { child, .. } => child.id(),
+            RuntimeHandle::Adopted { pid, .. } => Some(*pid),
             // VM/sandbox/container runtimes don't expose a simple PID
             RuntimeHandle::Firecracker { .. }
             | RuntimeHandle::Sandbox { .. }
             | RuntimeHandle::Quark { .. } => None,
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { .. } => None,
         }
     }
 
-    /// Kill the underlying process/VM
-    pub async fn kill(&mut self) -> Result<()> {
+    /// Ask the process (and its process group) to exit with SIGTERM.
+    /// Returns false for handles that can't be signalled this way (VMs,
+    /// containers); callers should fall back to `kill`.
+    pub fn terminate(&self) -> bool {
         match self {
-            RuntimeHandle::Process { child, .. }
-            | RuntimeHandle::Namespace { child, .. }
-            | RuntimeHandle::Litebox { child, .. } => {
-                // Kill the entire process group (child + all descendants)
+            RuntimeHandle::Process { .. }
+            | RuntimeHandle::Namespace { .. }
+            | RuntimeHandle::Litebox { .. }
+            | RuntimeHandle::Wasm { .. }
+            | RuntimeHandle::Container { .. }
+            | RuntimeHandle::Oci { .. }
+            | RuntimeHandle::Systemd { .. }
+            | RuntimeHandle::Adopted { .. } => match self.pid() {
                 #[cfg(unix)]
-                if let Some(pid) = child.id() {
+                Some(pid) => {
                     unsafe {
-                        libc::kill(-(pid as i32), libc::SIGKILL);
+                        libc::kill(-(pid as i32), libc::SIGTERM);
+                        libc::kill(pid as i32, libc::SIGTERM);
                     }
+                    true
+                }
+                _ => false,
+            },
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { process, .. } => {
+                process.exit(128 + 15);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Kill the underlying process/VM
+    pub async fn kill(&mut self) -> Result<()> {
+        match self {
+            RuntimeHandle::Process { child, .. }
+            | RuntimeHandle::Litebox { child, .. }
+            | RuntimeHandle::Wasm { child, .. } => {
+                Self::kill_process_group(child).await;
+                Ok(())
+            }
+            RuntimeHandle::Namespace { child, veth, .. } => {
+                Self::kill_process_group(child).await;
+                if let Some(veth) = veth.take() {
+                    veth.destroy().await;
                 }
-                // Also kill via tokio handle and reap the zombie
-                let _ = child.kill().await;
-                let _ = child.wait().await;
                 Ok(())
             }
             RuntimeHandle::Firecracker {
                 api_socket,
                 vsock_socket,
+                tap,
                 ..
             } => {
                 // For Firecracker, we need to find and kill the process that owns the API socket.
@@ -262,11 +413,15 @@ impl RuntimeHandle {
                     std::fs::remove_file(api_socket).ok();
                     std::fs::remove_file(vsock_socket).ok();
 
+                    if let Some(tap) = tap {
+                        tap.destroy().await;
+                    }
+
                     Ok(())
                 }
                 #[cfg(not(target_os = "linux"))]
                 {
-                    let _ = (api_socket, vsock_socket);
+                    let _ = (api_socket, vsock_socket, tap);
                     anyhow::bail!("Firecracker only supported on Linux")
                 }
             }
@@ -274,6 +429,7 @@ impl RuntimeHandle {
                 child,
                 qmp_socket,
                 serial_socket,
+                virtiofsd,
             } => {
                 // For QEMU, we can send quit command via QMP or just kill the process
                 // Try graceful shutdown first via QMP
@@ -284,6 +440,9 @@ impl RuntimeHandle {
 
                 // If still running, force kill
                 let _ = child.kill().await;
+                if let Some(virtiofsd) = virtiofsd {
+                    let _ = virtiofsd.kill().await;
+                }
 
                 // Clean up sockets
                 std::fs::remove_file(qmp_socket).ok();
@@ -311,6 +470,98 @@ impl RuntimeHandle {
                 }
                 Ok(())
             }
+            RuntimeHandle::Container {
+                child,
+                name,
+                engine,
+                socket,
+            } => {
+                // Killing the client alone would leave the container running
+                let _ = tokio::process::Command::new(&*engine)
+                    .args(["rm", "-f", name.as_str()])
+                    .output()
+                    .await;
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    unsafe {
+                        libc::kill(-(pid as i32), libc::SIGKILL);
+                    }
+                }
+                let _ = child.kill().await;
+                let _ = child.wait().await;
+                std::fs::remove_dir_all(docker::socket_dir(socket)).ok();
+                Ok(())
+            }
+            RuntimeHandle::Oci {
+                child,
+                id,
+                runc,
+                bundle,
+                socket,
+            } => {
+                // `runc run` can die before its container; delete it by id
+                let _ = tokio::process::Command::new(&*runc)
+                    .args(["kill", id.as_str(), "KILL"])
+                    .output()
+                    .await;
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    unsafe {
+                        libc::kill(-(pid as i32), libc::SIGKILL);
+                    }
+                }
+                let _ = child.kill().await;
+                let _ = child.wait().await;
+                let _ = tokio::process::Command::new(&*runc)
+                    .args(["delete", "--force", id.as_str()])
+                    .output()
+                    .await;
+                std::fs::remove_dir_all(&*bundle).ok();
+                std::fs::remove_dir_all(docker::socket_dir(socket)).ok();
+                Ok(())
+            }
+            RuntimeHandle::Systemd {
+                child,
+                unit,
+                systemctl,
+                user,
+                ..
+            } => {
+                // Stopping the scope also gets processes that left the group
+                let mut stop = tokio::process::Command::new(&*systemctl);
+                if *user {
+                    stop.arg("--user");
+                }
+                let _ = stop
+                    .args(["stop", &format!("{}.scope", unit)])
+                    .output()
+                    .await;
+                #[cfg(unix)]
+                if let Some(pid) = child.id() {
+                    unsafe {
+                        libc::kill(-(pid as i32), libc::SIGKILL);
+                    }
+                }
+                let _ = child.kill().await;
+                let _ = child.wait().await;
+                Ok(())
+            }
+            RuntimeHandle::Adopted { pid, .. } => {
+                // Not our child, so there is nothing to reap; init does that.
+                #[cfg(unix)]
+                unsafe {
+                    libc::kill(-(*pid as i32), libc::SIGKILL);
+                    libc::kill(*pid as i32, libc::SIGKILL);
+                }
+                #[cfg(not(unix))]
+                let _ = pid;
+                Ok(())
+            }
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { process, .. } => {
+                process.exit(128 + 9);
+                Ok(())
+            }
         }
     }
 
@@ -341,6 +592,19 @@ impl RuntimeHandle {
         Ok(())
     }
 
+    /// Kill the entire process group (child + all descendants) and reap it
+    async fn kill_process_group(child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(pid) = child.id() {
+            unsafe {
+                libc::kill(-(pid as i32), libc::SIGKILL);
+            }
+        }
+        // Also kill via tokio handle and reap the zombie
+        let _ = child.kill().await;
+        let _ = child.wait().await;
+    }
+
     /// Helper to send HTTP PUT to Firecracker API (used for shutdown)
     #[cfg(target_os = "linux")]
     async fn fc_api_put(socket_path: &PathBuf, endpoint: &str, body: &str) -> Result<()> {
@@ -360,45 +624,116 @@ impl RuntimeHandle {
         Ok(())
     }
 
+    /// Exit code if the process has exited, reaping it. Death by signal N is
+    /// reported as 128+N, like a shell. None while running, and always None
+    /// for handles without a child process (VMs, docker-managed containers,
+    /// adopted PIDs).
+    pub fn exit_code(&mut self) -> Option<i32> {
+        match self {
+            RuntimeHandle::Process { child, .. }
+            | RuntimeHandle::Namespace { child, .. }
+            | RuntimeHandle::Litebox { child, .. }
+            | RuntimeHandle::Wasm { child, .. }
+            | RuntimeHandle::Container { child, .. }
+            | RuntimeHandle::Oci { child, .. }
+            | RuntimeHandle::Systemd { child, .. }
+            | RuntimeHandle::Qemu { child, .. } => {
+                let status = child.try_wait().ok()??;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::process::ExitStatusExt;
+                    if let Some(signal) = status.signal() {
+                        return Some(128 + signal);
+                    }
+                }
+                status.code()
+            }
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { process, .. } => process.exit_code(),
+            _ => None,
+        }
+    }
+
     /// Check if the process/VM is still running
     pub async fn is_running(&mut self) -> bool {
+        if let Some(running) = self.try_is_running() {
+            return running;
+        }
+        match self.container_name() {
+            Some(name) => container_running(name).await,
+            None => false,
+        }
+    }
+
+    /// Like [`Self::is_running`], but only for handles that can answer
+    /// without waiting. None for container runtimes (quark, gVisor), which
+    /// have to ask docker: pass [`Self::container_name`] to
+    /// [`container_running`] for those.
+    pub fn try_is_running(&mut self) -> Option<bool> {
         match self {
             RuntimeHandle::Process { child, .. }
             | RuntimeHandle::Namespace { child, .. }
-            | RuntimeHandle::Litebox { child, .. } => {
+            | RuntimeHandle::Litebox { child, .. }
+            | RuntimeHandle::Wasm { child, .. }
+            | RuntimeHandle::Container { child, .. }
+            | RuntimeHandle::Oci { child, .. }
+            | RuntimeHandle::Systemd { child, .. } => {
                 // try_wait returns Ok(Some(status)) if exited, Ok(None) if still running
-                matches!(child.try_wait(), Ok(None))
+                Some(matches!(child.try_wait(), Ok(None)))
             }
             RuntimeHandle::Firecracker { api_socket, .. } => {
                 // Check if API socket exists
-                api_socket.exists()
+                Some(api_socket.exists())
             }
             RuntimeHandle::Qemu { child, .. } => {
                 // try_wait returns Ok(Some(status)) if exited, Ok(None) if still running
-                matches!(child.try_wait(), Ok(None))
+                Some(matches!(child.try_wait(), Ok(None)))
             }
-            RuntimeHandle::Quark { name, .. } | RuntimeHandle::Sandbox { name, .. } => {
-                // Container runtimes (quark, gVisor): ask docker.
-                #[cfg(target_os = "linux")]
+            RuntimeHandle::Quark { .. } | RuntimeHandle::Sandbox { .. } => None,
+            RuntimeHandle::Adopted { pid, .. } => {
+                #[cfg(unix)]
                 {
-                    use tokio::process::Command;
-                    let out = Command::new("docker")
-                        .args(["inspect", "-f", "{{.State.Running}}", name.as_str()])
-                        .output()
-                        .await;
-                    matches!(out, Ok(o) if o.status.success()
-                        && String::from_utf8_lossy(&o.stdout).trim() == "true")
+                    Some(unsafe { libc::kill(*pid as i32, 0) == 0 })
                 }
-                #[cfg(not(target_os = "linux"))]
+                #[cfg(not(unix))]
                 {
-                    let _ = name;
-                    false
+                    let _ = pid;
+                    Some(false)
                 }
             }
+            #[cfg(any(test, feature = "testing"))]
+            RuntimeHandle::Mock { process, .. } => Some(process.is_running()),
+        }
+    }
+
+    /// Docker container name, for the container runtimes (quark, gVisor)
+    pub fn container_name(&self) -> Option<&str> {
+        match self {
+            RuntimeHandle::Quark { name, .. } | RuntimeHandle::Sandbox { name, .. } => Some(name),
+            _ => None,
         }
     }
 }
 
+/// Ask docker whether container `name` is running
+pub async fn container_running(name: &str) -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        use tokio::process::Command;
+        let out = Command::new("docker")
+            .args(["inspect", "-f", "{{.State.Running}}", name])
+            .output()
+            .await;
+        matches!(out, Ok(o) if o.status.success()
+            && String::from_utf8_lossy(&o.stdout).trim() == "true")
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = name;
+        false
+    }
+}
+
 /// A host->guest bind mount (used by OCI runtimes like Quark).
 #[derive(Debug, Clone)]
 pub struct Mount {
@@ -437,9 +772,29 @@ pub struct SpawnConfig {
     /// Memory limit in MB for container runtimes. Process-like runtimes use
     /// Tenement's cgroup manager instead.
     pub memory_limit_mb: Option<u32>,
+    /// Memory soft limit in MB for container runtimes
+    pub memory_high_mb: Option<u32>,
+    /// Swap limit in MB for container runtimes
+    pub memory_swap_max_mb: Option<u32>,
     /// CPU weight/shares for container runtimes. Process-like runtimes use
     /// Tenement's cgroup manager instead.
     pub cpu_shares: Option<u32>,
+    /// Process/thread limit for container runtimes. Process-like runtimes
+    /// use Tenement's cgroup manager instead.
+    pub max_pids: Option<u32>,
+    /// CPU list (`0-3,6`) for container runtimes. Process-like runtimes use
+    /// Tenement's cgroup manager instead.
+    pub cpuset: Option<String>,
+    /// Extra unit properties (`Key=Value`) for the systemd runtime.
+    pub systemd_properties: Vec<String>,
+    /// Security hardening from the service's `profile`.
+    pub hardening: Hardening,
+    /// Unprivileged uid/gid range to run as (namespace runtime).
+    pub user_namespace: Option<UserNamespace>,
+    /// Network namespace mode (namespace runtime); `None` shares the host's.
+    pub network: Option<Network>,
+    /// OOM score, nice and ionice (process and namespace runtimes).
+    pub priority: Priority,
 }
 
 /// Firecracker VM configuration
@@ -455,6 +810,19 @@ pub struct VmConfig {
     pub rootfs: PathBuf,
     /// vsock port inside guest
     pub vsock_port: u32,
+    /// Directory for per-instance snapshots (Firecracker): a booted guest is
+    /// snapshotted once its app accepts connections and later spawns restore
+    /// from it instead of booting. Only honoured by `FirecrackerRuntime`
+    /// itself; the hypervisor doesn't start firecracker services yet.
+    #[serde(default)]
+    pub snapshot_dir: Option<PathBuf>,
+    /// Tap-device networking for outbound traffic (Firecracker)
+    #[serde(default)]
+    pub network: Option<PrivateNetwork>,
+    /// Host directory shared into the guest as `data` (QEMU): virtio-fs via
+    /// virtiofsd when available, else virtio-9p
+    #[serde(default)]
+    pub shared_dir: Option<PathBuf>,
 }
 
 impl Default for VmConfig {
@@ -465,6 +833,9 @@ impl Default for VmConfig {
             kernel: PathBuf::new(),
             rootfs: PathBuf::new(),
             vsock_port: 5000,
+            snapshot_dir: None,
+            network: None,
+            shared_dir: None,
         }
     }
 }
@@ -504,6 +875,10 @@ mod tests {
         assert_eq!(RuntimeType::Sandbox.to_string(), "sandbox");
         assert_eq!(RuntimeType::Litebox.to_string(), "litebox");
         assert_eq!(RuntimeType::Quark.to_string(), "quark");
+        assert_eq!(RuntimeType::Container.to_string(), "container");
+        assert_eq!(RuntimeType::Oci.to_string(), "oci");
+        assert_eq!(RuntimeType::Wasm.to_string(), "wasm");
+        assert_eq!(RuntimeType::Systemd.to_string(), "systemd");
         assert_eq!(RuntimeType::Firecracker.to_string(), "firecracker");
         assert_eq!(RuntimeType::Qemu.to_string(), "qemu");
     }
@@ -531,6 +906,16 @@ mod tests {
             RuntimeType::Litebox
         );
         assert_eq!("quark".parse::<RuntimeType>().unwrap(), RuntimeType::Quark);
+        assert_eq!(
+            "container".parse::<RuntimeType>().unwrap(),
+            RuntimeType::Container
+        );
+        assert_eq!("oci".parse::<RuntimeType>().unwrap(), RuntimeType::Oci);
+        assert_eq!("wasm".parse::<RuntimeType>().unwrap(), RuntimeType::Wasm);
+        assert_eq!(
+            "systemd".parse::<RuntimeType>().unwrap(),
+            RuntimeType::Systemd
+        );
         assert_eq!(
             "firecracker".parse::<RuntimeType>().unwrap(),
             RuntimeType::Firecracker
@@ -608,5 +993,6 @@ mod tests {
         assert_eq!(config.memory_mb, 128);
         assert_eq!(config.vcpus, 1);
         assert_eq!(config.vsock_port, 5000);
+        assert!(config.snapshot_dir.is_none());
     }
 }
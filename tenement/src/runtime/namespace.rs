@@ -12,10 +12,35 @@
 //! For untrusted code, use the sandbox runtime (gVisor) which also filters syscalls.
 //!
 //! **Linux only** - requires `unshare(2)` syscall.
+//!
+//! A service `profile` (see [`crate::hardening`]) is applied in the child's
+//! mount namespace before exec: a private tmpfs on `/tmp`, `/var/tmp` and
+//! `/dev/shm`, the whole root remounted read-only (`mount_setattr(2)`, Linux
+//! 5.12+) except the instance's data and socket dirs, then the capability
+//! bounding set, no-new-privs and the seccomp filter.
+//!
+//! `mounts` bind host paths into the instance's root. With a `rootfs` they
+//! land inside it; without one they form an allowlist: the child assembles a
+//! fresh root on a tmpfs holding only those paths (plus `/proc`, a writable
+//! `/tmp` and the basic `/dev` nodes) and chroots into it. A read-only root
+//! then covers the tmpfs skeleton, and `readonly` mounts are made read-only
+//! recursively.
+//!
+//! A `network` other than the host's puts the child in its own network
+//! namespace: `none` unshares an empty one with just loopback (the Unix
+//! socket still works, it's a file), `private` joins a named namespace wired
+//! to the host over a veth pair (see [`super::VethLink`]).
+//!
+//! With a [`UserNamespace`], the child also unshares its user namespace and
+//! runs as root inside it, mapped to an unprivileged host uid/gid range. An
+//! unprivileged process can only map its own id, so the (root) parent writes
+//! the child's `uid_map`/`gid_map` from a helper thread while the child waits.
 
 use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
 use anyhow::Result;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 /// Runtime that spawns processes in Linux namespaces (PID + Mount)
 ///
@@ -35,15 +60,595 @@ impl Default for NamespaceRuntime {
     }
 }
 
+/// Host uid/gid range a namespaced instance runs as. The instance's root
+/// (uid/gid 0) maps to `uid`/`gid`, and `count` ids are mapped in total.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserNamespace {
+    /// First host uid of the range
+    #[serde(default = "default_base_id")]
+    pub uid: u32,
+    /// First host gid of the range
+    #[serde(default = "default_base_id")]
+    pub gid: u32,
+    /// Number of ids mapped
+    #[serde(default = "default_id_count")]
+    pub count: u32,
+}
+
+fn default_base_id() -> u32 {
+    100_000
+}
+
+fn default_id_count() -> u32 {
+    65_536
+}
+
+impl Default for UserNamespace {
+    fn default() -> Self {
+        Self {
+            uid: default_base_id(),
+            gid: default_base_id(),
+            count: default_id_count(),
+        }
+    }
+}
+
+impl UserNamespace {
+    /// Check the range is unprivileged and fits in 32 bits
+    pub fn validate(&self) -> Result<()> {
+        if self.count == 0 {
+            anyhow::bail!("count must be at least 1");
+        }
+        for (kind, base) in [("uid", self.uid), ("gid", self.gid)] {
+            if base == 0 {
+                anyhow::bail!("{} 0 would map the instance to host root", kind);
+            }
+            // u32::MAX is the kernel's invalid id
+            if base.checked_add(self.count).is_none() {
+                anyhow::bail!("{} range {}+{} overflows", kind, base, self.count);
+            }
+        }
+        Ok(())
+    }
+
+    /// Contents for `/proc/<pid>/uid_map`
+    fn uid_map(&self) -> String {
+        format!("0 {} {}\n", self.uid, self.count)
+    }
+
+    /// Contents for `/proc/<pid>/gid_map`
+    fn gid_map(&self) -> String {
+        format!("0 {} {}\n", self.gid, self.count)
+    }
+
+    /// Hand `path` and everything below it to the instance's root. Symlinks
+    /// are changed themselves, never followed.
+    #[cfg(unix)]
+    pub fn chown_tree(&self, path: &Path) -> std::io::Result<()> {
+        crate::hardening::chown_tree(path, self.uid, self.gid)
+    }
+}
+
 #[cfg(target_os = "linux")]
 mod linux_impl {
     use super::*;
+    use crate::hardening::{ChildHardening, Hardening};
+    use crate::runtime::{Network, NetworkMode, VethLink};
     use anyhow::Context;
-    use std::ffi::CString;
+    use std::ffi::{CStr, CString};
+    use std::io::{Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
     use std::os::unix::ffi::OsStrExt;
     use std::process::Stdio;
     use tokio::process::Command;
 
+    /// Directories that get a private tmpfs
+    const TMP_DIRS: &[&str] = &["/tmp", "/var/tmp", "/dev/shm"];
+
+    /// Device nodes bound into an allowlist root
+    const DEV_NODES: &[&str] = &[
+        "/dev/null",
+        "/dev/zero",
+        "/dev/full",
+        "/dev/random",
+        "/dev/urandom",
+    ];
+
+    /// Where allowlist roots are assembled. Each child mounts its own tmpfs
+    /// here inside its private mount namespace, so the host only ever sees
+    /// an empty directory.
+    fn allowlist_root() -> std::path::PathBuf {
+        std::env::temp_dir().join("tenement-root")
+    }
+
+    /// linux/mount.h
+    const MOUNT_ATTR_RDONLY: u64 = 0x1;
+
+    #[repr(C)]
+    struct MountAttr {
+        attr_set: u64,
+        attr_clr: u64,
+        propagation: u64,
+        userns_fd: u64,
+    }
+
+    fn cstring(path: &Path) -> Result<CString> {
+        CString::new(path.as_os_str().as_bytes())
+            .with_context(|| format!("path {:?} contains NUL byte", path))
+    }
+
+    /// Mount changes for a profile, with every path resolved before fork
+    #[derive(Default)]
+    struct HardeningMounts {
+        read_only_root: bool,
+        /// Existing dirs (in the new root) to cover with a tmpfs, with its
+        /// mount options
+        tmp_dirs: Vec<(CString, CString)>,
+        /// Writable paths that stay writable on a read-only root or that live
+        /// under a tmp dir (host root only)
+        writable: Vec<CString>,
+        /// Directories to create on the new tmpfs so writable paths under a
+        /// tmp dir can be bound back, parents first
+        mkdirs: Vec<CString>,
+        /// workdir to re-enter once its mount changed (host root only)
+        workdir: Option<CString>,
+    }
+
+    impl HardeningMounts {
+        /// `root` is the directory the child chroots into, if any; an
+        /// allowlist root gets its tmpfs mounts from [`RootMounts`]
+        fn prepare(
+            hardening: &Hardening,
+            config: &SpawnConfig,
+            root: Option<&Path>,
+            allowlist: bool,
+        ) -> Result<Self> {
+            if !hardening.needs_mounts() {
+                return Ok(Self::default());
+            }
+            let chroot = root.is_some();
+            let root = root.unwrap_or(Path::new("/"));
+            let mut mounts = Self {
+                read_only_root: hardening.read_only_root,
+                ..Default::default()
+            };
+            let mut covered: Vec<&Path> = Vec::new();
+            if !allowlist {
+                for tmpfs in &hardening.tmpfs {
+                    let dir = root.join(tmpfs.path.strip_prefix("/").unwrap_or(&tmpfs.path));
+                    if !dir.is_dir() {
+                        anyhow::bail!("tmpfs mount point {:?} does not exist", tmpfs.path);
+                    }
+                    mounts
+                        .tmp_dirs
+                        .push((cstring(&tmpfs.path)?, CString::new(tmpfs.options())?));
+                    covered.push(&tmpfs.path);
+                }
+            }
+            if hardening.private_tmp {
+                for dir in TMP_DIRS.iter().map(Path::new) {
+                    if covered.contains(&dir) {
+                        continue;
+                    }
+                    if root.join(dir.strip_prefix("/").unwrap_or(dir)).is_dir() {
+                        mounts
+                            .tmp_dirs
+                            .push((cstring(dir)?, c"mode=1777".to_owned()));
+                        covered.push(dir);
+                    }
+                }
+            }
+            // Host paths aren't visible inside a rootfs
+            if chroot {
+                return Ok(mounts);
+            }
+            for path in hardening.writable.iter().filter(|p| p.is_dir()) {
+                mounts.writable.push(cstring(path)?);
+                for &dir in &covered {
+                    if !path.starts_with(dir) || path == dir {
+                        continue;
+                    }
+                    let mut ancestors: Vec<&Path> =
+                        path.ancestors().take_while(|a| *a != dir).collect();
+                    ancestors.reverse();
+                    for ancestor in ancestors {
+                        mounts.mkdirs.push(cstring(ancestor)?);
+                    }
+                }
+            }
+            mounts.workdir = config.workdir.as_deref().map(cstring).transpose()?;
+            Ok(mounts)
+        }
+    }
+
+    fn cloexec_pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+
+    /// Parent side of the uid/gid mapping handshake: the child writes its pid
+    /// to `ready` once it unshared its user namespace, the helper writes the
+    /// maps and answers on `done`
+    struct IdMapper {
+        ready: OwnedFd,
+        done: OwnedFd,
+        helper: std::thread::JoinHandle<Result<()>>,
+    }
+
+    /// Raw fds the child uses for the handshake
+    #[derive(Clone, Copy)]
+    struct ChildIdMap {
+        ready: libc::c_int,
+        done: libc::c_int,
+        /// The helper's end of `done`, closed in the child so a failed
+        /// helper shows up as EOF
+        helper_done: libc::c_int,
+    }
+
+    impl IdMapper {
+        fn start(userns: &UserNamespace) -> Result<(Self, ChildIdMap)> {
+            let (ready_read, ready) = cloexec_pipe().context("pipe failed")?;
+            let (done, done_write) = cloexec_pipe().context("pipe failed")?;
+            let child = ChildIdMap {
+                ready: ready.as_raw_fd(),
+                done: done.as_raw_fd(),
+                helper_done: done_write.as_raw_fd(),
+            };
+            let userns = userns.clone();
+            let helper = std::thread::spawn(move || {
+                let mut pid = [0u8; 4];
+                // EOF: the child failed before unsharing
+                if std::fs::File::from(ready_read)
+                    .read_exact(&mut pid)
+                    .is_err()
+                {
+                    return Ok(());
+                }
+                let pid = i32::from_ne_bytes(pid);
+                std::fs::write(format!("/proc/{}/uid_map", pid), userns.uid_map())
+                    .context("Failed to write uid_map (user namespaces need root)")?;
+                std::fs::write(format!("/proc/{}/gid_map", pid), userns.gid_map())
+                    .context("Failed to write gid_map")?;
+                std::fs::File::from(done_write).write_all(&[1])?;
+                Ok(())
+            });
+            Ok((
+                Self {
+                    ready,
+                    done,
+                    helper,
+                },
+                child,
+            ))
+        }
+
+        /// Wait for the helper once the child exec'd or failed
+        fn finish(self) -> Result<()> {
+            // Closing our copy of `ready` lets the helper see EOF if the child
+            // never got as far as writing to it
+            drop(self.ready);
+            drop(self.done);
+            self.helper
+                .join()
+                .map_err(|_| anyhow::anyhow!("uid/gid map helper panicked"))?
+        }
+    }
+
+    impl ChildIdMap {
+        /// Unshare the user namespace, wait for the maps and become its root.
+        /// Runs in the forked child.
+        unsafe fn enter(self) -> std::io::Result<()> {
+            libc::close(self.helper_done);
+            nix::sched::unshare(nix::sched::CloneFlags::CLONE_NEWUSER)
+                .map_err(|e| std::io::Error::other(format!("unshare user failed: {}", e)))?;
+            let pid = libc::getpid().to_ne_bytes();
+            if libc::write(self.ready, pid.as_ptr().cast(), pid.len()) != pid.len() as isize {
+                return Err(std::io::Error::last_os_error());
+            }
+            let mut ack = 0u8;
+            if libc::read(self.done, (&mut ack as *mut u8).cast(), 1) != 1 {
+                return Err(std::io::Error::other("uid/gid mapping failed"));
+            }
+            if libc::setgroups(0, std::ptr::null()) != 0
+                || libc::setresgid(0, 0, 0) != 0
+                || libc::setresuid(0, 0, 0) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    /// Bring up `lo` in a fresh network namespace. Runs in the forked child.
+    unsafe fn loopback_up() -> std::io::Result<()> {
+        let sock = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if sock < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut req: libc::ifreq = std::mem::zeroed();
+        req.ifr_name[0] = b'l' as libc::c_char;
+        req.ifr_name[1] = b'o' as libc::c_char;
+        let result = if libc::ioctl(sock, libc::SIOCGIFFLAGS as _, &mut req) != 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            req.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            if libc::ioctl(sock, libc::SIOCSIFFLAGS as _, &req) != 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        libc::close(sock);
+        result
+    }
+
+    /// `mounts` bound into the new root, with every path resolved before fork
+    #[derive(Default)]
+    struct RootMounts {
+        /// Assemble an allowlist root on a fresh tmpfs
+        tmpfs: bool,
+        /// Mount point directories to create, parents first
+        mkdirs: Vec<CString>,
+        /// Empty files to create as mount points for file sources
+        files: Vec<CString>,
+        /// World-writable `/tmp` of an allowlist root
+        tmp: Option<CString>,
+        /// tmpfs mounts of an allowlist root (target, options), mounted
+        /// before binding
+        tmp_mounts: Vec<(CString, CString)>,
+        /// (source, target, read-only)
+        binds: Vec<(CString, CString, bool)>,
+    }
+
+    impl RootMounts {
+        fn prepare(config: &SpawnConfig, root: &Path, allowlist: bool) -> Result<Self> {
+            let mut plan = Self {
+                tmpfs: allowlist,
+                ..Default::default()
+            };
+            let mut binds: Vec<(&Path, &Path, bool)> = config
+                .mounts
+                .iter()
+                .map(|m| (m.source.as_path(), m.destination.as_path(), m.readonly))
+                .collect();
+            if allowlist {
+                plan.mkdir_all(root, Path::new("/proc"))?;
+                plan.mkdir_all(root, Path::new("/tmp"))?;
+                plan.tmp = Some(cstring(&root.join("tmp"))?);
+                let hardening = &config.hardening;
+                for tmpfs in &hardening.tmpfs {
+                    plan.mkdir_all(root, &tmpfs.path)?;
+                    let target = root.join(tmpfs.path.strip_prefix("/").unwrap_or(&tmpfs.path));
+                    plan.tmp_mounts
+                        .push((cstring(&target)?, CString::new(tmpfs.options())?));
+                }
+                let custom_tmp = hardening.tmpfs.iter().any(|t| t.path == Path::new("/tmp"));
+                if hardening.private_tmp && !custom_tmp {
+                    plan.tmp_mounts
+                        .push((cstring(&root.join("tmp"))?, c"mode=1777".to_owned()));
+                }
+                binds.extend(
+                    DEV_NODES
+                        .iter()
+                        .map(Path::new)
+                        .filter(|node| node.exists())
+                        .map(|node| (node, node, false)),
+                );
+            }
+            for (source, destination, readonly) in binds {
+                let meta = std::fs::metadata(source)
+                    .with_context(|| format!("mount source {:?} does not exist", source))?;
+                let target = root.join(destination.strip_prefix("/").unwrap_or(destination));
+                if meta.is_dir() {
+                    plan.mkdir_all(root, destination)?;
+                } else {
+                    if let Some(parent) = destination.parent() {
+                        plan.mkdir_all(root, parent)?;
+                    }
+                    plan.files.push(cstring(&target)?);
+                }
+                plan.binds
+                    .push((cstring(source)?, cstring(&target)?, readonly));
+            }
+            Ok(plan)
+        }
+
+        /// Queue `dir` (a path inside the root) and its parents
+        fn mkdir_all(&mut self, root: &Path, dir: &Path) -> Result<()> {
+            let mut ancestors: Vec<&Path> = dir
+                .ancestors()
+                .filter(|a| !a.as_os_str().is_empty() && *a != Path::new("/"))
+                .collect();
+            ancestors.reverse();
+            for ancestor in ancestors {
+                let path = cstring(&root.join(ancestor.strip_prefix("/").unwrap_or(ancestor)))?;
+                if !self.mkdirs.contains(&path) {
+                    self.mkdirs.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        /// Create the mount points on the bare root. Failures (say, on a
+        /// read-only rootfs) surface as failed binds.
+        fn create_mount_points(&self) {
+            for dir in &self.mkdirs {
+                unsafe { libc::mkdir(dir.as_ptr(), 0o755) };
+            }
+            if let Some(tmp) = &self.tmp {
+                unsafe { libc::chmod(tmp.as_ptr(), 0o1777) };
+            }
+            for file in &self.files {
+                let fd = unsafe {
+                    libc::open(
+                        file.as_ptr(),
+                        libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                        0o644,
+                    )
+                };
+                if fd >= 0 {
+                    unsafe { libc::close(fd) };
+                }
+            }
+        }
+
+        /// Bind the mounts in; after the root went read-only so writable
+        /// mounts stay writable
+        fn bind(&self) -> std::io::Result<()> {
+            use nix::mount::{mount, MsFlags};
+            // Mounted here rather than after chroot, which would hide binds
+            // under /tmp
+            if !self.tmp_mounts.is_empty() {
+                mount_private_tmp(&self.tmp_mounts)?;
+                // Mount points under them went with the old directories
+                self.create_mount_points();
+            }
+            for (source, target, readonly) in &self.binds {
+                mount(
+                    Some(source.as_c_str()),
+                    target.as_c_str(),
+                    None::<&CStr>,
+                    MsFlags::MS_BIND | MsFlags::MS_REC,
+                    None::<&CStr>,
+                )
+                .map_err(|e| {
+                    std::io::Error::other(format!("bind-mount of {:?} failed: {}", source, e))
+                })?;
+                if *readonly {
+                    remount_read_only(target)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Recursively mark the mount at `path` and everything below it read-only
+    fn remount_read_only(path: &CStr) -> std::io::Result<()> {
+        let attr = MountAttr {
+            attr_set: MOUNT_ATTR_RDONLY,
+            attr_clr: 0,
+            propagation: 0,
+            userns_fd: 0,
+        };
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_mount_setattr,
+                libc::AT_FDCWD,
+                path.as_ptr(),
+                libc::AT_RECURSIVE as libc::c_uint,
+                &attr as *const MountAttr,
+                std::mem::size_of::<MountAttr>(),
+            )
+        };
+        if ret != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(std::io::Error::other(format!(
+                "read-only root failed (needs Linux 5.12+): {}",
+                e
+            )));
+        }
+        Ok(())
+    }
+
+    /// Cover each tmp dir with a fresh tmpfs
+    fn mount_private_tmp(tmp_dirs: &[(CString, CString)]) -> std::io::Result<()> {
+        use nix::mount::{mount, MsFlags};
+        for (dir, options) in tmp_dirs {
+            mount(
+                Some(c"tmpfs"),
+                dir.as_c_str(),
+                Some(c"tmpfs"),
+                MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                Some(options.as_c_str()),
+            )
+            .map_err(|e| std::io::Error::other(format!("private tmp mount failed: {}", e)))?;
+        }
+        Ok(())
+    }
+
+    /// `/proc/self/fd/<fd>` into `buf`, NUL-terminated, without allocating
+    fn fd_path(fd: libc::c_int, buf: &mut [u8; 32]) -> &CStr {
+        const PREFIX: &[u8] = b"/proc/self/fd/";
+        buf[..PREFIX.len()].copy_from_slice(PREFIX);
+        let mut digits = [0u8; 10];
+        let mut n = fd.unsigned_abs();
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (n % 10) as u8;
+            len += 1;
+            n /= 10;
+            if n == 0 {
+                break;
+            }
+        }
+        for i in 0..len {
+            buf[PREFIX.len() + i] = digits[len - 1 - i];
+        }
+        buf[PREFIX.len() + len] = 0;
+        CStr::from_bytes_until_nul(&buf[..]).unwrap_or_default()
+    }
+
+    /// Apply the profile's mounts on the host root, in the child
+    fn apply_host_mounts(mounts: &HardeningMounts) -> std::io::Result<()> {
+        use nix::mount::{mount, MsFlags};
+
+        // Hold on to the writable paths: the tmpfs mounts hide those under
+        // a tmp dir
+        let mut fds = [-1; 8];
+        for (fd, path) in fds.iter_mut().zip(&mounts.writable) {
+            *fd = unsafe {
+                libc::open(
+                    path.as_ptr(),
+                    libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+                )
+            };
+            if *fd < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        if mounts.read_only_root {
+            remount_read_only(c"/")?;
+        }
+        mount_private_tmp(&mounts.tmp_dirs)?;
+        for dir in &mounts.mkdirs {
+            unsafe { libc::mkdir(dir.as_ptr(), 0o755) };
+        }
+
+        // Bind each writable path back onto itself as a writable mount
+        for (fd, path) in fds.iter().zip(&mounts.writable) {
+            let mut buf = [0u8; 32];
+            mount(
+                Some(fd_path(*fd, &mut buf)),
+                path.as_c_str(),
+                None::<&CStr>,
+                MsFlags::MS_BIND,
+                None::<&CStr>,
+            )
+            .and_then(|_| {
+                mount(
+                    None::<&CStr>,
+                    path.as_c_str(),
+                    None::<&CStr>,
+                    MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
+                    None::<&CStr>,
+                )
+            })
+            .map_err(|e| std::io::Error::other(format!("writable bind-mount failed: {}", e)))?;
+            unsafe { libc::close(*fd) };
+        }
+
+        if let Some(workdir) = &mounts.workdir {
+            if unsafe { libc::chdir(workdir.as_ptr()) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
     pub async fn spawn_namespaced(config: &SpawnConfig) -> Result<RuntimeHandle> {
         // Remove old socket if exists
         if config.socket.exists() {
@@ -70,7 +675,17 @@ mod linux_impl {
         // When rootfs is set, chdir happens *inside* the new root in pre_exec.
         // Calling current_dir() here would chdir on the host, failing for guest-only
         // paths like /app.
-        if config.rootfs.is_none() {
+        // `mounts` without a rootfs: chroot into an allowlist root
+        let allowlist = config.rootfs.is_none() && !config.mounts.is_empty();
+        let root = if allowlist {
+            let root = allowlist_root();
+            std::fs::create_dir_all(&root)
+                .with_context(|| format!("Failed to create {:?}", root))?;
+            Some(root)
+        } else {
+            config.rootfs.clone()
+        };
+        if root.is_none() {
             if let Some(workdir) = &config.workdir {
                 cmd.current_dir(workdir);
             }
@@ -78,13 +693,31 @@ mod linux_impl {
 
         // Build CStrings for the rootfs path and workdir-inside-root before fork.
         // pre_exec is async-signal-context; no allocations or panics allowed.
-        let rootfs_c = match &config.rootfs {
+        let rootfs_c = match &root {
             Some(p) => Some(
                 CString::new(p.as_os_str().as_bytes()).context("rootfs path contains NUL byte")?,
             ),
             None => None,
         };
-        let chdir_target_c = if config.rootfs.is_some() {
+        let root_mounts = match &root {
+            Some(root) => RootMounts::prepare(config, root, allowlist)?,
+            None => RootMounts::default(),
+        };
+        let hardening_mounts =
+            HardeningMounts::prepare(&config.hardening, config, root.as_deref(), allowlist)?;
+        // The child holds their fds in a fixed array
+        if hardening_mounts.writable.len() > 8 {
+            anyhow::bail!("too many writable paths for a read-only root");
+        }
+        let child_hardening = ChildHardening::prepare(&config.hardening)?;
+        let (id_mapper, child_id_map) = match &config.user_namespace {
+            Some(userns) => {
+                let (mapper, child) = IdMapper::start(userns)?;
+                (Some(mapper), Some(child))
+            }
+            None => (None, None),
+        };
+        let chdir_target_c = if root.is_some() {
             let inside = config
                 .workdir
                 .as_ref()
@@ -95,6 +728,33 @@ mod linux_impl {
             None
         };
 
+        // The network comes last, so earlier errors have nothing to tear down
+        let unshare_net = config.network.as_ref().map(Network::mode) == Some(NetworkMode::None);
+        let veth = match config.network.as_ref().and_then(Network::private) {
+            Some(network) => {
+                let instance = config
+                    .socket
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("ns");
+                let veth = VethLink::allocate(&network, instance)?;
+                veth.create().await?;
+                Some(veth)
+            }
+            None => None,
+        };
+        let netns = match &veth {
+            Some(veth) => match std::fs::File::open(veth.netns_path()) {
+                Ok(netns) => Some(OwnedFd::from(netns)),
+                Err(e) => {
+                    veth.destroy().await;
+                    return Err(e).with_context(|| format!("Failed to open {:?}", veth.netns));
+                }
+            },
+            None => None,
+        };
+        let netns_fd = netns.as_ref().map(|fd| fd.as_raw_fd());
+
         unsafe {
             cmd.pre_exec(move || {
                 // Put child in its own process group so we can kill all descendants
@@ -105,6 +765,24 @@ mod linux_impl {
                 use nix::mount::{mount, MsFlags};
                 use nix::sched::{unshare, CloneFlags};
 
+                // Joining a namespace the host created needs host root, so
+                // before the user namespace
+                if let Some(netns) = netns_fd {
+                    if libc::setns(netns, libc::CLONE_NEWNET) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if unshare_net {
+                    unshare(CloneFlags::CLONE_NEWNET)
+                        .map_err(|e| std::io::Error::other(format!("unshare net failed: {}", e)))?;
+                    loopback_up()?;
+                }
+
+                // Before the mount and PID namespaces, so they belong to it
+                if let Some(id_map) = child_id_map {
+                    id_map.enter()?;
+                }
+
                 // Create new PID and Mount namespaces
                 unshare(CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS)
                     .map_err(|e| std::io::Error::other(format!("unshare failed: {}", e)))?;
@@ -120,17 +798,35 @@ mod linux_impl {
                 .map_err(|e| std::io::Error::other(format!("mount private failed: {}", e)))?;
 
                 if let Some(rootfs) = rootfs_c.as_ref() {
-                    // Bind-mount rootfs onto itself so it becomes a mount point we can chroot into.
-                    mount(
-                        Some(rootfs.as_c_str()),
-                        rootfs.as_c_str(),
-                        None::<&std::ffi::CStr>,
-                        MsFlags::MS_BIND | MsFlags::MS_REC,
-                        None::<&std::ffi::CStr>,
-                    )
-                    .map_err(|e| {
-                        std::io::Error::other(format!("rootfs bind-mount failed: {}", e))
-                    })?;
+                    if root_mounts.tmpfs {
+                        mount(
+                            Some(c"tmpfs"),
+                            rootfs.as_c_str(),
+                            Some(c"tmpfs"),
+                            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+                            Some(c"mode=0755"),
+                        )
+                        .map_err(|e| {
+                            std::io::Error::other(format!("allowlist root mount failed: {}", e))
+                        })?;
+                    } else {
+                        // Bind-mount rootfs onto itself so it becomes a mount point we can chroot into.
+                        mount(
+                            Some(rootfs.as_c_str()),
+                            rootfs.as_c_str(),
+                            None::<&std::ffi::CStr>,
+                            MsFlags::MS_BIND | MsFlags::MS_REC,
+                            None::<&std::ffi::CStr>,
+                        )
+                        .map_err(|e| {
+                            std::io::Error::other(format!("rootfs bind-mount failed: {}", e))
+                        })?;
+                    }
+                    root_mounts.create_mount_points();
+                    if hardening_mounts.read_only_root {
+                        remount_read_only(rootfs)?;
+                    }
+                    root_mounts.bind()?;
 
                     // chroot into the new rootfs.
                     if libc::chroot(rootfs.as_ptr()) != 0 {
@@ -158,7 +854,10 @@ mod linux_impl {
                     .map_err(|e| {
                         std::io::Error::other(format!("/proc mount in rootfs failed: {}", e))
                     })?;
+                    mount_private_tmp(&hardening_mounts.tmp_dirs)?;
                 } else {
+                    apply_host_mounts(&hardening_mounts)?;
+
                     // Legacy path: no rootfs, mount /proc on host's /proc.
                     // Best-effort; missing CAP_SYS_ADMIN is tolerated here for back-compat.
                     let _ = mount(
@@ -170,18 +869,41 @@ mod linux_impl {
                     );
                 }
 
-                Ok(())
+                // Last: the seccomp filter denies mount(2)
+                child_hardening.apply()
             });
         }
 
-        let child = cmd
-            .spawn()
-            .with_context(|| format!("Failed to spawn namespaced process: {}", config.command))?;
+        let spawned = cmd.spawn();
+        drop(cmd);
+        drop(netns);
+        let mapped = id_mapper.map_or(Ok(()), IdMapper::finish);
+        let child = match spawned {
+            Ok(child) => child,
+            Err(e) => {
+                if let Some(veth) = &veth {
+                    veth.destroy().await;
+                }
+                mapped?;
+                return Err(e).with_context(|| {
+                    format!("Failed to spawn namespaced process: {}", config.command)
+                });
+            }
+        };
 
-        Ok(RuntimeHandle::Namespace {
+        let pid = child.id();
+        let mut handle = RuntimeHandle::Namespace {
             child,
             socket: config.socket.clone(),
-        })
+            veth,
+        };
+        if let Some(pid) = pid {
+            if let Err(e) = config.priority.apply(pid) {
+                handle.kill().await.ok();
+                return Err(e);
+            }
+        }
+        Ok(handle)
     }
 }
 
@@ -318,4 +1040,348 @@ mod tests {
         // Clean up
         handle.kill().await.ok();
     }
+
+    // Integration test - requires Linux and root privileges
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_namespace_strict_profile() {
+        use crate::hardening::SecurityProfile;
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let mut hardening = SecurityProfile::Strict.hardening();
+        hardening.writable.push(data.clone());
+
+        // The data dir lives under /tmp, so it must survive the private tmpfs
+        let script = data.join("check.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "touch /usr/.ten-ro 2>/dev/null && echo root-writable\n\
+                 echo ok > {data}/file && echo data-writable\n\
+                 echo ok > /tmp/.ten-private && echo tmp-writable\n\
+                 grep -E '^(CapBnd|NoNewPrivs|Seccomp):' /proc/self/status\n",
+                data = data.display()
+            ),
+        )
+        .unwrap();
+        let config = SpawnConfig {
+            command: "sh".to_string(),
+            // The outer sh's first child is the PID namespace's init, so run
+            // the checks from one long-lived shell
+            args: vec!["-c".to_string(), format!("sh {}; true", script.display())],
+            socket: dir.path().join("strict.sock"),
+            hardening,
+            ..Default::default()
+        };
+        let mut handle = NamespaceRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Namespace { child, .. } = &mut handle else {
+            panic!("expected a namespace handle");
+        };
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+
+        assert!(!out.contains("root-writable"), "{}", out);
+        assert!(out.contains("data-writable"), "{}", out);
+        assert!(out.contains("tmp-writable"), "{}", out);
+        // The tmpfs was private to the instance
+        assert!(!std::path::Path::new("/tmp/.ten-private").exists());
+        // Only CAP_NET_BIND_SERVICE (bit 10) is left
+        assert!(out.contains("CapBnd:\t0000000000000400"), "{}", out);
+        assert!(out.contains("NoNewPrivs:\t1"), "{}", out);
+        assert!(out.contains("Seccomp:\t2"), "{}", out);
+    }
+
+    #[test]
+    fn test_user_namespace_maps() {
+        let userns = UserNamespace::default();
+        assert_eq!(userns.uid_map(), "0 100000 65536\n");
+        assert!(userns.validate().is_ok());
+
+        let overflow = UserNamespace {
+            gid: u32::MAX - 10,
+            ..Default::default()
+        };
+        assert!(overflow.validate().unwrap_err().to_string().contains("gid"));
+        let empty = UserNamespace {
+            count: 0,
+            ..Default::default()
+        };
+        assert!(empty.validate().is_err());
+    }
+
+    // Integration test - requires Linux and root privileges
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_namespace_user_namespace() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::set_permissions(dir.path(), std::fs::Permissions::from_mode(0o755)).unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let userns = UserNamespace::default();
+        userns.chown_tree(&data).unwrap();
+
+        let script = data.join("check.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "id -u\n\
+                 touch {}/file\n\
+                 touch /etc/.ten-userns 2>/dev/null && echo host-root\n",
+                data.display()
+            ),
+        )
+        .unwrap();
+        let config = SpawnConfig {
+            command: "sh".to_string(),
+            // One long-lived shell as the PID namespace's init
+            args: vec!["-c".to_string(), format!("sh {}; true", script.display())],
+            socket: data.join("app.sock"),
+            user_namespace: Some(userns.clone()),
+            ..Default::default()
+        };
+        let mut handle = NamespaceRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Namespace { child, .. } = &mut handle else {
+            panic!("expected a namespace handle");
+        };
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+
+        // Root inside, the mapped uid outside
+        assert_eq!(out.lines().next(), Some("0"), "{}", out);
+        let file = std::fs::metadata(data.join("file")).unwrap();
+        assert_eq!(file.uid(), userns.uid);
+        assert_eq!(file.gid(), userns.gid);
+        assert!(!out.contains("host-root"), "{}", out);
+        assert!(!std::path::Path::new("/etc/.ten-userns").exists());
+    }
+
+    // Integration test - requires Linux, root and iproute2
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_namespace_network_modes() {
+        use crate::runtime::{Network, NetworkMode, PrivateNetwork};
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let private = PrivateNetwork {
+            subnet: "10.93.0.0/24".to_string(),
+            nat: false,
+            allow: Vec::new(),
+        };
+        for network in [Network::Mode(NetworkMode::None), Network::Private(private)] {
+            let config = SpawnConfig {
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), "ip -o addr show; true".to_string()],
+                socket: dir.path().join("net.sock"),
+                network: Some(network.clone()),
+                ..Default::default()
+            };
+            let mut handle = NamespaceRuntime::new().spawn(&config).await.unwrap();
+            let RuntimeHandle::Namespace { child, veth, .. } = &mut handle else {
+                panic!("expected a namespace handle");
+            };
+            let veth = veth.clone();
+            let mut out = String::new();
+            child
+                .stdout
+                .take()
+                .unwrap()
+                .read_to_string(&mut out)
+                .await
+                .unwrap();
+            child.wait().await.unwrap();
+            handle.kill().await.unwrap();
+
+            assert!(out.contains("lo    inet 127.0.0.1/8"), "{}", out);
+            match veth {
+                Some(veth) => {
+                    assert!(out.contains(&format!("eth0    inet {}/30", veth.guest_ip)));
+                    assert!(!veth.netns_path().exists());
+                }
+                None => {
+                    assert_eq!(network.mode(), NetworkMode::None);
+                    assert!(out.lines().all(|l| l.starts_with("1: lo ")), "{}", out);
+                }
+            }
+        }
+    }
+
+    // Integration test - requires Linux and root privileges
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_namespace_mount_allowlist() {
+        use crate::runtime::Mount;
+        use std::path::{Path, PathBuf};
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let script = data.join("check.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "ls /\n\
+                 cat /etc/hostname 2>/dev/null && echo etc-visible\n\
+                 touch /usr/.ten-allowlist 2>/dev/null && echo usr-writable\n\
+                 touch /.ten-allowlist 2>/dev/null && echo root-writable\n\
+                 echo ok > {}/file && echo data-writable\n",
+                data.display()
+            ),
+        )
+        .unwrap();
+
+        let mut mounts: Vec<Mount> = ["/usr", "/bin", "/lib", "/lib64"]
+            .iter()
+            .filter(|p| Path::new(p).exists())
+            .map(|p| Mount {
+                source: PathBuf::from(p),
+                destination: PathBuf::from(p),
+                readonly: true,
+            })
+            .collect();
+        mounts.push(Mount {
+            source: data.clone(),
+            destination: data.clone(),
+            readonly: false,
+        });
+        // The data dir is under /tmp, so it must survive the private tmpfs
+        let hardening = crate::hardening::Hardening {
+            read_only_root: true,
+            private_tmp: true,
+            ..Default::default()
+        };
+        let config = SpawnConfig {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), format!("sh {}; true", script.display())],
+            socket: data.join("app.sock"),
+            mounts,
+            hardening,
+            ..Default::default()
+        };
+        let mut handle = NamespaceRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Namespace { child, .. } = &mut handle else {
+            panic!("expected a namespace handle");
+        };
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+
+        assert!(!out.contains("etc-visible"), "{}", out);
+        assert!(!out.contains("usr-writable"), "{}", out);
+        assert!(!out.contains("root-writable"), "{}", out);
+        assert!(out.contains("data-writable"), "{}", out);
+        let top: Vec<&str> = out
+            .lines()
+            .take_while(|l| !l.contains("writable"))
+            .collect();
+        assert!(top.contains(&"usr") && top.contains(&"proc") && top.contains(&"dev"));
+        assert!(!top.contains(&"etc") && !top.contains(&"root"), "{}", out);
+        // The host only has the empty assembly point
+        assert_eq!(
+            std::fs::read_dir(std::env::temp_dir().join("tenement-root"))
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    // Integration test - requires Linux and root privileges
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_namespace_tmpfs() {
+        use crate::hardening::{Hardening, Tmpfs};
+        use std::path::PathBuf;
+        use tokio::io::AsyncReadExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let data = dir.path().join("data");
+        std::fs::create_dir(&data).unwrap();
+        let marker = format!("ten-tmpfs-{}", std::process::id());
+        let script = data.join("check.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "dd if=/dev/zero of=/run/big bs=1M count=2 2>/dev/null || echo run-full\n\
+                 touch /tmp/{marker} && echo tmp-writable\n\
+                 echo ok > {}/file && echo data-writable\n",
+                data.display()
+            ),
+        )
+        .unwrap();
+
+        // The data dir lives under /tmp, so it must be bound back
+        let hardening = Hardening {
+            tmpfs: vec![
+                Tmpfs {
+                    path: PathBuf::from("/run"),
+                    size_mb: Some(1),
+                },
+                Tmpfs {
+                    path: PathBuf::from("/tmp"),
+                    size_mb: None,
+                },
+            ],
+            writable: vec![data.clone()],
+            ..Default::default()
+        };
+        let config = SpawnConfig {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), format!("sh {}; true", script.display())],
+            socket: data.join("app.sock"),
+            hardening,
+            ..Default::default()
+        };
+        let mut handle = NamespaceRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Namespace { child, .. } = &mut handle else {
+            panic!("expected a namespace handle");
+        };
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        child.wait().await.unwrap();
+
+        assert!(out.contains("run-full"), "{}", out);
+        assert!(out.contains("tmp-writable"), "{}", out);
+        assert!(out.contains("data-writable"), "{}", out);
+        assert!(!std::env::temp_dir().join(&marker).exists());
+        assert!(!std::path::Path::new("/run/big").exists());
+        assert_eq!(std::fs::read_to_string(data.join("file")).unwrap(), "ok\n");
+    }
 }
@@ -0,0 +1,661 @@
+//! OCI runtime - runs a service's rootfs as a container with plain `runc`.
+//!
+//! For hosts without gVisor or a container engine: `isolation = "oci"` plus a
+//! `rootfs` directory. Tenement writes an OCI bundle (`config.json` next to a
+//! pointer at the rootfs) and runs it attached, so `runc` is supervised like
+//! the container engine client: it relays the container's stdout/stderr,
+//! forwards signals and exits with the container's exit code.
+//!
+//! ```text
+//! runc run --bundle /run/tenement-oci/<id> <id>
+//! ```
+//!
+//! Bundles live in a directory only the daemon's user can enter
+//! (`/run/tenement-oci` for root, `<tmp>/tenement-oci-<uid>` otherwise), so no
+//! other user can plant the spec runc executes.
+//!
+//! The generated spec follows `runc spec` and docker's defaults rather than a
+//! bare minimum (real apps need them): new PID/IPC/UTS/mount namespaces, a
+//! fresh `/proc`, `/dev`, `/dev/pts`, `/dev/shm` and `/dev/mqueue`, docker's
+//! default capability set, `noNewPrivileges`, and the usual masked and
+//! read-only `/proc` paths. There is no network namespace: as with the other
+//! runtimes the app binds the allocated `PORT` on the host network. As with
+//! the container runtime, the instance's own `<socket>.d` is mounted over the
+//! socket's directory (skipped with `settings.tcp_only`), `mounts` become
+//! bind mounts, and
+//! `memory_limit_mb`/`memory_high_mb`/`memory_swap_max_mb`/`cpu_shares`/
+//! `max_pids`/`cpuset` become the container's cgroup limits. The rootfs is
+//! shared by every instance of the service, so it is always mounted
+//! read-only, with a tmpfs `/tmp`; an instance writes to its `mounts`. A
+//! service `profile` narrows the capability set and adds a seccomp denylist
+//! (see [`crate::hardening`]).
+//!
+//! The container id is derived from the socket path (like the container
+//! runtime's names), so a container left behind by a crashed daemon is
+//! deleted before the instance is spawned again.
+//!
+//! ## runc discovery (first match wins)
+//!
+//! 1. explicit path passed to [`OciRuntime::with_runc`];
+//! 2. `TENEMENT_RUNC` environment variable (a path, or a name looked up on
+//!    `PATH`, e.g. `crun`);
+//! 3. `runc` on `PATH`.
+
+use super::docker::{container_name, find_on_path, prepare_socket_dir, socket_dir};
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use crate::hardening::{
+    denied_syscalls, SeccompProfile, CLONE_NAMESPACE_FLAGS, DEFAULT_CAPABILITIES, ENOSYS,
+};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+
+/// Environment variable that selects the runc binary.
+pub const RUNC_ENV: &str = "TENEMENT_RUNC";
+
+/// `PATH` for the container process when the service env doesn't set one
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+const MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+];
+
+const READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Runtime that runs a generated OCI bundle attached via `runc run`.
+pub struct OciRuntime {
+    /// Explicit runc path. When `None`, discovered from env/PATH at use time.
+    runc: Option<PathBuf>,
+}
+
+impl OciRuntime {
+    pub fn new() -> Self {
+        Self { runc: None }
+    }
+
+    /// Construct with an explicit runc binary (highest precedence).
+    pub fn with_runc(path: PathBuf) -> Self {
+        Self { runc: Some(path) }
+    }
+
+    /// Resolve the runc binary: explicit path -> env var -> PATH.
+    fn find_runc(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.runc {
+            return path.exists().then(|| path.clone());
+        }
+        if let Ok(runc) = std::env::var(RUNC_ENV) {
+            return if runc.contains('/') {
+                let path = PathBuf::from(runc);
+                path.exists().then_some(path)
+            } else {
+                find_on_path(&runc)
+            };
+        }
+        find_on_path("runc")
+    }
+}
+
+impl Default for OciRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Directory the bundles are written to, created private to the daemon's
+/// user
+fn bundle_root() -> Result<PathBuf> {
+    let uid = unsafe { libc::geteuid() };
+    let root = if uid == 0 {
+        PathBuf::from("/run/tenement-oci")
+    } else {
+        std::env::temp_dir().join(format!("tenement-oci-{uid}"))
+    };
+    private_dir(&root, uid)?;
+    Ok(root)
+}
+
+/// Create `dir` with mode 0700, or check that the existing one is a real
+/// directory owned by `uid` that nobody else can enter
+fn private_dir(dir: &Path, uid: u32) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", dir)),
+    }
+    let metadata =
+        std::fs::symlink_metadata(dir).with_context(|| format!("Failed to inspect {:?}", dir))?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        bail!(
+            "Refusing to write OCI bundles to {:?}: it must be a directory owned by uid {} \
+             with mode 0700",
+            dir,
+            uid
+        );
+    }
+    Ok(())
+}
+
+fn bind_mount(source: &Path, destination: &Path, readonly: bool) -> Value {
+    let mut options = vec!["rbind", "nosuid", "nodev"];
+    if readonly {
+        options.push("ro");
+    }
+    json!({
+        "destination": destination,
+        "type": "bind",
+        "source": source,
+        "options": options,
+    })
+}
+
+/// The bundle's `config.json` for running `config` from `rootfs`
+fn spec(rootfs: &Path, config: &SpawnConfig) -> Value {
+    let mut args = vec![config.command.clone()];
+    args.extend(config.args.iter().cloned());
+
+    let mut env: Vec<String> = config.env.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    if !config.env.contains_key("PATH") {
+        env.push(format!("PATH={DEFAULT_PATH}"));
+    }
+    env.sort();
+
+    let cwd = config.workdir.clone().unwrap_or_else(|| PathBuf::from("/"));
+
+    let mut mounts = vec![
+        json!({"destination": "/proc", "type": "proc", "source": "proc"}),
+        json!({
+            "destination": "/dev",
+            "type": "tmpfs",
+            "source": "tmpfs",
+            "options": ["nosuid", "strictatime", "mode=755", "size=65536k"],
+        }),
+        json!({
+            "destination": "/dev/pts",
+            "type": "devpts",
+            "source": "devpts",
+            "options": ["nosuid", "noexec", "newinstance", "ptmxmode=0666", "mode=0620"],
+        }),
+        json!({
+            "destination": "/dev/shm",
+            "type": "tmpfs",
+            "source": "shm",
+            "options": ["nosuid", "noexec", "nodev", "mode=1777", "size=65536k"],
+        }),
+        json!({
+            "destination": "/dev/mqueue",
+            "type": "mqueue",
+            "source": "mqueue",
+            "options": ["nosuid", "noexec", "nodev"],
+        }),
+        // sysfs can't be mounted fresh without a network namespace
+        json!({
+            "destination": "/sys",
+            "type": "bind",
+            "source": "/sys",
+            "options": ["rbind", "nosuid", "noexec", "nodev", "ro"],
+        }),
+    ];
+    if config.env.contains_key("SOCKET_PATH") {
+        if let Some(dir) = config.socket.parent() {
+            mounts.push(bind_mount(&socket_dir(&config.socket), dir, false));
+        }
+    }
+    for m in &config.mounts {
+        mounts.push(bind_mount(&m.source, &m.destination, m.readonly));
+    }
+
+    let mut resources = json!({
+        "devices": [{"allow": false, "access": "rwm"}],
+    });
+    if let Some(memory_mb) = config.memory_limit_mb.filter(|mb| *mb > 0) {
+        resources["memory"] = json!({"limit": u64::from(memory_mb) * 1024 * 1024});
+    }
+    if let Some(cpu_shares) = config.cpu_shares {
+        resources["cpu"]["shares"] = json!(cpu_shares.clamp(2, 262_144));
+    }
+    if let Some(cpus) = &config.cpuset {
+        resources["cpu"]["cpus"] = json!(cpus);
+    }
+    if let Some(max_pids) = config.max_pids {
+        resources["pids"] = json!({"limit": max_pids});
+    }
+    // No OCI fields for these; runc writes `unified` keys to the cgroup as is
+    let mb = |mb: u32| (u64::from(mb) * 1024 * 1024).to_string();
+    if let Some(high_mb) = config.memory_high_mb {
+        resources["unified"]["memory.high"] = json!(mb(high_mb));
+    }
+    if let Some(swap_mb) = config.memory_swap_max_mb {
+        resources["unified"]["memory.swap.max"] = json!(mb(swap_mb));
+    }
+
+    let hardening = &config.hardening;
+    let capabilities = hardening.capabilities.unwrap_or(DEFAULT_CAPABILITIES);
+    let custom_tmp = hardening.tmpfs.iter().any(|t| t.path == Path::new("/tmp"));
+    // The root is read-only, so /tmp is always a tmpfs
+    if !custom_tmp {
+        mounts.push(json!({
+            "destination": "/tmp",
+            "type": "tmpfs",
+            "source": "tmpfs",
+            "options": ["nosuid", "nodev", "mode=1777"],
+        }));
+    }
+    for tmpfs in &hardening.tmpfs {
+        let mut options = vec!["nosuid".to_string(), "nodev".to_string()];
+        options.extend(tmpfs.options().split(',').map(str::to_string));
+        mounts.push(json!({
+            "destination": tmpfs.path,
+            "type": "tmpfs",
+            "source": "tmpfs",
+            "options": options,
+        }));
+    }
+
+    let mut spec = json!({
+        "ociVersion": "1.0.2",
+        "process": {
+            "terminal": false,
+            "user": {"uid": 0, "gid": 0},
+            "args": args,
+            "env": env,
+            "cwd": cwd,
+            "capabilities": {
+                "bounding": capabilities,
+                "effective": capabilities,
+                "permitted": capabilities,
+            },
+            "rlimits": [{"type": "RLIMIT_NOFILE", "hard": 1024, "soft": 1024}],
+            "noNewPrivileges": true,
+        },
+        // Shared by every instance of the service
+        "root": {"path": rootfs, "readonly": true},
+        "hostname": "tenement",
+        "mounts": mounts,
+        "linux": {
+            "resources": resources,
+            "namespaces": [
+                {"type": "pid"},
+                {"type": "ipc"},
+                {"type": "uts"},
+                {"type": "mount"},
+            ],
+            "maskedPaths": MASKED_PATHS,
+            "readonlyPaths": READONLY_PATHS,
+        },
+    });
+    if hardening.seccomp == SeccompProfile::Default {
+        let mut syscalls = vec![
+            json!({
+                "names": denied_syscalls().collect::<Vec<_>>(),
+                "action": "SCMP_ACT_ERRNO",
+                "errnoRet": 1,
+            }),
+            json!({"names": ["clone3"], "action": "SCMP_ACT_ERRNO", "errnoRet": ENOSYS}),
+        ];
+        // Conditions in one rule must all match, so one rule per flag
+        for flag in CLONE_NAMESPACE_FLAGS {
+            syscalls.push(json!({
+                "names": ["clone"],
+                "action": "SCMP_ACT_ERRNO",
+                "errnoRet": 1,
+                "args": [{"index": 0, "value": flag, "valueTwo": flag, "op": "SCMP_CMP_MASKED_EQ"}],
+            }));
+        }
+        spec["linux"]["seccomp"] = json!({
+            "defaultAction": "SCMP_ACT_ALLOW",
+            "syscalls": syscalls,
+        });
+    }
+    spec
+}
+
+#[async_trait]
+impl Runtime for OciRuntime {
+    async fn spawn(&self, config: &SpawnConfig) -> Result<RuntimeHandle> {
+        use std::process::Stdio;
+        use tokio::process::Command;
+
+        let runc = self.find_runc().with_context(|| {
+            format!("runc not found. Install runc, or set {RUNC_ENV}=/path/to/runc.")
+        })?;
+        let rootfs = config
+            .rootfs
+            .as_deref()
+            .context("oci isolation needs a `rootfs` directory in the service config")?;
+        if !rootfs.is_dir() {
+            bail!("rootfs {:?} is not a directory", rootfs);
+        }
+        if config.command.is_empty() {
+            bail!("oci isolation needs a `command` to run inside the rootfs");
+        }
+        // runc resolves a relative root against the bundle, not our cwd
+        let rootfs = std::fs::canonicalize(rootfs)
+            .with_context(|| format!("Failed to resolve rootfs {:?}", rootfs))?;
+        let id = container_name(&config.socket);
+
+        // A container from a daemon that crashed would hold the id
+        let _ = Command::new(&runc)
+            .args(["delete", "--force", id.as_str()])
+            .output()
+            .await;
+        if let Some(dir) = config.socket.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create socket directory {:?}", dir))?;
+        }
+        if config.env.contains_key("SOCKET_PATH") {
+            prepare_socket_dir(&config.socket)?;
+        }
+
+        let bundle = bundle_root()?.join(&id);
+        if bundle.exists() {
+            std::fs::remove_dir_all(&bundle)
+                .with_context(|| format!("Failed to remove stale OCI bundle {:?}", bundle))?;
+        }
+        std::fs::create_dir(&bundle)
+            .with_context(|| format!("Failed to create OCI bundle {:?}", bundle))?;
+        std::fs::write(
+            bundle.join("config.json"),
+            serde_json::to_vec_pretty(&spec(&rootfs, config))?,
+        )
+        .with_context(|| format!("Failed to write {:?}", bundle.join("config.json")))?;
+
+        let mut cmd = Command::new(&runc);
+        cmd.arg("run")
+            .arg("--bundle")
+            .arg(&bundle)
+            .arg(&id)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Own process group, like the process runtime
+        #[cfg(unix)]
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = match cmd.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                std::fs::remove_dir_all(&bundle).ok();
+                return Err(e).with_context(|| format!("Failed to run {:?}", runc));
+            }
+        };
+
+        Ok(RuntimeHandle::Oci {
+            child,
+            id,
+            runc,
+            bundle,
+            socket: config.socket.clone(),
+        })
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Oci
+    }
+
+    fn is_available(&self) -> bool {
+        cfg!(target_os = "linux") && self.find_runc().is_some()
+    }
+
+    fn name(&self) -> &'static str {
+        "oci"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardening::SecurityProfile;
+    use crate::runtime::Mount;
+    use std::collections::HashMap;
+
+    fn spawn_config() -> SpawnConfig {
+        let env = HashMap::from([
+            ("PORT".to_string(), "31000".to_string()),
+            (
+                "SOCKET_PATH".to_string(),
+                "/run/tenement/api-alice.sock".to_string(),
+            ),
+        ]);
+        SpawnConfig {
+            command: "/app/server".to_string(),
+            args: vec!["--port".to_string(), "31000".to_string()],
+            env,
+            socket: PathBuf::from("/run/tenement/api-alice.sock"),
+            workdir: Some(PathBuf::from("/app")),
+            mounts: vec![Mount {
+                source: PathBuf::from("/var/lib/api/data"),
+                destination: PathBuf::from("/data"),
+                readonly: true,
+            }],
+            memory_limit_mb: Some(128),
+            memory_high_mb: Some(96),
+            cpu_shares: Some(500),
+            max_pids: Some(64),
+            cpuset: Some("0-3".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_oci_runtime_type_and_name() {
+        let rt = OciRuntime::new();
+        assert_eq!(rt.runtime_type(), RuntimeType::Oci);
+        assert_eq!(rt.name(), "oci");
+    }
+
+    #[test]
+    fn test_spec() {
+        let spec = spec(Path::new("/srv/rootfs"), &spawn_config());
+        assert_eq!(spec["root"]["path"], "/srv/rootfs");
+        assert_eq!(
+            spec["process"]["args"],
+            json!(["/app/server", "--port", "31000"])
+        );
+        assert_eq!(spec["process"]["cwd"], "/app");
+        let env = spec["process"]["env"].as_array().unwrap();
+        assert!(env.contains(&json!("PORT=31000")));
+        assert!(env.iter().any(|e| e.as_str().unwrap().starts_with("PATH=")));
+
+        // Host network, like every other runtime
+        let namespaces = spec["linux"]["namespaces"].as_array().unwrap();
+        assert!(namespaces.contains(&json!({"type": "pid"})));
+        assert!(!namespaces.contains(&json!({"type": "network"})));
+
+        let mounts = spec["mounts"].as_array().unwrap();
+        assert!(mounts.iter().any(|m| m["destination"] == "/run/tenement"
+            && m["source"] == "/run/tenement/api-alice.sock.d"
+            && m["type"] == "bind"));
+        let data = mounts.iter().find(|m| m["destination"] == "/data").unwrap();
+        assert_eq!(data["source"], "/var/lib/api/data");
+        assert!(data["options"].as_array().unwrap().contains(&json!("ro")));
+
+        let resources = &spec["linux"]["resources"];
+        assert_eq!(resources["memory"]["limit"], 128 * 1024 * 1024);
+        assert_eq!(resources["cpu"]["shares"], 500);
+        assert_eq!(resources["pids"]["limit"], 64);
+        assert_eq!(resources["cpu"]["cpus"], "0-3");
+        assert_eq!(resources["unified"]["memory.high"], "100663296");
+        assert!(resources["unified"].get("memory.swap.max").is_none());
+
+        // tcp_only: no SOCKET_PATH, so no socket mount; no limits, no entries
+        let mut config = spawn_config();
+        config.env.remove("SOCKET_PATH");
+        config.memory_limit_mb = None;
+        config.cpu_shares = None;
+        config.max_pids = None;
+        config.cpuset = None;
+        let spec = super::spec(Path::new("/srv/rootfs"), &config);
+        assert!(!spec["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m["destination"] == "/run/tenement"));
+        assert!(spec["linux"]["resources"].get("memory").is_none());
+        assert!(spec["linux"]["resources"].get("cpu").is_none());
+        assert!(spec["linux"]["resources"].get("pids").is_none());
+        // Read-only even without a profile: instances share the rootfs
+        assert_eq!(spec["root"]["readonly"], true);
+        assert!(spec["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m["destination"] == "/tmp" && m["type"] == "tmpfs"));
+        assert!(spec["linux"].get("seccomp").is_none());
+    }
+
+    #[test]
+    fn test_spec_with_strict_profile() {
+        let mut config = spawn_config();
+        config.hardening = SecurityProfile::Strict.hardening();
+        let spec = spec(Path::new("/srv/rootfs"), &config);
+        assert_eq!(spec["root"]["readonly"], true);
+        assert_eq!(
+            spec["process"]["capabilities"]["bounding"],
+            json!(["CAP_NET_BIND_SERVICE"])
+        );
+        assert!(spec["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|m| m["destination"] == "/tmp" && m["type"] == "tmpfs"));
+        let seccomp = &spec["linux"]["seccomp"];
+        assert_eq!(seccomp["defaultAction"], "SCMP_ACT_ALLOW");
+        assert!(seccomp["syscalls"][0]["names"]
+            .as_array()
+            .unwrap()
+            .contains(&json!("ptrace")));
+        let clone_rules: Vec<_> = seccomp["syscalls"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|rule| rule["names"] == json!(["clone"]))
+            .collect();
+        assert_eq!(clone_rules.len(), CLONE_NAMESPACE_FLAGS.len());
+        assert!(clone_rules
+            .iter()
+            .any(|rule| rule["args"][0]["value"] == 0x1000_0000));
+
+        config.hardening.tmpfs = vec![crate::hardening::Tmpfs {
+            path: PathBuf::from("/tmp"),
+            size_mb: Some(32),
+        }];
+        let spec = super::spec(Path::new("/srv/rootfs"), &config);
+        let tmp: Vec<_> = spec["mounts"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|m| m["destination"] == "/tmp")
+            .collect();
+        assert_eq!(tmp.len(), 1);
+        assert_eq!(
+            tmp[0]["options"],
+            json!(["nosuid", "nodev", "mode=1777", "size=32m"])
+        );
+    }
+
+    #[test]
+    fn test_private_dir() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::TempDir::new().unwrap();
+        let uid = unsafe { libc::geteuid() };
+
+        let bundles = dir.path().join("bundles");
+        private_dir(&bundles, uid).unwrap();
+        let mode = std::fs::metadata(&bundles).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        private_dir(&bundles, uid).unwrap();
+
+        // Someone else's directory, one others can enter, or a symlink
+        assert!(private_dir(&bundles, uid + 1).is_err());
+        let open = dir.path().join("open");
+        std::fs::create_dir(&open).unwrap();
+        std::fs::set_permissions(&open, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(private_dir(&open, uid).is_err());
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&bundles, &link).unwrap();
+        assert!(private_dir(&link, uid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_explicit_runc() {
+        // A stand-in runc that prints its arguments and exits
+        let dir = tempfile::TempDir::new().unwrap();
+        let runc = dir.path().join("runc");
+        std::fs::write(&runc, "#!/bin/sh\necho \"$@\"\n").unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&runc, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let rootfs = dir.path().join("rootfs");
+        std::fs::create_dir(&rootfs).unwrap();
+        let rt = OciRuntime::with_runc(runc);
+
+        let mut config = spawn_config();
+        config.socket = dir.path().join("sock/api-alice.sock");
+        config.rootfs = Some(rootfs.clone());
+        let mut handle = rt.spawn(&config).await.unwrap();
+        assert_eq!(handle.runtime_type(), RuntimeType::Oci);
+        assert!(dir.path().join("sock").is_dir());
+
+        let RuntimeHandle::Oci {
+            child, id, bundle, ..
+        } = &mut handle
+        else {
+            panic!("expected an oci handle");
+        };
+        assert_eq!(id, &container_name(&config.socket));
+        let written: Value =
+            serde_json::from_slice(&std::fs::read(bundle.join("config.json")).unwrap()).unwrap();
+        assert_eq!(
+            written["root"]["path"],
+            json!(std::fs::canonicalize(&rootfs).unwrap())
+        );
+        let bundle = bundle.clone();
+        let output = child.stdout.take().unwrap();
+        let mut line = String::new();
+        use tokio::io::AsyncBufReadExt;
+        tokio::io::BufReader::new(output)
+            .read_line(&mut line)
+            .await
+            .unwrap();
+        assert!(line.starts_with(&format!("run --bundle {}", bundle.display())));
+
+        // Killing removes the bundle
+        handle.kill().await.unwrap();
+        assert!(!bundle.exists());
+
+        // Missing rootfs is an error, not a container of the host's /
+        config.rootfs = Some(dir.path().join("missing"));
+        assert!(rt.spawn(&config).await.is_err());
+        config.rootfs = None;
+        assert!(rt.spawn(&config).await.is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! OOM score, CPU niceness and IO priority for spawned processes
+//!
+//! The process and namespace runtimes set these on the instance's process
+//! right after spawning it, so low-priority workers are the first to be
+//! OOM-killed and the last to get CPU and disk time. Children the app forks
+//! later inherit all three.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+/// Scheduling knobs for an instance (`oom_score_adj`, `nice`, `ionice`)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Priority {
+    /// -1000 (never OOM-kill) to 1000 (kill first)
+    pub oom_score_adj: Option<i32>,
+    /// -20 (highest) to 19 (lowest)
+    pub nice: Option<i32>,
+    pub ionice: Option<IoNice>,
+}
+
+/// IO scheduling class, as in `ionice -c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// IO priority: `"idle"`, `"best-effort"` or `"realtime"`, optionally with a
+/// level from 0 (highest) to 7 (`"best-effort:7"`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IoNice {
+    pub class: IoClass,
+    /// Ignored by the idle class
+    pub level: u8,
+}
+
+impl TryFrom<String> for IoNice {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s.as_str(), None),
+        };
+        let class = match class {
+            "realtime" => IoClass::Realtime,
+            "best-effort" => IoClass::BestEffort,
+            "idle" => IoClass::Idle,
+            _ => bail!(
+                "invalid ionice class {:?}; use \"realtime\", \"best-effort\" or \"idle\"",
+                class
+            ),
+        };
+        let level = match level {
+            Some(level) => match level.parse::<u8>() {
+                Ok(level) if level <= 7 => level,
+                _ => bail!("invalid ionice level {:?}; use 0 to 7", level),
+            },
+            // The kernel's default for best-effort
+            None => 4,
+        };
+        Ok(Self { class, level })
+    }
+}
+
+impl From<IoNice> for String {
+    fn from(ionice: IoNice) -> Self {
+        ionice.to_string()
+    }
+}
+
+impl std::fmt::Display for IoNice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.class {
+            IoClass::Realtime => write!(f, "realtime:{}", self.level),
+            IoClass::BestEffort => write!(f, "best-effort:{}", self.level),
+            IoClass::Idle => write!(f, "idle"),
+        }
+    }
+}
+
+impl IoNice {
+    /// Value for `ioprio_set` (linux/ioprio.h)
+    fn ioprio(self) -> i32 {
+        const IOPRIO_CLASS_SHIFT: i32 = 13;
+        let class = match self.class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        let level = match self.class {
+            IoClass::Idle => 0,
+            _ => i32::from(self.level),
+        };
+        (class << IOPRIO_CLASS_SHIFT) | level
+    }
+}
+
+impl Priority {
+    /// Whether any knob is set
+    pub fn is_set(&self) -> bool {
+        self.oom_score_adj.is_some() || self.nice.is_some() || self.ionice.is_some()
+    }
+
+    /// Check the values are in the kernel's ranges
+    pub fn validate(&self) -> Result<()> {
+        if let Some(adj) = self.oom_score_adj {
+            if !(-1000..=1000).contains(&adj) {
+                bail!("oom_score_adj {} is outside -1000..=1000", adj);
+            }
+        }
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                bail!("nice {} is outside -20..=19", nice);
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply to the process `pid`
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, pid: u32) -> Result<()> {
+        use anyhow::Context;
+
+        if let Some(adj) = self.oom_score_adj {
+            std::fs::write(format!("/proc/{}/oom_score_adj", pid), adj.to_string())
+                .with_context(|| format!("Failed to set oom_score_adj {} on {}", adj, pid))?;
+        }
+        if let Some(nice) = self.nice {
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, pid as libc::id_t, nice) } != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to set nice {} on {}", nice, pid));
+            }
+        }
+        if let Some(ionice) = self.ionice {
+            /// linux/ioprio.h
+            const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_ioprio_set,
+                    IOPRIO_WHO_PROCESS,
+                    pid as libc::c_int,
+                    ionice.ioprio(),
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("Failed to set ionice {} on {}", ionice, pid));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _pid: u32) -> Result<()> {
+        if self.is_set() {
+            bail!("oom_score_adj, nice and ionice are only supported on Linux");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ionice_parse() {
+        let idle = IoNice::try_from("idle".to_string()).unwrap();
+        assert_eq!(idle.class, IoClass::Idle);
+        assert_eq!(idle.ioprio(), 3 << 13);
+        let be = IoNice::try_from("best-effort:7".to_string()).unwrap();
+        assert_eq!((be.class, be.level), (IoClass::BestEffort, 7));
+        assert_eq!(be.ioprio(), (2 << 13) | 7);
+        assert_eq!(be.to_string(), "best-effort:7");
+        assert_eq!(IoNice::try_from("realtime".to_string()).unwrap().level, 4);
+        assert!(IoNice::try_from("best-effort:8".to_string()).is_err());
+        assert!(IoNice::try_from("fast".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Priority::default().validate().is_ok());
+        let ok = Priority {
+            oom_score_adj: Some(-1000),
+            nice: Some(19),
+            ionice: None,
+        };
+        assert!(ok.validate().is_ok());
+        for bad in [
+            Priority {
+                oom_score_adj: Some(1001),
+                ..Default::default()
+            },
+            Priority {
+                nice: Some(-21),
+                ..Default::default()
+            },
+        ] {
+            assert!(bad.validate().is_err());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_apply_to_child() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("5")
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        // Lowering priority needs no privileges
+        let priority = Priority {
+            oom_score_adj: Some(500),
+            nice: Some(10),
+            ionice: Some(IoNice::try_from("idle".to_string()).unwrap()),
+        };
+        let applied = priority.apply(pid);
+        let adj = std::fs::read_to_string(format!("/proc/{}/oom_score_adj", pid)).unwrap();
+        let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).unwrap();
+        child.kill().ok();
+        child.wait().ok();
+
+        applied.unwrap();
+        assert_eq!(adj.trim(), "500");
+        // Field 19 of /proc/<pid>/stat, counting after the `(comm)` field
+        let nice = stat.rsplit(')').next().unwrap().split_whitespace().nth(16);
+        assert_eq!(nice, Some("10"));
+    }
+}
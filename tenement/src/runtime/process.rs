@@ -40,13 +40,19 @@ impl Runtime for ProcessRuntime {
             .stderr(Stdio::piped())
             .kill_on_drop(true);
 
+        // A `seccomp` profile, compiled before the fork
+        #[cfg(target_os = "linux")]
+        let hardening = crate::hardening::ChildHardening::prepare(&config.hardening)?;
+
         // Put child in its own process group so we can kill all descendants
         #[cfg(unix)]
         unsafe {
-            cmd.pre_exec(|| {
+            cmd.pre_exec(move || {
                 if libc::setpgid(0, 0) != 0 {
                     return Err(std::io::Error::last_os_error());
                 }
+                #[cfg(target_os = "linux")]
+                hardening.apply()?;
                 Ok(())
             });
         }
@@ -55,13 +61,19 @@ impl Runtime for ProcessRuntime {
             cmd.current_dir(workdir);
         }
 
-        let child = cmd.spawn().with_context(|| {
+        let mut child = cmd.spawn().with_context(|| {
             format!(
                 "Failed to spawn process: {} {}\nCheck that the command exists and is executable. Use 'ten logs' to see output.",
                 config.command,
                 config.args.join(" ")
             )
         })?;
+        if let Some(pid) = child.id() {
+            if let Err(e) = config.priority.apply(pid) {
+                child.start_kill().ok();
+                return Err(e);
+            }
+        }
 
         Ok(RuntimeHandle::Process {
             child,
@@ -180,6 +192,71 @@ mod tests {
         assert_eq!(handle.runtime_type(), RuntimeType::Process);
     }
 
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    #[tokio::test]
+    async fn test_process_runtime_spawn_with_seccomp_profile() {
+        let dir = TempDir::new().unwrap();
+        let profile = dir.path().join("seccomp.json");
+        std::fs::write(
+            &profile,
+            r#"{"defaultAction": "SCMP_ACT_ALLOW", "syscalls": [
+                {"names": ["mkdir", "mkdirat"], "action": "SCMP_ACT_ERRNO"}
+            ]}"#,
+        )
+        .unwrap();
+        let target = dir.path().join("denied");
+        let mut config = test_spawn_config(
+            "mkdir",
+            vec![target.to_str().unwrap()],
+            dir.path().join("app.sock"),
+        );
+        config.hardening.seccomp = crate::hardening::SeccompProfile::File(profile);
+
+        let mut handle = ProcessRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Process { child, .. } = &mut handle else {
+            panic!("expected a process handle");
+        };
+        assert!(!child.wait().await.unwrap().success());
+        assert!(!target.exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_process_runtime_spawn_as_user() {
+        use tokio::io::AsyncReadExt;
+
+        let dir = TempDir::new().unwrap();
+        let mut config = test_spawn_config(
+            "sh",
+            vec!["-c", "id -u; id -g; id -G"],
+            dir.path().join("app.sock"),
+        );
+        config.hardening.run_as = Some(crate::hardening::RunAs {
+            uid: 4_000_000,
+            gid: 4_000_001,
+            groups: vec![4_000_001],
+        });
+
+        let mut handle = ProcessRuntime::new().spawn(&config).await.unwrap();
+        let RuntimeHandle::Process { child, .. } = &mut handle else {
+            panic!("expected a process handle");
+        };
+        let mut out = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut out)
+            .await
+            .unwrap();
+        assert!(child.wait().await.unwrap().success());
+        assert_eq!(out, "4000000\n4000001\n4000001\n");
+    }
+
     #[tokio::test]
     async fn test_process_runtime_spawn_with_workdir() {
         let dir = TempDir::new().unwrap();
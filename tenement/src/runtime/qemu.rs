@@ -10,21 +10,154 @@
 //!
 //! ## QEMU Binary
 //! Requires `qemu-system-x86_64` (or `qemu-system-aarch64` on ARM) in PATH.
-
+//!
+//! ## Shared data directory
+//! With `VmConfig::shared_dir` set (the instance's data dir), the directory is
+//! exported to the guest under the tag `data`, so files the app writes land on
+//! the host where storage quotas and `storage_persist` apply. It uses
+//! virtio-fs when `virtiofsd` is available (Linux; found via
+//! `TENEMENT_VIRTIOFSD`, `PATH` or the usual libexec paths) and virtio-9p,
+//! served by QEMU itself, otherwise. The guest mounts it with
+//! `mount -t virtiofs data /data` or
+//! `mount -t 9p -o trans=virtio,version=9p2000.L data /data`; the kernel
+//! command line carries `tenement.data=virtiofs|9p` for init scripts.
+
+use super::docker::find_on_path;
 use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 use tracing::{debug, info, warn};
 
 /// Counter for unique instance IDs
 static NEXT_INSTANCE_ID: AtomicU32 = AtomicU32::new(1);
 
+/// Environment variable that selects the virtiofsd binary.
+pub const VIRTIOFSD_ENV: &str = "TENEMENT_VIRTIOFSD";
+
+/// Where distributions install virtiofsd outside `PATH`
+const VIRTIOFSD_PATHS: &[&str] = &[
+    "/usr/libexec/virtiofsd",
+    "/usr/lib/qemu/virtiofsd",
+    "/usr/lib/virtiofsd",
+];
+
+/// Tag the shared data dir is exported under
+const SHARED_DIR_TAG: &str = "data";
+
+/// How the shared data dir reaches the guest
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SharedFs {
+    /// virtio-fs through a virtiofsd vhost-user socket
+    VirtioFs { socket: PathBuf },
+    /// virtio-9p, served by QEMU itself
+    NineP,
+}
+
+impl SharedFs {
+    /// Filesystem type the guest mounts
+    fn fstype(&self) -> &'static str {
+        match self {
+            SharedFs::VirtioFs { .. } => "virtiofs",
+            SharedFs::NineP => "9p",
+        }
+    }
+}
+
+/// Resolve virtiofsd: env var -> PATH -> libexec locations.
+fn find_virtiofsd() -> Option<PathBuf> {
+    if let Ok(virtiofsd) = std::env::var(VIRTIOFSD_ENV) {
+        return if virtiofsd.contains('/') {
+            let path = PathBuf::from(virtiofsd);
+            path.exists().then_some(path)
+        } else {
+            find_on_path(&virtiofsd)
+        };
+    }
+    find_on_path("virtiofsd").or_else(|| {
+        VIRTIOFSD_PATHS
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists())
+    })
+}
+
+/// `path` as a QEMU option value: a `,` ends the value, so a literal one is
+/// doubled
+fn option_path(path: &Path) -> String {
+    path.display().to_string().replace(',', ",,")
+}
+
+/// QEMU arguments exporting `dir` to the guest. microvm machines have no PCI
+/// bus, so they get the virtio-mmio variants of the devices.
+fn shared_dir_args(dir: &Path, fs: &SharedFs, microvm: bool, memory_mb: u32) -> Vec<String> {
+    let bus = if microvm { "device" } else { "pci" };
+    match fs {
+        SharedFs::VirtioFs { socket } => vec![
+            "-chardev".to_string(),
+            format!("socket,id=datafs,path={}", option_path(socket)),
+            "-device".to_string(),
+            format!(
+                "vhost-user-fs-{},chardev=datafs,tag={}",
+                bus, SHARED_DIR_TAG
+            ),
+            // virtiofsd maps guest memory, so it has to be shared
+            "-object".to_string(),
+            format!("memory-backend-memfd,id=mem,size={}M,share=on", memory_mb),
+            "-machine".to_string(),
+            "memory-backend=mem".to_string(),
+        ],
+        SharedFs::NineP => vec![
+            "-fsdev".to_string(),
+            format!(
+                "local,id=datafs,path={},security_model=mapped-xattr",
+                option_path(dir)
+            ),
+            "-device".to_string(),
+            format!(
+                "virtio-9p-{},fsdev=datafs,mount_tag={}",
+                bus, SHARED_DIR_TAG
+            ),
+        ],
+    }
+}
+
+/// Start virtiofsd serving `dir` on `socket` and wait for the socket
+async fn start_virtiofsd(virtiofsd: &Path, dir: &Path, socket: &Path) -> Result<Child> {
+    std::fs::remove_file(socket).ok();
+    let mut cmd = Command::new(virtiofsd);
+    cmd.arg(format!("--socket-path={}", socket.display()))
+        .arg(format!("--shared-dir={}", dir.display()))
+        .arg("--cache=auto")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true);
+    // The namespace sandbox needs root
+    #[cfg(unix)]
+    if unsafe { libc::geteuid() } != 0 {
+        cmd.arg("--sandbox=none");
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to spawn virtiofsd at {:?}", virtiofsd))?;
+
+    let start = std::time::Instant::now();
+    while !socket.exists() {
+        if start.elapsed() > Duration::from_secs(5) || matches!(child.try_wait(), Ok(Some(_))) {
+            child.start_kill().ok();
+            anyhow::bail!("virtiofsd did not create {:?}", socket);
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    Ok(child)
+}
+
 /// Runtime that spawns QEMU microVMs
 ///
 /// This runtime spawns QEMU processes with virtio-serial for guest communication.
@@ -280,24 +413,59 @@ impl Runtime for QemuRuntime {
         cmd.arg("-smp").arg(vm_config.vcpus.to_string());
         cmd.arg("-m").arg(format!("{}M", vm_config.memory_mb));
 
+        // Share the data dir before QEMU starts: virtiofsd must be listening
+        let microvm = self.use_microvm && accel == "kvm";
+        let mut virtiofsd = None;
+        let shared = match &vm_config.shared_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create shared dir {:?}", dir))?;
+                let fs = match find_virtiofsd().filter(|_| cfg!(target_os = "linux")) {
+                    Some(bin) => {
+                        let socket = socket_dir
+                            .join(format!("qemu-{}-{}-fs.sock", instance_name, instance_id));
+                        virtiofsd = Some(start_virtiofsd(&bin, dir, &socket).await?);
+                        SharedFs::VirtioFs { socket }
+                    }
+                    None => SharedFs::NineP,
+                };
+                info!(
+                    "Sharing {} with the guest over {}",
+                    dir.display(),
+                    fs.fstype()
+                );
+                Some((dir, fs))
+            }
+            None => None,
+        };
+
         // Kernel and boot args
         cmd.arg("-kernel").arg(&vm_config.kernel);
-        let boot_args = "console=ttyS0 root=/dev/vda rw";
+        let mut boot_args = "console=ttyS0 root=/dev/vda rw".to_string();
+        if let Some((_, fs)) = &shared {
+            boot_args.push_str(&format!(" tenement.data={}", fs.fstype()));
+        }
         cmd.arg("-append").arg(boot_args);
 
         // Root filesystem
         cmd.arg("-drive").arg(format!(
             "file={},format=raw,if=virtio",
-            vm_config.rootfs.display()
+            option_path(&vm_config.rootfs)
         ));
 
+        if let Some((dir, fs)) = &shared {
+            cmd.args(shared_dir_args(dir, fs, microvm, vm_config.memory_mb));
+        }
+
         // QMP control socket
         cmd.arg("-qmp")
-            .arg(format!("unix:{},server,nowait", qmp_socket.display()));
+            .arg(format!("unix:{},server,nowait", option_path(&qmp_socket)));
 
         // Serial console as Unix socket (for guest communication)
-        cmd.arg("-serial")
-            .arg(format!("unix:{},server,nowait", serial_socket.display()));
+        cmd.arg("-serial").arg(format!(
+            "unix:{},server,nowait",
+            option_path(&serial_socket)
+        ));
 
         // No display
         cmd.arg("-nographic");
@@ -335,6 +503,7 @@ impl Runtime for QemuRuntime {
             child,
             qmp_socket,
             serial_socket,
+            virtiofsd,
         })
     }
 
@@ -395,6 +564,46 @@ mod tests {
         assert!(runtime.use_microvm);
     }
 
+    #[test]
+    fn test_shared_dir_args() {
+        let dir = Path::new("/var/lib/tenement/worker/alice");
+        let socket = PathBuf::from("/run/tenement/qemu-worker-1-fs.sock");
+
+        let args = shared_dir_args(dir, &SharedFs::VirtioFs { socket }, false, 256);
+        assert!(args
+            .windows(2)
+            .any(|w| w[0] == "-device" && w[1] == "vhost-user-fs-pci,chardev=datafs,tag=data"));
+        assert!(
+            args.contains(&"socket,id=datafs,path=/run/tenement/qemu-worker-1-fs.sock".to_string())
+        );
+        assert!(args.contains(&"memory-backend-memfd,id=mem,size=256M,share=on".to_string()));
+
+        // microvm has no PCI bus
+        let args = shared_dir_args(dir, &SharedFs::NineP, true, 256);
+        assert_eq!(
+            args,
+            [
+                "-fsdev",
+                "local,id=datafs,path=/var/lib/tenement/worker/alice,security_model=mapped-xattr",
+                "-device",
+                "virtio-9p-device,fsdev=datafs,mount_tag=data",
+            ]
+        );
+        assert_eq!(SharedFs::NineP.fstype(), "9p");
+
+        // A comma in the path can't smuggle in another option
+        let args = shared_dir_args(
+            Path::new("/data/a,security_model=none"),
+            &SharedFs::NineP,
+            true,
+            256,
+        );
+        assert_eq!(
+            args[1],
+            "local,id=datafs,path=/data/a,,security_model=none,security_model=mapped-xattr"
+        );
+    }
+
     #[test]
     fn test_get_accel() {
         let accel = QemuRuntime::get_accel();
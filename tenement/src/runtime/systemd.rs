@@ -0,0 +1,371 @@
+//! systemd runtime - runs each instance in a transient systemd scope.
+//!
+//! For hosts already managed by systemd: `isolation = "systemd"` hands
+//! resource control and process tracking to the service manager instead of
+//! Tenement's own cgroup manager. Each instance is started through
+//! `systemd-run --scope`, which registers a transient `.scope` unit and then
+//! execs the command in place, so the child Tenement supervises is the app
+//! itself: output is captured, health checks and routing work as for bare
+//! processes, and the instance shows up in `systemctl status` and
+//! `systemd-cgtop` under its unit name.
+//!
+//! ```text
+//! systemd-run [--user] --scope --unit=ten-<name>-<hash> --collect --quiet \
+//!   [-p MemoryMax=<memory_limit_mb>M] [-p MemoryHigh=<memory_high_mb>M] \
+//!   [-p MemorySwapMax=<memory_swap_max_mb>M] [-p CPUWeight=<cpu_shares>] \
+//!   [-p TasksMax=<max_pids>] [-p AllowedCPUs=<cpuset>] \
+//!   [-p <systemd_properties> ...] -- <command> [args...]
+//! ```
+//!
+//! `memory_limit_mb`, `memory_high_mb`, `memory_swap_max_mb`, `cpu_shares`,
+//! `max_pids` and `cpuset` become `MemoryMax=`, `MemoryHigh=`,
+//! `MemorySwapMax=`, `CPUWeight=`, `TasksMax=` and `AllowedCPUs=`; anything
+//! else systemd supports for scopes (`IOWeight=`, `MemoryLow=`, ...) goes in
+//! the service's `systemd_properties`. Stopping an instance stops the unit,
+//! which kills every process in the scope, even ones that left the process
+//! group.
+//!
+//! The unit name is derived from the socket path (like the container
+//! runtimes' names), so a scope left behind by a crashed daemon is stopped
+//! before the instance is spawned again. When the daemon isn't root, units go
+//! to the user's service manager (`--user`).
+//!
+//! ## systemd-run discovery (first match wins)
+//!
+//! 1. explicit path passed to [`SystemdRuntime::with_systemd_run`];
+//! 2. `TENEMENT_SYSTEMD_RUN` environment variable (a path, or a name looked
+//!    up on `PATH`);
+//! 3. `systemd-run` on `PATH`.
+//!
+//! `systemctl` is taken from the same directory as `systemd-run`, falling
+//! back to `PATH`.
+
+use super::docker::{container_name, find_on_path};
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that selects the systemd-run binary.
+pub const SYSTEMD_RUN_ENV: &str = "TENEMENT_SYSTEMD_RUN";
+
+/// Present when systemd is the running init system (see sd_booted(3))
+const SYSTEMD_RUNTIME_DIR: &str = "/run/systemd/system";
+
+/// Runtime that runs instances in transient scopes via `systemd-run`.
+pub struct SystemdRuntime {
+    /// Explicit systemd-run path. When `None`, discovered from env/PATH at use time.
+    systemd_run: Option<PathBuf>,
+}
+
+impl SystemdRuntime {
+    pub fn new() -> Self {
+        Self { systemd_run: None }
+    }
+
+    /// Construct with an explicit systemd-run binary (highest precedence).
+    pub fn with_systemd_run(path: PathBuf) -> Self {
+        Self {
+            systemd_run: Some(path),
+        }
+    }
+
+    /// Resolve the systemd-run binary: explicit path -> env var -> PATH.
+    fn find_systemd_run(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.systemd_run {
+            return path.exists().then(|| path.clone());
+        }
+        if let Ok(systemd_run) = std::env::var(SYSTEMD_RUN_ENV) {
+            return if systemd_run.contains('/') {
+                let path = PathBuf::from(systemd_run);
+                path.exists().then_some(path)
+            } else {
+                find_on_path(&systemd_run)
+            };
+        }
+        find_on_path("systemd-run")
+    }
+}
+
+impl Default for SystemdRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `systemctl` next to `systemd_run`, else from `PATH`
+fn find_systemctl(systemd_run: &Path) -> PathBuf {
+    systemd_run
+        .parent()
+        .map(|dir| dir.join("systemctl"))
+        .filter(|path| path.exists())
+        .or_else(|| find_on_path("systemctl"))
+        .unwrap_or_else(|| PathBuf::from("systemctl"))
+}
+
+/// Whether units go to the per-user service manager (daemon isn't root)
+fn user_manager() -> bool {
+    #[cfg(unix)]
+    {
+        unsafe { libc::geteuid() != 0 }
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// Arguments for systemd-run (everything after the binary)
+fn systemd_run_args(unit: &str, user: bool, config: &SpawnConfig) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    if user {
+        args.push("--user".to_string());
+    }
+    args.extend([
+        "--scope".to_string(),
+        format!("--unit={}", unit),
+        "--collect".to_string(),
+        "--quiet".to_string(),
+    ]);
+    if let Some(memory_mb) = config.memory_limit_mb.filter(|mb| *mb > 0) {
+        args.push("-p".to_string());
+        args.push(format!("MemoryMax={}M", memory_mb));
+    }
+    if let Some(high_mb) = config.memory_high_mb {
+        args.push("-p".to_string());
+        args.push(format!("MemoryHigh={}M", high_mb));
+    }
+    if let Some(swap_mb) = config.memory_swap_max_mb {
+        args.push("-p".to_string());
+        args.push(format!("MemorySwapMax={}M", swap_mb));
+    }
+    if let Some(cpu_shares) = config.cpu_shares {
+        args.push("-p".to_string());
+        args.push(format!("CPUWeight={}", cpu_shares.clamp(1, 10000)));
+    }
+    if let Some(max_pids) = config.max_pids {
+        args.push("-p".to_string());
+        args.push(format!("TasksMax={}", max_pids));
+    }
+    if let Some(cpus) = &config.cpuset {
+        args.push("-p".to_string());
+        args.push(format!("AllowedCPUs={}", cpus));
+    }
+    for property in &config.systemd_properties {
+        if !property.contains('=') {
+            bail!(
+                "systemd property {:?} is not of the form Key=Value",
+                property
+            );
+        }
+        args.push("-p".to_string());
+        args.push(property.clone());
+    }
+    args.push("--".to_string());
+    args.push(config.command.clone());
+    args.extend(config.args.iter().cloned());
+    Ok(args)
+}
+
+#[async_trait]
+impl Runtime for SystemdRuntime {
+    async fn spawn(&self, config: &SpawnConfig) -> Result<RuntimeHandle> {
+        use std::process::Stdio;
+        use tokio::process::Command;
+
+        let systemd_run = self.find_systemd_run().with_context(|| {
+            format!(
+                "systemd-run not found. Install systemd, or set {SYSTEMD_RUN_ENV}=/path/to/systemd-run."
+            )
+        })?;
+        if config.command.is_empty() {
+            bail!("systemd isolation needs a `command` to run");
+        }
+        let systemctl = find_systemctl(&systemd_run);
+        let user = user_manager();
+        let unit = container_name(&config.socket);
+        let args = systemd_run_args(&unit, user, config)?;
+
+        if config.socket.exists() {
+            std::fs::remove_file(&config.socket).ok();
+        }
+
+        // A scope from a daemon that crashed would hold the unit name
+        let mut stop = Command::new(&systemctl);
+        if user {
+            stop.arg("--user");
+        }
+        let _ = stop
+            .args(["stop", &format!("{}.scope", unit)])
+            .output()
+            .await;
+
+        let mut cmd = Command::new(&systemd_run);
+        cmd.args(&args)
+            .envs(&config.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+
+        // Own process group, like the process runtime. systemd-run execs the
+        // command, so the group leader is the app itself.
+        #[cfg(unix)]
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        if let Some(workdir) = &config.workdir {
+            cmd.current_dir(workdir);
+        }
+
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to run {:?}", systemd_run))?;
+
+        Ok(RuntimeHandle::Systemd {
+            child,
+            unit,
+            systemctl,
+            user,
+            socket: config.socket.clone(),
+        })
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Systemd
+    }
+
+    fn is_available(&self) -> bool {
+        cfg!(target_os = "linux")
+            && Path::new(SYSTEMD_RUNTIME_DIR).is_dir()
+            && self.find_systemd_run().is_some()
+    }
+
+    fn name(&self) -> &'static str {
+        "systemd"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn spawn_config() -> SpawnConfig {
+        SpawnConfig {
+            command: "/app/server".to_string(),
+            args: vec!["--port".to_string(), "31000".to_string()],
+            env: HashMap::from([("PORT".to_string(), "31000".to_string())]),
+            socket: PathBuf::from("/run/tenement/api-alice.sock"),
+            memory_limit_mb: Some(128),
+            memory_high_mb: Some(96),
+            cpu_shares: Some(50000),
+            max_pids: Some(64),
+            cpuset: Some("2-3".to_string()),
+            systemd_properties: vec!["IOWeight=50".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_systemd_runtime_type_and_name() {
+        let rt = SystemdRuntime::new();
+        assert_eq!(rt.runtime_type(), RuntimeType::Systemd);
+        assert_eq!(rt.name(), "systemd");
+    }
+
+    #[test]
+    fn test_systemd_run_args() {
+        let args = systemd_run_args("ten-api-alice-1234", false, &spawn_config()).unwrap();
+        assert_eq!(
+            args,
+            [
+                "--scope",
+                "--unit=ten-api-alice-1234",
+                "--collect",
+                "--quiet",
+                "-p",
+                "MemoryMax=128M",
+                "-p",
+                "MemoryHigh=96M",
+                "-p",
+                "CPUWeight=10000",
+                "-p",
+                "TasksMax=64",
+                "-p",
+                "AllowedCPUs=2-3",
+                "-p",
+                "IOWeight=50",
+                "--",
+                "/app/server",
+                "--port",
+                "31000",
+            ]
+        );
+
+        // Non-root daemons use the user manager; no limits, no properties
+        let mut config = spawn_config();
+        config.memory_limit_mb = Some(0);
+        config.memory_high_mb = None;
+        config.cpu_shares = None;
+        config.max_pids = None;
+        config.cpuset = None;
+        config.systemd_properties.clear();
+        let args = systemd_run_args("ten-x", true, &config).unwrap();
+        assert_eq!(args[0], "--user");
+        assert!(!args.iter().any(|a| a == "-p"));
+
+        config.systemd_properties = vec!["TasksMax".to_string()];
+        assert!(systemd_run_args("ten-x", false, &config).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spawn_with_explicit_systemd_run() {
+        // A stand-in systemd-run that prints its arguments, then execs the
+        // command like the real one does
+        let dir = tempfile::TempDir::new().unwrap();
+        let systemd_run = dir.path().join("systemd-run");
+        std::fs::write(
+            &systemd_run,
+            "#!/bin/sh\necho \"$@\"\nwhile [ \"$1\" != \"--\" ]; do shift; done\nshift\nexec \"$@\"\n",
+        )
+        .unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&systemd_run, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        let rt = SystemdRuntime::with_systemd_run(systemd_run);
+
+        let mut config = spawn_config();
+        config.command = "sh".to_string();
+        config.args = vec!["-c".to_string(), "echo port=$PORT; sleep 30".to_string()];
+        config.socket = dir.path().join("api-alice.sock");
+        let mut handle = rt.spawn(&config).await.unwrap();
+        assert_eq!(handle.runtime_type(), RuntimeType::Systemd);
+        assert!(handle.pid().is_some());
+
+        let RuntimeHandle::Systemd { child, unit, .. } = &mut handle else {
+            panic!("expected a systemd handle");
+        };
+        assert_eq!(unit, &container_name(&config.socket));
+        let unit = unit.clone();
+        let output = child.stdout.take().unwrap();
+        use tokio::io::AsyncBufReadExt;
+        let mut lines = tokio::io::BufReader::new(output).lines();
+        let args = lines.next_line().await.unwrap().unwrap();
+        assert!(args.contains(&format!("--scope --unit={} --collect", unit)));
+        assert!(args.contains("-p IOWeight=50 -- sh -c"));
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "port=31000");
+
+        assert!(handle.is_running().await);
+        handle.kill().await.unwrap();
+        assert!(!handle.is_running().await);
+    }
+}
@@ -0,0 +1,696 @@
+//! Private networking for microVMs and network namespaces
+//!
+//! vsock only carries traffic from Tenement into the guest; a guest that
+//! calls out (APIs, package mirrors, databases) needs a network interface.
+//! With a private `network`, each VM gets its own host tap device on a
+//! point-to-point /30 carved from `subnet`:
+//!
+//! ```text
+//! host  tentap<N>  <subnet + 4N + 1>/30
+//! guest eth0       <subnet + 4N + 2>/30, default route via the host side
+//! ```
+//!
+//! The guest's address is set on the kernel command line (`ip=`, needs
+//! `CONFIG_IP_PNP` in the guest kernel). With `nat` on, guest traffic is
+//! masqueraded behind the host's address and forwarded to the internet.
+//! Private, shared-address and link-local ranges (other tenants' links, the
+//! host's LAN, cloud metadata) are dropped unless listed in `allow`, and the
+//! guest can't open connections to the host itself. Slots are derived from
+//! the instance name, so
+//! an instance keeps its tap and addresses across restarts (and snapshot
+//! restores) unless another instance already holds them.
+//!
+//! Namespaced instances get the same /30 over a veth pair instead
+//! ([`VethLink`]): the far end is `eth0` in a named network namespace
+//! (`ip netns`) the instance joins before exec.
+//!
+//! Creating taps, veths and NAT rules needs root (`CAP_NET_ADMIN`), `ip` and
+//! `iptables`.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Subnet the per-VM links are carved from unless configured
+pub const DEFAULT_SUBNET: &str = "172.30.0.0/16";
+
+/// Slots in use, by subnet base address
+static SLOTS: Mutex<BTreeSet<(u32, u32)>> = Mutex::new(BTreeSet::new());
+
+/// Network a service's instances get
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkMode {
+    /// The host's network stack (namespaces' default)
+    #[default]
+    Host,
+    /// An empty network namespace with loopback only (VMs' default)
+    None,
+    /// A /30 link to the host, optionally NATed out (see [`PrivateNetwork`])
+    Private,
+}
+
+impl std::fmt::Display for NetworkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Private => write!(f, "private"),
+        }
+    }
+}
+
+/// `network = "none" | "host" | "private"`, or a table configuring a
+/// private network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Network {
+    Mode(NetworkMode),
+    Private(PrivateNetwork),
+}
+
+impl Network {
+    pub fn mode(&self) -> NetworkMode {
+        match self {
+            Network::Mode(mode) => *mode,
+            Network::Private(_) => NetworkMode::Private,
+        }
+    }
+
+    /// Settings for a private network, defaults for `"private"`
+    pub fn private(&self) -> Option<PrivateNetwork> {
+        match self {
+            Network::Mode(NetworkMode::Private) => Some(PrivateNetwork::default()),
+            Network::Mode(_) => None,
+            Network::Private(network) => Some(network.clone()),
+        }
+    }
+}
+
+/// Settings for a private network
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrivateNetwork {
+    /// IPv4 subnet (CIDR) the per-instance /30 links are carved from
+    #[serde(default = "default_subnet")]
+    pub subnet: String,
+    /// Masquerade and forward guest traffic so it can leave the host
+    #[serde(default = "default_nat")]
+    pub nat: bool,
+    /// CIDRs in the otherwise blocked private ranges the guest may reach
+    /// (e.g. a database on the LAN)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+fn default_subnet() -> String {
+    DEFAULT_SUBNET.to_string()
+}
+
+fn default_nat() -> bool {
+    true
+}
+
+impl Default for PrivateNetwork {
+    fn default() -> Self {
+        Self {
+            subnet: default_subnet(),
+            nat: default_nat(),
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl PrivateNetwork {
+    /// The subnet's base address and prefix length
+    pub fn parse_subnet(&self) -> Result<(Ipv4Addr, u8)> {
+        let (addr, prefix) = parse_cidr(&self.subnet).context("invalid subnet")?;
+        if !(8..=30).contains(&prefix) {
+            bail!(
+                "subnet {:?} must be between /8 and /30 (each instance needs a /30)",
+                self.subnet
+            );
+        }
+        Ok((addr, prefix))
+    }
+
+    /// Check the subnet and the `allow` list
+    pub fn validate(&self) -> Result<()> {
+        self.parse_subnet()?;
+        for cidr in &self.allow {
+            parse_cidr(cidr).context("invalid allow entry")?;
+        }
+        Ok(())
+    }
+}
+
+/// Address and prefix length of an IPv4 CIDR without host bits
+fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8)> {
+    let (addr, prefix) = cidr
+        .split_once('/')
+        .with_context(|| format!("{:?} is not in CIDR form", cidr))?;
+    let addr: Ipv4Addr = addr
+        .parse()
+        .with_context(|| format!("{:?} has an invalid address", cidr))?;
+    let prefix: u8 = prefix
+        .parse()
+        .ok()
+        .filter(|prefix| *prefix <= 32)
+        .with_context(|| format!("{:?} has an invalid prefix", cidr))?;
+    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+    if u32::from(addr) & !mask != 0 {
+        bail!(
+            "{:?} has host bits set (did you mean {}/{}?)",
+            cidr,
+            Ipv4Addr::from(u32::from(addr) & mask),
+            prefix
+        );
+    }
+    Ok((addr, prefix))
+}
+
+/// A host tap device linked to one VM over a point-to-point /30
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapDevice {
+    /// Host interface name
+    pub name: String,
+    /// Address of the host end (the guest's gateway)
+    pub host_ip: Ipv4Addr,
+    /// Address of the guest's eth0
+    pub guest_ip: Ipv4Addr,
+    /// Whether NAT rules are installed for the guest
+    pub nat: bool,
+    subnet: String,
+    allow: Vec<String>,
+    base: u32,
+    slot: u32,
+    preferred: bool,
+}
+
+impl TapDevice {
+    /// Reserve a tap name and /30 for `instance`
+    pub fn allocate(network: &PrivateNetwork, instance: &str) -> Result<Self> {
+        let (base, slot, preferred) = reserve_slot(network, instance)?;
+        Ok(Self {
+            name: format!("tentap{}", slot),
+            host_ip: Ipv4Addr::from(base + slot * 4 + 1),
+            guest_ip: Ipv4Addr::from(base + slot * 4 + 2),
+            nat: network.nat,
+            subnet: network.subnet.clone(),
+            allow: network.allow.clone(),
+            base,
+            slot,
+            preferred,
+        })
+    }
+
+    /// Whether the instance got its usual slot. A snapshot taken in another
+    /// slot has the wrong tap and addresses baked in.
+    pub fn is_preferred(&self) -> bool {
+        self.preferred
+    }
+
+    /// Locally administered MAC for the guest, derived from its address
+    pub fn guest_mac(&self) -> String {
+        let [a, b, c, d] = self.guest_ip.octets();
+        format!("06:00:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d)
+    }
+
+    /// Kernel command line argument that configures the guest's eth0
+    pub fn boot_arg(&self) -> String {
+        format!(
+            "ip={}::{}:255.255.255.252::eth0:off",
+            self.guest_ip, self.host_ip
+        )
+    }
+
+    fn rules(&self) -> Vec<Vec<String>> {
+        link_rules(
+            &self.name,
+            self.guest_ip,
+            self.nat,
+            &self.subnet,
+            &self.allow,
+        )
+    }
+
+    /// Create the tap, address it and install the firewall rules
+    pub async fn create(&self) -> Result<()> {
+        // A tap left behind by a crashed daemon
+        let _ = run("ip", &["link", "del", &self.name]).await;
+        let host = format!("{}/30", self.host_ip);
+        let setup = async {
+            run("ip", &["tuntap", "add", "dev", &self.name, "mode", "tap"]).await?;
+            run("ip", &["addr", "add", &host, "dev", &self.name]).await?;
+            run("ip", &["link", "set", &self.name, "up"]).await?;
+            install_rules(&self.rules()).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(e) = setup.await {
+            self.destroy().await;
+            return Err(e.context(format!("Failed to set up tap device {}", self.name)));
+        }
+        Ok(())
+    }
+
+    /// Remove the firewall rules and the tap, and free the slot
+    pub async fn destroy(&self) {
+        remove_rules(&self.rules()).await;
+        let _ = run("ip", &["link", "del", &self.name]).await;
+        release_slot(self.base, self.slot);
+    }
+}
+
+/// A veth pair linking a named network namespace to the host over a /30
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VethLink {
+    /// Host end of the pair; the other end is `eth0` in `netns`
+    pub name: String,
+    /// Named network namespace (`ip netns`) the instance joins
+    pub netns: String,
+    /// Address of the host end (the instance's gateway)
+    pub host_ip: Ipv4Addr,
+    /// Address of the instance's eth0
+    pub guest_ip: Ipv4Addr,
+    /// Whether NAT rules are installed for the instance
+    pub nat: bool,
+    subnet: String,
+    allow: Vec<String>,
+    base: u32,
+    slot: u32,
+}
+
+impl VethLink {
+    /// Reserve a veth name, namespace and /30 for `instance`
+    pub fn allocate(network: &PrivateNetwork, instance: &str) -> Result<Self> {
+        let (base, slot, _) = reserve_slot(network, instance)?;
+        Ok(Self {
+            name: format!("tenveth{}", slot),
+            netns: format!("tenement{}", slot),
+            host_ip: Ipv4Addr::from(base + slot * 4 + 1),
+            guest_ip: Ipv4Addr::from(base + slot * 4 + 2),
+            nat: network.nat,
+            subnet: network.subnet.clone(),
+            allow: network.allow.clone(),
+            base,
+            slot,
+        })
+    }
+
+    /// Where `ip netns` keeps the namespace, for `setns(2)`
+    pub fn netns_path(&self) -> PathBuf {
+        Path::new("/var/run/netns").join(&self.netns)
+    }
+
+    fn rules(&self) -> Vec<Vec<String>> {
+        link_rules(
+            &self.name,
+            self.guest_ip,
+            self.nat,
+            &self.subnet,
+            &self.allow,
+        )
+    }
+
+    /// Create the namespace and the veth pair, address both ends, route the
+    /// namespace through the host and install the firewall rules
+    pub async fn create(&self) -> Result<()> {
+        // Leftovers from a crashed daemon
+        let _ = run("ip", &["link", "del", &self.name]).await;
+        let _ = run("ip", &["netns", "del", &self.netns]).await;
+        let host = format!("{}/30", self.host_ip);
+        let guest = format!("{}/30", self.guest_ip);
+        let gateway = self.host_ip.to_string();
+        let ns = self.netns.as_str();
+        let setup = async {
+            run("ip", &["netns", "add", ns]).await?;
+            run(
+                "ip",
+                &[
+                    "link", "add", &self.name, "type", "veth", "peer", "name", "eth0", "netns", ns,
+                ],
+            )
+            .await?;
+            run("ip", &["addr", "add", &host, "dev", &self.name]).await?;
+            run("ip", &["link", "set", &self.name, "up"]).await?;
+            run("ip", &["-n", ns, "link", "set", "lo", "up"]).await?;
+            run("ip", &["-n", ns, "addr", "add", &guest, "dev", "eth0"]).await?;
+            run("ip", &["-n", ns, "link", "set", "eth0", "up"]).await?;
+            run(
+                "ip",
+                &["-n", ns, "route", "add", "default", "via", &gateway],
+            )
+            .await?;
+            install_rules(&self.rules()).await?;
+            Ok::<_, anyhow::Error>(())
+        };
+        if let Err(e) = setup.await {
+            self.destroy().await;
+            return Err(e.context(format!("Failed to set up veth {}", self.name)));
+        }
+        Ok(())
+    }
+
+    /// Remove the firewall rules, the veth pair and the namespace, and free the
+    /// slot
+    pub async fn destroy(&self) {
+        remove_rules(&self.rules()).await;
+        let _ = run("ip", &["link", "del", &self.name]).await;
+        let _ = run("ip", &["netns", "del", &self.netns]).await;
+        release_slot(self.base, self.slot);
+    }
+}
+
+/// Reserve a /30 in `network` for `instance`: its base address, slot and
+/// whether that's the instance's usual slot
+fn reserve_slot(network: &PrivateNetwork, instance: &str) -> Result<(u32, u32, bool)> {
+    let (base, prefix) = network.parse_subnet()?;
+    let base = u32::from(base);
+    let slots = 1u32 << (32 - prefix - 2);
+    // Hashed, so the slot is stable across restarts
+    let preferred = (crate::hash::fnv1a(instance.bytes()) % u64::from(slots)) as u32;
+
+    let mut used = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+    let slot = (0..slots)
+        .map(|i| (preferred + i) % slots)
+        .find(|slot| !used.contains(&(base, *slot)))
+        .with_context(|| format!("no free addresses left in {}", network.subnet))?;
+    used.insert((base, slot));
+    Ok((base, slot, slot == preferred))
+}
+
+fn release_slot(base: u32, slot: u32) {
+    SLOTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&(base, slot));
+}
+
+/// Destinations a guest never reaches through the host unless allowed:
+/// RFC 1918, shared address space (CGNAT) and link-local (cloud metadata)
+const BLOCKED_RANGES: [&str; 5] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "100.64.0.0/10",
+    "169.254.0.0/16",
+];
+
+/// `iptables` rules for the link `iface` to `guest`, in evaluation order and
+/// without the `-A`/`-I`/`-D` operation. The guest only answers connections
+/// to the host; with `nat` it's masqueraded out to anything but the tenement
+/// `subnet` and the private ranges, except the `allow`ed ones.
+fn link_rules(
+    iface: &str,
+    guest: Ipv4Addr,
+    nat: bool,
+    subnet: &str,
+    allow: &[String],
+) -> Vec<Vec<String>> {
+    let rule = |args: &[&str]| -> Vec<String> { args.iter().map(|s| s.to_string()).collect() };
+    let established = ["-m", "conntrack", "--ctstate", "RELATED,ESTABLISHED"];
+    let mut rules = Vec::new();
+
+    let input_established = [&["INPUT", "-i", iface][..], &established, &["-j", "ACCEPT"]];
+    rules.push(rule(&input_established.concat()));
+    rules.push(rule(&["INPUT", "-i", iface, "-j", "DROP"]));
+
+    if nat {
+        let source = format!("{}/32", guest);
+        rules.push(rule(&[
+            "-t",
+            "nat",
+            "POSTROUTING",
+            "-s",
+            &source,
+            "!",
+            "-o",
+            iface,
+            "-j",
+            "MASQUERADE",
+        ]));
+        let forward_established = [
+            &["FORWARD", "-o", iface][..],
+            &established,
+            &["-j", "ACCEPT"],
+        ];
+        rules.push(rule(&forward_established.concat()));
+        for cidr in allow {
+            rules.push(rule(&["FORWARD", "-i", iface, "-d", cidr, "-j", "ACCEPT"]));
+        }
+        for cidr in std::iter::once(subnet).chain(BLOCKED_RANGES) {
+            rules.push(rule(&["FORWARD", "-i", iface, "-d", cidr, "-j", "DROP"]));
+        }
+        rules.push(rule(&["FORWARD", "-i", iface, "-j", "ACCEPT"]));
+    } else {
+        rules.push(rule(&["FORWARD", "-i", iface, "-j", "DROP"]));
+    }
+    rules
+}
+
+/// `iptables` arguments applying `op` (`-I`, `-D`) to `rule`
+fn iptables_args(op: &str, rule: &[String]) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut rule = rule;
+    if rule.first().is_some_and(|a| a == "-t") {
+        args.extend(rule[..2].iter().cloned());
+        rule = &rule[2..];
+    }
+    args.push(op.to_string());
+    args.extend(rule.iter().cloned());
+    args
+}
+
+/// Insert `rules` at the top of their chains, keeping their order
+async fn install_rules(rules: &[Vec<String>]) -> Result<()> {
+    let nat = rules
+        .iter()
+        .any(|rule| rule.first().is_some_and(|a| a == "-t"));
+    if nat {
+        std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")
+            .context("Failed to enable IPv4 forwarding")?;
+    }
+    // Each insert goes on top, so the last rule goes in first
+    for rule in rules.iter().rev() {
+        // Drop a duplicate from a previous run first
+        let _ = run_args("iptables", iptables_args("-D", rule)).await;
+        run_args("iptables", iptables_args("-I", rule)).await?;
+    }
+    Ok(())
+}
+
+async fn remove_rules(rules: &[Vec<String>]) {
+    for rule in rules {
+        let _ = run_args("iptables", iptables_args("-D", rule)).await;
+    }
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    run_args(program, args.iter().map(|a| a.to_string()).collect()).await
+}
+
+async fn run_args(program: &str, args: Vec<String>) -> Result<()> {
+    let output = tokio::process::Command::new(program)
+        .args(&args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run {}", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(subnet: &str) -> PrivateNetwork {
+        PrivateNetwork {
+            subnet: subnet.to_string(),
+            nat: true,
+            allow: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_subnet() {
+        assert_eq!(
+            PrivateNetwork::default().parse_subnet().unwrap(),
+            (Ipv4Addr::new(172, 30, 0, 0), 16)
+        );
+        assert!(network("10.0.0.0/31").parse_subnet().is_err());
+        assert!(network("10.0.0.1/24").parse_subnet().is_err());
+        assert!(network("10.0.0.0").parse_subnet().is_err());
+        assert!(network("10.0.0.300/24").parse_subnet().is_err());
+
+        let mut allowed = network("10.0.0.0/24");
+        allowed.allow = vec!["192.168.1.0/24".to_string(), "10.1.2.3/32".to_string()];
+        assert!(allowed.validate().is_ok());
+        allowed.allow.push("192.168.1.1/24".to_string());
+        assert!(allowed.validate().is_err());
+        allowed.allow = vec!["192.168.1.0/33".to_string()];
+        assert!(allowed.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_allocate_is_stable_and_unique() {
+        let net = network("10.99.0.0/24");
+        let a = TapDevice::allocate(&net, "worker-alice").unwrap();
+        assert!(a.is_preferred());
+        assert_eq!(u32::from(a.host_ip) % 4, 1);
+        assert_eq!(u32::from(a.guest_ip), u32::from(a.host_ip) + 1);
+        assert!(a.name.len() < 16, "tap names are limited to 15 bytes");
+        assert_eq!(
+            a.boot_arg(),
+            format!("ip={}::{}:255.255.255.252::eth0:off", a.guest_ip, a.host_ip)
+        );
+        let [_, _, c, d] = a.guest_ip.octets();
+        assert!(a.guest_mac().ends_with(&format!("{:02x}:{:02x}", c, d)));
+
+        // Held slots are skipped; the second holder isn't in its usual slot
+        let b = TapDevice::allocate(&net, "worker-alice").unwrap();
+        assert_ne!(a.slot, b.slot);
+        assert!(!b.is_preferred());
+
+        // Freed slots come back
+        b.destroy().await;
+        a.destroy().await;
+        let again = TapDevice::allocate(&net, "worker-alice").unwrap();
+        assert_eq!(again.slot, a.slot);
+        SLOTS.lock().unwrap().remove(&(again.base, again.slot));
+
+        // A /30 holds exactly one VM
+        let tiny = network("10.98.0.0/30");
+        let only = TapDevice::allocate(&tiny, "x").unwrap();
+        assert!(TapDevice::allocate(&tiny, "y").is_err());
+        SLOTS.lock().unwrap().remove(&(only.base, only.slot));
+    }
+
+    #[test]
+    fn test_nat_rule_args() {
+        let mut net = network("10.97.0.0/24");
+        net.allow = vec!["192.168.1.0/24".to_string()];
+        let tap = TapDevice::allocate(&net, "api").unwrap();
+        let rules = tap.rules();
+        let position = |args: &[&str]| {
+            rules
+                .iter()
+                .position(|rule| rule == args)
+                .unwrap_or_else(|| panic!("no rule {:?} in {:?}", args, rules))
+        };
+
+        let masquerade = rules.iter().find(|r| r[0] == "-t").unwrap();
+        let masquerade = iptables_args("-I", masquerade);
+        assert_eq!(&masquerade[..4], ["-t", "nat", "-I", "POSTROUTING"]);
+        assert!(masquerade.contains(&format!("{}/32", tap.guest_ip)));
+        assert_eq!(masquerade.last().unwrap(), "MASQUERADE");
+
+        // Other tenants, private ranges and metadata are dropped before the
+        // catch-all accept; allowed ranges come before the drops
+        let name = tap.name.as_str();
+        let accept = position(&["FORWARD", "-i", name, "-j", "ACCEPT"]);
+        assert_eq!(accept, rules.len() - 1);
+        let allowed = position(&[
+            "FORWARD",
+            "-i",
+            name,
+            "-d",
+            "192.168.1.0/24",
+            "-j",
+            "ACCEPT",
+        ]);
+        for cidr in [
+            "10.97.0.0/24",
+            "10.0.0.0/8",
+            "192.168.0.0/16",
+            "169.254.0.0/16",
+        ] {
+            let drop = position(&["FORWARD", "-i", name, "-d", cidr, "-j", "DROP"]);
+            assert!(allowed < drop && drop < accept);
+        }
+
+        // The host only sees replies
+        let input_drop = position(&["INPUT", "-i", name, "-j", "DROP"]);
+        assert!(rules[..input_drop]
+            .iter()
+            .any(|r| r[0] == "INPUT" && r.contains(&"RELATED,ESTABLISHED".to_string())));
+        assert_eq!(
+            iptables_args("-D", &rules[input_drop]),
+            ["-D", "INPUT", "-i", name, "-j", "DROP"]
+        );
+        SLOTS.lock().unwrap().remove(&(tap.base, tap.slot));
+
+        // Without NAT nothing is forwarded at all
+        let mut isolated = network("10.97.0.0/24");
+        isolated.nat = false;
+        let tap = TapDevice::allocate(&isolated, "api").unwrap();
+        let rules = tap.rules();
+        assert!(rules.iter().all(|r| r[0] != "-t"));
+        assert_eq!(
+            rules.last().unwrap(),
+            &["FORWARD", "-i", tap.name.as_str(), "-j", "DROP"]
+        );
+        SLOTS.lock().unwrap().remove(&(tap.base, tap.slot));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires root and /dev/net/tun
+    async fn test_create_and_destroy() {
+        let net = PrivateNetwork {
+            subnet: "10.96.0.0/24".to_string(),
+            nat: false,
+            allow: Vec::new(),
+        };
+        let tap = TapDevice::allocate(&net, "tap-test").unwrap();
+        tap.create().await.unwrap();
+        let dev = std::path::Path::new("/sys/class/net").join(&tap.name);
+        assert!(dev.exists());
+        tap.destroy().await;
+        assert!(!dev.exists());
+    }
+
+    #[test]
+    fn test_veth_allocate() {
+        let veth = VethLink::allocate(&network("10.95.0.0/24"), "api-alice").unwrap();
+        assert!(
+            veth.name.len() < 16,
+            "interface names are limited to 15 bytes"
+        );
+        assert_eq!(
+            veth.netns_path(),
+            Path::new("/var/run/netns").join(&veth.netns)
+        );
+        assert_eq!(u32::from(veth.guest_ip), u32::from(veth.host_ip) + 1);
+        // Taps and veths share the subnet's slots
+        let tap = TapDevice::allocate(&network("10.95.0.0/24"), "api-alice").unwrap();
+        assert_ne!(tap.host_ip, veth.host_ip);
+        release_slot(tap.base, tap.slot);
+        release_slot(veth.base, veth.slot);
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires root
+    async fn test_veth_create_and_destroy() {
+        let net = PrivateNetwork {
+            subnet: "10.94.0.0/24".to_string(),
+            nat: false,
+            allow: Vec::new(),
+        };
+        let veth = VethLink::allocate(&net, "veth-test").unwrap();
+        veth.create().await.unwrap();
+        let dev = Path::new("/sys/class/net").join(&veth.name);
+        assert!(dev.exists());
+        assert!(veth.netns_path().exists());
+        veth.destroy().await;
+        assert!(!dev.exists());
+        assert!(!veth.netns_path().exists());
+    }
+}
@@ -0,0 +1,400 @@
+//! WASM runtime - runs a `.wasm` module or component under wasmtime with WASI.
+//!
+//! For untrusted tenant functions: `isolation = "wasm"` with `command` naming
+//! the `.wasm` file. The guest only gets what is handed to it: its env, the
+//! service's `mounts` as preopened directories, and a listener on the
+//! instance's `PORT`. Nothing else on the host is visible, and there is no
+//! image or rootfs to start, so instances come up in milliseconds.
+//!
+//! Tenement runs the `wasmtime` CLI as an ordinary child (own process group,
+//! output captured, cgroup limits applied), so a crash in the engine can't
+//! take the hypervisor with it. How the listener is provided depends on the
+//! binary format:
+//!
+//! ```text
+//! # core module (WASI preview 1): a preopened TCP listener, fd 3
+//! wasmtime run -S tcplisten=127.0.0.1:<PORT> --env LISTEN_FDS=1 \
+//!   [--env KEY ...] [--dir host::guest ...] [-W max-memory-size=..] \
+//!   <module.wasm> [args...]
+//!
+//! # component (wasi:http proxy world): wasmtime serves HTTP itself
+//! wasmtime serve --addr 127.0.0.1:<PORT> \
+//!   [--env KEY ...] [--dir host::guest ...] [-W max-memory-size=..] \
+//!   <component.wasm>
+//! ```
+//!
+//! `--env KEY` passes the variable on from wasmtime's own environment, where
+//! the values are set, so they never show up in `ps`.
+//!
+//! Guests can't open Unix sockets, so `SOCKET_PATH` is not passed through;
+//! the proxy reaches wasm instances over `PORT`.
+//!
+//! ## wasmtime discovery (first match wins)
+//!
+//! 1. explicit path passed to [`WasmRuntime::with_wasmtime`];
+//! 2. `TENEMENT_WASMTIME` environment variable (a path, or a name looked up
+//!    on `PATH`);
+//! 3. `wasmtime` on `PATH`.
+
+use super::docker::find_on_path;
+use super::{Runtime, RuntimeHandle, RuntimeType, SpawnConfig};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that selects the wasmtime binary.
+pub const WASMTIME_ENV: &str = "TENEMENT_WASMTIME";
+
+/// Runtime that runs WASI modules and components with the wasmtime CLI.
+pub struct WasmRuntime {
+    /// Explicit wasmtime path. When `None`, discovered from env/PATH at use time.
+    wasmtime: Option<PathBuf>,
+}
+
+impl WasmRuntime {
+    pub fn new() -> Self {
+        Self { wasmtime: None }
+    }
+
+    /// Construct with an explicit wasmtime binary (highest precedence).
+    pub fn with_wasmtime(path: PathBuf) -> Self {
+        Self {
+            wasmtime: Some(path),
+        }
+    }
+
+    /// Resolve the wasmtime binary: explicit path -> env var -> PATH.
+    fn find_wasmtime(&self) -> Option<PathBuf> {
+        if let Some(path) = &self.wasmtime {
+            return path.exists().then(|| path.clone());
+        }
+        if let Ok(wasmtime) = std::env::var(WASMTIME_ENV) {
+            return if wasmtime.contains('/') {
+                let path = PathBuf::from(wasmtime);
+                path.exists().then_some(path)
+            } else {
+                find_on_path(&wasmtime)
+            };
+        }
+        find_on_path("wasmtime")
+    }
+}
+
+impl Default for WasmRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether `header` (the first 8 bytes of a `.wasm` file) is a component
+/// rather than a core module. Both start with `\0asm`; modules are version 1,
+/// components carry a layer of 1 in the upper half of the version field.
+pub fn is_component(header: &[u8]) -> bool {
+    header.len() >= 8 && header[..4] == *b"\0asm" && header[6..8] == [1, 0]
+}
+
+/// Arguments for wasmtime (everything after the binary)
+fn wasmtime_args(
+    module: &Path,
+    component: bool,
+    port: &str,
+    config: &SpawnConfig,
+) -> Result<Vec<String>> {
+    let mut args = if component {
+        if !config.args.is_empty() {
+            bail!("wasi:http components take no arguments; move them to env");
+        }
+        vec![
+            "serve".to_string(),
+            "--addr".to_string(),
+            format!("127.0.0.1:{}", port),
+        ]
+    } else {
+        vec![
+            "run".to_string(),
+            "-S".to_string(),
+            format!("tcplisten=127.0.0.1:{}", port),
+            "--env".to_string(),
+            "LISTEN_FDS=1".to_string(),
+        ]
+    };
+
+    // Sorted so argv is stable between spawns
+    let mut env: Vec<_> = config
+        .env
+        .keys()
+        .filter(|k| k.as_str() != "SOCKET_PATH")
+        .collect();
+    env.sort();
+    for key in env {
+        args.push("--env".to_string());
+        args.push(key.clone());
+    }
+    for mount in &config.mounts {
+        if mount.readonly {
+            bail!(
+                "wasm isolation can't mount {:?} read-only; wasmtime preopens directories read-write",
+                mount.source
+            );
+        }
+        args.push("--dir".to_string());
+        args.push(format!(
+            "{}::{}",
+            mount.source.display(),
+            mount.destination.display()
+        ));
+    }
+    if let Some(mb) = config.memory_limit_mb {
+        args.push("-W".to_string());
+        args.push(format!("max-memory-size={}", u64::from(mb) * 1024 * 1024));
+    }
+
+    args.push(module.display().to_string());
+    args.extend(config.args.iter().cloned());
+    Ok(args)
+}
+
+#[async_trait]
+impl Runtime for WasmRuntime {
+    async fn spawn(&self, config: &SpawnConfig) -> Result<RuntimeHandle> {
+        use std::process::Stdio;
+        use tokio::process::Command;
+
+        let wasmtime = self.find_wasmtime().with_context(|| {
+            format!(
+                "wasmtime not found. Install it (https://wasmtime.dev), or set \
+                 {WASMTIME_ENV}=/path/to/wasmtime."
+            )
+        })?;
+        let port = config
+            .env
+            .get("PORT")
+            .context("wasm isolation needs a PORT to listen on")?;
+
+        // `command` is the .wasm file, relative to workdir like a process's
+        let module = match &config.workdir {
+            Some(workdir) => workdir.join(&config.command),
+            None => PathBuf::from(&config.command),
+        };
+        let mut header = [0u8; 8];
+        {
+            use std::io::Read;
+            std::fs::File::open(&module)
+                .and_then(|mut f| f.read_exact(&mut header))
+                .with_context(|| format!("Failed to read WASM binary {:?}", module))?;
+        }
+        if header[..4] != *b"\0asm" {
+            bail!("{:?} is not a WASM module or component", module);
+        }
+
+        let mut cmd = Command::new(&wasmtime);
+        cmd.args(wasmtime_args(&module, is_component(&header), port, config)?)
+            .envs(&config.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(workdir) = &config.workdir {
+            cmd.current_dir(workdir);
+        }
+
+        // Own process group, like the process runtime
+        #[cfg(unix)]
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = cmd
+            .spawn()
+            .with_context(|| format!("Failed to run {:?} for {:?}", wasmtime, module))?;
+
+        Ok(RuntimeHandle::Wasm {
+            child,
+            socket: config.socket.clone(),
+        })
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Wasm
+    }
+
+    fn is_available(&self) -> bool {
+        self.find_wasmtime().is_some()
+    }
+
+    fn name(&self) -> &'static str {
+        "wasm"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::Mount;
+    use std::collections::HashMap;
+
+    const MODULE_HEADER: [u8; 8] = [0, b'a', b's', b'm', 1, 0, 0, 0];
+    const COMPONENT_HEADER: [u8; 8] = [0, b'a', b's', b'm', 0x0d, 0, 1, 0];
+
+    fn spawn_config() -> SpawnConfig {
+        let env = HashMap::from([
+            ("PORT".to_string(), "31000".to_string()),
+            ("GREETING".to_string(), "hi".to_string()),
+            (
+                "SOCKET_PATH".to_string(),
+                "/run/tenement/fn-alice.sock".to_string(),
+            ),
+        ]);
+        SpawnConfig {
+            command: "hello.wasm".to_string(),
+            args: vec!["--verbose".to_string()],
+            env,
+            socket: PathBuf::from("/run/tenement/fn-alice.sock"),
+            mounts: vec![Mount {
+                source: PathBuf::from("/srv/data/fn/alice"),
+                destination: PathBuf::from("/data"),
+                readonly: false,
+            }],
+            memory_limit_mb: Some(64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_wasm_runtime_type_and_name() {
+        let rt = WasmRuntime::new();
+        assert_eq!(rt.runtime_type(), RuntimeType::Wasm);
+        assert_eq!(rt.name(), "wasm");
+        assert!(!WasmRuntime::with_wasmtime(PathBuf::from("/nonexistent/wasmtime")).is_available());
+    }
+
+    #[test]
+    fn test_is_component() {
+        assert!(!is_component(&MODULE_HEADER));
+        assert!(is_component(&COMPONENT_HEADER));
+        assert!(!is_component(b"\0asm"));
+        assert!(!is_component(b"#!/bin/sh\n"));
+    }
+
+    #[test]
+    fn test_module_args_preopen_listener() {
+        let config = spawn_config();
+        let args = wasmtime_args(Path::new("/fns/hello.wasm"), false, "31000", &config).unwrap();
+        assert_eq!(
+            args,
+            [
+                "run",
+                "-S",
+                "tcplisten=127.0.0.1:31000",
+                "--env",
+                "LISTEN_FDS=1",
+                "--env",
+                "GREETING",
+                "--env",
+                "PORT",
+                "--dir",
+                "/srv/data/fn/alice::/data",
+                "-W",
+                "max-memory-size=67108864",
+                "/fns/hello.wasm",
+                "--verbose",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_component_args_serve() {
+        let mut config = spawn_config();
+        assert!(wasmtime_args(Path::new("/fns/hello.wasm"), true, "31000", &config).is_err());
+
+        config.args.clear();
+        config.mounts.clear();
+        config.memory_limit_mb = None;
+        let args = wasmtime_args(Path::new("/fns/hello.wasm"), true, "31000", &config).unwrap();
+        assert_eq!(
+            args,
+            [
+                "serve",
+                "--addr",
+                "127.0.0.1:31000",
+                "--env",
+                "GREETING",
+                "--env",
+                "PORT",
+                "/fns/hello.wasm",
+            ]
+        );
+
+        config.mounts.push(Mount {
+            source: PathBuf::from("/srv/shared"),
+            destination: PathBuf::from("/shared"),
+            readonly: true,
+        });
+        let err = wasmtime_args(Path::new("/fns/hello.wasm"), true, "31000", &config)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("read-only"), "got: {err}");
+    }
+
+    // A fake wasmtime records its argv, proving spawn resolves the module
+    // against workdir and picks `run` for a core module.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_spawn_runs_module_with_wasmtime() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.wasm"), MODULE_HEADER).unwrap();
+        std::fs::write(dir.path().join("not.wasm"), b"#!/bin/sh\nexit 0\n").unwrap();
+        let argv_dump = dir.path().join("argv.txt");
+        let wasmtime = dir.path().join("fake-wasmtime");
+        std::fs::write(
+            &wasmtime,
+            format!(
+                "#!/bin/sh\n(for a in \"$@\"; do echo \"$a\"; done; echo \"GREETING=$GREETING\") > {}\n",
+                argv_dump.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&wasmtime, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let rt = WasmRuntime::with_wasmtime(wasmtime);
+        let mut config = spawn_config();
+        config.workdir = Some(dir.path().to_path_buf());
+        config.mounts.clear();
+
+        let mut handle = rt.spawn(&config).await.unwrap();
+        assert_eq!(handle.runtime_type(), RuntimeType::Wasm);
+        let mut exited = false;
+        for _ in 0..100 {
+            if handle.exit_code().is_some() {
+                exited = true;
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        assert!(exited);
+        let argv = std::fs::read_to_string(&argv_dump).unwrap();
+        let lines: Vec<&str> = argv.lines().collect();
+        assert_eq!(lines.first(), Some(&"run"), "argv: {argv:?}");
+        // The value comes from the environment, not argv
+        assert_eq!(lines.last(), Some(&"GREETING=hi"), "argv: {argv:?}");
+        assert!(!lines[..lines.len() - 1].contains(&"GREETING=hi"));
+        assert!(
+            lines.contains(&dir.path().join("hello.wasm").to_string_lossy().as_ref()),
+            "argv: {argv:?}"
+        );
+
+        config.command = "not.wasm".to_string();
+        let err = rt.spawn(&config).await.unwrap_err().to_string();
+        assert!(err.contains("not a WASM"), "got: {err}");
+        config.command = "missing.wasm".to_string();
+        let err = rt.spawn(&config).await.unwrap_err().to_string();
+        assert!(err.contains("Failed to read WASM"), "got: {err}");
+    }
+}
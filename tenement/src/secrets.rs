@@ -0,0 +1,127 @@
+//! `{secret:NAME}` interpolation in env values
+//!
+//! Secrets come from `settings.secrets_file`, the output of
+//! `settings.secrets_cmd`, or both (the command's values win). Either source
+//! holds dotenv-style `KEY=VALUE` lines, so API keys can live in an encrypted
+//! file (e.g. `sops -d --output-type dotenv secrets.enc.env`) rather than in
+//! tenement.toml.
+
+use crate::config::{parse_env_file, Settings};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+const PREFIX: &str = "{secret:";
+
+/// Secret values by name
+#[derive(Debug, Default)]
+pub struct Secrets {
+    values: HashMap<String, String>,
+}
+
+impl Secrets {
+    /// Read `secrets_file` and run `secrets_cmd`, whichever are set
+    pub async fn load(settings: &Settings) -> Result<Self> {
+        if settings.secrets_file.is_none() && settings.secrets_cmd.is_none() {
+            anyhow::bail!("{{secret:...}} needs settings.secrets_file or settings.secrets_cmd");
+        }
+        let mut values = HashMap::new();
+        if let Some(path) = &settings.secrets_file {
+            let content = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Failed to read secrets_file {}", path.display()))?;
+            let vars = parse_env_file(&content)
+                .with_context(|| format!("Invalid secrets_file {}", path.display()))?;
+            values.extend(vars);
+        }
+        if let Some(cmd) = &settings.secrets_cmd {
+            let output = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(cmd)
+                .stdin(std::process::Stdio::null())
+                .output()
+                .await
+                .with_context(|| format!("Failed to run secrets_cmd `{}`", cmd))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "secrets_cmd `{}` failed ({}): {}",
+                    cmd,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            let vars = parse_env_file(&String::from_utf8_lossy(&output.stdout))
+                .with_context(|| format!("Invalid output from secrets_cmd `{}`", cmd))?;
+            values.extend(vars);
+        }
+        Ok(Self { values })
+    }
+
+    /// Replace every `{secret:NAME}` in `template`. An unknown name is an
+    /// error rather than an empty value.
+    pub fn resolve(&self, template: &str) -> Result<String> {
+        let mut resolved = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find(PREFIX) {
+            resolved.push_str(&rest[..start]);
+            let after = &rest[start + PREFIX.len()..];
+            let end = after
+                .find('}')
+                .with_context(|| format!("Unclosed {}...}} in env value", PREFIX))?;
+            let name = &after[..end];
+            let value = self
+                .values
+                .get(name)
+                .with_context(|| format!("Unknown secret '{}'", name))?;
+            resolved.push_str(value);
+            rest = &after[end + 1..];
+        }
+        resolved.push_str(rest);
+        Ok(resolved)
+    }
+}
+
+/// Whether `value` refers to any secret
+pub fn has_secrets(value: &str) -> bool {
+    value.contains(PREFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_load_and_resolve() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("secrets.env");
+        std::fs::write(&file, "API_KEY=from-file\nDB_PASS=hunter2\n").unwrap();
+        let settings = Settings {
+            secrets_file: Some(file),
+            secrets_cmd: Some("echo API_KEY=from-cmd".to_string()),
+            ..Default::default()
+        };
+        let secrets = Secrets::load(&settings).await.unwrap();
+
+        assert_eq!(
+            secrets
+                .resolve("postgres://app:{secret:DB_PASS}@db/app?key={secret:API_KEY}")
+                .unwrap(),
+            "postgres://app:hunter2@db/app?key=from-cmd"
+        );
+        assert_eq!(secrets.resolve("plain").unwrap(), "plain");
+        let err = secrets.resolve("{secret:MISSING}").unwrap_err();
+        assert!(err.to_string().contains("MISSING"), "got: {}", err);
+        assert!(secrets.resolve("{secret:API_KEY").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_errors() {
+        assert!(Secrets::load(&Settings::default()).await.is_err());
+
+        let settings = Settings {
+            secrets_cmd: Some("echo nope >&2; exit 3".to_string()),
+            ..Default::default()
+        };
+        let err = Secrets::load(&settings).await.unwrap_err();
+        assert!(err.to_string().contains("nope"), "got: {}", err);
+    }
+}
@@ -2,16 +2,20 @@
 //!
 //! Persists logs with FTS5 full-text search and handles config storage.
 
-use crate::logs::{LogEntry, LogLevel, LogQuery};
+use crate::capacity::UsageSample;
+use crate::logs::{LogBuffer, LogEntry, LogLevel, LogQuery};
+use crate::metering::{MeterReading, UsageRollup};
 use anyhow::{Context, Result};
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::{Pool, Row, Sqlite};
+use std::collections::HashMap;
 use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc;
-use tracing::{error, info};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, info, warn};
 
 /// SQLite connection pool
 pub type DbPool = Pool<Sqlite>;
@@ -34,6 +38,30 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         .await
         .context("Failed to connect to SQLite database")?;
 
+    create_schema(&pool).await?;
+
+    info!("Database initialized at {:?}", path);
+    Ok(pool)
+}
+
+/// Initialize an in-memory database with the same tables.
+/// Holds a single connection, since each SQLite `:memory:` connection is its
+/// own database; meant for tests.
+pub async fn init_memory_db() -> Result<DbPool> {
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(SqliteConnectOptions::from_str("sqlite::memory:")?)
+        .await
+        .context("Failed to open in-memory SQLite database")?;
+    create_schema(&pool).await?;
+    Ok(pool)
+}
+
+/// Create tables, indexes and triggers (idempotent)
+async fn create_schema(pool: &DbPool) -> Result<()> {
     // Create tables
     sqlx::query(
         r#"
@@ -43,7 +71,8 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
             level TEXT NOT NULL,
             process TEXT NOT NULL,
             instance_id TEXT NOT NULL,
-            message TEXT NOT NULL
+            message TEXT NOT NULL,
+            original_bytes INTEGER
         );
 
         CREATE INDEX IF NOT EXISTS idx_logs_process ON logs(process);
@@ -51,10 +80,15 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         CREATE INDEX IF NOT EXISTS idx_logs_timestamp ON logs(timestamp DESC);
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create logs table")?;
 
+    // Databases created before line truncation lack original_bytes
+    let _ = sqlx::query("ALTER TABLE logs ADD COLUMN original_bytes INTEGER")
+        .execute(pool)
+        .await;
+
     // Create FTS5 virtual table for full-text search
     sqlx::query(
         r#"
@@ -65,7 +99,7 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create FTS5 table")?;
 
@@ -77,7 +111,7 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         END;
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create FTS insert trigger")?;
 
@@ -88,7 +122,7 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         END;
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create FTS delete trigger")?;
 
@@ -101,7 +135,7 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create config table")?;
 
@@ -114,14 +148,49 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
             id TEXT NOT NULL,
             pid INTEGER NOT NULL,
             port INTEGER,
-            started_at TEXT NOT NULL
+            started_at TEXT NOT NULL,
+            socket TEXT NOT NULL DEFAULT '',
+            runtime TEXT NOT NULL DEFAULT '',
+            listeners TEXT NOT NULL DEFAULT '',
+            config TEXT NOT NULL DEFAULT ''
         );
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create instance_state table")?;
 
+    // Databases created before re-adoption support lack the socket/runtime
+    // columns (and later ones the listeners and config columns). SQLite has
+    // no ADD COLUMN IF NOT EXISTS, so ignore the duplicate-column error.
+    for column in ["socket", "runtime", "listeners", "config"] {
+        let _ = sqlx::query(&format!(
+            "ALTER TABLE instance_state ADD COLUMN {} TEXT NOT NULL DEFAULT ''",
+            column
+        ))
+        .execute(pool)
+        .await;
+    }
+
+    // Create routing tables: weights set by hand or by deploys, and the
+    // current/previous version of each deployed service
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS routing_weights (
+            instance_id TEXT PRIMARY KEY,
+            weight INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS service_versions (
+            service TEXT PRIMARY KEY,
+            current TEXT,
+            previous TEXT
+        );
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create routing tables")?;
+
     // Create tenant tokens table (per-tenant API access)
     sqlx::query(
         r#"
@@ -137,10 +206,40 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         CREATE INDEX IF NOT EXISTS idx_tenant_tokens_prefix ON tenant_tokens(token_prefix);
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create tenant_tokens table")?;
 
+    // Create tenant key table (salts for per-tenant data dir encryption keys)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tenant_keys (
+            tenant_id TEXT PRIMARY KEY,
+            salt TEXT NOT NULL,
+            created_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create tenant_keys table")?;
+
+    // Create tenant secrets table (e.g. provisioned database connection strings)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS tenant_secrets (
+            tenant_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            value TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            PRIMARY KEY (tenant_id, name)
+        )
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create tenant_secrets table")?;
+
     // Create deployment audit log table
     sqlx::query(
         r#"
@@ -156,12 +255,83 @@ pub async fn init_db(path: &Path) -> Result<DbPool> {
         CREATE INDEX IF NOT EXISTS idx_deploy_log_timestamp ON deploy_log(timestamp DESC);
         "#,
     )
-    .execute(&pool)
+    .execute(pool)
     .await
     .context("Failed to create deploy_log table")?;
 
-    info!("Database initialized at {:?}", path);
-    Ok(pool)
+    // Usage history for capacity forecasts (see `crate::capacity`)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_samples (
+            timestamp INTEGER NOT NULL,
+            process TEXT NOT NULL,
+            instance_id TEXT NOT NULL,
+            memory_bytes INTEGER NOT NULL,
+            disk_bytes INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_usage_samples_timestamp ON usage_samples(timestamp);
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create usage_samples table")?;
+
+    // Hourly request metering per instance (see `crate::metering`)
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_rollups (
+            period_start INTEGER NOT NULL,
+            process TEXT NOT NULL,
+            instance_id TEXT NOT NULL,
+            requests INTEGER NOT NULL,
+            bytes_in INTEGER NOT NULL,
+            bytes_out INTEGER NOT NULL,
+            compute_seconds REAL NOT NULL,
+            PRIMARY KEY (period_start, process, instance_id)
+        );
+        CREATE INDEX IF NOT EXISTS idx_usage_rollups_instance ON usage_rollups(process, instance_id);
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create usage_rollups table")?;
+
+    // Proxied requests, separate from instance stdout/stderr
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS access_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            host TEXT NOT NULL,
+            method TEXT NOT NULL,
+            path TEXT NOT NULL,
+            status INTEGER NOT NULL,
+            latency_ms REAL NOT NULL,
+            process TEXT NOT NULL,
+            instance_id TEXT,
+            bytes INTEGER NOT NULL,
+            experiment TEXT,
+            experiment_group TEXT
+        );
+        CREATE INDEX IF NOT EXISTS idx_access_log_timestamp ON access_log(timestamp DESC);
+        CREATE INDEX IF NOT EXISTS idx_access_log_process ON access_log(process, instance_id);
+        "#,
+    )
+    .execute(pool)
+    .await
+    .context("Failed to create access_log table")?;
+
+    // Databases created before experiments were logged lack their columns
+    for column in ["experiment", "experiment_group"] {
+        let _ = sqlx::query(&format!(
+            "ALTER TABLE access_log ADD COLUMN {} TEXT",
+            column
+        ))
+        .execute(pool)
+        .await;
+    }
+
+    Ok(())
 }
 
 /// Tenant token with scoped access
@@ -260,6 +430,148 @@ impl TenantTokenStore {
     }
 }
 
+/// Store for per-instance usage samples
+pub struct UsageStore {
+    pool: DbPool,
+}
+
+impl UsageStore {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Insert one sampling pass
+    pub async fn record(&self, samples: &[UsageSample]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for sample in samples {
+            sqlx::query(
+                "INSERT INTO usage_samples (timestamp, process, instance_id, memory_bytes, disk_bytes) VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(sample.timestamp)
+            .bind(&sample.process)
+            .bind(&sample.instance_id)
+            .bind(sample.memory_bytes as i64)
+            .bind(sample.disk_bytes as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Samples taken at or after `timestamp` (unix seconds), oldest first
+    pub async fn since(&self, timestamp: i64) -> Result<Vec<UsageSample>> {
+        let rows = sqlx::query(
+            "SELECT timestamp, process, instance_id, memory_bytes, disk_bytes FROM usage_samples WHERE timestamp >= ? ORDER BY timestamp",
+        )
+        .bind(timestamp)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UsageSample {
+                timestamp: row.get("timestamp"),
+                process: row.get("process"),
+                instance_id: row.get("instance_id"),
+                memory_bytes: row.get::<i64, _>("memory_bytes") as u64,
+                disk_bytes: row.get::<i64, _>("disk_bytes") as u64,
+            })
+            .collect())
+    }
+
+    /// Delete samples older than `timestamp`. Returns the number deleted.
+    pub async fn prune(&self, timestamp: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM usage_samples WHERE timestamp < ?")
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Add metered usage to the rollups for its period, creating them if
+    /// needed
+    pub async fn add_rollups(&self, rollups: &[UsageRollup]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for rollup in rollups {
+            sqlx::query(
+                r#"
+                INSERT INTO usage_rollups (period_start, process, instance_id, requests, bytes_in, bytes_out, compute_seconds)
+                VALUES (?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (period_start, process, instance_id) DO UPDATE SET
+                    requests = requests + excluded.requests,
+                    bytes_in = bytes_in + excluded.bytes_in,
+                    bytes_out = bytes_out + excluded.bytes_out,
+                    compute_seconds = compute_seconds + excluded.compute_seconds
+                "#,
+            )
+            .bind(rollup.period_start)
+            .bind(&rollup.process)
+            .bind(&rollup.instance_id)
+            .bind(rollup.usage.requests as i64)
+            .bind(rollup.usage.bytes_in as i64)
+            .bind(rollup.usage.bytes_out as i64)
+            .bind(rollup.usage.compute_seconds)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Rollups for periods starting at or after `timestamp`, oldest first,
+    /// optionally only those of one service or one instance of it
+    pub async fn rollups(
+        &self,
+        timestamp: i64,
+        process: Option<&str>,
+        instance_id: Option<&str>,
+    ) -> Result<Vec<UsageRollup>> {
+        let rows = sqlx::query(
+            r#"
+            SELECT period_start, process, instance_id, requests, bytes_in, bytes_out, compute_seconds
+            FROM usage_rollups
+            WHERE period_start >= ?
+              AND (? IS NULL OR process = ?)
+              AND (? IS NULL OR instance_id = ?)
+            ORDER BY period_start, process, instance_id
+            "#,
+        )
+        .bind(timestamp)
+        .bind(process)
+        .bind(process)
+        .bind(instance_id)
+        .bind(instance_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| UsageRollup {
+                period_start: row.get("period_start"),
+                process: row.get("process"),
+                instance_id: row.get("instance_id"),
+                usage: MeterReading {
+                    requests: row.get::<i64, _>("requests") as u64,
+                    bytes_in: row.get::<i64, _>("bytes_in") as u64,
+                    bytes_out: row.get::<i64, _>("bytes_out") as u64,
+                    compute_seconds: row.get("compute_seconds"),
+                },
+            })
+            .collect())
+    }
+
+    /// Delete rollups for periods starting before `timestamp`. Returns the
+    /// number deleted.
+    pub async fn prune_rollups(&self, timestamp: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM usage_rollups WHERE period_start < ?")
+            .bind(timestamp)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
 /// Deployment audit log entry
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct DeployLogEntry {
@@ -330,10 +642,174 @@ impl DeployLogStore {
     }
 }
 
+/// One proxied request
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AccessLogEntry {
+    /// When the request arrived (millis since epoch)
+    pub timestamp: u64,
+    pub host: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// Time until the response headers were ready
+    pub latency_ms: f64,
+    pub process: String,
+    /// Instance that served the request, if one was reached
+    pub instance_id: Option<String>,
+    /// Response body bytes sent to the client
+    pub bytes: u64,
+    /// A/B experiment the client was routed by, if any
+    #[serde(default)]
+    pub experiment: Option<String>,
+    /// The client's group in `experiment`
+    #[serde(default)]
+    pub experiment_group: Option<String>,
+}
+
+/// Filters for [`AccessLogStore::query`]
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+pub struct AccessLogQuery {
+    pub process: Option<String>,
+    pub instance_id: Option<String>,
+    pub status: Option<u16>,
+    /// Only entries at or after this time (millis since epoch)
+    pub since: Option<u64>,
+    /// Most recent entries returned (default: 100)
+    pub limit: Option<usize>,
+}
+
+/// Store for the proxy access log, written in batches off the request path
+pub struct AccessLogStore {
+    pool: DbPool,
+    tx: mpsc::Sender<AccessLogEntry>,
+}
+
+impl AccessLogStore {
+    pub fn new(pool: DbPool) -> Arc<Self> {
+        let (tx, rx) = mpsc::channel::<AccessLogEntry>(10000);
+        tokio::spawn(access_log_flusher(pool.clone(), rx));
+        Arc::new(Self { pool, tx })
+    }
+
+    /// Queue an entry. Dropped, rather than slowing the proxy, when the
+    /// writer is behind.
+    pub fn push(&self, entry: AccessLogEntry) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.tx.try_send(entry) {
+            warn!("Access log writer fell behind; dropping entry");
+        }
+    }
+
+    /// Most recent entries matching `query`, newest first
+    pub async fn query(&self, query: &AccessLogQuery) -> Result<Vec<AccessLogEntry>> {
+        let mut sql = String::from(
+            "SELECT timestamp, host, method, path, status, latency_ms, process, instance_id, bytes, experiment, experiment_group FROM access_log WHERE 1=1",
+        );
+        if query.process.is_some() {
+            sql.push_str(" AND process = ?");
+        }
+        if query.instance_id.is_some() {
+            sql.push_str(" AND instance_id = ?");
+        }
+        if query.status.is_some() {
+            sql.push_str(" AND status = ?");
+        }
+        if query.since.is_some() {
+            sql.push_str(" AND timestamp >= ?");
+        }
+        sql.push_str(" ORDER BY timestamp DESC, id DESC LIMIT ?");
+
+        let mut q = sqlx::query(&sql);
+        if let Some(process) = &query.process {
+            q = q.bind(process);
+        }
+        if let Some(id) = &query.instance_id {
+            q = q.bind(id);
+        }
+        if let Some(status) = query.status {
+            q = q.bind(i64::from(status));
+        }
+        if let Some(since) = query.since {
+            q = q.bind(since as i64);
+        }
+        q = q.bind(query.limit.unwrap_or(100) as i64);
+
+        let rows = q.fetch_all(&self.pool).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| AccessLogEntry {
+                timestamp: row.get::<i64, _>("timestamp") as u64,
+                host: row.get("host"),
+                method: row.get("method"),
+                path: row.get("path"),
+                status: row.get::<i64, _>("status") as u16,
+                latency_ms: row.get("latency_ms"),
+                process: row.get("process"),
+                instance_id: row.get("instance_id"),
+                bytes: row.get::<i64, _>("bytes") as u64,
+                experiment: row.get("experiment"),
+                experiment_group: row.get("experiment_group"),
+            })
+            .collect())
+    }
+
+    /// Delete entries older than `max_age`. Returns the number deleted.
+    pub async fn rotate(&self, max_age: Duration) -> Result<u64> {
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(max_age)
+            .unwrap_or(std::time::UNIX_EPOCH)
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        let result = sqlx::query("DELETE FROM access_log WHERE timestamp < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Write queued access log entries, up to 1000 at a time
+async fn access_log_flusher(pool: DbPool, mut rx: mpsc::Receiver<AccessLogEntry>) {
+    let mut batch = Vec::with_capacity(1000);
+    while rx.recv_many(&mut batch, 1000).await > 0 {
+        if let Err(e) = flush_access_log(&pool, &batch).await {
+            error!("Failed to flush access log batch: {}", e);
+        }
+        batch.clear();
+    }
+}
+
+async fn flush_access_log(pool: &DbPool, entries: &[AccessLogEntry]) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    for entry in entries {
+        sqlx::query(
+            "INSERT INTO access_log (timestamp, host, method, path, status, latency_ms, process, instance_id, bytes, experiment, experiment_group) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(entry.timestamp as i64)
+        .bind(&entry.host)
+        .bind(&entry.method)
+        .bind(&entry.path)
+        .bind(i64::from(entry.status))
+        .bind(entry.latency_ms)
+        .bind(&entry.process)
+        .bind(&entry.instance_id)
+        .bind(entry.bytes as i64)
+        .bind(&entry.experiment)
+        .bind(&entry.experiment_group)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
 /// Log store with batch flushing
 pub struct LogStore {
     pool: DbPool,
     tx: mpsc::Sender<LogEntry>,
+    /// While set, pushed entries are dropped instead of written
+    /// (see [`crate::disk_guard`])
+    paused: AtomicBool,
 }
 
 impl LogStore {
@@ -343,6 +819,7 @@ impl LogStore {
         let store = Arc::new(Self {
             pool: pool.clone(),
             tx,
+            paused: AtomicBool::new(false),
         });
 
         // Spawn background batch flusher
@@ -351,13 +828,43 @@ impl LogStore {
         store
     }
 
-    /// Push a log entry (batched for efficiency)
+    /// Push a log entry (batched for efficiency). Dropped while paused.
     pub async fn push(&self, entry: LogEntry) {
+        if self.is_paused() {
+            return;
+        }
         if let Err(e) = self.tx.send(entry).await {
             error!("Failed to queue log entry: {}", e);
         }
     }
 
+    /// Stop or resume writing pushed entries. Returns the previous state.
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::SeqCst)
+    }
+
+    /// Whether pushed entries are currently being dropped
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Persist every entry pushed to `buffer` from now on
+    pub fn record(self: &Arc<Self>, buffer: &LogBuffer) {
+        let store = self.clone();
+        let mut rx = buffer.subscribe();
+        tokio::spawn(async move {
+            loop {
+                match rx.recv().await {
+                    Ok(entry) => store.push(entry).await,
+                    Err(broadcast::error::RecvError::Lagged(n)) => {
+                        warn!("Log persistence fell behind; {} line(s) not stored", n);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
     /// Query logs with filters
     pub async fn query(&self, query: &LogQuery) -> Result<Vec<LogEntry>> {
         let limit = query.limit.unwrap_or(100);
@@ -369,7 +876,7 @@ impl LogStore {
 
         // Build dynamic query
         let mut sql = String::from(
-            "SELECT id, timestamp, level, process, instance_id, message FROM logs WHERE 1=1",
+            "SELECT id, timestamp, level, process, instance_id, message, original_bytes FROM logs WHERE 1=1",
         );
         let mut params: Vec<String> = Vec::new();
 
@@ -435,6 +942,9 @@ impl LogStore {
                     process: row.get("process"),
                     instance_id: row.get("instance_id"),
                     message: row.get("message"),
+                    original_bytes: row
+                        .get::<Option<i64>, _>("original_bytes")
+                        .map(|n| n as usize),
                 }
             })
             .collect())
@@ -449,7 +959,7 @@ impl LogStore {
     ) -> Result<Vec<LogEntry>> {
         let mut sql = String::from(
             r#"
-            SELECT l.id, l.timestamp, l.level, l.process, l.instance_id, l.message
+            SELECT l.id, l.timestamp, l.level, l.process, l.instance_id, l.message, l.original_bytes
             FROM logs l
             JOIN logs_fts f ON l.id = f.rowid
             WHERE logs_fts MATCH ?
@@ -528,6 +1038,9 @@ impl LogStore {
                     process: row.get("process"),
                     instance_id: row.get("instance_id"),
                     message: row.get("message"),
+                    original_bytes: row
+                        .get::<Option<i64>, _>("original_bytes")
+                        .map(|n| n as usize),
                 }
             })
             .collect())
@@ -543,6 +1056,20 @@ impl LogStore {
         Ok(result.rows_affected())
     }
 
+    /// Delete each listed service's entries older than its own retention
+    pub async fn rotate_services(&self, retention: &HashMap<String, Duration>) -> Result<u64> {
+        let mut deleted = 0;
+        for (process, max_age) in retention {
+            let result = sqlx::query("DELETE FROM logs WHERE process = ? AND timestamp < ?")
+                .bind(process)
+                .bind(chrono_cutoff(*max_age))
+                .execute(&self.pool)
+                .await?;
+            deleted += result.rows_affected();
+        }
+        Ok(deleted)
+    }
+
     /// Get total log count
     pub async fn count(&self) -> Result<i64> {
         let row = sqlx::query("SELECT COUNT(*) as count FROM logs")
@@ -611,13 +1138,14 @@ async fn flush_batch(pool: &DbPool, entries: &[LogEntry]) -> Result<()> {
         // Convert millis timestamp to ISO8601 string
         let timestamp = millis_to_iso8601(entry.timestamp);
         sqlx::query(
-            "INSERT INTO logs (timestamp, level, process, instance_id, message) VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO logs (timestamp, level, process, instance_id, message, original_bytes) VALUES (?, ?, ?, ?, ?, ?)",
         )
         .bind(&timestamp)
         .bind(entry.level.to_string())
         .bind(&entry.process)
         .bind(&entry.instance_id)
         .bind(&entry.message)
+        .bind(entry.original_bytes.map(|n| n as i64))
         .execute(&mut *tx)
         .await?;
     }
@@ -694,6 +1222,98 @@ impl ConfigStore {
     }
 }
 
+/// Store for per-tenant encryption key material.
+///
+/// Only a random salt is stored per tenant; the key itself is derived from
+/// the master key file (see `crate::encryption`), so a copy of the database
+/// alone doesn't unlock any tenant's data.
+pub struct TenantKeyStore {
+    pool: DbPool,
+}
+
+impl TenantKeyStore {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Get the tenant's salt, creating one on first use
+    pub async fn salt(&self, tenant_id: &str) -> Result<String> {
+        let salt = crate::auth::generate_token();
+        let now = chrono::Utc::now().to_rfc3339();
+        sqlx::query(
+            "INSERT OR IGNORE INTO tenant_keys (tenant_id, salt, created_at) VALUES (?, ?, ?)",
+        )
+        .bind(tenant_id)
+        .bind(&salt)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        let row = sqlx::query("SELECT salt FROM tenant_keys WHERE tenant_id = ?")
+            .bind(tenant_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.get("salt"))
+    }
+}
+
+/// Store for per-tenant secrets such as provisioned database connection
+/// strings. Values are stored as-is; protect the database file accordingly.
+pub struct TenantSecretStore {
+    pool: DbPool,
+}
+
+impl TenantSecretStore {
+    pub fn new(pool: DbPool) -> Self {
+        Self { pool }
+    }
+
+    /// Get a tenant's secret by name
+    pub async fn get(&self, tenant_id: &str, name: &str) -> Result<Option<String>> {
+        let row = sqlx::query("SELECT value FROM tenant_secrets WHERE tenant_id = ? AND name = ?")
+            .bind(tenant_id)
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(row.map(|r| r.get("value")))
+    }
+
+    /// Set a tenant's secret, replacing any previous value
+    pub async fn set(&self, tenant_id: &str, name: &str, value: &str) -> Result<()> {
+        let now = chrono::Utc::now().to_rfc3339();
+        sqlx::query(
+            "INSERT OR REPLACE INTO tenant_secrets (tenant_id, name, value, created_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(tenant_id)
+        .bind(name)
+        .bind(value)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Tenants holding a secret with this name
+    pub async fn owners(&self, name: &str) -> Result<Vec<String>> {
+        let rows =
+            sqlx::query("SELECT tenant_id FROM tenant_secrets WHERE name = ? ORDER BY tenant_id")
+                .bind(name)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(rows.iter().map(|r| r.get("tenant_id")).collect())
+    }
+
+    /// Delete a tenant's secret. Returns true if it existed.
+    pub async fn delete(&self, tenant_id: &str, name: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM tenant_secrets WHERE tenant_id = ? AND name = ?")
+            .bind(tenant_id)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
 /// Persisted instance state for crash recovery
 #[derive(Debug, Clone)]
 pub struct InstanceState {
@@ -703,6 +1323,17 @@ pub struct InstanceState {
     pub pid: u32,
     pub port: Option<u16>,
     pub started_at: String,
+    /// Unix socket path the instance was spawned with
+    pub socket: String,
+    /// Runtime the instance was spawned with (e.g. "process", "namespace").
+    /// Empty for rows written before this was recorded.
+    pub runtime: String,
+    /// Listen addresses (JSON) the instance was spawned with.
+    /// Empty for rows written before this was recorded.
+    pub listeners: String,
+    /// Service definition (JSON) the instance was spawned from.
+    /// Empty for rows written before this was recorded.
+    pub config: String,
 }
 
 /// Store for instance state persistence (crash recovery)
@@ -718,7 +1349,7 @@ impl StateStore {
     /// Record a running instance
     pub async fn save(&self, state: &InstanceState) -> Result<()> {
         sqlx::query(
-            "INSERT OR REPLACE INTO instance_state (instance_id, process_name, id, pid, port, started_at) VALUES (?, ?, ?, ?, ?, ?)",
+            "INSERT OR REPLACE INTO instance_state (instance_id, process_name, id, pid, port, started_at, socket, runtime, listeners, config) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(&state.instance_id)
         .bind(&state.process_name)
@@ -726,6 +1357,10 @@ impl StateStore {
         .bind(state.pid as i64)
         .bind(state.port.map(|p| p as i64))
         .bind(&state.started_at)
+        .bind(&state.socket)
+        .bind(&state.runtime)
+        .bind(&state.listeners)
+        .bind(&state.config)
         .execute(&self.pool)
         .await?;
         Ok(())
@@ -743,7 +1378,7 @@ impl StateStore {
     /// Get all persisted instance states (called on startup for recovery)
     pub async fn list(&self) -> Result<Vec<InstanceState>> {
         let rows = sqlx::query(
-            "SELECT instance_id, process_name, id, pid, port, started_at FROM instance_state",
+            "SELECT instance_id, process_name, id, pid, port, started_at, socket, runtime, listeners, config FROM instance_state",
         )
         .fetch_all(&self.pool)
         .await?;
@@ -757,6 +1392,10 @@ impl StateStore {
                 pid: row.get::<i64, _>("pid") as u32,
                 port: row.get::<Option<i64>, _>("port").map(|p| p as u16),
                 started_at: row.get("started_at"),
+                socket: row.get("socket"),
+                runtime: row.get("runtime"),
+                listeners: row.get("listeners"),
+                config: row.get("config"),
             })
             .collect())
     }
@@ -768,6 +1407,70 @@ impl StateStore {
             .await?;
         Ok(())
     }
+
+    /// Record an instance's traffic weight
+    pub async fn save_weight(&self, instance_id: &str, weight: u8) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO routing_weights (instance_id, weight) VALUES (?, ?)")
+            .bind(instance_id)
+            .bind(weight as i64)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Forget an instance's traffic weight (back to the default)
+    pub async fn remove_weight(&self, instance_id: &str) -> Result<()> {
+        sqlx::query("DELETE FROM routing_weights WHERE instance_id = ?")
+            .bind(instance_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// All recorded traffic weights, by instance ID ("process:id")
+    pub async fn weights(&self) -> Result<Vec<(String, u8)>> {
+        let rows = sqlx::query("SELECT instance_id, weight FROM routing_weights")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                (
+                    row.get("instance_id"),
+                    row.get::<i64, _>("weight").clamp(0, 100) as u8,
+                )
+            })
+            .collect())
+    }
+
+    /// Record the current and previous deployed version of a service
+    pub async fn save_versions(
+        &self,
+        service: &str,
+        current: Option<&str>,
+        previous: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO service_versions (service, current, previous) VALUES (?, ?, ?)",
+        )
+        .bind(service)
+        .bind(current)
+        .bind(previous)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// All recorded service versions as (service, current, previous)
+    pub async fn versions(&self) -> Result<Vec<(String, Option<String>, Option<String>)>> {
+        let rows = sqlx::query("SELECT service, current, previous FROM service_versions")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("service"), row.get("current"), row.get("previous")))
+            .collect())
+    }
 }
 
 // Helper to parse LogLevel from string
@@ -900,6 +1603,45 @@ mod tests {
         let results = store.query(&query).await.unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].message, "hello world");
+        assert_eq!(results[0].original_bytes, None);
+    }
+
+    #[tokio::test]
+    async fn test_log_store_records_buffer() {
+        let (pool, _dir) = create_test_db().await;
+        let store = LogStore::new(pool);
+        let buffer = LogBuffer::new();
+        store.record(&buffer);
+
+        buffer.push_stdout("api", "prod", "one".to_string()).await;
+        buffer.push_stderr("api", "prod", "two".to_string()).await;
+        wait_for_count(&store, 2).await;
+
+        // Paused: the buffer keeps the line, the store doesn't
+        store.set_paused(true);
+        buffer.push_stdout("api", "prod", "three".to_string()).await;
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(store.count().await.unwrap(), 2);
+        assert_eq!(buffer.len().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_log_store_keeps_original_bytes() {
+        let (pool, _dir) = create_test_db().await;
+        let store = LogStore::new(pool);
+
+        let line = crate::logs::LogLine {
+            text: "head".to_string(),
+            original_bytes: Some(1_048_576),
+        };
+        store
+            .push(LogEntry::from_line("api", "prod", LogLevel::Stderr, line))
+            .await;
+        wait_for_count(&store, 1).await;
+
+        let results = store.query(&LogQuery::default()).await.unwrap();
+        assert_eq!(results[0].original_bytes, Some(1_048_576));
+        assert!(results[0].message.ends_with("[truncated: 1048576 bytes]"));
     }
 
     #[tokio::test]
@@ -1312,6 +2054,97 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[tokio::test]
+    async fn test_log_store_rotate_services() {
+        let (pool, _dir) = create_test_db().await;
+        let store = LogStore::new(pool);
+
+        for process in ["api", "chatty"] {
+            store
+                .push(LogEntry::new(
+                    process,
+                    "prod",
+                    LogLevel::Stdout,
+                    "msg".to_string(),
+                ))
+                .await;
+        }
+        wait_for_count(&store, 2).await;
+
+        // Only the listed service's lines are subject to its retention
+        let retention = HashMap::from([("chatty".to_string(), Duration::from_secs(0))]);
+        assert_eq!(store.rotate_services(&retention).await.unwrap(), 1);
+        let remaining = store.query(&LogQuery::default()).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].process, "api");
+    }
+
+    #[tokio::test]
+    async fn test_access_log_store() {
+        let (pool, _dir) = create_test_db().await;
+        let store = AccessLogStore::new(pool);
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64;
+        let entry = |offset: u64, instance: Option<&str>, status: u16| AccessLogEntry {
+            timestamp: now - offset,
+            host: "prod.api.example.com".to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status,
+            latency_ms: 1.5,
+            process: "api".to_string(),
+            instance_id: instance.map(String::from),
+            bytes: 42,
+            experiment: None,
+            experiment_group: None,
+        };
+        store.push(entry(3_600_000, Some("prod"), 200));
+        store.push(AccessLogEntry {
+            experiment: Some("checkout".to_string()),
+            experiment_group: Some("treatment".to_string()),
+            ..entry(2, Some("prod"), 500)
+        });
+        store.push(entry(1, None, 503));
+
+        let mut all = Vec::new();
+        for _ in 0..50 {
+            all = store.query(&AccessLogQuery::default()).await.unwrap();
+            if all.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0], entry(1, None, 503));
+        assert_eq!(all[1].experiment.as_deref(), Some("checkout"));
+        assert_eq!(all[1].experiment_group.as_deref(), Some("treatment"));
+
+        let query = AccessLogQuery {
+            instance_id: Some("prod".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap().len(), 2);
+        let query = AccessLogQuery {
+            status: Some(500),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap()[0].status, 500);
+        let query = AccessLogQuery {
+            since: Some(now - 60_000),
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(store.query(&query).await.unwrap().len(), 1);
+
+        assert_eq!(store.rotate(Duration::from_secs(60)).await.unwrap(), 1);
+        assert_eq!(
+            store.query(&AccessLogQuery::default()).await.unwrap().len(),
+            2
+        );
+    }
+
     #[tokio::test]
     async fn test_log_store_count() {
         let (pool, _dir) = create_test_db().await;
@@ -1349,6 +2182,121 @@ mod tests {
         assert_eq!(store.count().await.unwrap(), 3);
     }
 
+    // ===================
+    // TENANT KEY STORE TESTS
+    // ===================
+
+    #[tokio::test]
+    async fn test_usage_store_record_since_prune() {
+        let pool = init_memory_db().await.unwrap();
+        let store = UsageStore::new(pool);
+        let sample = |timestamp: i64, id: &str| UsageSample {
+            timestamp,
+            process: "api".to_string(),
+            instance_id: id.to_string(),
+            memory_bytes: 64 << 20,
+            disk_bytes: 5 << 30,
+        };
+        store
+            .record(&[sample(100, "alice"), sample(100, "bob")])
+            .await
+            .unwrap();
+        store.record(&[sample(400, "alice")]).await.unwrap();
+
+        assert_eq!(store.since(0).await.unwrap().len(), 3);
+        let recent = store.since(200).await.unwrap();
+        assert_eq!(recent, vec![sample(400, "alice")]);
+
+        assert_eq!(store.prune(200).await.unwrap(), 2);
+        assert_eq!(store.since(0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_usage_rollups_accumulate() {
+        let pool = init_memory_db().await.unwrap();
+        let store = UsageStore::new(pool);
+        let rollup = |period_start: i64, id: &str, requests: u64| UsageRollup {
+            period_start,
+            process: "api".to_string(),
+            instance_id: id.to_string(),
+            usage: MeterReading {
+                requests,
+                bytes_in: 10,
+                bytes_out: 100,
+                compute_seconds: 60.0,
+            },
+        };
+        store
+            .add_rollups(&[rollup(3600, "alice", 2), rollup(3600, "bob", 1)])
+            .await
+            .unwrap();
+        // A second flush in the same hour adds to its row
+        store
+            .add_rollups(&[rollup(3600, "alice", 3)])
+            .await
+            .unwrap();
+        store
+            .add_rollups(&[rollup(7200, "alice", 1)])
+            .await
+            .unwrap();
+
+        let alice = store.rollups(0, Some("api"), Some("alice")).await.unwrap();
+        assert_eq!(alice.len(), 2);
+        assert_eq!(alice[0].usage.requests, 5);
+        assert_eq!(alice[0].usage.bytes_out, 200);
+        assert_eq!(alice[0].usage.compute_seconds, 120.0);
+        assert_eq!(store.rollups(0, Some("api"), None).await.unwrap().len(), 3);
+        assert!(store
+            .rollups(0, Some("web"), None)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(store.rollups(7200, None, None).await.unwrap().len(), 1);
+
+        assert_eq!(store.prune_rollups(7200).await.unwrap(), 2);
+        assert_eq!(store.rollups(0, None, None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tenant_key_store_salt_is_stable_per_tenant() {
+        let (pool, _dir) = create_test_db().await;
+        let store = TenantKeyStore::new(pool);
+
+        let alice = store.salt("alice").await.unwrap();
+        assert_eq!(store.salt("alice").await.unwrap(), alice);
+        assert_ne!(store.salt("bob").await.unwrap(), alice);
+    }
+
+    // ===================
+    // TENANT SECRET STORE TESTS
+    // ===================
+
+    #[tokio::test]
+    async fn test_tenant_secret_store_get_set_delete() {
+        let (pool, _dir) = create_test_db().await;
+        let store = TenantSecretStore::new(pool);
+
+        assert!(store.get("alice", "db:api_alice").await.unwrap().is_none());
+        store
+            .set("alice", "db:api_alice", "postgres://a@h/api_alice")
+            .await
+            .unwrap();
+        store
+            .set("alice", "db:api_alice", "postgres://b@h/api_alice")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get("alice", "db:api_alice").await.unwrap().as_deref(),
+            Some("postgres://b@h/api_alice")
+        );
+        assert!(store.get("bob", "db:api_alice").await.unwrap().is_none());
+        assert_eq!(store.owners("db:api_alice").await.unwrap(), vec!["alice"]);
+        assert!(store.owners("db:api_bob").await.unwrap().is_empty());
+
+        assert!(store.delete("alice", "db:api_alice").await.unwrap());
+        assert!(!store.delete("alice", "db:api_alice").await.unwrap());
+    }
+
     // ===================
     // CONFIG STORE TESTS
     // ===================
@@ -1433,6 +2381,143 @@ mod tests {
         assert_eq!(store.get("key").await.unwrap(), Some(special.to_string()));
     }
 
+    // ===================
+    // STATE STORE TESTS
+    // ===================
+
+    fn test_state(instance_id: &str, pid: u32) -> InstanceState {
+        let (process_name, id) = instance_id.split_once(':').unwrap();
+        InstanceState {
+            instance_id: instance_id.to_string(),
+            process_name: process_name.to_string(),
+            id: id.to_string(),
+            pid,
+            port: Some(30001),
+            started_at: "2024-01-01T00:00:00+00:00".to_string(),
+            socket: format!("/tmp/{}.sock", id),
+            runtime: "process".to_string(),
+            listeners: String::new(),
+            config: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_state_store_roundtrip() {
+        let (pool, _dir) = create_test_db().await;
+        let store = StateStore::new(pool);
+
+        store.save(&test_state("api:prod", 1234)).await.unwrap();
+        store.save(&test_state("api:prod", 5678)).await.unwrap();
+        store.save(&test_state("api:staging", 42)).await.unwrap();
+
+        let mut states = store.list().await.unwrap();
+        states.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].pid, 5678);
+        assert_eq!(states[0].socket, "/tmp/prod.sock");
+        assert_eq!(states[0].runtime, "process");
+
+        store.remove("api:prod").await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 1);
+        store.clear_all().await.unwrap();
+        assert!(store.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_routing_state_roundtrip() {
+        let (pool, _dir) = create_test_db().await;
+        let store = StateStore::new(pool);
+
+        store.save_weight("api:v1", 30).await.unwrap();
+        store.save_weight("api:v1", 0).await.unwrap();
+        store.save_weight("api:v2", 70).await.unwrap();
+        let mut weights = store.weights().await.unwrap();
+        weights.sort();
+        assert_eq!(
+            weights,
+            vec![("api:v1".to_string(), 0), ("api:v2".to_string(), 70)]
+        );
+        store.remove_weight("api:v1").await.unwrap();
+        assert_eq!(store.weights().await.unwrap().len(), 1);
+
+        store
+            .save_versions("api", Some("v2"), Some("v1"))
+            .await
+            .unwrap();
+        store
+            .save_versions("api", Some("v3"), Some("v2"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.versions().await.unwrap(),
+            vec![(
+                "api".to_string(),
+                Some("v3".to_string()),
+                Some("v2".to_string())
+            )]
+        );
+        // Instance state is separate: clearing it keeps routing state
+        store.clear_all().await.unwrap();
+        assert_eq!(store.weights().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_state_store_migrates_old_schema() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.db");
+        let pool = init_db(&path).await.unwrap();
+
+        // Recreate the table as it looked before socket/runtime were recorded
+        let mut conn = pool.acquire().await.unwrap();
+        for sql in [
+            "DROP TABLE instance_state",
+            "CREATE TABLE instance_state (instance_id TEXT PRIMARY KEY, process_name TEXT NOT NULL, id TEXT NOT NULL, pid INTEGER NOT NULL, port INTEGER, started_at TEXT NOT NULL)",
+            "INSERT INTO instance_state VALUES ('api:prod', 'api', 'prod', 99, NULL, '2024-01-01T00:00:00+00:00')",
+        ] {
+            sqlx::query(sql).execute(&mut *conn).await.unwrap();
+        }
+        drop(conn);
+        pool.close().await;
+
+        let pool = init_db(&path).await.unwrap();
+        let store = StateStore::new(pool);
+        let states = store.list().await.unwrap();
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].socket, "");
+        assert_eq!(states[0].runtime, "");
+        assert_eq!(states[0].listeners, "");
+        assert_eq!(states[0].config, "");
+
+        store.save(&test_state("api:staging", 7)).await.unwrap();
+        assert_eq!(store.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_access_log_migrates_old_schema() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("test.db");
+        let pool = init_db(&path).await.unwrap();
+
+        // Recreate the table as it looked before experiments were recorded
+        let mut conn = pool.acquire().await.unwrap();
+        for sql in [
+            "DROP TABLE access_log",
+            "CREATE TABLE access_log (id INTEGER PRIMARY KEY AUTOINCREMENT, timestamp INTEGER NOT NULL, host TEXT NOT NULL, method TEXT NOT NULL, path TEXT NOT NULL, status INTEGER NOT NULL, latency_ms REAL NOT NULL, process TEXT NOT NULL, instance_id TEXT, bytes INTEGER NOT NULL)",
+            "INSERT INTO access_log (timestamp, host, method, path, status, latency_ms, process, instance_id, bytes) VALUES (1, 'api.example.com', 'GET', '/', 200, 1.0, 'api', 'prod', 5)",
+        ] {
+            sqlx::query(sql).execute(&mut *conn).await.unwrap();
+        }
+        drop(conn);
+        pool.close().await;
+
+        let pool = init_db(&path).await.unwrap();
+        let store = AccessLogStore::new(pool);
+        let entries = store.query(&AccessLogQuery::default()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].experiment, None);
+        assert_eq!(entries[0].experiment_group, None);
+    }
+
     // ===================
     // TIMESTAMP CONVERSION TESTS
     // ===================
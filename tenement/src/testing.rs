@@ -0,0 +1,213 @@
+//! Test helpers for code that embeds a [`Hypervisor`]
+//!
+//! Enabled with the `testing` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! tenement = { version = "0.2", features = ["testing"] }
+//! ```
+//!
+//! [`TestHarness`] builds a hypervisor that spawns [`MockProcess`]es instead of
+//! real processes, reads time from a [`FakeClock`], and keeps its state in an
+//! in-memory database. Instances still get ports or sockets that answer HTTP,
+//! so health checks and proxying work. Nothing runs in the background:
+//! [`TestHarness::tick`] advances the clock and runs one monitor pass, so a
+//! test controls exactly when restarts and reaping happen.
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use std::time::Duration;
+//! use tenement::testing::TestHarness;
+//! use tenement::Config;
+//!
+//! let config = Config::from_str(r#"
+//! [service.api]
+//! command = "./api"
+//! isolation = "process"
+//! idle_timeout = 60
+//! "#)?;
+//! let harness = TestHarness::new(config).await?;
+//! harness.hypervisor.spawn("api", "alice").await?;
+//!
+//! // Move virtual time forward and run one monitor pass
+//! harness.tick(Duration::from_secs(61)).await;
+//! assert!(!harness.hypervisor.is_running("api", "alice").await);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::clock::Clock;
+use crate::config::Config;
+use crate::hypervisor::Hypervisor;
+use crate::store::{
+    init_memory_db, DbPool, StateStore, TenantKeyStore, TenantSecretStore, TenantTokenStore,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+
+pub use crate::runtime::{MockProcess, MockRuntime};
+
+/// A clock that only moves when told to
+///
+/// `sleep` waits until the clock has been advanced past the deadline, so
+/// backoff delays resolve as soon as a test calls [`FakeClock::advance`].
+#[derive(Debug)]
+pub struct FakeClock {
+    start: Instant,
+    offset: Mutex<Duration>,
+    ticks: watch::Sender<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            offset: Mutex::new(Duration::ZERO),
+            ticks: watch::channel(Duration::ZERO).0,
+        }
+    }
+
+    /// Move the clock forward, waking any sleeps that are now due
+    pub fn advance(&self, by: Duration) {
+        let mut offset = self.offset.lock().unwrap();
+        *offset += by;
+        self.ticks.send_replace(*offset);
+    }
+
+    /// Total time advanced since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        *self.offset.lock().unwrap()
+    }
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let deadline = self.elapsed() + duration;
+        let mut ticks = self.ticks.subscribe();
+        // The sender lives as long as the clock, so this only ends once due
+        let _ = ticks.wait_for(|offset| *offset >= deadline).await;
+    }
+}
+
+/// Stores backed by one in-memory database
+#[derive(Clone)]
+pub struct MemoryStores {
+    pub pool: DbPool,
+    pub state: Arc<StateStore>,
+    pub tenant_keys: Arc<TenantKeyStore>,
+    pub tenant_secrets: Arc<TenantSecretStore>,
+    pub tenant_tokens: Arc<TenantTokenStore>,
+}
+
+impl MemoryStores {
+    pub async fn new() -> Result<Self> {
+        let pool = init_memory_db().await?;
+        Ok(Self {
+            state: Arc::new(StateStore::new(pool.clone())),
+            tenant_keys: Arc::new(TenantKeyStore::new(pool.clone())),
+            tenant_secrets: Arc::new(TenantSecretStore::new(pool.clone())),
+            tenant_tokens: Arc::new(TenantTokenStore::new(pool.clone())),
+            pool,
+        })
+    }
+}
+
+/// A hypervisor wired to a mock runtime, fake clock and in-memory stores
+pub struct TestHarness {
+    pub hypervisor: Arc<Hypervisor>,
+    pub runtime: Arc<MockRuntime>,
+    pub clock: Arc<FakeClock>,
+    pub stores: MemoryStores,
+}
+
+impl TestHarness {
+    pub async fn new(config: Config) -> Result<Self> {
+        let stores = MemoryStores::new().await?;
+        let runtime = Arc::new(MockRuntime::new());
+        let clock = Arc::new(FakeClock::new());
+        let hypervisor = Hypervisor::with_state_store(config, stores.state.clone())
+            .with_tenant_keys(stores.tenant_keys.clone())
+            .with_secrets(stores.tenant_secrets.clone())
+            .with_runtime(runtime.clone())
+            .with_clock(clock.clone());
+        Ok(Self {
+            hypervisor,
+            runtime,
+            clock,
+            stores,
+        })
+    }
+
+    /// Advance virtual time by `by`, then run one monitor pass
+    /// (health checks and restarts, idle reaping, warm pool refills).
+    /// Restart backoff sleeps on the fake clock too, so a pass that restarts
+    /// an instance with a non-zero backoff waits for a later `advance`; set
+    /// `backoff_base_ms = 0` or tick from a spawned task.
+    pub async fn tick(&self, by: Duration) {
+        self.clock.advance(by);
+        self.hypervisor.monitor_tick().await;
+    }
+
+    /// The current mock process behind `process:id`, if it is running
+    pub async fn process(&self, process_name: &str, id: &str) -> Option<Arc<MockProcess>> {
+        let info = self.hypervisor.get(process_name, id).await?;
+        self.runtime.process(&info.socket)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fake_clock_sleep_waits_for_advance() {
+        let clock = Arc::new(FakeClock::new());
+        let start = clock.now();
+
+        let sleeper = {
+            let clock = clock.clone();
+            tokio::spawn(async move { clock.sleep(Duration::from_secs(30)).await })
+        };
+        // Let the sleep start (and take its deadline) before moving the clock
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        clock.advance(Duration::from_secs(10));
+        tokio::task::yield_now().await;
+        assert!(!sleeper.is_finished());
+
+        clock.advance(Duration::from_secs(20));
+        tokio::time::timeout(Duration::from_secs(1), sleeper)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(clock.since(start), Duration::from_secs(30));
+    }
+
+    #[tokio::test]
+    async fn test_memory_stores_share_one_database() {
+        let stores = MemoryStores::new().await.unwrap();
+        stores
+            .tenant_secrets
+            .set("alice", "db:app", "postgres://x")
+            .await
+            .unwrap();
+        let other = TenantSecretStore::new(stores.pool.clone());
+        assert_eq!(
+            other.get("alice", "db:app").await.unwrap().as_deref(),
+            Some("postgres://x")
+        );
+    }
+}
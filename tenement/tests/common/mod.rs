@@ -34,7 +34,7 @@ pub fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> C
         restart: "on-failure".to_string(),
         idle_timeout: None,
         startup_timeout: 5,
-        request_timeout: 30,
+        request_timeout: None,
         memory_limit_mb: None,
         cpu_shares: None,
         kernel: None,
@@ -44,6 +44,7 @@ pub fn test_config_with_process(name: &str, command: &str, args: Vec<&str>) -> C
         vsock_port: 5000,
         storage_quota_mb: None,
         storage_persist: false,
+        ..Default::default()
     };
 
     config.service.insert(name.to_string(), process);